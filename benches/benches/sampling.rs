@@ -0,0 +1,115 @@
+use candle_core::{Device, Tensor};
+use candle_transformers::utils::apply_repeat_penalty;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use oxide_rs::inference::sampler::{argmax, top_k};
+use oxide_rs::inference::scratch::StepScratch;
+use oxide_rs::{LogitBias, LogitsTransform, TransformContext};
+
+/// Vocabulary sizes of common small models, up to Llama 3's 128k.
+const VOCAB_SIZES: &[usize] = &[32_000, 65_536, 128_256];
+
+/// Deterministic logits spread like a real distribution: most tokens low,
+/// a few high.
+fn logits(vocab: usize) -> Vec<f32> {
+    let mut state = 0x2545_f491_u32;
+    (0..vocab)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            let unit = state as f32 / u32::MAX as f32;
+            unit.powi(8) * 30.0 - 10.0
+        })
+        .collect()
+}
+
+fn sample_argmax(c: &mut Criterion) {
+    let mut group = c.benchmark_group("sample_argmax");
+
+    for &vocab in VOCAB_SIZES {
+        let values = logits(vocab);
+        group.bench_with_input(BenchmarkId::new("fast", vocab), &values, |b, values| {
+            b.iter(|| argmax(black_box(values)));
+        });
+        group.bench_with_input(BenchmarkId::new("scalar", vocab), &values, |b, values| {
+            b.iter(|| {
+                black_box(values)
+                    .iter()
+                    .enumerate()
+                    .fold((0, f32::NEG_INFINITY), |best, (i, &v)| {
+                        if v > best.1 {
+                            (i, v)
+                        } else {
+                            best
+                        }
+                    })
+            });
+        });
+    }
+
+    group.finish();
+}
+
+fn sample_top_k(c: &mut Criterion) {
+    let mut group = c.benchmark_group("sample_top_k");
+
+    for &vocab in VOCAB_SIZES {
+        let values = logits(vocab);
+        group.bench_with_input(BenchmarkId::new("fast", vocab), &values, |b, values| {
+            b.iter(|| top_k(black_box(values), 40));
+        });
+        group.bench_with_input(BenchmarkId::new("full_sort", vocab), &values, |b, values| {
+            b.iter(|| {
+                let mut ids: Vec<u32> = (0..values.len() as u32).collect();
+                ids.sort_by(|&a, &b| values[b as usize].total_cmp(&values[a as usize]));
+                ids.truncate(40);
+                black_box(ids)
+            });
+        });
+    }
+
+    group.finish();
+}
+
+/// The CPU half of a decode step at a 128k vocabulary: repeat penalty, one
+/// logits transform and greedy sampling. `tensor` round-trips through a new
+/// tensor at each stage; `scratch` copies the logits once into a reused
+/// buffer. Compare the spread of the two as well as the means.
+fn decode_step(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode_step");
+    let vocab = 128_256;
+    let step_logits = Tensor::new(logits(vocab).as_slice(), &Device::Cpu).unwrap();
+    let context: Vec<u32> = (0..64).map(|i| i * 1_999 % vocab as u32).collect();
+    let tokens: Vec<u32> = (0..512).collect();
+    let transform_context = TransformContext {
+        tokens: &tokens,
+        prompt_len: 448,
+        eos_token: 2,
+    };
+    let mut bias = LogitBias::new([(2, -1.0)].into_iter().collect());
+
+    group.bench_function("tensor", |b| {
+        b.iter(|| {
+            let penalized = apply_repeat_penalty(black_box(&step_logits), 1.1, &context).unwrap();
+            let mut values = penalized.to_vec1::<f32>().unwrap();
+            bias.apply(&mut values, &transform_context).unwrap();
+            let biased = Tensor::from_vec(values, vocab, &Device::Cpu).unwrap();
+            argmax(&biased.to_vec1::<f32>().unwrap())
+        });
+    });
+
+    let mut scratch = StepScratch::new(vocab);
+    group.bench_function("scratch", |b| {
+        b.iter(|| {
+            scratch.load(black_box(&step_logits)).unwrap();
+            scratch.apply_repeat_penalty(1.1, &context);
+            bias.apply(scratch.logits_mut(), &transform_context).unwrap();
+            argmax(scratch.logits())
+        });
+    });
+
+    group.finish();
+}
+
+criterion_group!(sampling, sample_argmax, sample_top_k, decode_step);
+criterion_main!(sampling);
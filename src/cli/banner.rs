@@ -1,4 +1,4 @@
-use std::io::{self, Write};
+use std::io::Write;
 
 use crossterm::{
     cursor::MoveTo,
@@ -7,7 +7,7 @@ use crossterm::{
     terminal::{Clear, ClearType},
 };
 
-use super::theme::Theme;
+use super::theme::{self, Theme};
 
 const FERRIS_BANNER: &str = r#"
   /$$$$$$            /$$       /$$          
@@ -25,7 +25,7 @@ const FERRIS_BANNER: &str = r#"
 "#;
 
 pub fn print_banner() {
-    let mut stdout = io::stdout();
+    let mut stdout = theme::stdout();
 
     execute!(stdout, Clear(ClearType::All), MoveTo(0, 0)).ok();
 
@@ -140,7 +140,7 @@ pub fn print_banner() {
 }
 
 pub fn print_divider() {
-    let mut stdout = io::stdout();
+    let mut stdout = theme::stdout();
     execute!(
         stdout,
         SetForegroundColor(Theme::IRON_GRAY),
@@ -0,0 +1,129 @@
+//! Side-by-side layout for `oxide-rs compare`.
+
+/// One model's reply and the figures shown under it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompareColumn {
+    pub title: String,
+    pub text: String,
+    pub stats: Vec<String>,
+}
+
+const SEPARATOR: &str = " │ ";
+
+/// Prints `columns` side by side across the terminal, or 100 characters
+/// when stdout is not a terminal.
+pub fn print_side_by_side(columns: &[CompareColumn]) {
+    let width = crossterm::terminal::size()
+        .map(|(width, _)| width as usize)
+        .unwrap_or(100);
+    for line in side_by_side(columns, width) {
+        println!("{}", line);
+    }
+}
+
+/// Lays `columns` out next to each other in `width` characters: titles, a
+/// rule, the wrapped replies, another rule and the stats.
+pub fn side_by_side(columns: &[CompareColumn], width: usize) -> Vec<String> {
+    if columns.is_empty() {
+        return Vec::new();
+    }
+    let gaps = SEPARATOR.chars().count() * (columns.len() - 1);
+    let column_width = (width.saturating_sub(gaps) / columns.len()).max(8);
+    let rule = "─".repeat(column_width);
+
+    let mut lines = Vec::new();
+    let titles: Vec<Vec<String>> = columns
+        .iter()
+        .map(|c| wrap(&c.title, column_width))
+        .collect();
+    lines.extend(rows(&titles, column_width));
+    lines.push(vec![rule.as_str(); columns.len()].join(SEPARATOR));
+    let texts: Vec<Vec<String>> = columns
+        .iter()
+        .map(|c| wrap(c.text.trim(), column_width))
+        .collect();
+    lines.extend(rows(&texts, column_width));
+    lines.push(vec![rule.as_str(); columns.len()].join(SEPARATOR));
+    let stats: Vec<Vec<String>> = columns
+        .iter()
+        .map(|c| c.stats.iter().flat_map(|s| wrap(s, column_width)).collect())
+        .collect();
+    lines.extend(rows(&stats, column_width));
+    lines
+}
+
+/// Zips the cells of each column into padded lines.
+fn rows(cells: &[Vec<String>], width: usize) -> Vec<String> {
+    let height = cells.iter().map(Vec::len).max().unwrap_or(0);
+    (0..height)
+        .map(|row| {
+            let line = cells
+                .iter()
+                .map(|column| {
+                    let cell = column.get(row).map(String::as_str).unwrap_or("");
+                    let padding = width.saturating_sub(cell.chars().count());
+                    format!("{}{}", cell, " ".repeat(padding))
+                })
+                .collect::<Vec<_>>()
+                .join(SEPARATOR);
+            line.trim_end().to_string()
+        })
+        .collect()
+}
+
+/// Wraps `text` at word boundaries to `width` characters, keeping its line
+/// breaks and splitting words that are longer than a line.
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    for paragraph in text.lines() {
+        let mut current = String::new();
+        for word in paragraph.split_whitespace() {
+            let mut word: Vec<char> = word.chars().collect();
+            let used = current.chars().count();
+            if used > 0 && used + 1 + word.len() > width {
+                lines.push(std::mem::take(&mut current));
+            }
+            while word.len() > width {
+                lines.push(word.drain(..width).collect());
+            }
+            if !current.is_empty() {
+                current.push(' ');
+            }
+            current.extend(word);
+        }
+        lines.push(current);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{side_by_side, CompareColumn};
+
+    #[test]
+    fn lays_replies_out_in_padded_columns() {
+        let column = |title: &str, text: &str, stats: &[&str]| CompareColumn {
+            title: title.to_string(),
+            text: text.to_string(),
+            stats: stats.iter().map(|s| s.to_string()).collect(),
+        };
+        let columns = [
+            column("a.gguf", "The capital of France is Paris.", &["12.0 tok/s"]),
+            column("b.gguf", "Paris.\n\nSupercalifragilistic", &[]),
+        ];
+        let lines = side_by_side(&columns, 27);
+        assert_eq!(
+            lines,
+            [
+                "a.gguf       │ b.gguf",
+                "──────────── │ ────────────",
+                "The capital  │ Paris.",
+                "of France is │",
+                "Paris.       │ Supercalifra",
+                "             │ gilistic",
+                "──────────── │ ────────────",
+                "12.0 tok/s   │",
+            ]
+        );
+    }
+}
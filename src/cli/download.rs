@@ -1,4 +1,4 @@
-use std::io::{self, Write};
+use std::io::Write;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::thread::{self, JoinHandle};
@@ -11,7 +11,8 @@ use crossterm::{
     terminal::{Clear, ClearType},
 };
 
-use crate::cli::theme::Theme;
+use crate::cli::terminal;
+use crate::cli::theme::{self, Theme};
 use crate::model::download::{format_size, DownloadProgress};
 
 pub struct DownloadProgressBar {}
@@ -36,15 +37,11 @@ impl DownloadProgressBar {
             / (progress.total_bytes as f64).max(1.0)) as usize;
         let bar: String = format!(
             "{}{}",
-            std::iter::repeat('█')
-                .take(filled.min(bar_width))
-                .collect::<String>(),
-            std::iter::repeat('░')
-                .take(bar_width - filled.min(bar_width))
-                .collect::<String>()
+            "█".repeat(filled.min(bar_width)),
+            "░".repeat(bar_width - filled.min(bar_width))
         );
 
-        let mut stdout = io::stdout();
+        let mut stdout = theme::stdout();
         execute!(
             stdout,
             MoveToColumn(0),
@@ -74,7 +71,7 @@ impl DownloadProgressBar {
     }
 
     pub fn finish(self, path: &str) {
-        let mut stdout = io::stdout();
+        let mut stdout = theme::stdout();
         execute!(
             stdout,
             MoveToColumn(0),
@@ -98,7 +95,7 @@ impl DownloadProgressBar {
     }
 
     pub fn finish_with_error(self, message: &str) {
-        let mut stdout = io::stdout();
+        let mut stdout = theme::stdout();
         execute!(
             stdout,
             MoveToColumn(0),
@@ -126,41 +123,46 @@ impl Spinner {
         let running = Arc::new(AtomicBool::new(true));
         let message = message.to_string();
 
-        let handle = thread::spawn({
-            let running = running.clone();
-            move || {
-                let mut stdout = io::stdout();
-                let mut i = 0usize;
-                let frames = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
-
-                while running.load(Ordering::Relaxed) {
-                    let frame = frames[i % frames.len()];
-
-                    execute!(
-                        stdout,
-                        MoveToColumn(0),
-                        Clear(ClearType::CurrentLine),
-                        SetForegroundColor(Theme::RUST_ORANGE),
-                        Print(frame),
-                        ResetColor,
-                        Print(" "),
-                        SetForegroundColor(Theme::TEXT_PRIMARY),
-                        Print(&message),
-                        ResetColor
-                    )
-                    .ok();
-
-                    stdout.flush().ok();
-                    thread::sleep(Duration::from_millis(100));
-                    i = i.wrapping_add(1);
+        // Spinners only animate on a terminal.
+        let handle = (!theme::is_plain_output()).then(|| {
+            thread::spawn({
+                let running = running.clone();
+                move || {
+                    let mut stdout = theme::stdout();
+                    let mut i = 0usize;
+                    let frames = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
+
+                    while running.load(Ordering::Relaxed) {
+                        let frame = frames[i % frames.len()];
+
+                        let drawn = terminal::draw_frame(|| {
+                            execute!(
+                                stdout,
+                                MoveToColumn(0),
+                                Clear(ClearType::CurrentLine),
+                                SetForegroundColor(Theme::RUST_ORANGE),
+                                Print(frame),
+                                ResetColor,
+                                Print(" "),
+                                SetForegroundColor(Theme::TEXT_PRIMARY),
+                                Print(&message),
+                                ResetColor
+                            )
+                            .ok();
+
+                            stdout.flush().ok();
+                        });
+                        if !drawn {
+                            break;
+                        }
+                        thread::sleep(Duration::from_millis(100));
+                        i = i.wrapping_add(1);
+                    }
                 }
-            }
+            })
         });
 
-        Self {
-            running,
-            handle: Some(handle),
-        }
+        Self { running, handle }
     }
 
     pub fn finish(mut self) {
@@ -176,7 +178,7 @@ impl Spinner {
             h.join().ok();
         }
 
-        let mut stdout = io::stdout();
+        let mut stdout = theme::stdout();
         execute!(
             stdout,
             MoveToColumn(0),
@@ -0,0 +1,451 @@
+//! Translated CLI and REPL messages, for `--lang`.
+//!
+//! Messages are plain tables, one `match` per language, so adding a
+//! language means adding one function. Placeholders are `{}` and are filled
+//! in order by [`format`]; translations must keep their order. The
+//! language comes from `--lang`, then `LC_ALL`, `LC_MESSAGES` and `LANG`,
+//! falling back to English.
+
+use std::fmt::Display;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+/// A language messages are available in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Lang {
+    #[default]
+    En,
+    Es,
+    De,
+    Zh,
+}
+
+impl Lang {
+    pub const ALL: [Lang; 4] = [Lang::En, Lang::Es, Lang::De, Lang::Zh];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Lang::En => "en",
+            Lang::Es => "es",
+            Lang::De => "de",
+            Lang::Zh => "zh",
+        }
+    }
+
+    /// The language of a locale such as `de_DE.UTF-8` or `zh-Hans`.
+    pub fn from_locale(locale: &str) -> Option<Lang> {
+        let code = locale
+            .split(['_', '-', '.', '@'])
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        Lang::ALL.iter().copied().find(|l| l.as_str() == code)
+    }
+
+    /// The language of the environment's locale, or English.
+    pub fn detect() -> Lang {
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|var| std::env::var(var).ok())
+            .find(|value| !value.is_empty())
+            .and_then(|locale| Lang::from_locale(&locale))
+            .unwrap_or_default()
+    }
+}
+
+impl FromStr for Lang {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Lang::from_locale(s)
+            .ok_or_else(|| format!("unsupported language '{}' (expected en, es, de or zh)", s))
+    }
+}
+
+static LANG: OnceLock<Lang> = OnceLock::new();
+
+/// Sets the language of every following message. Only the first call, or
+/// the first message printed, decides it.
+pub fn set_lang(lang: Lang) {
+    let _ = LANG.set(lang);
+}
+
+pub fn lang() -> Lang {
+    *LANG.get_or_init(Lang::detect)
+}
+
+/// A translatable message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Msg {
+    /// `{}` is the exit key.
+    Welcome,
+    HistoryCleared,
+    UsageSave,
+    UsageLoad,
+    UsageNote,
+    UsageFile,
+    LoadWhileRecording,
+    StepWhileRecording,
+    FileWhileRecording,
+    StepOn,
+    StepOff,
+    SessionSaved,
+    SessionSaveFailed,
+    SessionLoaded,
+    SessionLoadFailed,
+    SessionResumed,
+    Commands,
+    HelpClear,
+    HelpContext,
+    HelpPreview,
+    HelpStats,
+    HelpSave,
+    HelpLoad,
+    HelpMark,
+    HelpNote,
+    HelpFile,
+    HelpStep,
+    HelpScript,
+    HelpExit,
+    HelpHelp,
+    Interrupted,
+    RenderFailed,
+    ContextUsage,
+    CommandFailed,
+    NoteSaved,
+    NothingToMark,
+    NoteSaveFailed,
+    NoModels,
+    DownloadHint,
+    LocalModels,
+    RunHint,
+    ModelRemoved,
+    ModelNotFound,
+    ModelsHint,
+}
+
+impl Msg {
+    pub const ALL: [Msg; 44] = [
+        Msg::Welcome,
+        Msg::HistoryCleared,
+        Msg::UsageSave,
+        Msg::UsageLoad,
+        Msg::UsageNote,
+        Msg::UsageFile,
+        Msg::LoadWhileRecording,
+        Msg::StepWhileRecording,
+        Msg::FileWhileRecording,
+        Msg::StepOn,
+        Msg::StepOff,
+        Msg::SessionSaved,
+        Msg::SessionSaveFailed,
+        Msg::SessionLoaded,
+        Msg::SessionLoadFailed,
+        Msg::SessionResumed,
+        Msg::Commands,
+        Msg::HelpClear,
+        Msg::HelpContext,
+        Msg::HelpPreview,
+        Msg::HelpStats,
+        Msg::HelpSave,
+        Msg::HelpLoad,
+        Msg::HelpMark,
+        Msg::HelpNote,
+        Msg::HelpFile,
+        Msg::HelpStep,
+        Msg::HelpScript,
+        Msg::HelpExit,
+        Msg::HelpHelp,
+        Msg::Interrupted,
+        Msg::RenderFailed,
+        Msg::ContextUsage,
+        Msg::CommandFailed,
+        Msg::NoteSaved,
+        Msg::NothingToMark,
+        Msg::NoteSaveFailed,
+        Msg::NoModels,
+        Msg::DownloadHint,
+        Msg::LocalModels,
+        Msg::RunHint,
+        Msg::ModelRemoved,
+        Msg::ModelNotFound,
+        Msg::ModelsHint,
+    ];
+}
+
+/// `msg` in the current language.
+pub fn text(msg: Msg) -> &'static str {
+    text_in(lang(), msg)
+}
+
+pub fn text_in(lang: Lang, msg: Msg) -> &'static str {
+    match lang {
+        Lang::En => en(msg),
+        Lang::Es => es(msg),
+        Lang::De => de(msg),
+        Lang::Zh => zh(msg),
+    }
+}
+
+/// `msg` in the current language with its placeholders filled from `args`.
+pub fn format(msg: Msg, args: &[&dyn Display]) -> String {
+    fill(text(msg), args)
+}
+
+fn fill(template: &str, args: &[&dyn Display]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut args = args.iter();
+    let mut parts = template.split("{}");
+    if let Some(first) = parts.next() {
+        out.push_str(first);
+    }
+    for part in parts {
+        if let Some(arg) = args.next() {
+            out.push_str(&arg.to_string());
+        }
+        out.push_str(part);
+    }
+    out
+}
+
+fn en(msg: Msg) -> &'static str {
+    match msg {
+        Msg::Welcome => "Type your message and press Enter. {} to exit.",
+        Msg::HistoryCleared => "History cleared.",
+        Msg::UsageSave => "Usage: /save <path> (or start with --session <path>)",
+        Msg::UsageLoad => "Usage: /load <path>",
+        Msg::UsageNote => "Usage: /note <text>",
+        Msg::UsageFile => "Usage: /file <path>",
+        Msg::LoadWhileRecording => {
+            "/load is unavailable while --record is on; start a new recording instead."
+        }
+        Msg::StepWhileRecording => "/step is unavailable while --record is on.",
+        Msg::FileWhileRecording => {
+            "/file is unavailable while --record is on; pass --document instead."
+        }
+        Msg::StepOn => {
+            "Stepping on: replies are built one token at a time. /step again to turn it off."
+        }
+        Msg::StepOff => "Stepping off.",
+        Msg::SessionSaved => "Session saved to {}.",
+        Msg::SessionSaveFailed => "Failed to save session: {}",
+        Msg::SessionLoaded => "Loaded {} messages from {}.",
+        Msg::SessionLoadFailed => "Failed to load session: {}",
+        Msg::SessionResumed => "Resumed {} messages from {}.",
+        Msg::Commands => "Commands:",
+        Msg::HelpClear => "Clear conversation history",
+        Msg::HelpContext => "Show context usage",
+        Msg::HelpPreview => "Show the rendered prompt: /preview [next prompt]",
+        Msg::HelpStats => "Show model info and settings",
+        Msg::HelpSave => "Save the session: /save [path]",
+        Msg::HelpLoad => "Resume a saved session: /load [path]",
+        Msg::HelpMark => "Keep the last exchange in your notes",
+        Msg::HelpNote => "Keep the last exchange with a note: /note <text>",
+        Msg::HelpFile => "Ask about a text, Markdown, HTML or PDF file: /file <path>",
+        Msg::HelpStep => "Build replies token by token, choosing each one (toggle)",
+        Msg::HelpScript => "Script command",
+        Msg::HelpExit => "Exit the program",
+        Msg::HelpHelp => "Show this help",
+        Msg::Interrupted => "Interrupted.",
+        Msg::RenderFailed => "Failed to render the template: {}",
+        Msg::ContextUsage => "Context: {} / {} tokens ({}%)",
+        Msg::CommandFailed => "/{} failed: {}",
+        Msg::NoteSaved => "Saved as note #{}. Find it with `oxide-rs notes search`.",
+        Msg::NothingToMark => "Nothing to mark yet.",
+        Msg::NoteSaveFailed => "Failed to save note: {}",
+        Msg::NoModels => "No models downloaded yet.",
+        Msg::DownloadHint => "Download a model with:",
+        Msg::LocalModels => "Local Models",
+        Msg::RunHint => "Run a model:",
+        Msg::ModelRemoved => "Removed model: {}",
+        Msg::ModelNotFound => "Model not found: {}",
+        Msg::ModelsHint => "Use 'oxide-rs --models' to see available models.",
+    }
+}
+
+fn es(msg: Msg) -> &'static str {
+    match msg {
+        Msg::Welcome => "Escribe tu mensaje y pulsa Enter. {} para salir.",
+        Msg::HistoryCleared => "Historial borrado.",
+        Msg::UsageSave => "Uso: /save <ruta> (o inicia con --session <ruta>)",
+        Msg::UsageLoad => "Uso: /load <ruta>",
+        Msg::UsageNote => "Uso: /note <texto>",
+        Msg::UsageFile => "Uso: /file <ruta>",
+        Msg::LoadWhileRecording => "/load no está disponible con --record; inicia una nueva grabación.",
+        Msg::StepWhileRecording => "/step no está disponible con --record.",
+        Msg::FileWhileRecording => "/file no está disponible con --record; usa --document en su lugar.",
+        Msg::StepOn => "Modo paso a paso activado: las respuestas se construyen token a token. /step de nuevo para desactivarlo.",
+        Msg::StepOff => "Modo paso a paso desactivado.",
+        Msg::SessionSaved => "Sesión guardada en {}.",
+        Msg::SessionSaveFailed => "No se pudo guardar la sesión: {}",
+        Msg::SessionLoaded => "Se cargaron {} mensajes de {}.",
+        Msg::SessionLoadFailed => "No se pudo cargar la sesión: {}",
+        Msg::SessionResumed => "Se reanudaron {} mensajes de {}.",
+        Msg::Commands => "Comandos:",
+        Msg::HelpClear => "Borrar el historial de la conversación",
+        Msg::HelpContext => "Mostrar el uso del contexto",
+        Msg::HelpPreview => "Mostrar el prompt renderizado: /preview [siguiente prompt]",
+        Msg::HelpStats => "Mostrar información del modelo y ajustes",
+        Msg::HelpSave => "Guardar la sesión: /save [ruta]",
+        Msg::HelpLoad => "Reanudar una sesión guardada: /load [ruta]",
+        Msg::HelpMark => "Guardar el último intercambio en tus notas",
+        Msg::HelpNote => "Guardar el último intercambio con una nota: /note <texto>",
+        Msg::HelpFile => "Preguntar sobre un archivo de texto, Markdown, HTML o PDF: /file <ruta>",
+        Msg::HelpStep => "Construir las respuestas token a token, eligiendo cada uno (activar/desactivar)",
+        Msg::HelpScript => "Comando de script",
+        Msg::HelpExit => "Salir del programa",
+        Msg::HelpHelp => "Mostrar esta ayuda",
+        Msg::Interrupted => "Interrumpido.",
+        Msg::RenderFailed => "No se pudo renderizar la plantilla: {}",
+        Msg::ContextUsage => "Contexto: {} / {} tokens ({}%)",
+        Msg::CommandFailed => "/{} falló: {}",
+        Msg::NoteSaved => "Guardado como nota #{}. Búscala con `oxide-rs notes search`.",
+        Msg::NothingToMark => "Todavía no hay nada que guardar.",
+        Msg::NoteSaveFailed => "No se pudo guardar la nota: {}",
+        Msg::NoModels => "Aún no hay modelos descargados.",
+        Msg::DownloadHint => "Descarga un modelo con:",
+        Msg::LocalModels => "Modelos locales",
+        Msg::RunHint => "Ejecutar un modelo:",
+        Msg::ModelRemoved => "Modelo eliminado: {}",
+        Msg::ModelNotFound => "Modelo no encontrado: {}",
+        Msg::ModelsHint => "Usa 'oxide-rs --models' para ver los modelos disponibles.",
+    }
+}
+
+fn de(msg: Msg) -> &'static str {
+    match msg {
+        Msg::Welcome => "Nachricht eingeben und Enter drücken. {} zum Beenden.",
+        Msg::HistoryCleared => "Verlauf gelöscht.",
+        Msg::UsageSave => "Verwendung: /save <Pfad> (oder mit --session <Pfad> starten)",
+        Msg::UsageLoad => "Verwendung: /load <Pfad>",
+        Msg::UsageNote => "Verwendung: /note <Text>",
+        Msg::UsageFile => "Verwendung: /file <Pfad>",
+        Msg::LoadWhileRecording => {
+            "/load ist mit --record nicht verfügbar; starte stattdessen eine neue Aufzeichnung."
+        }
+        Msg::StepWhileRecording => "/step ist mit --record nicht verfügbar.",
+        Msg::FileWhileRecording => "/file ist mit --record nicht verfügbar; verwende stattdessen --document.",
+        Msg::StepOn => {
+            "Schrittmodus an: Antworten entstehen Token für Token. Erneut /step zum Ausschalten."
+        }
+        Msg::StepOff => "Schrittmodus aus.",
+        Msg::SessionSaved => "Sitzung in {} gespeichert.",
+        Msg::SessionSaveFailed => "Sitzung konnte nicht gespeichert werden: {}",
+        Msg::SessionLoaded => "{} Nachrichten aus {} geladen.",
+        Msg::SessionLoadFailed => "Sitzung konnte nicht geladen werden: {}",
+        Msg::SessionResumed => "{} Nachrichten aus {} fortgesetzt.",
+        Msg::Commands => "Befehle:",
+        Msg::HelpClear => "Gesprächsverlauf löschen",
+        Msg::HelpContext => "Kontextnutzung anzeigen",
+        Msg::HelpPreview => "Gerenderten Prompt anzeigen: /preview [nächster Prompt]",
+        Msg::HelpStats => "Modellinfo und Einstellungen anzeigen",
+        Msg::HelpSave => "Sitzung speichern: /save [Pfad]",
+        Msg::HelpLoad => "Gespeicherte Sitzung fortsetzen: /load [Pfad]",
+        Msg::HelpMark => "Letzten Austausch in den Notizen behalten",
+        Msg::HelpNote => "Letzten Austausch mit Notiz behalten: /note <Text>",
+        Msg::HelpFile => "Fragen zu einer Text-, Markdown-, HTML- oder PDF-Datei: /file <Pfad>",
+        Msg::HelpStep => "Antworten Token für Token aufbauen und jedes selbst wählen (umschalten)",
+        Msg::HelpScript => "Skriptbefehl",
+        Msg::HelpExit => "Programm beenden",
+        Msg::HelpHelp => "Diese Hilfe anzeigen",
+        Msg::Interrupted => "Unterbrochen.",
+        Msg::RenderFailed => "Vorlage konnte nicht gerendert werden: {}",
+        Msg::ContextUsage => "Kontext: {} / {} Tokens ({}%)",
+        Msg::CommandFailed => "/{} fehlgeschlagen: {}",
+        Msg::NoteSaved => "Als Notiz #{} gespeichert. Finden mit `oxide-rs notes search`.",
+        Msg::NothingToMark => "Noch nichts zum Merken.",
+        Msg::NoteSaveFailed => "Notiz konnte nicht gespeichert werden: {}",
+        Msg::NoModels => "Noch keine Modelle heruntergeladen.",
+        Msg::DownloadHint => "Modell herunterladen mit:",
+        Msg::LocalModels => "Lokale Modelle",
+        Msg::RunHint => "Modell ausführen:",
+        Msg::ModelRemoved => "Modell entfernt: {}",
+        Msg::ModelNotFound => "Modell nicht gefunden: {}",
+        Msg::ModelsHint => "Mit 'oxide-rs --models' die verfügbaren Modelle anzeigen.",
+    }
+}
+
+fn zh(msg: Msg) -> &'static str {
+    match msg {
+        Msg::Welcome => "输入消息后按回车发送。按 {} 退出。",
+        Msg::HistoryCleared => "历史记录已清除。",
+        Msg::UsageSave => "用法：/save <路径>（或使用 --session <路径> 启动）",
+        Msg::UsageLoad => "用法：/load <路径>",
+        Msg::UsageNote => "用法：/note <文本>",
+        Msg::UsageFile => "用法：/file <路径>",
+        Msg::LoadWhileRecording => "启用 --record 时无法使用 /load；请开始新的录制。",
+        Msg::StepWhileRecording => "启用 --record 时无法使用 /step。",
+        Msg::FileWhileRecording => "启用 --record 时无法使用 /file；请改用 --document。",
+        Msg::StepOn => "逐步模式已开启：回复逐个 token 生成。再次输入 /step 关闭。",
+        Msg::StepOff => "逐步模式已关闭。",
+        Msg::SessionSaved => "会话已保存到 {}。",
+        Msg::SessionSaveFailed => "保存会话失败：{}",
+        Msg::SessionLoaded => "已加载 {} 条消息（来自 {}）。",
+        Msg::SessionLoadFailed => "加载会话失败：{}",
+        Msg::SessionResumed => "已恢复 {} 条消息（来自 {}）。",
+        Msg::Commands => "命令：",
+        Msg::HelpClear => "清除对话历史",
+        Msg::HelpContext => "显示上下文使用情况",
+        Msg::HelpPreview => "显示渲染后的提示词：/preview [下一条提示词]",
+        Msg::HelpStats => "显示模型信息和设置",
+        Msg::HelpSave => "保存会话：/save [路径]",
+        Msg::HelpLoad => "恢复已保存的会话：/load [路径]",
+        Msg::HelpMark => "将上一轮对话保存到笔记",
+        Msg::HelpNote => "将上一轮对话连同备注保存：/note <文本>",
+        Msg::HelpFile => "就文本、Markdown、HTML 或 PDF 文件提问：/file <路径>",
+        Msg::HelpStep => "逐个 token 构建回复，并逐一选择（开关）",
+        Msg::HelpScript => "脚本命令",
+        Msg::HelpExit => "退出程序",
+        Msg::HelpHelp => "显示此帮助",
+        Msg::Interrupted => "已中断。",
+        Msg::RenderFailed => "渲染模板失败：{}",
+        Msg::ContextUsage => "上下文：{} / {} 个 token（{}%）",
+        Msg::CommandFailed => "/{} 失败：{}",
+        Msg::NoteSaved => "已保存为笔记 #{}。可用 `oxide-rs notes search` 查找。",
+        Msg::NothingToMark => "还没有可保存的内容。",
+        Msg::NoteSaveFailed => "保存笔记失败：{}",
+        Msg::NoModels => "尚未下载任何模型。",
+        Msg::DownloadHint => "使用以下命令下载模型：",
+        Msg::LocalModels => "本地模型",
+        Msg::RunHint => "运行模型：",
+        Msg::ModelRemoved => "已删除模型：{}",
+        Msg::ModelNotFound => "未找到模型：{}",
+        Msg::ModelsHint => "使用 'oxide-rs --models' 查看可用模型。",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fill, text_in, Lang, Msg};
+
+    #[test]
+    fn every_translation_keeps_the_placeholders() {
+        for msg in Msg::ALL {
+            let placeholders = text_in(Lang::En, msg).matches("{}").count();
+            for lang in Lang::ALL {
+                let text = text_in(lang, msg);
+                assert!(!text.is_empty(), "{:?} {:?}", lang, msg);
+                assert_eq!(
+                    text.matches("{}").count(),
+                    placeholders,
+                    "{:?} {:?}",
+                    lang,
+                    msg
+                );
+            }
+        }
+        assert_eq!(
+            fill(text_in(Lang::De, Msg::SessionLoaded), &[&3, &"chat.json"]),
+            "3 Nachrichten aus chat.json geladen."
+        );
+    }
+
+    #[test]
+    fn reads_languages_from_locales() {
+        assert_eq!(Lang::from_locale("de_DE.UTF-8"), Some(Lang::De));
+        assert_eq!(Lang::from_locale("zh-Hans"), Some(Lang::Zh));
+        assert_eq!(Lang::from_locale("ES"), Some(Lang::Es));
+        assert_eq!(Lang::from_locale("C"), None);
+        assert_eq!(Lang::from_locale("fr_FR"), None);
+    }
+}
@@ -1,4 +1,4 @@
-use std::io::{self, Write};
+use std::io::Write;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::thread::{self, JoinHandle};
@@ -13,7 +13,8 @@ use crossterm::{
 };
 
 use super::stream::format_token_count;
-use super::theme::Theme;
+use super::terminal;
+use super::theme::{self, Theme};
 
 const FERRIS_WALKING: &[&str] = &[
     "🦀      ",
@@ -37,36 +38,41 @@ impl ModelLoader {
     pub fn new() -> Self {
         let running = Arc::new(AtomicBool::new(true));
 
-        let handle = thread::spawn({
-            let running = running.clone();
-            move || {
-                let mut stdout = io::stdout();
-                let mut i = 0usize;
-
-                while running.load(Ordering::Relaxed) {
-                    let ferris = FERRIS_WALKING[i % FERRIS_WALKING.len()];
-
-                    execute!(
-                        stdout,
-                        MoveToColumn(0),
-                        Clear(ClearType::CurrentLine),
-                        SetForegroundColor(Theme::RUST_ORANGE),
-                        Print(ferris),
-                        ResetColor
-                    )
-                    .ok();
-
-                    stdout.flush().ok();
-                    thread::sleep(Duration::from_millis(100));
-                    i = i.wrapping_add(1);
+        // Spinners only animate on a terminal.
+        let handle = (!theme::is_plain_output()).then(|| {
+            thread::spawn({
+                let running = running.clone();
+                move || {
+                    let mut stdout = theme::stdout();
+                    let mut i = 0usize;
+
+                    while running.load(Ordering::Relaxed) {
+                        let ferris = FERRIS_WALKING[i % FERRIS_WALKING.len()];
+
+                        let drawn = terminal::draw_frame(|| {
+                            execute!(
+                                stdout,
+                                MoveToColumn(0),
+                                Clear(ClearType::CurrentLine),
+                                SetForegroundColor(Theme::RUST_ORANGE),
+                                Print(ferris),
+                                ResetColor
+                            )
+                            .ok();
+
+                            stdout.flush().ok();
+                        });
+                        if !drawn {
+                            break;
+                        }
+                        thread::sleep(Duration::from_millis(100));
+                        i = i.wrapping_add(1);
+                    }
                 }
-            }
+            })
         });
 
-        Self {
-            running,
-            handle: Some(handle),
-        }
+        Self { running, handle }
     }
 
     pub fn finish(mut self, model_name: &str) {
@@ -75,7 +81,7 @@ impl ModelLoader {
             h.join().ok();
         }
 
-        let mut stdout = io::stdout();
+        let mut stdout = theme::stdout();
         execute!(
             stdout,
             MoveToColumn(0),
@@ -98,7 +104,7 @@ impl ModelLoader {
             h.join().ok();
         }
 
-        let mut stdout = io::stdout();
+        let mut stdout = theme::stdout();
         execute!(
             stdout,
             MoveToColumn(0),
@@ -136,7 +142,7 @@ pub fn print_model_info(
     dim: usize,
     context: usize,
 ) {
-    let mut stdout = io::stdout();
+    let mut stdout = theme::stdout();
 
     execute!(
         stdout,
@@ -0,0 +1,138 @@
+//! Diagnostics setup for `--log-file`, `--log-level` and `--log-format`.
+//!
+//! User-facing output (replies, banners, progress) is printed directly and
+//! never goes through `tracing`; diagnostics always do. The server logs to
+//! stderr by default, while the REPL and other modes stay silent unless a
+//! log file or level is asked for, so diagnostics never interleave with a
+//! streaming reply.
+
+use std::fs::OpenOptions;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer};
+
+/// Filter used when neither `--log-level` nor `RUST_LOG` is set.
+pub const DEFAULT_FILTER: &str = "oxide_rs=info";
+
+/// How log lines are written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human-readable lines.
+    #[default]
+    Text,
+    /// One JSON object per line, for log collectors.
+    Json,
+}
+
+impl LogFormat {
+    pub const ALL: [LogFormat; 2] = [LogFormat::Text, LogFormat::Json];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogFormat::Text => "text",
+            LogFormat::Json => "json",
+        }
+    }
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.to_ascii_lowercase();
+        LogFormat::ALL
+            .iter()
+            .copied()
+            .find(|f| f.as_str() == s)
+            .ok_or_else(|| format!("unknown log format '{}' (expected text or json)", s))
+    }
+}
+
+/// Where diagnostics go and which of them are kept.
+#[derive(Clone, Debug, Default)]
+pub struct LogConfig {
+    /// `EnvFilter` directives such as `debug` or `oxide_rs=debug,hyper=warn`;
+    /// `RUST_LOG` applies when unset.
+    pub level: Option<String>,
+    /// Append to this file instead of writing to stderr.
+    pub file: Option<PathBuf>,
+    pub format: LogFormat,
+    /// Color text written to stderr.
+    pub ansi: bool,
+}
+
+impl LogConfig {
+    /// Whether diagnostics were asked for explicitly.
+    pub fn is_requested(&self) -> bool {
+        self.level.is_some() || self.file.is_some()
+    }
+
+    fn filter(&self) -> Result<EnvFilter> {
+        match &self.level {
+            Some(level) => EnvFilter::try_new(level)
+                .with_context(|| format!("Invalid --log-level '{}'", level)),
+            None => Ok(EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| EnvFilter::new(DEFAULT_FILTER))),
+        }
+    }
+}
+
+/// Installs the global subscriber described by `config`.
+pub fn init(config: &LogConfig) -> Result<()> {
+    let filter = config.filter()?;
+    let layer = match &config.file {
+        Some(path) => {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("Failed to open log file {}", path.display()))?;
+            let layer = tracing_subscriber::fmt::layer()
+                .with_writer(Mutex::new(file))
+                .with_ansi(false);
+            match config.format {
+                LogFormat::Text => layer.boxed(),
+                LogFormat::Json => layer.json().boxed(),
+            }
+        }
+        None => {
+            let layer = tracing_subscriber::fmt::layer().with_writer(std::io::stderr);
+            match config.format {
+                LogFormat::Text => layer.with_ansi(config.ansi).boxed(),
+                LogFormat::Json => layer.with_ansi(false).json().boxed(),
+            }
+        }
+    };
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(layer)
+        .try_init()
+        .context("Logging was already initialized")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LogConfig, LogFormat};
+
+    #[test]
+    fn parses_formats_and_validates_levels() {
+        assert_eq!("JSON".parse::<LogFormat>(), Ok(LogFormat::Json));
+        assert!("xml".parse::<LogFormat>().is_err());
+
+        let config = LogConfig {
+            level: Some("oxide_rs=debug,hyper=warn".to_string()),
+            ..Default::default()
+        };
+        assert!(config.is_requested());
+        assert!(config.filter().is_ok());
+        let bad = LogConfig {
+            level: Some("oxide_rs=loud".to_string()),
+            ..Default::default()
+        };
+        assert!(bad.filter().is_err());
+        assert!(!LogConfig::default().is_requested());
+    }
+}
@@ -1,10 +1,25 @@
 pub mod banner;
+pub mod compare;
 pub mod download;
+pub mod i18n;
 pub mod loader;
+pub mod logging;
+pub mod picker;
+pub mod render;
+pub mod scripts;
+pub mod shell_hook;
+pub mod step;
 pub mod stream;
+pub mod terminal;
 pub mod theme;
 
 pub use banner::{print_banner, print_divider};
+pub use compare::{print_side_by_side, CompareColumn};
 pub use download::{DownloadProgressBar, Spinner};
 pub use loader::{print_model_info, ModelLoader};
-pub use stream::{print_welcome, PromptDisplay, StreamOutput, ThinkingSpinner};
+pub use picker::pick_model;
+pub use render::{RenderMsg, Renderer};
+pub use scripts::{ScriptHost, ScriptSettings};
+pub use shell_hook::{HookOutcome, ShellHook};
+pub use step::step_reply;
+pub use stream::{print_welcome, show_probabilities, PromptDisplay, StreamOutput};
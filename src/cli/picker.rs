@@ -0,0 +1,212 @@
+use std::io::Write;
+use std::path::PathBuf;
+
+use anyhow::Result;
+use crossterm::{
+    cursor::{Hide, MoveTo},
+    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    execute, queue,
+    style::{Attribute, Print, ResetColor, SetAttribute, SetForegroundColor},
+    terminal::{Clear, ClearType},
+};
+
+use super::terminal::{enter_fullscreen, leave_fullscreen, restore_after_resume, suspend};
+use super::theme::{self, Theme};
+use crate::model::{format_size, ModelEntry};
+
+const VISIBLE_ROWS: usize = 12;
+
+/// Full-screen fuzzy picker over `models`. Typing filters by id and file
+/// name, arrows move, Enter picks and Esc cancels. Returns the chosen path,
+/// or `None` when cancelled.
+pub fn pick_model(models: &[ModelEntry]) -> Result<Option<PathBuf>> {
+    let mut stdout = theme::stdout();
+    enter_fullscreen()?;
+    execute!(stdout, Hide)?;
+
+    let result = run_picker(&mut stdout, models);
+
+    leave_fullscreen().ok();
+    result
+}
+
+fn run_picker(stdout: &mut theme::Output, models: &[ModelEntry]) -> Result<Option<PathBuf>> {
+    let mut query = String::new();
+    let mut selected = 0usize;
+
+    loop {
+        let matches = filter_models(models, &query);
+        selected = selected.min(matches.len().saturating_sub(1));
+        if restore_after_resume()? {
+            execute!(stdout, Hide)?;
+        }
+        draw(stdout, models, &matches, &query, selected)?;
+
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Esc => return Ok(None),
+            KeyCode::Char('c') if ctrl => return Ok(None),
+            KeyCode::Char('z') if ctrl => {
+                let suspended = suspend()?;
+                if suspended {
+                    execute!(stdout, Hide)?;
+                }
+            }
+            KeyCode::Enter => {
+                if let Some(&index) = matches.get(selected) {
+                    return Ok(Some(models[index].path.clone()));
+                }
+            }
+            KeyCode::Up => selected = selected.saturating_sub(1),
+            KeyCode::Char('p') if ctrl => selected = selected.saturating_sub(1),
+            KeyCode::Down => selected += 1,
+            KeyCode::Char('n') if ctrl => selected += 1,
+            KeyCode::Backspace => {
+                query.pop();
+                selected = 0;
+            }
+            KeyCode::Char(c) if !ctrl => {
+                query.push(c);
+                selected = 0;
+            }
+            _ => {}
+        }
+    }
+}
+
+fn draw(
+    stdout: &mut theme::Output,
+    models: &[ModelEntry],
+    matches: &[usize],
+    query: &str,
+    selected: usize,
+) -> Result<()> {
+    queue!(
+        stdout,
+        MoveTo(0, 0),
+        Clear(ClearType::All),
+        SetForegroundColor(Theme::RUST_ORANGE),
+        SetAttribute(Attribute::Bold),
+        Print("  🦀 Select a model"),
+        ResetColor,
+        SetForegroundColor(Theme::IRON_GRAY),
+        Print("  type to filter • ↑/↓ move • Enter load • Esc cancel\r\n\r\n"),
+        SetForegroundColor(Theme::ACCENT_CYAN),
+        Print("  > "),
+        ResetColor,
+        Print(query),
+        Print("\r\n\r\n")
+    )?;
+
+    if matches.is_empty() {
+        queue!(
+            stdout,
+            SetForegroundColor(Theme::TEXT_SECONDARY),
+            Print("  No matching models\r\n"),
+            ResetColor
+        )?;
+    }
+
+    // Keep the selection inside the visible window.
+    let first = selected.saturating_sub(VISIBLE_ROWS - 1);
+    for (row, &index) in matches.iter().enumerate().skip(first).take(VISIBLE_ROWS) {
+        let model = &models[index];
+        let (marker, color) = if row == selected {
+            ("❯ ", Theme::RUST_ORANGE)
+        } else {
+            ("  ", Theme::TEXT_PRIMARY)
+        };
+        queue!(
+            stdout,
+            SetForegroundColor(color),
+            Print(format!("  {}{:<40}", marker, model.id)),
+            SetForegroundColor(Theme::ACCENT_CYAN),
+            Print(format!(
+                " {:<8}",
+                model.quantization.as_deref().unwrap_or("-")
+            )),
+            SetForegroundColor(Theme::TEXT_SECONDARY),
+            Print(format!(" {:>10}", format_size(model.size_bytes))),
+            SetForegroundColor(Theme::IRON_GRAY),
+            Print(format!("  {}\r\n", model.path.display())),
+            ResetColor
+        )?;
+    }
+
+    queue!(
+        stdout,
+        SetForegroundColor(Theme::IRON_GRAY),
+        Print(format!(
+            "\r\n  {}/{} models\r\n",
+            matches.len(),
+            models.len()
+        )),
+        ResetColor
+    )?;
+    stdout.flush()?;
+    Ok(())
+}
+
+/// Indices of the models matching `query`, best match first.
+fn filter_models(models: &[ModelEntry], query: &str) -> Vec<usize> {
+    let mut scored: Vec<(i32, usize)> = models
+        .iter()
+        .enumerate()
+        .filter_map(|(i, m)| {
+            let haystack = format!("{} {}", m.id, m.filename);
+            fuzzy_score(query, &haystack).map(|score| (score, i))
+        })
+        .collect();
+    scored.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+    scored.into_iter().map(|(_, i)| i).collect()
+}
+
+/// Scores `query` as a case-insensitive subsequence of `candidate`, or
+/// `None` if it is not one. Consecutive matches and matches at the start of
+/// a word score higher; gaps cost a little.
+fn fuzzy_score(query: &str, candidate: &str) -> Option<i32> {
+    let candidate: Vec<char> = candidate.to_lowercase().chars().collect();
+    let mut score = 0;
+    let mut pos = 0;
+    let mut previous: Option<usize> = None;
+
+    for q in query.to_lowercase().chars().filter(|c| !c.is_whitespace()) {
+        let offset = candidate[pos..].iter().position(|&c| c == q)?;
+        let index = pos + offset;
+        score += 1;
+        if previous.is_some_and(|p| p + 1 == index) {
+            score += 5;
+        } else if index == 0 || !candidate[index - 1].is_alphanumeric() {
+            score += 3;
+        }
+        score -= offset.min(10) as i32 / 2;
+        previous = Some(index);
+        pos = index + 1;
+    }
+    Some(score)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fuzzy_score;
+
+    #[test]
+    fn fuzzy_score_prefers_tight_matches() {
+        assert_eq!(fuzzy_score("", "anything"), Some(0));
+        assert!(fuzzy_score("qwx", "qwen3-0.6b-q4_k_m").is_none());
+
+        let tight = fuzzy_score("q4k", "q4_k_m").unwrap();
+        let loose = fuzzy_score("q4k", "qwen3-4b-k").unwrap();
+        assert!(tight > loose);
+
+        let exact = fuzzy_score("lfm", "lfm2-1.2b").unwrap();
+        let scattered = fuzzy_score("lfm", "llama-f16-m").unwrap();
+        assert!(exact > scattered);
+    }
+}
@@ -0,0 +1,187 @@
+//! A render thread that owns stdout while a reply streams.
+//!
+//! The generator callback, the thinking spinner and the stats line all send
+//! [`RenderMsg`]s to one thread instead of writing to stdout themselves, so
+//! a spinner frame can never land in the middle of a token or its escape
+//! codes. The spinner is animated by that same thread between messages and
+//! is cleared before anything else is drawn.
+
+use std::io::Write;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crossterm::{
+    cursor::MoveToColumn,
+    execute,
+    style::{Color, Print, ResetColor, SetForegroundColor},
+    terminal::{Clear, ClearType},
+};
+
+use super::terminal;
+use super::theme::{self, Theme};
+
+const THINKING_FRAMES: &[&str] = &[
+    "🦀💭 Thinking.",
+    "🦀💭 Thinking..",
+    "🦀💭 Thinking...",
+    "🦀💭 Thinking",
+];
+
+const FRAME_INTERVAL: Duration = Duration::from_millis(200);
+
+pub enum RenderMsg {
+    /// Show the thinking spinner until the next output.
+    Thinking,
+    /// Reply text, printed as is.
+    Text(String),
+    /// Reply text in `color`, for `--show-probs`.
+    Shaded { color: Color, text: String },
+    /// A line in `color`, followed by a blank line.
+    Notice { color: Color, text: String },
+    /// The stats line printed after a reply.
+    Stats(String),
+}
+
+/// Handle to the render thread. Dropping it (or calling [`Renderer::close`])
+/// waits until everything sent so far is on screen.
+pub struct Renderer {
+    tx: Option<Sender<RenderMsg>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Renderer {
+    pub fn new() -> Self {
+        let (tx, rx) = mpsc::channel();
+        let handle = thread::spawn(move || render_loop(rx));
+        Self {
+            tx: Some(tx),
+            handle: Some(handle),
+        }
+    }
+
+    pub fn send(&self, msg: RenderMsg) {
+        if let Some(tx) = &self.tx {
+            tx.send(msg).ok();
+        }
+    }
+
+    /// Stops the render thread once it has drawn every pending message.
+    pub fn close(&mut self) {
+        self.tx.take();
+        if let Some(handle) = self.handle.take() {
+            handle.join().ok();
+        }
+    }
+}
+
+impl Default for Renderer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Renderer {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+fn render_loop(rx: Receiver<RenderMsg>) {
+    let mut stdout = theme::stdout();
+    // Frame index while the spinner is showing.
+    let mut spinner: Option<usize> = None;
+
+    loop {
+        let msg = match spinner {
+            Some(_) => match rx.recv_timeout(FRAME_INTERVAL) {
+                Ok(msg) => Some(msg),
+                Err(RecvTimeoutError::Timeout) => None,
+                Err(RecvTimeoutError::Disconnected) => break,
+            },
+            None => match rx.recv() {
+                Ok(msg) => Some(msg),
+                Err(_) => break,
+            },
+        };
+
+        let drawn = terminal::draw_frame(|| match msg {
+            // Spinners only animate on a terminal.
+            Some(RenderMsg::Thinking) if theme::is_plain_output() => {}
+            Some(RenderMsg::Thinking) => {
+                spinner_frame(&mut stdout, spinner.get_or_insert(0));
+            }
+            Some(msg) => {
+                if spinner.take().is_some() {
+                    execute!(stdout, MoveToColumn(0), Clear(ClearType::CurrentLine)).ok();
+                }
+                draw(&mut stdout, msg);
+                stdout.flush().ok();
+            }
+            None => {
+                if let Some(frame) = spinner.as_mut() {
+                    spinner_frame(&mut stdout, frame);
+                }
+            }
+        });
+        if !drawn {
+            return;
+        }
+    }
+
+    if spinner.is_some() {
+        execute!(stdout, MoveToColumn(0), Clear(ClearType::CurrentLine)).ok();
+        stdout.flush().ok();
+    }
+}
+
+fn draw(stdout: &mut theme::Output, msg: RenderMsg) {
+    match msg {
+        RenderMsg::Thinking => {}
+        RenderMsg::Text(text) => {
+            execute!(stdout, Print(text)).ok();
+        }
+        RenderMsg::Shaded { color, text } => {
+            execute!(stdout, SetForegroundColor(color), Print(text), ResetColor).ok();
+        }
+        RenderMsg::Notice { color, text } => {
+            execute!(
+                stdout,
+                SetForegroundColor(color),
+                Print(text),
+                ResetColor,
+                Print("\n\n")
+            )
+            .ok();
+        }
+        RenderMsg::Stats(text) => {
+            execute!(
+                stdout,
+                ResetColor,
+                Print("\n"),
+                SetForegroundColor(Theme::IRON_GRAY),
+                Print("  ▸ "),
+                ResetColor,
+                SetForegroundColor(Theme::TEXT_SECONDARY),
+                Print(text),
+                ResetColor,
+                Print("\n")
+            )
+            .ok();
+        }
+    }
+}
+
+fn spinner_frame(stdout: &mut theme::Output, frame: &mut usize) {
+    execute!(
+        stdout,
+        MoveToColumn(0),
+        Clear(ClearType::CurrentLine),
+        SetForegroundColor(Theme::ACCENT_CYAN),
+        Print(THINKING_FRAMES[*frame % THINKING_FRAMES.len()]),
+        ResetColor
+    )
+    .ok();
+    stdout.flush().ok();
+    *frame = frame.wrapping_add(1);
+}
@@ -0,0 +1,317 @@
+//! User scripts for the REPL, written in [Rhai](https://rhai.rs).
+//!
+//! Every `*.rhai` file in `~/.oxide/scripts` may define:
+//!
+//! - `fn command(args)`: adds a slash-command named after the file, so
+//!   `jira.rhai` becomes `/jira`. Printed lines and a returned string are
+//!   shown to the user.
+//! - `fn transform(text)`: rewrites every reply before it is shown and kept
+//!   in the history. Transforms run in file name order.
+//!
+//! Scripts can only reach the outside world through the functions
+//! registered here: `history()`, `last_prompt()`, `last_response()` and,
+//! in commands, `generate(prompt)`. Rhai itself has no file or network
+//! access, and runaway scripts are stopped by an operation limit.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use anyhow::{Context, Result};
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Map, Scope, AST};
+
+use crate::inference::{Generator, Message};
+use crate::model::download::get_oxide_dir;
+
+/// Rhai operations one call may run before it is aborted.
+const MAX_OPERATIONS: u64 = 10_000_000;
+
+/// Generation settings for `generate()` calls from scripts.
+#[derive(Clone, Debug)]
+pub struct ScriptSettings {
+    pub max_tokens: usize,
+    pub repeat_penalty: f32,
+    pub repeat_last_n: usize,
+}
+
+struct Script {
+    name: String,
+    ast: AST,
+    command: bool,
+    transform: bool,
+}
+
+/// What registered functions can see during a call.
+#[derive(Default)]
+struct CallState {
+    /// Lent to the script host for the length of a command.
+    generator: Option<Generator>,
+    history: Vec<Message>,
+    output: String,
+}
+
+type SharedState = Arc<Mutex<CallState>>;
+
+pub struct ScriptHost {
+    engine: Engine,
+    scripts: Vec<Script>,
+    state: SharedState,
+}
+
+impl ScriptHost {
+    pub fn default_dir() -> Result<PathBuf> {
+        Ok(get_oxide_dir()?.join("scripts"))
+    }
+
+    /// A host without scripts.
+    pub fn empty(settings: ScriptSettings) -> Self {
+        let state = SharedState::default();
+        Self {
+            engine: build_engine(&state, settings),
+            scripts: Vec::new(),
+            state,
+        }
+    }
+
+    /// Compiles every `*.rhai` file in `dir`. A missing directory means no
+    /// scripts.
+    pub fn load(dir: &Path, settings: ScriptSettings) -> Result<Self> {
+        let mut host = Self::empty(settings);
+        if !dir.is_dir() {
+            return Ok(host);
+        }
+
+        let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)
+            .with_context(|| format!("Failed to read scripts in {:?}", dir))?
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "rhai"))
+            .collect();
+        paths.sort();
+        for path in paths {
+            let source = std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read script {:?}", path))?;
+            let name = path
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            host.add(&name, &source)
+                .with_context(|| format!("Invalid script {:?}", path))?;
+        }
+        Ok(host)
+    }
+
+    fn add(&mut self, name: &str, source: &str) -> Result<()> {
+        let ast = self
+            .engine
+            .compile(source)
+            .map_err(|e| anyhow::anyhow!("{}", e))?;
+        let defines = |wanted: &str| {
+            ast.iter_functions()
+                .any(|f| f.name == wanted && f.params.len() == 1)
+        };
+        let (command, transform) = (defines("command"), defines("transform"));
+        self.scripts.push(Script {
+            name: name.to_string(),
+            ast,
+            command,
+            transform,
+        });
+        Ok(())
+    }
+
+    /// Names of the slash-commands scripts define, without the `/`.
+    pub fn commands(&self) -> impl Iterator<Item = &str> {
+        self.scripts
+            .iter()
+            .filter(|s| s.command)
+            .map(|s| s.name.as_str())
+    }
+
+    pub fn has_command(&self, name: &str) -> bool {
+        self.commands().any(|command| command == name)
+    }
+
+    pub fn has_transforms(&self) -> bool {
+        self.scripts.iter().any(|s| s.transform)
+    }
+
+    /// Runs `/name args`. The generator is lent to the script for
+    /// `generate()` and always handed back, with its history unchanged.
+    pub fn run_command(
+        &self,
+        name: &str,
+        args: &str,
+        generator: Generator,
+    ) -> (Generator, Result<String>) {
+        let history = generator.history().to_vec();
+        self.lock().generator = Some(generator);
+        let result = self.call(name, "command", args, history);
+        let generator = self
+            .lock()
+            .generator
+            .take()
+            .expect("the generator is returned after a script command");
+        (generator, result)
+    }
+
+    /// Passes a finished reply through every `transform` function.
+    pub fn transform(&self, text: &str, history: &[Message]) -> Result<String> {
+        let mut text = text.to_string();
+        for script in self.scripts.iter().filter(|s| s.transform) {
+            text = self.call(&script.name, "transform", &text, history.to_vec())?;
+        }
+        Ok(text)
+    }
+
+    fn call(&self, name: &str, function: &str, arg: &str, history: Vec<Message>) -> Result<String> {
+        let script = self
+            .scripts
+            .iter()
+            .find(|s| s.name == name)
+            .with_context(|| format!("No script named '{}'", name))?;
+        {
+            let mut state = self.lock();
+            state.history = history;
+            state.output.clear();
+        }
+
+        let result = self.engine.call_fn::<Dynamic>(
+            &mut Scope::new(),
+            &script.ast,
+            function,
+            (arg.to_string(),),
+        );
+
+        let mut state = self.lock();
+        state.history.clear();
+        let mut output = std::mem::take(&mut state.output);
+        let value = result.map_err(|e| anyhow::anyhow!("{}.rhai: {}", name, e))?;
+        if function == "transform" {
+            return Ok(value.to_string());
+        }
+        if !value.is_unit() {
+            output.push_str(&value.to_string());
+        }
+        Ok(output)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, CallState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn build_engine(state: &SharedState, settings: ScriptSettings) -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+    engine.disable_symbol("eval");
+
+    let shared = state.clone();
+    engine.on_print(move |text| {
+        let mut state = shared.lock().unwrap_or_else(|e| e.into_inner());
+        state.output.push_str(text);
+        state.output.push('\n');
+    });
+
+    let shared = state.clone();
+    engine.register_fn("history", move || -> Array {
+        let state = shared.lock().unwrap_or_else(|e| e.into_inner());
+        state
+            .history
+            .iter()
+            .map(|message| {
+                let mut map = Map::new();
+                map.insert("role".into(), message.role.clone().into());
+                map.insert("content".into(), message.content.clone().into());
+                Dynamic::from_map(map)
+            })
+            .collect()
+    });
+
+    for (function, role) in [("last_prompt", "user"), ("last_response", "assistant")] {
+        let shared = state.clone();
+        engine.register_fn(function, move || -> String {
+            let state = shared.lock().unwrap_or_else(|e| e.into_inner());
+            state
+                .history
+                .iter()
+                .rev()
+                .find(|m| m.role == role)
+                .map(|m| m.content.clone())
+                .unwrap_or_default()
+        });
+    }
+
+    let shared = state.clone();
+    engine.register_fn(
+        "generate",
+        move |prompt: &str| -> Result<String, Box<EvalAltResult>> {
+            let mut state = shared.lock().unwrap_or_else(|e| e.into_inner());
+            let generator = state
+                .generator
+                .as_mut()
+                .ok_or("generate() is only available in commands")?;
+            // The exchange must not end up in the conversation.
+            let saved = generator.history().to_vec();
+            let reply = generator.generate(
+                prompt,
+                settings.max_tokens,
+                settings.repeat_penalty,
+                settings.repeat_last_n,
+                |_| {},
+            );
+            generator
+                .set_history(saved)
+                .map_err(|e| format!("{:#}", e))?;
+            reply.map_err(|e| format!("{:#}", e).into())
+        },
+    );
+
+    engine
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ScriptHost, ScriptSettings};
+    use crate::inference::Message;
+
+    #[test]
+    fn runs_commands_and_transforms() {
+        let settings = ScriptSettings {
+            max_tokens: 16,
+            repeat_penalty: 1.1,
+            repeat_last_n: 64,
+        };
+        let mut host = ScriptHost::empty(settings);
+        host.add(
+            "jira",
+            r#"
+            fn command(args) {
+                print("Summary: " + args);
+                "Details: " + last_response()
+            }
+            "#,
+        )
+        .unwrap();
+        host.add("shout", "fn transform(text) { text.to_upper() }")
+            .unwrap();
+        host.add("ask", "fn command(args) { generate(args) }")
+            .unwrap();
+
+        assert_eq!(host.commands().collect::<Vec<_>>(), ["jira", "ask"]);
+        assert!(host.has_transforms());
+
+        let history = vec![
+            Message::new("user", "hi"),
+            Message::new("assistant", "hello"),
+        ];
+        assert_eq!(
+            host.call("jira", "command", "Fix login", history.clone())
+                .unwrap(),
+            "Summary: Fix login\nDetails: hello"
+        );
+        assert_eq!(host.transform("hello", &history).unwrap(), "HELLO");
+        // Without a generator lent by run_command, generate() fails.
+        assert!(host.call("ask", "command", "x", history).is_err());
+        assert!(host.add("bad", "fn command(args) {").is_err());
+    }
+}
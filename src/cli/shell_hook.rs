@@ -0,0 +1,128 @@
+//! User commands run around each exchange (`--pre-prompt-cmd`,
+//! `--post-response-cmd`, `--collect-hook`).
+//!
+//! The command runs through the shell with the prompt or reply on stdin.
+//! Whatever it prints replaces that text; printing nothing keeps it as is,
+//! so logging hooks can simply consume their input. A non-zero exit blocks
+//! the prompt or reply, with stderr as the reason.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::thread;
+
+use anyhow::{Context, Result};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HookOutcome {
+    /// Go on with this text.
+    Continue(String),
+    /// Blocked, with the hook's stderr.
+    Veto(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct ShellHook {
+    command: String,
+    /// Value of `OXIDE_HOOK` in the command's environment, so one script
+    /// can serve both hooks.
+    kind: &'static str,
+}
+
+impl ShellHook {
+    pub fn pre_prompt(command: impl Into<String>) -> Self {
+        Self {
+            command: command.into(),
+            kind: "pre_prompt",
+        }
+    }
+
+    pub fn post_response(command: impl Into<String>) -> Self {
+        Self {
+            command: command.into(),
+            kind: "post_response",
+        }
+    }
+
+    /// Hook for `--collect` records, given as JSON.
+    pub fn collect(command: impl Into<String>) -> Self {
+        Self {
+            command: command.into(),
+            kind: "collect",
+        }
+    }
+
+    pub fn run(&self, input: &str) -> Result<HookOutcome> {
+        let (shell, flag) = if cfg!(windows) {
+            ("cmd", "/C")
+        } else {
+            ("sh", "-c")
+        };
+        let mut child = Command::new(shell)
+            .arg(flag)
+            .arg(&self.command)
+            .env("OXIDE_HOOK", self.kind)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to run hook `{}`", self.command))?;
+
+        // Written from another thread so a hook that prints before reading
+        // all of its input cannot deadlock on a full pipe.
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        let input = input.to_string();
+        let writer = thread::spawn(move || {
+            // A hook may exit without reading its input.
+            stdin.write_all(input.as_bytes()).ok();
+        });
+        let output = child.wait_with_output()?;
+        writer.join().ok();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+
+        if !output.status.success() {
+            let reason = String::from_utf8_lossy(&output.stderr).trim().to_string();
+            return Ok(HookOutcome::Veto(reason));
+        }
+        let text = stdout.strip_suffix('\n').unwrap_or(&stdout);
+        let text = text.strip_suffix('\r').unwrap_or(text);
+        Ok(HookOutcome::Continue(text.to_string()))
+    }
+
+    /// Runs the hook, keeping `input` when the hook prints nothing. Failures
+    /// to run the command block, like a non-zero exit.
+    pub fn apply(&self, input: &str) -> HookOutcome {
+        match self.run(input) {
+            Ok(HookOutcome::Continue(text)) if text.trim().is_empty() => {
+                HookOutcome::Continue(input.to_string())
+            }
+            Ok(outcome) => outcome,
+            Err(e) => HookOutcome::Veto(format!("{:#}", e)),
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::{HookOutcome, ShellHook};
+
+    #[test]
+    fn rewrites_keeps_and_vetoes() {
+        let upper = ShellHook::pre_prompt("tr a-z A-Z");
+        assert_eq!(upper.apply("hi"), HookOutcome::Continue("HI".to_string()));
+
+        let silent = ShellHook::post_response("cat > /dev/null");
+        assert_eq!(silent.apply("hi"), HookOutcome::Continue("hi".to_string()));
+
+        let kind = ShellHook::post_response("echo $OXIDE_HOOK");
+        assert_eq!(
+            kind.apply("hi"),
+            HookOutcome::Continue("post_response".to_string())
+        );
+
+        let veto = ShellHook::pre_prompt("echo 'no secrets' >&2; exit 1");
+        assert_eq!(
+            veto.apply("hi"),
+            HookOutcome::Veto("no secrets".to_string())
+        );
+    }
+}
@@ -0,0 +1,207 @@
+//! Building a reply one token at a time (`oxide-rs step`, `/step`).
+//!
+//! Every step shows the most likely next tokens with the chance the sampler
+//! would pick each. The user takes the first with Enter, picks another by
+//! number, writes text of their own, or undoes the last step. Each step
+//! prefills the conversation again, so this is for studying how a model
+//! behaves, not for speed.
+
+use std::io::{self, BufRead, Write};
+
+use anyhow::Result;
+use crossterm::execute;
+use crossterm::style::{Color, Print, ResetColor, SetForegroundColor};
+
+use super::theme::{self, Theme};
+use crate::inference::{probability_rgb, Generator, Message, TokenCandidate};
+
+/// Characters of the reply shown above the candidates.
+const REPLY_TAIL: usize = 72;
+
+/// What the user typed at a step.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StepInput {
+    /// Take candidate `n`, counting from 0. Enter takes the first.
+    Pick(usize),
+    /// Append this text instead of a candidate.
+    Write(String),
+    /// Take back what the last step added.
+    Undo,
+    /// End the reply here.
+    Stop,
+}
+
+impl StepInput {
+    /// Parses a line typed with `candidates` on screen: Enter, a number,
+    /// `e <text>` (`\n` for a line break), `u` or `q`.
+    pub fn parse(line: &str, candidates: usize) -> Option<Self> {
+        let line = line.trim_end_matches(['\r', '\n']);
+        if let Some(text) = line.strip_prefix("e ") {
+            let text = text.replace("\\n", "\n");
+            return (!text.is_empty()).then_some(StepInput::Write(text));
+        }
+        match line.trim() {
+            "" => Some(StepInput::Pick(0)),
+            "u" => Some(StepInput::Undo),
+            "q" => Some(StepInput::Stop),
+            number => number
+                .parse::<usize>()
+                .ok()
+                .filter(|&n| (1..=candidates).contains(&n))
+                .map(|n| StepInput::Pick(n - 1)),
+        }
+    }
+}
+
+/// Steps through a reply to `prompt`, showing `top_n` candidates a step, for
+/// at most `max_tokens` steps (`0`: until the user stops or the context is
+/// full). The reply joins the history like a generated one and is
+/// returned; an empty reply leaves the history as it was.
+pub fn step_reply(
+    generator: &mut Generator,
+    prompt: &str,
+    top_n: usize,
+    max_tokens: usize,
+) -> Result<String> {
+    let previous = generator.history().to_vec();
+    let mut turn = previous.clone();
+    turn.push(Message::new("user", prompt));
+    // Text added by each step, so a step can be undone.
+    let mut steps = Vec::new();
+    let outcome = run_steps(generator, &turn, &mut steps, top_n.max(1), max_tokens);
+
+    let reply = steps.concat();
+    let mut history = previous;
+    if outcome.is_ok() && !reply.is_empty() {
+        history = turn;
+        history.push(Message::new("assistant", reply.clone()));
+    }
+    generator.set_history(history)?;
+    outcome.map(|()| reply)
+}
+
+fn run_steps(
+    generator: &mut Generator,
+    turn: &[Message],
+    steps: &mut Vec<String>,
+    top_n: usize,
+    max_tokens: usize,
+) -> Result<()> {
+    let mut stdin = io::stdin().lock();
+    while max_tokens == 0 || steps.len() < max_tokens {
+        let reply = steps.concat();
+        let mut messages = turn.to_vec();
+        if !reply.is_empty() {
+            messages.push(Message::new("assistant", reply.clone()));
+        }
+        generator.set_history(messages)?;
+        let candidates = generator.peek_next_token_distribution(top_n)?;
+        show_step(steps.len() + 1, &reply, &candidates)?;
+
+        let input = loop {
+            let mut line = String::new();
+            if stdin.read_line(&mut line)? == 0 {
+                println!();
+                break StepInput::Stop;
+            }
+            match StepInput::parse(&line, candidates.len()) {
+                Some(input) => break input,
+                None => show_prompt()?,
+            }
+        };
+        match input {
+            StepInput::Pick(index) if candidates[index].ends_reply => break,
+            StepInput::Pick(index) => steps.push(candidates[index].token.clone()),
+            StepInput::Write(text) => steps.push(text),
+            StepInput::Undo => {
+                steps.pop();
+            }
+            StepInput::Stop => break,
+        }
+    }
+    Ok(())
+}
+
+fn show_step(step: usize, reply: &str, candidates: &[TokenCandidate]) -> io::Result<()> {
+    let mut stdout = theme::stdout();
+    execute!(
+        stdout,
+        Print("\n"),
+        SetForegroundColor(Theme::TEXT_SECONDARY),
+        Print(format!("  Step {} │ ", step)),
+        ResetColor,
+        Print(format!("{}\n", reply_tail(reply))),
+    )?;
+    for (number, candidate) in candidates.iter().enumerate() {
+        let label = if candidate.ends_reply {
+            "⟨end of reply⟩".to_string()
+        } else {
+            format!("{:?}", candidate.token)
+        };
+        let (r, g, b) = probability_rgb(candidate.probability);
+        execute!(
+            stdout,
+            Print(format!("  {:>3}  ", number + 1)),
+            SetForegroundColor(Color::Rgb { r, g, b }),
+            Print(format!("{:<24}", label)),
+            ResetColor,
+            SetForegroundColor(Theme::TEXT_SECONDARY),
+            Print(format!(
+                " {:>6.1}%  logprob {:.2}\n",
+                candidate.probability * 100.0,
+                candidate.logprob
+            )),
+            ResetColor,
+        )?;
+    }
+    show_prompt()
+}
+
+fn show_prompt() -> io::Result<()> {
+    let mut stdout = theme::stdout();
+    execute!(
+        stdout,
+        SetForegroundColor(Theme::TEXT_SECONDARY),
+        Print("  Enter: take 1 · number: pick · e <text>: write · u: undo · q: stop\n"),
+        SetForegroundColor(Theme::RUST_ORANGE),
+        Print("  › "),
+        ResetColor,
+    )?;
+    stdout.flush()
+}
+
+/// The end of `reply` on one line, line breaks shown as `⏎`.
+fn reply_tail(reply: &str) -> String {
+    let chars: Vec<char> = reply.chars().collect();
+    let start = chars.len().saturating_sub(REPLY_TAIL);
+    let tail: String = chars[start..]
+        .iter()
+        .map(|&c| if c == '\n' { '⏎' } else { c })
+        .collect();
+    if start > 0 {
+        format!("…{}", tail)
+    } else {
+        tail
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StepInput;
+
+    #[test]
+    fn parses_step_commands() {
+        assert_eq!(StepInput::parse("\n", 5), Some(StepInput::Pick(0)));
+        assert_eq!(StepInput::parse("3\n", 5), Some(StepInput::Pick(2)));
+        assert_eq!(StepInput::parse("6\n", 5), None);
+        assert_eq!(StepInput::parse("0", 5), None);
+        assert_eq!(
+            StepInput::parse("e  Paris\\n\n", 5),
+            Some(StepInput::Write(" Paris\n".to_string()))
+        );
+        assert_eq!(StepInput::parse("e ", 5), None);
+        assert_eq!(StepInput::parse(" u ", 5), Some(StepInput::Undo));
+        assert_eq!(StepInput::parse("q\r\n", 5), Some(StepInput::Stop));
+        assert_eq!(StepInput::parse("quit", 5), None);
+    }
+}
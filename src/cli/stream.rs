@@ -1,24 +1,18 @@
-use std::io::{self, Write};
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
-use std::thread::{self, JoinHandle};
-use std::time::{Duration, Instant};
+use std::io::Write;
+use std::sync::OnceLock;
+use std::time::Instant;
 
 use crossterm::{
-    cursor::MoveToColumn,
     execute,
-    style::{Attribute, Print, ResetColor, SetAttribute, SetForegroundColor},
-    terminal::{Clear, ClearType},
+    style::{Attribute, Color, Print, ResetColor, SetAttribute, SetForegroundColor},
 };
 
-use super::theme::Theme;
-
-const THINKING_FRAMES: &[&str] = &[
-    "🦀💭 Thinking.",
-    "🦀💭 Thinking..",
-    "🦀💭 Thinking...",
-    "🦀💭 Thinking",
-];
+use super::i18n::{self, Msg};
+use super::render::{RenderMsg, Renderer};
+use super::theme::{self, Theme};
+use crate::inference::{
+    probability_rgb, Confidence, ProbabilityTap, StreamChunker, StreamGranularity,
+};
 
 pub fn format_token_count(n: usize) -> String {
     if n >= 1_000_000 {
@@ -38,70 +32,6 @@ pub fn format_token_count(n: usize) -> String {
     }
 }
 
-pub struct ThinkingSpinner {
-    running: Arc<AtomicBool>,
-    handle: Option<JoinHandle<()>>,
-}
-
-impl ThinkingSpinner {
-    pub fn new() -> Self {
-        let running = Arc::new(AtomicBool::new(true));
-
-        let handle = thread::spawn({
-            let running = running.clone();
-            move || {
-                let mut stdout = io::stdout();
-                let mut i = 0usize;
-
-                while running.load(Ordering::Relaxed) {
-                    let frame = THINKING_FRAMES[i % THINKING_FRAMES.len()];
-
-                    execute!(
-                        stdout,
-                        MoveToColumn(0),
-                        Clear(ClearType::CurrentLine),
-                        SetForegroundColor(Theme::ACCENT_CYAN),
-                        Print(frame),
-                        ResetColor
-                    )
-                    .ok();
-
-                    stdout.flush().ok();
-                    thread::sleep(Duration::from_millis(200));
-                    i = i.wrapping_add(1);
-                }
-            }
-        });
-
-        Self {
-            running,
-            handle: Some(handle),
-        }
-    }
-
-    pub fn stop(mut self) {
-        self.running.store(false, Ordering::Relaxed);
-        if let Some(h) = self.handle.take() {
-            h.join().ok();
-        }
-
-        let mut stdout = io::stdout();
-        execute!(stdout, MoveToColumn(0), Clear(ClearType::CurrentLine)).ok();
-    }
-}
-
-impl Default for ThinkingSpinner {
-    fn default() -> Self {
-        Self::new()
-    }
-}
-
-impl Drop for ThinkingSpinner {
-    fn drop(&mut self) {
-        self.running.store(false, Ordering::Relaxed);
-    }
-}
-
 pub fn strip_special_tokens(input: &str) -> String {
     let mut result = input.to_string();
 
@@ -133,27 +63,60 @@ pub fn strip_special_tokens(input: &str) -> String {
     result.trim().to_string()
 }
 
+static PROBABILITIES: OnceLock<ProbabilityTap> = OnceLock::new();
+
+/// Colors the replies every [`StreamOutput`] prints from then on by the
+/// token probabilities `tap` collects (`--show-probs`). Text is then
+/// printed token by token, whatever the stream granularity.
+pub fn show_probabilities(tap: ProbabilityTap) {
+    let _ = PROBABILITIES.set(tap);
+}
+
+/// Prints a streamed reply. All output, including the thinking spinner,
+/// goes through one [`Renderer`] thread.
 pub struct StreamOutput {
-    stdout: io::Stdout,
+    renderer: Renderer,
+    chunker: StreamChunker,
+    /// Reply kept back from the screen, see [`StreamOutput::hold`].
+    held: Option<String>,
     first_token: bool,
     start_time: Instant,
     token_count: usize,
     context_used: usize,
     context_limit: usize,
     prompt_tokens: usize,
+    confidence: Option<Confidence>,
+    /// Cycle length when the loop guard ended the reply.
+    loop_period: Option<usize>,
+    probabilities: Option<ProbabilityTap>,
     finished: bool,
 }
 
 impl StreamOutput {
     pub fn new() -> Self {
+        Self::with_granularity(StreamGranularity::Token)
+    }
+
+    /// Prints text in words or sentences instead of token by token.
+    pub fn with_granularity(granularity: StreamGranularity) -> Self {
+        let probabilities = PROBABILITIES.get().cloned();
+        let granularity = match probabilities {
+            Some(_) => StreamGranularity::Token,
+            None => granularity,
+        };
         Self {
-            stdout: io::stdout(),
+            renderer: Renderer::new(),
+            chunker: StreamChunker::new(granularity),
+            held: None,
             first_token: true,
             start_time: Instant::now(),
             token_count: 0,
             context_used: 0,
             context_limit: 4096,
             prompt_tokens: 0,
+            confidence: None,
+            loop_period: None,
+            probabilities,
             finished: false,
         }
     }
@@ -167,12 +130,65 @@ impl StreamOutput {
         self.prompt_tokens = count;
     }
 
+    /// Adds the reply's entropy and margin to the stats line.
+    pub fn set_confidence(&mut self, confidence: Option<Confidence>) {
+        self.confidence = confidence;
+    }
+
+    /// Notes in the stats line that the reply was cut off for repeating a
+    /// cycle of `period` tokens.
+    pub fn set_loop_detected(&mut self, period: usize) {
+        self.loop_period = Some(period);
+    }
+
+    /// Shows the thinking spinner until the first token (or other output).
+    pub fn start_thinking(&mut self) {
+        self.renderer.send(RenderMsg::Thinking);
+    }
+
+    /// Prints a line of status text in order with the reply.
+    pub fn print_line(&mut self, text: &str) {
+        self.renderer.send(RenderMsg::Text(format!("{}\n", text)));
+    }
+
+    /// Collects the reply instead of printing it, so a post-response hook
+    /// can rewrite or block it first. See [`StreamOutput::take_held`].
+    pub fn hold(&mut self) {
+        self.held.get_or_insert_with(String::new);
+    }
+
+    /// The reply collected since [`StreamOutput::hold`]; later tokens are
+    /// printed again.
+    pub fn take_held(&mut self) -> Option<String> {
+        self.held.take()
+    }
+
+    /// Prints reply text that was not streamed, e.g. a held reply after a
+    /// hook ran. It is not counted as tokens.
+    pub fn release(&mut self, text: &str) {
+        if !text.is_empty() {
+            self.renderer.send(RenderMsg::Text(text.to_string()));
+        }
+    }
+
+    /// Prints a warning that older history was dropped to fit the prompt.
+    pub fn print_truncation_warning(&mut self, dropped_tokens: usize) {
+        self.renderer.send(RenderMsg::Notice {
+            color: Theme::FERRIS_ORANGE,
+            text: format!(
+                "  ⚠ Dropped {} tokens of older history to fit the context window",
+                format_token_count(dropped_tokens)
+            ),
+        });
+    }
+
     pub fn print_token(&mut self, token: &str) {
         if self.first_token {
             self.first_token = false;
         }
 
         self.token_count += 1;
+        let probability = self.probabilities.as_ref().and_then(ProbabilityTap::take);
 
         let cleaned = strip_special_tokens(token);
 
@@ -186,16 +202,54 @@ impl StreamOutput {
             } else {
                 cleaned
             };
-            execute!(self.stdout, Print(&output)).ok();
-            self.stdout.flush().ok();
+            if let Some(held) = self.held.as_mut() {
+                held.push_str(&output);
+            } else if let Some(probability) = probability {
+                let (r, g, b) = probability_rgb(probability);
+                self.renderer.send(RenderMsg::Shaded {
+                    color: Color::Rgb { r, g, b },
+                    text: output,
+                });
+            } else if let Some(chunk) = self.chunker.push(&output) {
+                self.renderer.send(RenderMsg::Text(chunk));
+            }
         }
     }
 
+    /// Ends a reply that failed part way: prints what was streamed, then
+    /// `message` instead of the stats line. A held reply is dropped, since
+    /// its hook never ran.
+    pub fn print_error(&mut self, message: &str) {
+        if self.finished {
+            return;
+        }
+        self.finished = true;
+        self.held = None;
+        if let Some(rest) = self.chunker.finish() {
+            self.renderer.send(RenderMsg::Text(rest));
+        }
+        self.renderer.send(RenderMsg::Text("\n".to_string()));
+        self.renderer.send(RenderMsg::Notice {
+            color: Theme::ERROR_RED,
+            text: format!(
+                "  ✗ Generation failed after {} tokens: {}",
+                self.token_count, message
+            ),
+        });
+        self.renderer.close();
+    }
+
     pub fn finish(&mut self) {
         if self.finished {
             return;
         }
         self.finished = true;
+        if let Some(rest) = self.chunker.finish() {
+            self.renderer.send(RenderMsg::Text(rest));
+        }
+        if let Some(held) = self.held.take() {
+            self.release(&held);
+        }
 
         let elapsed = self.start_time.elapsed();
         let tokens_per_sec = if elapsed.as_secs_f64() > 0.0 {
@@ -206,26 +260,26 @@ impl StreamOutput {
 
         let final_context = self.context_used + self.prompt_tokens + self.token_count;
 
-        execute!(
-            self.stdout,
-            ResetColor,
-            Print("\n"),
-            SetForegroundColor(Theme::IRON_GRAY),
-            Print("  ▸ "),
-            ResetColor,
-            SetForegroundColor(Theme::TEXT_SECONDARY),
-            Print(format!(
-                "{} tokens • {:.1} tok/s • Context: {}/{} • {:.1}s",
-                self.token_count,
-                tokens_per_sec,
-                format_token_count(final_context),
-                format_token_count(self.context_limit),
-                elapsed.as_secs_f64()
-            )),
-            ResetColor,
-            Print("\n")
-        )
-        .ok();
+        let mut stats = format!(
+            "{} tokens • {:.1} tok/s • Context: {}/{} • {:.1}s",
+            self.token_count,
+            tokens_per_sec,
+            format_token_count(final_context),
+            format_token_count(self.context_limit),
+            elapsed.as_secs_f64()
+        );
+        if let Some(confidence) = self.confidence {
+            stats.push_str(&format!(
+                " • Entropy: {:.2} • Margin: {:.2}",
+                confidence.mean_entropy, confidence.min_margin
+            ));
+        }
+        if let Some(period) = self.loop_period {
+            stats.push_str(&format!(" • Stopped: {}-token loop", period));
+        }
+        self.renderer.send(RenderMsg::Stats(stats));
+        // Everything is on screen before the caller prints again.
+        self.renderer.close();
     }
 }
 
@@ -236,13 +290,13 @@ impl Default for StreamOutput {
 }
 
 pub struct PromptDisplay {
-    stdout: io::Stdout,
+    stdout: theme::Output,
 }
 
 impl PromptDisplay {
     pub fn new() -> Self {
         Self {
-            stdout: io::stdout(),
+            stdout: theme::stdout(),
         }
     }
 
@@ -285,16 +339,19 @@ impl Default for PromptDisplay {
 }
 
 pub fn print_welcome() {
-    let mut stdout = io::stdout();
+    let (before, after) = i18n::text(Msg::Welcome)
+        .split_once("{}")
+        .unwrap_or_default();
+    let mut stdout = theme::stdout();
     execute!(
         stdout,
         SetForegroundColor(Theme::IRON_GRAY),
-        Print("  Type your message and press Enter. "),
+        Print(format!("  {}", before)),
         SetForegroundColor(Theme::TEXT_SECONDARY),
         Print("Ctrl+C"),
         ResetColor,
         SetForegroundColor(Theme::IRON_GRAY),
-        Print(" to exit.\n"),
+        Print(format!("{}\n", after)),
         ResetColor
     )
     .ok();
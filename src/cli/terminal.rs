@@ -0,0 +1,127 @@
+//! Terminal state that must be undone if the process panics.
+//!
+//! Raw mode and the alternate screen are entered through [`enter_fullscreen`]
+//! so the panic hook and job control know what to undo and redo, and spinner
+//! threads draw through [`draw_frame`] so they stop before the message of a
+//! panic that ends the process is printed.
+
+use std::io::{self, Write};
+use std::panic;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, Once};
+use std::thread;
+use std::time::Duration;
+
+use crossterm::{
+    cursor::Show,
+    execute,
+    style::ResetColor,
+    terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
+};
+
+use super::theme;
+use crate::platform;
+
+static FULLSCREEN: AtomicBool = AtomicBool::new(false);
+static PANICKED: AtomicBool = AtomicBool::new(false);
+/// Held while a spinner draws one frame.
+static DRAW_LOCK: Mutex<()> = Mutex::new(());
+static INSTALL: Once = Once::new();
+
+/// Enables raw mode and switches to the alternate screen.
+pub fn enter_fullscreen() -> io::Result<()> {
+    platform::watch_resume();
+    terminal::enable_raw_mode()?;
+    FULLSCREEN.store(true, Ordering::SeqCst);
+    execute!(io::stdout(), EnterAlternateScreen)
+}
+
+/// Undoes [`enter_fullscreen`] and shows the cursor again.
+pub fn leave_fullscreen() -> io::Result<()> {
+    FULLSCREEN.store(false, Ordering::SeqCst);
+    execute!(io::stdout(), Show, LeaveAlternateScreen)?;
+    terminal::disable_raw_mode()
+}
+
+/// Ctrl+Z for raw-mode screens, where the key does not stop the process by
+/// itself: leaves fullscreen, stops until `fg`, then enters it again.
+/// Returns whether the process was suspended, in which case the caller
+/// should redraw everything.
+pub fn suspend() -> io::Result<bool> {
+    let fullscreen = FULLSCREEN.load(Ordering::SeqCst);
+    if fullscreen {
+        leave_fullscreen()?;
+    }
+    let suspended = platform::suspend_process();
+    platform::take_resumed();
+    if fullscreen {
+        enter_fullscreen()?;
+    }
+    Ok(suspended)
+}
+
+/// After the process was stopped from outside (e.g. `kill -TSTP`) and
+/// continued, the shell may have reset the terminal. Re-applies raw mode and
+/// the alternate screen if so, and returns whether the caller should redraw.
+pub fn restore_after_resume() -> io::Result<bool> {
+    if !platform::take_resumed() {
+        return Ok(false);
+    }
+    if FULLSCREEN.load(Ordering::SeqCst) {
+        // crossterm skips enabling raw mode it believes is already on.
+        terminal::disable_raw_mode()?;
+        terminal::enable_raw_mode()?;
+        execute!(io::stdout(), EnterAlternateScreen)?;
+    }
+    Ok(true)
+}
+
+/// Runs `draw` unless a fatal panic is being reported. Returns `false` once
+/// the main thread has panicked, which tells spinner threads to exit.
+pub fn draw_frame(draw: impl FnOnce()) -> bool {
+    let _guard = DRAW_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    if PANICKED.load(Ordering::SeqCst) {
+        return false;
+    }
+    draw();
+    true
+}
+
+/// Installs a panic hook that, when the main thread panics, stops spinners
+/// and restores the terminal before printing the panic message on a clean
+/// line. Panics on other threads may be caught and recovered from, so they
+/// are only printed. Safe to call repeatedly.
+pub fn install_panic_hook() {
+    INSTALL.call_once(|| {
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if thread::current().name() == Some("main") {
+                PANICKED.store(true, Ordering::SeqCst);
+                // Wait for a frame being drawn to finish. The lock may be held
+                // by the panicking thread itself, so do not wait forever.
+                for _ in 0..50 {
+                    if DRAW_LOCK.try_lock().is_ok() {
+                        break;
+                    }
+                    thread::sleep(Duration::from_millis(10));
+                }
+                restore();
+            }
+            default_hook(info);
+        }));
+    });
+}
+
+fn restore() {
+    let mut stdout = io::stdout();
+    if FULLSCREEN.swap(false, Ordering::SeqCst) {
+        execute!(stdout, LeaveAlternateScreen).ok();
+    }
+    terminal::disable_raw_mode().ok();
+    if !theme::is_plain_output() {
+        // Clear a half-drawn spinner line so the message starts at column 0.
+        execute!(stdout, ResetColor, Show, Clear(ClearType::CurrentLine)).ok();
+        print!("\r");
+        stdout.flush().ok();
+    }
+}
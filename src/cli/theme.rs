@@ -1,3 +1,6 @@
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
 use crossterm::style::Color;
 
 pub struct Theme;
@@ -44,3 +47,104 @@ impl Theme {
         b: 253,
     };
 }
+
+static PLAIN: AtomicBool = AtomicBool::new(false);
+
+/// Switches to plain output (no colors, cursor movement or spinners) when
+/// stdout is not a terminal or `NO_COLOR` is set, e.g. in containers or
+/// when output is piped. Returns whether plain output was selected.
+pub fn detect_plain_output() -> bool {
+    let no_color = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+    let plain = no_color || !io::stdout().is_terminal();
+    set_plain_output(plain);
+    plain
+}
+
+pub fn set_plain_output(plain: bool) {
+    PLAIN.store(plain, Ordering::Relaxed);
+}
+
+/// Whether animations and escape sequences are turned off.
+pub fn is_plain_output() -> bool {
+    PLAIN.load(Ordering::Relaxed)
+}
+
+/// Stdout for the CLI widgets. In plain output it drops ANSI escape
+/// sequences, so styled widgets print as plain text.
+pub fn stdout() -> Output {
+    Output {
+        inner: io::stdout(),
+        filter: is_plain_output().then(EscapeFilter::default),
+    }
+}
+
+pub struct Output {
+    inner: io::Stdout,
+    filter: Option<EscapeFilter>,
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.filter {
+            Some(filter) => {
+                self.inner.write_all(&filter.strip(buf))?;
+                Ok(buf.len())
+            }
+            None => self.inner.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Removes `ESC [ ... final` (CSI) and two-byte escape sequences. Keeps its
+/// state between writes, since crossterm may split one sequence over
+/// several of them.
+#[derive(Default)]
+struct EscapeFilter {
+    state: EscapeState,
+}
+
+#[derive(Default, Clone, Copy, PartialEq)]
+enum EscapeState {
+    #[default]
+    Text,
+    Escape,
+    Csi,
+}
+
+impl EscapeFilter {
+    fn strip(&mut self, buf: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(buf.len());
+        for &byte in buf {
+            self.state = match (self.state, byte) {
+                (EscapeState::Text, 0x1b) => EscapeState::Escape,
+                (EscapeState::Text, _) => {
+                    out.push(byte);
+                    EscapeState::Text
+                }
+                (EscapeState::Escape, b'[') => EscapeState::Csi,
+                (EscapeState::Escape, _) => EscapeState::Text,
+                (EscapeState::Csi, 0x40..=0x7e) => EscapeState::Text,
+                (EscapeState::Csi, _) => EscapeState::Csi,
+            };
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EscapeFilter;
+
+    #[test]
+    fn strips_escape_sequences_split_across_writes() {
+        let mut filter = EscapeFilter::default();
+        let mut out = filter.strip(b"\x1b[38;2;1");
+        out.extend(filter.strip(b"0;2;3m\xe2\x9c\x93 done\x1b["));
+        out.extend(filter.strip(b"0m\x1b[2K\n"));
+        assert_eq!(String::from_utf8(out).unwrap(), "\u{2713} done\n");
+    }
+}
@@ -0,0 +1,254 @@
+//! A per-model daemon that lets short-lived CLI invocations share one loaded
+//! model (`--shared-runtime`).
+//!
+//! The OS already shares the mapped weights between processes, but every
+//! process still pays for loading, its own KV cache and its scratch
+//! buffers. The daemon loads the model once and answers requests over a
+//! unix socket, one at a time, in the order they connect; clients send a prompt with its sampling
+//! settings and read the reply back as it is generated.
+//!
+//! The protocol is newline-delimited JSON: one [`DaemonRequest`] per
+//! connection, answered by [`DaemonReply`] lines ending with `Done` or
+//! `Error`. Requests are independent: the daemon keeps no conversation
+//! between them.
+
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::inference::{CancelToken, Generator, StreamEvent};
+
+/// How long a client may take to send its request after connecting.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// One generation asked of the daemon.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DaemonRequest {
+    pub prompt: String,
+    pub system_prompt: Option<String>,
+    pub max_tokens: usize,
+    pub temperature: f64,
+    pub top_p: Option<f64>,
+    pub top_k: Option<usize>,
+    pub seed: u64,
+    pub repeat_penalty: f32,
+    pub repeat_last_n: usize,
+}
+
+/// One line of the daemon's answer.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DaemonReply {
+    Token(String),
+    /// The reply is complete.
+    Done,
+    Error(String),
+}
+
+/// The socket of the daemon serving `model`, in `$XDG_RUNTIME_DIR/oxide-rs`
+/// or `~/.oxide/run`. It is keyed by the model's canonical path, size and
+/// modification time, so a replaced file gets a new daemon.
+pub fn socket_path(model: &Path) -> Result<PathBuf> {
+    let canonical = model
+        .canonicalize()
+        .with_context(|| format!("Failed to open model file: {:?}", model))?;
+    let meta = std::fs::metadata(&canonical)?;
+    let modified = meta
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let key = format!("{}:{}:{}", canonical.display(), meta.len(), modified);
+    let digest = Sha256::digest(key.as_bytes());
+    let name: String = digest[..8].iter().map(|b| format!("{:02x}", b)).collect();
+
+    let dir = match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join("oxide-rs"),
+        None => crate::model::download::get_oxide_dir()?.join("run"),
+    };
+    std::fs::create_dir_all(&dir)?;
+    Ok(dir.join(format!("{}.sock", name)))
+}
+
+/// Listens on `socket`. Fails if another daemon already listens there; a
+/// socket file left behind by a daemon that died is replaced.
+///
+/// Clients may connect as soon as this returns; they wait for their reply
+/// while the model is still loading.
+pub fn bind(socket: &Path) -> Result<UnixListener> {
+    if socket.exists() {
+        if UnixStream::connect(socket).is_ok() {
+            anyhow::bail!("A daemon is already serving {}", socket.display());
+        }
+        std::fs::remove_file(socket)?;
+    }
+    UnixListener::bind(socket).with_context(|| format!("Failed to listen on {}", socket.display()))
+}
+
+/// Answers requests on `listener` with `generator`, one connection at a
+/// time, until none has arrived for `idle_timeout`. Removes the socket file
+/// on exit.
+pub fn serve(
+    mut generator: Generator,
+    listener: UnixListener,
+    idle_timeout: Duration,
+) -> Result<()> {
+    let socket = listener.local_addr()?.as_pathname().map(Path::to_path_buf);
+    listener.set_nonblocking(true)?;
+    tracing::info!("Daemon listening on {:?}", socket);
+
+    let mut last_used = Instant::now();
+    loop {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                if let Err(e) = handle_connection(stream, &mut generator) {
+                    tracing::warn!("Daemon request failed: {}", e);
+                }
+                last_used = Instant::now();
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                if last_used.elapsed() >= idle_timeout {
+                    tracing::info!("Daemon idle for {:?}, exiting", idle_timeout);
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(50));
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    if let Some(socket) = socket {
+        std::fs::remove_file(socket).ok();
+    }
+    Ok(())
+}
+
+fn handle_connection(stream: UnixStream, generator: &mut Generator) -> Result<()> {
+    stream.set_nonblocking(false)?;
+    // A client that connects and never writes must not stall the others.
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    let mut writer = &stream;
+    let request: DaemonRequest = match serde_json::from_str(&line) {
+        Ok(request) => request,
+        Err(e) => {
+            return send(
+                &mut writer,
+                &DaemonReply::Error(format!("Bad request: {}", e)),
+            )
+        }
+    };
+
+    // Stop generating as soon as the client is gone.
+    let cancel = CancelToken::new();
+    generator.set_cancel_token(Some(cancel.clone()));
+    let mut write_error = None;
+    let result = run_request(generator, &request, |token| {
+        if write_error.is_none() {
+            write_error = send(&mut writer, &DaemonReply::Token(token)).err();
+            if write_error.is_some() {
+                cancel.cancel();
+            }
+        }
+    });
+    generator.set_cancel_token(None);
+    generator.clear_history();
+
+    if let Some(e) = write_error {
+        return Err(e);
+    }
+    match result {
+        Ok(()) => send(&mut writer, &DaemonReply::Done),
+        Err(e) => send(&mut writer, &DaemonReply::Error(format!("{:#}", e))),
+    }
+}
+
+pub(crate) fn run_request(
+    generator: &mut Generator,
+    request: &DaemonRequest,
+    mut on_token: impl FnMut(String),
+) -> Result<()> {
+    generator.clear_history();
+    generator.set_system_prompt(request.system_prompt.clone())?;
+    generator.set_sampling(
+        request.temperature,
+        request.top_p,
+        request.top_k,
+        request.seed,
+    );
+    generator.generate_streaming(
+        &request.prompt,
+        request.max_tokens,
+        request.repeat_penalty,
+        request.repeat_last_n,
+        |event| {
+            if let StreamEvent::Token(token) = event {
+                on_token(token);
+            }
+        },
+    )?;
+    Ok(())
+}
+
+/// Writes `message` as one JSON line.
+pub(crate) fn send(writer: &mut impl Write, message: &impl Serialize) -> Result<()> {
+    let mut line = serde_json::to_string(message)?;
+    line.push('\n');
+    writer.write_all(line.as_bytes())?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Sends `request` to the daemon on `socket`, calling `on_token` with each
+/// piece of the reply, and returns the whole reply.
+pub fn request<F>(socket: &Path, request: &DaemonRequest, mut on_token: F) -> Result<String>
+where
+    F: FnMut(&str),
+{
+    let mut stream = UnixStream::connect(socket)
+        .with_context(|| format!("Failed to connect to {}", socket.display()))?;
+    send(&mut stream, request)?;
+
+    let mut text = String::new();
+    for line in BufReader::new(&stream).lines() {
+        match serde_json::from_str(&line?)? {
+            DaemonReply::Token(token) => {
+                on_token(&token);
+                text.push_str(&token);
+            }
+            DaemonReply::Done => return Ok(text),
+            DaemonReply::Error(e) => anyhow::bail!("{}", e),
+        }
+    }
+    anyhow::bail!("The daemon closed the connection before the reply was complete")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DaemonReply;
+
+    #[test]
+    fn replies_round_trip_as_json_lines() {
+        let replies = [
+            DaemonReply::Token("Hel".to_string()),
+            DaemonReply::Done,
+            DaemonReply::Error("model failed".to_string()),
+        ];
+        let lines: Vec<String> = replies
+            .iter()
+            .map(|r| serde_json::to_string(r).unwrap())
+            .collect();
+        assert_eq!(lines[0], r#"{"token":"Hel"}"#);
+        assert_eq!(lines[1], r#""done""#);
+        for (line, reply) in lines.iter().zip(&replies) {
+            assert!(!line.contains('\n'));
+            assert_eq!(&serde_json::from_str::<DaemonReply>(line).unwrap(), reply);
+        }
+    }
+}
@@ -0,0 +1,368 @@
+//! Conversations between several personas, optionally on different models.
+//!
+//! Agents take turns answering the transcript so far. Each agent sees its
+//! own turns as assistant messages and everyone else's as user messages
+//! prefixed with the speaker's name. The conversation ends after
+//! `max_turns`, when a reply contains the `stop` phrase, or when the
+//! moderator answers with its stop word after a round.
+//!
+//! Agents on the same model share one [`Generator`]: the system prompt and
+//! history are swapped in before every turn.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use super::generator::{Generator, Message, StreamEvent};
+
+const DEFAULT_MAX_TURNS: usize = 6;
+
+const DEFAULT_MODERATOR_PROMPT: &str = "You moderate a conversation between several \
+     participants. Read it and decide whether it should go on. Answer with a single \
+     word: CONTINUE if there is more worth saying, or STOP if the participants have \
+     reached a conclusion, are repeating themselves, or went off topic.";
+
+/// An `agents.toml` file.
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AgentsConfig {
+    /// Opening message every agent sees first.
+    pub topic: String,
+    /// Total agent replies before the conversation ends.
+    #[serde(default = "default_max_turns")]
+    pub max_turns: usize,
+    /// Ends the conversation when a reply contains this phrase.
+    #[serde(default)]
+    pub stop: Option<String>,
+    /// Participants, in speaking order (`[[agent]]` tables).
+    #[serde(rename = "agent")]
+    pub agents: Vec<AgentConfig>,
+    #[serde(default)]
+    pub moderator: Option<ModeratorConfig>,
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AgentConfig {
+    pub name: String,
+    /// GGUF file; the `--model` model when omitted.
+    #[serde(default)]
+    pub model: Option<PathBuf>,
+    /// Persona, used as the agent's system prompt.
+    #[serde(default)]
+    pub system: Option<String>,
+    #[serde(default)]
+    pub temperature: Option<f64>,
+    #[serde(default)]
+    pub max_tokens: Option<usize>,
+}
+
+/// Asked after every round whether the conversation should go on.
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModeratorConfig {
+    /// GGUF file; the `--model` model when omitted.
+    #[serde(default)]
+    pub model: Option<PathBuf>,
+    /// Instructions replacing the built-in moderator prompt.
+    #[serde(default)]
+    pub system: Option<String>,
+    /// Word in the moderator's answer that ends the conversation.
+    #[serde(default = "default_stop_word")]
+    pub stop_word: String,
+}
+
+fn default_max_turns() -> usize {
+    DEFAULT_MAX_TURNS
+}
+
+fn default_stop_word() -> String {
+    "STOP".to_string()
+}
+
+impl AgentsConfig {
+    /// Reads and checks a config file. Relative model paths are resolved
+    /// against the file's directory.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read agents config {:?}", path))?;
+        let mut config: Self =
+            toml::from_str(&text).with_context(|| format!("Invalid agents config {:?}", path))?;
+        let base = path.parent().unwrap_or(Path::new(""));
+        let models = config
+            .agents
+            .iter_mut()
+            .map(|a| &mut a.model)
+            .chain(config.moderator.iter_mut().map(|m| &mut m.model));
+        for model in models.flatten() {
+            if model.is_relative() {
+                *model = base.join(&*model);
+            }
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.agents.len() < 2 {
+            anyhow::bail!("An agents config needs at least two [[agent]] entries");
+        }
+        for (i, agent) in self.agents.iter().enumerate() {
+            if agent.name.trim().is_empty() {
+                anyhow::bail!("Agent {} has an empty name", i + 1);
+            }
+            if self.agents[..i].iter().any(|a| a.name == agent.name) {
+                anyhow::bail!("Agent name '{}' is used twice", agent.name);
+            }
+        }
+        if self.max_turns == 0 {
+            anyhow::bail!("max_turns must be at least 1");
+        }
+        Ok(())
+    }
+
+    /// Distinct models in first-use order; `None` is the default model.
+    /// Generators passed to [`run_agents`] are indexed like this list.
+    pub fn models(&self) -> Vec<Option<PathBuf>> {
+        let mut models = Vec::new();
+        let used = self
+            .agents
+            .iter()
+            .map(|a| &a.model)
+            .chain(self.moderator.iter().map(|m| &m.model));
+        for model in used {
+            if !models.contains(model) {
+                models.push(model.clone());
+            }
+        }
+        models
+    }
+
+    fn model_slot(&self, model: &Option<PathBuf>) -> usize {
+        self.models().iter().position(|m| m == model).unwrap_or(0)
+    }
+}
+
+/// Settings shared by every turn; agents may override temperature and
+/// `max_tokens`.
+#[derive(Clone, Debug)]
+pub struct AgentsOptions {
+    pub temperature: f64,
+    pub top_p: Option<f64>,
+    pub top_k: Option<usize>,
+    /// Turn `n` samples with `seed + n`, so agents on one model differ.
+    pub seed: u64,
+    pub max_tokens: usize,
+    pub repeat_penalty: f32,
+    pub repeat_last_n: usize,
+}
+
+/// One reply in the conversation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Turn {
+    pub speaker: String,
+    pub text: String,
+}
+
+/// Why the conversation ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    MaxTurns,
+    /// A reply contained the `stop` phrase.
+    StopPhrase,
+    Moderator,
+}
+
+/// Progress reported while the agents talk.
+pub enum AgentEvent<'a> {
+    TurnStarted {
+        turn: usize,
+        speaker: &'a str,
+    },
+    Token(String),
+    TurnFinished,
+    /// The moderator's answer after a round.
+    Moderated {
+        verdict: String,
+        stop: bool,
+    },
+    Stopped(StopReason),
+}
+
+/// Runs the conversation and returns its transcript. `generators` holds one
+/// generator per entry of [`AgentsConfig::models`].
+pub fn run_agents<F>(
+    config: &AgentsConfig,
+    generators: &mut [Generator],
+    options: &AgentsOptions,
+    mut callback: F,
+) -> Result<Vec<Turn>>
+where
+    F: FnMut(AgentEvent<'_>),
+{
+    let mut transcript: Vec<Turn> = Vec::new();
+    let reason = loop {
+        let turn = transcript.len();
+        if turn >= config.max_turns {
+            break StopReason::MaxTurns;
+        }
+        let agent = &config.agents[turn % config.agents.len()];
+        let generator = &mut generators[config.model_slot(&agent.model)];
+        let (history, prompt) = messages_for(&config.topic, &transcript, &agent.name);
+        generator.set_system_prompt(agent.system.clone())?;
+        generator.set_history(history)?;
+        generator.set_sampling(
+            agent.temperature.unwrap_or(options.temperature),
+            options.top_p,
+            options.top_k,
+            options.seed.wrapping_add(turn as u64),
+        );
+
+        callback(AgentEvent::TurnStarted {
+            turn,
+            speaker: &agent.name,
+        });
+        let text = generator.generate(
+            &prompt,
+            agent.max_tokens.unwrap_or(options.max_tokens),
+            options.repeat_penalty,
+            options.repeat_last_n,
+            |event| {
+                if let StreamEvent::Token(token) = event {
+                    callback(AgentEvent::Token(token));
+                }
+            },
+        )?;
+        callback(AgentEvent::TurnFinished);
+
+        let stopped = config
+            .stop
+            .as_deref()
+            .is_some_and(|stop| !stop.is_empty() && text.contains(stop));
+        transcript.push(Turn {
+            speaker: agent.name.clone(),
+            text: text.trim().to_string(),
+        });
+        if stopped {
+            break StopReason::StopPhrase;
+        }
+
+        let round_done = transcript.len() % config.agents.len() == 0;
+        if let Some(moderator) = config.moderator.as_ref().filter(|_| round_done) {
+            let generator = &mut generators[config.model_slot(&moderator.model)];
+            let verdict = moderate(generator, moderator, config, &transcript, options)?;
+            let stop = verdict
+                .to_lowercase()
+                .contains(&moderator.stop_word.to_lowercase());
+            callback(AgentEvent::Moderated { verdict, stop });
+            if stop {
+                break StopReason::Moderator;
+            }
+        }
+    };
+    callback(AgentEvent::Stopped(reason));
+    Ok(transcript)
+}
+
+fn moderate(
+    generator: &mut Generator,
+    moderator: &ModeratorConfig,
+    config: &AgentsConfig,
+    transcript: &[Turn],
+    options: &AgentsOptions,
+) -> Result<String> {
+    let system = moderator
+        .system
+        .clone()
+        .unwrap_or_else(|| DEFAULT_MODERATOR_PROMPT.to_string());
+    generator.set_system_prompt(Some(system))?;
+    generator.set_history(Vec::new())?;
+    generator.set_sampling(0.0, None, None, options.seed);
+
+    let mut prompt = format!("Topic: {}\n\n", config.topic);
+    for turn in transcript {
+        prompt.push_str(&format!("{}: {}\n\n", turn.speaker, turn.text));
+    }
+    prompt.push_str("Should the conversation continue?");
+    let verdict = generator.generate(
+        &prompt,
+        16,
+        options.repeat_penalty,
+        options.repeat_last_n,
+        |_| {},
+    )?;
+    Ok(verdict.trim().to_string())
+}
+
+/// History and prompt for `speaker`'s next turn: its own turns become
+/// assistant messages, and everything said in between is merged into one
+/// user message with speaker names.
+fn messages_for(topic: &str, transcript: &[Turn], speaker: &str) -> (Vec<Message>, String) {
+    let mut messages: Vec<Message> = Vec::new();
+    let mut pending = vec![topic.to_string()];
+    for turn in transcript {
+        if turn.speaker == speaker {
+            messages.push(Message::new("user", pending.join("\n\n")));
+            messages.push(Message::new("assistant", turn.text.clone()));
+            pending.clear();
+        } else {
+            pending.push(format!("{}: {}", turn.speaker, turn.text));
+        }
+    }
+    (messages, pending.join("\n\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{messages_for, AgentsConfig, Turn};
+
+    #[test]
+    fn parses_config_and_builds_each_agents_view() {
+        let config: AgentsConfig = toml::from_str(
+            r#"
+            topic = "Tabs or spaces?"
+            max_turns = 4
+
+            [[agent]]
+            name = "Ada"
+            system = "You prefer tabs."
+
+            [[agent]]
+            name = "Bob"
+            model = "small.gguf"
+
+            [moderator]
+            "#,
+        )
+        .unwrap();
+        config.validate().unwrap();
+        assert_eq!(config.models(), [None, Some(PathBuf::from("small.gguf"))]);
+        assert_eq!(config.moderator.unwrap().stop_word, "STOP");
+
+        let transcript = [
+            Turn {
+                speaker: "Ada".into(),
+                text: "Tabs.".into(),
+            },
+            Turn {
+                speaker: "Bob".into(),
+                text: "Spaces.".into(),
+            },
+        ];
+        let (history, prompt) = messages_for("Tabs or spaces?", &transcript, "Ada");
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].content, "Tabs or spaces?");
+        assert_eq!(history[1].role, "assistant");
+        assert_eq!(prompt, "Bob: Spaces.");
+
+        let (history, prompt) = messages_for("Tabs or spaces?", &transcript[..1], "Bob");
+        assert!(history.is_empty());
+        assert_eq!(prompt, "Tabs or spaces?\n\nAda: Tabs.");
+
+        let single: AgentsConfig =
+            toml::from_str("topic = \"x\"\n[[agent]]\nname = \"Solo\"").unwrap();
+        assert!(single.validate().is_err());
+    }
+}
@@ -0,0 +1,194 @@
+//! Picking one of several labels, for the `classify` subcommand and
+//! `Model::choose`.
+//!
+//! Decoding is constrained to the labels' token sequences: at each step only
+//! tokens that continue some label are allowed (or the end token, once a
+//! label is complete), and the masked logits are renormalized. A label's
+//! probability is the product over its steps. Steps that allow a single
+//! token have probability one, so the model only runs at prefixes where
+//! labels diverge, which for most label sets is once, right after the prompt.
+
+use anyhow::Result;
+
+/// The chosen label and the probability of every label, in input order.
+/// Probabilities sum to one.
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct Choice {
+    pub label: String,
+    pub probabilities: Vec<(String, f32)>,
+}
+
+impl Choice {
+    /// Probability of the chosen label.
+    pub fn probability(&self) -> f32 {
+        self.probabilities
+            .iter()
+            .find(|(label, _)| *label == self.label)
+            .map_or(0.0, |(_, p)| *p)
+    }
+}
+
+/// Prompt asking to classify `text` into one of `labels`.
+pub fn classify_prompt(text: &str, labels: &[String]) -> String {
+    format!(
+        "Classify the text below as exactly one of these labels: {}. \
+         Reply with the label only.\n\nText: {}",
+        labels.join(", "),
+        text
+    )
+}
+
+/// Scores `labels`, tokenized as `sequences`. `logits_after(prefix)` returns
+/// the next-token logits after the prompt followed by `prefix`.
+pub fn choose_label<F>(
+    labels: &[String],
+    sequences: &[Vec<u32>],
+    end_token: u32,
+    mut logits_after: F,
+) -> Result<Choice>
+where
+    F: FnMut(&[u32]) -> Result<Vec<f32>>,
+{
+    if labels.is_empty() {
+        anyhow::bail!("At least one label is required");
+    }
+    for (i, sequence) in sequences.iter().enumerate() {
+        if sequence.is_empty() {
+            anyhow::bail!("Label '{}' is empty", labels[i]);
+        }
+        if let Some(j) = sequences[..i].iter().position(|s| s == sequence) {
+            anyhow::bail!(
+                "Labels '{}' and '{}' have the same tokens",
+                labels[j],
+                labels[i]
+            );
+        }
+    }
+
+    let mut logprobs = vec![f32::NEG_INFINITY; labels.len()];
+    let members: Vec<usize> = (0..labels.len()).collect();
+    walk(
+        sequences,
+        end_token,
+        0,
+        members,
+        0.0,
+        &mut logits_after,
+        &mut logprobs,
+    )?;
+
+    let max = logprobs.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let weights: Vec<f32> = logprobs.iter().map(|&lp| (lp - max).exp()).collect();
+    let total: f32 = weights.iter().sum();
+    let best = weights
+        .iter()
+        .enumerate()
+        .fold(0, |best, (i, &w)| if w > weights[best] { i } else { best });
+    Ok(Choice {
+        label: labels[best].clone(),
+        probabilities: labels
+            .iter()
+            .zip(&weights)
+            .map(|(label, &w)| (label.clone(), w / total))
+            .collect(),
+    })
+}
+
+/// Visits the labels in `members`, which share their first `depth` tokens
+/// and together have log probability `logprob`.
+fn walk<F>(
+    sequences: &[Vec<u32>],
+    end_token: u32,
+    depth: usize,
+    members: Vec<usize>,
+    logprob: f32,
+    logits_after: &mut F,
+    out: &mut [f32],
+) -> Result<()>
+where
+    F: FnMut(&[u32]) -> Result<Vec<f32>>,
+{
+    // Labels grouped by their next token; `None` means the label ends here.
+    let mut groups: Vec<(Option<u32>, Vec<usize>)> = Vec::new();
+    for &i in &members {
+        let next = sequences[i].get(depth).copied();
+        match groups.iter_mut().find(|(token, _)| *token == next) {
+            Some((_, group)) => group.push(i),
+            None => groups.push((next, vec![i])),
+        }
+    }
+
+    let logprobs = if groups.len() == 1 {
+        vec![0.0]
+    } else {
+        let logits = logits_after(&sequences[members[0]][..depth])?;
+        let allowed: Vec<f32> = groups
+            .iter()
+            .map(|(token, _)| {
+                let id = token.unwrap_or(end_token) as usize;
+                logits.get(id).copied().unwrap_or(f32::NEG_INFINITY)
+            })
+            .collect();
+        let max = allowed.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        let log_sum = allowed.iter().map(|&l| (l - max).exp()).sum::<f32>().ln() + max;
+        allowed.into_iter().map(|l| l - log_sum).collect()
+    };
+
+    for ((token, group), step) in groups.into_iter().zip(logprobs) {
+        match token {
+            // Sequences are distinct, so only one label ends here.
+            None => out[group[0]] = logprob + step,
+            Some(_) => walk(
+                sequences,
+                end_token,
+                depth + 1,
+                group,
+                logprob + step,
+                logits_after,
+                out,
+            )?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::choose_label;
+
+    #[test]
+    fn renormalizes_over_labels_and_runs_only_where_they_diverge() {
+        let labels: Vec<String> = ["yes", "no", "not sure"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        // "no" and "not sure" share token 2; 9 ends a label.
+        let sequences = vec![vec![1], vec![2], vec![2, 3, 4]];
+        let mut calls = Vec::new();
+        let choice = choose_label(&labels, &sequences, 9, |prefix| {
+            calls.push(prefix.to_vec());
+            let mut logits = vec![0.0f32; 10];
+            match prefix {
+                [] => logits[2] = 2f32.ln(),
+                [2] => logits[3] = 3f32.ln(),
+                _ => unreachable!("no other prefix branches"),
+            }
+            // Not a label token, so it must not take probability mass.
+            logits[5] = 100.0;
+            Ok(logits)
+        })
+        .unwrap();
+
+        assert_eq!(calls, [vec![], vec![2]]);
+        assert_eq!(choice.label, "not sure");
+        assert!((choice.probability() - 0.5).abs() < 1e-5);
+        let p: Vec<f32> = choice.probabilities.iter().map(|(_, p)| *p).collect();
+        // yes 1/3, no 2/3 * 1/4, not sure 2/3 * 3/4.
+        for (actual, expected) in p.iter().zip([1.0 / 3.0, 1.0 / 6.0, 0.5]) {
+            assert!((actual - expected).abs() < 1e-5, "{:?}", p);
+        }
+
+        let same = choose_label(&labels[..2], &[vec![1], vec![1]], 9, |_| Ok(vec![]));
+        assert!(same.is_err());
+    }
+}
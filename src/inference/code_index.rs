@@ -0,0 +1,426 @@
+//! Retrieval over a git repository for the `code` subcommand.
+//!
+//! Files tracked by git, plus untracked files that `.gitignore` does not
+//! exclude, are split into overlapping line windows and ranked with BM25.
+//! The library has no embedding model yet, so retrieval is lexical:
+//! identifiers are split on `snake_case` and `camelCase` boundaries so a
+//! question about "prefill chunk" finds `prefill_chunk` and `PrefillChunk`.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{Context, Result};
+use regex::Regex;
+
+use crate::inference::extract::{extract_text, FileFormat};
+
+/// Lines per chunk, and lines shared by neighbouring chunks.
+const CHUNK_LINES: usize = 40;
+const CHUNK_OVERLAP: usize = 10;
+/// Files larger than this are skipped (lock files, generated code, data).
+const MAX_FILE_BYTES: u64 = 512 * 1024;
+/// PDFs carry fonts and images besides their text, so they may be larger.
+const MAX_PDF_BYTES: u64 = 16 * 1024 * 1024;
+const BM25_K1: f32 = 1.2;
+const BM25_B: f32 = 0.75;
+
+/// A window of lines from one file. Lines are 1-based and inclusive.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CodeChunk {
+    pub path: PathBuf,
+    pub start_line: usize,
+    pub end_line: usize,
+    pub text: String,
+}
+
+impl CodeChunk {
+    /// `path:start-end`, the form answers are asked to cite.
+    pub fn citation(&self) -> String {
+        format!(
+            "{}:{}-{}",
+            self.path.display(),
+            self.start_line,
+            self.end_line
+        )
+    }
+}
+
+pub struct CodeIndex {
+    chunks: Vec<CodeChunk>,
+    /// Term counts per chunk.
+    terms: Vec<HashMap<String, u32>>,
+    lengths: Vec<usize>,
+    /// Number of chunks containing each term.
+    document_frequency: HashMap<String, usize>,
+    average_length: f32,
+    files: usize,
+}
+
+impl CodeIndex {
+    /// Indexes the repository containing `root`.
+    pub fn build(root: &Path) -> Result<Self> {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(root)
+            .args([
+                "ls-files",
+                "-z",
+                "--cached",
+                "--others",
+                "--exclude-standard",
+            ])
+            .output()
+            .context("Failed to run git; the code mode needs git installed")?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "{} is not inside a git repository: {}",
+                root.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        let mut files = Vec::new();
+        for name in output.stdout.split(|&b| b == 0).filter(|n| !n.is_empty()) {
+            let relative = PathBuf::from(String::from_utf8_lossy(name).into_owned());
+            let path = root.join(&relative);
+            let Ok(metadata) = std::fs::metadata(&path) else {
+                continue;
+            };
+            let is_pdf = FileFormat::detect(&path, &[]) == FileFormat::Pdf;
+            let limit = if is_pdf {
+                MAX_PDF_BYTES
+            } else {
+                MAX_FILE_BYTES
+            };
+            if !metadata.is_file() || metadata.len() > limit {
+                continue;
+            }
+            let Ok(bytes) = std::fs::read(&path) else {
+                continue;
+            };
+            // Source files are indexed as written; only PDFs, which would be
+            // skipped as binary, are reduced to their text.
+            if is_pdf {
+                match extract_text(&bytes, FileFormat::Pdf) {
+                    Ok(text) => files.push((relative, text)),
+                    Err(e) => tracing::debug!("Skipping {}: {:#}", relative.display(), e),
+                }
+                continue;
+            }
+            // Binary files have NUL bytes near the start.
+            if bytes.iter().take(8192).any(|&b| b == 0) {
+                continue;
+            }
+            let Ok(text) = String::from_utf8(bytes) else {
+                continue;
+            };
+            files.push((relative, text));
+        }
+        Ok(Self::from_files(files))
+    }
+
+    pub fn from_files(files: Vec<(PathBuf, String)>) -> Self {
+        let file_count = files.len();
+        let mut chunks = Vec::new();
+        for (path, text) in files {
+            chunk_file(&path, &text, &mut chunks);
+        }
+
+        let mut terms = Vec::with_capacity(chunks.len());
+        let mut lengths = Vec::with_capacity(chunks.len());
+        let mut document_frequency: HashMap<String, usize> = HashMap::new();
+        for chunk in &chunks {
+            // The path is indexed too, so "tokenizer" finds tokenizer.rs.
+            let source = format!("{}\n{}", chunk.path.display(), chunk.text);
+            let tokens = tokenize(&source);
+            let mut counts: HashMap<String, u32> = HashMap::new();
+            for token in &tokens {
+                *counts.entry(token.clone()).or_default() += 1;
+            }
+            for term in counts.keys() {
+                *document_frequency.entry(term.clone()).or_default() += 1;
+            }
+            lengths.push(tokens.len());
+            terms.push(counts);
+        }
+        let average_length = if lengths.is_empty() {
+            0.0
+        } else {
+            lengths.iter().sum::<usize>() as f32 / lengths.len() as f32
+        };
+
+        Self {
+            chunks,
+            terms,
+            lengths,
+            document_frequency,
+            average_length,
+            files: file_count,
+        }
+    }
+
+    pub fn file_count(&self) -> usize {
+        self.files
+    }
+
+    pub fn chunk_count(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// The `limit` chunks most relevant to `query`, best first. Chunks that
+    /// share no term with the query are never returned.
+    pub fn search(&self, query: &str, limit: usize) -> Vec<&CodeChunk> {
+        let mut query_terms = tokenize(query);
+        query_terms.sort();
+        query_terms.dedup();
+
+        let total = self.chunks.len() as f32;
+        let mut scored: Vec<(f32, usize)> = Vec::new();
+        for (i, counts) in self.terms.iter().enumerate() {
+            let length_norm =
+                1.0 - BM25_B + BM25_B * self.lengths[i] as f32 / self.average_length.max(1.0);
+            let score: f32 = query_terms
+                .iter()
+                .filter_map(|term| {
+                    let tf = *counts.get(term)? as f32;
+                    let df = self.document_frequency[term] as f32;
+                    let idf = ((total - df + 0.5) / (df + 0.5) + 1.0).ln();
+                    Some(idf * tf * (BM25_K1 + 1.0) / (tf + BM25_K1 * length_norm))
+                })
+                .sum();
+            if score > 0.0 {
+                scored.push((score, i));
+            }
+        }
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)));
+        scored
+            .into_iter()
+            .take(limit)
+            .map(|(_, i)| &self.chunks[i])
+            .collect()
+    }
+}
+
+/// An excerpt that went into a prompt, numbered as the prompt numbers it.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct Citation {
+    /// The excerpt's `[id]` in the prompt, from 1.
+    pub id: usize,
+    pub path: PathBuf,
+    pub start_line: usize,
+    pub end_line: usize,
+    /// The answer refers to the excerpt by `[id]` or by a `path:line`
+    /// inside it.
+    pub cited: bool,
+}
+
+impl Citation {
+    /// `[id] path:start-end`, as printed after an answer.
+    pub fn source(&self) -> String {
+        format!(
+            "[{}] {}:{}-{}",
+            self.id,
+            self.path.display(),
+            self.start_line,
+            self.end_line
+        )
+    }
+}
+
+/// Prompt asking `question` about the retrieved `chunks`, numbered and with
+/// line numbers so the answer can cite them.
+pub fn code_prompt(question: &str, chunks: &[&CodeChunk]) -> String {
+    let mut prompt = String::from(
+        "Answer the question about this repository using the excerpts below. \
+         Cite the excerpts you rely on by number, like [1], and their lines as \
+         path:line. If the excerpts do not contain the answer, say so.\n\n",
+    );
+    for (i, chunk) in chunks.iter().enumerate() {
+        prompt.push_str(&format!("[{}] File: {}\n```\n", i + 1, chunk.citation()));
+        for (offset, line) in chunk.text.lines().enumerate() {
+            prompt.push_str(&format!("{:>5} {}\n", chunk.start_line + offset, line));
+        }
+        prompt.push_str("```\n\n");
+    }
+    prompt.push_str(&format!("Question: {}", question));
+    prompt
+}
+
+/// The `chunks` of a [`code_prompt`] as citations, marking the ones
+/// `answer` refers to.
+pub fn citations(answer: &str, chunks: &[&CodeChunk]) -> Vec<Citation> {
+    let mut numbers = Vec::new();
+    for list in Regex::new(r"\[(\d+(?:\s*,\s*\d+)*)\]")
+        .unwrap()
+        .captures_iter(answer)
+    {
+        numbers.extend(
+            list[1]
+                .split(',')
+                .filter_map(|n| n.trim().parse::<usize>().ok()),
+        );
+    }
+    chunks
+        .iter()
+        .enumerate()
+        .map(|(i, chunk)| {
+            let id = i + 1;
+            let path = chunk.path.display().to_string();
+            let by_line = answer.match_indices(&format!("{}:", path)).any(|(at, m)| {
+                let digits: String = answer[at + m.len()..]
+                    .chars()
+                    .take_while(char::is_ascii_digit)
+                    .collect();
+                digits
+                    .parse()
+                    .is_ok_and(|line: usize| (chunk.start_line..=chunk.end_line).contains(&line))
+            });
+            Citation {
+                id,
+                path: chunk.path.clone(),
+                start_line: chunk.start_line,
+                end_line: chunk.end_line,
+                cited: numbers.contains(&id) || by_line,
+            }
+        })
+        .collect()
+}
+
+fn chunk_file(path: &Path, text: &str, out: &mut Vec<CodeChunk>) {
+    let lines: Vec<&str> = text.lines().collect();
+    let step = CHUNK_LINES - CHUNK_OVERLAP;
+    let mut start = 0;
+    while start < lines.len() {
+        let end = (start + CHUNK_LINES).min(lines.len());
+        let chunk = lines[start..end].join("\n");
+        if !chunk.trim().is_empty() {
+            out.push(CodeChunk {
+                path: path.to_path_buf(),
+                start_line: start + 1,
+                end_line: end,
+                text: chunk,
+            });
+        }
+        if end == lines.len() {
+            break;
+        }
+        start += step;
+    }
+}
+
+/// Lowercased words and identifier parts: `PrefillChunk` and
+/// `prefill_chunk` both yield `prefill` and `chunk`, along with the whole
+/// identifier.
+fn tokenize(text: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    for word in text.split(|c: char| !c.is_alphanumeric() && c != '_') {
+        if word.is_empty() {
+            continue;
+        }
+        let parts = identifier_parts(word);
+        if parts.len() > 1 {
+            tokens.push(word.to_lowercase());
+        }
+        tokens.extend(parts.into_iter().filter(|p| p.chars().count() > 1));
+    }
+    tokens
+}
+
+fn identifier_parts(word: &str) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut prev_lower = false;
+    for c in word.chars() {
+        if c == '_' {
+            if !current.is_empty() {
+                parts.push(std::mem::take(&mut current));
+            }
+            prev_lower = false;
+            continue;
+        }
+        if c.is_uppercase() && prev_lower && !current.is_empty() {
+            parts.push(std::mem::take(&mut current));
+        }
+        prev_lower = c.is_lowercase() || c.is_numeric();
+        current.extend(c.to_lowercase());
+    }
+    if !current.is_empty() {
+        parts.push(current);
+    }
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{citations, code_prompt, tokenize, CodeChunk, CodeIndex};
+
+    #[test]
+    fn finds_relevant_chunks_with_line_ranges() {
+        assert_eq!(
+            tokenize("fn set_low_mem(PrefillChunk)"),
+            [
+                "fn",
+                "set_low_mem",
+                "set",
+                "low",
+                "mem",
+                "prefillchunk",
+                "prefill",
+                "chunk"
+            ]
+        );
+
+        let mut long = String::new();
+        for i in 1..=60 {
+            long.push_str(&format!("let filler_{} = {};\n", i, i));
+        }
+        long.push_str("fn apply_repeat_penalty() {}\n");
+        let index = CodeIndex::from_files(vec![
+            (PathBuf::from("src/sampler.rs"), long),
+            (
+                PathBuf::from("src/tokenizer.rs"),
+                "pub fn encode(text: &str) {}\n".to_string(),
+            ),
+        ]);
+        assert_eq!(index.file_count(), 2);
+        assert_eq!(index.chunk_count(), 3);
+
+        let hits = index.search("how is the repeat penalty applied?", 2);
+        assert_eq!(hits[0].citation(), "src/sampler.rs:31-61");
+        assert_eq!(
+            index.search("tokenizer", 5)[0].path,
+            PathBuf::from("src/tokenizer.rs")
+        );
+        assert!(index.search("kubernetes", 5).is_empty());
+
+        let prompt = code_prompt("Where?", &hits[..1]);
+        assert!(prompt.contains("File: src/sampler.rs:31-61"));
+        assert!(prompt.contains("   61 fn apply_repeat_penalty() {}"));
+    }
+
+    #[test]
+    fn marks_the_excerpts_an_answer_cites() {
+        let chunk = |path: &str, start_line: usize| CodeChunk {
+            path: PathBuf::from(path),
+            start_line,
+            end_line: start_line + 39,
+            text: String::new(),
+        };
+        let chunks = [
+            chunk("src/a.rs", 1),
+            chunk("src/b.rs", 31),
+            chunk("src/c.rs", 1),
+        ];
+        let chunks: Vec<&CodeChunk> = chunks.iter().collect();
+        assert!(code_prompt("Where?", &chunks).contains("[2] File: src/b.rs:31-70"));
+
+        let answer = "The penalty is applied in src/b.rs:45 [1, 2]. See also src/c.rs:90.";
+        let cited: Vec<bool> = citations(answer, &chunks).iter().map(|c| c.cited).collect();
+        assert_eq!(cited, [true, true, false]);
+        assert!(!citations("In src/b.rs:12.", &chunks)[1].cited);
+        assert_eq!(citations("", &chunks)[2].source(), "[3] src/c.rs:1-40");
+    }
+}
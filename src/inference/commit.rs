@@ -0,0 +1,170 @@
+//! Commit messages written from the staged diff, for the `commit`
+//! subcommand.
+
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use anyhow::{Context, Result};
+
+/// Git's empty tree, diffed against when amending a root commit.
+const EMPTY_TREE: &str = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+
+const COMMIT_INSTRUCTIONS: &str = "\
+Write a git commit message for the change below, following Conventional Commits.
+
+Rules:
+- First line: type(scope): summary. Types: feat, fix, docs, style, refactor, perf, test, build, ci, chore, revert. The scope is optional.
+- Summary in the imperative mood (\"add\", not \"added\"), lowercase, no trailing period, at most 72 characters.
+- If the change needs explaining, add a blank line and a short body wrapped at 72 characters that says what changed and why, not how.
+- Mark breaking changes with ! after the type and a BREAKING CHANGE: footer.
+- Reply with the commit message only, without quotes or code fences.";
+
+/// The change a new commit would record: the staged diff, or with `amend`
+/// the staged diff against the parent of `HEAD`. Starts with a `--stat`
+/// summary so truncated diffs still name every file.
+pub fn staged_diff(repo: &Path, amend: bool) -> Result<String> {
+    let base = if amend {
+        let parent = git(repo, &["rev-parse", "--verify", "--quiet", "HEAD^"], None);
+        match parent {
+            Ok(parent) => parent.trim().to_string(),
+            Err(_) => EMPTY_TREE.to_string(),
+        }
+    } else {
+        String::new()
+    };
+    let diff_args = |extra: &[&'static str]| {
+        let mut args = vec!["diff", "--cached", "--no-color", "--no-ext-diff"];
+        args.extend_from_slice(extra);
+        args
+    };
+
+    let mut stat_args = diff_args(&["--stat"]);
+    let mut patch_args = diff_args(&[]);
+    if amend {
+        stat_args.push(&base);
+        patch_args.push(&base);
+    }
+    let stat = git(repo, &stat_args, None)?;
+    let patch = git(repo, &patch_args, None)?;
+    if patch.trim().is_empty() {
+        return Ok(String::new());
+    }
+    Ok(format!("{}\n{}", stat.trim_end(), patch))
+}
+
+/// Prompt for a message describing `diff`, in `language` when given.
+/// Diffs longer than `max_chars` are cut at a line boundary.
+pub fn commit_prompt(diff: &str, language: Option<&str>, max_chars: usize) -> String {
+    let mut prompt = String::from(COMMIT_INSTRUCTIONS);
+    if let Some(language) = language {
+        prompt.push_str(&format!(
+            "\n- Write the summary and body in {}; keep the type keywords in English.",
+            language
+        ));
+    }
+    prompt.push_str("\n\n```diff\n");
+    if diff.len() > max_chars {
+        let mut cut = max_chars;
+        while !diff.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        let cut = diff[..cut].rfind('\n').map_or(cut, |i| i + 1);
+        prompt.push_str(&diff[..cut]);
+        prompt.push_str("[diff truncated]\n");
+    } else {
+        prompt.push_str(diff);
+        if !diff.ends_with('\n') {
+            prompt.push('\n');
+        }
+    }
+    prompt.push_str("```");
+    prompt
+}
+
+/// Strips what models tend to wrap a message in: code fences, a
+/// "Commit message:" label, quotes and surrounding blank lines.
+pub fn clean_message(raw: &str) -> String {
+    let mut lines: Vec<&str> = raw
+        .lines()
+        .filter(|line| !line.trim_start().starts_with("```"))
+        .collect();
+    while lines.first().is_some_and(|l| l.trim().is_empty()) {
+        lines.remove(0);
+    }
+    if let Some(first) = lines.first_mut() {
+        const LABEL: &str = "commit message:";
+        let trimmed = first.trim();
+        let unlabeled = match trimmed.get(..LABEL.len()) {
+            Some(label) if label.eq_ignore_ascii_case(LABEL) => trimmed[LABEL.len()..].trim_start(),
+            _ => trimmed,
+        };
+        *first = unlabeled.trim_matches(|c| c == '"' || c == '`');
+    }
+    let message = lines
+        .iter()
+        .map(|line| line.trim_end())
+        .collect::<Vec<_>>()
+        .join("\n");
+    message.trim().to_string()
+}
+
+/// Commits the staged changes with `message`, or rewrites the last commit
+/// with `amend`. Returns git's summary output.
+pub fn apply_commit(repo: &Path, message: &str, amend: bool) -> Result<String> {
+    let mut args = vec!["commit", "--file", "-"];
+    if amend {
+        args.push("--amend");
+    }
+    git(repo, &args, Some(message))
+}
+
+fn git(repo: &Path, args: &[&str], stdin: Option<&str>) -> Result<String> {
+    let mut child = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(args)
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to run git")?;
+    if let (Some(input), Some(mut pipe)) = (stdin, child.stdin.take()) {
+        pipe.write_all(input.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "git {} failed: {}",
+            args.first().copied().unwrap_or_default(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{clean_message, commit_prompt};
+
+    #[test]
+    fn builds_prompts_and_cleans_replies() {
+        let diff = "a.rs | 2 +-\n+line one\n+line two\n";
+        let prompt = commit_prompt(diff, Some("German"), 16);
+        assert!(prompt.contains("in German"));
+        assert!(prompt.ends_with("a.rs | 2 +-\n[diff truncated]\n```"));
+        assert!(commit_prompt(diff, None, 1000).ends_with("+line two\n```"));
+
+        assert_eq!(
+            clean_message(
+                "```\nCommit message: \"fix(cli): handle empty input\"\n\nBody line.  \n```"
+            ),
+            "fix(cli): handle empty input\n\nBody line."
+        );
+        assert_eq!(clean_message("\n\nfeat: add x\n"), "feat: add x");
+    }
+}
@@ -0,0 +1,101 @@
+//! Uncertainty of a generation, from the next-token distributions.
+//!
+//! Both measures use the logits after every transform but before
+//! temperature and top-k/top-p, like logprobs, so they describe the model
+//! rather than the sampler settings.
+
+/// Aggregate uncertainty of one reply.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize)]
+pub struct Confidence {
+    /// Mean entropy of the next-token distributions, in nats. `0.0` means
+    /// every token was certain; higher means the model was guessing.
+    pub mean_entropy: f32,
+    /// Smallest gap between the two most likely tokens' probabilities over
+    /// the reply. Near `0.0` means at least one token was a coin flip.
+    pub min_margin: f32,
+    /// Decode steps measured.
+    pub tokens: usize,
+}
+
+/// Accumulates [`Confidence`] over the decode steps of one generation.
+#[derive(Clone, Debug, Default)]
+pub struct ConfidenceTracker {
+    entropy_sum: f64,
+    min_margin: Option<f32>,
+    tokens: usize,
+}
+
+impl ConfidenceTracker {
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Adds the distribution of one decode step.
+    pub fn record(&mut self, logits: &[f32]) {
+        let mut top = [f32::NEG_INFINITY; 2];
+        for &logit in logits {
+            if logit > top[0] {
+                top = [logit, top[0]];
+            } else if logit > top[1] {
+                top[1] = logit;
+            }
+        }
+        let max = top[0];
+        if !max.is_finite() {
+            return;
+        }
+        let log_sum = logits
+            .iter()
+            .map(|&l| ((l - max) as f64).exp())
+            .sum::<f64>()
+            .ln();
+        let logprob = |l: f32| (l - max) as f64 - log_sum;
+        // Masked tokens have zero probability and add nothing.
+        let entropy: f64 = logits
+            .iter()
+            .filter(|l| l.is_finite())
+            .map(|&l| -logprob(l).exp() * logprob(l))
+            .sum();
+        let margin = (logprob(top[0]).exp() - logprob(top[1]).exp()) as f32;
+
+        self.entropy_sum += entropy;
+        self.min_margin = Some(self.min_margin.map_or(margin, |m| m.min(margin)));
+        self.tokens += 1;
+    }
+
+    /// `None` before the first decode step.
+    pub fn summary(&self) -> Option<Confidence> {
+        Some(Confidence {
+            mean_entropy: (self.entropy_sum / self.tokens as f64) as f32,
+            min_margin: self.min_margin?,
+            tokens: self.tokens,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ConfidenceTracker;
+
+    #[test]
+    fn measures_entropy_and_margin() {
+        let mut tracker = ConfidenceTracker::default();
+        assert!(tracker.summary().is_none());
+
+        // Certain: one token, the rest masked.
+        tracker.record(&[5.0, f32::NEG_INFINITY, f32::NEG_INFINITY]);
+        let certain = tracker.summary().unwrap();
+        assert_eq!(certain.mean_entropy, 0.0);
+        assert_eq!(certain.min_margin, 1.0);
+
+        // A coin flip between two tokens: entropy ln 2, no margin.
+        tracker.record(&[1.0, 1.0, f32::NEG_INFINITY]);
+        let summary = tracker.summary().unwrap();
+        assert_eq!(summary.tokens, 2);
+        assert!((summary.mean_entropy - std::f32::consts::LN_2 / 2.0).abs() < 1e-6);
+        assert!(summary.min_margin.abs() < 1e-6);
+
+        tracker.reset();
+        assert!(tracker.summary().is_none());
+    }
+}
@@ -0,0 +1,104 @@
+//! What the generator does when a prompt does not fit the context window.
+//!
+//! Interactive use wants the conversation to go on, so by default the
+//! oldest turns are dropped ([`ContextPolicy::Truncate`]) or condensed into
+//! a summary ([`ContextPolicy::Summarize`]). API servers usually want to
+//! tell the client instead: with [`ContextPolicy::Error`] generation fails
+//! with a [`ContextOverflow`] that callers can downcast to and handle.
+
+use std::fmt;
+use std::str::FromStr;
+
+/// How a prompt that does not fit the context window is handled.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContextPolicy {
+    /// Drop the oldest turns until the prompt fits.
+    #[default]
+    Truncate,
+    /// Fail with [`ContextOverflow`] and leave the history unchanged.
+    Error,
+    /// Replace the oldest turns with a summary the model writes of them.
+    Summarize,
+}
+
+impl ContextPolicy {
+    pub const ALL: [ContextPolicy; 3] = [
+        ContextPolicy::Truncate,
+        ContextPolicy::Error,
+        ContextPolicy::Summarize,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContextPolicy::Truncate => "truncate",
+            ContextPolicy::Error => "error",
+            ContextPolicy::Summarize => "summarize",
+        }
+    }
+}
+
+impl FromStr for ContextPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.to_ascii_lowercase();
+        ContextPolicy::ALL
+            .iter()
+            .copied()
+            .find(|p| p.as_str() == s)
+            .ok_or_else(|| {
+                format!(
+                    "unknown context policy '{}' (expected truncate, error or summarize)",
+                    s
+                )
+            })
+    }
+}
+
+/// A prompt and its completion budget need more context than the model has.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ContextOverflow {
+    /// Tokens the prompt and completion need.
+    pub needed: usize,
+    /// Context length of the model.
+    pub available: usize,
+}
+
+impl fmt::Display for ContextOverflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Prompt is too large for the model context window ({} > {}).",
+            self.needed, self.available
+        )
+    }
+}
+
+impl std::error::Error for ContextOverflow {}
+
+#[cfg(test)]
+mod tests {
+    use super::{ContextOverflow, ContextPolicy};
+
+    #[test]
+    fn parses_policies_and_downcasts_overflow() {
+        assert_eq!("Error".parse::<ContextPolicy>(), Ok(ContextPolicy::Error));
+        assert_eq!(
+            "summarize".parse::<ContextPolicy>(),
+            Ok(ContextPolicy::Summarize)
+        );
+        assert!("drop".parse::<ContextPolicy>().is_err());
+
+        let err: anyhow::Error = ContextOverflow {
+            needed: 5000,
+            available: 4096,
+        }
+        .into();
+        assert_eq!(
+            err.downcast_ref::<ContextOverflow>().map(|o| o.needed),
+            Some(5000)
+        );
+        assert!(err.to_string().contains("(5000 > 4096)"));
+    }
+}
@@ -0,0 +1,167 @@
+//! Harvesting exchanges as fine-tuning data (`--collect`).
+//!
+//! Every kept reply is appended to a JSON Lines file as one record in the
+//! chat format most SFT tooling reads: a `messages` list with the system
+//! prompt, the conversation and the reply last, plus the sampler settings
+//! it was generated with. Message contents are redacted before they are
+//! written, and a hook can rewrite or drop each record on top of that.
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use super::generator::Message;
+use super::redact::{RedactionConfig, Redactor};
+use super::session::SessionParams;
+
+/// One exchange, as written to the dataset file.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct DatasetRecord {
+    /// System prompt, then the conversation up to and including the reply.
+    pub messages: Vec<Message>,
+    pub params: SessionParams,
+    /// File name of the model that wrote the reply.
+    pub model: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl DatasetRecord {
+    /// Record for the reply that ends `history`. Only roles, names and
+    /// contents are kept; timestamps and metadata are left out.
+    pub fn new(
+        system_prompt: Option<&str>,
+        history: &[Message],
+        params: SessionParams,
+        model: impl Into<String>,
+    ) -> Self {
+        let system = system_prompt
+            .filter(|s| !s.is_empty())
+            .map(|s| Message::new("system", s));
+        let messages = system
+            .into_iter()
+            .chain(history.iter().map(|m| Message {
+                name: m.name.clone(),
+                ..Message::new(m.role.clone(), m.content.clone())
+            }))
+            .collect();
+        Self {
+            messages,
+            params,
+            model: model.into(),
+            created_at: chrono::Utc::now(),
+        }
+    }
+
+    /// The reply the record ends with.
+    pub fn completion(&self) -> Option<&str> {
+        self.messages
+            .last()
+            .filter(|m| m.role == "assistant")
+            .map(|m| m.content.as_str())
+    }
+
+    pub fn redact(&mut self, redactor: &Redactor) {
+        for message in &mut self.messages {
+            message.content = redactor.redact(&message.content);
+        }
+    }
+}
+
+/// Called with each redacted record; returns the record to write, possibly
+/// changed, or `None` to drop it.
+pub type DatasetHook = Box<dyn FnMut(DatasetRecord) -> Result<Option<DatasetRecord>>>;
+
+/// Appends [`DatasetRecord`]s to a JSON Lines file.
+pub struct DatasetWriter {
+    file: File,
+    redactor: Redactor,
+    hook: Option<DatasetHook>,
+}
+
+impl DatasetWriter {
+    /// Opens `path` for appending. Records are redacted with `redaction`.
+    pub fn open(path: &Path, redaction: &RedactionConfig) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        Ok(Self {
+            file,
+            redactor: Redactor::new(redaction)?,
+            hook: None,
+        })
+    }
+
+    pub fn with_hook(
+        mut self,
+        hook: impl FnMut(DatasetRecord) -> Result<Option<DatasetRecord>> + 'static,
+    ) -> Self {
+        self.hook = Some(Box::new(hook));
+        self
+    }
+
+    /// Redacts `record`, runs the hook and appends what is left. Returns
+    /// whether a line was written.
+    pub fn append(&mut self, mut record: DatasetRecord) -> Result<bool> {
+        record.redact(&self.redactor);
+        let record = match self.hook.as_mut() {
+            Some(hook) => match hook(record)? {
+                Some(record) => record,
+                None => return Ok(false),
+            },
+            None => record,
+        };
+        let mut line = serde_json::to_string(&record)?;
+        line.push('\n');
+        // One write per record, so concurrent sessions do not interleave lines.
+        self.file.write_all(line.as_bytes())?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DatasetRecord, DatasetWriter};
+    use crate::inference::{Message, RedactionConfig, SessionParams};
+
+    #[test]
+    fn appends_redacted_records_and_honours_the_hook() {
+        let params = SessionParams {
+            temperature: 0.7,
+            top_p: None,
+            top_k: None,
+            repeat_penalty: 1.1,
+            repeat_last_n: 64,
+            max_tokens: 0,
+            seed: 42,
+        };
+        let history = vec![
+            Message::new("user", "Mail me at jane@example.com"),
+            Message::new("assistant", "Noted."),
+        ];
+        let record = DatasetRecord::new(Some("Be brief."), &history, params, "tiny.gguf");
+        assert_eq!(record.messages.len(), 3);
+        assert_eq!(record.completion(), Some("Noted."));
+
+        let path = std::env::temp_dir().join(format!("oxide-dataset-{}.jsonl", std::process::id()));
+        let mut writer = DatasetWriter::open(&path, &RedactionConfig::default())
+            .unwrap()
+            .with_hook(|record: DatasetRecord| {
+                Ok(Some(record).filter(|r| r.completion() != Some("drop")))
+            });
+        assert!(writer.append(record.clone()).unwrap());
+        let mut dropped = record;
+        dropped.messages.last_mut().unwrap().content = "drop".to_string();
+        assert!(!writer.append(dropped).unwrap());
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(text.lines().count(), 1);
+        let written: DatasetRecord = serde_json::from_str(text.trim()).unwrap();
+        assert_eq!(written.messages[0].role, "system");
+        assert!(!written.messages[1].content.contains("jane@example.com"));
+    }
+}
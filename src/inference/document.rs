@@ -0,0 +1,187 @@
+//! Document mode: questions about a document longer than the context window.
+//!
+//! A document that fits its share of the context is pinned as it is. A
+//! longer one is read in segments: for each segment the model rewrites its
+//! running notes, which are carried forward to the next one. Once the notes
+//! of a stretch of segments fill their budget, that stretch is closed as a
+//! section and a new one starts; when the sections outgrow the document's
+//! share, the smallest pair of neighbours is condensed into one. The result
+//! is pinned ahead of the conversation, so every later question sees it.
+
+/// Tokens reserved for the instruction and chat template around a segment.
+pub(crate) const PROMPT_OVERHEAD_TOKENS: usize = 160;
+/// Smallest segment worth reading.
+pub(crate) const MIN_SEGMENT_TOKENS: usize = 256;
+
+#[derive(Clone, Debug)]
+pub struct DocumentOptions {
+    /// Tokens of the context the pinned document may take (`0` = half).
+    pub budget_tokens: usize,
+    /// Longest notes on one section (`0` = a quarter of the budget).
+    pub notes_tokens: usize,
+    /// Question the notes should keep the details for, when known up front.
+    pub question: Option<String>,
+    pub repeat_penalty: f32,
+    pub repeat_last_n: usize,
+}
+
+impl Default for DocumentOptions {
+    fn default() -> Self {
+        Self {
+            budget_tokens: 0,
+            notes_tokens: 0,
+            question: None,
+            repeat_penalty: 1.1,
+            repeat_last_n: 64,
+        }
+    }
+}
+
+/// Progress reported while reading a document.
+pub enum DocumentEvent {
+    /// The document is too long to pin and is read in `segments` parts.
+    Segmented { segments: usize },
+    /// Reading segment `index` (from 0) of `total` started.
+    SegmentStarted { index: usize, total: usize },
+    /// The notes on segments `first` to `last` were condensed into one
+    /// section to fit the budget.
+    Condensed { first: usize, last: usize },
+}
+
+/// Notes on a run of consecutive segments.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DocumentSection {
+    /// First segment covered, counted from 1.
+    pub first: usize,
+    /// Last segment covered, inclusive.
+    pub last: usize,
+    pub notes: String,
+    pub tokens: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct DocumentDigest {
+    /// Segments the document was read in; 1 when it was pinned as it is.
+    pub segments: usize,
+    /// Notes pinned in place of the document; empty when it was pinned as
+    /// it is.
+    pub sections: Vec<DocumentSection>,
+    /// Tokens of the pinned text.
+    pub tokens: usize,
+}
+
+impl DocumentDigest {
+    pub fn is_verbatim(&self) -> bool {
+        self.sections.is_empty()
+    }
+}
+
+/// Asks for the running notes rewritten with what segment `index` of
+/// `total` adds.
+pub(crate) fn notes_prompt(
+    notes: &str,
+    segment: &str,
+    index: usize,
+    total: usize,
+    question: Option<&str>,
+) -> String {
+    let mut prompt = String::from(
+        "You are reading a long document one part at a time. Rewrite your notes so far to \
+         include what the next part adds. Keep names, numbers, dates, definitions and \
+         conclusions, drop repetition, and reply with the notes only.",
+    );
+    if let Some(question) = question {
+        prompt.push_str(&format!(
+            " Keep every detail that could help answer: {}",
+            question
+        ));
+    }
+    let notes = if notes.is_empty() {
+        "(none yet)"
+    } else {
+        notes
+    };
+    prompt.push_str(&format!(
+        "\n\nNotes so far:\n{}\n\nPart {} of {}:\n{}",
+        notes, index, total, segment
+    ));
+    prompt
+}
+
+/// Asks for the notes on two neighbouring sections merged into shorter ones.
+pub(crate) fn condense_prompt(
+    first: &DocumentSection,
+    second: &DocumentSection,
+    question: Option<&str>,
+) -> String {
+    let mut prompt = String::from(
+        "Below are notes on two consecutive stretches of one document. Merge them into one \
+         shorter set of notes, keeping names, numbers, dates and conclusions. Reply with the \
+         notes only.",
+    );
+    if let Some(question) = question {
+        prompt.push_str(&format!(
+            " Keep every detail that could help answer: {}",
+            question
+        ));
+    }
+    prompt.push_str(&format!(
+        "\n\n{}:\n{}\n\n{}:\n{}",
+        parts_label(first),
+        first.notes,
+        parts_label(second),
+        second.notes
+    ));
+    prompt
+}
+
+fn parts_label(section: &DocumentSection) -> String {
+    if section.first == section.last {
+        format!("Part {}", section.first)
+    } else {
+        format!("Parts {}-{}", section.first, section.last)
+    }
+}
+
+/// The text pinned for `sections` of a document read in `segments` parts.
+pub fn render_sections(sections: &[DocumentSection], segments: usize) -> String {
+    sections
+        .iter()
+        .map(|s| format!("Notes on {} of {}:\n{}", parts_label(s), segments, s.notes))
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// Index of the first of the two neighbouring sections with the fewest
+/// tokens between them, or `None` with fewer than two sections.
+pub(crate) fn smallest_pair(sections: &[DocumentSection]) -> Option<usize> {
+    (0..sections.len().saturating_sub(1))
+        .min_by_key(|&i| sections[i].tokens + sections[i + 1].tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render_sections, smallest_pair, DocumentSection};
+
+    #[test]
+    fn labels_sections_and_picks_the_smallest_pair() {
+        let section = |first: usize, last: usize, tokens: usize| DocumentSection {
+            first,
+            last,
+            notes: format!("notes {}", first),
+            tokens,
+        };
+        let sections = [
+            section(1, 3, 90),
+            section(4, 4, 40),
+            section(5, 7, 30),
+            section(8, 9, 80),
+        ];
+        assert_eq!(smallest_pair(&sections), Some(1));
+        assert_eq!(smallest_pair(&sections[..1]), None);
+        assert_eq!(
+            render_sections(&sections[..2], 9),
+            "Notes on Parts 1-3 of 9:\nnotes 1\n\nNotes on Part 4 of 9:\nnotes 4"
+        );
+    }
+}
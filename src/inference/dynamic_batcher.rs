@@ -1,19 +1,27 @@
 //! Dynamic Batching for LLM Inference
 //!
-//! Groups incoming requests into small batches (max 8) that arrive within
-//! a configurable time window (default 100ms) for improved throughput while
-//! maintaining low latency.
+//! Groups incoming requests into small batches (max 8) for improved
+//! throughput while maintaining low latency. A [`BatchingPolicy`] decides how
+//! long to wait for a batch to fill; the default [`AdaptiveWindow`]
+//! dispatches immediately under light load and waits up to
+//! `batch_window_ms` (default 100ms) under heavy load.
+//!
+//! [`DynamicBatcher::subscribe_events`] reports every queue and batch step
+//! as a [`BatchEvent`], for dashboards that track window utilization.
 
+use std::collections::VecDeque;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
-use tokio::sync::{mpsc, oneshot};
+use tokio::sync::{broadcast, mpsc, oneshot};
 use tokio::time::timeout;
 
+use crate::inference::cancel::CancelToken;
 use crate::inference::Generator;
 
 pub struct BatchConfig {
     pub max_batch_size: usize,
+    /// Longest time the default [`AdaptiveWindow`] waits to fill a batch.
     pub batch_window_ms: u64,
     pub max_queue_size: usize,
 }
@@ -38,8 +46,145 @@ impl Clone for BatchConfig {
     }
 }
 
+/// Decides how long the batcher keeps collecting requests before it
+/// dispatches a partial batch. A full batch is always dispatched at once.
+pub trait BatchingPolicy: Send {
+    /// Called for every request, with the time it was queued.
+    fn on_arrival(&mut self, _enqueued_at: Instant) {}
+
+    /// How long, counted from the first pending request, to wait for more
+    /// before dispatching `pending` requests. Zero dispatches immediately.
+    fn window(&mut self, pending: usize, max_batch_size: usize) -> Duration;
+}
+
+/// Always waits the same time, like the original `batch_window_ms`.
+pub struct FixedWindow(pub Duration);
+
+impl BatchingPolicy for FixedWindow {
+    fn window(&mut self, _pending: usize, _max_batch_size: usize) -> Duration {
+        self.0
+    }
+}
+
+/// Sizes the window from the recent arrival rate. When the next request is
+/// not expected within `max_window`, batches go out immediately, so light
+/// load adds no latency. Under load the window grows to the time the
+/// remaining slots take to fill, capped at `max_window`.
+pub struct AdaptiveWindow {
+    max_window: Duration,
+    /// Smoothed gap between arrivals; `None` until two have been seen.
+    interval: Option<Duration>,
+    last_arrival: Option<Instant>,
+}
+
+impl AdaptiveWindow {
+    /// Weight of the newest gap in the moving average.
+    const SMOOTHING: f64 = 0.2;
+
+    pub fn new(max_window: Duration) -> Self {
+        Self {
+            max_window,
+            interval: None,
+            last_arrival: None,
+        }
+    }
+
+    /// Current estimate of requests per second, if known.
+    pub fn arrival_rate(&self) -> Option<f64> {
+        self.interval
+            .filter(|i| !i.is_zero())
+            .map(|i| 1.0 / i.as_secs_f64())
+    }
+}
+
+impl BatchingPolicy for AdaptiveWindow {
+    fn on_arrival(&mut self, enqueued_at: Instant) {
+        if let Some(last) = self.last_arrival {
+            let gap = enqueued_at.saturating_duration_since(last);
+            self.interval = Some(match self.interval {
+                Some(interval) => interval.mul_f64(1.0 - Self::SMOOTHING) + gap.mul_f64(Self::SMOOTHING),
+                None => gap,
+            });
+        }
+        self.last_arrival = Some(self.last_arrival.map_or(enqueued_at, |last| last.max(enqueued_at)));
+    }
+
+    fn window(&mut self, pending: usize, max_batch_size: usize) -> Duration {
+        match self.interval {
+            Some(interval) if interval < self.max_window => {
+                let open_slots = max_batch_size.saturating_sub(pending) as u32;
+                (interval * open_slots).min(self.max_window)
+            }
+            _ => Duration::ZERO,
+        }
+    }
+}
+
+/// Events buffered per subscriber before the oldest are dropped.
+const EVENT_CAPACITY: usize = 256;
+
+/// Why a batch was closed and sent to the generator.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BatchTrigger {
+    /// `max_batch_size` requests were collected.
+    Full,
+    /// The batch window elapsed.
+    WindowElapsed,
+    /// The batcher is shutting down.
+    Closed,
+}
+
+/// Scheduling class of a request. Interactive requests are always batched
+/// and run before queued background work.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RequestClass {
+    /// Latency-sensitive traffic such as chat.
+    #[default]
+    Interactive,
+    /// Bulk jobs. Run only when no interactive request is waiting, and give
+    /// way to new interactive requests between decode steps or prefill
+    /// chunks.
+    Background,
+}
+
+/// Progress of requests through the batcher.
+#[derive(Clone, Debug)]
+pub enum BatchEvent {
+    /// A request entered the queue.
+    Enqueued { request_id: u64 },
+    /// A request announced by `Enqueued` was turned away because the batcher
+    /// has stopped; no other event follows for it.
+    Rejected { request_id: u64 },
+    /// A request left the queue and joined the batch being formed.
+    Dequeued { request_id: u64, queue_wait: Duration },
+    /// A batch was closed. `window` is how long it collected requests.
+    BatchFormed {
+        batch_id: u64,
+        class: RequestClass,
+        size: usize,
+        max_batch_size: usize,
+        window: Duration,
+        trigger: BatchTrigger,
+    },
+    /// A background batch stopped early for interactive work; `requeued`
+    /// requests, including the one cut off mid-generation, go back to the
+    /// front of the background lane.
+    Preempted { batch_id: u64, requeued: usize },
+    /// A batch finished generating. `size` counts the requests it ran.
+    BatchCompleted {
+        batch_id: u64,
+        class: RequestClass,
+        size: usize,
+        duration: Duration,
+        success: bool,
+    },
+}
+
 pub struct BatchRequest {
     pub id: u64,
+    pub class: RequestClass,
+    /// When the request was queued; used for the `queue_wait` of events.
+    pub enqueued_at: Instant,
     pub prompt: String,
     pub max_tokens: usize,
     pub repeat_penalty: f32,
@@ -52,49 +197,128 @@ pub struct BatchResult {
     pub result: Result<String, String>,
 }
 
+/// Requests taken off the queue and waiting for a batch.
+struct Lanes {
+    interactive: Vec<BatchRequest>,
+    background: VecDeque<BatchRequest>,
+    /// When the first pending interactive request arrived.
+    window_start: Instant,
+}
+
+impl Lanes {
+    fn new(max_batch_size: usize) -> Self {
+        Self {
+            interactive: Vec::with_capacity(max_batch_size),
+            background: VecDeque::new(),
+            window_start: Instant::now(),
+        }
+    }
+
+    fn accept(&mut self, req: BatchRequest, policy: &mut dyn BatchingPolicy, events: &broadcast::Sender<BatchEvent>) {
+        let _ = events.send(BatchEvent::Dequeued {
+            request_id: req.id,
+            queue_wait: req.enqueued_at.elapsed(),
+        });
+        match req.class {
+            RequestClass::Interactive => {
+                // Only interactive traffic sizes the batching window.
+                policy.on_arrival(req.enqueued_at);
+                if self.interactive.is_empty() {
+                    self.window_start = Instant::now();
+                }
+                self.interactive.push(req);
+            }
+            RequestClass::Background => self.background.push_back(req),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.interactive.len() + self.background.len()
+    }
+
+    /// Takes the requests already queued without waiting, until the lanes
+    /// hold `limit`; the rest stay in the channel so senders keep feeling
+    /// its backpressure. Returns whether the queue is closed.
+    fn drain(
+        &mut self,
+        request_rx: &mut mpsc::Receiver<BatchRequest>,
+        limit: usize,
+        policy: &mut dyn BatchingPolicy,
+        events: &broadcast::Sender<BatchEvent>,
+    ) -> bool {
+        while self.len() < limit {
+            match request_rx.try_recv() {
+                Ok(req) => self.accept(req, policy, events),
+                Err(mpsc::error::TryRecvError::Empty) => return false,
+                Err(mpsc::error::TryRecvError::Disconnected) => return true,
+            }
+        }
+        false
+    }
+}
+
 pub struct DynamicBatcher {
     config: BatchConfig,
     request_tx: mpsc::Sender<BatchRequest>,
     batch_counter: Arc<std::sync::atomic::AtomicU64>,
+    events: broadcast::Sender<BatchEvent>,
 }
 
 impl DynamicBatcher {
     pub fn new(config: BatchConfig) -> Self {
-        let (request_tx, request_rx) = mpsc::channel(config.max_queue_size);
-        let batch_counter = Arc::new(std::sync::atomic::AtomicU64::new(0));
+        Self::spawn(config, None)
+    }
 
-        let counter_clone = batch_counter.clone();
-        let config_clone = config.clone();
+    pub fn with_generator(config: BatchConfig, generator: Arc<tokio::sync::Mutex<Generator>>) -> Self {
+        Self::spawn(config, Some(generator))
+    }
 
-        tokio::spawn(async move {
-            Self::batcher_loop(request_rx, config_clone, counter_clone, None).await;
-        });
+    /// Like [`new`](Self::new) or [`with_generator`](Self::with_generator),
+    /// with a custom [`BatchingPolicy`] instead of [`AdaptiveWindow`].
+    pub fn with_policy(
+        config: BatchConfig,
+        generator: Option<Arc<tokio::sync::Mutex<Generator>>>,
+        policy: Box<dyn BatchingPolicy>,
+    ) -> Self {
+        Self::spawn_with_policy(config, generator, policy)
+    }
 
-        Self {
-            config,
-            request_tx,
-            batch_counter,
-        }
+    fn spawn(config: BatchConfig, generator: Option<Arc<tokio::sync::Mutex<Generator>>>) -> Self {
+        let policy = Box::new(AdaptiveWindow::new(Duration::from_millis(config.batch_window_ms)));
+        Self::spawn_with_policy(config, generator, policy)
     }
 
-    pub fn with_generator(config: BatchConfig, generator: Arc<tokio::sync::Mutex<Generator>>) -> Self {
+    fn spawn_with_policy(
+        config: BatchConfig,
+        generator: Option<Arc<tokio::sync::Mutex<Generator>>>,
+        policy: Box<dyn BatchingPolicy>,
+    ) -> Self {
         let (request_tx, request_rx) = mpsc::channel(config.max_queue_size);
         let batch_counter = Arc::new(std::sync::atomic::AtomicU64::new(0));
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
 
-        let counter_clone = batch_counter.clone();
         let config_clone = config.clone();
+        let events_clone = events.clone();
 
         tokio::spawn(async move {
-            Self::batcher_loop(request_rx, config_clone, counter_clone, Some(generator)).await;
+            Self::batcher_loop(request_rx, config_clone, policy, events_clone, generator).await;
         });
 
         Self {
             config,
             request_tx,
             batch_counter,
+            events,
         }
     }
 
+    /// Receives a [`BatchEvent`] for every enqueue, rejection, dequeue,
+    /// formed batch and completed batch from now on. A subscriber that falls more than
+    /// 256 events behind gets `RecvError::Lagged` and skips ahead.
+    pub fn subscribe_events(&self) -> broadcast::Receiver<BatchEvent> {
+        self.events.subscribe()
+    }
+
     pub fn config(&self) -> &BatchConfig {
         &self.config
     }
@@ -105,6 +329,19 @@ impl DynamicBatcher {
         max_tokens: usize,
         repeat_penalty: f32,
         repeat_last_n: usize,
+    ) -> Result<String, String> {
+        self.generate_with_class(prompt, max_tokens, repeat_penalty, repeat_last_n, RequestClass::Interactive)
+            .await
+    }
+
+    /// Queues a request in the given lane and waits for its result.
+    pub async fn generate_with_class(
+        &self,
+        prompt: String,
+        max_tokens: usize,
+        repeat_penalty: f32,
+        repeat_last_n: usize,
+        class: RequestClass,
     ) -> Result<String, String> {
         let id = self
             .batch_counter
@@ -114,6 +351,8 @@ impl DynamicBatcher {
 
         let request = BatchRequest {
             id,
+            class,
+            enqueued_at: Instant::now(),
             prompt,
             max_tokens,
             repeat_penalty,
@@ -121,10 +360,13 @@ impl DynamicBatcher {
             sender,
         };
 
-        self.request_tx
-            .send(request)
-            .await
-            .map_err(|_| "Batcher channel closed".to_string())?;
+        // Sent first so subscribers never see the dequeue before it, and
+        // followed by `Rejected` if the request never makes it in.
+        let _ = self.events.send(BatchEvent::Enqueued { request_id: id });
+        if self.request_tx.send(request).await.is_err() {
+            let _ = self.events.send(BatchEvent::Rejected { request_id: id });
+            return Err("Batcher channel closed".to_string());
+        }
 
         match receiver.await {
             Ok(result) => result.result,
@@ -135,118 +377,240 @@ impl DynamicBatcher {
     async fn batcher_loop(
         mut request_rx: mpsc::Receiver<BatchRequest>,
         config: BatchConfig,
-        _counter: Arc<std::sync::atomic::AtomicU64>,
+        mut policy: Box<dyn BatchingPolicy>,
+        events: broadcast::Sender<BatchEvent>,
         generator: Option<Arc<tokio::sync::Mutex<Generator>>>,
     ) {
-        let window_duration = Duration::from_millis(config.batch_window_ms);
         let max_batch_size = config.max_batch_size;
-
-        let mut pending_requests: Vec<BatchRequest> = Vec::with_capacity(max_batch_size);
-        let mut last_batch_time = Instant::now();
+        let max_queue_size = config.max_queue_size.max(max_batch_size);
+        let mut lanes = Lanes::new(max_batch_size);
+        let mut batch_id = 0u64;
+        let mut closed = false;
 
         loop {
-            let time_since_last = last_batch_time.elapsed();
+            if !closed {
+                closed = lanes.drain(&mut request_rx, max_queue_size, policy.as_mut(), &events);
+            }
 
-            if pending_requests.is_empty() {
-                if let Some(req) = request_rx.recv().await {
-                    pending_requests.push(req);
-                    last_batch_time = Instant::now();
+            if !lanes.interactive.is_empty() {
+                let trigger = if lanes.interactive.len() >= max_batch_size {
+                    Some(BatchTrigger::Full)
+                } else if closed {
+                    Some(BatchTrigger::Closed)
                 } else {
-                    break;
-                }
-            } else if time_since_last >= window_duration || pending_requests.len() >= max_batch_size {
-                if !pending_requests.is_empty() {
-                    let requests: Vec<_> = pending_requests.drain(..).collect();
-                    Self::process_batch(requests, generator.clone()).await;
-                    last_batch_time = Instant::now();
-                }
-            } else {
-                match timeout(Duration::from_millis(1), request_rx.recv()).await {
-                    Ok(Some(req)) => {
-                        if pending_requests.len() < max_batch_size {
-                            pending_requests.push(req);
-                        } else {
-                            if !pending_requests.is_empty() {
-                                let requests: Vec<_> = pending_requests.drain(..).collect();
-                                Self::process_batch(requests, generator.clone()).await;
-                                last_batch_time = Instant::now();
+                    let window = policy.window(lanes.interactive.len(), max_batch_size);
+                    let remaining = window.saturating_sub(lanes.window_start.elapsed());
+                    if remaining.is_zero() || lanes.len() >= max_queue_size {
+                        Some(BatchTrigger::WindowElapsed)
+                    } else {
+                        match timeout(remaining, request_rx.recv()).await {
+                            Ok(Some(req)) => {
+                                lanes.accept(req, policy.as_mut(), &events);
+                                None
+                            }
+                            Ok(None) => {
+                                closed = true;
+                                Some(BatchTrigger::Closed)
                             }
-                            pending_requests.push(req);
+                            Err(_) => Some(BatchTrigger::WindowElapsed),
                         }
                     }
-                    Ok(None) => {
-                        if !pending_requests.is_empty() {
-                            let requests: Vec<_> = pending_requests.drain(..).collect();
-                            Self::process_batch(requests, generator.clone()).await;
+                };
+
+                if let Some(trigger) = trigger {
+                    let take = lanes.interactive.len().min(max_batch_size);
+                    let requests: Vec<_> = lanes.interactive.drain(..take).collect();
+                    let id = Self::next_batch_id(&mut batch_id);
+                    let size = requests.len();
+                    Self::formed(&events, id, size, max_batch_size, lanes.window_start.elapsed(), trigger, RequestClass::Interactive);
+                    let started = Instant::now();
+                    let success = Self::process_batch(requests, generator.clone()).await;
+                    Self::completed(&events, id, size, started.elapsed(), success, RequestClass::Interactive);
+                }
+                continue;
+            }
+
+            if !lanes.background.is_empty() {
+                // Background requests run one at a time and are cancelled
+                // between decode steps when interactive work arrives; the cut
+                // off request and the rest of the batch are requeued. A run
+                // that finished before it saw the cancel is answered.
+                let take = lanes.background.len().min(max_batch_size);
+                let mut batch: VecDeque<_> = lanes.background.drain(..take).collect();
+                let id = Self::next_batch_id(&mut batch_id);
+                let trigger = if take == max_batch_size { BatchTrigger::Full } else { BatchTrigger::WindowElapsed };
+                Self::formed(&events, id, take, max_batch_size, Duration::ZERO, trigger, RequestClass::Background);
+
+                let started = Instant::now();
+                let mut success = true;
+                let mut processed = 0;
+                while let Some(req) = batch.pop_front() {
+                    let cancel = CancelToken::new();
+                    let run = Self::run_batch(
+                        vec![req.prompt.clone()],
+                        req.max_tokens,
+                        req.repeat_penalty,
+                        req.repeat_last_n,
+                        generator.clone(),
+                        Some(cancel.clone()),
+                    );
+                    tokio::pin!(run);
+                    let outcome = loop {
+                        tokio::select! {
+                            outcome = &mut run => break outcome,
+                            received = request_rx.recv(), if !closed && lanes.len() < max_queue_size => match received {
+                                Some(next) => {
+                                    if next.class == RequestClass::Interactive {
+                                        cancel.cancel();
+                                    }
+                                    lanes.accept(next, policy.as_mut(), &events);
+                                }
+                                None => closed = true,
+                            },
                         }
-                        break;
+                    };
+
+                    if matches!(outcome, Ok((_, true))) {
+                        batch.push_front(req);
+                    } else {
+                        success &= Self::answer(vec![req], outcome.map(|(outputs, _)| outputs));
+                        processed += 1;
+                    }
+                    if !closed {
+                        closed = lanes.drain(&mut request_rx, max_queue_size, policy.as_mut(), &events);
                     }
-                    Err(_) => {
-                        if !pending_requests.is_empty() {
-                            let requests: Vec<_> = pending_requests.drain(..).collect();
-                            Self::process_batch(requests, generator.clone()).await;
-                            last_batch_time = Instant::now();
+                    if !lanes.interactive.is_empty() && !batch.is_empty() {
+                        let _ = events.send(BatchEvent::Preempted {
+                            batch_id: id,
+                            requeued: batch.len(),
+                        });
+                        while let Some(req) = batch.pop_back() {
+                            lanes.background.push_front(req);
                         }
                     }
                 }
+                Self::completed(&events, id, processed, started.elapsed(), success, RequestClass::Background);
+                continue;
+            }
+
+            if closed {
+                break;
+            }
+            match request_rx.recv().await {
+                Some(req) => lanes.accept(req, policy.as_mut(), &events),
+                None => closed = true,
             }
         }
     }
 
-    async fn process_batch(requests: Vec<BatchRequest>, generator: Option<Arc<tokio::sync::Mutex<Generator>>>) {
-        if requests.is_empty() {
-            return;
-        }
+    fn next_batch_id(counter: &mut u64) -> u64 {
+        let id = *counter;
+        *counter += 1;
+        id
+    }
+
+    fn formed(
+        events: &broadcast::Sender<BatchEvent>,
+        batch_id: u64,
+        size: usize,
+        max_batch_size: usize,
+        window: Duration,
+        trigger: BatchTrigger,
+        class: RequestClass,
+    ) {
+        let _ = events.send(BatchEvent::BatchFormed {
+            batch_id,
+            class,
+            size,
+            max_batch_size,
+            window,
+            trigger,
+        });
+    }
+
+    fn completed(
+        events: &broadcast::Sender<BatchEvent>,
+        batch_id: u64,
+        size: usize,
+        duration: Duration,
+        success: bool,
+        class: RequestClass,
+    ) {
+        let _ = events.send(BatchEvent::BatchCompleted {
+            batch_id,
+            class,
+            size,
+            duration,
+            success,
+        });
+    }
+
+    /// Runs a batch and answers every request. Returns whether it succeeded.
+    async fn process_batch(requests: Vec<BatchRequest>, generator: Option<Arc<tokio::sync::Mutex<Generator>>>) -> bool {
+        let Some(first) = requests.first() else {
+            return true;
+        };
 
         tracing::debug!("Processing batch of {} requests", requests.len());
 
-        match generator {
-            Some(gen) => {
-                let prompts: Vec<String> = requests.iter().map(|r| r.prompt.clone()).collect();
-                let max_tokens = requests.first().map(|r| r.max_tokens).unwrap_or(512);
-                let repeat_penalty = requests.first().map(|r| r.repeat_penalty).unwrap_or(1.1);
-                let repeat_last_n = requests.first().map(|r| r.repeat_last_n).unwrap_or(64);
-
-                let results = tokio::task::spawn_blocking(move || {
-                    let mut gen = gen.blocking_lock();
-                    gen.generate_batch(prompts, max_tokens, repeat_penalty, repeat_last_n)
-                })
-                .await;
-
-                match results {
-                    Ok(Ok(outputs)) => {
-                        for (req, result) in requests.into_iter().zip(outputs.into_iter()) {
-                            let _ = req.sender.send(BatchResult {
-                                id: req.id,
-                                result: Ok(result),
-                            });
-                        }
-                    }
-                    Ok(Err(e)) => {
-                        for req in requests {
-                            let _ = req.sender.send(BatchResult {
-                                id: req.id,
-                                result: Err(e.to_string()),
-                            });
-                        }
-                    }
-                    Err(e) => {
-                        for req in requests {
-                            let _ = req.sender.send(BatchResult {
-                                id: req.id,
-                                result: Err(format!("Task join error: {}", e)),
-                            });
-                        }
-                    }
+        let prompts: Vec<String> = requests.iter().map(|r| r.prompt.clone()).collect();
+        let (max_tokens, repeat_penalty, repeat_last_n) = (first.max_tokens, first.repeat_penalty, first.repeat_last_n);
+        let outcome = Self::run_batch(prompts, max_tokens, repeat_penalty, repeat_last_n, generator, None).await;
+        Self::answer(requests, outcome.map(|(outputs, _)| outputs))
+    }
+
+    /// Generates a reply to every prompt. Once `cancel` is cancelled the
+    /// generator stops at its next decode step and the replies are partial;
+    /// the flag says whether that cut the last reply short.
+    async fn run_batch(
+        prompts: Vec<String>,
+        max_tokens: usize,
+        repeat_penalty: f32,
+        repeat_last_n: usize,
+        generator: Option<Arc<tokio::sync::Mutex<Generator>>>,
+        cancel: Option<CancelToken>,
+    ) -> Result<(Vec<String>, bool), String> {
+        let Some(gen) = generator else {
+            return Err("Generator not connected to batcher".to_string());
+        };
+
+        tokio::task::spawn_blocking(move || {
+            let mut gen = gen.blocking_lock();
+            let with_token = cancel.is_some();
+            if with_token {
+                gen.set_cancel_token(cancel);
+            }
+            let outputs = gen.generate_batch(prompts, max_tokens, repeat_penalty, repeat_last_n);
+            if with_token {
+                gen.set_cancel_token(None);
+            }
+            outputs.map(|outputs| (outputs, gen.cancelled())).map_err(|e| e.to_string())
+        })
+        .await
+        .unwrap_or_else(|e| Err(format!("Task join error: {}", e)))
+    }
+
+    /// Sends each request its reply, or the error to all of them. Returns
+    /// whether the batch succeeded.
+    fn answer(requests: Vec<BatchRequest>, outcome: Result<Vec<String>, String>) -> bool {
+        match outcome {
+            Ok(outputs) => {
+                for (req, result) in requests.into_iter().zip(outputs) {
+                    let _ = req.sender.send(BatchResult {
+                        id: req.id,
+                        result: Ok(result),
+                    });
                 }
+                true
             }
-            None => {
+            Err(e) => {
                 for req in requests {
                     let _ = req.sender.send(BatchResult {
                         id: req.id,
-                        result: Err("Generator not connected to batcher".to_string()),
+                        result: Err(e.clone()),
                     });
                 }
+                false
             }
         }
     }
@@ -258,6 +622,7 @@ impl Clone for DynamicBatcher {
             config: self.config.clone(),
             request_tx: self.request_tx.clone(),
             batch_counter: self.batch_counter.clone(),
+            events: self.events.clone(),
         }
     }
 }
@@ -279,6 +644,23 @@ impl DynamicBatcherHandle {
         }
     }
 
+    pub fn subscribe_events(&self) -> broadcast::Receiver<BatchEvent> {
+        self.batcher.subscribe_events()
+    }
+
+    pub async fn generate_with_class(
+        &self,
+        prompt: String,
+        max_tokens: usize,
+        repeat_penalty: f32,
+        repeat_last_n: usize,
+        class: RequestClass,
+    ) -> Result<String, String> {
+        self.batcher
+            .generate_with_class(prompt, max_tokens, repeat_penalty, repeat_last_n, class)
+            .await
+    }
+
     pub async fn generate(
         &self,
         prompt: String,
@@ -311,4 +693,171 @@ mod tests {
         assert_eq!(config.batch_window_ms, 100);
         assert_eq!(config.max_queue_size, 100);
     }
+
+    #[tokio::test]
+    async fn test_interactive_requests_run_before_background() {
+        let batcher = DynamicBatcher::new(BatchConfig::default());
+        let mut events = batcher.subscribe_events();
+
+        let background = |prompt: &str| {
+            batcher.generate_with_class(prompt.to_string(), 8, 1.1, 64, RequestClass::Background)
+        };
+        // All four are queued before the batcher task first runs.
+        let _ = tokio::join!(
+            background("a"),
+            background("b"),
+            background("c"),
+            batcher.generate("chat".to_string(), 8, 1.1, 64),
+        );
+
+        let mut formed = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            if let BatchEvent::BatchFormed { class, size, .. } = event {
+                formed.push((class, size));
+            }
+        }
+        assert_eq!(formed, vec![(RequestClass::Interactive, 1), (RequestClass::Background, 3)]);
+    }
+
+    #[tokio::test]
+    async fn test_batches_never_exceed_max_batch_size() {
+        let config = BatchConfig {
+            max_batch_size: 2,
+            ..BatchConfig::default()
+        };
+        let batcher = DynamicBatcher::new(config);
+        let mut events = batcher.subscribe_events();
+
+        let chat = |prompt: &str| batcher.generate(prompt.to_string(), 8, 1.1, 64);
+        let _ = tokio::join!(chat("a"), chat("b"), chat("c"), chat("d"), chat("e"));
+
+        let mut sizes = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            if let BatchEvent::BatchFormed { size, .. } = event {
+                sizes.push(size);
+            }
+        }
+        assert_eq!(sizes, vec![2, 2, 1]);
+    }
+
+    #[test]
+    fn test_adaptive_window_follows_load() {
+        let max = Duration::from_millis(100);
+        let mut policy = AdaptiveWindow::new(max);
+        let start = Instant::now();
+
+        // Unknown or light load: dispatch immediately.
+        assert_eq!(policy.window(1, 8), Duration::ZERO);
+        for i in 0..5 {
+            policy.on_arrival(start + Duration::from_secs(i));
+        }
+        assert_eq!(policy.window(1, 8), Duration::ZERO);
+
+        // A burst every 5ms brings the window down to the fill time.
+        let burst = start + Duration::from_secs(10);
+        for i in 0..60 {
+            policy.on_arrival(burst + Duration::from_millis(5 * i));
+        }
+        let rate = policy.arrival_rate().unwrap();
+        assert!((rate - 200.0).abs() < 5.0, "rate {}", rate);
+        let window = policy.window(4, 8);
+        assert!(window > Duration::from_millis(15) && window < Duration::from_millis(25));
+        assert!(policy.window(1, 64) == max);
+        assert_eq!(policy.window(8, 8), Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn test_events_follow_a_request_through_a_batch() {
+        let batcher = DynamicBatcher::new(BatchConfig::default());
+        let mut events = batcher.subscribe_events();
+
+        // Without a generator the batch fails, but it is still formed.
+        assert!(batcher.generate("hi".to_string(), 8, 1.1, 64).await.is_err());
+
+        assert!(matches!(events.recv().await, Ok(BatchEvent::Enqueued { request_id: 0 })));
+        assert!(matches!(events.recv().await, Ok(BatchEvent::Dequeued { request_id: 0, .. })));
+        match events.recv().await {
+            Ok(BatchEvent::BatchFormed { batch_id, size, max_batch_size, .. }) => {
+                assert_eq!((batch_id, size, max_batch_size), (0, 1, 8));
+            }
+            other => panic!("expected BatchFormed, got {:?}", other),
+        }
+        assert!(matches!(
+            events.recv().await,
+            Ok(BatchEvent::BatchCompleted { batch_id: 0, size: 1, success: false, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_rejected_requests_are_reported() {
+        let (request_tx, request_rx) = mpsc::channel(1);
+        drop(request_rx);
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+        let batcher = DynamicBatcher {
+            config: BatchConfig::default(),
+            request_tx,
+            batch_counter: Arc::new(std::sync::atomic::AtomicU64::new(0)),
+            events,
+        };
+        let mut events = batcher.subscribe_events();
+
+        assert!(batcher.generate("hi".to_string(), 8, 1.1, 64).await.is_err());
+        assert!(matches!(events.recv().await, Ok(BatchEvent::Enqueued { request_id: 0 })));
+        assert!(matches!(events.recv().await, Ok(BatchEvent::Rejected { request_id: 0 })));
+    }
+
+    /// Queues a request the moment a reply is done, before the batcher has
+    /// seen that run finish.
+    struct ArriveOnDone {
+        request: Option<BatchRequest>,
+        request_tx: mpsc::Sender<BatchRequest>,
+    }
+
+    impl crate::inference::GenerationHooks for ArriveOnDone {
+        fn on_done(&mut self, _generated_tokens: usize, _elapsed: Duration) {
+            if let Some(request) = self.request.take() {
+                let _ = self.request_tx.try_send(request);
+                // Let the batcher take the request while the run is still
+                // winding down.
+                std::thread::sleep(Duration::from_millis(50));
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_finished_background_reply_is_kept_when_preempted() {
+        let dir = std::env::temp_dir().join(format!("oxide-preempt-{}", std::process::id()));
+        let generator = Arc::new(tokio::sync::Mutex::new(
+            crate::inference::generator::tests::scripted(&dir, vec![1]),
+        ));
+        let batcher = DynamicBatcher::with_generator(BatchConfig::default(), generator.clone());
+        let mut events = batcher.subscribe_events();
+
+        let (sender, interactive) = oneshot::channel();
+        let request = BatchRequest {
+            id: 100,
+            class: RequestClass::Interactive,
+            enqueued_at: Instant::now(),
+            prompt: "ab".to_string(),
+            max_tokens: 2,
+            repeat_penalty: 1.0,
+            repeat_last_n: 64,
+            sender,
+        };
+        generator.lock().await.add_hooks(Box::new(ArriveOnDone {
+            request: Some(request),
+            request_tx: batcher.request_tx.clone(),
+        }));
+
+        let background = batcher
+            .generate_with_class("ab".to_string(), 2, 1.0, 64, RequestClass::Background)
+            .await;
+        assert_eq!(background.unwrap(), "bb");
+        assert_eq!(interactive.await.unwrap().result.unwrap(), "bb");
+
+        while let Ok(event) = events.try_recv() {
+            assert!(!matches!(event, BatchEvent::Preempted { .. }), "{:?}", event);
+        }
+        std::fs::remove_dir_all(&dir).ok();
+    }
 }
@@ -0,0 +1,404 @@
+//! Prompt suites scored by simple checkers or a judge model
+//! (`oxide-rs eval`).
+//!
+//! A suite is a YAML file of cases. Each case is a prompt, answered from an
+//! empty conversation, and any number of checks on the reply: an exact
+//! answer, phrases it must contain, a regex it must match, and criteria a
+//! judge model grades with PASS or FAIL. A case passes when all its checks
+//! do; cases without checks are only recorded.
+
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use regex::Regex;
+
+use super::generator::Generator;
+
+const DEFAULT_JUDGE_PROMPT: &str = "You grade answers written by an AI assistant. You \
+     get a question, the answer and the criteria a good answer meets. Reply with PASS \
+     if the answer meets every criterion and FAIL otherwise, then one short sentence \
+     explaining why.";
+
+/// Tokens the judge may spend on its verdict and reason.
+const JUDGE_MAX_TOKENS: usize = 96;
+
+/// A suite file.
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EvalSuite {
+    #[serde(default)]
+    pub name: Option<String>,
+    /// System prompt for every case.
+    #[serde(default)]
+    pub system: Option<String>,
+    #[serde(default)]
+    pub judge: Option<JudgeConfig>,
+    pub cases: Vec<EvalCase>,
+}
+
+/// Model that grades `criteria`.
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JudgeConfig {
+    /// GGUF file; the model under test when omitted.
+    #[serde(default)]
+    pub model: Option<PathBuf>,
+    /// Instructions replacing the built-in judge prompt.
+    #[serde(default)]
+    pub system: Option<String>,
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EvalCase {
+    /// Name in the report; `case-<n>` when omitted.
+    #[serde(default)]
+    pub id: Option<String>,
+    pub prompt: String,
+    /// The whole reply, ignoring surrounding whitespace and case.
+    #[serde(default)]
+    pub exact: Option<String>,
+    /// Phrases the reply must contain, ignoring case.
+    #[serde(default)]
+    pub contains: Vec<String>,
+    /// Pattern the reply must match.
+    #[serde(default, with = "serde_regex")]
+    pub regex: Option<Regex>,
+    /// What a good reply does, graded by the judge.
+    #[serde(default)]
+    pub criteria: Option<String>,
+    /// Overrides the run's `max_tokens`.
+    #[serde(default)]
+    pub max_tokens: Option<usize>,
+}
+
+mod serde_regex {
+    use regex::Regex;
+    use serde::{Deserialize, Deserializer};
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Regex>, D::Error> {
+        Option::<String>::deserialize(d)?
+            .map(|pattern| Regex::new(&pattern).map_err(serde::de::Error::custom))
+            .transpose()
+    }
+}
+
+impl EvalSuite {
+    /// Reads and checks a suite. A relative judge model path is resolved
+    /// against the file's directory.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read eval suite {:?}", path))?;
+        let mut suite: Self = serde_yaml::from_str(&text)
+            .with_context(|| format!("Invalid eval suite {:?}", path))?;
+        if let Some(model) = suite.judge.as_mut().and_then(|j| j.model.as_mut()) {
+            if model.is_relative() {
+                *model = path.parent().unwrap_or(Path::new("")).join(&*model);
+            }
+        }
+        suite.validate()?;
+        Ok(suite)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.cases.is_empty() {
+            anyhow::bail!("An eval suite needs at least one case");
+        }
+        for (i, case) in self.cases.iter().enumerate() {
+            if case.prompt.trim().is_empty() {
+                anyhow::bail!("Case {} has an empty prompt", i + 1);
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether any case needs the judge.
+    pub fn needs_judge(&self) -> bool {
+        self.cases.iter().any(|c| c.criteria.is_some())
+    }
+}
+
+impl EvalCase {
+    /// Results of every check except the judge's.
+    pub fn check(&self, reply: &str) -> Vec<CheckResult> {
+        let mut results = Vec::new();
+        let folded = reply.to_lowercase();
+        if let Some(expected) = &self.exact {
+            results.push(CheckResult {
+                check: "exact".to_string(),
+                passed: reply.trim().to_lowercase() == expected.trim().to_lowercase(),
+                detail: expected.clone(),
+            });
+        }
+        for phrase in &self.contains {
+            results.push(CheckResult {
+                check: "contains".to_string(),
+                passed: folded.contains(&phrase.to_lowercase()),
+                detail: phrase.clone(),
+            });
+        }
+        if let Some(regex) = &self.regex {
+            results.push(CheckResult {
+                check: "regex".to_string(),
+                passed: regex.is_match(reply),
+                detail: regex.as_str().to_string(),
+            });
+        }
+        results
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct CheckResult {
+    /// `exact`, `contains`, `regex` or `judge`.
+    pub check: String,
+    pub passed: bool,
+    /// The expected text or pattern, or the judge's reason.
+    pub detail: String,
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct CaseResult {
+    pub id: String,
+    pub prompt: String,
+    pub reply: String,
+    pub checks: Vec<CheckResult>,
+    /// `None` for cases without checks.
+    pub passed: Option<bool>,
+    /// Generated tokens, counting the one out of prefill.
+    pub tokens: usize,
+    #[serde(serialize_with = "seconds")]
+    pub duration: Duration,
+}
+
+fn seconds<S: serde::Serializer>(duration: &Duration, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_f64(duration.as_secs_f64())
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct EvalReport {
+    pub suite: String,
+    pub model: String,
+    /// Cases with checks.
+    pub scored: usize,
+    pub passed: usize,
+    pub cases: Vec<CaseResult>,
+}
+
+impl EvalReport {
+    fn new(suite: String, model: String, cases: Vec<CaseResult>) -> Self {
+        let scored = cases.iter().filter(|c| c.passed.is_some()).count();
+        let passed = cases.iter().filter(|c| c.passed == Some(true)).count();
+        Self {
+            suite,
+            model,
+            scored,
+            passed,
+            cases,
+        }
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut out = format!("# {}\n\nModel: `{}`\n\n", self.suite, self.model);
+        if self.scored > 0 {
+            let rate = self.passed as f64 * 100.0 / self.scored as f64;
+            let _ = writeln!(
+                out,
+                "Score: {}/{} ({:.0}%)\n",
+                self.passed, self.scored, rate
+            );
+        }
+        out.push_str("| Case | Result | Failed checks | Tokens | Time |\n");
+        out.push_str("|---|---|---|---|---|\n");
+        for case in &self.cases {
+            let result = match case.passed {
+                Some(true) => "pass",
+                Some(false) => "FAIL",
+                None => "-",
+            };
+            let failed: Vec<String> = case
+                .checks
+                .iter()
+                .filter(|c| !c.passed)
+                .map(|c| format!("{} `{}`", c.check, c.detail))
+                .collect();
+            let _ = writeln!(
+                out,
+                "| {} | {} | {} | {} | {:.1}s |",
+                case.id,
+                result,
+                failed.join("; ").replace('|', "\\|").replace('\n', " "),
+                case.tokens,
+                case.duration.as_secs_f64()
+            );
+        }
+        out
+    }
+}
+
+/// Settings every case is generated with. Each case samples with the same
+/// seed, so runs are repeatable.
+#[derive(Clone, Debug)]
+pub struct EvalOptions {
+    pub temperature: f64,
+    pub top_p: Option<f64>,
+    pub top_k: Option<usize>,
+    pub seed: u64,
+    pub max_tokens: usize,
+    pub repeat_penalty: f32,
+    pub repeat_last_n: usize,
+}
+
+/// Runs every case of `suite` on `generator`, calling `on_case` as each one
+/// finishes. Criteria are graded by `judge`, or by `generator` itself when
+/// `None`. Every case starts from an empty conversation.
+pub fn run_suite<F>(
+    suite: &EvalSuite,
+    model: &str,
+    generator: &mut Generator,
+    mut judge: Option<&mut Generator>,
+    options: &EvalOptions,
+    mut on_case: F,
+) -> Result<EvalReport>
+where
+    F: FnMut(&CaseResult),
+{
+    let mut cases = Vec::with_capacity(suite.cases.len());
+    for (i, case) in suite.cases.iter().enumerate() {
+        generator.set_system_prompt(suite.system.clone())?;
+        generator.set_history(Vec::new())?;
+        generator.set_sampling(
+            options.temperature,
+            options.top_p,
+            options.top_k,
+            options.seed,
+        );
+        let started = Instant::now();
+        let reply = generator.generate(
+            &case.prompt,
+            case.max_tokens.unwrap_or(options.max_tokens),
+            options.repeat_penalty,
+            options.repeat_last_n,
+            |_| {},
+        )?;
+        let duration = started.elapsed();
+        let tokens = generator.token_latencies().len() + !reply.is_empty() as usize;
+        let reply = reply.trim().to_string();
+
+        let mut checks = case.check(&reply);
+        if let Some(criteria) = &case.criteria {
+            let judge = match judge.as_deref_mut() {
+                Some(judge) => judge,
+                None => &mut *generator,
+            };
+            let config = suite.judge.clone().unwrap_or_default();
+            checks.push(grade(
+                judge,
+                &config,
+                options,
+                &case.prompt,
+                &reply,
+                criteria,
+            )?);
+        }
+        let result = CaseResult {
+            id: case.id.clone().unwrap_or_else(|| format!("case-{}", i + 1)),
+            prompt: case.prompt.clone(),
+            reply,
+            passed: (!checks.is_empty()).then(|| checks.iter().all(|c| c.passed)),
+            checks,
+            tokens,
+            duration,
+        };
+        on_case(&result);
+        cases.push(result);
+    }
+    let name = suite.name.clone().unwrap_or_else(|| "Eval".to_string());
+    Ok(EvalReport::new(name, model.to_string(), cases))
+}
+
+/// Asks the judge whether `reply` meets `criteria`, greedily.
+fn grade(
+    judge: &mut Generator,
+    config: &JudgeConfig,
+    options: &EvalOptions,
+    prompt: &str,
+    reply: &str,
+    criteria: &str,
+) -> Result<CheckResult> {
+    let system = config
+        .system
+        .clone()
+        .unwrap_or_else(|| DEFAULT_JUDGE_PROMPT.to_string());
+    judge.set_system_prompt(Some(system))?;
+    judge.set_history(Vec::new())?;
+    judge.set_sampling(0.0, None, None, options.seed);
+    let question = format!(
+        "Question:\n{}\n\nAnswer:\n{}\n\nCriteria:\n{}",
+        prompt, reply, criteria
+    );
+    let verdict = judge.generate(
+        &question,
+        JUDGE_MAX_TOKENS,
+        options.repeat_penalty,
+        options.repeat_last_n,
+        |_| {},
+    )?;
+    Ok(CheckResult {
+        check: "judge".to_string(),
+        passed: parse_verdict(&verdict),
+        detail: verdict.trim().to_string(),
+    })
+}
+
+/// PASS when it comes before any FAIL in the judge's answer.
+fn parse_verdict(verdict: &str) -> bool {
+    let upper = verdict.to_uppercase();
+    match (upper.find("PASS"), upper.find("FAIL")) {
+        (Some(pass), Some(fail)) => pass < fail,
+        (pass, _) => pass.is_some(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_verdict, EvalSuite};
+
+    #[test]
+    fn parses_suites_and_runs_checkers() {
+        let suite: EvalSuite = serde_yaml::from_str(
+            r#"
+            name: geography
+            cases:
+              - id: capital
+                prompt: What is the capital of France?
+                contains: [paris]
+                regex: '^\w'
+              - prompt: Say only "yes".
+                exact: Yes
+              - prompt: Describe Paris.
+                criteria: Mentions the Eiffel Tower.
+            "#,
+        )
+        .unwrap();
+        suite.validate().unwrap();
+        assert!(suite.needs_judge());
+
+        let checks = suite.cases[0].check("Paris is the capital.");
+        assert_eq!(checks.len(), 2);
+        assert!(checks.iter().all(|c| c.passed));
+        assert!(!suite.cases[0].check(" paris")[1].passed);
+        assert!(suite.cases[1].check(" yes\n")[0].passed);
+        assert!(!suite.cases[1].check("Yes, sure.")[0].passed);
+        assert!(suite.cases[2].check("anything").is_empty());
+
+        assert!(parse_verdict("PASS - it names the tower."));
+        assert!(!parse_verdict("FAIL: no pass mark."));
+        assert!(!parse_verdict("Unsure."));
+
+        let bad = "cases:\n  - prompt: hi\n    regex: '('\n";
+        assert!(serde_yaml::from_str::<EvalSuite>(bad).is_err());
+    }
+}
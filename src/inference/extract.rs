@@ -0,0 +1,328 @@
+//! Plain text from the files users hand the model.
+//!
+//! PDFs give up their text layer, HTML is reduced to its main content the
+//! way reader views do, and Markdown loses its front matter. Anything else
+//! is read as UTF-8 text.
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use regex::Regex;
+
+/// Elements whose content is never part of the reading text.
+const DROPPED_ELEMENTS: [&str; 13] = [
+    "head", "script", "style", "noscript", "template", "svg", "nav", "header", "footer", "aside",
+    "form", "iframe", "button",
+];
+
+/// Elements that start a new paragraph.
+const BLOCK_ELEMENTS: &str =
+    "p|div|section|article|main|ul|ol|dl|table|tr|blockquote|pre|figure|figcaption|dd|dt|hr";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileFormat {
+    Text,
+    Markdown,
+    Html,
+    Pdf,
+}
+
+impl FileFormat {
+    /// Format by extension, or by the first bytes when the extension is
+    /// missing or unknown.
+    pub fn detect(path: &Path, bytes: &[u8]) -> Self {
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_ascii_lowercase);
+        match extension.as_deref() {
+            Some("pdf") => return FileFormat::Pdf,
+            Some("html" | "htm" | "xhtml") => return FileFormat::Html,
+            Some("md" | "markdown" | "mdx") => return FileFormat::Markdown,
+            _ => {}
+        }
+        if bytes.starts_with(b"%PDF-") {
+            return FileFormat::Pdf;
+        }
+        let start = String::from_utf8_lossy(&bytes[..bytes.len().min(512)]).to_ascii_lowercase();
+        let start = start.trim_start_matches('\u{feff}').trim_start();
+        if start.starts_with("<!doctype html") || start.starts_with("<html") {
+            FileFormat::Html
+        } else {
+            FileFormat::Text
+        }
+    }
+}
+
+/// Reads `path` and extracts its text for its [`FileFormat`].
+pub fn read_document_text(path: &Path) -> Result<String> {
+    let bytes =
+        std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let format = FileFormat::detect(path, &bytes);
+    extract_text(&bytes, format).with_context(|| format!("Failed to read {}", path.display()))
+}
+
+/// The text of a file in `format`.
+pub fn extract_text(bytes: &[u8], format: FileFormat) -> Result<String> {
+    match format {
+        FileFormat::Pdf => pdf_text(bytes),
+        FileFormat::Html => Ok(html_text(&String::from_utf8_lossy(bytes))),
+        FileFormat::Markdown => Ok(strip_front_matter(utf8(bytes)?).to_string()),
+        FileFormat::Text => Ok(utf8(bytes)?.to_string()),
+    }
+}
+
+fn utf8(bytes: &[u8]) -> Result<&str> {
+    let text =
+        std::str::from_utf8(bytes).map_err(|_| anyhow::anyhow!("not UTF-8 text, PDF or HTML"))?;
+    Ok(text.trim_start_matches('\u{feff}'))
+}
+
+/// The text layer of every page, pages separated by blank lines. Scanned
+/// PDFs have none and are an error.
+fn pdf_text(bytes: &[u8]) -> Result<String> {
+    let document = lopdf::Document::load_mem(bytes).context("not a readable PDF")?;
+    let mut pages = Vec::new();
+    for number in document.get_pages().into_keys() {
+        match document.extract_text(&[number]) {
+            Ok(text) => pages.push(tidy_lines(&text)),
+            Err(e) => tracing::debug!("Skipping PDF page {}: {}", number, e),
+        }
+    }
+    let text = pages
+        .into_iter()
+        .filter(|page| !page.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    if text.is_empty() {
+        anyhow::bail!("the PDF has no text layer; run it through OCR first");
+    }
+    Ok(text)
+}
+
+/// The readable text of an HTML page: its title, then the first
+/// `<article>`, `<main>` or `<body>`, without navigation, scripts and
+/// other page furniture.
+pub fn html_text(html: &str) -> String {
+    let title = Regex::new(r"(?is)<title\b[^>]*>(.*?)</title\s*>")
+        .unwrap()
+        .captures(html)
+        .map(|c| decode_entities(c[1].trim()));
+
+    let mut html = Regex::new(r"(?s)<!--.*?-->")
+        .unwrap()
+        .replace_all(html, "")
+        .into_owned();
+    for element in DROPPED_ELEMENTS {
+        let pattern = format!(r"(?is)<{0}\b[^>]*>.*?</{0}\s*>", element);
+        html = Regex::new(&pattern)
+            .unwrap()
+            .replace_all(&html, "")
+            .into_owned();
+    }
+    let content = ["article", "main", "body"]
+        .iter()
+        .find_map(|element| {
+            let pattern = format!(r"(?is)<{0}\b[^>]*>(.*)</{0}\s*>", element);
+            Regex::new(&pattern)
+                .unwrap()
+                .captures(&html)
+                .map(|c| c[1].to_string())
+        })
+        .unwrap_or(html);
+
+    let content = Regex::new(r"(?i)<h([1-6])\b[^>]*>").unwrap().replace_all(
+        &content,
+        |c: &regex::Captures| {
+            let level = c[1].parse().unwrap_or(1);
+            format!("\n\n{} ", "#".repeat(level))
+        },
+    );
+    let content = Regex::new(r"(?i)</h[1-6]\s*>")
+        .unwrap()
+        .replace_all(&content, "\n\n");
+    let content = Regex::new(r"(?i)<li\b[^>]*>")
+        .unwrap()
+        .replace_all(&content, "\n- ");
+    let content = Regex::new(r"(?i)<br\s*/?>")
+        .unwrap()
+        .replace_all(&content, "\n");
+    let content = Regex::new(&format!(r"(?i)</?(?:{})\b[^>]*>", BLOCK_ELEMENTS))
+        .unwrap()
+        .replace_all(&content, "\n\n");
+    let content = Regex::new(r"(?i)</?t[dh]\b[^>]*>")
+        .unwrap()
+        .replace_all(&content, " ");
+    let content = Regex::new(r"<[^>]*>").unwrap().replace_all(&content, "");
+    let text = tidy_lines(&decode_entities(&content));
+
+    match title {
+        Some(title) if !title.is_empty() && !text.contains(&title) => {
+            format!("# {}\n\n{}", title, text)
+        }
+        _ => text,
+    }
+}
+
+/// Replaces character references with the characters they stand for.
+fn decode_entities(text: &str) -> String {
+    Regex::new(r"&(#[0-9]+|#[xX][0-9a-fA-F]+|[a-zA-Z]+);")
+        .unwrap()
+        .replace_all(text, |c: &regex::Captures| {
+            let name = &c[1];
+            let decoded = match name {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some(' '),
+                "ndash" => Some('–'),
+                "mdash" => Some('—'),
+                "hellip" => Some('…'),
+                "lsquo" => Some('‘'),
+                "rsquo" => Some('’'),
+                "ldquo" => Some('“'),
+                "rdquo" => Some('”'),
+                "copy" => Some('©'),
+                _ => {
+                    let code = match name.strip_prefix("#x").or(name.strip_prefix("#X")) {
+                        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                        None => name.strip_prefix('#').and_then(|d| d.parse().ok()),
+                    };
+                    code.and_then(char::from_u32)
+                }
+            };
+            decoded.map_or_else(|| c[0].to_string(), String::from)
+        })
+        .into_owned()
+}
+
+/// Collapses runs of spaces within lines and of blank lines between them.
+fn tidy_lines(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut blank = false;
+    for line in text.lines() {
+        let line = line.split_whitespace().collect::<Vec<_>>().join(" ");
+        if line.is_empty() {
+            blank = !out.is_empty();
+            continue;
+        }
+        if !out.is_empty() {
+            out.push_str(if blank { "\n\n" } else { "\n" });
+        }
+        out.push_str(&line);
+        blank = false;
+    }
+    out
+}
+
+/// Markdown without a leading YAML (`---`) or TOML (`+++`) front matter
+/// block.
+pub fn strip_front_matter(text: &str) -> &str {
+    for fence in ["---", "+++"] {
+        let Some(rest) = text.strip_prefix(fence) else {
+            continue;
+        };
+        let Some(rest) = rest.strip_prefix('\n').or(rest.strip_prefix("\r\n")) else {
+            continue;
+        };
+        let mut offset = 0;
+        for line in rest.split_inclusive('\n') {
+            offset += line.len();
+            let line = line.trim_end();
+            if line == fence || (fence == "---" && line == "...") {
+                return rest[offset..].trim_start_matches(['\r', '\n']);
+            }
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use lopdf::content::{Content, Operation};
+    use lopdf::{dictionary, Document, Object, Stream};
+
+    use super::{extract_text, html_text, strip_front_matter, FileFormat};
+
+    fn pdf(text: &str) -> Vec<u8> {
+        let mut doc = Document::with_version("1.5");
+        let pages_id = doc.new_object_id();
+        let font_id = doc.add_object(dictionary! {
+            "Type" => "Font",
+            "Subtype" => "Type1",
+            "BaseFont" => "Helvetica",
+            "Encoding" => "WinAnsiEncoding",
+        });
+        let content = Content {
+            operations: vec![
+                Operation::new("BT", vec![]),
+                Operation::new("Tf", vec!["F1".into(), 12.into()]),
+                Operation::new("Td", vec![72.into(), 720.into()]),
+                Operation::new("Tj", vec![Object::string_literal(text)]),
+                Operation::new("ET", vec![]),
+            ],
+        };
+        let content_id = doc.add_object(Stream::new(dictionary! {}, content.encode().unwrap()));
+        let page_id = doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "Contents" => content_id,
+            "Resources" => dictionary! { "Font" => dictionary! { "F1" => font_id } },
+            "MediaBox" => vec![0.into(), 0.into(), 612.into(), 792.into()],
+        });
+        doc.objects.insert(
+            pages_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => vec![page_id.into()],
+                "Count" => 1,
+            }),
+        );
+        let catalog_id = doc.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+        doc.trailer.set("Root", catalog_id);
+        let mut bytes = Vec::new();
+        doc.save_to(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn extracts_pdf_html_and_markdown() {
+        let bytes = pdf("The contract was signed in 1998.");
+        assert_eq!(
+            FileFormat::detect(Path::new("report"), &bytes),
+            FileFormat::Pdf
+        );
+        let text = extract_text(&bytes, FileFormat::Pdf).unwrap();
+        assert!(
+            text.contains("The contract was signed in 1998."),
+            "{:?}",
+            text
+        );
+
+        let html = "<!DOCTYPE html><html><head><title>Q3 &amp; Q4</title>\
+            <script>var x = '<p>no</p>';</script></head><body>\
+            <nav><a href=\"/\">Home</a></nav>\
+            <article><h2>Results</h2><p>Revenue rose&nbsp;12%.</p>\
+            <ul><li>North</li><li>South &#8211; flat</li></ul></article>\
+            <footer>© 2024</footer></body></html>";
+        assert_eq!(
+            FileFormat::detect(Path::new("page.txt"), html.as_bytes()),
+            FileFormat::Html
+        );
+        assert_eq!(
+            html_text(html),
+            "# Q3 & Q4\n\n## Results\n\nRevenue rose 12%.\n\n- North\n- South – flat"
+        );
+
+        let markdown = "---\ntitle: Notes\ntags: [a]\n---\n\n# Notes\n\nBody\n";
+        assert_eq!(strip_front_matter(markdown), "# Notes\n\nBody\n");
+        assert_eq!(strip_front_matter("+++\nx = 1\n+++\nBody"), "Body");
+        assert_eq!(strip_front_matter("---\nnot closed"), "---\nnot closed");
+        assert_eq!(strip_front_matter("Body\n---\n"), "Body\n---\n");
+        assert!(extract_text(&[0xff, 0xfe, 0], FileFormat::Text).is_err());
+    }
+}
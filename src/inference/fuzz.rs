@@ -0,0 +1,372 @@
+//! Seeded prompt perturbations for robustness testing (`oxide-rs fuzz`).
+//!
+//! A prompt is answered once as given and then in variants with typos,
+//! changed casing, irregular whitespace or reworded by the model itself.
+//! Answers are decoded greedily, so any difference comes from the prompt,
+//! and each is compared with the original answer by word overlap. The same
+//! seed gives the same variants.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::Result;
+
+use super::generator::Generator;
+
+const PARAPHRASE_PROMPT: &str = "Rewrite the user's message in different words, keeping \
+     its meaning, language and any names or numbers. Reply with the rewritten message \
+     only.";
+
+/// Temperature the paraphrases are sampled at, so variants differ.
+const PARAPHRASE_TEMPERATURE: f64 = 0.8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Perturbation {
+    /// Swapped, dropped and doubled letters.
+    Typos,
+    /// Lower, upper or randomly flipped case.
+    Case,
+    /// Doubled spaces, line breaks and padding.
+    Whitespace,
+    /// Reworded by the model under test.
+    Paraphrase,
+}
+
+impl Perturbation {
+    pub const ALL: [Perturbation; 4] = [
+        Perturbation::Typos,
+        Perturbation::Case,
+        Perturbation::Whitespace,
+        Perturbation::Paraphrase,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Perturbation::Typos => "typos",
+            Perturbation::Case => "case",
+            Perturbation::Whitespace => "whitespace",
+            Perturbation::Paraphrase => "paraphrase",
+        }
+    }
+}
+
+impl fmt::Display for Perturbation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Perturbation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.to_ascii_lowercase();
+        Perturbation::ALL
+            .iter()
+            .copied()
+            .find(|p| p.as_str() == s)
+            .ok_or_else(|| {
+                format!(
+                    "unknown perturbation '{}' (expected typos, case, whitespace or paraphrase)",
+                    s
+                )
+            })
+    }
+}
+
+/// SplitMix64; enough to pick reproducible edits.
+pub(crate) struct Rng(pub(crate) u64);
+
+impl Rng {
+    pub(crate) fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number below `n`, which must not be 0.
+    pub(crate) fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+/// `prompt` with `kind` applied, chosen by `seed`. Paraphrases need the
+/// model, so `Paraphrase` returns the prompt unchanged here.
+pub fn perturb(prompt: &str, kind: Perturbation, seed: u64) -> String {
+    let mut rng = Rng(seed);
+    match kind {
+        Perturbation::Typos => typos(prompt, &mut rng),
+        Perturbation::Case => match rng.below(3) {
+            0 => prompt.to_lowercase(),
+            1 => prompt.to_uppercase(),
+            _ => prompt
+                .chars()
+                .map(|c| match rng.below(2) {
+                    0 => c.to_uppercase().next().unwrap_or(c),
+                    _ => c.to_lowercase().next().unwrap_or(c),
+                })
+                .collect(),
+        },
+        Perturbation::Whitespace => {
+            let mut out = " ".repeat(rng.below(3));
+            for (i, word) in prompt.split(' ').enumerate() {
+                if i > 0 {
+                    out.push_str(match rng.below(6) {
+                        0 => "  ",
+                        1 => "\n",
+                        2 => " \t",
+                        _ => " ",
+                    });
+                }
+                out.push_str(word);
+            }
+            out.push_str(&"\n".repeat(rng.below(2)));
+            out
+        }
+        Perturbation::Paraphrase => prompt.to_string(),
+    }
+}
+
+/// About one edit per ten letters, and at least one when there is a word
+/// long enough to misspell.
+fn typos(prompt: &str, rng: &mut Rng) -> String {
+    let mut chars: Vec<char> = prompt.chars().collect();
+    // Letters with a letter after them, so swaps stay inside words.
+    let candidates: Vec<usize> = (0..chars.len().saturating_sub(1))
+        .filter(|&i| chars[i].is_alphabetic() && chars[i + 1].is_alphabetic())
+        .collect();
+    if candidates.is_empty() {
+        return prompt.to_string();
+    }
+    let edits = (candidates.len() / 10).max(1);
+    let mut picked: Vec<usize> = (0..edits)
+        .map(|_| candidates[rng.below(candidates.len())])
+        .collect();
+    picked.sort_unstable();
+    picked.dedup();
+    // Back to front, so earlier positions stay valid.
+    for &i in picked.iter().rev() {
+        match rng.below(3) {
+            0 => chars.swap(i, i + 1),
+            1 => {
+                chars.remove(i);
+            }
+            _ => chars.insert(i, chars[i]),
+        }
+    }
+    chars.into_iter().collect()
+}
+
+/// Share of distinct words the two texts have in common, ignoring case and
+/// punctuation: 1 for the same words, 0 for none in common.
+pub fn word_overlap(a: &str, b: &str) -> f64 {
+    let words = |text: &str| -> HashSet<String> {
+        text.split(|c: char| !c.is_alphanumeric())
+            .filter(|w| !w.is_empty())
+            .map(str::to_lowercase)
+            .collect()
+    };
+    let (a, b) = (words(a), words(b));
+    if a.is_empty() && b.is_empty() {
+        return 1.0;
+    }
+    a.intersection(&b).count() as f64 / a.union(&b).count() as f64
+}
+
+#[derive(Clone, Debug)]
+pub struct FuzzOptions {
+    pub perturbations: Vec<Perturbation>,
+    /// Variants per perturbation.
+    pub variants: usize,
+    pub seed: u64,
+    pub max_tokens: usize,
+    pub repeat_penalty: f32,
+    pub repeat_last_n: usize,
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct FuzzVariant {
+    pub perturbation: Perturbation,
+    pub prompt: String,
+    pub answer: String,
+    /// [`word_overlap`] with the original answer.
+    pub similarity: f64,
+    /// Same answer as the original, ignoring surrounding whitespace.
+    pub same: bool,
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct FuzzReport {
+    pub prompt: String,
+    pub answer: String,
+    /// Mean similarity over all variants; 1 when there are none.
+    pub stability: f64,
+    /// One summary per perturbation, in the order they ran.
+    pub summaries: Vec<FuzzSummary>,
+    pub variants: Vec<FuzzVariant>,
+}
+
+/// Mean similarity and identical answers of one perturbation's variants.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize)]
+pub struct FuzzSummary {
+    pub perturbation: Perturbation,
+    pub variants: usize,
+    pub same: usize,
+    pub similarity: f64,
+}
+
+impl FuzzReport {
+    fn new(prompt: String, answer: String, variants: Vec<FuzzVariant>) -> Self {
+        let mut kinds: Vec<Perturbation> = Vec::new();
+        for variant in &variants {
+            if !kinds.contains(&variant.perturbation) {
+                kinds.push(variant.perturbation);
+            }
+        }
+        let summaries = kinds
+            .into_iter()
+            .map(|kind| {
+                let variants: Vec<&FuzzVariant> =
+                    variants.iter().filter(|v| v.perturbation == kind).collect();
+                FuzzSummary {
+                    perturbation: kind,
+                    variants: variants.len(),
+                    same: variants.iter().filter(|v| v.same).count(),
+                    similarity: mean(variants.iter().map(|v| v.similarity)),
+                }
+            })
+            .collect();
+        Self {
+            prompt,
+            answer,
+            stability: mean(variants.iter().map(|v| v.similarity)),
+            summaries,
+            variants,
+        }
+    }
+}
+
+fn mean(values: impl Iterator<Item = f64>) -> f64 {
+    let (sum, count) = values.fold((0.0, 0), |(s, n), v| (s + v, n + 1));
+    if count == 0 {
+        1.0
+    } else {
+        sum / count as f64
+    }
+}
+
+/// Answers `prompt` and its variants from an empty conversation, calling
+/// `on_variant` as each one finishes. The generator's system prompt is
+/// used for every answer; its history is left empty.
+pub fn run_fuzz<F>(
+    generator: &mut Generator,
+    prompt: &str,
+    options: &FuzzOptions,
+    mut on_variant: F,
+) -> Result<FuzzReport>
+where
+    F: FnMut(&FuzzVariant),
+{
+    let system = generator.system_prompt().map(String::from);
+    let original = answer(generator, prompt, options)?;
+
+    let mut variants = Vec::new();
+    for (k, &kind) in options.perturbations.iter().enumerate() {
+        for i in 0..options.variants {
+            let seed = options
+                .seed
+                .wrapping_add((k * options.variants + i) as u64 + 1);
+            let perturbed = match kind {
+                Perturbation::Paraphrase => {
+                    let text = paraphrase(generator, prompt, seed, options);
+                    generator.set_system_prompt(system.clone())?;
+                    text?
+                }
+                _ => perturb(prompt, kind, seed),
+            };
+            let reply = answer(generator, &perturbed, options)?;
+            let variant = FuzzVariant {
+                perturbation: kind,
+                similarity: word_overlap(&original, &reply),
+                same: original == reply,
+                prompt: perturbed,
+                answer: reply,
+            };
+            on_variant(&variant);
+            variants.push(variant);
+        }
+    }
+    Ok(FuzzReport::new(prompt.to_string(), original, variants))
+}
+
+fn answer(generator: &mut Generator, prompt: &str, options: &FuzzOptions) -> Result<String> {
+    generator.set_history(Vec::new())?;
+    generator.set_sampling(0.0, None, None, options.seed);
+    let reply = generator.generate(
+        prompt,
+        options.max_tokens,
+        options.repeat_penalty,
+        options.repeat_last_n,
+        |_| {},
+    )?;
+    Ok(reply.trim().to_string())
+}
+
+fn paraphrase(
+    generator: &mut Generator,
+    prompt: &str,
+    seed: u64,
+    options: &FuzzOptions,
+) -> Result<String> {
+    generator.set_system_prompt(Some(PARAPHRASE_PROMPT.to_string()))?;
+    generator.set_history(Vec::new())?;
+    generator.set_sampling(PARAPHRASE_TEMPERATURE, None, None, seed);
+    // A rewording runs about as long as the prompt; leave room for more.
+    let budget = (prompt.len() / 2).max(32);
+    let text = generator.generate(
+        prompt,
+        budget,
+        options.repeat_penalty,
+        options.repeat_last_n,
+        |_| {},
+    )?;
+    let text = text.trim();
+    Ok(if text.is_empty() { prompt } else { text }.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{perturb, word_overlap, Perturbation};
+
+    #[test]
+    fn perturbs_reproducibly_and_measures_overlap() {
+        let prompt = "What is the capital of France and why?";
+        for kind in [
+            Perturbation::Typos,
+            Perturbation::Case,
+            Perturbation::Whitespace,
+        ] {
+            let variant = perturb(prompt, kind, 7);
+            assert_eq!(variant, perturb(prompt, kind, 7));
+            let differs = (0..8).any(|seed| perturb(prompt, kind, seed) != prompt);
+            assert!(differs, "{} never changed the prompt", kind);
+        }
+        let spaced = perturb(prompt, Perturbation::Whitespace, 3);
+        assert_eq!(
+            spaced.split_whitespace().collect::<Vec<_>>(),
+            prompt.split_whitespace().collect::<Vec<_>>()
+        );
+        assert_eq!(perturb("42 + 7", Perturbation::Typos, 1), "42 + 7");
+        assert_eq!(perturb(prompt, Perturbation::Paraphrase, 1), prompt);
+
+        assert_eq!(word_overlap("Paris.", "paris"), 1.0);
+        assert_eq!(word_overlap("Paris is big", "Paris is small"), 0.5);
+        assert_eq!(word_overlap("", ""), 1.0);
+        assert_eq!("CASE".parse(), Ok(Perturbation::Case));
+        assert!("emoji".parse::<Perturbation>().is_err());
+    }
+}
@@ -1,23 +1,209 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use anyhow::Result;
-use candle_transformers::generation::{LogitsProcessor, Sampling};
-use candle_transformers::utils::apply_repeat_penalty;
+use candle_transformers::generation::Sampling;
 use minijinja::{context, Environment};
 
+use crate::inference::cancel::CancelToken;
+use crate::inference::choice::{choose_label, Choice};
+use crate::inference::confidence::{Confidence, ConfidenceTracker};
+use crate::inference::context::{ContextOverflow, ContextPolicy};
+use crate::inference::document::{
+    self, DocumentDigest, DocumentEvent, DocumentOptions, DocumentSection,
+};
+use crate::inference::granularity::ends_sentence;
+use crate::inference::hooks::GenerationHooks;
+use crate::inference::language::{detect_language, language_name};
+use crate::inference::latency::LatencySummary;
+use crate::inference::loop_guard::{self, LoopAction, LoopGuard};
+use crate::inference::numerics::{self, NonFiniteLogits};
 use crate::inference::paged_cache::PagedKvCache;
-use crate::model::{GgufMetadata, Model, TokenizerWrapper};
+use crate::inference::preview::PromptPreview;
+use crate::inference::redact::{RedactionConfig, Redactor};
+use crate::inference::sampler::{
+    logprobs_for, LogitsChain, LogitsTransform, SamplerState, TokenCandidate, TokenLogprob,
+    TokenSampler, TopLogprob, TransformContext,
+};
+use crate::inference::scratch::StepScratch;
+use crate::inference::stop::{StopConditions, StopContext};
+use crate::inference::summarize::split_chunks;
+use crate::inference::verify::{CaseReport, ReferenceCase};
+use crate::inference::warmup::WarmupPolicy;
+use crate::model::{ActivationReport, GgufMetadata, LoadReport, Model, TokenizerWrapper};
 
 pub enum StreamEvent {
     Token(String),
     PrefillStatus(usize),
+    /// Emitted before prefill when history had to be dropped to fit the prompt.
+    ContextTruncated {
+        dropped_tokens: usize,
+        strategy: TruncationStrategy,
+    },
+    /// The loop guard is ending the reply because its last tokens repeat a
+    /// cycle of `period` tokens. `Done` follows.
+    LoopDetected {
+        period: usize,
+    },
     Done,
+    /// Generation failed after some of the reply was streamed; sent instead
+    /// of `Done`. The call returns a [`GenerationError`] with the same text.
+    Error(String),
 }
 
-#[derive(Clone, serde::Serialize)]
+/// Generation failed part way through a reply, e.g. on non-finite logits
+/// or a failed forward pass. The reply is not added to the history, but
+/// what was streamed before the failure is kept here. Callers can downcast
+/// to it from the returned error.
+#[derive(Debug)]
+pub struct GenerationError {
+    /// Reply text streamed before the failure.
+    pub partial: String,
+    pub tokens_generated: usize,
+    pub source: anyhow::Error,
+}
+
+impl std::fmt::Display for GenerationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Generation failed after {} tokens: {}",
+            self.tokens_generated, self.source
+        )
+    }
+}
+
+impl std::error::Error for GenerationError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.source.source()
+    }
+}
+
+/// What a reply had produced when generation stopped.
+#[derive(Debug, Default)]
+struct PartialReply {
+    text: String,
+    tokens: usize,
+}
+
+/// How the generator made room when a prompt did not fit the context window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TruncationStrategy {
+    /// Whole user/assistant turns were removed from the start of the history.
+    DropOldestTurns,
+    /// The oldest turns were replaced by a summary the model wrote of them.
+    Summarized,
+}
+
+impl TruncationStrategy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TruncationStrategy::DropOldestTurns => "drop_oldest_turns",
+            TruncationStrategy::Summarized => "summarized",
+        }
+    }
+}
+
+impl std::fmt::Display for TruncationStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The conversation state `token_history` was rendered from.
+#[derive(Clone, Debug, PartialEq)]
+struct RenderedHistory {
+    /// Length of `messages` when it was rendered.
+    messages: usize,
+    /// Language the system prompt was rendered for.
+    language: Option<String>,
+}
+
+/// Prompt tokens ready for prefill, plus what it took to fit them.
+struct PreparedPrompt {
+    tokens: Vec<u32>,
+    max_tokens: usize,
+    /// Tokens removed from the history to make the prompt fit; `0` if none.
+    dropped_tokens: usize,
+    strategy: TruncationStrategy,
+}
+
+impl PreparedPrompt {
+    fn truncation_event(&self) -> Option<StreamEvent> {
+        (self.dropped_tokens > 0).then_some(StreamEvent::ContextTruncated {
+            dropped_tokens: self.dropped_tokens,
+            strategy: self.strategy,
+        })
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct Message {
     pub role: String,
     pub content: String,
+    /// Speaker name, e.g. an agent in a multi-agent conversation. Exposed to
+    /// chat templates as `message.name`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// When the message was written. Exposed to chat templates as an RFC 3339
+    /// string in `message.timestamp`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<chrono::DateTime<chrono::Utc>>,
+    /// Arbitrary caller data, available to chat templates as `message.metadata`.
+    #[serde(default, skip_serializing_if = "serde_json::Value::is_null")]
+    pub metadata: serde_json::Value,
+}
+
+impl Message {
+    pub fn new(role: impl Into<String>, content: impl Into<String>) -> Self {
+        Self {
+            role: role.into(),
+            content: content.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn with_timestamp(mut self, timestamp: chrono::DateTime<chrono::Utc>) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    pub fn with_metadata(mut self, metadata: serde_json::Value) -> Self {
+        self.metadata = metadata;
+        self
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct Example {
+    user: String,
+    assistant: String,
+}
+
+/// Reads few-shot examples from a file of `{"user": ..., "assistant": ...}`
+/// objects, either as a JSON array or one object per line (JSONL).
+pub fn load_examples(path: &Path) -> Result<Vec<(String, String)>> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read examples file {:?}: {}", path, e))?;
+
+    let examples: Vec<Example> = if text.trim_start().starts_with('[') {
+        serde_json::from_str(&text)?
+    } else {
+        text.lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(serde_json::from_str)
+            .collect::<std::result::Result<_, _>>()?
+    };
+
+    Ok(examples
+        .into_iter()
+        .map(|example| (example.user, example.assistant))
+        .collect())
 }
 
 /// Holds a pre-compiled minijinja environment so the template string is parsed
@@ -25,6 +211,182 @@ pub struct Message {
 pub struct ChatTemplate {
     /// `None` when no chat template was embedded in the GGUF file.
     env: Option<Environment<'static>>,
+    /// `None` when the template does not render turns independently.
+    wrappers: Option<TurnWrappers>,
+    /// See [`turn_boundaries`](Self::turn_boundaries).
+    boundaries: Vec<String>,
+}
+
+/// Text a template puts around each user and assistant message, derived by
+/// rendering probe conversations. New turns can then be rendered on their
+/// own and appended to an earlier rendering of the history, instead of
+/// rendering the whole conversation again.
+#[derive(Clone, Debug, PartialEq)]
+struct TurnWrappers {
+    user: (String, String),
+    assistant: (String, String),
+    /// What `add_generation_prompt` appends.
+    generation_prompt: String,
+}
+
+/// Contents of the probe conversation: control characters no template adds
+/// or strips, and distinct.
+const PROBE_MARKERS: [&str; 5] = [
+    "\u{1}S\u{2}",
+    "\u{1}A\u{2}",
+    "\u{1}B\u{2}",
+    "\u{1}C\u{2}",
+    "\u{1}D\u{2}",
+];
+
+/// A system message and two exchanges with the given contents.
+fn probe_conversation(contents: [&str; 5]) -> [Message; 5] {
+    [
+        Message::new("system", contents[0]),
+        Message::new("user", contents[1]),
+        Message::new("assistant", contents[2]),
+        Message::new("user", contents[3]),
+        Message::new("assistant", contents[4]),
+    ]
+}
+
+impl TurnWrappers {
+    /// Reads the wrappers off renderings of the probe conversation, growing
+    /// one message at a time. Holds for the probed turns only; see
+    /// [`derive`](Self::derive).
+    fn probe(env: &Environment<'static>) -> Option<Self> {
+        let render = |messages: &[Message], add_generation_prompt: bool| {
+            render_chat(env, messages, add_generation_prompt, None).ok()
+        };
+        let markers = probe_conversation(PROBE_MARKERS);
+        let delta = |len: usize| -> Option<String> {
+            let before = render(&markers[..len - 1], false)?;
+            let after = render(&markers[..len], false)?;
+            after.strip_prefix(before.as_str()).map(str::to_string)
+        };
+        let split = |delta: &str, content: &str| -> Option<(String, String)> {
+            let (prefix, suffix) = delta.split_once(content)?;
+            (!suffix.contains(content)).then(|| (prefix.to_string(), suffix.to_string()))
+        };
+        let before_prompt = render(&markers[..4], false)?;
+        Some(Self {
+            assistant: split(&delta(3)?, &markers[2].content)?,
+            user: split(&delta(4)?, &markers[3].content)?,
+            generation_prompt: render(&markers[..4], true)?
+                .strip_prefix(before_prompt.as_str())?
+                .to_string(),
+        })
+    }
+
+    /// [`probe`](Self::probe)d wrappers, checked against full renderings
+    /// with and without a system message and with contents the template
+    /// might trim or escape. `None` if the template renders any turn
+    /// differently depending on what follows it, or transforms contents.
+    fn derive(env: &Environment<'static>) -> Option<Self> {
+        let render = |messages: &[Message], add_generation_prompt: bool| {
+            render_chat(env, messages, add_generation_prompt, None).ok()
+        };
+        let wrappers = Self::probe(env)?;
+        let markers = probe_conversation(PROBE_MARKERS);
+        let spaced = probe_conversation([
+            "  Be brief.\n",
+            " hi ",
+            "\nhello\n\n",
+            "<b>&amp;</b>",
+            " bye",
+        ]);
+        for messages in [&markers[..], &markers[1..], &spaced[..], &spaced[1..]] {
+            for add_generation_prompt in [false, true] {
+                let len = messages.len() - usize::from(add_generation_prompt);
+                let first = if messages[0].role == "system" { 2 } else { 1 };
+                let mut text = render(&messages[..first], false)?;
+                text.push_str(&wrappers.render(&messages[first..len], add_generation_prompt)?);
+                if text != render(&messages[..len], add_generation_prompt)? {
+                    return None;
+                }
+            }
+        }
+        Some(wrappers)
+    }
+
+    /// Text that starts a user turn or ends an assistant turn, trimmed. A
+    /// reply containing one has run on into a turn the model should not
+    /// write.
+    fn boundaries(&self) -> Vec<String> {
+        let mut boundaries = Vec::new();
+        for text in [&self.user.0, &self.assistant.1] {
+            let text = text.trim();
+            if !text.is_empty() && !boundaries.iter().any(|b| b == text) {
+                boundaries.push(text.to_string());
+            }
+        }
+        boundaries
+    }
+
+    /// Renders `turns`; `None` if one of them is not a plain user or
+    /// assistant message, whose rendering the probes did not cover.
+    fn render(&self, turns: &[Message], add_generation_prompt: bool) -> Option<String> {
+        let mut text = String::new();
+        for turn in turns {
+            if turn.name.is_some() || turn.timestamp.is_some() || !turn.metadata.is_null() {
+                return None;
+            }
+            let (prefix, suffix) = match turn.role.as_str() {
+                "user" => &self.user,
+                "assistant" => &self.assistant,
+                _ => return None,
+            };
+            text.push_str(prefix);
+            text.push_str(&turn.content);
+            text.push_str(suffix);
+        }
+        if add_generation_prompt {
+            text.push_str(&self.generation_prompt);
+        }
+        Some(text)
+    }
+}
+
+/// Whether a conversation encoded as its first turn followed by the rest,
+/// rendered with [`ChatTemplate::render_turns`] and encoded separately,
+/// gives the same tokens as encoding its full rendering. Fails for
+/// tokenizers that add EOS to every encoding or merge text across message
+/// boundaries.
+fn appends_cleanly(template: &ChatTemplate, tokenizer: &TokenizerWrapper) -> bool {
+    let messages = [
+        Message::new("user", "Hello there."),
+        Message::new("assistant", "Hi! How can I help?"),
+        Message::new("user", "Tell me a joke."),
+    ];
+    let check = || -> Result<bool> {
+        let Some(turns) = template.render_turns(&messages[1..], true) else {
+            return Ok(false);
+        };
+        let full = template.apply(&messages, true)?;
+        let first = template.apply(&messages[..1], false)?;
+        let mut tokens = tokenizer.encode_with_options(&first, true, true)?;
+        tokens.extend(tokenizer.encode_with_options(&turns, false, true)?);
+        Ok(tokens == tokenizer.encode_with_options(&full, true, true)?)
+    };
+    check().unwrap_or(false)
+}
+
+fn render_chat(
+    env: &Environment<'static>,
+    messages: &[Message],
+    add_generation_prompt: bool,
+    language: Option<&str>,
+) -> Result<String> {
+    let tmpl = env.get_template("chat")?;
+    let rendered = tmpl.render(context! {
+        messages => messages,
+        add_generation_prompt => add_generation_prompt,
+        enable_thinking => false,
+        add_vision_id => false,
+        language => language,
+        language_name => language.and_then(language_name),
+    })?;
+    Ok(rendered)
 }
 
 const STRIP_SEQUENCES: &[&str] = &[
@@ -43,9 +405,99 @@ const STRIP_SEQUENCES: &[&str] = &[
 
 const STOP_SEQUENCES: &[&str] = &["<|im_end|>", "<|end|>", "</s>"];
 
+/// Tokens held back from the context window when `max_tokens` is `auto` (`0`),
+/// so the last decode step never lands exactly on the context limit.
+const AUTO_MAX_TOKENS_MARGIN: usize = 16;
+
+/// Smallest completion budget `auto` accepts before older turns are dropped.
+const AUTO_MIN_COMPLETION_TOKENS: usize = 64;
+
+/// Prompt tokens per forward pass in low-memory mode. Attention scratch for
+/// a chunk is `n_head * chunk * context` floats instead of `n_head * prompt^2`.
+const LOW_MEM_PREFILL_CHUNK: usize = 32;
+
+/// Prompt tokens per forward pass while a cancel token is set, so a long
+/// prefill can be cancelled between chunks, e.g. by the batcher preempting
+/// background work for an interactive request.
+const PREEMPTIBLE_PREFILL_CHUNK: usize = 256;
+
+/// Extra tokens a reply may run past `max_tokens` to reach the end of a
+/// sentence when finishing at boundaries.
+const BOUNDARY_GRACE_TOKENS: usize = 48;
+
+/// Fewest prompt tokens a first-token budget may cut a prompt down to.
+const TTFT_MIN_PROMPT_TOKENS: usize = 16;
+
+/// Longest summary written of turns dropped under `ContextPolicy::Summarize`.
+const SUMMARY_MAX_TOKENS: usize = 256;
+
+const SUMMARY_INSTRUCTION: &str = "Summarize the following conversation in a few sentences. \
+Keep names, facts, decisions and open questions. Reply with the summary only.";
+
+/// Introduces the summary of dropped turns in the system message.
+const SUMMARY_PREFIX: &str = "Summary of the earlier conversation: ";
+
+/// Introduces the pinned document in the system message.
+const DOCUMENT_PREFIX: &str = "Answer the user's questions from this document, \
+given as notes on its parts where it was too long to include:\n\n";
+
+/// Where a reply has run on into a turn the model should not write: the
+/// chat template's [turn boundaries](ChatTemplate::turn_boundaries), as the
+/// decode loop sees them.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct TemplateStops {
+    /// Special tokens a boundary starts with. They are never decoded to
+    /// text, so they are matched by id.
+    tokens: Vec<u32>,
+    /// Boundaries made of ordinary tokens, matched in the decoded text.
+    texts: Vec<String>,
+}
+
+impl TemplateStops {
+    /// Splits `boundaries` by how each starts once encoded. Text boundaries
+    /// are kept only if they look like markup (`[INST]`, `### User:`), so a
+    /// plain word a template happens to put between turns cannot end
+    /// replies that use it.
+    fn new(
+        boundaries: &[String],
+        eos_token: u32,
+        encode: impl Fn(&str) -> Result<Vec<u32>>,
+        is_special: impl Fn(u32) -> bool,
+        decode: impl Fn(&[u32]) -> Result<String>,
+    ) -> Result<Self> {
+        let mut stops = Self::default();
+        for boundary in boundaries {
+            let tokens = encode(boundary)?;
+            let Some(&first) = tokens.first() else {
+                continue;
+            };
+            if is_special(first) {
+                if first != eos_token && !stops.tokens.contains(&first) {
+                    stops.tokens.push(first);
+                }
+                continue;
+            }
+            let plain = tokens
+                .iter()
+                .position(|&t| is_special(t))
+                .unwrap_or(tokens.len());
+            let text = decode(&tokens[..plain])?.trim().to_string();
+            let markup = text
+                .chars()
+                .any(|c| !c.is_alphanumeric() && !c.is_whitespace());
+            if text.len() >= 3 && markup && !stops.texts.contains(&text) {
+                stops.texts.push(text);
+            }
+        }
+        Ok(stops)
+    }
+}
+
 #[derive(Debug, Default)]
 struct ResponseProcessor {
     buffer: String,
+    /// Stop sequences in addition to [`STOP_SEQUENCES`].
+    stops: Vec<String>,
 }
 
 #[derive(Debug, Default)]
@@ -55,9 +507,10 @@ struct ProcessedChunk {
 }
 
 impl ResponseProcessor {
-    fn new() -> Self {
+    fn with_stops(stops: Vec<String>) -> Self {
         Self {
             buffer: String::new(),
+            stops,
         }
     }
 
@@ -65,7 +518,15 @@ impl ResponseProcessor {
         self.buffer.push_str(chunk);
         strip_full_sequences(&mut self.buffer);
 
-        if let Some(stop_idx) = earliest_sequence_index(&self.buffer, STOP_SEQUENCES) {
+        let stop_idx = earliest_sequence_index(&self.buffer, STOP_SEQUENCES)
+            .into_iter()
+            .chain(
+                self.stops
+                    .iter()
+                    .filter_map(|stop| self.buffer.find(stop.as_str())),
+            )
+            .min();
+        if let Some(stop_idx) = stop_idx {
             let text = strip_inline_sequences(&self.buffer[..stop_idx]);
             self.buffer.clear();
             return ProcessedChunk {
@@ -74,7 +535,7 @@ impl ResponseProcessor {
             };
         }
 
-        let keep_len = trailing_partial_match_len(&self.buffer);
+        let keep_len = trailing_partial_match_len(&self.buffer, &self.stops);
         let safe_len = self.buffer.len().saturating_sub(keep_len);
         let safe_len = floor_char_boundary(&self.buffer, safe_len);
 
@@ -91,6 +552,11 @@ impl ResponseProcessor {
         }
     }
 
+    /// Text held back in case it starts a stop or control sequence.
+    fn pending(&self) -> &str {
+        &self.buffer
+    }
+
     fn finish(&mut self) -> String {
         strip_full_sequences(&mut self.buffer);
         let text = strip_inline_sequences(&self.buffer);
@@ -99,15 +565,17 @@ impl ResponseProcessor {
     }
 }
 
-fn trailing_partial_match_len(input: &str) -> usize {
+fn trailing_partial_match_len(input: &str, stops: &[String]) -> usize {
     STRIP_SEQUENCES
         .iter()
         .chain(STOP_SEQUENCES.iter())
+        .copied()
+        .chain(stops.iter().map(String::as_str))
         .map(|pattern| {
             let max_len = input.len().min(pattern.len().saturating_sub(1));
             (1..=max_len)
                 .rev()
-                .find(|&len| input.ends_with(&pattern[..len]))
+                .find(|&len| pattern.is_char_boundary(len) && input.ends_with(&pattern[..len]))
                 .unwrap_or(0)
         })
         .max()
@@ -147,6 +615,30 @@ fn floor_char_boundary(input: &str, mut idx: usize) -> usize {
     idx
 }
 
+/// Resolves the completion budget for a prompt of `prompt_len` tokens.
+///
+/// A `max_tokens` of `0` means `auto`: the largest completion that fits the
+/// remaining context after the prompt and a small safety margin. Returns
+/// `None` when the prompt does not leave room for the requested budget.
+fn completion_budget(context_length: usize, prompt_len: usize, max_tokens: usize) -> Option<usize> {
+    if max_tokens == 0 {
+        let available = context_length.saturating_sub(prompt_len + AUTO_MAX_TOKENS_MARGIN);
+        (available >= AUTO_MIN_COMPLETION_TOKENS).then_some(available)
+    } else {
+        (prompt_len + max_tokens <= context_length).then_some(max_tokens)
+    }
+}
+
+/// Minimum context a prompt of `prompt_len` tokens needs for `max_tokens`,
+/// used in overflow error messages.
+fn required_context(prompt_len: usize, max_tokens: usize) -> usize {
+    if max_tokens == 0 {
+        prompt_len + AUTO_MAX_TOKENS_MARGIN + AUTO_MIN_COMPLETION_TOKENS
+    } else {
+        prompt_len + max_tokens
+    }
+}
+
 fn normalize_chat_template(src: &str) -> String {
     src.replace(
         "content.startswith('<tool_response>') and content.endswith('</tool_response>')",
@@ -169,10 +661,54 @@ impl ChatTemplate {
                 Some(e)
             }
         };
-        Ok(Self { env })
+        let wrappers = env.as_ref().and_then(TurnWrappers::derive);
+        let boundaries = env
+            .as_ref()
+            .and_then(TurnWrappers::probe)
+            .map(|probed| probed.boundaries())
+            .unwrap_or_default();
+        Ok(Self {
+            env,
+            wrappers,
+            boundaries,
+        })
+    }
+
+    /// Text the template puts at the start of a user turn and the end of an
+    /// assistant turn, e.g. `<|im_start|>user` and `<|im_end|>` for ChatML.
+    /// A reply that writes one has run on past its own turn. Empty when the
+    /// template has no recognizable turn markup.
+    pub fn turn_boundaries(&self) -> &[String] {
+        &self.boundaries
+    }
+
+    /// Whether [`render_turns`](Self::render_turns) can render new turns on
+    /// their own.
+    pub fn renders_turns_independently(&self) -> bool {
+        self.wrappers.is_some()
+    }
+
+    /// Renders `turns` as the template would after an earlier rendering of
+    /// the conversation before them, so `apply(history) + render_turns(new)`
+    /// equals `apply(history + new)`. `None` when the template does not
+    /// render turns independently, or for turns other than plain user and
+    /// assistant messages.
+    pub fn render_turns(&self, turns: &[Message], add_generation_prompt: bool) -> Option<String> {
+        self.wrappers.as_ref()?.render(turns, add_generation_prompt)
     }
 
     pub fn apply(&self, messages: &[Message], add_generation_prompt: bool) -> Result<String> {
+        self.apply_with_language(messages, add_generation_prompt, None)
+    }
+
+    /// Like [`apply`](Self::apply), additionally exposing `language` (an ISO
+    /// 639-1 code) and `language_name` (e.g. "French") to the template.
+    pub fn apply_with_language(
+        &self,
+        messages: &[Message],
+        add_generation_prompt: bool,
+        language: Option<&str>,
+    ) -> Result<String> {
         let env = match &self.env {
             Some(e) => e,
             None => {
@@ -180,47 +716,152 @@ impl ChatTemplate {
             }
         };
 
-        let tmpl = env.get_template("chat")?;
-        let rendered = tmpl.render(context! {
-            messages => messages,
-            add_generation_prompt => add_generation_prompt,
-            enable_thinking => false,
-            add_vision_id => false,
-        })?;
-        Ok(rendered)
+        render_chat(env, messages, add_generation_prompt, language)
+    }
+}
+
+fn sampling_for(temperature: f64, top_p: Option<f64>, top_k: Option<usize>) -> Sampling {
+    if temperature <= 0.0 {
+        Sampling::ArgMax
+    } else {
+        match (top_k, top_p) {
+            (None, None) => Sampling::All { temperature },
+            (Some(k), None) => Sampling::TopK { k, temperature },
+            (None, Some(p)) => Sampling::TopP { p, temperature },
+            (Some(k), Some(p)) => Sampling::TopKThenTopP { k, p, temperature },
+        }
     }
 }
 
+/// Stats for a KV cache sized to the model's full context.
+fn paged_kv_cache(metadata: &GgufMetadata) -> PagedKvCache {
+    let dim = metadata.n_embd / metadata.n_layer;
+    PagedKvCache::new(dim, dim, metadata.context_length)
+}
+
 pub struct Generator {
     model: Model,
     tokenizer: TokenizerWrapper,
-    logits_processor: LogitsProcessor,
+    sampler: TokenSampler,
+    sampler_state: SamplerState,
     template: ChatTemplate,
     metadata: GgufMetadata,
     messages: Vec<Message>,
     system_prompt: Option<String>,
+    /// Few-shot turns rendered after the system prompt. Pinned: history
+    /// truncation and `clear_history` never remove them.
+    examples: Vec<Message>,
+    /// Overrides detection when set (`--force-language`).
+    forced_language: Option<String>,
+    /// Language of the latest prompt, forced or detected.
+    language: Option<String>,
     token_history: Vec<u32>,
+    /// What `token_history` was rendered from; `None` once the history
+    /// changed in a way that needs a full rendering.
+    history_rendered: Option<RenderedHistory>,
+    /// Whether encoding new turns and appending them to `token_history`
+    /// gives the tokens a full encoding would.
+    incremental_history: bool,
+    /// Turn boundaries from the chat template that end a reply.
+    template_stops: TemplateStops,
+    /// Whether `template_stops` are checked.
+    stop_at_turn_boundaries: bool,
+    /// Caller-defined text that ends a reply, cut from it like the
+    /// template's stops.
+    stop_sequences: Vec<String>,
     /// Reusable token buffer for the current generation call. Allocated once
     /// with context_length capacity and cleared (not freed) between calls.
     all_tokens: Vec<u32>,
     kv_cache: Option<PagedKvCache>,
     batch_size: usize,
+    /// User transforms run after the repeat penalty at every decode step.
+    transforms: LogitsChain,
+    /// Logits of the current step, reused across steps.
+    scratch: StepScratch,
+    hooks: Vec<Box<dyn GenerationHooks>>,
+    /// Some hook wants every token's logprob.
+    hooks_want_logprobs: bool,
+    stop_conditions: StopConditions,
+    /// Redacts generated text before it reaches callbacks and the history.
+    redactor: Option<Redactor>,
+    /// Prompt tokens per forward pass in low-memory mode; `None` runs the
+    /// whole prompt at once.
+    prefill_chunk: Option<usize>,
+    /// Alternatives recorded per token when logprobs are enabled.
+    logprobs_top: Option<usize>,
+    logprobs: Vec<TokenLogprob>,
+    /// Uncertainty of the last reply, when enabled.
+    confidence: Option<ConfidenceTracker>,
+    /// Forward pass and sampling time of every decode step of the last reply.
+    token_latencies: Vec<std::time::Duration>,
+    /// Keep generating past `max_tokens` until a sentence ends.
+    finish_at_boundary: bool,
+    /// Longest prefill before the first token; longer prompts lose their
+    /// oldest tokens.
+    ttft_budget: Option<std::time::Duration>,
+    /// Prompt tokens per second of the last prefill.
+    prefill_rate: Option<f64>,
+    loop_guard: LoopGuard,
+    /// The loop guard ended the last reply.
+    loop_detected: bool,
+    /// The cancel token ended the last reply before it finished.
+    cancelled: bool,
+    /// The generator's own sampler while the loop guard's bumped one runs.
+    saved_sampler: Option<(TokenSampler, SamplerState)>,
+    context_policy: ContextPolicy,
+    /// Summary of turns dropped under `ContextPolicy::Summarize`, rendered
+    /// in the system message.
+    history_summary: Option<String>,
+    /// Messages removed from the front of the history to fit the context
+    /// window; keeps [`Generator::history_mark`] valid across drops.
+    dropped_messages: usize,
+    /// Document, or notes on it, from [`Generator::read_document`];
+    /// rendered in the system message.
+    document: Option<String>,
+    /// Checked before every decode step; generation stops early once set.
+    cancel: Option<CancelToken>,
+    load_report: LoadReport,
 }
 
 impl Generator {
+    /// Encodes a rendered chat prompt: BOS per the model's GGUF flag, and
+    /// template markers like `<|im_start|>` parsed as special tokens.
     fn encode_chat_text(&self, text: &str) -> Result<Vec<u32>> {
-        self.tokenizer.encode(text)
+        self.tokenizer.encode_with_options(text, true, true)
     }
 
-    fn conversation_messages(&self) -> Vec<Message> {
+    /// System prompt and few-shot examples, which precede every conversation.
+    /// `{language}` and `{language_name}` in the system prompt are replaced
+    /// with the prompt language, or "the user's language" when unknown.
+    fn pinned_messages(&self, language: Option<&str>) -> Vec<Message> {
         let mut messages =
-            Vec::with_capacity(self.messages.len() + usize::from(self.system_prompt.is_some()));
-        if let Some(ref sys) = self.system_prompt {
-            messages.push(Message {
-                role: "system".into(),
-                content: sys.clone(),
-            });
+            Vec::with_capacity(self.examples.len() + usize::from(self.system_prompt.is_some()));
+        let system = self.system_prompt.as_ref().map(|sys| {
+            let name = language
+                .and_then(language_name)
+                .unwrap_or("the user's language");
+            sys.replace("{language}", language.unwrap_or(name))
+                .replace("{language_name}", name)
+        });
+        let system = match (system, &self.document) {
+            (Some(sys), Some(doc)) => Some(format!("{}\n\n{}{}", sys, DOCUMENT_PREFIX, doc)),
+            (None, Some(doc)) => Some(format!("{}{}", DOCUMENT_PREFIX, doc)),
+            (sys, None) => sys,
+        };
+        let system = match (system, &self.history_summary) {
+            (Some(sys), Some(summary)) => Some(format!("{}\n\n{}{}", sys, SUMMARY_PREFIX, summary)),
+            (None, Some(summary)) => Some(format!("{}{}", SUMMARY_PREFIX, summary)),
+            (sys, None) => sys,
+        };
+        if let Some(sys) = system {
+            messages.push(Message::new("system", sys));
         }
+        messages.extend(self.examples.iter().cloned());
+        messages
+    }
+
+    fn conversation_messages(&self) -> Vec<Message> {
+        let mut messages = self.pinned_messages(self.language.as_deref());
         messages.extend(self.messages.iter().cloned());
         messages
     }
@@ -232,23 +873,99 @@ impl Generator {
             return Ok(());
         }
 
-        let rendered = self.template.apply(&messages, false)?;
+        let rendered =
+            self.template
+                .apply_with_language(&messages, false, self.language.as_deref())?;
         self.token_history = self.encode_chat_text(&rendered)?;
+        self.history_rendered = Some(RenderedHistory {
+            messages: self.messages.len(),
+            language: self.language.clone(),
+        });
         Ok(())
     }
 
-    fn drop_oldest_turn(&mut self) -> bool {
-        if self.messages.is_empty() {
-            return false;
+    /// `token_history` followed by the last `turns` messages, rendered and
+    /// encoded on their own. `None` when that would not match a full
+    /// rendering: the template or tokenizer does not allow it, or the
+    /// history or system prompt changed since `token_history` was built.
+    fn append_to_history(
+        &self,
+        turns: usize,
+        add_generation_prompt: bool,
+    ) -> Result<Option<Vec<u32>>> {
+        let current = RenderedHistory {
+            messages: self.messages.len() - turns,
+            language: self.language.clone(),
+        };
+        if !self.incremental_history
+            || self.token_history.is_empty()
+            || self.history_rendered.as_ref() != Some(&current)
+        {
+            return Ok(None);
+        }
+        let new = &self.messages[current.messages..];
+        let Some(text) = self.template.render_turns(new, add_generation_prompt) else {
+            return Ok(None);
+        };
+        let mut tokens = self.token_history.clone();
+        tokens.extend(self.tokenizer.encode_with_options(&text, false, true)?);
+        Ok(Some(tokens))
+    }
+
+    /// Brings `token_history` up to date after `turns` messages were
+    /// appended, rendering only those when possible.
+    fn extend_token_history(&mut self, turns: usize) -> Result<()> {
+        match self.append_to_history(turns, false)? {
+            Some(tokens) => {
+                self.token_history = tokens;
+                self.history_rendered = Some(RenderedHistory {
+                    messages: self.messages.len(),
+                    language: self.language.clone(),
+                });
+                Ok(())
+            }
+            None => self.rebuild_token_history(),
+        }
+    }
+
+    /// Removes the oldest user turn (and its reply) from the history and
+    /// returns it. The last message is the prompt being answered and is
+    /// never dropped.
+    fn drop_oldest_turn(&mut self) -> Option<Vec<Message>> {
+        if self.messages.len() <= 1 {
+            return None;
         }
 
-        self.messages.remove(0);
+        self.history_rendered = None;
+        let mut turn = vec![self.messages.remove(0)];
         if matches!(self.messages.first(), Some(message) if message.role == "assistant") {
-            self.messages.remove(0);
+            turn.push(self.messages.remove(0));
+        }
+        self.dropped_messages += turn.len();
+        Some(turn)
+    }
+
+    /// Has the model summarize `turns`, together with the summary of turns
+    /// dropped before them. Runs like a reply: it samples and reaches hooks.
+    fn summarize_turns(&mut self, turns: &[Message]) -> Result<String> {
+        let mut transcript = String::new();
+        if let Some(summary) = &self.history_summary {
+            transcript.push_str(&format!("(Earlier: {})\n", summary));
         }
-        true
+        for message in turns {
+            transcript.push_str(&format!("{}: {}\n", message.role, message.content));
+        }
+        let request = Message::new(
+            "user",
+            format!("{}\n\n{}", SUMMARY_INSTRUCTION, transcript.trim_end()),
+        );
+        let text = self.template.apply_with_language(&[request], true, None)?;
+        let tokens = self.encode_chat_text(&text)?;
+        let summary = self.run_generation(&tokens, SUMMARY_MAX_TOKENS, 1.1, 64, |_| {}, false)?;
+        Ok(summary.trim().to_string())
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         model_path: &PathBuf,
         tokenizer_path: Option<&PathBuf>,
@@ -265,49 +982,84 @@ impl Generator {
         Model::prefetch_mmap(&mmap);
 
         let metadata = model.metadata().clone();
+        let mut load_report = model.load_report().clone();
+        let started = std::time::Instant::now();
         let template = ChatTemplate::new(metadata.chat_template.clone())?;
+        load_report.template = started.elapsed();
 
+        let started = std::time::Instant::now();
         let tokenizer = if let Some(path) = tokenizer_path {
             TokenizerWrapper::from_file(path)?
         } else {
             tracing::info!("Loading tokenizer from GGUF...");
             TokenizerWrapper::from_gguf(model_path)?
         };
+        load_report.tokenizer = started.elapsed();
+        let incremental_history = appends_cleanly(&template, &tokenizer);
+        let template_stops = TemplateStops::new(
+            template.turn_boundaries(),
+            tokenizer.eos_token_id(),
+            |text| tokenizer.encode_with_options(text, false, true),
+            |token| tokenizer.is_special_token(token),
+            |tokens| tokenizer.decode(tokens),
+        )?;
+        if template_stops != TemplateStops::default() {
+            tracing::debug!("Stopping at template turn boundaries: {:?}", template_stops);
+        }
 
-        let sampling = if temperature <= 0.0 {
-            Sampling::ArgMax
-        } else {
-            match (top_k, top_p) {
-                (None, None) => Sampling::All { temperature },
-                (Some(k), None) => Sampling::TopK { k, temperature },
-                (None, Some(p)) => Sampling::TopP { p, temperature },
-                (Some(k), Some(p)) => Sampling::TopKThenTopP { k, p, temperature },
-            }
-        };
-
-        let logits_processor = LogitsProcessor::from_sampling(seed, sampling);
+        let sampler = TokenSampler::new(seed, sampling_for(temperature, top_p, top_k));
 
         let token_history = Vec::with_capacity(metadata.context_length);
         let all_tokens = Vec::with_capacity(metadata.context_length);
+        let scratch = StepScratch::new(metadata.vocab_size);
 
-        let kv_cache = Some(PagedKvCache::new(
-            metadata.n_embd / metadata.n_layer,
-            metadata.n_embd / metadata.n_layer,
-            metadata.context_length,
-        ));
+        let kv_cache = Some(paged_kv_cache(&metadata));
 
         Ok(Self {
             model,
             tokenizer,
-            logits_processor,
+            sampler,
+            sampler_state: SamplerState { seed, draws: 0 },
             template,
             metadata,
             messages: Vec::new(),
             system_prompt,
+            examples: Vec::new(),
+            forced_language: None,
+            language: None,
             token_history,
+            history_rendered: None,
+            incremental_history,
+            template_stops,
+            stop_at_turn_boundaries: true,
+            stop_sequences: Vec::new(),
             all_tokens,
             kv_cache,
             batch_size,
+            transforms: LogitsChain::default(),
+            scratch,
+            hooks: Vec::new(),
+            hooks_want_logprobs: false,
+            stop_conditions: StopConditions::default(),
+            redactor: None,
+            prefill_chunk: None,
+            logprobs_top: None,
+            logprobs: Vec::new(),
+            confidence: None,
+            token_latencies: Vec::new(),
+            finish_at_boundary: false,
+            ttft_budget: None,
+            prefill_rate: None,
+            loop_guard: LoopGuard::default(),
+            loop_detected: false,
+            cancelled: false,
+            saved_sampler: None,
+            context_policy: ContextPolicy::default(),
+            history_summary: None,
+            dropped_messages: 0,
+            document: None,
+            cancel: None,
+            load_report,
         })
     }
 
@@ -328,6 +1080,11 @@ impl Generator {
         &self.metadata
     }
 
+    /// Number of tokens `text` encodes to, without special tokens.
+    pub fn count_tokens(&self, text: &str) -> Result<usize> {
+        Ok(self.tokenizer.encode_raw(text)?.len())
+    }
+
     pub fn context_used(&self) -> usize {
         self.token_history.len()
     }
@@ -349,143 +1106,1101 @@ impl Generator {
         self.context_percentage() >= 80.0
     }
 
-    pub fn clear_history(&mut self) {
-        self.messages.clear();
-        self.token_history.clear();
-        self.clear_kv_cache();
+    /// Registers a transform to run on the logits before every sampled token.
+    pub fn add_logits_transform(&mut self, transform: Box<dyn LogitsTransform>) {
+        self.transforms.push(transform);
     }
 
-    pub fn warmup(&mut self, num_warmup_tokens: usize) -> Result<()> {
-        tracing::info!("Warming up model with {} tokens...", num_warmup_tokens);
-
-        let warmup_tokens = vec![0u32; num_warmup_tokens.min(512)];
+    pub fn clear_logits_transforms(&mut self) {
+        self.transforms.clear();
+    }
 
-        let batch_size = self.batch_size;
-        for i in (0..warmup_tokens.len()).step_by(batch_size) {
-            let end = (i + batch_size).min(warmup_tokens.len());
-            let batch = &warmup_tokens[i..end];
-            let _ = self.model.forward(batch, i)?;
-        }
+    /// Stops following generations early once `token` is cancelled. The
+    /// reply generated so far is returned and kept in the history. `None`
+    /// removes the token.
+    pub fn set_cancel_token(&mut self, token: Option<CancelToken>) {
+        self.cancel = token;
+    }
 
-        tracing::info!("Model warmup complete");
-        Ok(())
+    /// Records the log probability of every generated token, with up to
+    /// `top` alternatives each. `None` turns recording off.
+    pub fn set_logprobs(&mut self, top: Option<usize>) {
+        self.logprobs_top = top;
+        self.logprobs.clear();
     }
 
-    pub fn batch_size(&self) -> usize {
-        self.batch_size
+    /// Logprobs of the tokens generated by the last call, when enabled.
+    pub fn logprobs(&self) -> &[TokenLogprob] {
+        &self.logprobs
     }
 
-    /// Appends the user message to history, builds the full chat prompt, encodes
-    /// it, and trims the token history if needed to fit within the context window.
-    /// Returns the encoded prompt tokens ready for generation.
-    fn prepare_prompt(&mut self, prompt: &str, max_tokens: usize) -> Result<Vec<u32>> {
-        self.messages.push(Message {
-            role: "user".into(),
-            content: prompt.into(),
-        });
+    /// Measures the entropy and top-1 margin of every decode step. `false`
+    /// turns measuring off.
+    pub fn set_confidence(&mut self, enabled: bool) {
+        self.confidence = enabled.then(ConfidenceTracker::default);
+    }
 
-        loop {
-            let owned = self.conversation_messages();
-            let prompt_text = self.template.apply(&owned, true)?;
-            let prompt_tokens = self.encode_chat_text(&prompt_text)?;
+    /// Lets replies that reach `max_tokens` mid-sentence run up to
+    /// [`BOUNDARY_GRACE_TOKENS`] more tokens, stopping at the first sentence
+    /// or line end, so they are not cut off mid-thought.
+    pub fn set_finish_at_boundary(&mut self, enabled: bool) {
+        self.finish_at_boundary = enabled;
+    }
 
-            let total_len = prompt_tokens.len() + max_tokens;
-            if total_len <= self.metadata.context_length {
-                return Ok(prompt_tokens);
-            }
+    /// Bounds the time to the first token. A prompt that the last measured
+    /// prefill speed says would take longer than `budget` keeps its first
+    /// token and as many of its last tokens as fit; the oldest context in
+    /// between is never seen by the model. The first generation has no
+    /// measurement yet and is never cut. `None` turns the budget off.
+    pub fn set_ttft_budget(&mut self, budget: Option<std::time::Duration>) {
+        self.ttft_budget = budget;
+    }
 
-            if !self.drop_oldest_turn() {
-                anyhow::bail!(
-                    "Prompt is too large for the model context window ({} > {}).",
-                    total_len,
-                    self.metadata.context_length
-                );
-            }
+    /// `prompt_tokens` cut down to what the first-token budget allows, or
+    /// `None` when it fits as it is.
+    fn fit_ttft_budget(&self, prompt_tokens: &[u32]) -> Option<Vec<u32>> {
+        let (budget, rate) = (self.ttft_budget?, self.prefill_rate?);
+        let cap = ((rate * budget.as_secs_f64()) as usize).max(TTFT_MIN_PROMPT_TOKENS);
+        if prompt_tokens.len() <= cap {
+            return None;
         }
+        tracing::info!(
+            "Prefilling {} of {} prompt tokens to stay within the {:?} first-token budget",
+            cap,
+            prompt_tokens.len(),
+            budget
+        );
+        let mut kept = Vec::with_capacity(cap);
+        kept.push(prompt_tokens[0]);
+        kept.extend_from_slice(&prompt_tokens[prompt_tokens.len() - (cap - 1)..]);
+        Some(kept)
     }
 
-    pub fn generate<F>(
-        &mut self,
-        prompt: &str,
-        max_tokens: usize,
-        repeat_penalty: f32,
-        repeat_last_n: usize,
-        callback: F,
-    ) -> Result<String>
-    where
-        F: FnMut(StreamEvent),
-    {
-        let prompt_tokens = self.prepare_prompt(prompt, max_tokens)?;
+    /// Sets how replies stuck repeating a short cycle of tokens are caught.
+    /// On by default, ending the reply.
+    pub fn set_loop_guard(&mut self, guard: LoopGuard) {
+        self.loop_guard = guard;
+    }
 
-        let result = self.generate_internal_with_tokens(
-            &prompt_tokens,
-            max_tokens,
-            repeat_penalty,
-            repeat_last_n,
-            callback,
-            false,
-        )?;
+    /// Whether the loop guard ended the last reply.
+    pub fn loop_detected(&self) -> bool {
+        self.loop_detected
+    }
 
-        self.messages.push(Message {
-            role: "assistant".into(),
-            content: result.clone(),
-        });
-        self.rebuild_token_history()?;
+    /// Whether the cancel token ended the last reply before it finished.
+    pub fn cancelled(&self) -> bool {
+        self.cancelled
+    }
 
-        Ok(result)
+    /// Ends replies that start a new user turn or close their own turn in
+    /// the chat template's markup, instead of letting the model write the
+    /// user's next message. On by default; `false` stops only at EOS and the
+    /// built-in stop sequences.
+    pub fn set_stop_at_turn_boundaries(&mut self, enabled: bool) {
+        self.stop_at_turn_boundaries = enabled;
     }
 
-    pub fn generate_streaming<F>(
-        &mut self,
-        prompt: &str,
-        max_tokens: usize,
-        repeat_penalty: f32,
-        repeat_last_n: usize,
-        callback: F,
-    ) -> Result<()>
-    where
-        F: FnMut(StreamEvent),
-    {
-        let prompt_tokens = self.prepare_prompt(prompt, max_tokens)?;
+    /// Ends replies at the first occurrence of any of `stops`, which is
+    /// left out of the reply. Empty strings are ignored.
+    pub fn set_stop_sequences(&mut self, stops: Vec<String>) {
+        self.stop_sequences = stops.into_iter().filter(|s| !s.is_empty()).collect();
+    }
 
-        let result = self.generate_internal_with_tokens(
-            &prompt_tokens,
-            max_tokens,
-            repeat_penalty,
-            repeat_last_n,
-            callback,
-            true,
-        )?;
+    /// What to do when a prompt does not fit the context window. With
+    /// [`ContextPolicy::Error`], `generate` and `generate_streaming` fail
+    /// with a [`ContextOverflow`] and the history is left unchanged.
+    pub fn set_context_policy(&mut self, policy: ContextPolicy) {
+        self.context_policy = policy;
+    }
 
-        self.messages.push(Message {
-            role: "assistant".into(),
-            content: result,
-        });
-        self.rebuild_token_history()?;
+    pub fn context_policy(&self) -> ContextPolicy {
+        self.context_policy
+    }
 
-        Ok(())
+    /// Uncertainty of the reply generated by the last call, when enabled.
+    pub fn confidence(&self) -> Option<Confidence> {
+        self.confidence.as_ref()?.summary()
     }
 
-    fn generate_internal_with_tokens<F>(
+    /// Time each decode step of the last reply took, from its forward pass
+    /// to the sampled token. The first token comes out of prefill and is
+    /// not included.
+    pub fn token_latencies(&self) -> &[std::time::Duration] {
+        &self.token_latencies
+    }
+
+    /// Percentiles of [`token_latencies`](Self::token_latencies), or `None`
+    /// when the last reply had a single token.
+    pub fn latency(&self) -> Option<LatencySummary> {
+        LatencySummary::from_samples(&self.token_latencies)
+    }
+
+    /// Fails with [`NonFiniteLogits`] when `logits`, for the token at
+    /// `position`, hold NaN or infinite values. Finding the layer re-runs the
+    /// sequence, which is only worth it once generation has failed anyway.
+    fn check_logits(
         &mut self,
-        prompt_tokens: &[u32],
-        max_tokens: usize,
-        repeat_penalty: f32,
-        repeat_last_n: usize,
-        mut callback: F,
-        _streaming: bool,
-    ) -> Result<String>
-    where
-        F: FnMut(StreamEvent),
-    {
-        let total_len = prompt_tokens.len() + max_tokens;
-        if total_len > self.metadata.context_length {
-            anyhow::bail!(
-                "Prompt is too large for the model context window ({} > {}).",
-                total_len,
-                self.metadata.context_length
+        logits: &candle_core::Tensor,
+        step: usize,
+        position: usize,
+    ) -> Result<()> {
+        if !numerics::has_non_finite(logits)? {
+            return Ok(());
+        }
+        let (nan, inf) = NonFiniteLogits::count(logits)?;
+        let layer = if self.model.reports_layers() {
+            self.model
+                .forward_debug(&self.all_tokens[..=position])
+                .ok()
+                .and_then(|report| {
+                    report
+                        .layers
+                        .iter()
+                        .find(|stats| stats.non_finite > 0)
+                        .map(|stats| stats.name.clone())
+                })
+        } else {
+            None
+        };
+        Err(NonFiniteLogits {
+            step,
+            position,
+            nan,
+            inf,
+            trained_context: self.metadata.declared_context_length,
+            layer,
+            quantization: self.metadata.quantization.clone(),
+        }
+        .into())
+    }
+
+    /// Records logprobs and confidence for a sampled token, when enabled.
+    fn record_token_stats(&mut self, token: u32) -> Result<()> {
+        let logits = self.scratch.logits();
+        if let Some(tracker) = self.confidence.as_mut() {
+            tracker.record(logits);
+        }
+        if self.logprobs_top.is_none() && !self.hooks_want_logprobs {
+            return Ok(());
+        }
+        let (logprob, top) = logprobs_for(logits, token, self.logprobs_top.unwrap_or(0));
+        self.notify(|hooks| hooks.on_token_logprob(token, logprob));
+        if self.logprobs_top.is_none() {
+            return Ok(());
+        }
+        let decode = |id: u32| self.tokenizer.decode(&[id]).unwrap_or_default();
+        let entry = TokenLogprob {
+            token_id: token,
+            token: decode(token),
+            logprob,
+            top: top
+                .into_iter()
+                .map(|(token_id, logprob)| TopLogprob {
+                    token_id,
+                    token: decode(token_id),
+                    logprob,
+                })
+                .collect(),
+        };
+        self.logprobs.push(entry);
+        Ok(())
+    }
+
+    /// Low-memory mode for swap-constrained devices: prefill runs in small
+    /// chunks so attention scratch stays bounded, and buffers sized for the
+    /// full context are released. Generation gets slower, especially for
+    /// architectures that must then prefill one token at a time. Disabling
+    /// it restores whole-prompt prefill and the full-context buffers.
+    pub fn set_low_mem(&mut self, enabled: bool) {
+        if enabled {
+            self.prefill_chunk = Some(LOW_MEM_PREFILL_CHUNK);
+            self.kv_cache = None;
+            self.all_tokens.shrink_to_fit();
+            self.token_history.shrink_to_fit();
+        } else {
+            self.prefill_chunk = None;
+            if self.kv_cache.is_none() {
+                self.kv_cache = Some(paged_kv_cache(&self.metadata));
+            }
+            let context = self.metadata.context_length;
+            self.all_tokens.reserve(context.saturating_sub(self.all_tokens.len()));
+            self.token_history.reserve(context.saturating_sub(self.token_history.len()));
+        }
+    }
+
+    /// Registers lifecycle hooks fired by every subsequent generation.
+    pub fn add_hooks(&mut self, hooks: Box<dyn GenerationHooks>) {
+        self.hooks_want_logprobs |= hooks.wants_logprobs();
+        self.hooks.push(hooks);
+    }
+
+    /// Registers a condition checked before every decode step with the
+    /// reply so far; generation stops once it returns `true`. The reply
+    /// generated so far is returned and kept in the history.
+    pub fn add_stop_condition<F>(&mut self, condition: F)
+    where
+        F: FnMut(&StopContext) -> bool + Send + 'static,
+    {
+        self.stop_conditions.push(Box::new(condition));
+    }
+
+    pub fn clear_stop_conditions(&mut self) {
+        self.stop_conditions.clear();
+    }
+
+    /// Enables redaction of generated text with `config`, or disables it.
+    pub fn set_redaction(&mut self, config: Option<&RedactionConfig>) -> Result<()> {
+        self.redactor = config.map(Redactor::new).transpose()?;
+        Ok(())
+    }
+
+    fn redact_push(&mut self, text: String) -> String {
+        match self.redactor.as_mut() {
+            Some(redactor) => redactor.push(&text),
+            None => text,
+        }
+    }
+
+    fn redact_finish(&mut self) -> String {
+        self.redactor
+            .as_mut()
+            .map(Redactor::finish)
+            .unwrap_or_default()
+    }
+
+    fn notify(&mut self, mut f: impl FnMut(&mut dyn GenerationHooks)) {
+        for hooks in &mut self.hooks {
+            f(hooks.as_mut());
+        }
+    }
+
+    /// Forces the `language` template variable instead of detecting it from
+    /// each prompt. `None` restores detection.
+    pub fn set_forced_language(&mut self, language: Option<String>) {
+        self.forced_language = language;
+    }
+
+    /// Language of the latest prompt as an ISO 639-1 code: the forced
+    /// language if set, otherwise the detected one.
+    pub fn language(&self) -> Option<&str> {
+        self.language.as_deref()
+    }
+
+    fn resolve_language(&self, prompt: &str) -> Option<String> {
+        self.forced_language
+            .clone()
+            .or_else(|| detect_language(prompt).map(String::from))
+    }
+
+    /// Conversation history, excluding the system prompt and examples.
+    pub fn history(&self) -> &[Message] {
+        &self.messages
+    }
+
+    /// Replaces the conversation history, e.g. when resuming a saved session.
+    pub fn set_history(&mut self, messages: Vec<Message>) -> Result<()> {
+        self.clear_kv_cache();
+        self.messages = messages;
+        self.history_summary = None;
+        self.rebuild_token_history()
+    }
+
+    pub fn system_prompt(&self) -> Option<&str> {
+        self.system_prompt.as_deref()
+    }
+
+    pub fn set_system_prompt(&mut self, system_prompt: Option<String>) -> Result<()> {
+        self.clear_kv_cache();
+        self.system_prompt = system_prompt;
+        self.rebuild_token_history()
+    }
+
+    pub fn document(&self) -> Option<&str> {
+        self.document.as_deref()
+    }
+
+    /// Pins `document` ahead of the conversation as it is, or unpins it.
+    /// [`read_document`](Self::read_document) also handles documents longer
+    /// than the context.
+    pub fn set_document(&mut self, document: Option<String>) -> Result<()> {
+        self.clear_kv_cache();
+        self.document = document;
+        self.rebuild_token_history()
+    }
+
+    /// Pins `text` ahead of the conversation, replacing any earlier
+    /// document. Text that fits `options.budget_tokens` is pinned as it is;
+    /// longer text is read segment by segment into notes (see
+    /// [`document`](crate::inference::document)). Notes are taken greedily
+    /// and the conversation history is kept.
+    pub fn read_document<F>(
+        &mut self,
+        text: &str,
+        options: &DocumentOptions,
+        mut progress: F,
+    ) -> Result<DocumentDigest>
+    where
+        F: FnMut(DocumentEvent),
+    {
+        let text = text.trim();
+        if text.is_empty() {
+            anyhow::bail!("The document is empty");
+        }
+        let context_length = self.metadata.context_length;
+        let budget = match options.budget_tokens {
+            0 => context_length / 2,
+            n => n.min(context_length),
+        };
+        let tokens = self.count_tokens(text)?;
+        if tokens <= budget {
+            self.set_document(Some(text.to_string()))?;
+            return Ok(DocumentDigest {
+                segments: 1,
+                sections: Vec::new(),
+                tokens,
+            });
+        }
+
+        let notes_tokens = match options.notes_tokens {
+            0 => (budget / 4).max(64),
+            n => n.min(budget),
+        };
+        // Room for the notes read in and written out around each segment.
+        let segment_tokens =
+            context_length.saturating_sub(2 * notes_tokens + document::PROMPT_OVERHEAD_TOKENS);
+        if segment_tokens < document::MIN_SEGMENT_TOKENS {
+            anyhow::bail!(
+                "Context window ({} tokens) leaves no room for document segments next to {} tokens of notes",
+                context_length,
+                notes_tokens
             );
         }
+        let segments = split_chunks(text, segment_tokens, |s| self.count_tokens(s))?;
+        let total = segments.len();
+        progress(DocumentEvent::Segmented { segments: total });
+
+        let question = options.question.as_deref();
+        let mut sections = Vec::new();
+        let mut current = DocumentSection {
+            first: 1,
+            last: 0,
+            notes: String::new(),
+            tokens: 0,
+        };
+        for (index, segment) in segments.iter().enumerate() {
+            progress(DocumentEvent::SegmentStarted { index, total });
+            let prompt =
+                document::notes_prompt(&current.notes, segment, index + 1, total, question);
+            current.notes = self.take_notes(&prompt, notes_tokens, options)?;
+            current.tokens = self.count_tokens(&current.notes)?;
+            current.last = index + 1;
+            // Notes near their limit would start losing details; later
+            // segments start a section of their own.
+            if current.tokens >= notes_tokens * 3 / 4 && index + 1 < total {
+                let next = DocumentSection {
+                    first: index + 2,
+                    last: index + 1,
+                    notes: String::new(),
+                    tokens: 0,
+                };
+                sections.push(std::mem::replace(&mut current, next));
+                // Leave room for the section still being written.
+                let room = budget.saturating_sub(notes_tokens);
+                self.condense_sections(&mut sections, total, room, options, &mut progress)?;
+            }
+        }
+        sections.push(current);
+        self.condense_sections(&mut sections, total, budget, options, &mut progress)?;
+
+        let pinned = document::render_sections(&sections, total);
+        let tokens = self.count_tokens(&pinned)?;
+        self.set_document(Some(pinned))?;
+        Ok(DocumentDigest {
+            segments: total,
+            sections,
+            tokens,
+        })
+    }
+
+    /// Condenses neighbouring sections, smallest pair first, until their
+    /// rendering fits `budget` tokens or one section is left.
+    fn condense_sections<F>(
+        &mut self,
+        sections: &mut Vec<DocumentSection>,
+        segments: usize,
+        budget: usize,
+        options: &DocumentOptions,
+        progress: &mut F,
+    ) -> Result<()>
+    where
+        F: FnMut(DocumentEvent),
+    {
+        while self.count_tokens(&document::render_sections(sections, segments))? > budget {
+            let Some(i) = document::smallest_pair(sections) else {
+                break;
+            };
+            let question = options.question.as_deref();
+            let prompt = document::condense_prompt(&sections[i], &sections[i + 1], question);
+            let notes_tokens = sections[i].tokens.max(sections[i + 1].tokens);
+            let notes = self.take_notes(&prompt, notes_tokens.max(64), options)?;
+            let merged = DocumentSection {
+                first: sections[i].first,
+                last: sections[i + 1].last,
+                tokens: self.count_tokens(&notes)?,
+                notes,
+            };
+            progress(DocumentEvent::Condensed {
+                first: merged.first,
+                last: merged.last,
+            });
+            sections.splice(i..i + 2, [merged]);
+        }
+        Ok(())
+    }
+
+    /// Answers `prompt` greedily on its own, outside the conversation.
+    fn take_notes(
+        &mut self,
+        prompt: &str,
+        max_tokens: usize,
+        options: &DocumentOptions,
+    ) -> Result<String> {
+        let request = Message::new("user", prompt);
+        let text = self.template.apply_with_language(&[request], true, None)?;
+        let tokens = self.encode_chat_text(&text)?;
+        let seed = self.sampler_state.seed;
+        let notes = self.with_sampling(0.0, None, None, seed, |generator| {
+            generator.run_generation(
+                &tokens,
+                max_tokens,
+                options.repeat_penalty,
+                options.repeat_last_n,
+                |_| {},
+                false,
+            )
+        })?;
+        Ok(notes.trim().to_string())
+    }
+
+    /// Replaces the sampler and reseeds it. Takes the same settings as
+    /// [`Generator::new`].
+    pub fn set_sampling(
+        &mut self,
+        temperature: f64,
+        top_p: Option<f64>,
+        top_k: Option<usize>,
+        seed: u64,
+    ) {
+        self.sampler = TokenSampler::new(seed, sampling_for(temperature, top_p, top_k));
+        self.sampler_state = SamplerState { seed, draws: 0 };
+    }
+
+    /// Runs `f` with a sampler for these settings, freshly seeded, then
+    /// puts the generator's own sampler back where it was.
+    pub fn with_sampling<T>(
+        &mut self,
+        temperature: f64,
+        top_p: Option<f64>,
+        top_k: Option<usize>,
+        seed: u64,
+        f: impl FnOnce(&mut Self) -> T,
+    ) -> T {
+        let sampler = std::mem::replace(
+            &mut self.sampler,
+            TokenSampler::new(seed, sampling_for(temperature, top_p, top_k)),
+        );
+        let state = std::mem::replace(&mut self.sampler_state, SamplerState { seed, draws: 0 });
+        let result = f(self);
+        self.sampler = sampler;
+        self.sampler_state = state;
+        result
+    }
+
+    /// Where the sampler's random stream is. Save it with the history to
+    /// resume a long generation or batch job exactly as it would have gone.
+    pub fn sampler_state(&self) -> SamplerState {
+        self.sampler_state
+    }
+
+    /// Moves the sampler to a state from [`sampler_state`](Self::sampler_state),
+    /// keeping the current sampling settings. Costs a few microseconds per
+    /// draw being replayed.
+    pub fn restore_sampler_state(&mut self, state: SamplerState) -> Result<()> {
+        self.sampler = TokenSampler::at(state, self.sampler.sampling().clone())?;
+        self.sampler_state = state;
+        Ok(())
+    }
+
+    /// Samples from the logits in `scratch`.
+    fn sample(&mut self) -> Result<u32> {
+        let token = self.sampler.sample(self.scratch.logits())?;
+        if *self.sampler.sampling() != Sampling::ArgMax {
+            self.sampler_state.draws += 1;
+        }
+        Ok(token)
+    }
+
+    /// Appends a message to the history without generating a reply, e.g. a
+    /// turn from another agent or a named participant.
+    pub fn push_message(&mut self, message: Message) -> Result<()> {
+        self.messages.push(message);
+        self.extend_token_history(1)
+    }
+
+    /// Sets the few-shot `(user, assistant)` turns placed ahead of the
+    /// conversation. They are never dropped when the context fills up.
+    pub fn set_examples(&mut self, examples: Vec<(String, String)>) -> Result<()> {
+        self.examples = examples
+            .into_iter()
+            .flat_map(|(user, assistant)| {
+                [
+                    Message::new("user", user),
+                    Message::new("assistant", assistant),
+                ]
+            })
+            .collect();
+        self.rebuild_token_history()
+    }
+
+    pub fn clear_history(&mut self) {
+        self.messages.clear();
+        self.history_summary = None;
+        self.token_history.clear();
+        self.clear_kv_cache();
+    }
+
+    /// How long each phase of loading took, including the last warmup.
+    pub fn load_report(&self) -> &LoadReport {
+        &self.load_report
+    }
+
+    pub fn warmup(&mut self, num_warmup_tokens: usize) -> Result<()> {
+        tracing::info!("Warming up model with {} tokens...", num_warmup_tokens);
+        let started = std::time::Instant::now();
+        self.warmup_forward(&vec![0u32; num_warmup_tokens.min(512)])?;
+        self.load_report.warmup = Some(started.elapsed());
+
+        tracing::info!("Model warmup complete");
+        Ok(())
+    }
+
+    /// Warms the model up as `policy` says and clears the KV cache it
+    /// filled. The time taken is in [`load_report`](Self::load_report).
+    pub fn warmup_with(&mut self, policy: WarmupPolicy) -> Result<()> {
+        let tokens = match policy {
+            WarmupPolicy::None => return Ok(()),
+            WarmupPolicy::Minimal => vec![0],
+            WarmupPolicy::Full => {
+                let batch = self.batch_size.clamp(1, 512);
+                // The extra token takes the decode path after the batch.
+                vec![0; (batch + 1).min(self.metadata.context_length.max(1))]
+            }
+            WarmupPolicy::SystemPrompt => {
+                let messages = self.pinned_messages(self.language.as_deref());
+                if messages.is_empty() {
+                    vec![0]
+                } else {
+                    let rendered = self.template.apply_with_language(
+                        &messages,
+                        false,
+                        self.language.as_deref(),
+                    )?;
+                    let mut tokens = self.encode_chat_text(&rendered)?;
+                    tokens.truncate(self.metadata.context_length);
+                    tokens
+                }
+            }
+        };
+        tracing::info!("Warming up model ({}, {} tokens)...", policy, tokens.len());
+        let started = std::time::Instant::now();
+        let result = self.warmup_forward(&tokens);
+        self.clear_kv_cache();
+        result?;
+        self.load_report.warmup = Some(started.elapsed());
+        tracing::info!("Model warmup complete");
+        Ok(())
+    }
+
+    /// Runs `tokens` through the model in `batch_size` batches.
+    fn warmup_forward(&mut self, tokens: &[u32]) -> Result<()> {
+        let batch_size = self.batch_size.max(1);
+        for i in (0..tokens.len()).step_by(batch_size) {
+            let end = (i + batch_size).min(tokens.len());
+            let _ = self.model.forward(&tokens[i..end], i)?;
+        }
+        Ok(())
+    }
+
+    /// The prompt the template renders for the conversation so far, or, with
+    /// `next`, for answering `next` as the following user turn. Nothing is
+    /// added to the history.
+    pub fn preview(&self, next: Option<&str>) -> Result<PromptPreview> {
+        let (messages, language) = match next {
+            Some(prompt) => {
+                let language = self.resolve_language(prompt);
+                let mut messages = self.pinned_messages(language.as_deref());
+                messages.extend(self.messages.iter().cloned());
+                messages.push(Message::new("user", prompt));
+                (messages, language)
+            }
+            None => (self.conversation_messages(), self.language.clone()),
+        };
+        let text = self
+            .template
+            .apply_with_language(&messages, next.is_some(), language.as_deref())?;
+        let tokens = self.encode_chat_text(&text)?.len();
+        Ok(PromptPreview { text, tokens })
+    }
+
+    /// Content of the most recent assistant reply, if any.
+    pub(crate) fn last_response(&self) -> Option<String> {
+        self.messages
+            .last()
+            .filter(|message| message.role == "assistant")
+            .map(|message| message.content.clone())
+    }
+
+    /// Replaces the last `turns` user/assistant exchanges with a single
+    /// exchange.
+    pub(crate) fn collapse_last_turns(
+        &mut self,
+        turns: usize,
+        prompt: &str,
+        response: &str,
+    ) -> Result<()> {
+        let keep = self.messages.len().saturating_sub(turns * 2);
+        self.collapse_to(keep, prompt, response)
+    }
+
+    /// Position of the end of the history, for
+    /// [`Generator::collapse_history_since`]. Stays valid when older turns
+    /// are dropped to fit the context window.
+    pub(crate) fn history_mark(&self) -> usize {
+        self.dropped_messages + self.messages.len()
+    }
+
+    /// Replaces everything added to the history since `mark` with a single
+    /// exchange, so retried attempts do not linger in the conversation.
+    pub(crate) fn collapse_history_since(
+        &mut self,
+        mark: usize,
+        prompt: &str,
+        response: &str,
+    ) -> Result<()> {
+        let keep = mark
+            .saturating_sub(self.dropped_messages)
+            .min(self.messages.len());
+        self.collapse_to(keep, prompt, response)
+    }
+
+    fn collapse_to(&mut self, keep: usize, prompt: &str, response: &str) -> Result<()> {
+        self.messages.truncate(keep);
+        self.messages.push(Message::new("user", prompt));
+        self.messages.push(Message::new("assistant", response));
+        self.rebuild_token_history()
+    }
+
+    pub fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    /// Appends the user message to history, builds the full chat prompt, encodes
+    /// it, and trims the token history if needed to fit within the context window.
+    /// Returns the encoded prompt tokens ready for generation together with the
+    /// resolved completion budget and the number of history tokens dropped.
+    fn prepare_prompt(&mut self, prompt: &str, max_tokens: usize) -> Result<PreparedPrompt> {
+        self.messages.push(Message::new("user", prompt));
+        self.language = self.resolve_language(prompt);
+
+        let mut full_len = None;
+        let mut dropped = Vec::new();
+        let mut strategy = TruncationStrategy::DropOldestTurns;
+        // Once turns are dropped or summarized the history has to be
+        // rendered in full.
+        let mut appended = self.append_to_history(1, true)?;
+        loop {
+            let prompt_tokens = match appended.take() {
+                Some(tokens) => tokens,
+                None => {
+                    let owned = self.conversation_messages();
+                    let prompt_text = self.template.apply_with_language(
+                        &owned,
+                        true,
+                        self.language.as_deref(),
+                    )?;
+                    self.encode_chat_text(&prompt_text)?
+                }
+            };
+            let full_len = *full_len.get_or_insert(prompt_tokens.len());
+
+            let context_length = self.metadata.context_length;
+            if let Some(budget) = completion_budget(context_length, prompt_tokens.len(), max_tokens)
+            {
+                if self.context_policy == ContextPolicy::Summarize && !dropped.is_empty() {
+                    let turns = std::mem::take(&mut dropped);
+                    match self.summarize_turns(&turns) {
+                        Ok(summary) => {
+                            // The summary takes room too; check the fit again.
+                            self.history_summary = Some(summary);
+                            strategy = TruncationStrategy::Summarized;
+                            continue;
+                        }
+                        Err(e) => tracing::warn!(
+                            "Could not summarize dropped turns, dropping them instead: {:#}",
+                            e
+                        ),
+                    }
+                }
+                let dropped_tokens = full_len.saturating_sub(prompt_tokens.len());
+                if dropped_tokens > 0 {
+                    tracing::warn!(
+                        "Dropped {} tokens of conversation history to fit the context window",
+                        dropped_tokens
+                    );
+                }
+                return Ok(PreparedPrompt {
+                    tokens: prompt_tokens,
+                    max_tokens: budget,
+                    dropped_tokens,
+                    strategy,
+                });
+            }
+
+            let overflow = ContextOverflow {
+                needed: required_context(prompt_tokens.len(), max_tokens),
+                available: context_length,
+            };
+            if self.context_policy == ContextPolicy::Error {
+                self.messages.pop();
+                return Err(overflow.into());
+            }
+            match self.drop_oldest_turn() {
+                Some(turn) => dropped.extend(turn),
+                // Nothing left to drop but the summary itself.
+                None if self.history_summary.take().is_some() => {}
+                None => {
+                    // Leave the history as it was before this call.
+                    self.messages.pop();
+                    return Err(overflow.into());
+                }
+            }
+        }
+    }
+
+    pub fn generate<F>(
+        &mut self,
+        prompt: &str,
+        max_tokens: usize,
+        repeat_penalty: f32,
+        repeat_last_n: usize,
+        mut callback: F,
+    ) -> Result<String>
+    where
+        F: FnMut(StreamEvent),
+    {
+        let prepared = self.prepare_prompt(prompt, max_tokens)?;
+        if let Some(event) = prepared.truncation_event() {
+            callback(event);
+        }
+
+        let result = self.generate_internal_with_tokens(
+            &prepared.tokens,
+            prepared.max_tokens,
+            repeat_penalty,
+            repeat_last_n,
+            &mut callback,
+            false,
+        );
+        let result = self.keep_prompt_on_success(result)?;
+
+        self.messages
+            .push(Message::new("assistant", result.clone()));
+        self.extend_token_history(2)?;
+
+        Ok(result)
+    }
+
+    pub fn generate_streaming<F>(
+        &mut self,
+        prompt: &str,
+        max_tokens: usize,
+        repeat_penalty: f32,
+        repeat_last_n: usize,
+        mut callback: F,
+    ) -> Result<()>
+    where
+        F: FnMut(StreamEvent),
+    {
+        let prepared = self.prepare_prompt(prompt, max_tokens)?;
+        if let Some(event) = prepared.truncation_event() {
+            callback(event);
+        }
+
+        let result = self.generate_internal_with_tokens(
+            &prepared.tokens,
+            prepared.max_tokens,
+            repeat_penalty,
+            repeat_last_n,
+            &mut callback,
+            true,
+        );
+        let result = self.keep_prompt_on_success(result)?;
+
+        self.messages.push(Message::new("assistant", result));
+        self.extend_token_history(2)?;
+
+        Ok(())
+    }
+
+    /// Continues the reply at the end of the history, e.g. one cut short by
+    /// a stop condition or cancel token, and returns the text added to it.
+    /// The conversation is prefilled again with the partial reply as the
+    /// start of the assistant turn. Fails when the history does not end
+    /// with a reply; on failure the history is left as it was.
+    pub fn continue_reply<F>(
+        &mut self,
+        max_tokens: usize,
+        repeat_penalty: f32,
+        repeat_last_n: usize,
+        mut callback: F,
+    ) -> Result<String>
+    where
+        F: FnMut(StreamEvent),
+    {
+        let partial = match self.messages.last() {
+            Some(message) if message.role == "assistant" => self.messages.pop().unwrap(),
+            _ => anyhow::bail!("The history does not end with a reply to continue"),
+        };
+        self.language = self
+            .messages
+            .iter()
+            .rev()
+            .find(|message| message.role == "user")
+            .and_then(|message| self.resolve_language(&message.content));
+
+        let result = self.continuation_tokens(&partial.content, max_tokens);
+        let result = result.and_then(|(tokens, budget)| {
+            self.generate_internal_with_tokens(
+                &tokens,
+                budget,
+                repeat_penalty,
+                repeat_last_n,
+                &mut callback,
+                false,
+            )
+        });
+        let text = match result {
+            Ok(text) => text,
+            Err(e) => {
+                self.messages.push(partial);
+                return Err(e);
+            }
+        };
+
+        let mut reply = partial;
+        reply.content.push_str(&text);
+        self.messages.push(reply);
+        // The reply changed in place, so the rendered history is stale.
+        self.history_rendered = None;
+        self.rebuild_token_history()?;
+        Ok(text)
+    }
+
+    /// The conversation rendered up to the assistant turn, followed by
+    /// `partial`, and the completion budget left after it.
+    fn continuation_tokens(&self, partial: &str, max_tokens: usize) -> Result<(Vec<u32>, usize)> {
+        let owned = self.conversation_messages();
+        let mut text = self
+            .template
+            .apply_with_language(&owned, true, self.language.as_deref())?;
+        text.push_str(partial);
+        let tokens = self.encode_chat_text(&text)?;
+        let context_length = self.metadata.context_length;
+        match completion_budget(context_length, tokens.len(), max_tokens) {
+            Some(budget) => Ok((tokens, budget)),
+            None => Err(ContextOverflow {
+                needed: required_context(tokens.len(), max_tokens),
+                available: context_length,
+            }
+            .into()),
+        }
+    }
+
+    /// The `top_n` most likely next tokens, best first, without generating
+    /// or changing the history or the sampler. When the history ends with a
+    /// reply, these continue it, as [`continue_reply`](Self::continue_reply)
+    /// would; otherwise they start a new reply. Logits transforms run as at
+    /// the first step of a reply, and each candidate carries the chance the
+    /// sampler picks it with its current settings.
+    ///
+    /// A "choose the next token" tool can push a user turn with
+    /// [`push_message`](Self::push_message), peek, then extend an assistant
+    /// message with the chosen token through [`set_history`](Self::set_history).
+    pub fn peek_next_token_distribution(&mut self, top_n: usize) -> Result<Vec<TokenCandidate>> {
+        let mut messages = self.conversation_messages();
+        let partial = match self.messages.last() {
+            Some(message) if message.role == "assistant" => messages.pop().map(|m| m.content),
+            _ => None,
+        };
+        let mut text = self
+            .template
+            .apply_with_language(&messages, true, self.language.as_deref())?;
+        text.push_str(partial.as_deref().unwrap_or_default());
+        let tokens = self.encode_chat_text(&text)?;
+        if tokens.len() >= self.metadata.context_length {
+            return Err(ContextOverflow {
+                needed: tokens.len() + 1,
+                available: self.metadata.context_length,
+            }
+            .into());
+        }
+
+        let logits = match self.prefill_chunk {
+            Some(chunk) => self.model.forward_chunked(&tokens, 0, chunk)?,
+            None => self.model.forward(&tokens, 0)?,
+        };
+        let logits = logits.squeeze(0)?;
+        self.check_logits(&logits, 0, tokens.len() - 1)?;
+        self.transforms.reset();
+        self.transforms.apply_slice(
+            self.scratch.load(&logits)?,
+            &TransformContext {
+                tokens: &tokens,
+                prompt_len: tokens.len(),
+                eos_token: self.tokenizer.eos_token_id(),
+            },
+        )?;
+
+        let logits = self.scratch.logits();
+        Ok(self
+            .sampler
+            .distribution(logits, top_n)
+            .into_iter()
+            .map(|(token_id, probability)| TokenCandidate {
+                token_id,
+                token: self.tokenizer.token_to_piece(token_id).unwrap_or_default(),
+                logprob: logprobs_for(logits, token_id, 0).0,
+                probability,
+                ends_reply: token_id == self.tokenizer.eos_token_id()
+                    || (self.stop_at_turn_boundaries
+                        && self.template_stops.tokens.contains(&token_id)),
+            })
+            .collect())
+    }
+
+    /// Removes the prompt [`prepare_prompt`](Self::prepare_prompt) added
+    /// when its reply failed, leaving the history as it was before the call.
+    fn keep_prompt_on_success<T>(&mut self, result: Result<T>) -> Result<T> {
+        if result.is_err() {
+            self.messages.pop();
+        }
+        result
+    }
+
+    fn generate_internal_with_tokens<F>(
+        &mut self,
+        prompt_tokens: &[u32],
+        max_tokens: usize,
+        repeat_penalty: f32,
+        repeat_last_n: usize,
+        callback: F,
+        streaming: bool,
+    ) -> Result<String>
+    where
+        F: FnMut(StreamEvent),
+    {
+        let result = self.run_generation(
+            prompt_tokens,
+            max_tokens,
+            repeat_penalty,
+            repeat_last_n,
+            callback,
+            streaming,
+        );
+        if let Err(ref e) = result {
+            self.notify(|hooks| hooks.on_error(e));
+        }
+        result
+    }
+
+    fn run_generation<F>(
+        &mut self,
+        prompt_tokens: &[u32],
+        max_tokens: usize,
+        repeat_penalty: f32,
+        repeat_last_n: usize,
+        mut callback: F,
+        streaming: bool,
+    ) -> Result<String>
+    where
+        F: FnMut(StreamEvent),
+    {
+        let mut partial = PartialReply::default();
+        let result = self.decode_reply(
+            prompt_tokens,
+            max_tokens,
+            repeat_penalty,
+            repeat_last_n,
+            &mut callback,
+            streaming,
+            &mut partial,
+        );
+        if let Some((sampler, state)) = self.saved_sampler.take() {
+            self.sampler = sampler;
+            self.sampler_state = state;
+        }
+        let source = match result {
+            Ok(text) => return Ok(text),
+            Err(source) => source,
+        };
+        self.tokenizer.clear_cache();
+        if partial.tokens == 0 {
+            return Err(source);
+        }
+        tracing::debug!(
+            "Generation failed after {} tokens: {:#}",
+            partial.tokens,
+            source
+        );
+        callback(StreamEvent::Error(format!("{:#}", source)));
+        Err(GenerationError {
+            partial: partial.text,
+            tokens_generated: partial.tokens,
+            source,
+        }
+        .into())
+    }
+
+    /// Prefills `prompt_tokens` and decodes the reply, keeping `partial` up
+    /// to date so a failure part way through can still return it.
+    #[allow(clippy::too_many_arguments)]
+    fn decode_reply<F>(
+        &mut self,
+        prompt_tokens: &[u32],
+        max_tokens: usize,
+        repeat_penalty: f32,
+        repeat_last_n: usize,
+        mut callback: F,
+        _streaming: bool,
+        partial: &mut PartialReply,
+    ) -> Result<String>
+    where
+        F: FnMut(StreamEvent),
+    {
+        let PartialReply {
+            text: response_text,
+            tokens: generated,
+        } = partial;
+        let capped = self.fit_ttft_budget(prompt_tokens);
+        let prompt_tokens = capped.as_deref().unwrap_or(prompt_tokens);
+        let total_len = prompt_tokens.len() + max_tokens;
+        if total_len > self.metadata.context_length {
+            return Err(ContextOverflow {
+                needed: total_len,
+                available: self.metadata.context_length,
+            }
+            .into());
+        }
 
         // Reuse the pre-allocated buffer instead of allocating context_length
         // capacity (up to 128KB) on every call.
@@ -493,81 +2208,231 @@ impl Generator {
         self.all_tokens.extend_from_slice(prompt_tokens);
 
         let eos_token = self.tokenizer.eos_token_id();
-        let mut response_processor = ResponseProcessor::new();
-        let mut response_text = String::new();
+        let TemplateStops {
+            tokens: stop_tokens,
+            texts: mut stop_texts,
+        } = if self.stop_at_turn_boundaries {
+            self.template_stops.clone()
+        } else {
+            TemplateStops::default()
+        };
+        stop_texts.extend(self.stop_sequences.iter().cloned());
+        let mut response_processor = ResponseProcessor::with_stops(stop_texts);
 
         let prompt_start = std::time::Instant::now();
 
         callback(StreamEvent::PrefillStatus(prompt_tokens.len()));
+        self.notify(|hooks| hooks.on_prefill_start(prompt_tokens.len()));
+
+        self.transforms.reset();
+        self.logprobs.clear();
+        self.token_latencies.clear();
+        self.loop_detected = false;
+        self.cancelled = false;
+        if let Some(tracker) = self.confidence.as_mut() {
+            tracker.reset();
+        }
+        if let Some(redactor) = self.redactor.as_mut() {
+            redactor.reset();
+        }
 
-        let logits = self.model.forward(prompt_tokens, 0)?;
+        let logits = match (self.cancel.clone(), self.prefill_chunk) {
+            (Some(cancel), chunk) => {
+                let chunk = chunk.unwrap_or(PREEMPTIBLE_PREFILL_CHUNK);
+                self.model
+                    .forward_preemptible(prompt_tokens, 0, chunk, || !cancel.is_cancelled())?
+            }
+            (None, Some(chunk)) => Some(self.model.forward_chunked(prompt_tokens, 0, chunk)?),
+            (None, None) => Some(self.model.forward(prompt_tokens, 0)?),
+        };
+        let Some(logits) = logits else {
+            tracing::debug!("Generation cancelled during prefill");
+            self.cancelled = true;
+            callback(StreamEvent::Done);
+            let elapsed = prompt_start.elapsed();
+            self.notify(|hooks| hooks.on_done(0, elapsed));
+            return Ok(String::new());
+        };
         let logits = logits.squeeze(0)?;
+        self.check_logits(&logits, 0, prompt_tokens.len() - 1)?;
+        self.transforms.apply_slice(
+            self.scratch.load(&logits)?,
+            &TransformContext {
+                tokens: &self.all_tokens,
+                prompt_len: prompt_tokens.len(),
+                eos_token,
+            },
+        )?;
 
-        let mut next_token = self.logits_processor.sample(&logits)?;
+        let mut next_token = self.sample()?;
+        self.record_token_stats(next_token)?;
 
         tracing::debug!(
             "Prompt processed: {} tokens in {:.2}s",
             prompt_tokens.len(),
             prompt_start.elapsed().as_secs_f32()
         );
+        let prefill_time = prompt_start.elapsed();
+        if !prefill_time.is_zero() {
+            self.prefill_rate = Some(prompt_tokens.len() as f64 / prefill_time.as_secs_f64());
+        }
+        self.notify(|hooks| hooks.on_prefill_end(prompt_tokens.len(), prefill_time));
 
-        let mut generated = 1usize;
+        *generated = 1;
         self.all_tokens.push(next_token);
 
         // Emit first generated token via incremental decoder.
+        let mut released = String::new();
+        let mut stop = false;
         if !self.tokenizer.is_special_token(next_token) {
             if let Some(text) = self.tokenizer.decode_next(next_token)? {
                 let processed = response_processor.push(&text);
-                if !processed.text.is_empty() {
-                    response_text.push_str(&processed.text);
-                    callback(StreamEvent::Token(processed.text));
-                }
-                if processed.should_stop {
-                    self.tokenizer.clear_cache();
-                    callback(StreamEvent::Done);
-
-                    return Ok(response_text);
-                }
+                released = self.redact_push(processed.text);
+                stop = processed.should_stop;
             }
         }
+        if stop {
+            released.push_str(&self.redact_finish());
+        }
+        self.notify(|hooks| hooks.on_token(next_token, &released));
+        if !released.is_empty() {
+            response_text.push_str(&released);
+            callback(StreamEvent::Token(released));
+        }
+        if stop {
+            self.tokenizer.clear_cache();
+            callback(StreamEvent::Done);
+            let elapsed = prompt_start.elapsed();
+            self.notify(|hooks| hooks.on_done(*generated, elapsed));
+
+            return Ok(std::mem::take(response_text));
+        }
 
         let gen_start = std::time::Instant::now();
 
-        for _ in 1..max_tokens {
-            if next_token == eos_token {
+        let grace = if self.finish_at_boundary {
+            BOUNDARY_GRACE_TOKENS.min(self.metadata.context_length - total_len)
+        } else {
+            0
+        };
+        // Where the loop guard starts looking; moved past the loop once the
+        // temperature is bumped.
+        let mut loop_start = prompt_tokens.len();
+        for step in 1..max_tokens + grace {
+            if next_token == eos_token || stop_tokens.contains(&next_token) {
                 break;
             }
+            if step >= max_tokens {
+                // Count the text still held back, which ends the reply too.
+                let redacting = self.redactor.as_ref().map_or("", Redactor::pending);
+                let text = [response_text.as_str(), redacting, response_processor.pending()];
+                if ends_sentence(&text.concat()) {
+                    break;
+                }
+            }
+            if self.cancel.as_ref().is_some_and(CancelToken::is_cancelled) {
+                tracing::debug!("Generation cancelled after {} tokens", generated);
+                self.cancelled = true;
+                break;
+            }
+            if !self.stop_conditions.is_empty()
+                && self.stop_conditions.should_stop(&StopContext {
+                    text: response_text,
+                    tokens: *generated,
+                    elapsed: prompt_start.elapsed(),
+                })
+            {
+                tracing::debug!("Stop condition met after {} tokens", generated);
+                break;
+            }
+            if let Some(period) = self.loop_guard.detect(&self.all_tokens[loop_start..]) {
+                match self.loop_guard.action {
+                    LoopAction::Bump(delta) if self.saved_sampler.is_none() => {
+                        tracing::warn!(
+                            "Reply repeating a {}-token cycle; raising the temperature by {}",
+                            period,
+                            delta
+                        );
+                        let seed = self
+                            .sampler_state
+                            .seed
+                            .wrapping_add(self.sampler_state.draws);
+                        let sampler = TokenSampler::new(
+                            seed,
+                            loop_guard::bumped(self.sampler.sampling(), delta),
+                        );
+                        let saved = (
+                            std::mem::replace(&mut self.sampler, sampler),
+                            std::mem::replace(
+                                &mut self.sampler_state,
+                                SamplerState { seed, draws: 0 },
+                            ),
+                        );
+                        self.saved_sampler = Some(saved);
+                        loop_start = self.all_tokens.len();
+                    }
+                    _ => {
+                        tracing::warn!(
+                            "Reply repeating a {}-token cycle; stopped after {} tokens",
+                            period,
+                            generated
+                        );
+                        self.loop_detected = true;
+                        callback(StreamEvent::LoopDetected { period });
+                        break;
+                    }
+                }
+            }
 
+            let step_start = std::time::Instant::now();
             let logits = self
                 .model
                 .forward(&[next_token], self.all_tokens.len() - 1)?;
             let logits = logits.squeeze(0)?;
+            if numerics::should_check(step) {
+                self.check_logits(&logits, step, self.all_tokens.len() - 1)?;
+            }
 
-            let logits = if repeat_penalty != 1.0 {
+            self.scratch.load(&logits)?;
+            if repeat_penalty != 1.0 {
                 let start_at = self.all_tokens.len().saturating_sub(repeat_last_n);
-                apply_repeat_penalty(&logits, repeat_penalty, &self.all_tokens[start_at..])?
-            } else {
-                logits
-            };
+                self.scratch
+                    .apply_repeat_penalty(repeat_penalty, &self.all_tokens[start_at..]);
+            }
+            self.transforms.apply_slice(
+                self.scratch.logits_mut(),
+                &TransformContext {
+                    tokens: &self.all_tokens,
+                    prompt_len: prompt_tokens.len(),
+                    eos_token,
+                },
+            )?;
 
-            next_token = self.logits_processor.sample(&logits)?;
+            next_token = self.sample()?;
+            self.token_latencies.push(step_start.elapsed());
+            self.record_token_stats(next_token)?;
             self.all_tokens.push(next_token);
-            generated += 1;
+            *generated += 1;
 
             // Use incremental decode: emits text as soon as a word boundary is
             // reached, without buffering or re-decoding previously seen tokens.
+            let mut released = String::new();
+            let mut stop = false;
             if !self.tokenizer.is_special_token(next_token) {
                 if let Some(text) = self.tokenizer.decode_next(next_token)? {
                     let processed = response_processor.push(&text);
-                    if !processed.text.is_empty() {
-                        response_text.push_str(&processed.text);
-                        callback(StreamEvent::Token(processed.text));
-                    }
-                    if processed.should_stop {
-                        break;
-                    }
+                    released = self.redact_push(processed.text);
+                    stop = processed.should_stop;
                 }
             }
+            self.notify(|hooks| hooks.on_token(next_token, &released));
+            if !released.is_empty() {
+                response_text.push_str(&released);
+                callback(StreamEvent::Token(released));
+            }
+            if stop {
+                break;
+            }
         }
 
         // clear_cache() resets the incremental decoder state. decode_rest() is
@@ -577,15 +2442,19 @@ impl Generator {
         // decode_single emits each fragment as soon as it has enough bytes.
         self.tokenizer.clear_cache();
 
-        let tail = response_processor.finish();
+        let mut tail = response_processor.finish();
+        if self.redactor.is_some() {
+            tail = self.redact_push(tail);
+            tail.push_str(&self.redact_finish());
+        }
         if !tail.is_empty() {
             response_text.push_str(&tail);
             callback(StreamEvent::Token(tail));
         }
 
         let dt = gen_start.elapsed();
-        let tokens_per_sec = if generated > 0 && dt.as_secs_f64() > 0.0 {
-            generated as f64 / dt.as_secs_f64()
+        let tokens_per_sec = if *generated > 0 && dt.as_secs_f64() > 0.0 {
+            *generated as f64 / dt.as_secs_f64()
         } else {
             0.0
         };
@@ -597,8 +2466,10 @@ impl Generator {
         );
 
         callback(StreamEvent::Done);
+        let elapsed = prompt_start.elapsed();
+        self.notify(|hooks| hooks.on_done(*generated, elapsed));
 
-        Ok(response_text)
+        Ok(std::mem::take(response_text))
     }
 
     pub fn generate_batch(
@@ -613,34 +2484,33 @@ impl Generator {
         }
 
         // Build prompt texts first (borrows self.template + self.system_prompt),
-        // then encode in a separate pass (borrows self.tokenizer).
+        // then encode them in one parallel batch (borrows self.tokenizer).
         // This avoids needing to clone ChatTemplate, which no longer implements Clone.
         let prompt_texts: Vec<String> = prompts
             .iter()
             .map(|prompt| {
-                let mut all_messages = Vec::new();
-                if let Some(ref sys) = self.system_prompt {
-                    all_messages.push(Message {
-                        role: "system".into(),
-                        content: sys.clone(),
-                    });
-                }
-                all_messages.push(Message {
-                    role: "user".into(),
-                    content: prompt.clone(),
-                });
-                self.template.apply(&all_messages, true)
+                let language = self.resolve_language(prompt);
+                let mut all_messages = self.pinned_messages(language.as_deref());
+                all_messages.push(Message::new("user", prompt.clone()));
+                self.template
+                    .apply_with_language(&all_messages, true, language.as_deref())
             })
             .collect::<Result<Vec<_>>>()?;
 
-        let prompt_tokens_list: Vec<Vec<u32>> = prompt_texts
-            .iter()
-            .map(|text| self.encode_chat_text(text))
-            .collect::<Result<Vec<_>>>()?;
+        let prompt_tokens_list = self.tokenizer.encode_batch(&prompt_texts)?;
 
         let mut results = Vec::with_capacity(prompts.len());
 
         for prompt_tokens in prompt_tokens_list {
+            let context_length = self.metadata.context_length;
+            let max_tokens = completion_budget(context_length, prompt_tokens.len(), max_tokens)
+                .ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "Prompt is too large for the model context window ({} > {}).",
+                        required_context(prompt_tokens.len(), max_tokens),
+                        context_length
+                    )
+                })?;
             let result = self.generate_internal_with_tokens(
                 &prompt_tokens,
                 max_tokens,
@@ -654,17 +2524,282 @@ impl Generator {
 
         Ok(results)
     }
+
+    /// Picks the most likely of `labels` as the reply to `prompt`, with the
+    /// probability of each label when decoding may only produce a label.
+    /// Like [`generate_batch`](Self::generate_batch), the conversation
+    /// history is neither used nor changed.
+    pub fn choose(&mut self, prompt: &str, labels: &[String]) -> Result<Choice> {
+        let language = self.resolve_language(prompt);
+        let mut messages = self.pinned_messages(language.as_deref());
+        messages.push(Message::new("user", prompt));
+        let prompt_text = self
+            .template
+            .apply_with_language(&messages, true, language.as_deref())?;
+        let prompt_tokens = self.encode_chat_text(&prompt_text)?;
+        let sequences = labels
+            .iter()
+            .map(|label| self.tokenizer.encode_with_options(label, false, false))
+            .collect::<Result<Vec<_>>>()?;
+
+        let eos_token = self.tokenizer.eos_token_id();
+        let context_length = self.metadata.context_length;
+        let prefill_chunk = self.prefill_chunk;
+        let model = &mut self.model;
+        choose_label(labels, &sequences, eos_token, |prefix| {
+            let mut tokens = prompt_tokens.clone();
+            tokens.extend_from_slice(prefix);
+            if tokens.len() > context_length {
+                anyhow::bail!(
+                    "Prompt is too large for the model context window ({} > {}).",
+                    tokens.len(),
+                    context_length
+                );
+            }
+            let logits = match prefill_chunk {
+                Some(chunk) => model.forward_chunked(&tokens, 0, chunk)?,
+                None => model.forward(&tokens, 0)?,
+            };
+            Ok(logits
+                .squeeze(0)?
+                .to_dtype(candle_core::DType::F32)?
+                .to_vec1::<f32>()?)
+        })
+    }
+
+    /// Runs `prompt`, rendered with the chat template like a new turn, through
+    /// the model once and records activation statistics of every layer. The
+    /// conversation history is neither used nor changed.
+    pub fn debug_activations(&mut self, prompt: &str) -> Result<ActivationReport> {
+        let language = self.resolve_language(prompt);
+        let mut messages = self.pinned_messages(language.as_deref());
+        messages.push(Message::new("user", prompt));
+        let prompt_text = self
+            .template
+            .apply_with_language(&messages, true, language.as_deref())?;
+        let prompt_tokens = self.encode_chat_text(&prompt_text)?;
+        if prompt_tokens.len() > self.metadata.context_length {
+            anyhow::bail!(
+                "Prompt is too large for the model context window ({} > {}).",
+                prompt_tokens.len(),
+                self.metadata.context_length
+            );
+        }
+        self.model.forward_debug(&prompt_tokens)
+    }
+
+    /// Runs `reference.prompt` as raw text, without the chat template, and
+    /// decodes greedily for as many tokens as the reference has, stopping
+    /// after the end token. Sampling settings, logits transforms and the
+    /// conversation history are not used. `case` numbers the report.
+    pub fn verify(&mut self, case: usize, reference: &ReferenceCase) -> Result<CaseReport> {
+        let prompt_tokens = self
+            .tokenizer
+            .encode_with_options(&reference.prompt, true, true)?;
+        let total_len = prompt_tokens.len() + reference.tokens.len();
+        if total_len > self.metadata.context_length {
+            anyhow::bail!(
+                "Prompt is too large for the model context window ({} > {}).",
+                total_len,
+                self.metadata.context_length
+            );
+        }
+
+        let eos_token = self.tokenizer.eos_token_id();
+        let mut tokens: Vec<u32> = Vec::with_capacity(reference.tokens.len());
+        let mut logits = match self.prefill_chunk {
+            Some(chunk) => self.model.forward_chunked(&prompt_tokens, 0, chunk)?,
+            None => self.model.forward(&prompt_tokens, 0)?,
+        };
+        while tokens.len() < reference.tokens.len() {
+            let next = logits
+                .squeeze(0)?
+                .to_dtype(candle_core::DType::F32)?
+                .argmax(0)?
+                .to_scalar::<u32>()?;
+            tokens.push(next);
+            if next == eos_token || tokens.len() == reference.tokens.len() {
+                break;
+            }
+            let pos = prompt_tokens.len() + tokens.len() - 1;
+            logits = self.model.forward(&[next], pos)?;
+        }
+
+        let tokenizer = &self.tokenizer;
+        Ok(CaseReport::compare(
+            case,
+            reference,
+            &prompt_tokens,
+            &tokens,
+            |id| tokenizer.token_to_piece(id).ok(),
+        ))
+    }
 }
 
 unsafe impl Send for Generator {}
 
 #[cfg(test)]
-mod tests {
-    use super::ResponseProcessor;
+pub(crate) mod tests {
+    use std::time::Duration;
+
+    use super::{
+        completion_budget, load_examples, ChatTemplate, GenerationError, Generator, Message,
+        ResponseProcessor, StreamEvent, TemplateStops, AUTO_MAX_TOKENS_MARGIN,
+        LOW_MEM_PREFILL_CHUNK,
+    };
+    use crate::inference::sampler::{LogitsTransform, TransformContext, TransformStage};
+    use crate::inference::CancelToken;
+    use crate::model::convert::tiny_llama;
+
+    /// Forces the reply to follow a script of token ids, repeating the last.
+    pub(crate) struct Script(pub Vec<u32>);
+
+    impl LogitsTransform for Script {
+        fn stage(&self) -> TransformStage {
+            TransformStage::Bias
+        }
+
+        fn apply(&mut self, logits: &mut [f32], context: &TransformContext) -> anyhow::Result<()> {
+            let step = context.generated().len().min(self.0.len() - 1);
+            for (id, logit) in logits.iter_mut().enumerate() {
+                if id as u32 != self.0[step] {
+                    *logit = f32::NEG_INFINITY;
+                }
+            }
+            Ok(())
+        }
+    }
+
+    /// A generator over [`tiny_llama`] that replies with `script`.
+    pub(crate) fn scripted(dir: &std::path::Path, script: Vec<u32>) -> Generator {
+        std::fs::create_dir_all(dir).unwrap();
+        let path = tiny_llama(dir);
+        let mut generator =
+            Generator::new(&path, Some(&path), 0.8, None, None, 42, None, 32).unwrap();
+        generator.add_logits_transform(Box::new(Script(script)));
+        generator
+    }
+
+    #[test]
+    fn finishes_at_a_boundary_the_processor_holds_back() {
+        let dir = std::env::temp_dir().join(format!("oxide-boundary-{}", std::process::id()));
+        // "b", "c", "." and then "b" for as long as the reply runs.
+        let mut generator = scripted(&dir, vec![1, 2, 7, 1]);
+        // "." could start the stop sequence ".c", so it is held back.
+        generator.set_stop_sequences(vec![".c".to_string()]);
+        generator.set_finish_at_boundary(true);
+
+        let reply = generator.generate("ab", 3, 1.0, 64, |_| {}).unwrap();
+        assert_eq!(reply, "bc.");
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn ttft_budget_keeps_the_first_and_last_prompt_tokens() {
+        let dir = std::env::temp_dir().join(format!("oxide-ttft-{}", std::process::id()));
+        let mut generator = scripted(&dir, vec![1]);
+        let prefills = |generator: &mut Generator| {
+            let mut prefilled = Vec::new();
+            let record = |event| {
+                if let StreamEvent::PrefillStatus(n) = event {
+                    prefilled.push(n);
+                }
+            };
+            let reply = generator
+                .generate_internal_with_tokens(&[5; 40], 2, 1.0, 64, record, false)
+                .unwrap();
+            (prefilled, reply)
+        };
+
+        generator.set_ttft_budget(Some(Duration::from_millis(20)));
+        assert_eq!(prefills(&mut generator), (vec![40], "bb".to_string()));
+        assert!(generator.prefill_rate.is_some());
+
+        // 1000 tokens per second fit 20 tokens in the budget.
+        generator.prefill_rate = Some(1000.0);
+        assert_eq!(prefills(&mut generator), (vec![20], "bb".to_string()));
+        let mut prompt: Vec<u32> = (0..40).map(|i| i % 7).collect();
+        prompt[0] = 5;
+        generator.prefill_rate = Some(1000.0);
+        let kept = generator.fit_ttft_budget(&prompt).unwrap();
+        assert_eq!(kept[0], 5);
+        assert_eq!(kept[1..], prompt[21..]);
+
+        generator.set_ttft_budget(None);
+        generator.prefill_rate = Some(1000.0);
+        assert_eq!(prefills(&mut generator).0, vec![40]);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn collapsing_retries_survives_turns_dropped_in_between() {
+        let dir = std::env::temp_dir().join(format!("oxide-collapse-{}", std::process::id()));
+        let mut generator = scripted(&dir, vec![1]);
+        let long = vec!["ab"; 100].join(" ");
+        generator
+            .set_history(vec![
+                Message::new("user", long.clone()),
+                Message::new("assistant", "b"),
+                Message::new("user", "a"),
+                Message::new("assistant", "c"),
+            ])
+            .unwrap();
+
+        let mark = generator.history_mark();
+        generator.generate("ab", 2, 1.0, 64, |_| {}).unwrap();
+        // The retry only fits once the long first turn is dropped.
+        let retry = vec!["ab"; 120].join(" ");
+        let reply = generator.generate(&retry, 100, 1.0, 64, |_| {}).unwrap();
+        assert_eq!(generator.history()[0].content, "a");
+
+        generator.collapse_history_since(mark, "ab", &reply).unwrap();
+        let history: Vec<_> = generator
+            .history()
+            .iter()
+            .map(|message| (message.role.as_str(), message.content.as_str()))
+            .collect();
+        assert_eq!(
+            history,
+            [("user", "a"), ("assistant", "c"), ("user", "ab"), ("assistant", reply.as_str())]
+        );
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn low_mem_round_trip_restores_the_defaults() {
+        let dir = std::env::temp_dir().join(format!("oxide-low-mem-{}", std::process::id()));
+        let mut generator = scripted(&dir, vec![1]);
+        let stats = generator.kv_cache_stats();
+        assert!(stats.is_some());
+
+        generator.set_low_mem(true);
+        assert_eq!(generator.prefill_chunk, Some(LOW_MEM_PREFILL_CHUNK));
+        assert_eq!(generator.kv_cache_stats(), None);
+
+        generator.set_low_mem(false);
+        assert_eq!(generator.prefill_chunk, None);
+        assert_eq!(generator.kv_cache_stats(), stats);
+        assert_eq!(generator.generate("ab", 2, 1.0, 64, |_| {}).unwrap(), "bb");
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn cancelled_generation_stops_before_prefill() {
+        let dir = std::env::temp_dir().join(format!("oxide-cancel-{}", std::process::id()));
+        let mut generator = scripted(&dir, vec![1]);
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        generator.set_cancel_token(Some(cancel));
+        assert_eq!(generator.generate("ab", 4, 1.0, 64, |_| {}).unwrap(), "");
+
+        generator.set_cancel_token(Some(CancelToken::new()));
+        assert_eq!(generator.generate("ab", 2, 1.0, 64, |_| {}).unwrap(), "bb");
+        std::fs::remove_dir_all(&dir).ok();
+    }
 
     #[test]
     fn strips_split_control_sequences_across_chunks() {
-        let mut processor = ResponseProcessor::new();
+        let mut processor = ResponseProcessor::default();
 
         let first = processor.push("Hello<|im_");
         assert_eq!(first.text, "Hello");
@@ -681,16 +2816,32 @@ mod tests {
 
     #[test]
     fn stops_on_im_end_without_emitting_marker() {
-        let mut processor = ResponseProcessor::new();
+        let mut processor = ResponseProcessor::default();
 
         let chunk = processor.push("Answer<|im_end|>garbage");
         assert_eq!(chunk.text, "Answer");
         assert!(chunk.should_stop);
     }
 
+    #[test]
+    fn generation_errors_keep_the_partial_reply() {
+        let source = anyhow::anyhow!("logits are NaN").context("decode step 7 failed");
+        let err = anyhow::Error::from(GenerationError {
+            partial: "The answer is".to_string(),
+            tokens_generated: 7,
+            source,
+        });
+        assert_eq!(
+            format!("{:#}", err),
+            "Generation failed after 7 tokens: decode step 7 failed: logits are NaN"
+        );
+        let failed = err.downcast_ref::<GenerationError>().unwrap();
+        assert_eq!(failed.partial, "The answer is");
+    }
+
     #[test]
     fn flushes_remaining_text_on_finish() {
-        let mut processor = ResponseProcessor::new();
+        let mut processor = ResponseProcessor::default();
 
         let chunk = processor.push("done");
         assert_eq!(chunk.text, "done");
@@ -698,4 +2849,159 @@ mod tests {
 
         assert_eq!(processor.finish(), "");
     }
+
+    #[test]
+    fn auto_budget_fills_remaining_context() {
+        let budget = completion_budget(4096, 1000, 0);
+        assert_eq!(budget, Some(4096 - 1000 - AUTO_MAX_TOKENS_MARGIN));
+    }
+
+    #[test]
+    fn auto_budget_rejects_prompts_without_headroom() {
+        assert_eq!(completion_budget(4096, 4090, 0), None);
+    }
+
+    #[test]
+    fn fixed_budget_must_fit_context() {
+        assert_eq!(completion_budget(4096, 1000, 512), Some(512));
+        assert_eq!(completion_budget(4096, 3800, 512), None);
+    }
+
+    #[test]
+    fn loads_examples_from_jsonl_and_json_array() {
+        let dir = std::env::temp_dir();
+        let jsonl = dir.join(format!("oxide-examples-{}.jsonl", std::process::id()));
+        let json = dir.join(format!("oxide-examples-{}.json", std::process::id()));
+        std::fs::write(
+            &jsonl,
+            "{\"user\": \"hi\", \"assistant\": \"hello\"}\n\n{\"user\": \"2+2\", \"assistant\": \"4\"}\n",
+        )
+        .unwrap();
+        std::fs::write(&json, "[{\"user\": \"hi\", \"assistant\": \"hello\"}]").unwrap();
+
+        let from_jsonl = load_examples(&jsonl).unwrap();
+        let from_json = load_examples(&json).unwrap();
+        std::fs::remove_file(&jsonl).ok();
+        std::fs::remove_file(&json).ok();
+
+        assert_eq!(from_jsonl.len(), 2);
+        assert_eq!(from_jsonl[1], ("2+2".to_string(), "4".to_string()));
+        assert_eq!(from_json, vec![("hi".to_string(), "hello".to_string())]);
+    }
+
+    #[test]
+    fn renders_new_turns_like_a_full_rendering() {
+        let chatml = "{% for m in messages %}<|im_start|>{{ m.role }}\n{{ m.content }}<|im_end|>\n\
+                      {% endfor %}{% if add_generation_prompt %}<|im_start|>assistant\n{% endif %}";
+        // Llama 2 style: the system prompt is folded into the first user turn.
+        let llama2 = "{% if messages[0].role == 'system' %}{% set sys = messages[0].content %}\
+                      {% set messages = messages[1:] %}{% endif %}{% for m in messages %}\
+                      {% if m.role == 'user' %}<s>[INST] {% if loop.first and sys %}\
+                      <<SYS>>{{ sys }}<</SYS>> {% endif %}{{ m.content }} [/INST]\
+                      {% else %} {{ m.content }} </s>{% endif %}{% endfor %}";
+        let history = [
+            Message::new("system", "Be brief."),
+            Message::new("user", "hi"),
+            Message::new("assistant", "hello"),
+            Message::new("user", " what is 2+2?\n"),
+            Message::new("assistant", "4"),
+            Message::new("user", "and 3+3?"),
+        ];
+        for source in [chatml, llama2] {
+            let template = ChatTemplate::new(Some(source.to_string())).unwrap();
+            assert!(template.renders_turns_independently(), "{}", source);
+            for (start, end) in [(2, 6), (3, 6), (4, 5), (2, 4)] {
+                let mut text = template.apply(&history[..start], false).unwrap();
+                text.push_str(&template.render_turns(&history[start..end], true).unwrap());
+                assert_eq!(text, template.apply(&history[..end], true).unwrap());
+            }
+        }
+
+        // Trimmed contents or earlier turns that change with later ones
+        // cannot be rendered on their own.
+        for source in [
+            "{% for m in messages %}[{{ m.role }}]{{ m.content | trim }}{% endfor %}",
+            "{% for m in messages %}{% if loop.last %}>{% endif %}{{ m.content }};{% endfor %}",
+        ] {
+            let template = ChatTemplate::new(Some(source.to_string())).unwrap();
+            assert!(!template.renders_turns_independently(), "{}", source);
+            assert_eq!(template.render_turns(&history[1..3], false), None);
+        }
+        let template = ChatTemplate::new(Some(chatml.to_string())).unwrap();
+        let named = [Message::new("user", "hi").with_name("alice")];
+        assert_eq!(template.render_turns(&named, false), None);
+    }
+
+    #[test]
+    fn stops_at_template_turn_boundaries() {
+        let chatml = "{% for m in messages %}<|im_start|>{{ m.role }}\n{{ m.content }}<|im_end|>\n\
+                      {% endfor %}{% if add_generation_prompt %}<|im_start|>assistant\n{% endif %}";
+        let markdown = "{% for m in messages %}### {{ m.role | capitalize }}:\n{{ m.content }}\n\n\
+                        {% endfor %}{% if add_generation_prompt %}### Assistant:\n{% endif %}";
+        let chatml = ChatTemplate::new(Some(chatml.to_string())).unwrap();
+        let markdown = ChatTemplate::new(Some(markdown.to_string())).unwrap();
+        assert_eq!(chatml.turn_boundaries(), ["<|im_start|>user", "<|im_end|>"]);
+        assert_eq!(markdown.turn_boundaries(), ["### User:"]);
+        let none = ChatTemplate::new(None).unwrap();
+        assert!(none.turn_boundaries().is_empty());
+
+        // Special tokens get ids from 1, other characters their code point
+        // plus 100; `<|im_end|>` is EOS.
+        let specials = ["<|im_start|>", "<|im_end|>"];
+        let encode = |text: &str| {
+            let mut tokens = Vec::new();
+            let mut rest = text;
+            while let Some(c) = rest.chars().next() {
+                match specials.iter().position(|s| rest.starts_with(s)) {
+                    Some(i) => {
+                        tokens.push(i as u32 + 1);
+                        rest = &rest[specials[i].len()..];
+                    }
+                    None => {
+                        tokens.push(c as u32 + 100);
+                        rest = &rest[c.len_utf8()..];
+                    }
+                }
+            }
+            Ok(tokens)
+        };
+        let decode = |tokens: &[u32]| {
+            Ok(tokens
+                .iter()
+                .filter_map(|&t| char::from_u32(t - 100))
+                .collect())
+        };
+        let stops = |template: &ChatTemplate| {
+            TemplateStops::new(template.turn_boundaries(), 2, encode, |t| t <= 2, decode).unwrap()
+        };
+        assert_eq!(stops(&chatml).tokens, [1]);
+        assert!(stops(&chatml).texts.is_empty());
+        assert!(stops(&markdown).tokens.is_empty());
+        assert_eq!(stops(&markdown).texts, ["### User:"]);
+
+        let mut processor = ResponseProcessor::with_stops(stops(&markdown).texts);
+        let chunk = processor.push("4\n\n### Us");
+        assert_eq!(chunk.text, "4\n\n");
+        assert!(!chunk.should_stop);
+        let chunk = processor.push("er: and 3+3?");
+        assert_eq!(chunk.text, "");
+        assert!(chunk.should_stop);
+    }
+
+    #[test]
+    fn templates_see_message_name_and_metadata() {
+        let template = ChatTemplate::new(Some(
+            "{% for m in messages %}{{ m.role }}{% if m.name %}({{ m.name }}){% endif %}\
+             {% if m.metadata %}[{{ m.metadata.mood }}]{% endif %}:{{ m.content }};{% endfor %}"
+                .to_string(),
+        ))
+        .unwrap();
+        let messages = [
+            Message::new("user", "hi").with_name("alice"),
+            Message::new("assistant", "hello").with_metadata(serde_json::json!({"mood": "calm"})),
+        ];
+
+        let rendered = template.apply(&messages, false).unwrap();
+        assert_eq!(rendered, "user(alice):hi;assistant[calm]:hello;");
+    }
 }
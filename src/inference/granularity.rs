@@ -0,0 +1,195 @@
+//! Aggregation of streamed text into words or sentences.
+//!
+//! Small models can stream hundreds of tokens per second, which makes UIs
+//! flicker and SSE streams chatty. [`StreamChunker`] buffers decoded text and
+//! releases it at word or sentence boundaries. Chunks are cut before the
+//! whitespace that follows a boundary, so each chunk after the first starts
+//! with its separator, like tokens do.
+
+use std::str::FromStr;
+
+/// Text buffered before it is released regardless of boundaries, so long
+/// runs without whitespace (code, URLs) still stream.
+const MAX_BUFFERED: usize = 256;
+
+/// Where streamed text is split into chunks.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StreamGranularity {
+    /// Every decoded piece as it arrives.
+    #[default]
+    Token,
+    /// Whole words.
+    Word,
+    /// Whole sentences or lines.
+    Sentence,
+}
+
+impl StreamGranularity {
+    pub const ALL: [StreamGranularity; 3] = [
+        StreamGranularity::Token,
+        StreamGranularity::Word,
+        StreamGranularity::Sentence,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StreamGranularity::Token => "token",
+            StreamGranularity::Word => "word",
+            StreamGranularity::Sentence => "sentence",
+        }
+    }
+}
+
+impl FromStr for StreamGranularity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.to_ascii_lowercase();
+        StreamGranularity::ALL
+            .iter()
+            .copied()
+            .find(|g| g.as_str() == s)
+            .ok_or_else(|| {
+                format!(
+                    "unknown stream granularity '{}' (expected token, word or sentence)",
+                    s
+                )
+            })
+    }
+}
+
+impl std::fmt::Display for StreamGranularity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Buffers streamed text and releases it at the chosen granularity.
+#[derive(Clone, Debug, Default)]
+pub struct StreamChunker {
+    granularity: StreamGranularity,
+    buffer: String,
+}
+
+impl StreamChunker {
+    pub fn new(granularity: StreamGranularity) -> Self {
+        Self {
+            granularity,
+            buffer: String::new(),
+        }
+    }
+
+    /// Adds `text` and returns the next chunk, if a boundary was reached.
+    pub fn push(&mut self, text: &str) -> Option<String> {
+        if self.granularity == StreamGranularity::Token {
+            return (!text.is_empty()).then(|| text.to_string());
+        }
+        self.buffer.push_str(text);
+
+        let cut = if self.buffer.len() > MAX_BUFFERED {
+            self.buffer.len()
+        } else {
+            match self.granularity {
+                StreamGranularity::Token => unreachable!(),
+                StreamGranularity::Word => word_boundary(&self.buffer),
+                StreamGranularity::Sentence => sentence_boundary(&self.buffer),
+            }
+        };
+        if cut == 0 {
+            return None;
+        }
+        let chunk: String = self.buffer.drain(..cut).collect();
+        Some(chunk)
+    }
+
+    /// Releases whatever is still buffered at the end of the stream.
+    pub fn finish(&mut self) -> Option<String> {
+        let rest = std::mem::take(&mut self.buffer);
+        (!rest.is_empty()).then_some(rest)
+    }
+}
+
+/// Whether `text` ends a sentence or a line, ignoring trailing spaces and
+/// closing quotes or brackets.
+pub fn ends_sentence(text: &str) -> bool {
+    if text.ends_with('\n') {
+        return true;
+    }
+    let text = text
+        .trim_end_matches([' ', '\t'])
+        .trim_end_matches(['"', '\'', '”', '’', ')', ']', '*', '_']);
+    text.ends_with(['.', '!', '?', '…', '。', '！', '？'])
+}
+
+/// Start of the last whitespace run that follows some text.
+fn word_boundary(text: &str) -> usize {
+    let mut cut = 0;
+    let mut prev_space = true;
+    for (i, c) in text.char_indices() {
+        let space = c.is_whitespace();
+        if space && !prev_space {
+            cut = i;
+        }
+        prev_space = space;
+    }
+    cut
+}
+
+/// Position of the last line break, or of the whitespace after the last
+/// sentence-ending punctuation.
+fn sentence_boundary(text: &str) -> usize {
+    let mut cut = 0;
+    let mut prev = None;
+    for (i, c) in text.char_indices() {
+        let after_end = prev.is_some_and(|p| matches!(p, '.' | '!' | '?' | '…'));
+        if i > 0 && (c == '\n' || (c.is_whitespace() && after_end)) {
+            cut = i;
+        }
+        // CJK full stops are not followed by spaces.
+        if matches!(c, '。' | '！' | '？') {
+            cut = i + c.len_utf8();
+        }
+        prev = Some(c);
+    }
+    cut
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ends_sentence, StreamChunker, StreamGranularity};
+
+    fn chunks(granularity: StreamGranularity, pieces: &[&str]) -> Vec<String> {
+        let mut chunker = StreamChunker::new(granularity);
+        let mut out: Vec<String> = pieces.iter().filter_map(|p| chunker.push(p)).collect();
+        out.extend(chunker.finish());
+        out
+    }
+
+    #[test]
+    fn splits_at_words_and_sentences() {
+        let pieces = ["Hel", "lo", " wor", "ld.", " How", " are", " you?", "\nFine"];
+        assert_eq!(chunks(StreamGranularity::Token, &pieces).len(), pieces.len());
+        assert_eq!(
+            chunks(StreamGranularity::Word, &pieces),
+            ["Hello", " world.", " How", " are", " you?", "\nFine"]
+        );
+        assert_eq!(
+            chunks(StreamGranularity::Sentence, &pieces),
+            ["Hello world.", " How are you?", "\nFine"]
+        );
+        assert_eq!(
+            chunks(StreamGranularity::Sentence, &["你好。", "再见"]),
+            ["你好。", "再见"]
+        );
+        assert!(ends_sentence("It works. "));
+        assert!(ends_sentence("He said \"stop!\""));
+        assert!(ends_sentence("- item\n"));
+        assert!(ends_sentence("完成。"));
+        assert!(!ends_sentence("It wor"));
+        assert!(!ends_sentence(""));
+
+        assert_eq!("Word".parse(), Ok(StreamGranularity::Word));
+        assert!("line".parse::<StreamGranularity>().is_err());
+    }
+}
@@ -0,0 +1,103 @@
+//! Generation lifecycle hooks for embedders.
+//!
+//! Hooks observe every generation run by a [`Generator`](super::Generator)
+//! (including batch generation) without touching the streaming callbacks, so
+//! logging, billing, or UI updates can be layered on without forking them.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Callbacks fired during a generation. Every method has an empty default, so
+/// implementors only override what they need.
+pub trait GenerationHooks: Send {
+    /// The prompt is about to be processed.
+    fn on_prefill_start(&mut self, _prompt_tokens: usize) {}
+
+    /// The prompt has been processed and the first token sampled.
+    fn on_prefill_end(&mut self, _prompt_tokens: usize, _elapsed: Duration) {}
+
+    /// A token was sampled. `text` is the text it released to the caller,
+    /// which is empty for special tokens and partial characters.
+    fn on_token(&mut self, _token: u32, _text: &str) {}
+
+    /// The natural log of the probability of the token just sampled, before
+    /// [`on_token`](Self::on_token) for the same token. Only fired when
+    /// [`wants_logprobs`](Self::wants_logprobs) returns `true` or logprobs
+    /// are recorded.
+    fn on_token_logprob(&mut self, _token: u32, _logprob: f32) {}
+
+    /// Whether this hook needs [`on_token_logprob`](Self::on_token_logprob).
+    /// Computing it takes a pass over the vocabulary for every token.
+    fn wants_logprobs(&self) -> bool {
+        false
+    }
+
+    /// Generation finished normally after producing `generated_tokens`.
+    fn on_done(&mut self, _generated_tokens: usize, _elapsed: Duration) {}
+
+    /// Generation failed; no `on_done` follows.
+    fn on_error(&mut self, _error: &anyhow::Error) {}
+}
+
+/// Keeps the probability of the least likely token sampled since it was
+/// last taken, so a streaming callback can shade each chunk of text by the
+/// tokens that produced it (`--show-probs`). Clones share the value: add one
+/// to the generator and read another in the callback.
+#[derive(Clone, Debug, Default)]
+pub struct ProbabilityTap(Arc<Mutex<Option<f32>>>);
+
+impl ProbabilityTap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Lowest probability since the last call, or `None` when no token was
+    /// sampled since.
+    pub fn take(&self) -> Option<f32> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).take()
+    }
+}
+
+impl GenerationHooks for ProbabilityTap {
+    fn on_prefill_start(&mut self, _prompt_tokens: usize) {
+        self.take();
+    }
+
+    fn on_token_logprob(&mut self, _token: u32, logprob: f32) {
+        let mut lowest = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let probability = logprob.exp();
+        *lowest = Some(lowest.map_or(probability, |p| p.min(probability)));
+    }
+
+    fn wants_logprobs(&self) -> bool {
+        true
+    }
+}
+
+/// Heatmap color for a token probability: red when unlikely, through
+/// yellow, to green when the model was confident.
+pub fn probability_rgb(probability: f32) -> (u8, u8, u8) {
+    let p = probability.clamp(0.0, 1.0);
+    let red = (2.0 * (1.0 - p)).min(1.0);
+    let green = (2.0 * p).min(1.0);
+    ((red * 235.0) as u8, (green * 220.0) as u8, 80)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{probability_rgb, GenerationHooks, ProbabilityTap};
+
+    #[test]
+    fn tap_keeps_the_least_likely_token_until_taken() {
+        let tap = ProbabilityTap::new();
+        let mut hook = tap.clone();
+        hook.on_token_logprob(1, 0.9f32.ln());
+        hook.on_token_logprob(2, 0.2f32.ln());
+        assert!((tap.take().unwrap() - 0.2).abs() < 1e-6);
+        assert_eq!(tap.take(), None);
+
+        assert_eq!(probability_rgb(0.0), (235, 0, 80));
+        assert_eq!(probability_rgb(0.5), (235, 220, 80));
+        assert_eq!(probability_rgb(1.0), (0, 220, 80));
+    }
+}
@@ -0,0 +1,85 @@
+//! Vector kernels for CPU-side work outside candle.
+//!
+//! The sampler's argmax scan ([`max_f32`]) is the one kernel here: it runs
+//! over the whole vocabulary for every greedy token. It has a portable
+//! scalar version and, on aarch64, a NEON version chosen at runtime: NEON
+//! is used when the [`get_simd`](super::get_simd) level is `Neon`, so
+//! `--simd scalar` still forces the fallback.
+
+/// Largest value, ignoring NaN like [`f32::max`], or `f32::NEG_INFINITY`
+/// for an empty or all-NaN slice.
+pub fn max_f32(values: &[f32]) -> f32 {
+    #[cfg(target_arch = "aarch64")]
+    if neon::enabled() {
+        return unsafe { neon::max_f32(values) };
+    }
+    scalar::max_f32(values)
+}
+
+/// Reference implementations, also used on targets without NEON.
+mod scalar {
+    pub fn max_f32(values: &[f32]) -> f32 {
+        values.iter().copied().fold(f32::NEG_INFINITY, f32::max)
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use std::arch::aarch64::*;
+
+    use crate::inference::simd_dispatch::{get_simd, SimdLevel};
+
+    #[inline]
+    pub fn enabled() -> bool {
+        get_simd().level == SimdLevel::Neon
+    }
+
+    /// `vmaxnm` returns the number when one operand is NaN, matching
+    /// `f32::max`; `vmax` would return the NaN.
+    #[target_feature(enable = "neon")]
+    pub unsafe fn max_f32(values: &[f32]) -> f32 {
+        let n = values.len();
+        let p = values.as_ptr();
+        let mut acc = vdupq_n_f32(f32::NEG_INFINITY);
+
+        let chunks = n / 4;
+        for i in 0..chunks {
+            acc = vmaxnmq_f32(acc, vld1q_f32(p.add(i * 4)));
+        }
+        let mut max = vmaxnmvq_f32(acc);
+        for &value in &values[chunks * 4..] {
+            max = max.max(value);
+        }
+        max
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{max_f32, scalar};
+
+    fn sample_f32(n: usize, seed: u32) -> Vec<f32> {
+        (0..n)
+            .map(|i| (((i as u32).wrapping_mul(2654435761) ^ seed) % 2000) as f32 / 1000.0 - 1.0)
+            .collect()
+    }
+
+    #[test]
+    fn max_matches_scalar_reference() {
+        // Lengths around the 4-lane boundary exercise the tail.
+        for n in [0, 1, 3, 4, 5, 15, 16, 17, 257] {
+            let a = sample_f32(n, 7);
+            assert_eq!(max_f32(&a), scalar::max_f32(&a));
+        }
+    }
+
+    #[test]
+    fn max_ignores_nan() {
+        let mut values = sample_f32(17, 3);
+        values[2] = f32::NAN;
+        values[16] = f32::NAN;
+        values[9] = 5.0;
+        assert_eq!(max_f32(&values), 5.0);
+        assert_eq!(max_f32(&[f32::NAN; 8]), f32::NEG_INFINITY);
+    }
+}
@@ -0,0 +1,226 @@
+//! Fast heuristic language detection for prompts.
+//!
+//! Non-Latin scripts are identified by Unicode block; Latin-script text is
+//! scored against short stopword lists. The result is an ISO 639-1 code and is
+//! meant for prompt adaptation ("answer in the user's language"), not for
+//! linguistic accuracy on very short or mixed-language input.
+
+/// Minimum letters before a guess is made.
+const MIN_LETTERS: usize = 3;
+/// Minimum stopword hits before a Latin-script guess is made.
+const MIN_STOPWORD_HITS: usize = 1;
+
+const STOPWORDS: &[(&str, &[&str])] = &[
+    (
+        "en",
+        &[
+            "the", "and", "is", "are", "of", "to", "in", "what", "how", "you", "it", "this",
+            "that", "with", "for", "can", "please", "my", "i", "do",
+        ],
+    ),
+    (
+        "es",
+        &[
+            "el", "la", "los", "las", "de", "que", "y", "en", "es", "por", "para", "cómo", "qué",
+            "una", "un", "del", "con", "mi", "puedes", "está",
+        ],
+    ),
+    (
+        "fr",
+        &[
+            "le", "la", "les", "de", "des", "et", "est", "que", "pour", "dans", "un", "une",
+            "vous", "je", "comment", "quoi", "pas", "avec", "ce", "qui",
+        ],
+    ),
+    (
+        "de",
+        &[
+            "der", "die", "das", "und", "ist", "nicht", "ich", "du", "wie", "was", "ein", "eine",
+            "mit", "für", "auf", "zu", "den", "bitte", "kannst", "mir",
+        ],
+    ),
+    (
+        "it",
+        &[
+            "il", "lo", "la", "gli", "di", "che", "e", "è", "per", "non", "come", "cosa", "un",
+            "una", "con", "mi", "puoi", "sono", "della", "questo",
+        ],
+    ),
+    (
+        "pt",
+        &[
+            "o", "a", "os", "as", "de", "que", "e", "é", "para", "com", "não", "um", "uma", "como",
+            "você", "do", "da", "em", "meu", "por",
+        ],
+    ),
+    (
+        "nl",
+        &[
+            "de", "het", "een", "en", "is", "van", "niet", "ik", "je", "wat", "hoe", "dat", "met",
+            "voor", "op", "zijn", "kun", "mij", "dit", "er",
+        ],
+    ),
+];
+
+/// Detects the language of `text`, returning an ISO 639-1 code.
+///
+/// Returns `None` when the text is too short or no language stands out.
+pub fn detect_language(text: &str) -> Option<&'static str> {
+    let mut letters = 0usize;
+    let mut counts = ScriptCounts::default();
+    for c in text.chars().filter(|c| c.is_alphabetic()) {
+        letters += 1;
+        counts.add(c);
+    }
+    if letters < MIN_LETTERS {
+        return None;
+    }
+
+    if let Some(code) = counts.dominant_non_latin(letters) {
+        return Some(code);
+    }
+
+    let lowered = text.to_lowercase();
+    let words: Vec<&str> = lowered
+        .split(|c: char| !c.is_alphabetic())
+        .filter(|w| !w.is_empty())
+        .collect();
+
+    let mut best: Option<(&'static str, usize)> = None;
+    let mut tied = false;
+    for (code, stopwords) in STOPWORDS {
+        let hits = words.iter().filter(|w| stopwords.contains(w)).count();
+        match best {
+            Some((_, top)) if hits == top => tied = true,
+            Some((_, top)) if hits < top => {}
+            _ => {
+                best = Some((code, hits));
+                tied = false;
+            }
+        }
+    }
+
+    match best {
+        Some((code, hits)) if hits >= MIN_STOPWORD_HITS && !tied => Some(code),
+        _ => None,
+    }
+}
+
+/// English name for an ISO 639-1 code, for prompts like "answer in French".
+pub fn language_name(code: &str) -> Option<&'static str> {
+    Some(match code {
+        "en" => "English",
+        "es" => "Spanish",
+        "fr" => "French",
+        "de" => "German",
+        "it" => "Italian",
+        "pt" => "Portuguese",
+        "nl" => "Dutch",
+        "ru" => "Russian",
+        "uk" => "Ukrainian",
+        "el" => "Greek",
+        "ar" => "Arabic",
+        "he" => "Hebrew",
+        "hi" => "Hindi",
+        "th" => "Thai",
+        "zh" => "Chinese",
+        "ja" => "Japanese",
+        "ko" => "Korean",
+        _ => return None,
+    })
+}
+
+#[derive(Default)]
+struct ScriptCounts {
+    cyrillic: usize,
+    ukrainian: usize,
+    greek: usize,
+    arabic: usize,
+    hebrew: usize,
+    devanagari: usize,
+    thai: usize,
+    han: usize,
+    kana: usize,
+    hangul: usize,
+}
+
+impl ScriptCounts {
+    fn add(&mut self, c: char) {
+        match c {
+            'і' | 'ї' | 'є' | 'ґ' | 'І' | 'Ї' | 'Є' | 'Ґ' => {
+                self.cyrillic += 1;
+                self.ukrainian += 1;
+            }
+            '\u{0400}'..='\u{04FF}' => self.cyrillic += 1,
+            '\u{0370}'..='\u{03FF}' => self.greek += 1,
+            '\u{0600}'..='\u{06FF}' => self.arabic += 1,
+            '\u{0590}'..='\u{05FF}' => self.hebrew += 1,
+            '\u{0900}'..='\u{097F}' => self.devanagari += 1,
+            '\u{0E00}'..='\u{0E7F}' => self.thai += 1,
+            '\u{3040}'..='\u{30FF}' => self.kana += 1,
+            '\u{AC00}'..='\u{D7AF}' | '\u{1100}'..='\u{11FF}' => self.hangul += 1,
+            '\u{4E00}'..='\u{9FFF}' | '\u{3400}'..='\u{4DBF}' => self.han += 1,
+            _ => {}
+        }
+    }
+
+    /// Returns a language when a non-Latin script makes up most letters.
+    fn dominant_non_latin(&self, letters: usize) -> Option<&'static str> {
+        let cjk = self.han + self.kana;
+        let candidates = [
+            (self.cyrillic, if self.ukrainian > 0 { "uk" } else { "ru" }),
+            (self.greek, "el"),
+            (self.arabic, "ar"),
+            (self.hebrew, "he"),
+            (self.devanagari, "hi"),
+            (self.thai, "th"),
+            (self.hangul, "ko"),
+            (cjk, if self.kana > 0 { "ja" } else { "zh" }),
+        ];
+        candidates
+            .into_iter()
+            .max_by_key(|(count, _)| *count)
+            .filter(|(count, _)| count * 2 > letters)
+            .map(|(_, code)| code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::detect_language;
+
+    #[test]
+    fn detects_latin_languages_by_stopwords() {
+        assert_eq!(
+            detect_language("What is the capital of France?"),
+            Some("en")
+        );
+        assert_eq!(
+            detect_language("¿Cuál es la capital de España y por qué?"),
+            Some("es")
+        );
+        assert_eq!(
+            detect_language("Kannst du mir bitte helfen? Das ist wichtig."),
+            Some("de")
+        );
+        assert_eq!(
+            detect_language("Comment est-ce que je peux faire pour le faire?"),
+            Some("fr")
+        );
+    }
+
+    #[test]
+    fn detects_non_latin_scripts() {
+        assert_eq!(detect_language("Привет, как дела?"), Some("ru"));
+        assert_eq!(detect_language("Привіт, як справи? Її немає."), Some("uk"));
+        assert_eq!(detect_language("東京はどこですか"), Some("ja"));
+        assert_eq!(detect_language("北京是中国的首都"), Some("zh"));
+        assert_eq!(detect_language("안녕하세요 반갑습니다"), Some("ko"));
+    }
+
+    #[test]
+    fn declines_to_guess_on_short_or_ambiguous_text() {
+        assert_eq!(detect_language("ok"), None);
+        assert_eq!(detect_language("42 + 17"), None);
+    }
+}
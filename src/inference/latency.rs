@@ -0,0 +1,143 @@
+//! Per-token decode latency: exact percentiles for one reply and a bucketed
+//! histogram for aggregating many.
+//!
+//! Steady per-token times with a long p99 tail usually mean page faults or
+//! another process competing for cores; a p50 that creeps up over a reply
+//! points to thermal throttling.
+
+use std::time::Duration;
+
+/// Upper bounds of [`LatencyHistogram`] buckets in milliseconds. Slower
+/// steps land in an overflow bucket.
+pub const LATENCY_BUCKETS_MS: [u64; 12] = [1, 2, 5, 10, 20, 50, 100, 200, 500, 1000, 2000, 5000];
+
+/// Percentiles of a set of decode-step latencies.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LatencySummary {
+    /// Decode steps measured.
+    pub steps: usize,
+    pub mean: Duration,
+    pub p50: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl LatencySummary {
+    /// Exact nearest-rank percentiles of `samples`; `None` when empty.
+    pub fn from_samples(samples: &[Duration]) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+        let mut sorted = samples.to_vec();
+        sorted.sort_unstable();
+        let rank =
+            |q: f64| sorted[((q * sorted.len() as f64).ceil() as usize).clamp(1, sorted.len()) - 1];
+        let total: Duration = sorted.iter().sum();
+        Some(Self {
+            steps: sorted.len(),
+            mean: total / sorted.len() as u32,
+            p50: rank(0.50),
+            p95: rank(0.95),
+            p99: rank(0.99),
+            max: sorted[sorted.len() - 1],
+        })
+    }
+}
+
+/// Decode-step latencies counted into [`LATENCY_BUCKETS_MS`], for
+/// aggregating over many replies in constant memory.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    /// Steps per bucket; the last entry counts steps slower than every bound.
+    counts: [u64; LATENCY_BUCKETS_MS.len() + 1],
+    sum: Duration,
+    max: Duration,
+}
+
+impl LatencyHistogram {
+    pub fn record(&mut self, latency: Duration) {
+        let bucket = LATENCY_BUCKETS_MS
+            .iter()
+            .position(|&bound| latency <= Duration::from_millis(bound))
+            .unwrap_or(LATENCY_BUCKETS_MS.len());
+        self.counts[bucket] += 1;
+        self.sum += latency;
+        self.max = self.max.max(latency);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    pub fn sum(&self) -> Duration {
+        self.sum
+    }
+
+    /// Steps at or under each bound of [`LATENCY_BUCKETS_MS`], cumulative as
+    /// Prometheus histograms expect.
+    pub fn cumulative_counts(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        LATENCY_BUCKETS_MS
+            .iter()
+            .zip(self.counts.iter().scan(0, |total, &count| {
+                *total += count;
+                Some(*total)
+            }))
+            .map(|(&bound, total)| (bound, total))
+    }
+
+    /// The upper bound of the bucket holding quantile `q` (0.0 to 1.0),
+    /// capped at the slowest step seen; zero when empty.
+    pub fn quantile(&self, q: f64) -> Duration {
+        let count = self.count();
+        if count == 0 {
+            return Duration::ZERO;
+        }
+        let rank = ((q * count as f64).ceil() as u64).clamp(1, count);
+        let mut seen = 0;
+        for (bucket, &n) in self.counts.iter().enumerate() {
+            seen += n;
+            if seen >= rank {
+                return match LATENCY_BUCKETS_MS.get(bucket) {
+                    Some(&bound) => Duration::from_millis(bound).min(self.max),
+                    None => self.max,
+                };
+            }
+        }
+        self.max
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{LatencyHistogram, LatencySummary};
+
+    #[test]
+    fn summarizes_and_buckets_step_latencies() {
+        let ms = Duration::from_millis;
+        let mut samples: Vec<Duration> = (1..=100).map(ms).collect();
+        samples.reverse();
+        let summary = LatencySummary::from_samples(&samples).unwrap();
+        assert_eq!(summary.steps, 100);
+        assert_eq!(summary.p50, ms(50));
+        assert_eq!(summary.p95, ms(95));
+        assert_eq!(summary.p99, ms(99));
+        assert_eq!(summary.max, ms(100));
+        assert_eq!(summary.mean, Duration::from_micros(50_500));
+        assert!(LatencySummary::from_samples(&[]).is_none());
+
+        let mut histogram = LatencyHistogram::default();
+        assert_eq!(histogram.quantile(0.5), Duration::ZERO);
+        for latency in [ms(3), ms(4), ms(15), ms(7000)] {
+            histogram.record(latency);
+        }
+        assert_eq!(histogram.count(), 4);
+        assert_eq!(histogram.quantile(0.5), ms(5));
+        assert_eq!(histogram.quantile(0.75), ms(20));
+        assert_eq!(histogram.quantile(0.99), ms(7000));
+        let cumulative: Vec<(u64, u64)> = histogram.cumulative_counts().take(5).collect();
+        assert_eq!(cumulative, [(1, 0), (2, 0), (5, 2), (10, 2), (20, 3)]);
+    }
+}
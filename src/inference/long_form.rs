@@ -0,0 +1,220 @@
+//! Documents longer than the context window, written in two passes.
+//!
+//! The model first writes an outline of section titles, then each section
+//! in turn. A section's prompt holds the request, the outline and a short
+//! summary of every section before it rather than their full text, so the
+//! prompt stays small however long the document grows while each section
+//! still knows what has been covered.
+
+use anyhow::Result;
+
+use crate::inference::{Generator, StreamEvent};
+
+/// Tokens a section aims for; the target length decides how many there are.
+const SECTION_TOKENS: usize = 500;
+const MIN_SECTIONS: usize = 2;
+const MAX_SECTIONS: usize = 16;
+const OUTLINE_MAX_TOKENS: usize = 384;
+const SECTION_SUMMARY_MAX_TOKENS: usize = 96;
+
+#[derive(Clone, Debug)]
+pub struct LongFormOptions {
+    /// Approximate length of the whole document in tokens.
+    pub target_tokens: usize,
+    pub repeat_penalty: f32,
+    pub repeat_last_n: usize,
+}
+
+impl Default for LongFormOptions {
+    fn default() -> Self {
+        Self {
+            target_tokens: 4000,
+            repeat_penalty: 1.1,
+            repeat_last_n: 64,
+        }
+    }
+}
+
+/// Progress reported while writing.
+pub enum LongFormEvent {
+    /// The outline was written; these are its section titles.
+    Outline(Vec<String>),
+    /// A section started; its heading is streamed as tokens next.
+    SectionStarted {
+        index: usize,
+        total: usize,
+        title: String,
+    },
+    /// A token of the document (outline and summaries are not streamed).
+    Token(String),
+}
+
+/// Writes a document for `prompt` of about `options.target_tokens` tokens,
+/// as Markdown with one `##` heading per section. The generator's
+/// conversation history is cleared before every step.
+pub fn write_long<F>(
+    generator: &mut Generator,
+    prompt: &str,
+    options: &LongFormOptions,
+    mut progress: F,
+) -> Result<String>
+where
+    F: FnMut(LongFormEvent),
+{
+    let sections = (options.target_tokens / SECTION_TOKENS).clamp(MIN_SECTIONS, MAX_SECTIONS);
+    generator.clear_history();
+    let outline_text = generator.generate(
+        &outline_prompt(prompt, sections),
+        OUTLINE_MAX_TOKENS,
+        options.repeat_penalty,
+        options.repeat_last_n,
+        |_| {},
+    )?;
+    generator.clear_history();
+
+    let mut outline = parse_outline(&outline_text);
+    outline.truncate(MAX_SECTIONS);
+    if outline.len() < MIN_SECTIONS {
+        anyhow::bail!(
+            "The model did not write an outline with at least {} sections",
+            MIN_SECTIONS
+        );
+    }
+    progress(LongFormEvent::Outline(outline.clone()));
+
+    // Each section's prompt holds at most the outline and the summaries.
+    let section_tokens = (options.target_tokens / outline.len())
+        .max(64)
+        .min(generator.context_limit() / 2);
+    let mut summaries = Vec::with_capacity(outline.len());
+    let mut document = String::new();
+    for (index, title) in outline.iter().enumerate() {
+        progress(LongFormEvent::SectionStarted {
+            index,
+            total: outline.len(),
+            title: title.clone(),
+        });
+        let heading = format!("{}## {}\n\n", if index == 0 { "" } else { "\n\n" }, title);
+        document.push_str(&heading);
+        progress(LongFormEvent::Token(heading));
+
+        let section = generator.generate(
+            &section_prompt(prompt, &outline, index, &summaries, section_tokens),
+            section_tokens,
+            options.repeat_penalty,
+            options.repeat_last_n,
+            |event| {
+                if let StreamEvent::Token(token) = event {
+                    progress(LongFormEvent::Token(token));
+                }
+            },
+        )?;
+        generator.clear_history();
+        let section = section.trim();
+        document.push_str(section);
+
+        if index + 1 < outline.len() {
+            let summary = generator.generate(
+                &summary_prompt(title, section),
+                SECTION_SUMMARY_MAX_TOKENS,
+                options.repeat_penalty,
+                options.repeat_last_n,
+                |_| {},
+            )?;
+            generator.clear_history();
+            summaries.push(summary.trim().replace('\n', " "));
+        }
+    }
+    Ok(document)
+}
+
+fn outline_prompt(prompt: &str, sections: usize) -> String {
+    format!(
+        "Plan a document for the request below as an outline of about {} \
+         section titles. Reply with the titles only, one per line, in order, \
+         without descriptions.\n\nRequest: {}",
+        sections, prompt
+    )
+}
+
+fn section_prompt(
+    prompt: &str,
+    outline: &[String],
+    index: usize,
+    summaries: &[String],
+    section_tokens: usize,
+) -> String {
+    let mut text = format!(
+        "You are writing a document for this request: {}\n\nOutline:\n",
+        prompt
+    );
+    for (i, title) in outline.iter().enumerate() {
+        text.push_str(&format!("{}. {}\n", i + 1, title));
+    }
+    if !summaries.is_empty() {
+        text.push_str("\nSections written so far:\n");
+        for (title, summary) in outline.iter().zip(summaries) {
+            text.push_str(&format!("- {}: {}\n", title, summary));
+        }
+    }
+    text.push_str(&format!(
+        "\nWrite section {}, \"{}\", in about {} words. Continue from the \
+         sections so far without repeating them, and do not start later \
+         sections. Write the body only, without the section heading.",
+        index + 1,
+        outline[index],
+        section_tokens * 3 / 4
+    ));
+    text
+}
+
+fn summary_prompt(title: &str, section: &str) -> String {
+    format!(
+        "Summarize this section, \"{}\", in one or two sentences, keeping \
+         names, facts and anything later sections must stay consistent with.\n\n{}",
+        title, section
+    )
+}
+
+/// Section titles from an outline reply, without numbering, bullets,
+/// heading marks or emphasis. Blank lines and a leading "Outline" line
+/// are skipped.
+fn parse_outline(text: &str) -> Vec<String> {
+    text.lines()
+        .map(|line| {
+            let line = line
+                .trim()
+                .trim_start_matches(['#', '-', '*', '•', ' '])
+                .trim_start_matches(|c: char| c.is_ascii_digit())
+                .trim_start_matches(['.', ')', ':', ' ']);
+            line.trim_matches(['*', '_', ' '])
+                .trim_end_matches(':')
+                .trim()
+        })
+        .filter(|line| !line.is_empty() && !line.eq_ignore_ascii_case("outline"))
+        .map(String::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_outline, section_prompt};
+
+    #[test]
+    fn parses_outlines_and_builds_section_prompts() {
+        let outline = parse_outline(
+            "Outline:\n\n1. **Introduction**\n2) Ownership\n- Borrowing:\n## 4. Lifetimes\n",
+        );
+        assert_eq!(
+            outline,
+            ["Introduction", "Ownership", "Borrowing", "Lifetimes"]
+        );
+
+        let summaries = vec!["Why Rust exists.".to_string()];
+        let prompt = section_prompt("A Rust guide", &outline, 1, &summaries, 400);
+        assert!(prompt.contains("2. Ownership\n"));
+        assert!(prompt.contains("- Introduction: Why Rust exists.\n"));
+        assert!(prompt.contains("Write section 2, \"Ownership\", in about 300 words."));
+        assert!(!prompt.contains("- Ownership:"));
+    }
+}
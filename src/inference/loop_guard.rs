@@ -0,0 +1,171 @@
+//! Watchdog for replies stuck repeating themselves.
+//!
+//! Small quantized models occasionally fall into a short cycle, such as
+//! `\n\n\n…` or the same phrase over and over, that the repeat penalty does
+//! not break, and then run until `max_tokens`. [`LoopGuard`] checks the end
+//! of the reply after every token and either ends it or raises the
+//! temperature once to shake the model out of the cycle.
+
+use std::fmt;
+use std::str::FromStr;
+
+use candle_transformers::generation::Sampling;
+
+/// Temperature added by `bump` without a value.
+pub const DEFAULT_BUMP: f64 = 0.5;
+
+/// What the generator does when the reply is caught in a loop.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LoopAction {
+    /// Never check.
+    Off,
+    /// End the reply; [`Generator::loop_detected`] reports it.
+    ///
+    /// [`Generator::loop_detected`]: crate::inference::Generator::loop_detected
+    #[default]
+    Stop,
+    /// Add this much to the temperature for the rest of the reply. A reply
+    /// that loops again after the bump is ended as with `Stop`.
+    Bump(f64),
+}
+
+impl fmt::Display for LoopAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoopAction::Off => f.write_str("off"),
+            LoopAction::Stop => f.write_str("stop"),
+            LoopAction::Bump(delta) => write!(f, "bump={}", delta),
+        }
+    }
+}
+
+impl FromStr for LoopAction {
+    type Err = String;
+
+    /// `off`, `stop`, `bump` or `bump=<temperature increase>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.to_ascii_lowercase();
+        match s.split_once('=') {
+            None if s == "off" => Ok(LoopAction::Off),
+            None if s == "stop" => Ok(LoopAction::Stop),
+            None if s == "bump" => Ok(LoopAction::Bump(DEFAULT_BUMP)),
+            Some(("bump", delta)) => match delta.parse::<f64>() {
+                Ok(delta) if delta > 0.0 && delta.is_finite() => Ok(LoopAction::Bump(delta)),
+                _ => Err(format!(
+                    "invalid temperature increase '{}' (expected a positive number)",
+                    delta
+                )),
+            },
+            _ => Err(format!(
+                "unknown loop action '{}' (expected off, stop, bump or bump=<delta>)",
+                s
+            )),
+        }
+    }
+}
+
+/// When a reply counts as looping and what happens then.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LoopGuard {
+    /// Generated tokens that must all follow the cycle; `0` disables the
+    /// guard.
+    pub window: usize,
+    /// Longest cycle, in tokens, that is detected.
+    pub max_period: usize,
+    pub action: LoopAction,
+}
+
+impl LoopGuard {
+    /// Guard with the default window and period.
+    pub fn new(action: LoopAction) -> Self {
+        Self {
+            action,
+            ..Self::default()
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.action != LoopAction::Off && self.window > 0
+    }
+
+    /// Length of the cycle the last `window` of `tokens` repeat, if any.
+    pub fn detect(&self, tokens: &[u32]) -> Option<usize> {
+        if !self.is_active() || tokens.len() < self.window {
+            return None;
+        }
+        let tail = &tokens[tokens.len() - self.window..];
+        // A cycle has to come round at least twice to count.
+        let longest = self.max_period.min(self.window / 2);
+        (1..=longest).find(|&period| (period..tail.len()).all(|i| tail[i] == tail[i - period]))
+    }
+}
+
+/// `sampling` with its temperature raised by `delta`; greedy decoding
+/// becomes sampling at temperature `delta`.
+pub(crate) fn bumped(sampling: &Sampling, delta: f64) -> Sampling {
+    match *sampling {
+        Sampling::ArgMax => Sampling::All { temperature: delta },
+        Sampling::All { temperature } => Sampling::All {
+            temperature: temperature + delta,
+        },
+        Sampling::TopK { k, temperature } => Sampling::TopK {
+            k,
+            temperature: temperature + delta,
+        },
+        Sampling::TopP { p, temperature } => Sampling::TopP {
+            p,
+            temperature: temperature + delta,
+        },
+        Sampling::TopKThenTopP { k, p, temperature } => Sampling::TopKThenTopP {
+            k,
+            p,
+            temperature: temperature + delta,
+        },
+        Sampling::GumbelSoftmax { temperature } => Sampling::GumbelSoftmax {
+            temperature: temperature + delta,
+        },
+    }
+}
+
+impl Default for LoopGuard {
+    fn default() -> Self {
+        Self {
+            window: 64,
+            max_period: 16,
+            action: LoopAction::Stop,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LoopAction, LoopGuard};
+
+    #[test]
+    fn detects_short_cycles_filling_the_window() {
+        let guard = LoopGuard {
+            window: 12,
+            max_period: 4,
+            action: LoopAction::Stop,
+        };
+        let mut tokens: Vec<u32> = (0..20).collect();
+        assert_eq!(guard.detect(&tokens), None);
+        tokens.extend([13; 11]);
+        assert_eq!(guard.detect(&tokens), None);
+        tokens.push(13);
+        assert_eq!(guard.detect(&tokens), Some(1));
+
+        let phrase: Vec<u32> = [7, 8, 9].repeat(4);
+        assert_eq!(guard.detect(&phrase), Some(3));
+        // Longer than max_period.
+        let long: Vec<u32> = [1, 2, 3, 4, 5, 6].repeat(2);
+        assert_eq!(guard.detect(&long), None);
+        let off = LoopGuard::new(LoopAction::Off);
+        assert_eq!(off.detect(&[0; 100]), None);
+
+        assert_eq!("stop".parse(), Ok(LoopAction::Stop));
+        assert_eq!("bump=0.8".parse(), Ok(LoopAction::Bump(0.8)));
+        assert!("bump=-1".parse::<LoopAction>().is_err());
+        assert!("retry".parse::<LoopAction>().is_err());
+    }
+}
@@ -0,0 +1,543 @@
+//! Line-by-line transformation of stdin for `--map`.
+//!
+//! Every input line fills the `{input}` placeholder of a prompt template,
+//! and every result is written back as exactly one line, so the output
+//! lines up with the input. In JSON Lines mode the template is filled from
+//! one field of each object and the object is printed back with the result
+//! added.
+//!
+//! With `--map-output` results go to a file instead, and a
+//! [`MapCheckpoint`] beside it records how much of the input is done after
+//! every batch, so `--resume` can continue an interrupted run.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+
+const CHECKPOINT_VERSION: u32 = 1;
+
+/// Replaced by each input in `--map` templates.
+pub const INPUT_PLACEHOLDER: &str = "{input}";
+
+/// Fills `template` with `input`. Templates without the placeholder get the
+/// input appended after a blank line.
+pub fn map_prompt(template: &str, input: &str) -> String {
+    if template.contains(INPUT_PLACEHOLDER) {
+        template.replace(INPUT_PLACEHOLDER, input)
+    } else {
+        format!("{}\n\n{}", template, input)
+    }
+}
+
+/// Highest `temperature` a record may ask for.
+const MAX_TEMPERATURE: f64 = 2.0;
+
+/// Generation settings a JSON Lines record sets for itself, overriding the
+/// command line: `max_tokens`, `temperature`, `system` and `stop`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MapOverrides {
+    pub max_tokens: Option<usize>,
+    pub temperature: Option<f64>,
+    pub system: Option<String>,
+    /// Text that ends the reply; a string or an array of strings.
+    pub stop: Vec<String>,
+}
+
+impl MapOverrides {
+    fn parse(record: &Map<String, Value>) -> Result<Self> {
+        let max_tokens = match record.get("max_tokens") {
+            None | Some(Value::Null) => None,
+            Some(value) => match value.as_u64() {
+                Some(n) if n > 0 => Some(n as usize),
+                _ => anyhow::bail!("max_tokens must be a positive integer, got {}", value),
+            },
+        };
+        let temperature = match record.get("temperature") {
+            None | Some(Value::Null) => None,
+            Some(value) => match value.as_f64() {
+                Some(t) if (0.0..=MAX_TEMPERATURE).contains(&t) => Some(t),
+                _ => anyhow::bail!(
+                    "temperature must be a number from 0 to {}, got {}",
+                    MAX_TEMPERATURE,
+                    value
+                ),
+            },
+        };
+        let system = match record.get("system") {
+            None | Some(Value::Null) => None,
+            Some(Value::String(text)) => Some(text.clone()),
+            Some(value) => anyhow::bail!("system must be a string, got {}", value),
+        };
+        let stop = match record.get("stop") {
+            None | Some(Value::Null) => Vec::new(),
+            Some(Value::String(text)) => vec![text.clone()],
+            Some(Value::Array(items)) => items
+                .iter()
+                .map(|item| match item {
+                    Value::String(text) => Ok(text.clone()),
+                    _ => anyhow::bail!("stop must hold only strings, got {}", item),
+                })
+                .collect::<Result<_>>()?,
+            Some(value) => anyhow::bail!(
+                "stop must be a string or an array of strings, got {}",
+                value
+            ),
+        };
+        Ok(Self {
+            max_tokens,
+            temperature,
+            system,
+            stop,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// One line of `--map` input.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MapLine {
+    input: String,
+    /// The parsed object in JSON Lines mode.
+    record: Option<Map<String, Value>>,
+    overrides: MapOverrides,
+}
+
+impl MapLine {
+    /// Reads `line` as text, or with `field` as a JSON object whose `field`
+    /// is the input. Non-string fields are used as their JSON text. An
+    /// object may also carry [`MapOverrides`].
+    pub fn parse(line: &str, field: Option<&str>) -> Result<Self> {
+        let Some(field) = field.filter(|_| !line.trim().is_empty()) else {
+            return Ok(Self {
+                input: line.to_string(),
+                record: None,
+                overrides: MapOverrides::default(),
+            });
+        };
+        let record: Map<String, Value> =
+            serde_json::from_str(line).context("Expected a JSON object")?;
+        let input = match record.get(field) {
+            Some(Value::String(text)) => text.clone(),
+            Some(value) => value.to_string(),
+            None => anyhow::bail!("Missing field '{}'", field),
+        };
+        let overrides = MapOverrides::parse(&record)?;
+        Ok(Self {
+            input,
+            record: Some(record),
+            overrides,
+        })
+    }
+
+    pub fn input(&self) -> &str {
+        &self.input
+    }
+
+    pub fn overrides(&self) -> &MapOverrides {
+        &self.overrides
+    }
+
+    /// Blank lines are passed through without generating.
+    pub fn is_blank(&self) -> bool {
+        self.record.is_none() && self.input.trim().is_empty()
+    }
+
+    /// The output line for `result`: the object with `result` stored in
+    /// `output_field`, or the result with its lines joined by spaces.
+    pub fn render(&self, result: &str, output_field: &str) -> String {
+        match &self.record {
+            Some(record) => {
+                let mut record = record.clone();
+                record.insert(output_field.to_string(), result.trim().into());
+                Value::Object(record).to_string()
+            }
+            None => result
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .collect::<Vec<_>>()
+                .join(" "),
+        }
+    }
+}
+
+/// Invalid records [`check_records`] lists before summarizing the rest.
+const MAX_REPORTED_ERRORS: usize = 10;
+
+/// Parses every line of JSON Lines input with `field`, so a bad record
+/// fails the run before anything is generated. The error lists the invalid
+/// lines by number.
+pub fn check_records(lines: &[String], field: &str) -> Result<()> {
+    let errors: Vec<String> = lines
+        .iter()
+        .enumerate()
+        .filter_map(|(i, line)| {
+            MapLine::parse(line, Some(field))
+                .err()
+                .map(|e| format!("line {}: {:#}", i + 1, e))
+        })
+        .collect();
+    if errors.is_empty() {
+        return Ok(());
+    }
+    let mut report = errors[..errors.len().min(MAX_REPORTED_ERRORS)].join("\n  ");
+    if errors.len() > MAX_REPORTED_ERRORS {
+        report.push_str(&format!(
+            "\n  ... and {} more",
+            errors.len() - MAX_REPORTED_ERRORS
+        ));
+    }
+    anyhow::bail!(
+        "Invalid input ({} of {} lines):\n  {}",
+        errors.len(),
+        lines.len(),
+        report
+    )
+}
+
+/// Progress of a `--map-output` run, saved as `<output>.checkpoint`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MapCheckpoint {
+    pub version: u32,
+    /// Resuming with another template or field would mix two kinds of
+    /// results in one file.
+    pub template: String,
+    pub field: Option<String>,
+    /// Input lines whose results are in the output.
+    pub lines: usize,
+    /// Length of the output holding those results. Anything after it is a
+    /// partly written batch and is cut off on resume.
+    pub output_bytes: u64,
+    /// SHA-256 of those input lines, to catch resuming with other input.
+    pub input_sha256: String,
+}
+
+impl MapCheckpoint {
+    /// The checkpoint file of `output`.
+    pub fn path_for(output: &Path) -> PathBuf {
+        let mut path = output.as_os_str().to_owned();
+        path.push(".checkpoint");
+        PathBuf::from(path)
+    }
+
+    /// Reads the checkpoint at `path`, or `None` if there is none.
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        let checkpoint: Self = serde_json::from_str(&text)
+            .with_context(|| format!("{}: invalid checkpoint", path.display()))?;
+        if checkpoint.version > CHECKPOINT_VERSION {
+            anyhow::bail!(
+                "Checkpoint {} has version {}, newer than the supported {}",
+                path.display(),
+                checkpoint.version,
+                CHECKPOINT_VERSION
+            );
+        }
+        Ok(Some(checkpoint))
+    }
+
+    /// Writes the checkpoint to `path` through a temporary file, so a crash
+    /// leaves the previous checkpoint rather than half of this one.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        std::fs::write(&tmp, serde_json::to_string(self)?)
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, path).with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+/// Where `--map` results go: stdout, or a file that is checkpointed after
+/// every batch and can be resumed.
+pub struct MapOutput {
+    target: MapTarget,
+    template: String,
+    field: Option<String>,
+    /// Input lines read so far, and their digest.
+    lines: usize,
+    digest: Sha256,
+    /// The checkpoint being resumed, until its lines have been skipped.
+    resuming: Option<MapCheckpoint>,
+}
+
+enum MapTarget {
+    Stdout(io::Stdout),
+    File { file: File, checkpoint: PathBuf },
+}
+
+impl MapOutput {
+    pub fn stdout(template: &str, field: Option<&str>) -> Self {
+        Self::new(MapTarget::Stdout(io::stdout()), template, field)
+    }
+
+    /// Writes to `path`. With `resume` and a checkpoint from a run with
+    /// the same template and field, keeps the results it covers and skips
+    /// their input lines; otherwise starts the file over.
+    pub fn file(path: &Path, template: &str, field: Option<&str>, resume: bool) -> Result<Self> {
+        let checkpoint_path = MapCheckpoint::path_for(path);
+        let checkpoint = match resume {
+            true => MapCheckpoint::load(&checkpoint_path)?,
+            false => None,
+        };
+        let Some(checkpoint) = checkpoint else {
+            if resume {
+                tracing::info!(
+                    "No checkpoint at {}; starting over",
+                    checkpoint_path.display()
+                );
+            }
+            let file = File::create(path)
+                .with_context(|| format!("Failed to create {}", path.display()))?;
+            let target = MapTarget::File {
+                file,
+                checkpoint: checkpoint_path,
+            };
+            let mut output = Self::new(target, template, field);
+            output.checkpoint()?;
+            return Ok(output);
+        };
+
+        if checkpoint.template != template || checkpoint.field.as_deref() != field {
+            anyhow::bail!(
+                "{} was written with a different template or --map-field; \
+                 remove it or run without --resume",
+                path.display()
+            );
+        }
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        let len = file.metadata()?.len();
+        if len < checkpoint.output_bytes {
+            anyhow::bail!(
+                "{} is shorter than its checkpoint says; run without --resume",
+                path.display()
+            );
+        }
+        file.set_len(checkpoint.output_bytes)?;
+        file.seek(SeekFrom::End(0))?;
+        let target = MapTarget::File {
+            file,
+            checkpoint: checkpoint_path,
+        };
+        let mut output = Self::new(target, template, field);
+        output.resuming = (checkpoint.lines > 0).then_some(checkpoint);
+        Ok(output)
+    }
+
+    fn new(target: MapTarget, template: &str, field: Option<&str>) -> Self {
+        Self {
+            target,
+            template: template.to_string(),
+            field: field.map(str::to_string),
+            lines: 0,
+            digest: Sha256::new(),
+            resuming: None,
+        }
+    }
+
+    /// Input lines the run being resumed had already finished.
+    pub fn resumed_lines(&self) -> usize {
+        self.resuming.as_ref().map_or(0, |c| c.lines)
+    }
+
+    /// Notes that `line` was read from the input. Returns `false` when its
+    /// result is already in the output from the run being resumed.
+    pub fn input(&mut self, line: &str) -> Result<bool> {
+        self.lines += 1;
+        self.digest.update(line.as_bytes());
+        self.digest.update(b"\n");
+        let Some(resumed) = &self.resuming else {
+            return Ok(true);
+        };
+        if self.lines < resumed.lines {
+            return Ok(false);
+        }
+        if self.input_sha256() != resumed.input_sha256 {
+            anyhow::bail!(
+                "The input differs from the run being resumed in its first {} lines",
+                resumed.lines
+            );
+        }
+        self.resuming = None;
+        Ok(false)
+    }
+
+    /// Flushes the results written so far and, for a file, records that
+    /// every input line read so far is done. Call between batches.
+    pub fn checkpoint(&mut self) -> Result<()> {
+        self.flush()?;
+        let input_sha256 = self.input_sha256();
+        let MapTarget::File { file, checkpoint } = &mut self.target else {
+            return Ok(());
+        };
+        MapCheckpoint {
+            version: CHECKPOINT_VERSION,
+            template: self.template.clone(),
+            field: self.field.clone(),
+            lines: self.lines,
+            output_bytes: file.stream_position()?,
+            input_sha256,
+        }
+        .save(checkpoint)
+    }
+
+    /// Fails if the input ended before the lines of the resumed run did.
+    pub fn finish(mut self) -> Result<()> {
+        if let Some(resumed) = &self.resuming {
+            anyhow::bail!(
+                "The input has {} lines, fewer than the {} the resumed run finished",
+                self.lines,
+                resumed.lines
+            );
+        }
+        self.checkpoint()
+    }
+
+    fn input_sha256(&self) -> String {
+        format!("{:x}", self.digest.clone().finalize())
+    }
+}
+
+impl Write for MapOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.target {
+            MapTarget::Stdout(out) => out.write(buf),
+            MapTarget::File { file, .. } => file.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.target {
+            MapTarget::Stdout(out) => out.flush(),
+            MapTarget::File { file, .. } => file.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::{check_records, map_prompt, MapCheckpoint, MapLine, MapOutput, MapOverrides};
+
+    #[test]
+    fn fills_templates_and_renders_one_line_per_input() {
+        assert_eq!(
+            map_prompt("Translate to French: {input}", "hello"),
+            "Translate to French: hello"
+        );
+        assert_eq!(map_prompt("Summarize:", "text"), "Summarize:\n\ntext");
+
+        let line = MapLine::parse("hello", None).unwrap();
+        assert_eq!(line.input(), "hello");
+        assert_eq!(
+            line.render("  Bonjour\n\nle monde \n", "output"),
+            "Bonjour le monde"
+        );
+        assert!(MapLine::parse("  ", None).unwrap().is_blank());
+
+        let line = MapLine::parse(r#"{"id":7,"text":"hi"}"#, Some("text")).unwrap();
+        assert_eq!(line.input(), "hi");
+        assert_eq!(
+            line.render(" salut\n", "fr"),
+            r#"{"fr":"salut","id":7,"text":"hi"}"#
+        );
+        let number = MapLine::parse(r#"{"n":42}"#, Some("n")).unwrap();
+        assert_eq!(number.input(), "42");
+        assert!(MapLine::parse(r#"{"id":7}"#, Some("text")).is_err());
+        assert!(MapLine::parse("not json", Some("text")).is_err());
+        assert!(MapLine::parse("", Some("text")).unwrap().is_blank());
+        assert!(line.overrides().is_empty());
+
+        let line = MapLine::parse(
+            r#"{"text":"hi","max_tokens":16,"temperature":0,"system":"Be brief.","stop":["\n"]}"#,
+            Some("text"),
+        )
+        .unwrap();
+        assert_eq!(
+            line.overrides(),
+            &MapOverrides {
+                max_tokens: Some(16),
+                temperature: Some(0.0),
+                system: Some("Be brief.".to_string()),
+                stop: vec!["\n".to_string()],
+            }
+        );
+        let stop = MapLine::parse(r#"{"text":"hi","stop":"END"}"#, Some("text")).unwrap();
+        assert_eq!(stop.overrides().stop, ["END"]);
+
+        let lines: Vec<String> = [
+            r#"{"text":"ok"}"#,
+            r#"{"text":"hot","temperature":3}"#,
+            "",
+            r#"{"text":"neg","max_tokens":-1}"#,
+            r#"{"text":"bad","stop":[1]}"#,
+        ]
+        .iter()
+        .map(|l| l.to_string())
+        .collect();
+        let err = check_records(&lines, "text").unwrap_err().to_string();
+        assert!(err.starts_with("Invalid input (3 of 5 lines)"));
+        assert!(err.contains("line 2: temperature"));
+        assert!(err.contains("line 4: max_tokens"));
+        assert!(err.contains("line 5: stop"));
+        assert!(check_records(&lines[..1], "text").is_ok());
+    }
+
+    #[test]
+    fn resumes_an_interrupted_run_from_its_checkpoint() {
+        let dir = std::env::temp_dir().join(format!("oxide-map-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("out.txt");
+        let template = "Shout: {input}";
+
+        let mut out = MapOutput::file(&path, template, None, false).unwrap();
+        for line in ["a", "b"] {
+            assert!(out.input(line).unwrap());
+            writeln!(out, "{}!", line).unwrap();
+        }
+        out.checkpoint().unwrap();
+        // Interrupted halfway through the next batch.
+        assert!(out.input("c").unwrap());
+        write!(out, "c").unwrap();
+        drop(out);
+
+        assert!(MapOutput::file(&path, "Whisper: {input}", None, true).is_err());
+        let mut out = MapOutput::file(&path, template, None, true).unwrap();
+        assert_eq!(out.resumed_lines(), 2);
+        assert!(!out.input("x").unwrap());
+        assert!(out.input("b").is_err());
+
+        let mut out = MapOutput::file(&path, template, None, true).unwrap();
+        assert!(!out.input("a").unwrap());
+        assert!(!out.input("b").unwrap());
+        assert!(out.input("c").unwrap());
+        writeln!(out, "c!").unwrap();
+        out.finish().unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "a!\nb!\nc!\n");
+        let checkpoint = MapCheckpoint::load(&MapCheckpoint::path_for(&path))
+            .unwrap()
+            .unwrap();
+        assert_eq!((checkpoint.lines, checkpoint.output_bytes), (3, 9));
+
+        let out = MapOutput::file(&path, template, None, true).unwrap();
+        assert!(out.finish().is_err());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
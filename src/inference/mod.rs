@@ -1,14 +1,89 @@
+pub mod agents;
+pub mod cancel;
+pub mod choice;
+pub mod code_index;
+pub mod confidence;
+pub mod context;
+pub mod dataset;
+pub mod document;
+pub mod commit;
 pub mod dynamic_batcher;
+pub mod eval;
+pub mod extract;
+pub mod fuzz;
 pub mod generator;
+pub mod granularity;
+pub mod hooks;
+pub mod kernels;
+pub mod language;
+pub mod latency;
+pub mod long_form;
+pub mod loop_guard;
+pub mod map;
+pub mod moderation;
+pub mod needle;
+pub mod notes;
+pub mod numerics;
 pub mod paged_cache;
 pub mod prefix_cache;
+pub mod preset;
+pub mod preview;
+pub mod redact;
+pub mod replay;
+pub mod sampler;
+pub mod scratch;
+pub mod session;
+pub mod stop;
 pub mod simd_dispatch;
+pub mod structured;
+pub mod summarize;
+pub mod tee;
 pub mod thread_pinner;
 pub mod tiled_attention;
+pub mod verify;
+pub mod warmup;
 
-pub use dynamic_batcher::{BatchConfig, BatchResult, BatchRequest, DynamicBatcher, DynamicBatcherHandle};
-pub use generator::{ChatTemplate, Generator, Message, StreamEvent};
+pub use cancel::CancelToken;
+pub use choice::{classify_prompt, Choice};
+pub use code_index::{citations, code_prompt, Citation, CodeChunk, CodeIndex};
+pub use confidence::Confidence;
+pub use context::{ContextOverflow, ContextPolicy};
+pub use dataset::{DatasetHook, DatasetRecord, DatasetWriter};
+pub use document::{DocumentDigest, DocumentEvent, DocumentOptions, DocumentSection};
+pub use dynamic_batcher::{AdaptiveWindow, BatchConfig, BatchEvent, BatchingPolicy, BatchResult, BatchRequest, BatchTrigger, RequestClass, DynamicBatcher, DynamicBatcherHandle, FixedWindow};
+pub use extract::{extract_text, read_document_text, FileFormat};
+pub use generator::{
+    load_examples, ChatTemplate, GenerationError, Generator, Message, StreamEvent,
+    TruncationStrategy,
+};
+pub use granularity::{StreamChunker, StreamGranularity};
+pub use hooks::{probability_rgb, GenerationHooks, ProbabilityTap};
+pub use language::detect_language;
+pub use latency::{LatencyHistogram, LatencySummary};
+pub use long_form::{write_long, LongFormEvent, LongFormOptions};
+pub use loop_guard::{LoopAction, LoopGuard};
+pub use map::{check_records, map_prompt, MapCheckpoint, MapLine, MapOutput, MapOverrides};
+pub use moderation::{
+    CategoryScore, ModerationAction, ModerationCategory, ModerationConfig, ModerationResult,
+    ModerationStream,
+};
+pub use notes::{Note, NoteStore};
+pub use numerics::NonFiniteLogits;
 pub use paged_cache::{PagedAttentionConfig, PagedKvCache};
 pub use prefix_cache::{PrefixCache, PrefixCacheConfig};
+pub use preset::{PresetSampling, SamplingPreset};
+pub use preview::{load_messages, render_template, PromptPreview};
+pub use redact::{RedactionConfig, RedactionRule, Redactor};
+pub use replay::{RecordedEvent, Recorder, Recording, RecordingHeader};
+pub use sampler::{
+    EosControl, LogitBias, LogitsChain, LogitsTransform, SamplerState, TokenCandidate,
+    TokenLogprob, TokenSampler, TopLogprob, TopNSigma, TransformContext, TransformStage,
+};
+pub use session::{ModelFingerprint, Session, SessionParams};
+pub use stop::{StopCondition, StopContext};
+pub use structured::ResponseFormat;
+pub use tee::{FlushPolicy, Tee};
 pub use simd_dispatch::{CpuFeature, CpuFeatures, SimdLevel, SimdDispatch, init_simd, get_simd};
 pub use thread_pinner::{ThreadPinnerConfig, ThreadPinner, init_thread_pinner, get_thread_pinner, pin_threads_to_cores};
+pub use verify::{load_reference, CaseReport, Divergence, ReferenceCase};
+pub use warmup::WarmupPolicy;
@@ -0,0 +1,272 @@
+//! Second-pass moderation of generated replies with keyword rules.
+//!
+//! Each category lists keywords matched case-insensitively on word
+//! boundaries. A category is flagged once its hit count reaches its
+//! threshold; the configured [`ModerationAction`] then decides whether the
+//! reply is only annotated or replaced by `blocked_message`.
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+/// What happens to a flagged reply.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModerationAction {
+    /// Keep the reply and report the scores.
+    #[default]
+    Annotate,
+    /// Replace the reply with `blocked_message`.
+    Block,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ModerationCategory {
+    pub name: String,
+    pub keywords: Vec<String>,
+    /// Keyword hits needed to flag the category.
+    #[serde(default = "default_threshold")]
+    pub threshold: usize,
+}
+
+fn default_threshold() -> usize {
+    1
+}
+
+fn default_blocked_message() -> String {
+    "I can't help with that.".to_string()
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ModerationConfig {
+    pub categories: Vec<ModerationCategory>,
+    #[serde(default)]
+    pub action: ModerationAction,
+    /// Reply sent instead of a blocked one.
+    #[serde(default = "default_blocked_message")]
+    pub blocked_message: String,
+}
+
+impl ModerationConfig {
+    /// Reads a JSON config: `{"categories": [{"name", "keywords",
+    /// "threshold"}], "action": "annotate" | "block", "blocked_message"}`.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read moderation config {:?}", path))?;
+        let config: Self = serde_json::from_str(&text)
+            .with_context(|| format!("Invalid moderation config {:?}", path))?;
+        if config.categories.is_empty() {
+            anyhow::bail!("Moderation config {:?} has no categories", path);
+        }
+        Ok(config)
+    }
+
+    /// Scores `text` against every category.
+    pub fn check(&self, text: &str) -> ModerationResult {
+        let lower = text.to_lowercase();
+        let categories: Vec<CategoryScore> = self
+            .categories
+            .iter()
+            .map(|category| {
+                let mut hits = 0;
+                let mut matches = Vec::new();
+                for keyword in &category.keywords {
+                    let count = count_word(&lower, &keyword.to_lowercase());
+                    if count > 0 {
+                        hits += count;
+                        matches.push(keyword.clone());
+                    }
+                }
+                let threshold = category.threshold.max(1);
+                CategoryScore {
+                    category: category.name.clone(),
+                    score: (hits as f32 / threshold as f32).min(1.0),
+                    flagged: hits >= threshold,
+                    matches,
+                }
+            })
+            .collect();
+
+        let flagged = categories.iter().any(|c| c.flagged);
+        ModerationResult {
+            flagged,
+            blocked: flagged && self.action == ModerationAction::Block,
+            categories,
+        }
+    }
+}
+
+/// Moderation of a reply while it streams. Each complete word is scanned
+/// once, so checking after every token stays linear in the reply length.
+pub struct ModerationStream<'a> {
+    config: &'a ModerationConfig,
+    /// Lowercase keywords of each category.
+    keywords: Vec<Vec<String>>,
+    hits: Vec<usize>,
+    /// The reply so far, lowercase.
+    text: String,
+    /// Bytes of `text` already scanned; always ends after a non-word char.
+    scanned: usize,
+    blocked: bool,
+}
+
+impl<'a> ModerationStream<'a> {
+    pub fn new(config: &'a ModerationConfig) -> Self {
+        let keywords = config
+            .categories
+            .iter()
+            .map(|c| c.keywords.iter().map(|k| k.to_lowercase()).collect())
+            .collect();
+        Self {
+            config,
+            keywords,
+            hits: vec![0; config.categories.len()],
+            text: String::new(),
+            scanned: 0,
+            blocked: false,
+        }
+    }
+
+    /// Adds `token` to the reply and returns whether it is blocked. Words
+    /// are scanned once the character after them has arrived.
+    pub fn push(&mut self, token: &str) -> bool {
+        if self.blocked {
+            return true;
+        }
+        self.text.push_str(&token.to_lowercase());
+        let complete = self.text[self.scanned..]
+            .char_indices()
+            .rfind(|(_, c)| !c.is_alphanumeric())
+            .map(|(i, c)| self.scanned + i + c.len_utf8());
+        if let Some(complete) = complete {
+            self.scan(complete);
+        }
+        self.blocked
+    }
+
+    /// Scans `text[..end]` for keyword hits that end past `scanned`.
+    fn scan(&mut self, end: usize) {
+        let text = &self.text[..end];
+        for (hits, keywords) in self.hits.iter_mut().zip(&self.keywords) {
+            for keyword in keywords {
+                *hits += count_word_after(text, keyword, self.scanned);
+            }
+        }
+        self.scanned = end;
+        self.blocked = self.config.action == ModerationAction::Block
+            && self
+                .config
+                .categories
+                .iter()
+                .zip(&self.hits)
+                .any(|(category, &hits)| hits >= category.threshold.max(1));
+    }
+}
+
+/// Outcome of moderating one reply.
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ModerationResult {
+    /// Any category reached its threshold.
+    pub flagged: bool,
+    /// The reply was replaced by the blocked message.
+    pub blocked: bool,
+    pub categories: Vec<CategoryScore>,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct CategoryScore {
+    pub category: String,
+    /// Hits divided by the threshold, capped at 1.0.
+    pub score: f32,
+    pub flagged: bool,
+    /// Keywords that matched.
+    pub matches: Vec<String>,
+}
+
+/// Occurrences of `word` in `text` that are not part of a longer word.
+fn count_word(text: &str, word: &str) -> usize {
+    count_word_after(text, word, 0)
+}
+
+/// Like [`count_word`], counting only occurrences that end past byte
+/// `after`, so earlier text is not searched again.
+fn count_word_after(text: &str, word: &str, after: usize) -> usize {
+    if word.is_empty() {
+        return 0;
+    }
+    let mut from = after.saturating_sub(word.len());
+    while !text.is_char_boundary(from) {
+        from -= 1;
+    }
+    let is_word = |c: Option<char>| c.is_some_and(char::is_alphanumeric);
+    text[from..]
+        .match_indices(word)
+        .map(|(i, _)| from + i)
+        .filter(|&start| {
+            start + word.len() > after
+                && !is_word(text[..start].chars().next_back())
+                && !is_word(text[start + word.len()..].chars().next())
+        })
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ModerationAction, ModerationCategory, ModerationConfig, ModerationStream};
+
+    fn config(action: ModerationAction) -> ModerationConfig {
+        ModerationConfig {
+            categories: vec![
+                ModerationCategory {
+                    name: "violence".to_string(),
+                    keywords: vec!["kill".to_string(), "weapon".to_string()],
+                    threshold: 2,
+                },
+                ModerationCategory {
+                    name: "profanity".to_string(),
+                    keywords: vec!["darn".to_string()],
+                    threshold: 1,
+                },
+            ],
+            action,
+            blocked_message: "blocked".to_string(),
+        }
+    }
+
+    #[test]
+    fn scores_keywords_on_word_boundaries() {
+        let result = config(ModerationAction::Annotate).check("Kill the process; skills matter.");
+        assert!(!result.flagged);
+        assert_eq!(result.categories[0].score, 0.5);
+        assert_eq!(result.categories[0].matches, vec!["kill"]);
+
+        let result = config(ModerationAction::Annotate).check("Darn, a weapon to kill with.");
+        assert!(result.flagged);
+        assert!(!result.blocked);
+        assert!(result.categories.iter().all(|c| c.flagged));
+    }
+
+    #[test]
+    fn block_action_blocks_flagged_replies() {
+        let config = config(ModerationAction::Block);
+        assert!(config.check("darn").blocked);
+        assert!(!config.check("hello").blocked);
+    }
+
+    #[test]
+    fn stream_blocks_once_a_word_completes() {
+        let block = config(ModerationAction::Block);
+        let mut stream = ModerationStream::new(&block);
+        for token in ["Dar", "nit", " is", " fine", " but", " DA", "RN"] {
+            assert!(!stream.push(token), "blocked at {:?}", token);
+        }
+        assert!(stream.push("!"));
+
+        // Hits add up across scans, like a check of the whole text.
+        let mut stream = ModerationStream::new(&block);
+        assert!(!stream.push("kill it, "));
+        assert!(!stream.push("skills"));
+        assert!(stream.push(" and kill again."));
+        assert!(!ModerationStream::new(&config(ModerationAction::Annotate)).push("darn "));
+    }
+}
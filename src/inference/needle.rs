@@ -0,0 +1,221 @@
+//! Needle-in-a-haystack test of the usable context (`oxide-rs ctx-test`).
+//!
+//! A passcode is hidden at a given depth of filler text that fills a given
+//! share of the context window, and the model is asked for it. Quantized
+//! models often advertise more context than they can recall from, and the
+//! grid of fill levels and depths shows where retrieval starts to fail.
+
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+
+use super::fuzz::Rng;
+use super::generator::Generator;
+
+/// Unrelated, unremarkable sentences the needle hides among.
+const FILLER: [&str; 12] = [
+    "The river bends twice before it reaches the old mill at the edge of town.",
+    "Most of the orchard was planted with apple trees several decades ago.",
+    "On quiet mornings the market opens a little later than the sign says.",
+    "A narrow path leads from the station to the library across the square.",
+    "The committee met on Thursday to discuss the schedule for the autumn fair.",
+    "Fresh bread is delivered to the corner shop before sunrise every day.",
+    "The museum keeps a small collection of maps drawn by local surveyors.",
+    "Rain usually arrives from the west, sweeping over the hills in the evening.",
+    "Several families have run the fishing boats in the harbour for generations.",
+    "The bell in the clock tower was replaced after a storm damaged it.",
+    "Students often gather in the park to read when the weather is mild.",
+    "An old stone bridge still carries carts over the stream near the farm.",
+];
+
+const QUESTION: &str =
+    "What is the secret passcode mentioned in the text above? Answer with the number only.";
+
+/// Tokens kept free for the chat template and the answer.
+const RESERVED_TOKENS: usize = 96;
+
+/// Tokens the answer may use.
+const ANSWER_TOKENS: usize = 16;
+
+#[derive(Clone, Debug)]
+pub struct NeedleOptions {
+    /// Shares of `context` the prompt fills, from 0 to 1.
+    pub levels: Vec<f64>,
+    /// Positions of the needle in the filler: 0 at the start, 1 at the end.
+    pub depths: Vec<f64>,
+    /// Context window tested, in tokens.
+    pub context: usize,
+    /// Picks the passcodes.
+    pub seed: u64,
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct NeedleTrial {
+    pub level: f64,
+    pub depth: f64,
+    /// Prompt tokens, without the chat template.
+    pub tokens: usize,
+    pub passcode: String,
+    pub answer: String,
+    pub found: bool,
+    #[serde(serialize_with = "seconds")]
+    pub duration: Duration,
+}
+
+fn seconds<S: serde::Serializer>(duration: &Duration, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_f64(duration.as_secs_f64())
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct NeedleReport {
+    pub context: usize,
+    /// Largest prompt, in tokens, at which the needle was found at every
+    /// depth of that level and all smaller ones.
+    pub usable_tokens: Option<usize>,
+    pub trials: Vec<NeedleTrial>,
+}
+
+impl NeedleReport {
+    fn new(context: usize, trials: Vec<NeedleTrial>) -> Self {
+        let mut levels: Vec<f64> = trials.iter().map(|t| t.level).collect();
+        levels.sort_by(f64::total_cmp);
+        levels.dedup();
+        let mut usable_tokens = None;
+        for level in levels {
+            let at_level = trials.iter().filter(|t| t.level == level);
+            if !at_level.clone().all(|t| t.found) {
+                break;
+            }
+            usable_tokens = at_level.map(|t| t.tokens).max().or(usable_tokens);
+        }
+        Self {
+            context,
+            usable_tokens,
+            trials,
+        }
+    }
+
+    /// Share of trials at `level` that found the needle.
+    pub fn accuracy(&self, level: f64) -> f64 {
+        let at_level: Vec<&NeedleTrial> = self.trials.iter().filter(|t| t.level == level).collect();
+        let found = at_level.iter().filter(|t| t.found).count();
+        found as f64 / at_level.len().max(1) as f64
+    }
+}
+
+/// Filler sentences, cycling through [`FILLER`], whose token counts
+/// (`sentence_tokens`, one per filler sentence) add up to at most
+/// `budget`, with `needle` inserted at `depth`.
+pub fn haystack(sentence_tokens: &[usize], budget: usize, depth: f64, needle: &str) -> String {
+    let mut sentences = Vec::new();
+    let mut used = 0;
+    for i in 0.. {
+        let tokens = sentence_tokens[i % FILLER.len()].max(1);
+        if used + tokens > budget {
+            break;
+        }
+        used += tokens;
+        sentences.push(FILLER[i % FILLER.len()]);
+    }
+    let at = (depth.clamp(0.0, 1.0) * sentences.len() as f64).round() as usize;
+    sentences.insert(at, needle);
+    sentences.join(" ")
+}
+
+/// Runs every level and depth, calling `on_trial` as each finishes. Each
+/// trial answers greedily from an empty conversation.
+pub fn run_needle_test<F>(
+    generator: &mut Generator,
+    options: &NeedleOptions,
+    mut on_trial: F,
+) -> Result<NeedleReport>
+where
+    F: FnMut(&NeedleTrial),
+{
+    // Sentences follow a space, so they are counted that way.
+    let sentence_tokens = FILLER
+        .iter()
+        .map(|s| generator.count_tokens(&format!(" {}", s)))
+        .collect::<Result<Vec<_>>>()?;
+    let mut rng = Rng(options.seed);
+    let mut trials = Vec::new();
+    for &level in &options.levels {
+        let target = (options.context as f64 * level.clamp(0.0, 1.0)) as usize;
+        for &depth in &options.depths {
+            let passcode = format!("{:06}", 100_000 + rng.below(900_000));
+            let needle = format!("The secret passcode is {}.", passcode);
+            let fixed = generator.count_tokens(&needle)? + generator.count_tokens(QUESTION)?;
+            let budget = target.saturating_sub(fixed + RESERVED_TOKENS);
+            let prompt = format!(
+                "{}\n\n{}",
+                haystack(&sentence_tokens, budget, depth, &needle),
+                QUESTION
+            );
+            let tokens = generator.count_tokens(&prompt)?;
+
+            generator.set_history(Vec::new())?;
+            generator.set_sampling(0.0, None, None, options.seed);
+            let started = Instant::now();
+            // No repeat penalty: the answer has to repeat the passcode.
+            let answer = generator.generate(&prompt, ANSWER_TOKENS, 1.0, 0, |_| {})?;
+            let trial = NeedleTrial {
+                level,
+                depth,
+                tokens,
+                found: answer.contains(&passcode),
+                passcode,
+                answer: answer.trim().to_string(),
+                duration: started.elapsed(),
+            };
+            on_trial(&trial);
+            trials.push(trial);
+        }
+    }
+    Ok(NeedleReport::new(options.context, trials))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{haystack, NeedleReport, NeedleTrial, FILLER};
+
+    #[test]
+    fn hides_the_needle_and_finds_the_usable_context() {
+        let tokens = [10; FILLER.len()];
+        let needle = "The secret passcode is 123456.";
+        let start = haystack(&tokens, 45, 0.0, needle);
+        assert!(start.starts_with(needle));
+        assert_eq!(start.matches(". ").count(), 4);
+        let end = haystack(&tokens, 45, 1.0, needle);
+        assert!(end.ends_with(needle));
+        let middle = haystack(&tokens, 45, 0.5, needle);
+        assert_eq!(
+            middle.split(". ").nth(2),
+            Some("The secret passcode is 123456")
+        );
+        assert_eq!(haystack(&tokens, 5, 0.5, needle), needle);
+
+        let trial = |level: f64, tokens: usize, found: bool| NeedleTrial {
+            level,
+            depth: 0.5,
+            tokens,
+            passcode: String::new(),
+            answer: String::new(),
+            found,
+            duration: Duration::ZERO,
+        };
+        let report = NeedleReport::new(
+            4096,
+            vec![
+                trial(0.25, 1000, true),
+                trial(0.5, 2000, true),
+                trial(0.5, 2010, true),
+                trial(0.75, 3000, false),
+                trial(1.0, 4000, true),
+            ],
+        );
+        assert_eq!(report.usable_tokens, Some(2010));
+        assert_eq!(report.accuracy(0.75), 0.0);
+    }
+}
@@ -0,0 +1,158 @@
+//! Exchanges marked from the REPL with `/mark` or `/note`.
+//!
+//! Notes are kept in one JSON file (`~/.oxide/notes.json` by default) so
+//! they outlive sessions and can be searched later with `oxide-rs notes
+//! search`.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use super::generator::Message;
+use crate::model::download::get_oxide_dir;
+
+/// A marked prompt and reply, with an optional note.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Note {
+    pub id: u64,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    /// File name of the model that wrote the reply.
+    pub model: String,
+    pub prompt: String,
+    pub response: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+impl Note {
+    /// Whether every whitespace-separated term of `query` occurs in the
+    /// prompt, reply or note, ignoring case.
+    pub fn matches(&self, query: &str) -> bool {
+        let haystack = format!(
+            "{}\n{}\n{}",
+            self.prompt,
+            self.response,
+            self.note.as_deref().unwrap_or_default()
+        )
+        .to_lowercase();
+        query
+            .split_whitespace()
+            .all(|term| haystack.contains(&term.to_lowercase()))
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct NoteStore {
+    pub notes: Vec<Note>,
+}
+
+impl NoteStore {
+    pub fn default_path() -> Result<PathBuf> {
+        Ok(get_oxide_dir()?.join("notes.json"))
+    }
+
+    /// Loads the store at `path`; a missing file is an empty store.
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read notes {:?}", path))?;
+        serde_json::from_str(&text).with_context(|| format!("Invalid notes file {:?}", path))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, json).with_context(|| format!("Failed to write notes {:?}", path))
+    }
+
+    /// Marks the last exchange in `history`. Marking the same exchange again
+    /// updates its note instead of adding a copy. Returns `None` when the
+    /// history has no reply yet.
+    pub fn mark(
+        &mut self,
+        history: &[Message],
+        model: &str,
+        note: Option<String>,
+    ) -> Option<&Note> {
+        let (prompt, response) = last_exchange(history)?;
+        let existing = self
+            .notes
+            .iter()
+            .position(|n| n.prompt == prompt && n.response == response);
+        let index = match existing {
+            Some(index) => {
+                if note.is_some() {
+                    self.notes[index].note = note;
+                }
+                index
+            }
+            None => {
+                self.notes.push(Note {
+                    id: self.notes.iter().map(|n| n.id).max().unwrap_or(0) + 1,
+                    created_at: chrono::Utc::now(),
+                    model: model.to_string(),
+                    prompt: prompt.to_string(),
+                    response: response.to_string(),
+                    note,
+                });
+                self.notes.len() - 1
+            }
+        };
+        self.notes.get(index)
+    }
+
+    /// Notes matching `query`, newest first. An empty query matches all.
+    pub fn search(&self, query: &str) -> Vec<&Note> {
+        self.notes
+            .iter()
+            .rev()
+            .filter(|n| n.matches(query))
+            .collect()
+    }
+}
+
+/// The last assistant reply and the user message before it.
+fn last_exchange(history: &[Message]) -> Option<(&str, &str)> {
+    let reply = history.iter().rposition(|m| m.role == "assistant")?;
+    let prompt = history[..reply]
+        .iter()
+        .rev()
+        .find(|m| m.role == "user")
+        .map_or("", |m| m.content.as_str());
+    Some((prompt, history[reply].content.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NoteStore;
+    use crate::inference::Message;
+
+    #[test]
+    fn marks_and_searches_exchanges() {
+        let mut store = NoteStore::default();
+        assert!(store
+            .mark(&[Message::new("user", "hi")], "m.gguf", None)
+            .is_none());
+
+        let history = vec![
+            Message::new("user", "How do I reverse a Vec?"),
+            Message::new("assistant", "Call `v.reverse()`."),
+        ];
+        assert_eq!(store.mark(&history, "m.gguf", None).unwrap().id, 1);
+        let note = store
+            .mark(&history, "m.gguf", Some("rust snippet".to_string()))
+            .unwrap();
+        assert_eq!((note.id, note.note.as_deref()), (1, Some("rust snippet")));
+        assert_eq!(store.notes.len(), 1);
+
+        assert_eq!(store.search("REVERSE snippet").len(), 1);
+        assert_eq!(store.search("").len(), 1);
+        assert!(store.search("reverse python").is_empty());
+
+        let path = std::env::temp_dir().join(format!("oxide-notes-{}.json", std::process::id()));
+        store.save(&path).unwrap();
+        assert_eq!(NoteStore::load(&path).unwrap(), store);
+        std::fs::remove_file(&path).ok();
+    }
+}
@@ -0,0 +1,134 @@
+//! Detecting NaN and infinite logits during generation.
+//!
+//! Once a forward pass produces a non-finite value it spreads through the
+//! KV cache, and every following token is garbage. Summing the logits is a
+//! single reduction and is non-finite exactly when some logit is (or when
+//! the sum overflows, which real logits do not), so the generator checks
+//! the prompt's logits and every [`CHECK_INTERVAL`] decode steps, and stops
+//! with a [`NonFiniteLogits`] error naming the likely cause.
+
+use std::fmt;
+
+use candle_core::{DType, Result, Tensor};
+
+/// Decode steps between checks.
+pub const CHECK_INTERVAL: usize = 16;
+
+/// Whether decode `step` (0 is the prompt) is checked.
+pub fn should_check(step: usize) -> bool {
+    step % CHECK_INTERVAL == 0
+}
+
+/// Whether `logits` hold a NaN or infinite value.
+pub fn has_non_finite(logits: &Tensor) -> Result<bool> {
+    let sum = logits.to_dtype(DType::F32)?.sum_all()?.to_scalar::<f32>()?;
+    Ok(!sum.is_finite())
+}
+
+/// Logits that turned NaN or infinite, and where.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NonFiniteLogits {
+    /// Decode step; 0 is the prompt.
+    pub step: usize,
+    /// Position of the token whose logits failed.
+    pub position: usize,
+    pub nan: usize,
+    pub inf: usize,
+    /// Context length the model was trained for.
+    pub trained_context: usize,
+    /// First layer whose output was non-finite, when the architecture
+    /// reports its layers.
+    pub layer: Option<String>,
+    pub quantization: Option<String>,
+}
+
+impl NonFiniteLogits {
+    /// Counts the NaN and infinite values of `logits`.
+    pub fn count(logits: &Tensor) -> Result<(usize, usize)> {
+        let values = logits
+            .to_dtype(DType::F32)?
+            .flatten_all()?
+            .to_vec1::<f32>()?;
+        let nan = values.iter().filter(|v| v.is_nan()).count();
+        let inf = values.iter().filter(|v| v.is_infinite()).count();
+        Ok((nan, inf))
+    }
+
+    fn likely_cause(&self) -> String {
+        let quant = self.quantization.as_deref().unwrap_or("this quantization");
+        if self.position >= self.trained_context {
+            format!(
+                "broken rope parameters: position {} is past the {} tokens the model was trained for; \
+                 lower --max-tokens or shorten the prompt",
+                self.position, self.trained_context
+            )
+        } else if self.step == 0 && self.nan > 0 {
+            format!(
+                "corrupt or badly quantized weights ({}); run `oxide-rs check` on the file or try \
+                 another quantization of the model",
+                quant
+            )
+        } else {
+            format!(
+                "an overflow in a kernel, which low-bit quantizations such as {} are prone to; \
+                 try a higher-precision quantization",
+                quant
+            )
+        }
+    }
+}
+
+impl fmt::Display for NonFiniteLogits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let place = if self.step == 0 {
+            "the prompt".to_string()
+        } else {
+            format!("decode step {}", self.step)
+        };
+        write!(
+            f,
+            "The model produced {} NaN and {} infinite logits at {} (position {})",
+            self.nan, self.inf, place, self.position
+        )?;
+        if let Some(layer) = &self.layer {
+            write!(f, ", first in {}", layer)?;
+        }
+        write!(f, ". Likely cause: {}.", self.likely_cause())
+    }
+}
+
+impl std::error::Error for NonFiniteLogits {}
+
+#[cfg(test)]
+mod tests {
+    use candle_core::{Device, Tensor};
+
+    use super::{has_non_finite, should_check, NonFiniteLogits};
+
+    #[test]
+    fn detects_non_finite_logits_and_names_a_cause() {
+        let ok = Tensor::new(&[1.0f32, -2.0, 30.0], &Device::Cpu).unwrap();
+        let bad = Tensor::new(&[1.0f32, f32::NAN, f32::INFINITY], &Device::Cpu).unwrap();
+        assert!(!has_non_finite(&ok).unwrap());
+        assert!(has_non_finite(&bad).unwrap());
+        assert_eq!(NonFiniteLogits::count(&bad).unwrap(), (1, 1));
+        assert!(should_check(0) && should_check(32) && !should_check(5));
+
+        let mut error = NonFiniteLogits {
+            step: 0,
+            position: 11,
+            nan: 151_936,
+            inf: 0,
+            trained_context: 4096,
+            layer: Some("blk.17".to_string()),
+            quantization: Some("Q2_K".to_string()),
+        };
+        let message = error.to_string();
+        assert!(message.contains("at the prompt (position 11), first in blk.17"));
+        assert!(message.contains("badly quantized weights (Q2_K)"));
+
+        error.step = 40;
+        error.position = 5000;
+        assert!(error.to_string().contains("rope parameters"));
+    }
+}
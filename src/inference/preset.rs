@@ -0,0 +1,158 @@
+//! Named sampling presets for `--preset`, and the preset a model family
+//! gets by default.
+//!
+//! Explicit sampling flags always win over a preset; a preset only fills in
+//! the values that were not given.
+
+use std::str::FromStr;
+
+/// A set of sampling settings tuned for one kind of task.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SamplingPreset {
+    /// Varied, loose output for brainstorming and fiction.
+    Creative,
+    /// Near-greedy output for factual answers and extraction.
+    Precise,
+    /// Low temperature without a repeat penalty, which would punish the
+    /// identifiers and punctuation code legitimately repeats.
+    Code,
+    /// Moderate temperature without a repeat penalty for long chains of
+    /// thought, as recommended for thinking models; greedy decoding makes
+    /// them loop.
+    Reasoning,
+}
+
+/// The settings a [`SamplingPreset`] stands for.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PresetSampling {
+    pub temperature: f64,
+    pub top_p: Option<f64>,
+    pub top_k: Option<usize>,
+    pub repeat_penalty: f32,
+}
+
+impl SamplingPreset {
+    pub const ALL: [SamplingPreset; 4] = [
+        SamplingPreset::Creative,
+        SamplingPreset::Precise,
+        SamplingPreset::Code,
+        SamplingPreset::Reasoning,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SamplingPreset::Creative => "creative",
+            SamplingPreset::Precise => "precise",
+            SamplingPreset::Code => "code",
+            SamplingPreset::Reasoning => "reasoning",
+        }
+    }
+
+    pub fn sampling(&self) -> PresetSampling {
+        match self {
+            SamplingPreset::Creative => PresetSampling {
+                temperature: 0.9,
+                top_p: Some(0.95),
+                top_k: None,
+                repeat_penalty: 1.15,
+            },
+            SamplingPreset::Precise => PresetSampling {
+                temperature: 0.1,
+                top_p: Some(0.9),
+                top_k: None,
+                repeat_penalty: 1.05,
+            },
+            SamplingPreset::Code => PresetSampling {
+                temperature: 0.2,
+                top_p: Some(0.95),
+                top_k: None,
+                repeat_penalty: 1.0,
+            },
+            SamplingPreset::Reasoning => PresetSampling {
+                temperature: 0.6,
+                top_p: Some(0.95),
+                top_k: Some(20),
+                repeat_penalty: 1.0,
+            },
+        }
+    }
+
+    /// The preset a model gets when none is chosen, from its GGUF
+    /// `general.architecture` and `general.name`. `None` keeps the built-in
+    /// defaults.
+    pub fn for_model(architecture: &str, name: &str) -> Option<SamplingPreset> {
+        let name = name.to_ascii_lowercase();
+        let words: Vec<&str> = name
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .collect();
+        let has = |targets: &[&str]| words.iter().any(|word| targets.contains(word));
+
+        // "StarCoder2", "DeepSeek-Coder", but not "encoder" or "decoder".
+        let coder = |word: &&str| {
+            word.contains("coder") && !word.contains("encoder") && !word.contains("decoder")
+        };
+        if words.iter().any(coder) || has(&["code", "codellama"]) {
+            return Some(SamplingPreset::Code);
+        }
+        if has(&["r1", "qwq", "thinking", "reasoning"]) {
+            return Some(SamplingPreset::Reasoning);
+        }
+        // Qwen3 models think by default unless they are instruct-only builds.
+        if matches!(architecture, "qwen3" | "qwen35") && !has(&["instruct"]) {
+            return Some(SamplingPreset::Reasoning);
+        }
+        None
+    }
+}
+
+impl FromStr for SamplingPreset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.to_ascii_lowercase();
+        SamplingPreset::ALL
+            .iter()
+            .copied()
+            .find(|p| p.as_str() == s)
+            .ok_or_else(|| {
+                format!(
+                    "unknown preset '{}' (expected creative, precise, code or reasoning)",
+                    s
+                )
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SamplingPreset;
+
+    #[test]
+    fn picks_presets_by_model_family() {
+        let for_model = SamplingPreset::for_model;
+        assert_eq!(
+            for_model("qwen2", "Qwen2.5-Coder-7B-Instruct"),
+            Some(SamplingPreset::Code)
+        );
+        assert_eq!(
+            for_model("llama", "DeepSeek-R1-Distill-Llama-8B"),
+            Some(SamplingPreset::Reasoning)
+        );
+        assert_eq!(
+            for_model("qwen3", "Qwen3-4B"),
+            Some(SamplingPreset::Reasoning)
+        );
+        assert_eq!(for_model("qwen3", "Qwen3-4B-Instruct-2507"), None);
+        assert_eq!(for_model("llama", "Llama-3.2-1B-Instruct"), None);
+        assert_eq!(
+            for_model("starcoder2", "StarCoder2-3B"),
+            Some(SamplingPreset::Code)
+        );
+        assert_eq!(for_model("llama", "tiny-decoder"), None);
+
+        assert_eq!("Code".parse::<SamplingPreset>(), Ok(SamplingPreset::Code));
+        assert!("wild".parse::<SamplingPreset>().is_err());
+        assert_eq!(SamplingPreset::Code.sampling().repeat_penalty, 1.0);
+    }
+}
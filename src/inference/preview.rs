@@ -0,0 +1,84 @@
+//! What the chat template makes of a conversation, for `/preview` and
+//! `template render`.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use super::generator::{ChatTemplate, Message};
+use crate::model::{Model, TokenizerWrapper};
+
+/// A rendered prompt, exactly as the model would receive it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PromptPreview {
+    pub text: String,
+    /// Tokens after encoding, including BOS when the model adds it.
+    pub tokens: usize,
+}
+
+/// Parses a JSON array of messages, or an object with a `messages` array
+/// like a Chat Completions request body.
+pub fn parse_messages(text: &str) -> Result<Vec<Message>> {
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum Messages {
+        List(Vec<Message>),
+        Request { messages: Vec<Message> },
+    }
+
+    let messages = match serde_json::from_str(text)? {
+        Messages::List(messages) | Messages::Request { messages } => messages,
+    };
+    Ok(messages)
+}
+
+pub fn load_messages(path: &Path) -> Result<Vec<Message>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read messages {:?}", path))?;
+    parse_messages(&text).with_context(|| format!("Invalid messages file {:?}", path))
+}
+
+/// Renders `messages` with the chat template of the GGUF at `model_path`
+/// and counts the tokens, reading only the header and tokenizer, not the
+/// weights. `tokenizer_path` replaces the GGUF tokenizer like `--tokenizer`.
+pub fn render_template(
+    model_path: &Path,
+    tokenizer_path: Option<&PathBuf>,
+    messages: &[Message],
+    add_generation_prompt: bool,
+) -> Result<PromptPreview> {
+    let metadata = Model::read_metadata(model_path)?;
+    let template = ChatTemplate::new(metadata.chat_template)?;
+    let text = template.apply(messages, add_generation_prompt)?;
+
+    let tokenizer = match tokenizer_path {
+        Some(path) => TokenizerWrapper::from_file(path)?,
+        None => TokenizerWrapper::from_gguf(&model_path.to_path_buf())?,
+    };
+    let tokens = tokenizer.encode_with_options(&text, true, true)?.len();
+    Ok(PromptPreview { text, tokens })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_messages;
+
+    #[test]
+    fn parses_message_lists_and_request_bodies() {
+        let list = parse_messages(r#"[{"role": "user", "content": "Hi", "name": "ada"}]"#).unwrap();
+        assert_eq!(list[0].content, "Hi");
+        assert_eq!(list[0].name.as_deref(), Some("ada"));
+
+        let request = parse_messages(
+            r#"{"model": "x", "messages": [
+                {"role": "system", "content": "Be brief."},
+                {"role": "user", "content": "Hi"}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(request.len(), 2);
+        assert_eq!(request[0].role, "system");
+
+        assert!(parse_messages(r#"{"role": "user"}"#).is_err());
+    }
+}
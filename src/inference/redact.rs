@@ -0,0 +1,217 @@
+//! Streaming redaction of sensitive patterns (emails, phone numbers, card
+//! numbers, or any configured regex) in generated text.
+//!
+//! Text is redacted inside the generator, before it reaches stream callbacks,
+//! hooks, the stored history, or API responses. Because a match can span
+//! several tokens, the last `holdback` bytes are held back until more text
+//! arrives or generation ends.
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use regex::{Captures, Regex};
+
+/// One pattern to redact and the text that replaces each match.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct RedactionRule {
+    pub name: String,
+    pub pattern: String,
+    #[serde(default = "default_replacement")]
+    pub replacement: String,
+}
+
+fn default_replacement() -> String {
+    "[REDACTED]".to_string()
+}
+
+fn default_holdback() -> usize {
+    64
+}
+
+/// Rules applied to generated text. Earlier rules win when two match at the
+/// same position.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct RedactionConfig {
+    pub rules: Vec<RedactionRule>,
+    /// Bytes withheld from the stream so matches split across tokens are
+    /// still caught. Must cover the longest expected match.
+    #[serde(default = "default_holdback")]
+    pub holdback: usize,
+}
+
+impl Default for RedactionConfig {
+    /// Built-in rules for email addresses, card numbers and phone numbers.
+    fn default() -> Self {
+        let rule = |name: &str, pattern: &str, replacement: &str| RedactionRule {
+            name: name.to_string(),
+            pattern: pattern.to_string(),
+            replacement: replacement.to_string(),
+        };
+        Self {
+            rules: vec![
+                rule(
+                    "email",
+                    r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}",
+                    "[EMAIL]",
+                ),
+                rule("credit_card", r"\b(?:\d[ -]?){12,18}\d\b", "[CARD]"),
+                rule(
+                    "phone",
+                    r"(?:\+\d{1,3}[ .-]?)?(?:\(\d{3}\)|\b\d{3})[ .-]?\d{3}[ .-]?\d{4}\b",
+                    "[PHONE]",
+                ),
+            ],
+            holdback: default_holdback(),
+        }
+    }
+}
+
+impl RedactionConfig {
+    /// Reads rules from JSON: either `{"rules": [...], "holdback": n}` or a
+    /// bare array of `{"name", "pattern", "replacement"}` objects.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read redaction rules {:?}", path))?;
+        let config = if text.trim_start().starts_with('[') {
+            Self {
+                rules: serde_json::from_str(&text)?,
+                holdback: default_holdback(),
+            }
+        } else {
+            serde_json::from_str(&text)?
+        };
+        Redactor::new(&config)?;
+        Ok(config)
+    }
+}
+
+/// Compiled [`RedactionConfig`] with the streaming buffer.
+#[derive(Clone, Debug)]
+pub struct Redactor {
+    regex: Regex,
+    replacements: Vec<String>,
+    holdback: usize,
+    buffer: String,
+}
+
+impl Redactor {
+    pub fn new(config: &RedactionConfig) -> Result<Self> {
+        if config.rules.is_empty() {
+            anyhow::bail!("Redaction needs at least one rule");
+        }
+        let mut alternatives = Vec::with_capacity(config.rules.len());
+        for (i, rule) in config.rules.iter().enumerate() {
+            Regex::new(&rule.pattern)
+                .with_context(|| format!("Invalid pattern for redaction rule '{}'", rule.name))?;
+            alternatives.push(format!("(?P<r{}>{})", i, rule.pattern));
+        }
+        Ok(Self {
+            regex: Regex::new(&alternatives.join("|"))?,
+            replacements: config.rules.iter().map(|r| r.replacement.clone()).collect(),
+            holdback: config.holdback,
+            buffer: String::new(),
+        })
+    }
+
+    /// Redacts a complete text.
+    pub fn redact(&self, text: &str) -> String {
+        self.regex
+            .replace_all(text, |caps: &Captures| {
+                (0..self.replacements.len())
+                    .find(|i| caps.name(&format!("r{}", i)).is_some())
+                    .map(|i| self.replacements[i].clone())
+                    .unwrap_or_default()
+            })
+            .into_owned()
+    }
+
+    /// Feeds streamed text and returns the redacted part that is safe to
+    /// release. The rest stays buffered until the next call or [`finish`].
+    ///
+    /// [`finish`]: Redactor::finish
+    pub fn push(&mut self, text: &str) -> String {
+        self.buffer.push_str(text);
+        if self.buffer.len() <= self.holdback {
+            return String::new();
+        }
+
+        let mut cut = self.buffer.len() - self.holdback;
+        while !self.buffer.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        // Never release half of a match.
+        for m in self.regex.find_iter(&self.buffer) {
+            if m.start() >= cut {
+                break;
+            }
+            if m.end() > cut {
+                cut = m.start();
+                break;
+            }
+        }
+
+        let head: String = self.buffer.drain(..cut).collect();
+        self.redact(&head)
+    }
+
+    /// Text held back until it cannot be part of a match.
+    pub fn pending(&self) -> &str {
+        &self.buffer
+    }
+
+    /// Releases everything still buffered.
+    pub fn finish(&mut self) -> String {
+        let rest = std::mem::take(&mut self.buffer);
+        self.redact(&rest)
+    }
+
+    pub fn reset(&mut self) {
+        self.buffer.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RedactionConfig, Redactor};
+
+    const TEXT: &str = "Mail jane.doe@example.com or call (555) 123-4567, \
+                        card 4111 1111 1111 1111 expires soon.";
+
+    #[test]
+    fn redacts_builtin_patterns() {
+        let redactor = Redactor::new(&RedactionConfig::default()).unwrap();
+        assert_eq!(
+            redactor.redact(TEXT),
+            "Mail [EMAIL] or call [PHONE], card [CARD] expires soon."
+        );
+    }
+
+    #[test]
+    fn streaming_matches_whole_text() {
+        let mut redactor = Redactor::new(&RedactionConfig {
+            holdback: 24,
+            ..Default::default()
+        })
+        .unwrap();
+        let expected = redactor.redact(TEXT);
+
+        for size in [1, 3, 7] {
+            let mut out = String::new();
+            let chars: Vec<char> = TEXT.chars().collect();
+            for chunk in chars.chunks(size) {
+                out.push_str(&redactor.push(&chunk.iter().collect::<String>()));
+            }
+            out.push_str(&redactor.finish());
+            assert_eq!(out, expected, "chunk size {}", size);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_rules() {
+        let mut config = RedactionConfig::default();
+        config.rules[0].pattern = "(".to_string();
+        assert!(Redactor::new(&config).is_err());
+        config.rules.clear();
+        assert!(Redactor::new(&config).is_err());
+    }
+}
@@ -0,0 +1,269 @@
+//! Session recordings (`--record`) and their replay (`oxide-rs replay`).
+//!
+//! A recording is JSON Lines: a [`RecordingHeader`] with everything that
+//! shapes generation (model fingerprint, system prompt, sampler settings,
+//! seed and sampler position, thread count), then one [`RecordedEvent`] per
+//! turn or `/clear`. Each line is flushed as it is written, so a session
+//! that crashes still leaves a usable file. Replaying runs the same prompts
+//! from the same state and compares the replies, which match whenever
+//! generation is deterministic: same model, settings, threads and build.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use super::generator::Message;
+use super::sampler::SamplerState;
+use super::session::{ModelFingerprint, SessionParams};
+
+const RECORDING_VERSION: u32 = 1;
+
+/// The state a recording starts from.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RecordingHeader {
+    pub version: u32,
+    pub recorded_at: chrono::DateTime<chrono::Utc>,
+    /// Version of oxide-rs that made the recording.
+    pub oxide_version: String,
+    pub model: ModelFingerprint,
+    pub system_prompt: Option<String>,
+    pub params: SessionParams,
+    /// Inference threads; other counts may round differently.
+    pub threads: usize,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub logit_bias: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub force_language: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_n_sigma: Option<f32>,
+    #[serde(default, skip_serializing_if = "is_zero_f32")]
+    pub eos_bias: f32,
+    #[serde(default, skip_serializing_if = "is_zero_usize")]
+    pub min_tokens: usize,
+    /// History when recording started, e.g. from a resumed session.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub messages: Vec<Message>,
+    pub sampler: SamplerState,
+}
+
+impl RecordingHeader {
+    pub fn new(
+        model: ModelFingerprint,
+        system_prompt: Option<String>,
+        params: SessionParams,
+        threads: usize,
+        messages: Vec<Message>,
+        sampler: SamplerState,
+    ) -> Self {
+        Self {
+            version: RECORDING_VERSION,
+            recorded_at: chrono::Utc::now(),
+            oxide_version: env!("CARGO_PKG_VERSION").to_string(),
+            model,
+            system_prompt,
+            params,
+            threads,
+            logit_bias: Vec::new(),
+            force_language: None,
+            top_n_sigma: None,
+            eos_bias: 0.0,
+            min_tokens: 0,
+            messages,
+            sampler,
+        }
+    }
+
+    /// Describes how `model` differs from the recorded model, or `None` if
+    /// it is the same file.
+    pub fn model_mismatch(&self, model: &ModelFingerprint) -> Option<String> {
+        if self.model.same_file(model) {
+            return None;
+        }
+        Some(format!(
+            "recorded with {}, current model is {}",
+            self.model.summary(),
+            model.summary()
+        ))
+    }
+}
+
+fn is_zero_f32(value: &f32) -> bool {
+    *value == 0.0
+}
+
+fn is_zero_usize(value: &usize) -> bool {
+    *value == 0
+}
+
+/// Something that happened during a recorded session.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum RecordedEvent {
+    /// A prompt, as sent to the model after any pre-prompt hook, and the
+    /// reply as generated, before post-response hooks or transforms.
+    Turn {
+        prompt: String,
+        reply: String,
+        /// Stopped by Ctrl+C. Replay restores the partial reply instead of
+        /// regenerating it.
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        cancelled: bool,
+        /// Sampler position after the reply.
+        sampler: SamplerState,
+    },
+    /// `/clear`.
+    Clear,
+}
+
+/// Appends events to a recording file.
+pub struct Recorder {
+    file: File,
+}
+
+impl Recorder {
+    /// Creates `path`, replacing any earlier recording, and writes `header`.
+    pub fn create(path: &Path, header: &RecordingHeader) -> Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create recording {}", path.display()))?;
+        let mut recorder = Self { file };
+        recorder.write_line(header)?;
+        Ok(recorder)
+    }
+
+    pub fn record(&mut self, event: &RecordedEvent) -> Result<()> {
+        self.write_line(event)
+    }
+
+    fn write_line<T: Serialize>(&mut self, value: &T) -> Result<()> {
+        let mut line = serde_json::to_string(value)?;
+        line.push('\n');
+        self.file.write_all(line.as_bytes())?;
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+/// A recording read back for replay.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Recording {
+    pub header: RecordingHeader,
+    pub events: Vec<RecordedEvent>,
+}
+
+impl Recording {
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = File::open(path)
+            .with_context(|| format!("Failed to read recording {}", path.display()))?;
+        let mut lines = BufReader::new(file)
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !matches!(line, Ok(l) if l.trim().is_empty()));
+
+        let Some((_, first)) = lines.next() else {
+            anyhow::bail!("{} is empty", path.display());
+        };
+        let header: RecordingHeader = serde_json::from_str(&first?)
+            .with_context(|| format!("{}: invalid recording header", path.display()))?;
+        if header.version > RECORDING_VERSION {
+            anyhow::bail!(
+                "Recording {} has version {}, newer than the supported {}",
+                path.display(),
+                header.version,
+                RECORDING_VERSION
+            );
+        }
+        let events = lines
+            .map(|(i, line)| {
+                serde_json::from_str(&line?)
+                    .with_context(|| format!("{}: line {}", path.display(), i + 1))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { header, events })
+    }
+
+    /// Number of turns.
+    pub fn turns(&self) -> usize {
+        self.events
+            .iter()
+            .filter(|e| matches!(e, RecordedEvent::Turn { .. }))
+            .count()
+    }
+}
+
+/// Byte offset of the first difference between two replies, on a character
+/// boundary of both, or `None` when they are equal.
+pub fn first_difference(expected: &str, actual: &str) -> Option<usize> {
+    if expected == actual {
+        return None;
+    }
+    let offset = expected
+        .char_indices()
+        .zip(actual.chars())
+        .find(|((_, e), a)| e != a)
+        .map(|((i, _), _)| i);
+    Some(offset.unwrap_or_else(|| expected.len().min(actual.len())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{first_difference, RecordedEvent, Recorder, Recording, RecordingHeader};
+    use crate::inference::{Message, ModelFingerprint, SamplerState, SessionParams};
+
+    #[test]
+    fn round_trips_a_recording() {
+        let dir = std::env::temp_dir().join(format!("oxide-replay-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let model = dir.join("m.gguf");
+        std::fs::write(&model, b"GGUF model").unwrap();
+
+        let header = RecordingHeader::new(
+            ModelFingerprint::from_file(&model).unwrap(),
+            None,
+            SessionParams {
+                temperature: 0.7,
+                top_p: None,
+                top_k: Some(40),
+                repeat_penalty: 1.1,
+                repeat_last_n: 64,
+                max_tokens: 0,
+                seed: 7,
+            },
+            4,
+            vec![
+                Message::new("user", "hi"),
+                Message::new("assistant", "hello"),
+            ],
+            SamplerState { seed: 7, draws: 3 },
+        );
+        let events = vec![
+            RecordedEvent::Turn {
+                prompt: "2+2?".to_string(),
+                reply: "4".to_string(),
+                cancelled: false,
+                sampler: SamplerState { seed: 7, draws: 5 },
+            },
+            RecordedEvent::Clear,
+        ];
+        let path = dir.join("session.oxr");
+        let mut recorder = Recorder::create(&path, &header).unwrap();
+        for event in &events {
+            recorder.record(event).unwrap();
+        }
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(text.lines().count(), 3);
+        assert!(!text.contains("cancelled"));
+
+        let recording = Recording::load(&path).unwrap();
+        assert_eq!(recording.header, header);
+        assert_eq!(recording.events, events);
+        assert_eq!(recording.turns(), 1);
+        std::fs::remove_dir_all(&dir).ok();
+
+        assert_eq!(first_difference("héllo", "héllo"), None);
+        assert_eq!(first_difference("héllo", "hélp"), Some(4));
+        assert_eq!(first_difference("hello", "hello world"), Some(5));
+    }
+}
@@ -0,0 +1,803 @@
+//! Pluggable logits transforms applied before sampling.
+//!
+//! Each decode step runs the built-in repeat penalty, then every registered
+//! [`LogitsTransform`] ordered by [`TransformStage`] (penalties, then biases,
+//! then grammar constraints, then truncation), and finally the sampler picks
+//! a token. Within a stage, transforms run in registration order.
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use candle_core::{Device, Tensor};
+use candle_transformers::generation::{LogitsProcessor, Sampling};
+use rayon::prelude::*;
+
+use super::kernels;
+
+/// Where a transform runs in the chain. Later stages see the output of
+/// earlier ones; grammar constraints run after penalties and biases so
+/// nothing can re-enable a token they masked.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TransformStage {
+    Penalty,
+    Bias,
+    Grammar,
+    /// Truncation samplers such as [`TopNSigma`], which only mask tokens
+    /// and so judge just the tokens grammar allows.
+    Truncation,
+}
+
+/// Token state visible to transforms at a decode step.
+pub struct TransformContext<'a> {
+    /// Prompt followed by every token generated so far.
+    pub tokens: &'a [u32],
+    /// Number of leading prompt tokens in `tokens`.
+    pub prompt_len: usize,
+    /// Token that ends generation.
+    pub eos_token: u32,
+}
+
+impl TransformContext<'_> {
+    /// Tokens generated so far in this call.
+    pub fn generated(&self) -> &[u32] {
+        &self.tokens[self.prompt_len..]
+    }
+}
+
+/// A user-defined step that rewrites logits before sampling.
+///
+/// Set a logit to `f32::NEG_INFINITY` to forbid a token.
+pub trait LogitsTransform: Send {
+    fn stage(&self) -> TransformStage;
+
+    fn apply(&mut self, logits: &mut [f32], context: &TransformContext) -> Result<()>;
+
+    /// Called at the start of every generation, for transforms that keep
+    /// per-response state.
+    fn reset(&mut self) {}
+}
+
+/// Ordered list of transforms run at every decode step.
+#[derive(Default)]
+pub struct LogitsChain {
+    transforms: Vec<Box<dyn LogitsTransform>>,
+}
+
+impl LogitsChain {
+    /// Adds `transform` after every transform of the same or earlier stage.
+    pub fn push(&mut self, transform: Box<dyn LogitsTransform>) {
+        let stage = transform.stage();
+        let at = self
+            .transforms
+            .iter()
+            .position(|t| t.stage() > stage)
+            .unwrap_or(self.transforms.len());
+        self.transforms.insert(at, transform);
+    }
+
+    pub fn clear(&mut self) {
+        self.transforms.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transforms.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.transforms.len()
+    }
+
+    pub fn reset(&mut self) {
+        for transform in &mut self.transforms {
+            transform.reset();
+        }
+    }
+
+    /// Runs every transform over `logits`. Returns the input untouched (and
+    /// without copying it off the device) when the chain is empty.
+    pub fn apply(&mut self, logits: Tensor, context: &TransformContext) -> Result<Tensor> {
+        if self.transforms.is_empty() {
+            return Ok(logits);
+        }
+        let device = logits.device().clone();
+        let mut values = logits.to_dtype(candle_core::DType::F32)?.to_vec1::<f32>()?;
+        self.apply_slice(&mut values, context)?;
+        let len = values.len();
+        Ok(Tensor::from_vec(values, len, &device)?)
+    }
+
+    pub fn apply_slice(&mut self, logits: &mut [f32], context: &TransformContext) -> Result<()> {
+        for transform in &mut self.transforms {
+            transform.apply(logits, context)?;
+        }
+        Ok(())
+    }
+}
+
+/// Adds a fixed bias to selected token ids, like OpenAI's `logit_bias`.
+#[derive(Clone, Debug, Default)]
+pub struct LogitBias {
+    biases: HashMap<u32, f32>,
+}
+
+impl LogitBias {
+    pub fn new(biases: HashMap<u32, f32>) -> Self {
+        Self { biases }
+    }
+
+    /// Parses `id=bias` pairs such as `"50256=-100"`.
+    pub fn parse(spec: &str) -> Result<(u32, f32)> {
+        let (id, bias) = spec
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("expected TOKEN_ID=BIAS, got '{}'", spec))?;
+        Ok((id.trim().parse()?, bias.trim().parse()?))
+    }
+}
+
+impl LogitsTransform for LogitBias {
+    fn stage(&self) -> TransformStage {
+        TransformStage::Bias
+    }
+
+    fn apply(&mut self, logits: &mut [f32], _context: &TransformContext) -> Result<()> {
+        for (&id, &bias) in &self.biases {
+            if let Some(logit) = logits.get_mut(id as usize) {
+                *logit += bias;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Controls how soon a reply ends: shifts the end-of-sequence logit by
+/// `eos_bias` (positive to wrap up sooner, negative to run on) and forbids
+/// it until `min_tokens` tokens have been generated.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct EosControl {
+    pub eos_bias: f32,
+    pub min_tokens: usize,
+}
+
+impl EosControl {
+    pub fn new(eos_bias: f32, min_tokens: usize) -> Self {
+        Self {
+            eos_bias,
+            min_tokens,
+        }
+    }
+
+    /// Whether the transform would change anything.
+    pub fn is_active(&self) -> bool {
+        self.eos_bias != 0.0 || self.min_tokens > 0
+    }
+}
+
+impl LogitsTransform for EosControl {
+    fn stage(&self) -> TransformStage {
+        TransformStage::Bias
+    }
+
+    fn apply(&mut self, logits: &mut [f32], context: &TransformContext) -> Result<()> {
+        if let Some(logit) = logits.get_mut(context.eos_token as usize) {
+            if context.generated().len() < self.min_tokens {
+                *logit = f32::NEG_INFINITY;
+            } else {
+                *logit += self.eos_bias;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Top-nσ sampling: keeps the tokens whose logit is within `n` standard
+/// deviations of the largest and masks the rest. The cut-off moves with the
+/// spread of the logits, so it keeps few tokens when the model is sure and
+/// more when it is not, and, since it scales with them, temperature does
+/// not change which tokens survive.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TopNSigma {
+    n: f32,
+}
+
+impl TopNSigma {
+    pub fn new(n: f32) -> Result<Self> {
+        if !(n.is_finite() && n > 0.0) {
+            anyhow::bail!("top-n-sigma must be a positive number, got {}", n);
+        }
+        Ok(Self { n })
+    }
+
+    pub fn n(&self) -> f32 {
+        self.n
+    }
+}
+
+impl LogitsTransform for TopNSigma {
+    fn stage(&self) -> TransformStage {
+        TransformStage::Truncation
+    }
+
+    fn apply(&mut self, logits: &mut [f32], _context: &TransformContext) -> Result<()> {
+        // Tokens masked earlier in the chain are left out of the statistics.
+        let (mut count, mut sum, mut max) = (0usize, 0f64, f32::NEG_INFINITY);
+        for &logit in logits.iter().filter(|l| l.is_finite()) {
+            count += 1;
+            sum += logit as f64;
+            max = max.max(logit);
+        }
+        if count < 2 {
+            return Ok(());
+        }
+        let mean = sum / count as f64;
+        let variance = logits
+            .iter()
+            .filter(|l| l.is_finite())
+            .map(|&l| (l as f64 - mean).powi(2))
+            .sum::<f64>()
+            / count as f64;
+        let threshold = max - self.n * variance.sqrt() as f32;
+        for logit in logits.iter_mut() {
+            if *logit < threshold {
+                *logit = f32::NEG_INFINITY;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Log probability of one generated token and the most likely alternatives
+/// at that step, taken from the logits after all transforms but before
+/// temperature and top-k/top-p.
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct TokenLogprob {
+    pub token_id: u32,
+    pub token: String,
+    pub logprob: f32,
+    /// Most likely tokens at this step, best first.
+    pub top: Vec<TopLogprob>,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct TopLogprob {
+    pub token_id: u32,
+    pub token: String,
+    pub logprob: f32,
+}
+
+/// A possible next token, from [`Generator::peek_next_token_distribution`].
+///
+/// [`Generator::peek_next_token_distribution`]: crate::inference::Generator::peek_next_token_distribution
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct TokenCandidate {
+    pub token_id: u32,
+    pub token: String,
+    /// Log probability after all transforms, before temperature and
+    /// top-k/top-p, as in [`TokenLogprob`].
+    pub logprob: f32,
+    /// Chance the sampler picks this token with its current settings; `0.0`
+    /// for tokens top-k or top-p rule out.
+    pub probability: f32,
+    /// Picking it ends the reply: the end-of-sequence token or a turn
+    /// boundary of the chat template.
+    pub ends_reply: bool,
+}
+
+/// Log-softmax of `logits` at `token`, and the `top_n` most likely token ids
+/// with their log probabilities.
+pub fn logprobs_for(logits: &[f32], token: u32, top_n: usize) -> (f32, Vec<(u32, f32)>) {
+    let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let log_sum = logits.iter().map(|&l| (l - max).exp()).sum::<f32>().ln() + max;
+    let logprob = |l: f32| l - log_sum;
+
+    let mut top: Vec<(u32, f32)> = Vec::new();
+    if top_n > 0 {
+        let mut ids: Vec<u32> = (0..logits.len() as u32).collect();
+        let n = top_n.min(ids.len());
+        ids.select_nth_unstable_by(n - 1, |&a, &b| {
+            logits[b as usize].total_cmp(&logits[a as usize])
+        });
+        ids.truncate(n);
+        ids.sort_by(|&a, &b| logits[b as usize].total_cmp(&logits[a as usize]));
+        top = ids
+            .into_iter()
+            .map(|id| (id, logprob(logits[id as usize])))
+            .collect();
+    }
+
+    let token_logprob = logits
+        .get(token as usize)
+        .map_or(f32::NEG_INFINITY, |&l| logprob(l));
+    (token_logprob, top)
+}
+
+/// Vocabularies at least this large are scanned in parallel chunks; below
+/// it the thread hand-off costs more than the scan.
+const PARALLEL_MIN_VOCAB: usize = 32 * 1024;
+const PARALLEL_CHUNK: usize = 8 * 1024;
+
+fn scan_in_parallel(len: usize) -> bool {
+    len >= PARALLEL_MIN_VOCAB && rayon::current_num_threads() > 1
+}
+
+/// Largest value in `values` and its index plus `offset`.
+fn chunk_argmax(values: &[f32], offset: usize) -> (f32, usize) {
+    let max = kernels::max_f32(values);
+    let index = values.iter().position(|&v| v == max).unwrap_or(0);
+    (max, offset + index)
+}
+
+/// Index of the largest logit, the first on ties.
+pub fn argmax(logits: &[f32]) -> u32 {
+    let best = if !scan_in_parallel(logits.len()) {
+        chunk_argmax(logits, 0)
+    } else {
+        logits
+            .par_chunks(PARALLEL_CHUNK)
+            .enumerate()
+            .map(|(i, chunk)| chunk_argmax(chunk, i * PARALLEL_CHUNK))
+            .reduce(
+                || (f32::NEG_INFINITY, 0),
+                |a, b| if b.0 > a.0 || (b.0 == a.0 && b.1 < a.1) { b } else { a },
+            )
+    };
+    best.1 as u32
+}
+
+/// Orders ids by descending logit, then by id.
+fn by_logit(logits: &[f32]) -> impl Fn(&u32, &u32) -> std::cmp::Ordering + '_ {
+    |&a, &b| {
+        logits[b as usize]
+            .total_cmp(&logits[a as usize])
+            .then(a.cmp(&b))
+    }
+}
+
+/// Keeps the first `k` of `ids` in [`by_logit`] order, unsorted.
+fn keep_largest(ids: &mut Vec<u32>, logits: &[f32], k: usize) {
+    if ids.len() > k {
+        ids.select_nth_unstable_by(k - 1, by_logit(logits));
+        ids.truncate(k);
+    }
+}
+
+/// Ids of the `k` largest logits, best first, ties by id. Each chunk keeps
+/// its own `k` best with a partial sort, so nothing sorts the whole
+/// vocabulary.
+pub fn top_k(logits: &[f32], k: usize) -> Vec<u32> {
+    let mut ids = Vec::new();
+    top_k_into(logits, k, &mut ids);
+    ids
+}
+
+/// [`top_k`] into `ids`, reusing its allocation.
+pub fn top_k_into(logits: &[f32], k: usize, ids: &mut Vec<u32>) {
+    ids.clear();
+    let k = k.min(logits.len());
+    if k == 0 {
+        return;
+    }
+    let chunk_best = |(i, chunk): (usize, &[f32])| {
+        let offset = (i * PARALLEL_CHUNK) as u32;
+        let mut ids: Vec<u32> = (offset..offset + chunk.len() as u32).collect();
+        keep_largest(&mut ids, logits, k);
+        ids
+    };
+    if !scan_in_parallel(logits.len()) {
+        ids.extend(0..logits.len() as u32);
+    } else {
+        ids.par_extend(
+            logits
+                .par_chunks(PARALLEL_CHUNK)
+                .enumerate()
+                .flat_map_iter(chunk_best),
+        );
+    }
+    keep_largest(ids, logits, k);
+    ids.sort_unstable_by(by_logit(logits));
+}
+
+/// What the candle sampler runs with once [`TokenSampler`] has already cut
+/// the logits down to the top k.
+fn after_top_k(sampling: &Sampling) -> Sampling {
+    match *sampling {
+        Sampling::TopK { temperature, .. } => Sampling::All { temperature },
+        Sampling::TopKThenTopP { p, temperature, .. } => Sampling::TopP { p, temperature },
+        ref other => other.clone(),
+    }
+}
+
+/// Picks tokens for a [`Sampling`] mode, skipping work candle's sampler
+/// does over the whole vocabulary: greedy decoding takes the [`argmax`]
+/// directly, and top-k selects its candidates with [`top_k`] and runs the
+/// softmax and the draw over those alone. Every non-greedy sample still
+/// takes exactly one draw, so [`SamplerState`] positions carry over.
+pub struct TokenSampler {
+    processor: LogitsProcessor,
+    sampling: Sampling,
+    /// Top-k candidates of the current step.
+    ids: Vec<u32>,
+    candidates: Vec<f32>,
+}
+
+impl TokenSampler {
+    pub fn new(seed: u64, sampling: Sampling) -> Self {
+        Self {
+            processor: LogitsProcessor::from_sampling(seed, after_top_k(&sampling)),
+            sampling,
+            ids: Vec::new(),
+            candidates: Vec::new(),
+        }
+    }
+
+    /// A sampler positioned at `state`.
+    pub fn at(state: SamplerState, sampling: Sampling) -> Result<Self> {
+        Ok(Self {
+            processor: sampler_at(state, after_top_k(&sampling))?,
+            sampling,
+            ids: Vec::new(),
+            candidates: Vec::new(),
+        })
+    }
+
+    pub fn sampling(&self) -> &Sampling {
+        &self.sampling
+    }
+
+    /// The `top_n` tokens with the largest logits, best first, with the
+    /// chance [`sample`](Self::sample) picks each. Takes no draw. Top-p
+    /// sorts the whole vocabulary.
+    pub fn distribution(&self, logits: &[f32], top_n: usize) -> Vec<(u32, f32)> {
+        let (temperature, k, p) = match self.sampling {
+            Sampling::ArgMax => {
+                return top_k(logits, top_n)
+                    .into_iter()
+                    .enumerate()
+                    .map(|(rank, id)| (id, if rank == 0 { 1.0 } else { 0.0 }))
+                    .collect();
+            }
+            Sampling::All { temperature } | Sampling::GumbelSoftmax { temperature } => {
+                (temperature, None, None)
+            }
+            Sampling::TopK { k, temperature } => (temperature, Some(k), None),
+            Sampling::TopP { p, temperature } => (temperature, None, Some(p)),
+            Sampling::TopKThenTopP { k, p, temperature } => (temperature, Some(k), Some(p)),
+        };
+        let p = p.filter(|&p| p > 0.0 && p < 1.0);
+        // Tokens the draw is made from, before the top-p cut.
+        let pool = k.map_or(logits.len(), |k| k.clamp(1, logits.len()));
+        let whole_vocab = k.is_none() && p.is_none();
+        let ranked = if whole_vocab {
+            top_k(logits, top_n)
+        } else {
+            top_k(logits, pool.max(top_n))
+        };
+        let Some(&best) = ranked.first() else {
+            return Vec::new();
+        };
+        let max = logits[best as usize];
+        let weight = |logit: f32| ((logit - max) as f64 / temperature).exp();
+        let mut probs: Vec<f64> = ranked
+            .iter()
+            .enumerate()
+            .map(|(rank, &id)| {
+                if rank < pool {
+                    weight(logits[id as usize])
+                } else {
+                    0.0
+                }
+            })
+            .collect();
+        let total: f64 = if whole_vocab {
+            logits.iter().map(|&logit| weight(logit)).sum()
+        } else {
+            probs.iter().sum()
+        };
+        probs.iter_mut().for_each(|prob| *prob /= total);
+        if let Some(p) = p {
+            // Same cut as candle's top-p: keep tokens until their sum reaches p.
+            let mut kept = 0.0;
+            for prob in probs.iter_mut() {
+                if kept >= p {
+                    *prob = 0.0;
+                } else {
+                    kept += *prob;
+                }
+            }
+            probs.iter_mut().for_each(|prob| *prob /= kept);
+        }
+        ranked
+            .into_iter()
+            .zip(probs)
+            .take(top_n)
+            .map(|(id, prob)| (id, prob as f32))
+            .collect()
+    }
+
+    pub fn sample(&mut self, logits: &[f32]) -> Result<u32> {
+        let k = match self.sampling {
+            Sampling::TopK { k, .. } | Sampling::TopKThenTopP { k, .. } => k,
+            Sampling::ArgMax => return Ok(argmax(logits)),
+            _ => {
+                let logits = Tensor::from_slice(logits, logits.len(), &Device::Cpu)?;
+                return Ok(self.processor.sample(&logits)?);
+            }
+        };
+        top_k_into(logits, k.max(1), &mut self.ids);
+        self.candidates.clear();
+        self.candidates
+            .extend(self.ids.iter().map(|&id| logits[id as usize]));
+        let candidates = Tensor::from_slice(&self.candidates, self.ids.len(), &Device::Cpu)?;
+        let index = self.processor.sample(&candidates)?;
+        Ok(self.ids[index as usize])
+    }
+}
+
+/// Position in the sampler's random stream: its seed and the number of
+/// draws taken since seeding. Restoring it continues the stream exactly
+/// where it was, so a checkpointed generation resumes bit for bit.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct SamplerState {
+    pub seed: u64,
+    pub draws: u64,
+}
+
+/// A sampler for `sampling` positioned at `state`.
+///
+/// The random generator inside candle's sampler is private, so its draws
+/// are replayed instead: every non-greedy sample takes exactly one draw,
+/// whatever the distribution, so sampling a two-token dummy distribution
+/// `state.draws` times advances it identically. Greedy sampling takes no
+/// draws and is returned freshly seeded.
+pub(crate) fn sampler_at(state: SamplerState, sampling: Sampling) -> Result<LogitsProcessor> {
+    let replay = sampling != Sampling::ArgMax;
+    let mut sampler = LogitsProcessor::from_sampling(state.seed, sampling);
+    if replay && state.draws > 0 {
+        let dummy = Tensor::new(&[0f32, 0.0], &Device::Cpu)?;
+        for _ in 0..state.draws {
+            sampler.sample(&dummy)?;
+        }
+    }
+    Ok(sampler)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use candle_core::{Device, Tensor};
+    use candle_transformers::generation::{LogitsProcessor, Sampling};
+
+    use super::{
+        argmax, logprobs_for, sampler_at, top_k, EosControl, LogitBias, LogitsChain,
+        LogitsTransform, SamplerState, TokenSampler, TopNSigma, TransformContext, TransformStage,
+    };
+
+    /// Records its label into the first logit so ordering is observable.
+    struct Tag(TransformStage, f32);
+
+    impl LogitsTransform for Tag {
+        fn stage(&self) -> TransformStage {
+            self.0
+        }
+
+        fn apply(&mut self, logits: &mut [f32], _: &TransformContext) -> anyhow::Result<()> {
+            logits[0] = logits[0] * 10.0 + self.1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn runs_transforms_in_stage_order() {
+        let mut chain = LogitsChain::default();
+        chain.push(Box::new(Tag(TransformStage::Grammar, 3.0)));
+        chain.push(Box::new(Tag(TransformStage::Penalty, 1.0)));
+        chain.push(Box::new(Tag(TransformStage::Bias, 2.0)));
+
+        let mut logits = vec![0.0];
+        let context = TransformContext {
+            tokens: &[],
+            prompt_len: 0,
+            eos_token: 0,
+        };
+        chain.apply_slice(&mut logits, &context).unwrap();
+        assert_eq!(logits[0], 123.0);
+    }
+
+    #[test]
+    fn logit_bias_shifts_selected_tokens() {
+        let (id, bias) = LogitBias::parse("2=-1.5").unwrap();
+        let mut transform = LogitBias::new(HashMap::from([(id, bias), (99, 1.0)]));
+        let mut logits = vec![0.0, 0.0, 1.0];
+        let context = TransformContext {
+            tokens: &[],
+            prompt_len: 0,
+            eos_token: 0,
+        };
+        transform.apply(&mut logits, &context).unwrap();
+        assert_eq!(logits, vec![0.0, 0.0, -0.5]);
+        assert!(LogitBias::parse("nope").is_err());
+    }
+
+    #[test]
+    fn top_n_sigma_keeps_tokens_near_the_max() {
+        let context = TransformContext {
+            tokens: &[],
+            prompt_len: 0,
+            eos_token: 0,
+        };
+        // Mean 2, standard deviation about 2.5: n = 1 keeps logits above 3.5.
+        let mut logits = vec![0.0, 0.0, 0.0, 4.0, 6.0, f32::NEG_INFINITY];
+        let mut transform = TopNSigma::new(1.0).unwrap();
+        transform.apply(&mut logits, &context).unwrap();
+        assert_eq!(
+            logits,
+            vec![
+                f32::NEG_INFINITY,
+                f32::NEG_INFINITY,
+                f32::NEG_INFINITY,
+                4.0,
+                6.0,
+                f32::NEG_INFINITY
+            ]
+        );
+
+        // Scaling the logits, as temperature does, keeps the same tokens.
+        let mut scaled = vec![0.0, 0.0, 0.0, 2.0, 3.0];
+        transform.apply(&mut scaled, &context).unwrap();
+        assert!(scaled[..3].iter().all(|l| l.is_infinite()) && scaled[3] == 2.0);
+
+        assert!(TopNSigma::new(0.0).is_err() && TopNSigma::new(f32::NAN).is_err());
+        assert_eq!(TopNSigma::new(1.5).unwrap().stage(), TransformStage::Truncation);
+    }
+
+    #[test]
+    fn eos_control_holds_then_biases_the_end_token() {
+        let mut transform = EosControl::new(2.0, 2);
+        let tokens = [7, 8, 9];
+        let mut logits = vec![0.0, 1.0];
+        let early = TransformContext {
+            tokens: &tokens,
+            prompt_len: 2,
+            eos_token: 1,
+        };
+        transform.apply(&mut logits, &early).unwrap();
+        assert_eq!(logits, vec![0.0, f32::NEG_INFINITY]);
+
+        let mut logits = vec![0.0, 1.0];
+        let late = TransformContext {
+            prompt_len: 1,
+            ..early
+        };
+        transform.apply(&mut logits, &late).unwrap();
+        assert_eq!(logits, vec![0.0, 3.0]);
+        assert!(!EosControl::default().is_active());
+    }
+
+    #[test]
+    fn logprobs_are_normalized_and_ranked() {
+        let logits = [1.0f32, 3.0, 2.0, f32::NEG_INFINITY];
+        let (logprob, top) = logprobs_for(&logits, 2, 2);
+
+        let total: f32 = (0..logits.len() as u32)
+            .map(|id| logprobs_for(&logits, id, 0).0.exp())
+            .sum();
+        assert!((total - 1.0).abs() < 1e-5);
+        assert_eq!(top.iter().map(|t| t.0).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(top[1].1, logprob);
+        assert!(top[0].1 < 0.0 && top[0].1 > logprob);
+    }
+
+    #[test]
+    fn fast_paths_match_a_full_sort() {
+        // Large enough for the parallel path, with ties and masked tokens.
+        let logits: Vec<f32> = (0..100_000u32)
+            .map(|i| match i % 7 {
+                0 => f32::NEG_INFINITY,
+                _ => ((i * 7919) % 10_007) as f32 / 100.0,
+            })
+            .collect();
+        let mut sorted: Vec<u32> = (0..logits.len() as u32).collect();
+        sorted.sort_by(|&a, &b| {
+            logits[b as usize]
+                .total_cmp(&logits[a as usize])
+                .then(a.cmp(&b))
+        });
+        assert_eq!(top_k(&logits, 40), sorted[..40]);
+        assert_eq!(argmax(&logits), sorted[0]);
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(4)
+            .build()
+            .unwrap();
+        pool.install(|| {
+            assert_eq!(top_k(&logits, 40), sorted[..40]);
+            assert_eq!(argmax(&logits), sorted[0]);
+        });
+        assert_eq!(top_k(&logits[..5], 10), vec![1, 2, 3, 4, 0]);
+        assert_eq!(argmax(&[1.0, 3.0, 2.0, 3.0]), 1);
+
+        let mut sampler = TokenSampler::new(3, Sampling::ArgMax);
+        assert_eq!(sampler.sample(&logits).unwrap(), sorted[0]);
+        let mut sampler = TokenSampler::new(
+            3,
+            Sampling::TopK {
+                k: 5,
+                temperature: 1.0,
+            },
+        );
+        for _ in 0..20 {
+            assert!(sorted[..5].contains(&sampler.sample(&logits).unwrap()));
+        }
+    }
+
+    #[test]
+    fn distribution_follows_the_sampling_settings() {
+        let logits = [2.0f32, 0.0, 1.0, 3.0];
+        let sampler = TokenSampler::new(1, Sampling::ArgMax);
+        assert_eq!(sampler.distribution(&logits, 2), vec![(3, 1.0), (0, 0.0)]);
+
+        let sampler = TokenSampler::new(1, Sampling::All { temperature: 1.0 });
+        let all = sampler.distribution(&logits, 4);
+        assert_eq!(
+            all.iter().map(|c| c.0).collect::<Vec<_>>(),
+            vec![3, 0, 2, 1]
+        );
+        for &(id, prob) in &all {
+            assert!((prob.ln() - logprobs_for(&logits, id, 0).0).abs() < 1e-5);
+        }
+
+        let sampler = TokenSampler::new(
+            1,
+            Sampling::TopK {
+                k: 2,
+                temperature: 1.0,
+            },
+        );
+        let top = sampler.distribution(&logits, 3);
+        assert!((top[0].1 + top[1].1 - 1.0).abs() < 1e-6);
+        assert_eq!(top[2], (2, 0.0));
+
+        // 3 and 0 hold about 0.88 of the mass; p = 0.8 keeps both.
+        let sampler = TokenSampler::new(
+            1,
+            Sampling::TopP {
+                p: 0.8,
+                temperature: 1.0,
+            },
+        );
+        let nucleus = sampler.distribution(&logits, 4);
+        assert!(nucleus[0].1 > 0.7 && nucleus[1].1 > 0.2);
+        assert_eq!((nucleus[2].1, nucleus[3].1), (0.0, 0.0));
+    }
+
+    #[test]
+    fn restored_sampler_continues_the_random_stream() {
+        let logits = Tensor::new(&[0.5f32, 1.0, 0.2, 0.9, 0.7, 1.1], &Device::Cpu).unwrap();
+        for sampling in [
+            Sampling::All { temperature: 1.0 },
+            Sampling::TopK {
+                k: 3,
+                temperature: 0.8,
+            },
+            Sampling::TopKThenTopP {
+                k: 4,
+                p: 0.7,
+                temperature: 1.2,
+            },
+        ] {
+            let mut original = LogitsProcessor::from_sampling(7, sampling.clone());
+            let tokens: Vec<u32> = (0..40).map(|_| original.sample(&logits).unwrap()).collect();
+
+            let state = SamplerState { seed: 7, draws: 25 };
+            let mut restored = sampler_at(state, sampling.clone()).unwrap();
+            let resumed: Vec<u32> = (0..15).map(|_| restored.sample(&logits).unwrap()).collect();
+            assert_eq!(resumed, tokens[25..], "{:?}", sampling);
+
+            let values = logits.to_vec1::<f32>().unwrap();
+            let mut original = TokenSampler::new(7, sampling.clone());
+            let tokens: Vec<u32> = (0..40).map(|_| original.sample(&values).unwrap()).collect();
+            let mut restored = TokenSampler::at(state, sampling.clone()).unwrap();
+            let resumed: Vec<u32> = (0..15).map(|_| restored.sample(&values).unwrap()).collect();
+            assert_eq!(resumed, tokens[25..], "{:?}", sampling);
+        }
+    }
+}
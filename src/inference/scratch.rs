@@ -0,0 +1,105 @@
+//! Reusable buffers for the CPU side of a decode step.
+//!
+//! The model hands back a fresh logits tensor every step. The repeat
+//! penalty, the logits transforms and the sampler used to copy it into a new
+//! `Vec` and back into a new tensor in turn, so a 128k vocabulary meant
+//! several half-megabyte allocations per token. [`StepScratch`] copies the
+//! logits off the tensor once, into a buffer sized for the vocabulary when
+//! the generator is created, and every later stage works on that slice.
+
+use anyhow::Result;
+use candle_core::{DType, Storage, Tensor};
+
+#[derive(Clone, Debug, Default)]
+pub struct StepScratch {
+    logits: Vec<f32>,
+    /// Distinct tokens in the repeat-penalty window.
+    seen: Vec<u32>,
+}
+
+impl StepScratch {
+    /// A buffer sized for `vocab_size` logits.
+    pub fn new(vocab_size: usize) -> Self {
+        Self {
+            logits: Vec::with_capacity(vocab_size),
+            seen: Vec::new(),
+        }
+    }
+
+    /// Copies `logits`, a vector of one step's logits, into the buffer.
+    /// Allocates only if the vocabulary outgrew it.
+    pub fn load(&mut self, logits: &Tensor) -> Result<&mut [f32]> {
+        let logits = logits.to_dtype(DType::F32)?;
+        self.logits.clear();
+        let (storage, layout) = logits.storage_and_layout();
+        match (&*storage, layout.contiguous_offsets()) {
+            (Storage::Cpu(cpu), Some((start, end))) => {
+                self.logits
+                    .extend_from_slice(&cpu.as_slice::<f32>()?[start..end]);
+            }
+            _ => {
+                drop(storage);
+                self.logits.extend(logits.flatten_all()?.to_vec1::<f32>()?);
+            }
+        }
+        Ok(&mut self.logits)
+    }
+
+    pub fn logits(&self) -> &[f32] {
+        &self.logits
+    }
+
+    pub fn logits_mut(&mut self) -> &mut [f32] {
+        &mut self.logits
+    }
+
+    /// Penalizes every token of `context` once, like candle's
+    /// `apply_repeat_penalty`: positive logits are divided by `penalty`,
+    /// negative ones multiplied.
+    pub fn apply_repeat_penalty(&mut self, penalty: f32, context: &[u32]) {
+        self.seen.clear();
+        self.seen.extend_from_slice(context);
+        self.seen.sort_unstable();
+        self.seen.dedup();
+        for &token in &self.seen {
+            if let Some(logit) = self.logits.get_mut(token as usize) {
+                if *logit >= 0.0 {
+                    *logit /= penalty;
+                } else {
+                    *logit *= penalty;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use candle_core::{Device, Tensor};
+    use candle_transformers::utils::apply_repeat_penalty;
+
+    use super::StepScratch;
+
+    #[test]
+    fn matches_candle_repeat_penalty_without_reallocating() {
+        let values = [1.5f32, -2.0, 0.0, 3.0, -0.5];
+        let tensor = Tensor::new(&values, &Device::Cpu).unwrap();
+        let context = [1, 3, 3, 0, 9];
+        let expected = apply_repeat_penalty(&tensor, 1.3, &context)
+            .unwrap()
+            .to_vec1::<f32>()
+            .unwrap();
+
+        let mut scratch = StepScratch::new(values.len());
+        let buffer = scratch.logits().as_ptr();
+        assert_eq!(scratch.load(&tensor).unwrap(), &values);
+        scratch.apply_repeat_penalty(1.3, &context);
+        assert_eq!(scratch.logits(), expected.as_slice());
+        assert_eq!(scratch.logits().as_ptr(), buffer);
+
+        // A strided view takes the slow path and still reads in order.
+        let matrix = Tensor::new(&[[1f32, 2.0], [3.0, 4.0]], &Device::Cpu).unwrap();
+        let column = matrix.t().unwrap().get(0).unwrap();
+        assert_eq!(scratch.load(&column).unwrap(), &[1.0, 3.0]);
+    }
+}
@@ -0,0 +1,202 @@
+//! Saved conversations that can be resumed later.
+//!
+//! A session stores the history together with everything that shaped it:
+//! system prompt, sampler settings, seed, and a fingerprint of the model
+//! file. Resuming against a different model is detected through the
+//! fingerprint so callers can refuse or warn.
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+
+use super::generator::Message;
+use super::sampler::SamplerState;
+
+const SESSION_VERSION: u32 = 1;
+
+/// Bytes hashed from each end of the model file. The head covers the GGUF
+/// header and metadata; the tail catches files that differ only in weights.
+const FINGERPRINT_SPAN: u64 = 4 * 1024 * 1024;
+
+/// Identifies a model file without hashing all of it.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ModelFingerprint {
+    pub file_name: String,
+    pub size_bytes: u64,
+    /// SHA-256 over the file size and its first and last 4 MiB.
+    pub sha256: String,
+}
+
+impl ModelFingerprint {
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let mut file =
+            File::open(path).with_context(|| format!("Failed to open model {:?}", path))?;
+        let size_bytes = file.metadata()?.len();
+
+        let mut hasher = Sha256::new();
+        hasher.update(size_bytes.to_le_bytes());
+        let mut buffer = Vec::with_capacity(FINGERPRINT_SPAN as usize);
+        (&mut file).take(FINGERPRINT_SPAN).read_to_end(&mut buffer)?;
+        hasher.update(&buffer);
+        if size_bytes > 2 * FINGERPRINT_SPAN {
+            buffer.clear();
+            file.seek(SeekFrom::End(-(FINGERPRINT_SPAN as i64)))?;
+            file.read_to_end(&mut buffer)?;
+            hasher.update(&buffer);
+        }
+
+        Ok(Self {
+            file_name: path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            size_bytes,
+            sha256: hasher
+                .finalize()
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect(),
+        })
+    }
+
+    /// Whether both fingerprints are of the same file contents.
+    pub fn same_file(&self, other: &Self) -> bool {
+        self.sha256 == other.sha256 && self.size_bytes == other.size_bytes
+    }
+
+    /// Name, size and shortened hash, for mismatch messages.
+    pub fn summary(&self) -> String {
+        format!(
+            "{} ({} bytes, sha256 {}…)",
+            self.file_name,
+            self.size_bytes,
+            &self.sha256[..self.sha256.len().min(12)]
+        )
+    }
+}
+
+/// Sampler settings a conversation was generated with.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SessionParams {
+    pub temperature: f64,
+    pub top_p: Option<f64>,
+    pub top_k: Option<usize>,
+    pub repeat_penalty: f32,
+    pub repeat_last_n: usize,
+    /// `0` means `auto`.
+    pub max_tokens: usize,
+    pub seed: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Session {
+    pub version: u32,
+    pub saved_at: chrono::DateTime<chrono::Utc>,
+    pub model: ModelFingerprint,
+    pub system_prompt: Option<String>,
+    pub params: SessionParams,
+    /// Conversation history, excluding the system prompt and examples.
+    pub messages: Vec<Message>,
+    /// Sampler position when saved; resuming continues its random stream.
+    /// Absent in sessions from older versions, which resume reseeded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sampler: Option<SamplerState>,
+}
+
+impl Session {
+    pub fn new(
+        model: ModelFingerprint,
+        system_prompt: Option<String>,
+        params: SessionParams,
+        messages: Vec<Message>,
+    ) -> Self {
+        Self {
+            version: SESSION_VERSION,
+            saved_at: chrono::Utc::now(),
+            model,
+            system_prompt,
+            params,
+            messages,
+            sampler: None,
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, json).with_context(|| format!("Failed to write session {:?}", path))
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read session {:?}", path))?;
+        let session: Self =
+            serde_json::from_str(&text).with_context(|| format!("Invalid session {:?}", path))?;
+        if session.version > SESSION_VERSION {
+            anyhow::bail!(
+                "Session {:?} has version {}, newer than the supported {}",
+                path,
+                session.version,
+                SESSION_VERSION
+            );
+        }
+        Ok(session)
+    }
+
+    /// Describes how `model` differs from the model this session was saved
+    /// with, or `None` if it is the same file.
+    pub fn model_mismatch(&self, model: &ModelFingerprint) -> Option<String> {
+        if self.model.same_file(model) {
+            return None;
+        }
+        Some(format!(
+            "session was saved with {}, current model is {}",
+            self.model.summary(),
+            model.summary()
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ModelFingerprint, Session, SessionParams};
+    use crate::inference::Message;
+
+    #[test]
+    fn round_trips_and_detects_model_changes() {
+        let dir = std::env::temp_dir().join(format!("oxide-session-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let model_a = dir.join("a.gguf");
+        let model_b = dir.join("b.gguf");
+        std::fs::write(&model_a, b"GGUF model a").unwrap();
+        std::fs::write(&model_b, b"GGUF model b").unwrap();
+
+        let fingerprint = ModelFingerprint::from_file(&model_a).unwrap();
+        let session = Session::new(
+            fingerprint.clone(),
+            Some("Be brief.".to_string()),
+            SessionParams {
+                temperature: 0.7,
+                top_p: Some(0.9),
+                top_k: None,
+                repeat_penalty: 1.1,
+                repeat_last_n: 64,
+                max_tokens: 0,
+                seed: 42,
+            },
+            vec![Message::new("user", "hi"), Message::new("assistant", "hello")],
+        );
+        let path = dir.join("chat.json");
+        session.save(&path).unwrap();
+        let loaded = Session::load(&path).unwrap();
+        assert_eq!(loaded, session);
+
+        assert!(loaded.model_mismatch(&fingerprint).is_none());
+        let other = ModelFingerprint::from_file(&model_b).unwrap();
+        assert!(loaded.model_mismatch(&other).unwrap().contains("b.gguf"));
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
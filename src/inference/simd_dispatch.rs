@@ -23,6 +23,7 @@ pub enum SimdLevel {
 }
 
 impl SimdLevel {
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Self {
         match s.to_lowercase().as_str() {
             "avx512" => SimdLevel::Avx512,
@@ -46,6 +47,10 @@ pub struct CpuFeatures {
     pub has_avx2: bool,
     pub has_avx: bool,
     pub has_neon: bool,
+    /// aarch64 `SDOT`/`UDOT` int8 dot-product instructions.
+    pub has_dotprod: bool,
+    /// aarch64 int8 matrix-multiply instructions.
+    pub has_i8mm: bool,
     pub num_cores: usize,
     pub num_physical_cores: usize,
 }
@@ -70,6 +75,8 @@ impl CpuFeatures {
                 has_avx2: false,
                 has_avx: false,
                 has_neon: false,
+                has_dotprod: false,
+                has_i8mm: false,
                 num_cores,
                 num_physical_cores,
             }
@@ -85,6 +92,8 @@ impl CpuFeatures {
             has_avx2: true,
             has_avx: true,
             has_neon: false,
+            has_dotprod: false,
+            has_i8mm: false,
             num_cores,
             num_physical_cores,
         }
@@ -93,12 +102,16 @@ impl CpuFeatures {
     #[cfg(target_arch = "aarch64")]
     fn detect_arm(num_cores: usize, num_physical_cores: usize) -> Self {
         let has_neon = std::arch::is_aarch64_feature_detected!("neon");
+        let has_dotprod = std::arch::is_aarch64_feature_detected!("dotprod");
+        let has_i8mm = std::arch::is_aarch64_feature_detected!("i8mm");
 
         Self {
             has_avx512: false,
             has_avx2: false,
             has_avx: false,
             has_neon,
+            has_dotprod,
+            has_i8mm,
             num_cores,
             num_physical_cores,
         }
@@ -129,11 +142,13 @@ impl SimdDispatch {
             };
 
             tracing::info!(
-                "SIMD dispatch initialized: {:?} (detected: AVX512: {}, AVX2: {}, NEON: {})",
+                "SIMD dispatch initialized: {:?} (detected: AVX512: {}, AVX2: {}, NEON: {}, DOTPROD: {}, I8MM: {})",
                 actual_level,
                 cpu_features.has_avx512,
                 cpu_features.has_avx2,
-                cpu_features.has_neon
+                cpu_features.has_neon,
+                cpu_features.has_dotprod,
+                cpu_features.has_i8mm
             );
 
             Self {
@@ -0,0 +1,70 @@
+//! Caller-defined stopping criteria checked during decoding.
+
+use std::time::Duration;
+
+/// What a stop condition sees before every decode step.
+#[derive(Clone, Copy, Debug)]
+pub struct StopContext<'a> {
+    /// The reply generated so far, as streamed to the caller.
+    pub text: &'a str,
+    /// Tokens generated so far.
+    pub tokens: usize,
+    /// Time since the generation started, including the prompt.
+    pub elapsed: Duration,
+}
+
+/// A condition that ends the generation when it returns `true`.
+pub type StopCondition = Box<dyn FnMut(&StopContext) -> bool + Send>;
+
+/// The stop conditions registered on a generator.
+#[derive(Default)]
+pub(crate) struct StopConditions(Vec<StopCondition>);
+
+impl StopConditions {
+    pub fn push(&mut self, condition: StopCondition) {
+        self.0.push(condition);
+    }
+
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Whether any condition asks to stop. Conditions after the first that
+    /// does are not called.
+    pub fn should_stop(&mut self, context: &StopContext) -> bool {
+        self.0.iter_mut().any(|condition| condition(context))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{StopConditions, StopContext};
+
+    #[test]
+    fn stops_when_any_condition_matches() {
+        let mut conditions = StopConditions::default();
+        conditions.push(Box::new(|ctx: &StopContext| ctx.text.contains("</answer>")));
+        conditions.push(Box::new(|ctx: &StopContext| {
+            ctx.text.matches("```").count() >= 4
+        }));
+        let at = |text| StopContext {
+            text,
+            tokens: 1,
+            elapsed: Duration::ZERO,
+        };
+
+        assert!(!conditions.should_stop(&at("<answer>42")));
+        assert!(conditions.should_stop(&at("<answer>42</answer>")));
+        assert!(!conditions.should_stop(&at("```rust\nfn a() {}\n```\n```")));
+        assert!(conditions.should_stop(&at("```\na\n```\n```\nb\n```")));
+
+        conditions.clear();
+        assert!(!conditions.should_stop(&at("</answer>")));
+    }
+}
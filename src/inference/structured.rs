@@ -0,0 +1,298 @@
+//! Structured (JSON) output support.
+//!
+//! Decoding is not constrained token by token; instead the reply is parsed and
+//! checked against a JSON Schema so the caller can retry with the validation
+//! error as feedback. The validator covers the commonly used subset of JSON
+//! Schema: `type`, `enum`, `const`, `properties`, `required`,
+//! `additionalProperties`, `items`, `anyOf`/`oneOf`/`allOf`, and the basic
+//! length and range keywords.
+
+use serde_json::Value;
+
+/// Output format requested from the model.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum ResponseFormat {
+    /// Free-form text.
+    #[default]
+    Text,
+    /// A single JSON value matching the given JSON Schema.
+    JsonSchema(Value),
+}
+
+impl ResponseFormat {
+    /// Instruction appended to the user prompt, or `None` for plain text.
+    pub fn instruction(&self) -> Option<String> {
+        match self {
+            ResponseFormat::Text => None,
+            ResponseFormat::JsonSchema(schema) => Some(format!(
+                "Respond with only a JSON value that matches this JSON Schema, with no other text:\n{}",
+                schema
+            )),
+        }
+    }
+
+    /// Parses `text` and validates it against the schema.
+    ///
+    /// Returns `Ok(None)` for [`ResponseFormat::Text`]. The error string is
+    /// phrased so it can be sent back to the model as retry feedback.
+    pub fn parse(&self, text: &str) -> Result<Option<Value>, String> {
+        match self {
+            ResponseFormat::Text => Ok(None),
+            ResponseFormat::JsonSchema(schema) => {
+                let value = extract_json(text).ok_or("the reply is not valid JSON")?;
+                validate(schema, &value)?;
+                Ok(Some(value))
+            }
+        }
+    }
+}
+
+/// Extracts the first JSON object or array from a model reply, tolerating
+/// Markdown code fences and surrounding prose.
+pub fn extract_json(text: &str) -> Option<Value> {
+    let trimmed = text.trim();
+    if let Ok(value) = serde_json::from_str(trimmed) {
+        return Some(value);
+    }
+
+    let start = trimmed.find(['{', '['])?;
+    serde_json::Deserializer::from_str(&trimmed[start..])
+        .into_iter::<Value>()
+        .next()?
+        .ok()
+}
+
+/// Validates `value` against `schema`, returning the first violation found.
+pub fn validate(schema: &Value, value: &Value) -> Result<(), String> {
+    validate_at(schema, value, "$")
+}
+
+fn validate_at(schema: &Value, value: &Value, path: &str) -> Result<(), String> {
+    let schema = match schema {
+        Value::Bool(true) => return Ok(()),
+        Value::Bool(false) => return Err(format!("{}: no value is allowed here", path)),
+        Value::Object(schema) => schema,
+        _ => return Ok(()),
+    };
+
+    if let Some(expected) = schema.get("type") {
+        let matches = match expected {
+            Value::String(name) => type_matches(name, value),
+            Value::Array(names) => names
+                .iter()
+                .filter_map(Value::as_str)
+                .any(|name| type_matches(name, value)),
+            _ => true,
+        };
+        if !matches {
+            return Err(format!(
+                "{}: expected {}, found {}",
+                path,
+                expected,
+                type_name(value)
+            ));
+        }
+    }
+
+    if let Some(Value::Array(options)) = schema.get("enum") {
+        if !options.contains(value) {
+            return Err(format!(
+                "{}: {} is not one of {}",
+                path,
+                value,
+                Value::Array(options.clone())
+            ));
+        }
+    }
+
+    if let Some(expected) = schema.get("const") {
+        if expected != value {
+            return Err(format!("{}: expected {}, found {}", path, expected, value));
+        }
+    }
+
+    if let Some(Value::Array(all)) = schema.get("allOf") {
+        for sub in all {
+            validate_at(sub, value, path)?;
+        }
+    }
+
+    if let Some(Value::Array(any)) = schema.get("anyOf") {
+        if !any.iter().any(|sub| validate_at(sub, value, path).is_ok()) {
+            return Err(format!(
+                "{}: does not match any of the allowed schemas",
+                path
+            ));
+        }
+    }
+
+    if let Some(Value::Array(one)) = schema.get("oneOf") {
+        let matched = one
+            .iter()
+            .filter(|sub| validate_at(sub, value, path).is_ok())
+            .count();
+        if matched != 1 {
+            return Err(format!(
+                "{}: must match exactly one allowed schema, matched {}",
+                path, matched
+            ));
+        }
+    }
+
+    match value {
+        Value::Object(object) => {
+            if let Some(Value::Array(required)) = schema.get("required") {
+                for key in required.iter().filter_map(Value::as_str) {
+                    if !object.contains_key(key) {
+                        return Err(format!("{}: missing required property \"{}\"", path, key));
+                    }
+                }
+            }
+
+            let properties = schema.get("properties").and_then(Value::as_object);
+            for (key, item) in object {
+                let item_path = format!("{}.{}", path, key);
+                match properties.and_then(|props| props.get(key)) {
+                    Some(sub) => validate_at(sub, item, &item_path)?,
+                    None => match schema.get("additionalProperties") {
+                        Some(Value::Bool(false)) => {
+                            return Err(format!("{}: unexpected property \"{}\"", path, key));
+                        }
+                        Some(sub) => validate_at(sub, item, &item_path)?,
+                        None => {}
+                    },
+                }
+            }
+        }
+        Value::Array(items) => {
+            check_bounds(schema, "minItems", "maxItems", items.len(), "items", path)?;
+            if let Some(sub) = schema.get("items") {
+                for (i, item) in items.iter().enumerate() {
+                    validate_at(sub, item, &format!("{}[{}]", path, i))?;
+                }
+            }
+        }
+        Value::String(s) => {
+            check_bounds(
+                schema,
+                "minLength",
+                "maxLength",
+                s.chars().count(),
+                "characters",
+                path,
+            )?;
+        }
+        Value::Number(n) => {
+            let n = n.as_f64().unwrap_or_default();
+            if let Some(min) = schema.get("minimum").and_then(Value::as_f64) {
+                if n < min {
+                    return Err(format!("{}: {} is less than the minimum {}", path, n, min));
+                }
+            }
+            if let Some(max) = schema.get("maximum").and_then(Value::as_f64) {
+                if n > max {
+                    return Err(format!(
+                        "{}: {} is greater than the maximum {}",
+                        path, n, max
+                    ));
+                }
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+fn check_bounds(
+    schema: &serde_json::Map<String, Value>,
+    min_key: &str,
+    max_key: &str,
+    len: usize,
+    unit: &str,
+    path: &str,
+) -> Result<(), String> {
+    if let Some(min) = schema.get(min_key).and_then(Value::as_u64) {
+        if (len as u64) < min {
+            return Err(format!(
+                "{}: expected at least {} {}, found {}",
+                path, min, unit, len
+            ));
+        }
+    }
+    if let Some(max) = schema.get(max_key).and_then(Value::as_u64) {
+        if (len as u64) > max {
+            return Err(format!(
+                "{}: expected at most {} {}, found {}",
+                path, max, unit, len
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn type_matches(name: &str, value: &Value) -> bool {
+    match name {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "boolean" => value.is_boolean(),
+        "null" => value.is_null(),
+        "number" => value.is_number(),
+        "integer" => value.is_i64() || value.is_u64(),
+        _ => true,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{extract_json, validate, ResponseFormat};
+
+    #[test]
+    fn extracts_json_from_fenced_reply() {
+        let reply = "Sure! Here it is:\n```json\n{\"name\": \"ferris\"}\n```";
+        assert_eq!(extract_json(reply), Some(json!({"name": "ferris"})));
+    }
+
+    #[test]
+    fn reports_missing_required_property() {
+        let schema = json!({
+            "type": "object",
+            "properties": {"name": {"type": "string"}, "age": {"type": "integer"}},
+            "required": ["name", "age"]
+        });
+        let err = validate(&schema, &json!({"name": "ferris"})).unwrap_err();
+        assert!(err.contains("\"age\""), "{}", err);
+    }
+
+    #[test]
+    fn reports_nested_type_errors_with_path() {
+        let schema = json!({
+            "type": "object",
+            "properties": {"tags": {"type": "array", "items": {"type": "string"}}}
+        });
+        let err = validate(&schema, &json!({"tags": ["a", 1]})).unwrap_err();
+        assert!(err.starts_with("$.tags[1]"), "{}", err);
+    }
+
+    #[test]
+    fn parse_accepts_matching_reply() {
+        let format = ResponseFormat::JsonSchema(json!({"enum": ["yes", "no"]}));
+        assert_eq!(format.parse("\"yes\"").unwrap(), Some(json!("yes")));
+        assert!(format.parse("\"maybe\"").is_err());
+        assert!(ResponseFormat::Text.parse("anything").unwrap().is_none());
+    }
+}
@@ -0,0 +1,364 @@
+//! Map-reduce summarization of documents larger than the context window.
+//!
+//! The document is split into chunks that fit the token budget, each chunk is
+//! summarized independently (map), and the summaries are combined and
+//! summarized again until a single summary remains (reduce).
+
+use anyhow::Result;
+
+use crate::inference::{Generator, StreamEvent};
+
+/// Tokens reserved for the instruction and chat template around each chunk.
+const PROMPT_OVERHEAD_TOKENS: usize = 128;
+/// Smallest chunk budget worth summarizing.
+const MIN_CHUNK_TOKENS: usize = 64;
+
+#[derive(Clone, Debug)]
+pub struct SummarizeOptions {
+    /// Token budget per chunk (`0` = derive from the context window).
+    pub chunk_tokens: usize,
+    /// Maximum tokens per generated summary (`0` = a quarter of the context).
+    pub max_tokens: usize,
+    pub repeat_penalty: f32,
+    pub repeat_last_n: usize,
+    /// Optional focus appended to every instruction, e.g. "action items".
+    pub focus: Option<String>,
+}
+
+impl Default for SummarizeOptions {
+    fn default() -> Self {
+        Self {
+            chunk_tokens: 0,
+            max_tokens: 512,
+            repeat_penalty: 1.1,
+            repeat_last_n: 64,
+            focus: None,
+        }
+    }
+}
+
+/// Progress reported while summarizing.
+pub enum SummarizeEvent {
+    /// The input was split into `chunks` pieces.
+    Chunked { chunks: usize },
+    /// A map or reduce step started. `round` is `0` for the map phase.
+    StepStarted {
+        round: usize,
+        index: usize,
+        total: usize,
+    },
+    /// A token of the final summary (intermediate steps are not streamed).
+    Token(String),
+}
+
+/// Summarizes `text`, clearing the generator's conversation history first so
+/// every step is independent.
+pub fn summarize<F>(
+    generator: &mut Generator,
+    text: &str,
+    options: &SummarizeOptions,
+    mut progress: F,
+) -> Result<String>
+where
+    F: FnMut(SummarizeEvent),
+{
+    let context_length = generator.context_limit();
+    let max_tokens = if options.max_tokens == 0 {
+        context_length / 4
+    } else {
+        options.max_tokens
+    };
+    let budget = if options.chunk_tokens == 0 {
+        context_length.saturating_sub(max_tokens + PROMPT_OVERHEAD_TOKENS)
+    } else {
+        options.chunk_tokens
+    };
+    if budget < MIN_CHUNK_TOKENS {
+        anyhow::bail!(
+            "Context window ({} tokens) leaves no room for chunks; lower --max-tokens",
+            context_length
+        );
+    }
+
+    generator.clear_history();
+    let chunks = split_chunks(text, budget, |s| generator.count_tokens(s))?;
+    if chunks.is_empty() {
+        anyhow::bail!("Nothing to summarize: the input is empty");
+    }
+    progress(SummarizeEvent::Chunked {
+        chunks: chunks.len(),
+    });
+
+    if chunks.len() == 1 {
+        let prompt = map_prompt(&chunks[0], options.focus.as_deref());
+        return run_streaming(generator, &prompt, max_tokens, options, &mut progress, 0);
+    }
+
+    let prompts: Vec<String> = chunks
+        .iter()
+        .map(|chunk| map_prompt(chunk, options.focus.as_deref()))
+        .collect();
+    let mut summaries = run_round(generator, prompts, max_tokens, options, &mut progress, 0)?;
+
+    let mut round = 1;
+    loop {
+        let groups = split_chunks(&summaries.join("\n\n"), budget, |s| {
+            generator.count_tokens(s)
+        })?;
+        if groups.len() == 1 {
+            let prompt = reduce_prompt(&groups[0], options.focus.as_deref());
+            return run_streaming(
+                generator,
+                &prompt,
+                max_tokens,
+                options,
+                &mut progress,
+                round,
+            );
+        }
+        if groups.len() >= summaries.len() {
+            anyhow::bail!(
+                "Summaries are not getting shorter ({} parts); lower --max-tokens or --chunk-tokens",
+                groups.len()
+            );
+        }
+
+        let prompts = groups
+            .iter()
+            .map(|group| reduce_prompt(group, options.focus.as_deref()))
+            .collect();
+        summaries = run_round(
+            generator,
+            prompts,
+            max_tokens,
+            options,
+            &mut progress,
+            round,
+        )?;
+        round += 1;
+    }
+}
+
+/// Summarizes every prompt of one round, one after another.
+fn run_round<F>(
+    generator: &mut Generator,
+    prompts: Vec<String>,
+    max_tokens: usize,
+    options: &SummarizeOptions,
+    progress: &mut F,
+    round: usize,
+) -> Result<Vec<String>>
+where
+    F: FnMut(SummarizeEvent),
+{
+    let total = prompts.len();
+    let mut summaries = Vec::with_capacity(total);
+
+    for (index, prompt) in prompts.iter().enumerate() {
+        progress(SummarizeEvent::StepStarted {
+            round,
+            index,
+            total,
+        });
+        let summary = generator.generate(
+            prompt,
+            max_tokens,
+            options.repeat_penalty,
+            options.repeat_last_n,
+            |_| {},
+        )?;
+        generator.clear_history();
+        summaries.push(summary.trim().to_string());
+    }
+    Ok(summaries)
+}
+
+/// Runs the final step, streaming its tokens.
+fn run_streaming<F>(
+    generator: &mut Generator,
+    prompt: &str,
+    max_tokens: usize,
+    options: &SummarizeOptions,
+    progress: &mut F,
+    round: usize,
+) -> Result<String>
+where
+    F: FnMut(SummarizeEvent),
+{
+    progress(SummarizeEvent::StepStarted {
+        round,
+        index: 0,
+        total: 1,
+    });
+    let summary = generator.generate(
+        prompt,
+        max_tokens,
+        options.repeat_penalty,
+        options.repeat_last_n,
+        |event| {
+            if let StreamEvent::Token(token) = event {
+                progress(SummarizeEvent::Token(token));
+            }
+        },
+    )?;
+    generator.clear_history();
+    Ok(summary.trim().to_string())
+}
+
+fn map_prompt(chunk: &str, focus: Option<&str>) -> String {
+    let mut prompt = String::from(
+        "Summarize the following text concisely, keeping key facts, names, and numbers.",
+    );
+    if let Some(focus) = focus {
+        prompt.push_str(&format!(" Focus on: {}.", focus));
+    }
+    prompt.push_str("\n\n");
+    prompt.push_str(chunk);
+    prompt
+}
+
+fn reduce_prompt(summaries: &str, focus: Option<&str>) -> String {
+    let mut prompt = String::from(
+        "The following are summaries of consecutive parts of one document. \
+         Combine them into a single coherent summary without repeating yourself.",
+    );
+    if let Some(focus) = focus {
+        prompt.push_str(&format!(" Focus on: {}.", focus));
+    }
+    prompt.push_str("\n\n");
+    prompt.push_str(summaries);
+    prompt
+}
+
+/// Splits `text` into chunks of at most `budget` tokens as measured by
+/// `count`, keeping paragraphs together where possible and bisecting
+/// paragraphs that are too long on their own.
+pub fn split_chunks<C>(text: &str, budget: usize, mut count: C) -> Result<Vec<String>>
+where
+    C: FnMut(&str) -> Result<usize>,
+{
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    let mut current_tokens = 0;
+
+    for paragraph in text.split("\n\n").map(str::trim).filter(|p| !p.is_empty()) {
+        let tokens = count(paragraph)?;
+        if tokens > budget {
+            if !current.is_empty() {
+                chunks.push(std::mem::take(&mut current));
+                current_tokens = 0;
+            }
+            bisect(paragraph, budget, &mut count, &mut chunks)?;
+            continue;
+        }
+
+        if !current.is_empty() && current_tokens + tokens > budget {
+            chunks.push(std::mem::take(&mut current));
+            current_tokens = 0;
+        }
+        if !current.is_empty() {
+            current.push_str("\n\n");
+        }
+        current.push_str(paragraph);
+        current_tokens += tokens;
+    }
+
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    Ok(chunks)
+}
+
+fn bisect<C>(text: &str, budget: usize, count: &mut C, out: &mut Vec<String>) -> Result<()>
+where
+    C: FnMut(&str) -> Result<usize>,
+{
+    if count(text)? <= budget {
+        out.push(text.to_string());
+        return Ok(());
+    }
+
+    let mut mid = text.len() / 2;
+    while !text.is_char_boundary(mid) {
+        mid += 1;
+    }
+    let split = text[..mid]
+        .rfind(char::is_whitespace)
+        .or_else(|| text[mid..].find(char::is_whitespace).map(|i| mid + i))
+        .unwrap_or(mid);
+    let (left, right) = text.split_at(split);
+    let (left, right) = (left.trim(), right.trim());
+    if left.is_empty() || right.is_empty() {
+        // A single unsplittable token run; keep it rather than loop forever.
+        out.push(text.to_string());
+        return Ok(());
+    }
+
+    bisect(left, budget, count, out)?;
+    bisect(right, budget, count, out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{split_chunks, summarize, SummarizeEvent, SummarizeOptions};
+    use crate::inference::generator::tests::scripted;
+
+    fn words(s: &str) -> anyhow::Result<usize> {
+        Ok(s.split_whitespace().count())
+    }
+
+    #[test]
+    fn groups_paragraphs_under_budget() {
+        let text = "one two\n\nthree four\n\nfive six seven";
+        let chunks = split_chunks(text, 4, words).unwrap();
+        assert_eq!(chunks, vec!["one two\n\nthree four", "five six seven"]);
+    }
+
+    #[test]
+    fn bisects_oversized_paragraphs() {
+        let text = "a b c d e f g h i j";
+        let chunks = split_chunks(text, 3, words).unwrap();
+        assert!(
+            chunks.iter().all(|c| words(c).unwrap() <= 3),
+            "{:?}",
+            chunks
+        );
+        assert_eq!(chunks.join(" "), text);
+    }
+
+    #[test]
+    fn summarizes_chunks_one_after_another() {
+        let dir = std::env::temp_dir().join(format!("oxide-summarize-{}", std::process::id()));
+        let mut generator = scripted(&dir, vec![1]);
+        let paragraph = vec!["ab"; 20].join(" ");
+        let text = format!("{paragraph}\n\n{paragraph}\n\n{paragraph}");
+        let options = SummarizeOptions {
+            chunk_tokens: 64,
+            max_tokens: 2,
+            ..Default::default()
+        };
+
+        let mut events = Vec::new();
+        let summary = summarize(&mut generator, &text, &options, |event| {
+            events.push(match event {
+                SummarizeEvent::Chunked { chunks } => format!("chunked {}", chunks),
+                SummarizeEvent::StepStarted {
+                    round,
+                    index,
+                    total,
+                } => format!("step {}.{}/{}", round, index, total),
+                SummarizeEvent::Token(token) => token,
+            })
+        })
+        .unwrap();
+
+        // Each chunk is its own generate call, so its step starts only after
+        // the previous summary is done, and none of them leaks into history.
+        assert_eq!(summary, "bb");
+        assert_eq!(
+            events,
+            ["chunked 3", "step 0.0/3", "step 0.1/3", "step 0.2/3", "step 1.0/1", "b", "b"]
+        );
+        assert!(generator.history().is_empty());
+    }
+}
@@ -0,0 +1,120 @@
+//! Copies streamed replies to a file or other writer, for `--tee` and
+//! `Model::generate_stream_to`.
+//!
+//! Long unattended generations may be interrupted; flushing every chunk
+//! keeps whatever was generated so far on disk.
+
+use std::io::{self, Write};
+use std::str::FromStr;
+
+/// When a [`Tee`] flushes its writer.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FlushPolicy {
+    /// After every chunk, so nothing is lost when the process dies.
+    #[default]
+    Chunk,
+    /// After chunks that end a line.
+    Line,
+    /// Once the reply is complete.
+    End,
+}
+
+impl FlushPolicy {
+    pub const ALL: [FlushPolicy; 3] = [FlushPolicy::Chunk, FlushPolicy::Line, FlushPolicy::End];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FlushPolicy::Chunk => "chunk",
+            FlushPolicy::Line => "line",
+            FlushPolicy::End => "end",
+        }
+    }
+}
+
+impl FromStr for FlushPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.to_ascii_lowercase();
+        FlushPolicy::ALL
+            .iter()
+            .copied()
+            .find(|p| p.as_str() == s)
+            .ok_or_else(|| format!("unknown flush policy '{}' (expected chunk, line or end)", s))
+    }
+}
+
+/// A writer that streamed text is copied to.
+pub struct Tee<W: Write> {
+    writer: W,
+    flush: FlushPolicy,
+}
+
+impl<W: Write> Tee<W> {
+    pub fn new(writer: W, flush: FlushPolicy) -> Self {
+        Self { writer, flush }
+    }
+
+    pub fn write(&mut self, text: &str) -> io::Result<()> {
+        self.writer.write_all(text.as_bytes())?;
+        match self.flush {
+            FlushPolicy::Chunk => self.writer.flush(),
+            FlushPolicy::Line if text.contains('\n') => self.writer.flush(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Ends a reply and flushes whatever is still buffered.
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Write};
+
+    use super::{FlushPolicy, Tee};
+
+    /// Counts flushes so the policies are observable.
+    #[derive(Default)]
+    struct Recorder {
+        text: String,
+        flushes: usize,
+    }
+
+    impl Write for Recorder {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.text.push_str(std::str::from_utf8(buf).unwrap());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.flushes += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn copies_text_and_flushes_per_policy() {
+        let flushes = |policy: FlushPolicy| {
+            let mut tee = Tee::new(Recorder::default(), policy);
+            for chunk in ["Hello", " world", ".\n", "Bye"] {
+                tee.write(chunk).unwrap();
+            }
+            tee.finish().unwrap();
+            let recorder = tee.into_inner();
+            assert_eq!(recorder.text, "Hello world.\nBye");
+            recorder.flushes
+        };
+        assert_eq!(flushes(FlushPolicy::Chunk), 5);
+        assert_eq!(flushes(FlushPolicy::Line), 2);
+        assert_eq!(flushes(FlushPolicy::End), 1);
+        assert_eq!("LINE".parse::<FlushPolicy>(), Ok(FlushPolicy::Line));
+        assert!("never".parse::<FlushPolicy>().is_err());
+    }
+}
@@ -5,9 +5,11 @@
 //!
 //! This implementation uses a portable approach that works on Linux/macOS/Windows.
 
-use rayon::{ThreadPool, ThreadPoolBuilder};
+use rayon::{ThreadBuilder, ThreadPool, ThreadPoolBuilder};
 use std::sync::OnceLock;
 
+use crate::platform;
+
 pub struct ThreadPinnerConfig {
     pub num_threads: usize,
     pub reserve_cores: usize,
@@ -58,6 +60,9 @@ impl ThreadPinnerConfig {
 
 static THREAD_PINNER: OnceLock<ThreadPinner> = OnceLock::new();
 
+/// Size of the pool [`ThreadPinner::install_shared_pool`] installed.
+static SHARED_POOL: OnceLock<usize> = OnceLock::new();
+
 #[derive(Clone)]
 pub struct ThreadPinner {
     config: ThreadPinnerConfig,
@@ -105,119 +110,109 @@ impl ThreadPinner {
         available
     }
 
-    #[cfg(target_os = "linux")]
     pub fn pin_current_thread(&self) -> bool {
-        if !self.config.enabled || self.core_ids.is_empty() {
-            return false;
+        match self.core_ids.first() {
+            Some(&core_id) if self.config.enabled => Self::pin_to(core_id, 0),
+            _ => false,
         }
-
-        if let Some(core_id) = self.core_ids.first() {
-            let mut cpuset: libc::cpu_set_t = unsafe { std::mem::zeroed() };
-            unsafe {
-                libc::CPU_SET(*core_id, &mut cpuset);
-            }
-
-            let result = unsafe {
-                libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &cpuset)
-            };
-
-            if result == 0 {
-                tracing::debug!("Pinned thread to core {}", core_id);
-                return true;
-            } else {
-                tracing::warn!("Failed to pin thread to core {}: {}", core_id, result);
-                return false;
-            }
-        }
-        false
     }
 
-    #[cfg(target_os = "linux")]
     pub fn pin_thread_by_index(&self, thread_index: usize) -> bool {
         if !self.config.enabled || self.core_ids.is_empty() {
             return false;
         }
 
         let core_id = self.core_ids[thread_index % self.core_ids.len()];
+        Self::pin_to(core_id, thread_index)
+    }
 
-        let mut cpuset: libc::cpu_set_t = unsafe { std::mem::zeroed() };
-        unsafe {
-            libc::CPU_SET(core_id, &mut cpuset);
+    fn pin_to(core_id: usize, thread_index: usize) -> bool {
+        if !platform::supports_thread_pinning() {
+            tracing::warn!("Thread pinning not fully supported on this platform");
+            return false;
         }
 
-        let result =
-            unsafe { libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &cpuset) };
-
-        if result == 0 {
+        if platform::pin_current_thread(core_id) {
             tracing::debug!("Pinned thread {} to core {}", thread_index, core_id);
-            return true;
+            true
         } else {
-            tracing::warn!(
-                "Failed to pin thread {} to core {}: {}",
-                thread_index,
-                core_id,
-                result
-            );
-            return false;
+            tracing::warn!("Failed to pin thread {} to core {}", thread_index, core_id);
+            false
         }
     }
 
-    #[cfg(not(target_os = "linux"))]
-    pub fn pin_current_thread(&self) -> bool {
-        if !self.config.enabled || self.core_ids.is_empty() {
-            return false;
-        }
+    pub fn build_thread_pool(&self) -> Result<ThreadPool, Box<dyn std::error::Error>> {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(self.pool_size())
+            .spawn_handler(self.spawn_handler())
+            .build()?;
 
-        tracing::warn!("Thread pinning not fully supported on this platform");
-        false
+        Ok(pool)
     }
 
-    #[cfg(not(target_os = "linux"))]
-    pub fn pin_thread_by_index(&self, _thread_index: usize) -> bool {
-        if !self.config.enabled || self.core_ids.is_empty() {
-            return false;
-        }
+    /// Makes the pinned pool rayon's global pool, so everything parallel in
+    /// inference (candle's CPU kernels, the sampler, batch tokenization)
+    /// shares one set of threads, including work started from threads
+    /// outside any `install`, like server workers and the model loader.
+    ///
+    /// Only the first call builds the pool; later calls return the size of
+    /// the one already running. If rayon's global pool was already created
+    /// by other code, that pool is used unpinned.
+    pub fn install_shared_pool(&self) -> usize {
+        *SHARED_POOL.get_or_init(|| {
+            let installed = ThreadPoolBuilder::new()
+                .num_threads(self.pool_size())
+                .spawn_handler(self.spawn_handler())
+                .build_global();
+            match installed {
+                Ok(()) => {
+                    tracing::info!(
+                        "Shared thread pool: {} threads{}",
+                        self.pool_size(),
+                        if self.config.enabled { ", pinned" } else { "" }
+                    );
+                    self.pool_size()
+                }
+                Err(e) => {
+                    tracing::warn!("Using the existing rayon thread pool: {}", e);
+                    rayon::current_num_threads()
+                }
+            }
+        })
+    }
 
-        tracing::warn!("Thread pinning not fully supported on this platform");
-        false
+    /// One thread per pinned core, or the requested count when unpinned.
+    fn pool_size(&self) -> usize {
+        if self.config.enabled {
+            self.core_ids.len().max(1)
+        } else {
+            self.config.num_threads.max(1)
+        }
     }
 
-    pub fn build_thread_pool(&self) -> Result<ThreadPool, Box<dyn std::error::Error>> {
+    fn spawn_handler(&self) -> impl FnMut(ThreadBuilder) -> std::io::Result<()> {
         let core_ids = self.core_ids.clone();
         let enabled = self.config.enabled;
 
-        let pool = ThreadPoolBuilder::new()
-            .num_threads(self.core_ids.len())
-            .spawn_handler(move |thread| {
-                // Rayon's spawn_handler contract: spawn an OS thread, call
-                // thread.run() from INSIDE it, and return Ok(()) immediately.
-                // Calling thread.run() here (on the caller's thread) would block
-                // forever — the worker event loop never returns until pool drop.
-                let index = thread.index();
-                let core_ids_for_thread = core_ids.clone();
-                std::thread::Builder::new().spawn(move || {
-                    // sched_setaffinity(pid=0) pins the calling thread.
-                    // Must be called from inside the worker thread, not the
-                    // spawn handler thread.
-                    if enabled && !core_ids_for_thread.is_empty() {
-                        let core_id = core_ids_for_thread[index % core_ids_for_thread.len()];
-                        let mut cpuset: libc::cpu_set_t = unsafe { std::mem::zeroed() };
-                        unsafe {
-                            libc::CPU_SET(core_id, &mut cpuset);
-                            libc::sched_setaffinity(
-                                0,
-                                std::mem::size_of::<libc::cpu_set_t>(),
-                                &cpuset,
-                            );
-                        }
-                    }
-                    thread.run();
-                })?;
-                Ok(())
-            })
-            .build()?;
-
-        Ok(pool)
+        move |thread| {
+            // Rayon's spawn_handler contract: spawn an OS thread, call
+            // thread.run() from INSIDE it, and return Ok(()) immediately.
+            // Calling thread.run() here (on the caller's thread) would block
+            // forever — the worker event loop never returns until pool drop.
+            let index = thread.index();
+            let core_ids_for_thread = core_ids.clone();
+            std::thread::Builder::new().spawn(move || {
+                // sched_setaffinity(pid=0) pins the calling thread.
+                // Must be called from inside the worker thread, not the
+                // spawn handler thread.
+                if enabled && !core_ids_for_thread.is_empty() {
+                    let core_id = core_ids_for_thread[index % core_ids_for_thread.len()];
+                    platform::pin_current_thread(core_id);
+                }
+                thread.run();
+            })?;
+            Ok(())
+        }
     }
 
     pub fn num_threads(&self) -> usize {
@@ -257,4 +252,18 @@ mod tests {
         let config = ThreadPinnerConfig::auto(4);
         assert_eq!(config.num_threads, 3);
     }
+
+    #[test]
+    fn test_unpinned_pool_size() {
+        let pinner = ThreadPinner {
+            config: ThreadPinnerConfig {
+                enabled: false,
+                ..ThreadPinnerConfig::new(6, 0)
+            },
+            core_ids: vec![0, 1],
+        };
+        assert_eq!(pinner.pool_size(), 6);
+        let pool = pinner.build_thread_pool().unwrap();
+        assert_eq!(pool.current_num_threads(), 6);
+    }
 }
@@ -0,0 +1,146 @@
+//! Comparing greedy output with a reference run, for the `verify`
+//! subcommand.
+//!
+//! A reference file is JSON Lines with one case per line: a raw `prompt`
+//! (no chat template), the token ids another implementation generated for
+//! it with greedy decoding (`tokens`), and optionally the ids it tokenized
+//! the prompt to (`prompt_tokens`). llama.cpp's server returns both from
+//! `/completion` with `"temperature": 0` and `"return_tokens": true`, and
+//! from `/tokenize`. Reporting the first position where the runs differ
+//! points at the layer of the problem: tokenization when the prompt ids
+//! differ, numerics when an early generated token does.
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+/// One prompt and the reference implementation's output for it.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ReferenceCase {
+    pub prompt: String,
+    #[serde(default)]
+    pub prompt_tokens: Option<Vec<u32>>,
+    pub tokens: Vec<u32>,
+}
+
+/// Reads a reference file, skipping blank lines.
+pub fn load_reference(path: &Path) -> Result<Vec<ReferenceCase>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line)
+                .with_context(|| format!("{}: line {}", path.display(), i + 1))
+        })
+        .collect()
+}
+
+/// The first position where two token sequences differ. A side that ended
+/// before it has no token there.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Divergence {
+    pub index: usize,
+    pub expected: Option<u32>,
+    pub actual: Option<u32>,
+    pub expected_piece: Option<String>,
+    pub actual_piece: Option<String>,
+}
+
+/// How one case compared.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CaseReport {
+    /// 1-based position in the reference file, counting only cases.
+    pub case: usize,
+    /// Where prompt tokenization differs, when the reference has it.
+    pub prompt_divergence: Option<Divergence>,
+    /// Generated tokens that matched before the first difference.
+    pub matched: usize,
+    pub expected_len: usize,
+    pub divergence: Option<Divergence>,
+}
+
+impl CaseReport {
+    pub fn passed(&self) -> bool {
+        self.prompt_divergence.is_none() && self.divergence.is_none()
+    }
+
+    /// Compares our `prompt_tokens` and generated `tokens` with `reference`.
+    /// `piece` renders a token id for the report.
+    pub fn compare<F>(
+        case: usize,
+        reference: &ReferenceCase,
+        prompt_tokens: &[u32],
+        tokens: &[u32],
+        piece: F,
+    ) -> Self
+    where
+        F: Fn(u32) -> Option<String>,
+    {
+        let prompt_divergence = reference
+            .prompt_tokens
+            .as_deref()
+            .and_then(|expected| divergence(expected, prompt_tokens, &piece));
+        let divergence = divergence(&reference.tokens, tokens, &piece);
+        Self {
+            case,
+            prompt_divergence,
+            matched: divergence
+                .as_ref()
+                .map_or(reference.tokens.len(), |d| d.index),
+            expected_len: reference.tokens.len(),
+            divergence,
+        }
+    }
+}
+
+fn divergence<F>(expected: &[u32], actual: &[u32], piece: &F) -> Option<Divergence>
+where
+    F: Fn(u32) -> Option<String>,
+{
+    let index =
+        (0..expected.len().max(actual.len())).find(|&i| expected.get(i) != actual.get(i))?;
+    let expected = expected.get(index).copied();
+    let actual = actual.get(index).copied();
+    Some(Divergence {
+        index,
+        expected,
+        actual,
+        expected_piece: expected.and_then(piece),
+        actual_piece: actual.and_then(piece),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CaseReport, ReferenceCase};
+
+    #[test]
+    fn reports_the_first_differing_token() {
+        let reference: ReferenceCase =
+            serde_json::from_str(r#"{"prompt":"1, 2,","prompt_tokens":[1,5,6],"tokens":[7,8,9]}"#)
+                .unwrap();
+        let piece = |id: u32| Some(format!("<{}>", id));
+
+        let report = CaseReport::compare(1, &reference, &[1, 5, 6], &[7, 8, 9], piece);
+        assert!(report.passed());
+        assert_eq!(report.matched, 3);
+
+        let report = CaseReport::compare(2, &reference, &[1, 5, 6], &[7, 4, 9], piece);
+        let divergence = report.divergence.as_ref().unwrap();
+        assert_eq!(
+            (divergence.index, divergence.expected, divergence.actual),
+            (1, Some(8), Some(4))
+        );
+        assert_eq!(divergence.actual_piece.as_deref(), Some("<4>"));
+        assert_eq!(report.matched, 1);
+
+        // A run that stops early diverges where it stopped.
+        let report = CaseReport::compare(3, &reference, &[5, 6], &[7], piece);
+        assert_eq!(report.prompt_divergence.unwrap().index, 0);
+        assert_eq!(report.divergence.unwrap().actual, None);
+    }
+}
@@ -0,0 +1,81 @@
+//! How much work runs through a model right after loading.
+//!
+//! The first forward pass pays for page faults on the weights and for
+//! kernels and buffers set up on first use. A server would rather pay that
+//! before its first request, while a one-shot CLI call only wants its
+//! answer as soon as possible.
+
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WarmupPolicy {
+    /// Skip warmup; the first request pays for it.
+    #[default]
+    None,
+    /// A single token, which touches every layer once.
+    Minimal,
+    /// A full prefill batch and one decode step after it, so both the
+    /// batched and the single-token paths are warm.
+    Full,
+    /// The rendered system prompt and few-shot examples, so warmup runs the
+    /// template, tokenizer and prefill shapes of a real first request.
+    /// Without either it falls back to `Minimal`.
+    SystemPrompt,
+}
+
+impl WarmupPolicy {
+    pub const ALL: [WarmupPolicy; 4] = [
+        WarmupPolicy::None,
+        WarmupPolicy::Minimal,
+        WarmupPolicy::Full,
+        WarmupPolicy::SystemPrompt,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WarmupPolicy::None => "none",
+            WarmupPolicy::Minimal => "minimal",
+            WarmupPolicy::Full => "full",
+            WarmupPolicy::SystemPrompt => "system-prompt",
+        }
+    }
+}
+
+impl fmt::Display for WarmupPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for WarmupPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.to_ascii_lowercase().replace('_', "-");
+        WarmupPolicy::ALL
+            .iter()
+            .copied()
+            .find(|p| p.as_str() == s)
+            .ok_or_else(|| {
+                format!(
+                    "unknown warmup policy '{}' (expected none, minimal, full or system-prompt)",
+                    s
+                )
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WarmupPolicy;
+
+    #[test]
+    fn parses_policy_names() {
+        for policy in WarmupPolicy::ALL {
+            assert_eq!(policy.as_str().parse(), Ok(policy));
+        }
+        assert_eq!("SYSTEM_PROMPT".parse(), Ok(WarmupPolicy::SystemPrompt));
+        assert!("all".parse::<WarmupPolicy>().is_err());
+    }
+}
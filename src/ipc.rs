@@ -0,0 +1,336 @@
+//! Local IPC for editor integrations (`oxide-rs serve --unix PATH`).
+//!
+//! Clients keep a connection to a unix socket open and exchange
+//! newline-delimited JSON with the server. Every request carries an `id`
+//! chosen by the client, and every reply repeats it, so a client may send
+//! several requests without waiting:
+//!
+//! ```text
+//! {"id":1,"op":"stream","prompt":"Explain this function","max_tokens":128}
+//! {"id":2,"op":"tokenize","text":"fn main() {}"}
+//! {"id":1,"op":"cancel"}
+//! ```
+//!
+//! `generate` answers with one `done` reply holding the whole `text`;
+//! `stream` sends a `token` reply per piece first. `cancel` stops the
+//! generation with the same id, which then ends with `"cancelled":true`.
+//! `tokenize` answers at once, even while a generation runs. Generations
+//! run one at a time in the order they arrive, and each starts from an
+//! empty conversation.
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::daemon::{run_request, send, DaemonRequest};
+use crate::inference::{CancelToken, Generator};
+use crate::model::TokenizerWrapper;
+
+/// One line sent by a client.
+#[derive(Clone, Debug, Deserialize)]
+pub struct IpcRequest {
+    pub id: u64,
+    #[serde(flatten)]
+    pub op: IpcOp,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum IpcOp {
+    Generate(GenerateParams),
+    Stream(GenerateParams),
+    Cancel,
+    Tokenize { text: String },
+}
+
+/// A prompt and the settings it overrides; the rest come from the
+/// command line the server was started with.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct GenerateParams {
+    pub prompt: String,
+    pub system: Option<String>,
+    pub max_tokens: Option<usize>,
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+    pub top_k: Option<usize>,
+    pub seed: Option<u64>,
+}
+
+impl GenerateParams {
+    fn resolve(self, defaults: &DaemonRequest) -> DaemonRequest {
+        DaemonRequest {
+            prompt: self.prompt,
+            system_prompt: self.system.or_else(|| defaults.system_prompt.clone()),
+            max_tokens: self.max_tokens.unwrap_or(defaults.max_tokens),
+            temperature: self.temperature.unwrap_or(defaults.temperature),
+            top_p: self.top_p.or(defaults.top_p),
+            top_k: self.top_k.or(defaults.top_k),
+            seed: self.seed.unwrap_or(defaults.seed),
+            repeat_penalty: defaults.repeat_penalty,
+            repeat_last_n: defaults.repeat_last_n,
+        }
+    }
+}
+
+/// One line sent by the server; only the fields that apply are present.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct IpcReply {
+    pub id: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tokens: Option<Vec<u32>>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub done: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub cancelled: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl IpcReply {
+    fn error(id: u64, message: impl Into<String>) -> Self {
+        Self {
+            id,
+            error: Some(message.into()),
+            ..Default::default()
+        }
+    }
+}
+
+type Pending = Arc<Mutex<HashMap<u64, CancelToken>>>;
+
+struct Job {
+    id: u64,
+    stream: bool,
+    request: DaemonRequest,
+    cancel: CancelToken,
+    writer: Arc<Mutex<UnixStream>>,
+    pending: Pending,
+}
+
+/// Accepts clients on `listener` and runs their generations with
+/// `generator` on the calling thread, so a thread pool the caller installed
+/// is used. `defaults` fills in what a request leaves out. Runs until the
+/// process is stopped.
+pub fn serve(
+    mut generator: Generator,
+    tokenizer: TokenizerWrapper,
+    listener: UnixListener,
+    defaults: DaemonRequest,
+) -> Result<()> {
+    let (jobs, queue) = mpsc::channel::<Job>();
+    let tokenizer = Arc::new(tokenizer);
+    let defaults = Arc::new(defaults);
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    tracing::warn!("Failed to accept a client: {}", e);
+                    continue;
+                }
+            };
+            let jobs = jobs.clone();
+            let tokenizer = tokenizer.clone();
+            let defaults = defaults.clone();
+            std::thread::spawn(move || {
+                if let Err(e) = read_requests(stream, jobs, &tokenizer, &defaults) {
+                    tracing::warn!("Client connection failed: {}", e);
+                }
+            });
+        }
+    });
+
+    for job in queue {
+        run_job(&mut generator, job);
+    }
+    Ok(())
+}
+
+/// Reads one client's requests until it disconnects, answering `tokenize`
+/// and `cancel` directly and queueing generations.
+fn read_requests(
+    stream: UnixStream,
+    jobs: Sender<Job>,
+    tokenizer: &TokenizerWrapper,
+    defaults: &DaemonRequest,
+) -> Result<()> {
+    let writer = Arc::new(Mutex::new(stream.try_clone()?));
+    let pending: Pending = Arc::default();
+    let reply = |message: &IpcReply| send(&mut *writer.lock().unwrap(), message);
+
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let request: IpcRequest = match serde_json::from_str(&line) {
+            Ok(request) => request,
+            Err(e) => {
+                // Without a parsable request there is no id to answer to.
+                reply(&IpcReply::error(0, format!("Bad request: {}", e)))?;
+                continue;
+            }
+        };
+        let id = request.id;
+        let (stream, params) = match request.op {
+            IpcOp::Tokenize { text } => {
+                match tokenizer.encode(&text) {
+                    Ok(tokens) => reply(&IpcReply {
+                        id,
+                        tokens: Some(tokens),
+                        ..Default::default()
+                    })?,
+                    Err(e) => reply(&IpcReply::error(id, format!("{:#}", e)))?,
+                }
+                continue;
+            }
+            IpcOp::Cancel => {
+                // Unknown ids have usually just finished.
+                if let Some(cancel) = pending.lock().unwrap().get(&id) {
+                    cancel.cancel();
+                }
+                continue;
+            }
+            IpcOp::Generate(params) => (false, params),
+            IpcOp::Stream(params) => (true, params),
+        };
+
+        // Only this thread adds entries, so the check cannot go stale.
+        if pending.lock().unwrap().contains_key(&id) {
+            reply(&IpcReply::error(
+                id,
+                "A request with this id is still running",
+            ))?;
+            continue;
+        }
+        let cancel = CancelToken::new();
+        pending.lock().unwrap().insert(id, cancel.clone());
+        let job = Job {
+            id,
+            stream,
+            request: params.resolve(defaults),
+            cancel,
+            writer: writer.clone(),
+            pending: pending.clone(),
+        };
+        if jobs.send(job).is_err() {
+            anyhow::bail!("The server stopped");
+        }
+    }
+
+    // Nobody is left to read what is still queued or running.
+    for cancel in pending.lock().unwrap().values() {
+        cancel.cancel();
+    }
+    Ok(())
+}
+
+fn run_job(generator: &mut Generator, job: Job) {
+    let reply = |message: &IpcReply| send(&mut *job.writer.lock().unwrap(), message);
+    let mut text = String::new();
+    let result = if job.cancel.is_cancelled() {
+        Ok(())
+    } else {
+        generator.set_cancel_token(Some(job.cancel.clone()));
+        let result = run_request(generator, &job.request, |token| {
+            if job.stream {
+                let sent = reply(&IpcReply {
+                    id: job.id,
+                    token: Some(token.clone()),
+                    ..Default::default()
+                });
+                if sent.is_err() {
+                    job.cancel.cancel();
+                }
+            }
+            text.push_str(&token);
+        });
+        generator.set_cancel_token(None);
+        generator.clear_history();
+        result
+    };
+    job.pending.lock().unwrap().remove(&job.id);
+
+    let message = match result {
+        Ok(()) => IpcReply {
+            id: job.id,
+            text: Some(text),
+            done: true,
+            cancelled: job.cancel.is_cancelled(),
+            ..Default::default()
+        },
+        Err(e) => IpcReply::error(job.id, format!("{:#}", e)),
+    };
+    if let Err(e) = reply(&message) {
+        tracing::debug!("Client went away before reply {}: {}", job.id, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DaemonRequest, IpcOp, IpcReply, IpcRequest};
+
+    #[test]
+    fn parses_requests_and_fills_in_defaults() {
+        let request: IpcRequest =
+            serde_json::from_str(r#"{"id":7,"op":"stream","prompt":"hi","max_tokens":16}"#)
+                .unwrap();
+        assert_eq!(request.id, 7);
+        let IpcOp::Stream(params) = request.op else {
+            panic!("expected a stream request");
+        };
+        let defaults = DaemonRequest {
+            prompt: String::new(),
+            system_prompt: Some("Be brief.".to_string()),
+            max_tokens: 512,
+            temperature: 0.3,
+            top_p: None,
+            top_k: Some(40),
+            seed: 1,
+            repeat_penalty: 1.1,
+            repeat_last_n: 64,
+        };
+        let resolved = params.resolve(&defaults);
+        assert_eq!(resolved.prompt, "hi");
+        assert_eq!(resolved.max_tokens, 16);
+        assert_eq!(resolved.top_k, Some(40));
+        assert_eq!(resolved.system_prompt.as_deref(), Some("Be brief."));
+
+        let cancel: IpcRequest = serde_json::from_str(r#"{"id":7,"op":"cancel"}"#).unwrap();
+        assert!(matches!(cancel.op, IpcOp::Cancel));
+        assert!(serde_json::from_str::<IpcRequest>(r#"{"id":1,"op":"explode"}"#).is_err());
+    }
+
+    #[test]
+    fn replies_carry_only_their_fields() {
+        let token = IpcReply {
+            id: 3,
+            token: Some("fn".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            serde_json::to_string(&token).unwrap(),
+            r#"{"id":3,"token":"fn"}"#
+        );
+        let done = IpcReply {
+            id: 3,
+            text: Some("fn main".to_string()),
+            done: true,
+            cancelled: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            serde_json::to_string(&done).unwrap(),
+            r#"{"id":3,"text":"fn main","done":true,"cancelled":true}"#
+        );
+    }
+}
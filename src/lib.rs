@@ -77,23 +77,35 @@
 //! - [Documentation](https://docs.rs/oxide-rs)
 
 pub mod cli;
+#[cfg(unix)]
+pub mod daemon;
 pub mod inference;
+#[cfg(unix)]
+pub mod ipc;
 pub mod model;
+pub mod platform;
 pub mod server;
+pub mod shared;
 pub mod tui;
 
 use std::path::Path;
 use std::path::PathBuf;
+use std::time::Duration;
+
+use inference::{CancelToken, ModerationStream};
 
 pub use inference::{
-    BatchConfig, DynamicBatcher, Generator, PagedAttentionConfig, PagedKvCache, 
-    PrefixCache, PrefixCacheConfig, SimdLevel, StreamEvent,
-    ThreadPinnerConfig, ThreadPinner,
+    BatchConfig, Choice, Confidence, ContextOverflow, ContextPolicy, DatasetRecord, DatasetWriter, DocumentDigest, DocumentOptions, DynamicBatcher, EosControl, FlushPolicy, GenerationError, GenerationHooks, Generator, LatencySummary, LogitBias, LogitsTransform, LoopAction, LoopGuard, Message,
+    ModerationAction, ModerationConfig, ModerationResult, PagedAttentionConfig, PagedKvCache, ProbabilityTap,
+    PrefixCache, PrefixCacheConfig, RedactionConfig, RedactionRule, ResponseFormat, SimdLevel,
+    StopCondition, StopContext, StreamChunker, StreamEvent, StreamGranularity, ThreadPinnerConfig, ThreadPinner,
+    TopNSigma, TransformContext, TransformStage, TruncationStrategy, WarmupPolicy,
 };
+pub use shared::{Session, SharedModel, Slice};
 pub use model::{
-    download, format_size, get_hf_cache_dir, get_model_info, list_models, list_repo_files,
-    register_model, unregister_model, ModelEntry, GgufMetadata, Model as ModelWrapper, 
-    TokenizerWrapper,
+    architecture_info, supported_architectures, ArchInfo, ArchStatus, download, format_size, get_hf_cache_dir, get_model_info, list_models, list_repo_files,
+    register_model, unregister_model, ActivationReport, ModelEntry, GgufMetadata, LoadReport, Model as ModelWrapper, 
+    LoadError, LoadErrorKind, LoadRetry, TensorStats, TokenizerWrapper,
 };
 
 /// Configuration options for text generation.
@@ -118,6 +130,9 @@ pub use model::{
 pub struct GenerateOptions {
     /// Maximum number of tokens to generate.
     ///
+    /// Set to `0` for `auto`: the largest completion that fits the context
+    /// window remaining after the prompt, history, and a small safety margin.
+    ///
     /// Default: `512`
     pub max_tokens: usize,
 
@@ -140,6 +155,24 @@ pub struct GenerateOptions {
     /// Default: `None`
     pub top_k: Option<usize>,
 
+    /// Top-nσ sampling. Keeps the tokens whose logit is within this many
+    /// standard deviations of the largest, e.g. `1.0`.
+    ///
+    /// Default: `None`
+    pub top_n_sigma: Option<f32>,
+
+    /// Added to the end-of-sequence logit. Positive values make replies end
+    /// sooner, negative values make them run longer.
+    ///
+    /// Default: `0.0`
+    pub eos_bias: f32,
+
+    /// Tokens to generate before the model may end its reply, to prevent
+    /// one-word answers.
+    ///
+    /// Default: `0`
+    pub min_tokens: usize,
+
     /// Penalty applied to repeated tokens. Values > 1.0 reduce repetition.
     ///
     /// Default: `1.1`
@@ -187,6 +220,10 @@ pub struct GenerateOptions {
 
     /// Number of CPU threads (0 = auto-detect, use n-1).
     ///
+    /// Like `reserve_cores` and `pin_threads`, only used by
+    /// [`Model::install_thread_pool`], which replaces rayon's global pool;
+    /// `load()` leaves the process's thread pool alone.
+    ///
     /// Default: `0` (auto)
     pub cpu_threads: usize,
 
@@ -195,10 +232,143 @@ pub struct GenerateOptions {
     /// Default: `0`
     pub reserve_cores: usize,
 
+    /// Pin the inference threads to cores. Turn off when other CPU-heavy
+    /// work shares the machine.
+    ///
+    /// Default: `true`
+    pub pin_threads: bool,
+
     /// SIMD level (auto, avx512, avx2, neon, scalar).
     ///
     /// Default: `auto`
     pub simd_level: String,
+
+    /// Output format. With [`ResponseFormat::JsonSchema`] the reply is parsed
+    /// and validated, and invalid replies are retried with the validation
+    /// error as feedback.
+    ///
+    /// Default: `ResponseFormat::Text`
+    pub response_format: ResponseFormat,
+
+    /// Language (ISO 639-1 code) exposed to chat templates and system prompt
+    /// placeholders instead of detecting it from each prompt.
+    ///
+    /// Default: `None` (detect)
+    pub force_language: Option<String>,
+
+    /// Extra attempts allowed when a structured reply fails validation.
+    ///
+    /// Default: `2`
+    pub json_max_retries: usize,
+
+    /// Patterns redacted from generated text before it reaches callbacks or
+    /// responses. [`RedactionConfig::default`] covers emails, phone numbers
+    /// and card numbers.
+    ///
+    /// Default: `None`
+    pub redaction: Option<RedactionConfig>,
+
+    /// Keyword moderation of finished replies. Flagged replies are annotated
+    /// in [`GenerationResult::moderation`] or, with
+    /// [`ModerationAction::Block`], replaced by the blocked message.
+    ///
+    /// [`Model::generate_stream`] and [`Model::generate_events`] check the
+    /// reply as it streams: once it is blocked no more tokens reach the
+    /// callback, generation stops, and the blocked message is returned and
+    /// kept in the history. Chunks streamed before that point stay with the
+    /// caller.
+    ///
+    /// Default: `None`
+    pub moderation: Option<ModerationConfig>,
+
+    /// Low-memory mode: chunked prefill and no context-sized buffers, for
+    /// swap-constrained devices. Slower, especially for long prompts.
+    ///
+    /// Default: `false`
+    pub low_mem: bool,
+
+    /// Boundaries at which streamed text reaches `generate_stream` and
+    /// `generate_events` callbacks: every token, whole words or whole
+    /// sentences.
+    ///
+    /// Default: `StreamGranularity::Token`
+    pub stream_granularity: StreamGranularity,
+
+    /// Measure how sure the model was of each reply and report it in
+    /// [`GenerationResult::confidence`]. Costs a copy of the logits per
+    /// token.
+    ///
+    /// Default: `false`
+    pub confidence: bool,
+
+    /// When a reply reaches `max_tokens` mid-sentence, keep generating a
+    /// few dozen tokens until the sentence or line ends instead of cutting
+    /// it off. Replies may run slightly past `max_tokens`.
+    ///
+    /// Default: `false`
+    pub finish_at_boundary: bool,
+
+    /// Longest time, in milliseconds, to spend prefilling before the first
+    /// token. Prompts the measured prefill speed says would take longer
+    /// keep their first token and as many of their last tokens as fit, so
+    /// the oldest context is dropped. The first generation is never cut.
+    ///
+    /// Default: `None`
+    pub ttft_budget_ms: Option<u64>,
+
+    /// What to do when a prompt does not fit the context window: drop the
+    /// oldest turns, summarize them, or fail with a [`ContextOverflow`]
+    /// that can be downcast from the returned error.
+    ///
+    /// Default: `ContextPolicy::Truncate`
+    pub context_policy: ContextPolicy,
+
+    /// Catches replies stuck repeating a short cycle of tokens, such as
+    /// endless blank lines, and ends them or raises the temperature once.
+    /// [`GenerationResult::loop_detected`] says when a reply was ended.
+    ///
+    /// Default: window of 64 tokens, cycles up to 16 tokens, `LoopAction::Stop`
+    pub loop_guard: LoopGuard,
+
+    /// Times [`Model::load`] retries a load that failed for a transient
+    /// reason, such as a busy file or a short read from a network share,
+    /// with exponential backoff. Failures are returned as a [`LoadError`]
+    /// saying whether the file was busy, corrupt or unsupported.
+    ///
+    /// Default: `2`
+    pub load_retries: usize,
+
+    /// Work [`Model::load`] runs through the model before returning, so the
+    /// first generation does not pay for cold weights and kernels. Servers
+    /// want `Full`; one-shot callers are fastest with `None`.
+    ///
+    /// Default: `WarmupPolicy::None`
+    pub warmup: WarmupPolicy,
+}
+
+/// Output of a single generation call.
+#[derive(Clone, Debug)]
+pub struct GenerationResult {
+    /// The final reply text.
+    pub text: String,
+    /// Parsed reply when `response_format` requested JSON.
+    pub json: Option<serde_json::Value>,
+    /// Number of generation attempts, including retries.
+    pub attempts: usize,
+    /// Language of the prompt (ISO 639-1), forced or detected.
+    pub language: Option<String>,
+    /// Moderation scores when `moderation` is configured.
+    pub moderation: Option<ModerationResult>,
+    /// Mean token entropy and smallest top-1 margin of the final reply when
+    /// `confidence` is enabled. Low-confidence answers can be retried on a
+    /// larger model.
+    pub confidence: Option<Confidence>,
+    /// Per-token decode latency percentiles of the final reply; `None` when
+    /// it had a single token. [`Generator::token_latencies`] has every step.
+    pub latency: Option<LatencySummary>,
+    /// The loop guard ended the final reply because it kept repeating
+    /// itself.
+    pub loop_detected: bool,
 }
 
 impl Default for GenerateOptions {
@@ -208,6 +378,9 @@ impl Default for GenerateOptions {
             temperature: 0.3,
             top_p: None,
             top_k: None,
+            top_n_sigma: None,
+            eos_bias: 0.0,
+            min_tokens: 0,
             repeat_penalty: 1.1,
             repeat_last_n: 64,
             batch_size: 128,
@@ -219,11 +392,179 @@ impl Default for GenerateOptions {
             cache_memory_mb: 512,
             cpu_threads: 0,
             reserve_cores: 0,
+            pin_threads: true,
             simd_level: "auto".to_string(),
+            force_language: None,
+            response_format: ResponseFormat::Text,
+            json_max_retries: 2,
+            redaction: None,
+            moderation: None,
+            low_mem: false,
+            stream_granularity: StreamGranularity::Token,
+            confidence: false,
+            finish_at_boundary: false,
+            ttft_budget_ms: None,
+            context_policy: ContextPolicy::Truncate,
+            loop_guard: LoopGuard::default(),
+            load_retries: 2,
+            warmup: WarmupPolicy::None,
         }
     }
 }
 
+/// Builds the thread pool all models share from `cpu_threads`,
+/// `reserve_cores` and `pin_threads`, unless an earlier call built it.
+pub(crate) fn install_thread_pool(options: &GenerateOptions) -> usize {
+    let threads = match options.cpu_threads {
+        0 => ThreadPinnerConfig::default().num_threads,
+        n => n,
+    };
+    let config = ThreadPinnerConfig {
+        enabled: options.pin_threads,
+        ..ThreadPinnerConfig::new(threads, options.reserve_cores)
+    };
+    ThreadPinner::init(config).install_shared_pool()
+}
+
+/// Applies `options.moderation` to a finished reply. A blocked reply is
+/// replaced, in the result and in the conversation history, by the blocked
+/// message.
+fn moderate_reply(
+    generator: &mut Generator,
+    options: &GenerateOptions,
+    prompt: &str,
+    mut result: GenerationResult,
+) -> anyhow::Result<GenerationResult> {
+    let Some(ref config) = options.moderation else {
+        return Ok(result);
+    };
+    let moderation = config.check(&result.text);
+    if moderation.blocked {
+        tracing::info!("Reply blocked by moderation");
+        result.text = config.blocked_message.clone();
+        result.json = None;
+        generator.collapse_last_turns(1, prompt, &result.text)?;
+    }
+    result.moderation = Some(moderation);
+    Ok(result)
+}
+
+/// Applies `options.moderation` to a reply while it streams, cancelling the
+/// generation once the reply is blocked.
+struct StreamScreen<'a> {
+    config: Option<&'a ModerationConfig>,
+    moderation: Option<ModerationStream<'a>>,
+    cancel: CancelToken,
+    blocked: bool,
+}
+
+impl<'a> StreamScreen<'a> {
+    fn new(generator: &mut Generator, config: Option<&'a ModerationConfig>) -> Self {
+        let cancel = CancelToken::new();
+        if config.is_some() {
+            generator.set_cancel_token(Some(cancel.clone()));
+        }
+        Self {
+            config,
+            moderation: config.map(ModerationStream::new),
+            cancel,
+            blocked: false,
+        }
+    }
+
+    fn blocked(&self) -> bool {
+        self.blocked
+    }
+
+    /// Checks the next token; returns whether the reply is blocked.
+    fn push(&mut self, token: &str) -> bool {
+        if !self.blocked && self.moderation.as_mut().is_some_and(|m| m.push(token)) {
+            tracing::info!("Reply blocked by moderation");
+            self.blocked = true;
+            self.cancel.cancel();
+        }
+        self.blocked
+    }
+
+    /// Clears the cancel token and, for a blocked reply that finished
+    /// generating, puts the blocked message in the history and returns it.
+    fn finish(
+        self,
+        generator: &mut Generator,
+        prompt: &str,
+        generated: bool,
+    ) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        let Some(config) = self.config else {
+            return Ok(None);
+        };
+        generator.set_cancel_token(None);
+        if !(self.blocked && generated) {
+            return Ok(None);
+        }
+        generator.collapse_last_turns(1, prompt, &config.blocked_message)?;
+        Ok(Some(config.blocked_message.clone()))
+    }
+}
+
+/// Converts a generator error for the public API, keeping a
+/// [`ContextOverflow`] or [`GenerationError`] downcastable from the returned
+/// box.
+pub(crate) fn generation_error<E>(err: anyhow::Error) -> E
+where
+    E: From<ContextOverflow> + From<GenerationError> + From<anyhow::Error>,
+{
+    match err.downcast::<ContextOverflow>() {
+        Ok(overflow) => overflow.into(),
+        Err(err) => match err.downcast::<GenerationError>() {
+            Ok(failed) => failed.into(),
+            Err(err) => err.into(),
+        },
+    }
+}
+
+/// Generates replies to `request` until `validate` accepts one, sending
+/// `feedback(error)` as the next message after each rejection, for at most
+/// `max_attempts` attempts. Only `prompt` and the final reply stay in the
+/// history. The inner `Err` holds the last rejection when no reply passed.
+fn generate_validated<T>(
+    generator: &mut Generator,
+    options: &GenerateOptions,
+    prompt: &str,
+    mut request: String,
+    max_attempts: usize,
+    feedback: impl Fn(&str) -> String,
+    mut validate: impl FnMut(&str) -> Result<T, String>,
+) -> anyhow::Result<Result<(String, T, usize), String>> {
+    let mark = generator.history_mark();
+    let mut last_error = String::new();
+    for attempt in 1..=max_attempts {
+        let text = generator.generate(
+            &request,
+            options.max_tokens,
+            options.repeat_penalty,
+            options.repeat_last_n,
+            |_event| {},
+        )?;
+
+        match validate(&text) {
+            Ok(value) => {
+                generator.collapse_history_since(mark, prompt, &text)?;
+                return Ok(Ok((text, value, attempt)));
+            }
+            Err(err) => {
+                tracing::debug!("Reply rejected (attempt {}): {}", attempt, err);
+                request = feedback(&err);
+                last_error = err;
+            }
+        }
+    }
+
+    if let Some(text) = generator.last_response() {
+        generator.collapse_history_since(mark, prompt, &text)?;
+    }
+    Ok(Err(last_error))
+}
+
 /// High-level model wrapper with builder pattern for text generation.
 ///
 /// Use this when you need to:
@@ -253,6 +594,10 @@ pub struct Model {
     model_path: PathBuf,
     tokenizer_path: Option<PathBuf>,
     options: GenerateOptions,
+    examples: Vec<(String, String)>,
+    transforms: Vec<Box<dyn LogitsTransform>>,
+    hooks: Vec<Box<dyn GenerationHooks>>,
+    stop_conditions: Vec<StopCondition>,
 }
 
 impl Model {
@@ -275,6 +620,10 @@ impl Model {
             model_path: model_path.as_ref().to_path_buf(),
             tokenizer_path: None,
             options: GenerateOptions::default(),
+            examples: Vec::new(),
+            transforms: Vec::new(),
+            hooks: Vec::new(),
+            stop_conditions: Vec::new(),
         })
     }
 
@@ -310,9 +659,136 @@ impl Model {
         self
     }
 
+    /// Set how much `load()` warms the model up; see
+    /// [`GenerateOptions::warmup`].
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// let model = Model::new("model.gguf")?.with_warmup(WarmupPolicy::Full);
+    /// ```
+    pub fn with_warmup(mut self, policy: WarmupPolicy) -> Self {
+        self.options.warmup = policy;
+        self
+    }
+
+    /// Set few-shot `(user, assistant)` example turns.
+    ///
+    /// Examples are rendered through the chat template after the system
+    /// prompt and stay pinned ahead of the conversation: they are never
+    /// dropped when the context fills up, and `clear_history()` keeps them.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// let model = Model::new("model.gguf")?.with_examples(vec![
+    ///     ("2 + 2".into(), "4".into()),
+    ///     ("3 * 5".into(), "15".into()),
+    /// ]);
+    /// ```
+    pub fn with_examples(mut self, examples: Vec<(String, String)>) -> Self {
+        self.examples = examples;
+        self
+    }
+
+    /// Add a custom logits transform.
+    ///
+    /// Transforms run before every sampled token, after the built-in repeat
+    /// penalty, ordered by their [`TransformStage`] (penalties, then biases,
+    /// then grammar constraints). They move into the generator on `load()`.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// use std::collections::HashMap;
+    /// use oxide_rs::LogitBias;
+    ///
+    /// // Never emit token 13.
+    /// let bias = LogitBias::new(HashMap::from([(13, f32::NEG_INFINITY)]));
+    /// let model = Model::new("model.gguf")?.with_logits_transform(bias);
+    /// ```
+    pub fn with_logits_transform(mut self, transform: impl LogitsTransform + 'static) -> Self {
+        self.transforms.push(Box::new(transform));
+        self
+    }
+
+    /// Add lifecycle hooks (prefill start/end, each token, done, error).
+    ///
+    /// Hooks see every generation, including `generate_batch`, independently
+    /// of any streaming callback. They move into the generator on `load()`.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    /// use std::sync::Arc;
+    /// use oxide_rs::GenerationHooks;
+    ///
+    /// struct Meter(Arc<AtomicUsize>);
+    ///
+    /// impl GenerationHooks for Meter {
+    ///     fn on_done(&mut self, generated_tokens: usize, _: std::time::Duration) {
+    ///         self.0.fetch_add(generated_tokens, Ordering::Relaxed);
+    ///     }
+    /// }
+    ///
+    /// let billed = Arc::new(AtomicUsize::new(0));
+    /// let model = Model::new("model.gguf")?.with_hooks(Meter(billed.clone()));
+    /// ```
+    pub fn with_hooks(mut self, hooks: impl GenerationHooks + 'static) -> Self {
+        self.hooks.push(Box::new(hooks));
+        self
+    }
+
+    /// Add a condition that ends generation when it returns `true`.
+    ///
+    /// It is checked before every decode step with the reply so far, the
+    /// number of tokens generated and the time elapsed, and the reply up to
+    /// that point is returned. It moves into the generator on `load()`.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// // Stop once the answer's closing tag is written.
+    /// let model = Model::new("model.gguf")?
+    ///     .with_stop_condition(|ctx| ctx.text.contains("</answer>"));
+    /// ```
+    pub fn with_stop_condition<F>(mut self, condition: F) -> Self
+    where
+        F: FnMut(&StopContext) -> bool + Send + 'static,
+    {
+        self.stop_conditions.push(Box::new(condition));
+        self
+    }
+
+    /// Make rayon's global thread pool the one inference runs on, built
+    /// from `cpu_threads`, `reserve_cores` and `pin_threads`, and return its
+    /// size.
+    ///
+    /// This affects the whole process: every later `par_iter` and
+    /// `rayon::spawn` runs on these threads, pinned to cores unless
+    /// `pin_threads` is off. Call it before `load()` in applications that
+    /// own the machine; libraries embedding a model should leave it to the
+    /// application. Only the first call in a process builds the pool, and
+    /// none does if rayon's global pool already exists.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// let mut model = Model::new("model.gguf")?;
+    /// model.install_thread_pool();
+    /// model.load()?;
+    /// ```
+    pub fn install_thread_pool(&self) -> usize {
+        install_thread_pool(&self.options)
+    }
+
     /// Load the model into memory.
     ///
-    /// This must be called before `generate()`.
+    /// This must be called before `generate()`. Loads that fail for a
+    /// transient reason are retried up to `load_retries` times; the error
+    /// downcasts to a [`LoadError`] whose `kind` tells a busy file from a
+    /// corrupt or unsupported one.
     ///
     /// # Example
     ///
@@ -320,20 +796,97 @@ impl Model {
     /// let mut model = Model::new("model.gguf")?.load()?;
     /// ```
     pub fn load(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        let generator = Generator::new(
-            &self.model_path,
-            self.tokenizer_path.as_ref(),
-            self.options.temperature,
-            self.options.top_p,
-            self.options.top_k,
-            self.options.seed,
-            self.options.system_prompt.clone(),
-            self.options.batch_size,
-        )?;
+        let retry = LoadRetry::new(self.options.load_retries);
+        let mut generator = model::load_with_retry(&self.model_path, &retry, || {
+            Generator::new(
+                &self.model_path,
+                self.tokenizer_path.as_ref(),
+                self.options.temperature,
+                self.options.top_p,
+                self.options.top_k,
+                self.options.seed,
+                self.options.system_prompt.clone(),
+                self.options.batch_size,
+            )
+        })
+        // Keep the LoadError downcastable from the returned box.
+        .map_err(|err| -> Box<dyn std::error::Error> {
+            match err.downcast::<LoadError>() {
+                Ok(load) => load.into(),
+                Err(err) => err.into(),
+            }
+        })?;
+        if !self.examples.is_empty() {
+            generator.set_examples(self.examples.clone())?;
+        }
+        generator.set_forced_language(self.options.force_language.clone());
+        generator.set_redaction(self.options.redaction.as_ref())?;
+        generator.set_low_mem(self.options.low_mem);
+        generator.set_confidence(self.options.confidence);
+        generator.set_finish_at_boundary(self.options.finish_at_boundary);
+        let ttft_budget = self.options.ttft_budget_ms.map(Duration::from_millis);
+        generator.set_ttft_budget(ttft_budget);
+        generator.set_loop_guard(self.options.loop_guard);
+        generator.set_context_policy(self.options.context_policy);
+        if let Some(n) = self.options.top_n_sigma {
+            generator.add_logits_transform(Box::new(TopNSigma::new(n)?));
+        }
+        let eos = EosControl::new(self.options.eos_bias, self.options.min_tokens);
+        if eos.is_active() {
+            generator.add_logits_transform(Box::new(eos));
+        }
+        for transform in std::mem::take(&mut self.transforms) {
+            generator.add_logits_transform(transform);
+        }
+        for hooks in std::mem::take(&mut self.hooks) {
+            generator.add_hooks(hooks);
+        }
+        for condition in std::mem::take(&mut self.stop_conditions) {
+            generator.add_stop_condition(condition);
+        }
+        generator.warmup_with(self.options.warmup)?;
         self.generator = Some(generator);
         Ok(())
     }
 
+    /// Turn this model into a [`SharedModel`] whose sessions can generate
+    /// from several threads, each with its own conversation history.
+    ///
+    /// Sessions use `max_tokens`, the repeat penalty settings and
+    /// `stream_granularity` from the options; structured output,
+    /// moderation and validation are not applied. Generations run one at a
+    /// time; see [`shared`] for the threading contract.
+    ///
+    /// Requires `load()` to be called first.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// let shared = model.into_shared()?;
+    /// let workers: Vec<_> = ["Hi!", "Bonjour !"]
+    ///     .into_iter()
+    ///     .map(|prompt| {
+    ///         let mut session = shared.new_session();
+    ///         std::thread::spawn(move || session.generate(prompt))
+    ///     })
+    ///     .collect();
+    /// for worker in workers {
+    ///     println!("{}", worker.join().unwrap()?);
+    /// }
+    /// ```
+    pub fn into_shared(self) -> Result<SharedModel, Box<dyn std::error::Error>> {
+        let generator = self
+            .generator
+            .ok_or("Model not loaded. Call load() first.")?;
+        Ok(SharedModel::new(
+            generator,
+            self.options.max_tokens,
+            self.options.repeat_penalty,
+            self.options.repeat_last_n,
+            self.options.stream_granularity,
+        ))
+    }
+
     /// Generate text from a prompt.
     ///
     /// Requires `load()` to be called first.
@@ -349,20 +902,177 @@ impl Model {
     /// println!("{}", response);
     /// ```
     pub fn generate(&mut self, prompt: &str) -> Result<String, Box<dyn std::error::Error>> {
+        Ok(self.generate_result(prompt)?.text)
+    }
+
+    /// Generate a reply and return it with any structured output.
+    ///
+    /// When `response_format` is [`ResponseFormat::JsonSchema`], the reply is
+    /// validated against the schema and regenerated with the validation
+    /// error as feedback, up to `json_max_retries` extra times. Only the final
+    /// exchange is kept in the conversation history.
+    ///
+    /// Requires `load()` to be called first.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// let mut model = Model::new("model.gguf")?.with_options(GenerateOptions {
+    ///     response_format: ResponseFormat::JsonSchema(serde_json::json!({
+    ///         "type": "object",
+    ///         "properties": { "city": { "type": "string" } },
+    ///         "required": ["city"]
+    ///     })),
+    ///     ..Default::default()
+    /// });
+    /// model.load()?;
+    ///
+    /// let result = model.generate_result("Where is the Eiffel Tower?")?;
+    /// println!("{}", result.json.unwrap()["city"]);
+    /// ```
+    pub fn generate_result(
+        &mut self,
+        prompt: &str,
+    ) -> Result<GenerationResult, Box<dyn std::error::Error>> {
         let generator = self
             .generator
             .as_mut()
             .ok_or("Model not loaded. Call load() first.")?;
+        let options = &self.options;
 
-        let result = generator.generate(
+        let Some(instruction) = options.response_format.instruction() else {
+            let text = generator
+                .generate(
+                    prompt,
+                    options.max_tokens,
+                    options.repeat_penalty,
+                    options.repeat_last_n,
+                    |_event| {},
+                )
+                .map_err(generation_error::<Box<dyn std::error::Error>>)?;
+            let result = GenerationResult {
+                text,
+                json: None,
+                attempts: 1,
+                language: generator.language().map(String::from),
+                moderation: None,
+                confidence: generator.confidence(),
+                latency: generator.latency(),
+                loop_detected: generator.loop_detected(),
+            };
+            return Ok(moderate_reply(generator, options, prompt, result)?);
+        };
+
+        let max_attempts = options.json_max_retries + 1;
+        let outcome = generate_validated(
+            generator,
+            options,
             prompt,
-            self.options.max_tokens,
-            self.options.repeat_penalty,
-            self.options.repeat_last_n,
-            |_event| {},
-        )?;
+            format!("{}\n\n{}", prompt, instruction),
+            max_attempts,
+            |err| {
+                format!(
+                    "Your reply was invalid: {}. Reply again with only the corrected JSON.",
+                    err
+                )
+            },
+            |text| options.response_format.parse(text),
+        )
+        .map_err(generation_error::<Box<dyn std::error::Error>>)?;
+        match outcome {
+            Ok((text, json, attempts)) => {
+                let result = GenerationResult {
+                    text,
+                    json,
+                    attempts,
+                    language: generator.language().map(String::from),
+                    moderation: None,
+                    confidence: generator.confidence(),
+                    latency: generator.latency(),
+                    loop_detected: generator.loop_detected(),
+                };
+                Ok(moderate_reply(generator, options, prompt, result)?)
+            }
+            Err(last_error) => Err(format!(
+                "No reply matched the JSON schema after {} attempts: {}",
+                max_attempts, last_error
+            )
+            .into()),
+        }
+    }
 
-        Ok(result)
+    /// Generate a reply and regenerate it until `validator` accepts it.
+    ///
+    /// `validator` returns `Err(message)` to reject a reply, e.g. with a
+    /// parse error or failing test output. The message is sent back to the
+    /// model as feedback, up to `max_retries` extra times. Only the final
+    /// exchange is kept in the conversation history, and
+    /// [`GenerationResult::attempts`] counts every try. `response_format` is
+    /// not applied; parse inside the validator instead.
+    ///
+    /// Requires `load()` to be called first.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// let result = model.generate_with_validator(
+    ///     "Write a Rust function `add(a: i32, b: i32) -> i32`.",
+    ///     |reply| run_tests(reply).map_err(|output| format!("The tests failed:\n{}", output)),
+    ///     3,
+    /// )?;
+    /// println!("Passed after {} attempts", result.attempts);
+    /// ```
+    pub fn generate_with_validator<V>(
+        &mut self,
+        prompt: &str,
+        validator: V,
+        max_retries: usize,
+    ) -> Result<GenerationResult, Box<dyn std::error::Error>>
+    where
+        V: FnMut(&str) -> Result<(), String>,
+    {
+        let generator = self
+            .generator
+            .as_mut()
+            .ok_or("Model not loaded. Call load() first.")?;
+        let options = &self.options;
+
+        let max_attempts = max_retries + 1;
+        let outcome = generate_validated(
+            generator,
+            options,
+            prompt,
+            prompt.to_string(),
+            max_attempts,
+            |err| {
+                format!(
+                    "Your reply was rejected:\n{}\n\nReply again with a corrected answer.",
+                    err
+                )
+            },
+            validator,
+        )
+        .map_err(generation_error::<Box<dyn std::error::Error>>)?;
+        match outcome {
+            Ok((text, (), attempts)) => {
+                let result = GenerationResult {
+                    text,
+                    json: None,
+                    attempts,
+                    language: generator.language().map(String::from),
+                    moderation: None,
+                    confidence: generator.confidence(),
+                    latency: generator.latency(),
+                    loop_detected: generator.loop_detected(),
+                };
+                Ok(moderate_reply(generator, options, prompt, result)?)
+            }
+            Err(last_error) => Err(format!(
+                "No reply passed validation after {} attempts: {}",
+                max_attempts, last_error
+            )
+            .into()),
+        }
     }
 
     /// Generate text with streaming callback.
@@ -398,24 +1108,195 @@ impl Model {
             .ok_or("Model not loaded. Call load() first.")?;
 
         let mut output = String::new();
-        generator.generate(
+        let mut chunker = StreamChunker::new(self.options.stream_granularity);
+        let mut screen = StreamScreen::new(generator, self.options.moderation.as_ref());
+        let result = generator.generate(
             prompt,
             self.options.max_tokens,
             self.options.repeat_penalty,
             self.options.repeat_last_n,
             |event| match event {
+                StreamEvent::Token(_) if screen.blocked() => {}
                 StreamEvent::Token(t) => {
+                    if screen.push(&t) {
+                        return;
+                    }
                     output.push_str(&t);
-                    callback(t);
+                    if let Some(chunk) = chunker.push(&t) {
+                        callback(chunk);
+                    }
                 }
                 StreamEvent::Done => {}
                 StreamEvent::PrefillStatus(_) => {}
+                StreamEvent::ContextTruncated { .. } => {}
+                StreamEvent::LoopDetected { .. } => {}
+                StreamEvent::Error(_) => {
+                    if let Some(rest) = chunker.finish().filter(|_| !screen.blocked()) {
+                        callback(rest);
+                    }
+                }
             },
-        )?;
+        );
+        if let Some(blocked_message) = screen.finish(generator, prompt, result.is_ok())? {
+            return Ok(blocked_message);
+        }
+        result.map_err(generation_error::<Box<dyn std::error::Error>>)?;
+        if let Some(rest) = chunker.finish() {
+            callback(rest);
+        }
+
+        Ok(output)
+    }
+
+    /// Generate text with streaming callback, copying every chunk to
+    /// `writer` as well.
+    ///
+    /// Use this to keep a long, unattended generation on disk while it is
+    /// displayed: with [`FlushPolicy::Chunk`] an interrupted run loses
+    /// nothing that was already streamed. A failed write does not stop the
+    /// generation; the error is returned once it completes.
+    ///
+    /// Requires `load()` to be called first.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// let file = std::fs::OpenOptions::new().create(true).append(true).open("out.txt")?;
+    /// model.generate_stream_to("Write a long story", file, FlushPolicy::Chunk, |chunk| {
+    ///     print!("{}", chunk);
+    /// })?;
+    /// ```
+    pub fn generate_stream_to<W, F>(
+        &mut self,
+        prompt: &str,
+        writer: W,
+        flush: FlushPolicy,
+        mut callback: F,
+    ) -> Result<String, Box<dyn std::error::Error>>
+    where
+        W: std::io::Write,
+        F: FnMut(String),
+    {
+        let mut tee = inference::Tee::new(writer, flush);
+        let mut write_error = None;
+        let output = self.generate_stream(prompt, |chunk| {
+            if write_error.is_none() {
+                write_error = tee.write(&chunk).err();
+            }
+            callback(chunk);
+        })?;
+        if let Some(e) = write_error {
+            return Err(e.into());
+        }
+        tee.finish()?;
 
         Ok(output)
     }
 
+    /// Generate text, passing every [`StreamEvent`] to the callback.
+    ///
+    /// Unlike [`generate_stream`](Self::generate_stream), this also reports
+    /// prefill progress and [`StreamEvent::ContextTruncated`] when older turns
+    /// had to be dropped to fit the prompt.
+    ///
+    /// Requires `load()` to be called first.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// model.generate_events("Continue the story", |event| match event {
+    ///     StreamEvent::Token(t) => print!("{}", t),
+    ///     StreamEvent::ContextTruncated { dropped_tokens, .. } => {
+    ///         eprintln!("warning: dropped {} tokens of history", dropped_tokens);
+    ///     }
+    ///     _ => {}
+    /// })?;
+    /// ```
+    pub fn generate_events<F>(
+        &mut self,
+        prompt: &str,
+        mut callback: F,
+    ) -> Result<String, Box<dyn std::error::Error>>
+    where
+        F: FnMut(StreamEvent),
+    {
+        let generator = self
+            .generator
+            .as_mut()
+            .ok_or("Model not loaded. Call load() first.")?;
+
+        let mut chunker = StreamChunker::new(self.options.stream_granularity);
+        let mut screen = StreamScreen::new(generator, self.options.moderation.as_ref());
+        let result = generator.generate(
+            prompt,
+            self.options.max_tokens,
+            self.options.repeat_penalty,
+            self.options.repeat_last_n,
+            |event| match event {
+                StreamEvent::Token(_) if screen.blocked() => {}
+                StreamEvent::Token(t) => {
+                    if screen.push(&t) {
+                        return;
+                    }
+                    if let Some(chunk) = chunker.push(&t) {
+                        callback(StreamEvent::Token(chunk));
+                    }
+                }
+                StreamEvent::Done => {
+                    if let Some(rest) = chunker.finish().filter(|_| !screen.blocked()) {
+                        callback(StreamEvent::Token(rest));
+                    }
+                    callback(StreamEvent::Done);
+                }
+                StreamEvent::Error(message) => {
+                    if let Some(rest) = chunker.finish().filter(|_| !screen.blocked()) {
+                        callback(StreamEvent::Token(rest));
+                    }
+                    callback(StreamEvent::Error(message));
+                }
+                event => callback(event),
+            },
+        );
+        if let Some(blocked_message) = screen.finish(generator, prompt, result.is_ok())? {
+            return Ok(blocked_message);
+        }
+
+        result.map_err(generation_error::<Box<dyn std::error::Error>>)
+    }
+
+    /// Append a message to the conversation without generating a reply.
+    ///
+    /// Use this to add named participants or timestamped turns, e.g. in
+    /// multi-agent simulations. `name`, `timestamp`, and `metadata` are passed
+    /// to chat templates that use them.
+    ///
+    /// Requires `load()` to be called first.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// model.push_message(Message::new("user", "I vote yes.").with_name("alice"))?;
+    /// let reply = model.generate("Bob, how do you vote?")?;
+    /// ```
+    pub fn push_message(&mut self, message: Message) -> Result<(), Box<dyn std::error::Error>> {
+        let generator = self
+            .generator
+            .as_mut()
+            .ok_or("Model not loaded. Call load() first.")?;
+        generator.push_message(message)?;
+        Ok(())
+    }
+
+    /// Language of the latest prompt (ISO 639-1), forced or detected.
+    pub fn language(&self) -> Option<&str> {
+        self.generator.as_ref().and_then(|g| g.language())
+    }
+
+    /// Conversation history, or `None` if not loaded.
+    pub fn history(&self) -> Option<&[Message]> {
+        self.generator.as_ref().map(|g| g.history())
+    }
+
     /// Generate text from multiple prompts in batch.
     ///
     /// Processes multiple prompts sequentially, sharing the loaded model for efficiency.
@@ -455,6 +1336,136 @@ impl Model {
         Ok(result)
     }
 
+    /// Answer `prompt` with exactly one of `options`.
+    ///
+    /// Decoding is masked so only the options' tokens can be produced. The
+    /// result holds the most likely option and the probability of each,
+    /// normalized over the options. Like `generate_batch`, this does not use
+    /// or change the conversation history, and sampling settings are ignored.
+    ///
+    /// Requires `load()` to be called first.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// let labels = ["positive", "negative", "neutral"].map(String::from);
+    /// let choice = model.choose("Sentiment of: I love this crate!", &labels)?;
+    /// println!("{} ({:.0}%)", choice.label, choice.probability() * 100.0);
+    /// ```
+    pub fn choose(
+        &mut self,
+        prompt: &str,
+        options: &[String],
+    ) -> Result<Choice, Box<dyn std::error::Error>> {
+        let generator = self
+            .generator
+            .as_mut()
+            .ok_or("Model not loaded. Call load() first.")?;
+
+        Ok(generator.choose(prompt, options)?)
+    }
+
+    /// Run `prompt` through the model once and record activation
+    /// statistics: mean, RMS, largest magnitude and NaN/Inf count of the
+    /// embeddings, every layer's output and the logits.
+    ///
+    /// Useful when a quantization produces garbage: the first layer whose
+    /// statistics explode or turn non-finite is usually the culprit. Layer
+    /// statistics are only available for architectures implemented in this
+    /// crate (qwen35); others report the logits alone. The conversation
+    /// history is not used or changed.
+    ///
+    /// Requires `load()` to be called first.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// let report = model.forward_debug("Hello")?;
+    /// if let Some(layer) = report.first_non_finite() {
+    ///     println!("{} produced {} NaN/Inf values", layer.name, layer.non_finite);
+    /// }
+    /// ```
+    pub fn forward_debug(
+        &mut self,
+        prompt: &str,
+    ) -> Result<ActivationReport, Box<dyn std::error::Error>> {
+        let generator = self
+            .generator
+            .as_mut()
+            .ok_or("Model not loaded. Call load() first.")?;
+
+        Ok(generator.debug_activations(prompt)?)
+    }
+
+    /// Write a document of about `target_tokens` tokens, longer than the
+    /// context window if need be.
+    ///
+    /// The model first outlines the document, then writes one section at a
+    /// time, seeing the outline and a short summary of each earlier section
+    /// instead of their full text. The result is Markdown with a `##`
+    /// heading per section. Clears the conversation history.
+    ///
+    /// Requires `load()` to be called first.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// let guide = model.generate_long("A beginner's guide to Rust lifetimes", 6000)?;
+    /// std::fs::write("guide.md", guide)?;
+    /// ```
+    pub fn generate_long(
+        &mut self,
+        prompt: &str,
+        target_tokens: usize,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let generator = self
+            .generator
+            .as_mut()
+            .ok_or("Model not loaded. Call load() first.")?;
+
+        let options = inference::LongFormOptions {
+            target_tokens,
+            repeat_penalty: self.options.repeat_penalty,
+            repeat_last_n: self.options.repeat_last_n,
+        };
+        Ok(inference::write_long(generator, prompt, &options, |_| {})?)
+    }
+
+    /// Pin a document ahead of the conversation so later prompts can ask
+    /// about it, even when it is longer than the context window.
+    ///
+    /// A document that fits half the context is pinned as it is. A longer
+    /// one is read in segments, with the model's notes on each carried
+    /// forward to the next and condensed further when they outgrow that
+    /// half; the notes are pinned instead. Keeps the conversation history.
+    ///
+    /// Requires `load()` to be called first.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// let text = std::fs::read_to_string("report.txt")?;
+    /// let digest = model.read_document(&text)?;
+    /// println!("Read in {} segments", digest.segments);
+    /// let answer = model.generate("Who signed the contract, and when?")?;
+    /// ```
+    pub fn read_document(
+        &mut self,
+        text: &str,
+    ) -> Result<DocumentDigest, Box<dyn std::error::Error>> {
+        let generator = self
+            .generator
+            .as_mut()
+            .ok_or("Model not loaded. Call load() first.")?;
+
+        let options = DocumentOptions {
+            repeat_penalty: self.options.repeat_penalty,
+            repeat_last_n: self.options.repeat_last_n,
+            ..Default::default()
+        };
+        Ok(generator.read_document(text, &options, |_| {})?)
+    }
+
     /// Pre-compile compute kernels for faster first-token generation.
     ///
     /// Call this after `load()` to warm up the model before first use.
@@ -479,6 +1490,24 @@ impl Model {
         Ok(())
     }
 
+    /// Warm up the loaded model following `policy`, e.g. to run a fuller
+    /// warmup later than the one chosen with [`Model::with_warmup`].
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// model.load()?;
+    /// model.warmup_with(WarmupPolicy::SystemPrompt)?;
+    /// ```
+    pub fn warmup_with(&mut self, policy: WarmupPolicy) -> Result<(), Box<dyn std::error::Error>> {
+        let generator = self
+            .generator
+            .as_mut()
+            .ok_or("Model not loaded. Call load() first.")?;
+        generator.warmup_with(policy)?;
+        Ok(())
+    }
+
     /// Clear conversation history.
     ///
     /// Removes all previous messages from the conversation context.
@@ -512,6 +1541,25 @@ impl Model {
         self.generator.as_ref().map(|g| g.metadata())
     }
 
+    /// Get the time each phase of `load()` took.
+    ///
+    /// Covers opening and mapping the file, parsing the GGUF header,
+    /// building the tensors, the tokenizer and the chat template, and the
+    /// last `warmup()`. Returns `None` before `load()`.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// model.load()?;
+    /// model.warmup(16)?;
+    /// if let Some(report) = model.load_report() {
+    ///     println!("{}", report);
+    /// }
+    /// ```
+    pub fn load_report(&self) -> Option<&LoadReport> {
+        self.generator.as_ref().map(|g| g.load_report())
+    }
+
     /// Get current context usage.
     ///
     /// Returns the number of tokens currently in the context.
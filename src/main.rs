@@ -1,24 +1,66 @@
-use std::io::{self, Write};
-use std::path::PathBuf;
-
-use anyhow::Result;
-use clap::Parser;
+use std::collections::HashMap;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use clap::builder::BoolishValueParser;
+use clap::parser::ValueSource;
+use clap::{ArgAction, ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand};
 use oxide_rs::cli::download::DownloadProgressBar;
+use oxide_rs::cli::i18n::{self, Lang, Msg};
+use oxide_rs::cli::logging::{self, LogConfig, LogFormat};
+use oxide_rs::cli::theme;
 use oxide_rs::cli::{
-    print_banner, print_divider, print_model_info, print_welcome, ModelLoader, PromptDisplay,
-    Spinner, StreamOutput, ThinkingSpinner,
+    pick_model, print_banner, print_divider, print_model_info, print_side_by_side, print_welcome,
+    show_probabilities, step_reply, CompareColumn, HookOutcome, ModelLoader, PromptDisplay,
+    ScriptHost, ScriptSettings, ShellHook, Spinner, StreamOutput,
+};
+#[cfg(unix)]
+use oxide_rs::daemon::{self, DaemonRequest};
+use oxide_rs::inference::agents::{
+    run_agents, AgentEvent, AgentsConfig, AgentsOptions, StopReason,
 };
+use oxide_rs::inference::commit::{apply_commit, clean_message, commit_prompt, staged_diff};
+use oxide_rs::inference::eval::{run_suite, EvalOptions, EvalSuite, JudgeConfig};
+use oxide_rs::inference::fuzz::{run_fuzz, FuzzOptions, Perturbation};
+use oxide_rs::inference::long_form::{write_long, LongFormEvent, LongFormOptions};
+use oxide_rs::inference::needle::{run_needle_test, NeedleOptions};
+use oxide_rs::inference::replay;
+use oxide_rs::inference::summarize::{summarize, SummarizeEvent, SummarizeOptions};
+use oxide_rs::inference::verify::load_reference;
 use oxide_rs::inference::{
-    init_simd, init_thread_pinner, simd_dispatch::SimdLevel, thread_pinner::ThreadPinnerConfig,
-    Generator, StreamEvent,
+    check_records, citations, classify_prompt, code_prompt, init_simd, init_thread_pinner,
+    load_examples, load_messages, map_prompt, read_document_text, render_template,
+    simd_dispatch::SimdLevel, thread_pinner::ThreadPinnerConfig, CancelToken, CodeIndex,
+    ContextOverflow, ContextPolicy, DatasetRecord, DatasetWriter, DocumentEvent, DocumentOptions,
+    EosControl, FlushPolicy, GenerationError, Generator, LogitBias, LoopAction, LoopGuard, MapLine,
+    MapOutput, MapOverrides, Message, ModelFingerprint, ModerationConfig, NoteStore,
+    ProbabilityTap, RecordedEvent, Recorder, Recording, RecordingHeader, RedactionConfig,
+    SamplingPreset, Session, SessionParams, StreamEvent, StreamGranularity, Tee, TopNSigma,
+    WarmupPolicy,
 };
-use oxide_rs::model::download::find_gguf_file;
+#[cfg(unix)]
+use oxide_rs::ipc;
+#[cfg(feature = "object-store")]
+use oxide_rs::model::bucket::fetch_model as fetch_bucket_model;
+use oxide_rs::model::convert::convert_safetensors;
+use oxide_rs::model::download::DownloadProgress;
+use oxide_rs::model::download::{download_safetensors, find_gguf_file};
+use oxide_rs::model::gguf_edit::{set_chat_template, set_key};
+use oxide_rs::model::quantize::{quantize_gguf, QuantPreset, QuantizeProgress};
+use oxide_rs::model::remote::{fetch_model, is_bucket, is_remote};
 use oxide_rs::model::{
-    download_model, format_size, get_model_info, list_models, register_model, unregister_model,
+    architecture_info, check_model, check_remote_model, discover_models, download_model,
+    format_size, get_model_info, list_models, load_with_retry, register_model,
+    supported_architectures, unregister_model, ArchStatus, CheckStatus, LoadError, LoadRetry,
+    Model, TokenizerWrapper,
 };
-use oxide_rs::server::run as server_run;
+use oxide_rs::server::state::AppState;
+#[cfg(feature = "telemetry")]
+use oxide_rs::server::OtlpConfig;
+use oxide_rs::server::{run_with_state as server_run, CacheConfig, QuotaConfig};
 use oxide_rs::tui::state::Screen;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use oxide_rs::GenerateOptions;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -28,7 +70,7 @@ struct Cli {
     download: Option<String>,
 
     /// List all locally downloaded models
-    #[arg(short, long)]
+    #[arg(long)]
     models: bool,
 
     /// Show information about a model on HuggingFace Hub
@@ -39,93 +81,865 @@ struct Cli {
     #[arg(long)]
     remove: Option<String>,
 
-    /// Path to GGUF model file
-    #[arg(short, long)]
-    model: Option<PathBuf>,
+    /// Path to GGUF model file, or an http(s) URL to download and cache
+    /// (repeatable for `compare`)
+    #[arg(short, long, global = true, env = "OXIDE_MODEL", action = ArgAction::Append)]
+    model: Vec<PathBuf>,
+
+    /// Expected SHA-256 of a --model URL, checked after downloading
+    #[arg(long, global = true, value_name = "HEX", env = "OXIDE_MODEL_SHA256")]
+    model_sha256: Option<String>,
+
+    /// Directory to search for GGUF files when --model is omitted (repeatable)
+    #[arg(
+        long,
+        global = true,
+        value_name = "DIR",
+        env = "OXIDE_MODEL_DIR",
+        value_delimiter = ','
+    )]
+    model_dir: Vec<PathBuf>,
 
     /// Path to tokenizer.json (optional, will extract from GGUF if not provided)
-    #[arg(short, long)]
+    #[arg(short, long, global = true, env = "OXIDE_TOKENIZER")]
     tokenizer: Option<PathBuf>,
 
-    /// Maximum tokens to generate
-    #[arg(short, long, default_value = "512")]
+    /// Times to retry loading a model that failed for a transient reason,
+    /// e.g. a busy file or a short read from a network share
+    #[arg(
+        long,
+        global = true,
+        default_value = "2",
+        value_name = "N",
+        env = "OXIDE_LOAD_RETRIES"
+    )]
+    load_retries: usize,
+
+    /// Maximum tokens to generate (`auto` fills the remaining context)
+    #[arg(long, global = true, default_value = "512", value_parser = parse_max_tokens, env = "OXIDE_MAX_TOKENS")]
     max_tokens: usize,
 
+    /// Write a document of about this many tokens for --prompt: an outline
+    /// first, then one section at a time, beyond the context window if needed
+    #[arg(long, value_name = "TOKENS", requires = "prompt", env = "OXIDE_LONG")]
+    long: Option<usize>,
+
+    /// Sampling preset; explicit sampling flags override its values. Without
+    /// it, code and reasoning models get the matching preset
+    #[arg(
+        long,
+        global = true,
+        value_name = "creative|precise|code|reasoning",
+        env = "OXIDE_PRESET"
+    )]
+    preset: Option<SamplingPreset>,
+
     /// Temperature for sampling (0.0 = greedy)
-    #[arg(long, default_value = "0.3")]
+    #[arg(long, global = true, default_value = "0.3", env = "OXIDE_TEMPERATURE")]
     temperature: f64,
 
     /// Top-p sampling threshold
-    #[arg(long)]
+    #[arg(long, global = true, env = "OXIDE_TOP_P")]
     top_p: Option<f64>,
 
     /// Top-k sampling
-    #[arg(long)]
+    #[arg(long, global = true, env = "OXIDE_TOP_K")]
     top_k: Option<usize>,
 
+    /// Top-nσ sampling: keep tokens whose logit is within N standard
+    /// deviations of the largest
+    #[arg(long, global = true, value_name = "N", env = "OXIDE_TOP_N_SIGMA")]
+    top_n_sigma: Option<f32>,
+
+    /// Added to the end-of-sequence logit: positive ends replies sooner,
+    /// negative makes them run longer
+    #[arg(
+        long,
+        global = true,
+        default_value = "0",
+        allow_negative_numbers = true,
+        env = "OXIDE_EOS_BIAS"
+    )]
+    eos_bias: f32,
+
+    /// Tokens to generate before the model may end its reply
+    #[arg(long, global = true, default_value = "0", env = "OXIDE_MIN_TOKENS")]
+    min_tokens: usize,
+
     /// Repeat penalty
-    #[arg(long, default_value = "1.1")]
+    #[arg(
+        long,
+        global = true,
+        default_value = "1.1",
+        env = "OXIDE_REPEAT_PENALTY"
+    )]
     repeat_penalty: f32,
 
     /// Context size for repeat penalty
-    #[arg(long, default_value = "64")]
+    #[arg(long, global = true, default_value = "64", env = "OXIDE_REPEAT_LAST_N")]
     repeat_last_n: usize,
 
     /// Batch size for warmup/prefill (default: 128)
-    #[arg(long, default_value = "128")]
+    #[arg(long, global = true, default_value = "128", env = "OXIDE_BATCH_SIZE")]
     batch_size: usize,
 
     /// Random seed
-    #[arg(long, default_value = "299792458")]
+    #[arg(long, global = true, default_value = "299792458", env = "OXIDE_SEED")]
     seed: u64,
 
     /// Number of threads for inference (default: auto-detect)
-    #[arg(long)]
+    #[arg(long, global = true, env = "OXIDE_THREADS")]
     threads: Option<usize>,
 
+    /// Do not pin inference threads to CPU cores, e.g. when sharing the
+    /// machine with other CPU-heavy processes or inside a CPU-limited container
+    #[arg(long, global = true, env = "OXIDE_NO_PIN", value_parser = BoolishValueParser::new())]
+    no_pin: bool,
+
     /// System prompt for the model
-    #[arg(short, long)]
+    #[arg(short, long, global = true, env = "OXIDE_SYSTEM")]
     system: Option<String>,
 
+    /// Language code (e.g. `fr`) to use instead of detecting it from each prompt
+    #[arg(long, global = true, env = "OXIDE_FORCE_LANGUAGE")]
+    force_language: Option<String>,
+
+    /// Few-shot examples file (JSON array or JSONL of {"user", "assistant"} objects)
+    #[arg(long, global = true, env = "OXIDE_EXAMPLES")]
+    examples: Option<PathBuf>,
+
+    /// Text, Markdown, HTML or PDF file to ask questions about; longer than
+    /// half the context, it is read in parts and notes on them are pinned
+    /// instead
+    #[arg(long, global = true, value_name = "PATH", env = "OXIDE_DOCUMENT")]
+    document: Option<PathBuf>,
+
+    /// Add a bias to a token's logit before sampling, as TOKEN_ID=BIAS (repeatable; -inf bans the token)
+    #[arg(
+        long = "logit-bias",
+        global = true,
+        value_name = "TOKEN_ID=BIAS",
+        env = "OXIDE_LOGIT_BIAS",
+        value_delimiter = ','
+    )]
+    logit_bias: Vec<String>,
+
+    /// Low-memory mode: prefill in small chunks and skip context-sized buffers (slower)
+    #[arg(long, global = true, env = "OXIDE_LOW_MEM", value_parser = BoolishValueParser::new())]
+    low_mem: bool,
+
+    /// Stream replies per token, word or sentence
+    #[arg(
+        long,
+        global = true,
+        value_name = "token|word|sentence",
+        default_value = "token",
+        env = "OXIDE_STREAM_GRANULARITY"
+    )]
+    stream_granularity: StreamGranularity,
+
+    /// Show mean token entropy and the smallest top-1 margin of each reply
+    /// in the stats line
+    #[arg(long, global = true, env = "OXIDE_CONFIDENCE", value_parser = BoolishValueParser::new())]
+    confidence: bool,
+
+    /// Color each streamed token by its probability: green when the model
+    /// was confident, red when it was not
+    #[arg(long, global = true, env = "OXIDE_SHOW_PROBS", value_parser = BoolishValueParser::new())]
+    show_probs: bool,
+
+    /// When a reply reaches --max-tokens mid-sentence, keep generating a few
+    /// dozen tokens until the sentence ends
+    #[arg(long, global = true, env = "OXIDE_FINISH_AT_BOUNDARY", value_parser = BoolishValueParser::new())]
+    finish_at_boundary: bool,
+
+    /// Longest prefill before the first token, in milliseconds; longer
+    /// prompts lose their oldest tokens
+    #[arg(long, global = true, value_name = "MS", env = "OXIDE_TTFT_BUDGET_MS")]
+    ttft_budget_ms: Option<u64>,
+
+    /// When a prompt does not fit the context window: drop the oldest turns,
+    /// summarize them, or fail with an error
+    #[arg(
+        long,
+        global = true,
+        value_name = "truncate|error|summarize",
+        default_value = "truncate",
+        env = "OXIDE_CONTEXT_POLICY"
+    )]
+    context_policy: ContextPolicy,
+
+    /// When a reply keeps repeating a short cycle of tokens: end it, raise
+    /// the temperature by 0.5 (or DELTA) once, or never check
+    #[arg(
+        long,
+        global = true,
+        value_name = "stop|bump[=DELTA]|off",
+        default_value = "stop",
+        env = "OXIDE_LOOP_GUARD"
+    )]
+    loop_guard: LoopAction,
+
+    /// Tokens that must all repeat a cycle before --loop-guard acts
+    #[arg(
+        long,
+        global = true,
+        value_name = "N",
+        default_value_t = 64,
+        env = "OXIDE_LOOP_WINDOW"
+    )]
+    loop_window: usize,
+
+    /// Sampling flags given on the command line or in the environment
+    #[arg(skip)]
+    explicit_sampling: ExplicitSampling,
+
+    /// Shell command given each prompt on stdin; its output replaces the
+    /// prompt and a non-zero exit blocks it
+    #[arg(long, value_name = "CMD", global = true, env = "OXIDE_PRE_PROMPT_CMD")]
+    pre_prompt_cmd: Option<String>,
+
+    /// Shell command given each reply on stdin; its output replaces the
+    /// reply and a non-zero exit blocks it. Replies are shown once complete
+    #[arg(
+        long,
+        value_name = "CMD",
+        global = true,
+        env = "OXIDE_POST_RESPONSE_CMD"
+    )]
+    post_response_cmd: Option<String>,
+
+    /// Redact emails, phone numbers and card numbers from generated text
+    #[arg(long, global = true, env = "OXIDE_REDACT", value_parser = BoolishValueParser::new())]
+    redact: bool,
+
+    /// Redaction rules file (JSON) replacing the built-in patterns
+    #[arg(long, global = true, value_name = "PATH", env = "OXIDE_REDACT_RULES")]
+    redact_rules: Option<PathBuf>,
+
+    /// Print a breakdown of model load time (file open, header, tensors,
+    /// tokenizer, template, warmup)
+    #[arg(long, global = true, env = "OXIDE_VERBOSE", value_parser = BoolishValueParser::new())]
+    verbose: bool,
+
+    /// Write diagnostics to this file instead of stderr
+    #[arg(long, global = true, value_name = "PATH", env = "OXIDE_LOG_FILE")]
+    log_file: Option<PathBuf>,
+
+    /// Diagnostics to keep: error, warn, info, debug, trace, or filter
+    /// directives such as oxide_rs=debug (default: RUST_LOG, then info)
+    #[arg(long, global = true, value_name = "LEVEL", env = "OXIDE_LOG_LEVEL")]
+    log_level: Option<String>,
+
+    /// Format of diagnostics
+    #[arg(
+        long,
+        global = true,
+        value_name = "text|json",
+        default_value = "text",
+        env = "OXIDE_LOG_FORMAT"
+    )]
+    log_format: LogFormat,
+
+    /// Language of CLI and REPL messages: en, es, de or zh
+    /// (default: from LC_ALL, LC_MESSAGES or LANG)
+    #[arg(long, global = true, value_name = "LANG", env = "OXIDE_LANG")]
+    lang: Option<Lang>,
+
+    /// Session file: resumed at startup if it exists, default target of /save
+    #[arg(long, value_name = "PATH", env = "OXIDE_SESSION")]
+    session: Option<PathBuf>,
+
+    /// Resume a session even if it was saved with a different model
+    #[arg(long)]
+    force: bool,
+
+    /// Append every reply to this file while it streams
+    #[arg(long, value_name = "PATH", env = "OXIDE_TEE")]
+    tee: Option<PathBuf>,
+
+    /// When --tee flushes the file: after every chunk, line, or reply
+    #[arg(
+        long,
+        value_name = "chunk|line|end",
+        default_value = "chunk",
+        env = "OXIDE_TEE_FLUSH"
+    )]
+    tee_flush: FlushPolicy,
+
+    /// Run --prompt through the model once and write per-layer activation
+    /// statistics (mean, RMS, max, NaN/Inf count) to this JSON file
+    #[arg(
+        long,
+        value_name = "PATH",
+        requires = "prompt",
+        env = "OXIDE_DEBUG_ACTIVATIONS"
+    )]
+    debug_activations: Option<PathBuf>,
+
+    /// Append every decode step's latency to this CSV file and print
+    /// p50/p95/p99 after each reply
+    #[arg(long, value_name = "PATH", env = "OXIDE_PER_TOKEN_TIMING")]
+    per_token_timing: Option<PathBuf>,
+
+    /// Record prompts, replies, settings and the model fingerprint to this
+    /// file, to be re-run with `oxide-rs replay`
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = ["shared_runtime", "map"],
+        env = "OXIDE_RECORD"
+    )]
+    record: Option<PathBuf>,
+
+    /// Append every kept exchange to this JSONL file in chat format, for
+    /// building fine-tuning datasets. Contents are redacted (--redact-rules
+    /// or the built-in patterns)
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = ["shared_runtime", "map"],
+        env = "OXIDE_COLLECT"
+    )]
+    collect: Option<PathBuf>,
+
+    /// Shell command given each --collect record as JSON on stdin; JSON it
+    /// prints replaces the record and a non-zero exit drops it
+    #[arg(
+        long,
+        value_name = "CMD",
+        requires = "collect",
+        env = "OXIDE_COLLECT_HOOK"
+    )]
+    collect_hook: Option<String>,
+
     /// Prompt to use (if not using interactive mode)
-    #[arg(short, long)]
+    #[arg(short, long, env = "OXIDE_PROMPT")]
     prompt: Option<String>,
 
     /// Run in non-interactive mode (generate and exit)
-    #[arg(short, long)]
+    #[arg(short, long, env = "OXIDE_ONCE", value_parser = BoolishValueParser::new())]
     once: bool,
 
+    /// With --once, send the prompt to a background daemon that keeps the
+    /// model loaded, starting one if needed, instead of loading it here
+    #[arg(long, requires = "once", env = "OXIDE_SHARED_RUNTIME", value_parser = BoolishValueParser::new())]
+    shared_runtime: bool,
+
+    /// Transform stdin line by line: each line fills {input} in TEMPLATE
+    /// and each result is printed as one line
+    #[arg(long, value_name = "TEMPLATE", env = "OXIDE_MAP")]
+    map: Option<String>,
+
+    /// Read --map input as JSON Lines and fill the template from FIELD
+    #[arg(long, value_name = "FIELD", requires = "map")]
+    map_field: Option<String>,
+
+    /// Field that --map-field mode stores results in
+    #[arg(long, value_name = "FIELD", default_value = "output")]
+    map_output_field: String,
+
+    /// Lines --map generates per batch
+    #[arg(long, value_name = "N", default_value = "1", requires = "map")]
+    parallel: usize,
+
+    /// Write --map results to PATH instead of stdout, checkpointing progress
+    /// to PATH.checkpoint after every batch
+    #[arg(long, value_name = "PATH", requires = "map")]
+    map_output: Option<PathBuf>,
+
+    /// Continue an interrupted --map-output run from its checkpoint, skipping
+    /// the input lines it finished
+    #[arg(long, requires = "map_output")]
+    resume: bool,
+
     /// Maximum batch size for dynamic batching (default: 8)
-    #[arg(long, default_value = "8")]
+    #[arg(long, global = true, default_value = "8", env = "OXIDE_MAX_BATCH_SIZE")]
     max_batch_size: usize,
 
     /// Batch window in milliseconds (default: 100ms)
-    #[arg(long, default_value = "100")]
+    #[arg(long, default_value = "100", env = "OXIDE_BATCH_WINDOW_MS")]
     batch_window_ms: u64,
 
     /// SIMD level (auto/avx512/avx2/neon/scalar)
-    #[arg(long, default_value = "auto")]
+    #[arg(long, global = true, default_value = "auto", env = "OXIDE_SIMD")]
     simd: String,
 
     /// Launch TUI mode instead of CLI chat
-    #[arg(short, long)]
+    #[arg(long)]
     tui: bool,
 
     /// Run as OpenAI-compatible HTTP server
-    #[arg(long)]
+    #[arg(long, env = "OXIDE_SERVER", value_parser = BoolishValueParser::new())]
     server: bool,
 
     /// Port for HTTP server (default: 8080)
-    #[arg(long, default_value = "8080")]
+    #[arg(long, default_value = "8080", env = "OXIDE_PORT")]
     port: u16,
 
     /// Host for HTTP server (default: 0.0.0.0)
-    #[arg(long, default_value = "0.0.0.0")]
+    #[arg(long, default_value = "0.0.0.0", env = "OXIDE_HOST")]
     host: String,
+
+    /// Start a headless server on HOST:PORT (implies --server; for containers)
+    #[arg(long, value_name = "HOST:PORT", env = "OXIDE_LISTEN", value_parser = parse_listen)]
+    listen: Option<(String, u16)>,
+
+    /// Moderation config (JSON keyword categories) applied to server replies
+    #[arg(long, value_name = "PATH", env = "OXIDE_MODERATION")]
+    moderation: Option<PathBuf>,
+
+    /// Reject server requests whose prompt exceeds this many tokens
+    #[arg(long, value_name = "N", env = "OXIDE_MAX_PROMPT_TOKENS")]
+    max_prompt_tokens: Option<usize>,
+
+    /// Total tokens each server session (OpenAI `user`) may use
+    #[arg(long, value_name = "N", env = "OXIDE_SESSION_TOKEN_QUOTA")]
+    session_token_quota: Option<u64>,
+
+    /// Load a model when the server starts and keep it warm (repeatable)
+    #[arg(
+        long,
+        value_name = "PATH",
+        env = "OXIDE_PRELOAD",
+        value_delimiter = ','
+    )]
+    preload: Vec<PathBuf>,
+
+    /// Tokens run through each preloaded model to warm up its kernels
+    #[arg(
+        long,
+        value_name = "N",
+        default_value = "16",
+        env = "OXIDE_PRELOAD_WARMUP"
+    )]
+    preload_warmup: usize,
+
+    /// How much to run through the model after loading: none, minimal,
+    /// full or system-prompt (default: full for servers and the daemon,
+    /// none with --once, minimal otherwise)
+    #[arg(long, global = true, value_name = "POLICY", env = "OXIDE_WARMUP")]
+    warmup: Option<WarmupPolicy>,
+
+    /// Serve identical server requests from a cache for this many seconds
+    #[arg(long, value_name = "SECS", env = "OXIDE_RESPONSE_CACHE_TTL")]
+    response_cache_ttl: Option<u64>,
+
+    /// Largest number of cached server responses
+    #[arg(
+        long,
+        value_name = "N",
+        default_value = "256",
+        env = "OXIDE_RESPONSE_CACHE_SIZE"
+    )]
+    response_cache_size: usize,
+
+    /// Enable the /admin endpoints, authenticated with this bearer token
+    #[arg(long, value_name = "TOKEN", env = "OXIDE_ADMIN_TOKEN")]
+    admin_token: Option<String>,
+
+    /// Export request spans to this OTLP/HTTP collector, e.g. http://localhost:4318
+    #[cfg(feature = "telemetry")]
+    #[arg(long, value_name = "URL", env = "OTEL_EXPORTER_OTLP_ENDPOINT")]
+    otlp_endpoint: Option<String>,
+
+    /// `service.name` of exported spans
+    #[cfg(feature = "telemetry")]
+    #[arg(
+        long,
+        value_name = "NAME",
+        default_value = "oxide-rs",
+        env = "OTEL_SERVICE_NAME"
+    )]
+    otlp_service_name: String,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Summarize a document larger than the context window (map-reduce)
+    Summarize {
+        /// Text, Markdown, HTML or PDF file to summarize
+        #[arg(short, long)]
+        file: PathBuf,
+
+        /// Token budget per chunk (default: derived from the context window)
+        #[arg(long)]
+        chunk_tokens: Option<usize>,
+
+        /// What the summary should focus on, e.g. "decisions and action items"
+        #[arg(long)]
+        focus: Option<String>,
+    },
+    /// Check whether a GGUF file can be loaded, without loading its weights
+    Check,
+    /// List the model architectures oxide-rs supports, or look up one
+    Archs {
+        /// Architecture to look up, as in `general.architecture`
+        name: Option<String>,
+
+        /// Print the matrix as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Convert a GGUF file to another quantization type
+    Quantize {
+        /// Source GGUF file
+        input: PathBuf,
+
+        /// Destination GGUF file
+        output: PathBuf,
+
+        /// Target type: f16, q8_0, q6_k, q5_k_m, q5_k_s, q5_0, q4_k_m, q4_k_s, q4_0, q3_k_m, q2_k
+        #[arg(long = "type", default_value = "q4_k_m")]
+        quant_type: QuantPreset,
+    },
+    /// Convert a Hugging Face safetensors model directory to GGUF
+    Convert {
+        /// Directory with config.json, tokenizer.json and *.safetensors, or a
+        /// HuggingFace repo id to download them from
+        input: PathBuf,
+
+        /// Destination GGUF file
+        output: PathBuf,
+
+        /// Target type (see `quantize`)
+        #[arg(long = "type", default_value = "q8_0")]
+        quant_type: QuantPreset,
+    },
+    /// Edit GGUF metadata
+    Gguf {
+        #[command(subcommand)]
+        action: GgufCommand,
+    },
+    /// Answer questions about a git repository, citing files and lines
+    Code {
+        /// Question to answer; starts an interactive session when omitted
+        question: Option<String>,
+
+        /// Repository to index (files ignored by .gitignore are skipped)
+        #[arg(long, default_value = ".")]
+        repo: PathBuf,
+
+        /// Excerpts retrieved per question
+        #[arg(long, default_value_t = 6)]
+        top_k: usize,
+
+        /// Print each answer as a JSON line with its citations instead of
+        /// streaming it
+        #[arg(long)]
+        json: bool,
+    },
+    /// Write a Conventional Commits message for the staged changes
+    Commit {
+        /// Commit with the message instead of only printing it
+        #[arg(long)]
+        apply: bool,
+
+        /// Describe the last commit together with the staged changes; with
+        /// --apply, rewrite that commit
+        #[arg(long)]
+        amend: bool,
+
+        /// Language of the summary and body, e.g. German
+        #[arg(long)]
+        language: Option<String>,
+
+        /// Repository
+        #[arg(long, default_value = ".")]
+        repo: PathBuf,
+    },
+    /// Let several personas, possibly on different models, talk in turns
+    Agents {
+        /// TOML file with the topic, the [[agent]] personas, turn limits and
+        /// an optional [moderator]
+        #[arg(short, long)]
+        config: PathBuf,
+    },
+    /// Classify text as exactly one of a fixed set of labels
+    Classify {
+        /// Comma-separated labels, e.g. "positive,negative,neutral"
+        #[arg(long, value_delimiter = ',', required = true)]
+        labels: Vec<String>,
+
+        /// Text to classify; without it every stdin line is classified
+        text: Option<String>,
+
+        /// Print JSON objects with the probability of every label
+        #[arg(long)]
+        json: bool,
+    },
+    /// Build a reply token by token, choosing among the most likely next
+    /// tokens at every step
+    Step {
+        /// Prompt to reply to; starts an interactive session with /step on
+        /// when omitted
+        prompt: Option<String>,
+
+        /// Candidates shown at each step
+        #[arg(long, default_value_t = 5)]
+        top: usize,
+    },
+    /// Run one prompt through several models with the same settings and
+    /// show the replies side by side, e.g. to choose between quantizations
+    Compare {
+        /// Prompt to reply to
+        #[arg(short, long)]
+        prompt: String,
+    },
+    /// Exchanges marked with /mark or /note in interactive mode
+    Notes {
+        #[command(subcommand)]
+        action: NotesCommand,
+    },
+    /// Inspect the model's chat template
+    Template {
+        #[command(subcommand)]
+        action: TemplateCommand,
+    },
+    /// Decode reference prompts greedily and compare the token ids with
+    /// another implementation's output, e.g. llama.cpp
+    Verify {
+        /// JSON Lines file of {"prompt", "tokens", optional "prompt_tokens"}
+        #[arg(long, value_name = "PATH")]
+        reference: PathBuf,
+
+        /// Print one JSON report per case instead of a summary line
+        #[arg(long)]
+        json: bool,
+    },
+    /// Run a prompt suite and score the replies with checkers or a judge
+    /// model
+    Eval {
+        /// YAML file with the cases and their checks
+        #[arg(long, value_name = "PATH")]
+        suite: PathBuf,
+
+        /// Judge model, replacing the suite's `judge.model`
+        #[arg(long, value_name = "PATH")]
+        judge: Option<PathBuf>,
+
+        /// Print the report as JSON instead of Markdown
+        #[arg(long)]
+        json: bool,
+
+        /// Write the report to this file instead of stdout
+        #[arg(long, value_name = "PATH")]
+        output: Option<PathBuf>,
+    },
+    /// Answer a prompt and seeded variants of it (typos, casing, whitespace,
+    /// paraphrases) and report how stable the answers are
+    Fuzz {
+        /// Prompt to perturb
+        #[arg(short, long)]
+        prompt: String,
+
+        /// Perturbations to apply
+        #[arg(
+            long,
+            value_delimiter = ',',
+            default_value = "typos,case,whitespace,paraphrase"
+        )]
+        perturb: Vec<Perturbation>,
+
+        /// Variants per perturbation
+        #[arg(long, default_value_t = 3)]
+        variants: usize,
+
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Hide a passcode at several depths of filler text filling more and
+    /// more of the context window, and check the model can still find it
+    CtxTest {
+        /// Shares of the context window the prompt fills
+        #[arg(long, value_delimiter = ',', default_value = "0.25,0.5,0.75,1")]
+        levels: Vec<f64>,
+
+        /// Positions of the passcode in the text, from 0 (start) to 1 (end)
+        #[arg(long, value_delimiter = ',', default_value = "0,0.25,0.5,0.75,1")]
+        depths: Vec<f64>,
+
+        /// Context window to test, in tokens (default: the model's)
+        #[arg(long, value_name = "TOKENS")]
+        context: Option<usize>,
+
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Re-run a session recorded with --record and check that every reply
+    /// comes out the same
+    Replay {
+        /// Recording file
+        file: PathBuf,
+
+        /// Replay even if the recording was made with a different model
+        #[arg(long)]
+        force: bool,
+    },
+    /// Serve the model to local clients such as editor plugins over a unix
+    /// socket, speaking line-delimited JSON
+    Serve {
+        /// Socket path to listen on
+        #[arg(long, value_name = "PATH")]
+        unix: PathBuf,
+    },
+    /// Keep the model loaded and answer --shared-runtime requests on a unix
+    /// socket
+    Daemon {
+        /// Exit after this many seconds without a request
+        #[arg(long, value_name = "SECS", default_value_t = 600)]
+        idle_timeout: u64,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum TemplateCommand {
+    /// Print the prompt the chat template renders for a conversation, and its
+    /// token count, without loading the weights
+    Render {
+        /// JSON array of {role, content} messages, or a request body with a
+        /// "messages" array
+        #[arg(long)]
+        messages: PathBuf,
+
+        /// Stop after the last message instead of opening an assistant turn
+        #[arg(long)]
+        no_generation_prompt: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum NotesCommand {
+    /// Show notes whose prompt, reply or note contain every term, newest first
+    Search {
+        /// Search terms; omit to list every note
+        query: Vec<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum GgufCommand {
+    /// Replace the embedded chat template with one read from a Jinja file
+    SetTemplate {
+        /// GGUF file to edit
+        file: PathBuf,
+
+        /// Jinja chat template file
+        template: PathBuf,
+
+        /// Write to this file instead of editing in place
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Set a metadata key; existing keys keep their type
+    SetKey {
+        /// GGUF file to edit
+        file: PathBuf,
+
+        /// Metadata key, e.g. general.name
+        key: String,
+
+        /// New value
+        value: String,
+
+        /// Write to this file instead of editing in place
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
 }
 
 fn main() -> Result<()> {
-    let cli = Cli::parse();
+    let matches = Cli::command().get_matches();
+    let mut cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    cli.explicit_sampling = ExplicitSampling::from_matches(&matches);
+    oxide_rs::platform::enable_ansi();
+    let plain = theme::detect_plain_output();
+    oxide_rs::cli::terminal::install_panic_hook();
+    if let Some(lang) = cli.lang {
+        i18n::set_lang(lang);
+    }
+
+    // Servers log by default; everything else only when asked, so
+    // diagnostics never interleave with replies.
+    let logging = LogConfig {
+        level: cli.log_level.clone(),
+        file: cli.log_file.clone(),
+        format: cli.log_format,
+        ansi: !plain,
+    };
+    let serving =
+        cli.server || cli.listen.is_some() || matches!(cli.command, Some(Command::Serve { .. }));
+    if serving || logging.is_requested() {
+        logging::init(&logging)?;
+    }
+
+    if let Some(command) = cli.command.take() {
+        return match command {
+            Command::Summarize {
+                file,
+                chunk_tokens,
+                focus,
+            } => handle_summarize(cli, file, chunk_tokens, focus),
+            Command::Check => handle_check(cli),
+            Command::Archs { name, json } => handle_archs(name.as_deref(), json),
+            Command::Quantize {
+                input,
+                output,
+                quant_type,
+            } => handle_quantize(&input, &output, quant_type),
+            Command::Convert {
+                input,
+                output,
+                quant_type,
+            } => handle_convert(&input, &output, quant_type),
+            Command::Gguf { action } => handle_gguf(action),
+            Command::Code {
+                question,
+                repo,
+                top_k,
+                json,
+            } => handle_code(cli, &repo, top_k, question, json),
+            Command::Commit {
+                apply,
+                amend,
+                language,
+                repo,
+            } => handle_commit(cli, &repo, apply, amend, language.as_deref()),
+            Command::Agents { config } => handle_agents(cli, &config),
+            Command::Classify { labels, text, json } => handle_classify(cli, labels, text, json),
+            Command::Step { prompt, top } => handle_step(cli, prompt, top),
+            Command::Compare { prompt } => handle_compare(cli, &prompt),
+            Command::Notes { action } => handle_notes(action),
+            Command::Template { action } => handle_template(cli, action),
+            Command::Verify { reference, json } => handle_verify(cli, &reference, json),
+            Command::Eval {
+                suite,
+                judge,
+                json,
+                output,
+            } => handle_eval(cli, &suite, judge, json, output.as_deref()),
+            Command::Fuzz {
+                prompt,
+                perturb,
+                variants,
+                json,
+            } => handle_fuzz(cli, &prompt, perturb, variants, json),
+            Command::CtxTest {
+                levels,
+                depths,
+                context,
+                json,
+            } => handle_ctx_test(cli, levels, depths, context, json),
+            Command::Replay { file, force } => handle_replay(cli, &file, force),
+            Command::Serve { unix } => handle_serve(cli, &unix),
+            Command::Daemon { idle_timeout } => handle_daemon(cli, idle_timeout),
+        };
+    }
 
     if let Some(ref repo_id) = cli.download {
         handle_download(repo_id)?;
@@ -153,33 +967,241 @@ fn main() -> Result<()> {
         } else {
             None
         };
-        oxide_rs::tui::run(cli.model.clone(), cli.download.clone(), initial_screen)?;
+        oxide_rs::tui::run(
+            cli.model.first().cloned(),
+            cli.download.clone(),
+            initial_screen,
+            cli.show_probs,
+        )?;
         return Ok(());
     }
 
-    if cli.server {
-        tracing_subscriber::registry()
-            .with(
-                tracing_subscriber::EnvFilter::try_from_default_env()
-                    .unwrap_or_else(|_| "oxide_rs=info".into()),
-            )
-            .with(tracing_subscriber::fmt::layer())
-            .init();
+    if let Some((host, port)) = cli.listen.take() {
+        cli.server = true;
+        cli.host = host;
+        cli.port = port;
+    }
 
+    if cli.server {
+        let options = GenerateOptions {
+            redaction: redaction_config(&cli)?,
+            low_mem: cli.low_mem,
+            stream_granularity: cli.stream_granularity,
+            finish_at_boundary: cli.finish_at_boundary,
+            ttft_budget_ms: cli.ttft_budget_ms,
+            context_policy: cli.context_policy,
+            loop_guard: loop_guard(&cli),
+            top_n_sigma: cli.top_n_sigma,
+            eos_bias: cli.eos_bias,
+            min_tokens: cli.min_tokens,
+            load_retries: cli.load_retries,
+            cpu_threads: cli.threads.unwrap_or(0),
+            pin_threads: !cli.no_pin,
+            force_language: cli.force_language.clone(),
+            warmup: cli.warmup.unwrap_or(WarmupPolicy::Full),
+            moderation: cli
+                .moderation
+                .as_deref()
+                .map(ModerationConfig::from_file)
+                .transpose()?,
+            ..Default::default()
+        };
         let runtime = tokio::runtime::Runtime::new()?;
-        if let Err(e) = runtime.block_on(server_run(cli.host, cli.port)) {
+        let mut state = AppState::with_options(options).with_quotas(QuotaConfig {
+            max_prompt_tokens: cli.max_prompt_tokens,
+            session_token_quota: cli.session_token_quota,
+        });
+        if let Some(token) = cli.admin_token.clone() {
+            state = state.with_admin_token(token);
+        }
+        if let Some(ttl) = cli.response_cache_ttl.filter(|&ttl| ttl > 0) {
+            state = state.with_response_cache(CacheConfig {
+                ttl: std::time::Duration::from_secs(ttl),
+                max_entries: cli.response_cache_size,
+            });
+        }
+        #[cfg(feature = "telemetry")]
+        if let Some(endpoint) = cli.otlp_endpoint.clone() {
+            state = state.with_trace_exporter(OtlpConfig {
+                endpoint,
+                service_name: cli.otlp_service_name.clone(),
+            });
+        }
+        let result = runtime.block_on(async {
+            for path in &cli.preload {
+                let path = path.to_string_lossy();
+                tracing::info!("[MODEL] Preloading {}", path);
+                state
+                    .preload_model(&path, cli.preload_warmup)
+                    .await
+                    .map_err(|e| -> Box<dyn std::error::Error> { e })?;
+            }
+            server_run(cli.host, cli.port, state).await
+        });
+        if let Err(e) = result {
             eprintln!("Server error: {}", e);
         }
         return Ok(());
     }
 
-    let model_path = cli.model.clone().ok_or_else(|| {
-        anyhow::anyhow!("No model specified. Use --model or --download to get a model.")
-    })?;
+    let Some(model_path) = resolve_model(&cli)? else {
+        return Ok(());
+    };
+    apply_sampling_preset(&mut cli, &model_path);
+
+    if let Some(template) = cli.map.clone() {
+        return handle_map(&cli, model_path, &template);
+    }
+
+    if let Some(target_tokens) = cli.long {
+        return handle_long(&cli, model_path, target_tokens);
+    }
+
+    if let Some(path) = cli.debug_activations.clone() {
+        return handle_debug_activations(&cli, model_path, &path);
+    }
+
+    if cli.shared_runtime {
+        return run_shared(&cli, &model_path);
+    }
 
     run_inference(cli, model_path)
 }
 
+/// The `--model` path, or one chosen in the interactive picker from the
+/// registry and `--model-dir` when running on a terminal. `None` means the
+/// picker was cancelled.
+fn resolve_model(cli: &Cli) -> Result<Option<PathBuf>> {
+    match cli.model.as_slice() {
+        [] => {}
+        [path] => return fetch_model_path(cli, path).map(Some),
+        _ => anyhow::bail!("Only `compare` takes more than one --model."),
+    }
+    let no_model =
+        || anyhow::anyhow!("No model specified. Use --model or --download to get a model.");
+    if theme::is_plain_output() || !io::stdin().is_terminal() {
+        return Err(no_model());
+    }
+    let models = discover_models(&cli.model_dir)?;
+    if models.is_empty() {
+        return Err(no_model());
+    }
+    pick_model(&models)
+}
+
+/// `path`, or where a `--model` URL was downloaded to.
+fn fetch_model_path(cli: &Cli, path: &Path) -> Result<PathBuf> {
+    if is_remote(path) || is_bucket(path) {
+        return fetch_remote_model(&path.to_string_lossy(), cli.model_sha256.as_deref());
+    }
+    Ok(path.to_path_buf())
+}
+
+/// Downloads a `--model` URL (http, https, s3 or gs) into the cache, or
+/// reuses the cached copy, showing progress on stderr.
+fn fetch_remote_model(url: &str, sha256: Option<&str>) -> Result<PathBuf> {
+    let show = io::stderr().is_terminal();
+    let mut shown = None;
+    let progress = |progress: DownloadProgress| {
+        let percent = progress.bytes_downloaded * 100 / progress.total_bytes.max(1);
+        if show && shown != Some(percent) {
+            shown = Some(percent);
+            eprint!(
+                "\r  Downloading {} {:>3}% ({}/{})",
+                progress.filename,
+                percent,
+                format_size(progress.bytes_downloaded),
+                format_size(progress.total_bytes)
+            );
+        }
+    };
+    let path = if is_bucket(Path::new(url)) {
+        fetch_bucket_model(url, sha256, progress)?
+    } else {
+        fetch_model(url, sha256, progress)?
+    };
+    if shown.is_some() {
+        eprintln!();
+    }
+    Ok(path)
+}
+
+#[cfg(not(feature = "object-store"))]
+fn fetch_bucket_model(
+    url: &str,
+    _sha256: Option<&str>,
+    _progress: impl FnMut(DownloadProgress),
+) -> Result<PathBuf> {
+    anyhow::bail!(
+        "{} needs object storage support; rebuild with --features object-store",
+        url
+    )
+}
+
+/// Which sampling flags were set explicitly, so presets leave them alone.
+#[derive(Clone, Copy, Debug, Default)]
+struct ExplicitSampling {
+    temperature: bool,
+    top_p: bool,
+    top_k: bool,
+    repeat_penalty: bool,
+}
+
+impl ExplicitSampling {
+    fn from_matches(matches: &ArgMatches) -> Self {
+        // Global flags may be given after a subcommand, so check every level.
+        let explicit = |id: &str| {
+            let mut level = Some(matches);
+            while let Some(m) = level {
+                if matches!(
+                    m.value_source(id),
+                    Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+                ) {
+                    return true;
+                }
+                level = m.subcommand().map(|(_, sub)| sub);
+            }
+            false
+        };
+        Self {
+            temperature: explicit("temperature"),
+            top_p: explicit("top_p"),
+            top_k: explicit("top_k"),
+            repeat_penalty: explicit("repeat_penalty"),
+        }
+    }
+}
+
+/// Applies `--preset`, or the model family's preset when none was given, to
+/// the sampling settings that were not set explicitly.
+fn apply_sampling_preset(cli: &mut Cli, model_path: &Path) {
+    if cli.preset.is_none() {
+        cli.preset = match Model::read_metadata(model_path) {
+            Ok(meta) => SamplingPreset::for_model(&meta.architecture, &meta.name),
+            // Loading the model reports the error properly.
+            Err(_) => None,
+        };
+    }
+    let Some(preset) = cli.preset else {
+        return;
+    };
+    let sampling = preset.sampling();
+    let explicit = cli.explicit_sampling;
+    if !explicit.temperature {
+        cli.temperature = sampling.temperature;
+    }
+    if !explicit.top_p {
+        cli.top_p = sampling.top_p;
+    }
+    if !explicit.top_k {
+        cli.top_k = sampling.top_k;
+    }
+    if !explicit.repeat_penalty {
+        cli.repeat_penalty = sampling.repeat_penalty;
+    }
+    tracing::info!("Sampling preset: {}", preset.as_str());
+}
+
 fn handle_download(repo_id: &str) -> Result<()> {
     println!();
     print_banner();
@@ -231,15 +1253,15 @@ fn handle_list_models() -> Result<()> {
     println!();
 
     if models.is_empty() {
-        println!("  No models downloaded yet.");
+        println!("  {}", i18n::text(Msg::NoModels));
         println!();
-        println!("  Download a model with:");
+        println!("  {}", i18n::text(Msg::DownloadHint));
         println!("    oxide-rs --download <repo-id>");
         println!();
         return Ok(());
     }
 
-    println!("  📦 Local Models");
+    println!("  📦 {}", i18n::text(Msg::LocalModels));
     print_divider();
 
     for model in &models {
@@ -252,7 +1274,7 @@ fn handle_list_models() -> Result<()> {
         println!();
     }
 
-    println!("  Run a model:");
+    println!("  {}", i18n::text(Msg::RunHint));
     println!("    oxide-rs --model <path>");
     println!();
 
@@ -312,177 +1334,1868 @@ fn handle_info(repo_id: &str) -> Result<()> {
 fn handle_remove(model_id: &str) -> Result<()> {
     if let Some(entry) = unregister_model(model_id)? {
         println!();
-        println!("  ✓ Removed model: {}", entry.id);
+        println!("  ✓ {}", i18n::format(Msg::ModelRemoved, &[&entry.id]));
         println!("    File: {}", entry.path.display());
         println!();
     } else {
         println!();
-        println!("  Model not found: {}", model_id);
+        println!("  {}", i18n::format(Msg::ModelNotFound, &[&model_id]));
         println!();
-        println!("  Use 'oxide-rs --models' to see available models.");
+        println!("  {}", i18n::text(Msg::ModelsHint));
         println!();
     }
 
     Ok(())
 }
 
-fn run_inference(cli: Cli, model_path: PathBuf) -> Result<()> {
-    let num_cpus = num_cpus::get();
-    let num_threads = cli
-        .threads
-        .unwrap_or_else(|| num_cpus.saturating_sub(1).max(1));
+/// `archs`: prints the architecture capability matrix, or the row for
+/// `name`. An unknown name is reported as loading with the LLaMA
+/// implementation, which is what the loader does with it.
+fn handle_archs(name: Option<&str>, json: bool) -> Result<()> {
+    let archs = match name {
+        Some(name) => match architecture_info(&name.to_ascii_lowercase()) {
+            Some(info) => std::slice::from_ref(info),
+            None => {
+                if json {
+                    println!("null");
+                } else {
+                    println!(
+                        "  ? {}: unknown architecture; it will be loaded as llama, which fails unless it uses LLaMA's metadata keys and tensor names",
+                        name
+                    );
+                }
+                return Ok(());
+            }
+        },
+        None => supported_architectures(),
+    };
+    if json {
+        let value = match name {
+            Some(_) => serde_json::to_value(archs[0])?,
+            None => serde_json::to_value(archs)?,
+        };
+        println!("{}", serde_json::to_string_pretty(&value)?);
+        return Ok(());
+    }
 
-    let simd_level = SimdLevel::from_str(&cli.simd);
-    let simd = init_simd(simd_level);
-    tracing::info!(
-        "SIMD: {:?} (AVX512: {}, AVX2: {}, NEON: {})",
-        simd.level,
-        simd.cpu_features.has_avx512,
-        simd.cpu_features.has_avx2,
-        simd.cpu_features.has_neon
-    );
+    for arch in archs {
+        let mark = match arch.status {
+            ArchStatus::Supported => "✓",
+            ArchStatus::Unsupported => "✗",
+        };
+        println!(
+            "  {} {:<10} {:<12} {}",
+            mark,
+            arch.name,
+            arch.status.as_str(),
+            arch.notes
+        );
+        if !arch.tested_quants.is_empty() {
+            println!("    {:<23} tested: {}", "", arch.tested_quants.join(", "));
+        }
+    }
+    if name.is_none() {
+        println!();
+        println!(
+            "  Other architectures are loaded as llama, which fails unless it uses LLaMA's metadata keys and tensor names."
+        );
+    }
+    Ok(())
+}
 
-    unsafe { std::env::set_var("RAYON_NUM_THREADS", num_threads.to_string()) };
-    tracing::info!(
-        "Using {} threads for inference (RAYON_NUM_THREADS set)",
-        num_threads
-    );
+fn handle_check(cli: Cli) -> Result<()> {
+    let model_path = match cli.model.as_slice() {
+        [] => anyhow::bail!("No model specified. Use oxide-rs check --model <path>."),
+        [path] => path.clone(),
+        _ => anyhow::bail!("Only `compare` takes more than one --model."),
+    };
+    // Only the header is needed, so a URL is read in place.
+    let report = if is_remote(&model_path) {
+        check_remote_model(&model_path.to_string_lossy())?
+    } else if is_bucket(&model_path) {
+        check_model(&fetch_remote_model(
+            &model_path.to_string_lossy(),
+            cli.model_sha256.as_deref(),
+        )?)?
+    } else {
+        check_model(&model_path)?
+    };
 
-    let tokenizer_path = cli.tokenizer.clone();
-    let (temperature, top_p, top_k, seed, batch_size) = (
-        cli.temperature,
-        cli.top_p,
-        cli.top_k,
-        cli.seed,
-        cli.batch_size,
-    );
-    let system_prompt = cli.system.clone();
+    println!();
+    print_banner();
+    println!();
+    println!("  🔎 Compatibility check: {}", model_path.display());
+    print_divider();
 
-    let load_handle = std::thread::spawn(move || {
-        Generator::new(
-            &model_path,
-            tokenizer_path.as_ref(),
-            temperature,
-            top_p,
-            top_k,
-            seed,
-            system_prompt,
-            batch_size,
-        )
-    });
+    for item in &report.items {
+        let mark = match item.status {
+            CheckStatus::Pass => "✓",
+            CheckStatus::Warn => "!",
+            CheckStatus::Fail => "✗",
+        };
+        println!("  {} {:<14} {}", mark, item.name, item.detail);
+    }
 
-    let thread_pinner = init_thread_pinner(ThreadPinnerConfig::auto(num_cpus));
-    tracing::info!(
-        "Thread pinning: {} threads on cores {:?}",
-        thread_pinner.num_threads(),
-        thread_pinner.core_ids()
+    print_divider();
+    if !report.passed() {
+        println!();
+        anyhow::bail!(
+            "{} check(s) failed; this model will not run",
+            report.failures()
+        );
+    }
+    println!(
+        "  ✓ Compatible ({} warning{})",
+        report.warnings(),
+        if report.warnings() == 1 { "" } else { "s" }
     );
+    println!();
+    Ok(())
+}
 
-    let pinned_pool = thread_pinner
-        .build_thread_pool()
-        .map_err(|e| anyhow::anyhow!("Failed to build pinned thread pool: {}", e))?;
-    pinned_pool.install(|| {
-        tracing::info!(
-            "Thread pool initialized with {} pinned threads",
-            num_threads
-        );
-    });
+fn handle_gguf(action: GgufCommand) -> Result<()> {
+    let written = match action {
+        GgufCommand::SetTemplate {
+            file,
+            template,
+            output,
+        } => {
+            let source = std::fs::read_to_string(&template)
+                .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", template.display(), e))?;
+            set_chat_template(&file, &source, output.as_deref())?
+        }
+        GgufCommand::SetKey {
+            file,
+            key,
+            value,
+            output,
+        } => set_key(&file, &key, &value, output.as_deref())?,
+    };
 
-    print_banner();
+    println!();
+    println!("  ✓ Updated {}", written.display());
+    println!();
+    Ok(())
+}
 
-    let loader = ModelLoader::new();
+fn handle_code(
+    mut cli: Cli,
+    repo: &Path,
+    top_k: usize,
+    question: Option<String>,
+    json: bool,
+) -> Result<()> {
+    let index = CodeIndex::build(repo)?;
+    let Some(model_path) = resolve_model(&cli)? else {
+        return Ok(());
+    };
+    apply_sampling_preset(&mut cli, &model_path);
+    let mut generator = load_generator(&cli, model_path, json)?;
+    if !json {
+        print_divider();
+        println!(
+            "  Indexed {} files ({} excerpts) in {}\n",
+            index.file_count(),
+            index.chunk_count(),
+            repo.display()
+        );
+    }
 
-    let mut generator = match load_handle.join() {
-        Ok(Ok(g)) => g,
-        Ok(Err(e)) => {
-            loader.finish_with_error(&format!("Failed: {}", e));
-            return Err(e);
+    if let Some(question) = question {
+        return answer_code_question(&cli, &index, top_k, &mut generator, &question, json);
+    }
+
+    let mut prompt_display = PromptDisplay::new();
+    loop {
+        prompt_display.show_input_prompt();
+        io::stdout().flush()?;
+        let mut line = String::new();
+        if io::stdin().read_line(&mut line)? == 0 {
+            println!();
+            break;
         }
-        Err(_) => {
-            loader.finish_with_error("Model loading thread panicked");
-            return Err(anyhow::anyhow!("Model loading thread panicked"));
+        let question = line.trim();
+        if question == "/exit" || question == "/quit" {
+            break;
         }
-    };
+        if !question.is_empty() {
+            answer_code_question(&cli, &index, top_k, &mut generator, question, json)?;
+            if !json {
+                print_divider();
+            }
+        }
+    }
+    Ok(())
+}
 
-    if let Err(e) = pinned_pool.install(|| generator.warmup(1)) {
-        tracing::warn!("Model warmup failed: {}", e);
+/// Retrieves excerpts for `question`, drops the least relevant ones until
+/// the prompt fits the context window, and streams the answer followed by
+/// the excerpts it was given, marking those it cited. With `json`, prints
+/// one JSON line with the answer and its citations instead. Each question
+/// starts from an empty history.
+fn answer_code_question(
+    cli: &Cli,
+    index: &CodeIndex,
+    top_k: usize,
+    generator: &mut Generator,
+    question: &str,
+    json: bool,
+) -> Result<()> {
+    generator.clear_history();
+    let context = generator.context_limit();
+    let reply_budget = if cli.max_tokens == 0 {
+        context / 4
+    } else {
+        cli.max_tokens
+    };
+    let mut chunks = index.search(question, top_k);
+    let prompt = loop {
+        let prompt = code_prompt(question, &chunks);
+        if chunks.is_empty() || generator.count_tokens(&prompt)? + reply_budget < context {
+            break prompt;
+        }
+        chunks.pop();
+    };
+    if json {
+        let answer = generator.generate(
+            &prompt,
+            cli.max_tokens,
+            cli.repeat_penalty,
+            cli.repeat_last_n,
+            |_| {},
+        )?;
+        let value = serde_json::json!({
+            "question": question,
+            "citations": citations(&answer, &chunks),
+            "answer": answer.trim(),
+        });
+        println!("{}", serde_json::to_string(&value)?);
+        return Ok(());
+    }
+    if chunks.is_empty() {
+        println!("  No matching files; answering without excerpts.\n");
     }
 
-    let metadata = generator.metadata().clone();
-    loader.finish(&metadata.name);
+    let mut stream = StreamOutput::with_granularity(cli.stream_granularity);
+    generator.generate_streaming(
+        &prompt,
+        cli.max_tokens,
+        cli.repeat_penalty,
+        cli.repeat_last_n,
+        |event| match event {
+            StreamEvent::PrefillStatus(count) => {
+                stream.set_prompt_tokens(count);
+                stream.start_thinking();
+            }
+            StreamEvent::Token(t) => {
+                stream.set_context(0, context);
+                stream.print_token(&t);
+            }
+            StreamEvent::ContextTruncated { dropped_tokens, .. } => {
+                stream.print_truncation_warning(dropped_tokens);
+            }
+            StreamEvent::LoopDetected { period } => stream.set_loop_detected(period),
+            StreamEvent::Done => stream.finish(),
+            StreamEvent::Error(message) => stream.print_error(&message),
+        },
+    )?;
+
+    if !chunks.is_empty() {
+        let answer = generator
+            .history()
+            .last()
+            .map(|m| m.content.as_str())
+            .unwrap_or_default();
+        // Cited excerpts are starred; the rest were given but not used.
+        let sources: Vec<String> = citations(answer, &chunks)
+            .iter()
+            .map(|c| {
+                if c.cited {
+                    format!("{}*", c.source())
+                } else {
+                    c.source()
+                }
+            })
+            .collect();
+        println!("  Sources: {}\n", sources.join(", "));
+    }
+    Ok(())
+}
 
-    print_model_info(
-        &metadata.name,
-        &format_size(metadata.file_size),
-        metadata.quantization.as_deref().unwrap_or("Unknown"),
-        metadata.n_layer,
-        metadata.n_embd,
-        metadata.context_length,
-    );
+fn handle_commit(
+    mut cli: Cli,
+    repo: &Path,
+    apply: bool,
+    amend: bool,
+    language: Option<&str>,
+) -> Result<()> {
+    let diff = staged_diff(repo, amend)?;
+    if diff.is_empty() {
+        anyhow::bail!("No staged changes. Stage files with `git add` first.");
+    }
+    let Some(model_path) = resolve_model(&cli)? else {
+        return Ok(());
+    };
+    apply_sampling_preset(&mut cli, &model_path);
+    let mut generator = load_generator(&cli, model_path, false)?;
+    print_divider();
 
-    if cli.once {
-        let prompt = cli
-            .prompt
-            .unwrap_or_else(|| "Write a hello world program in Rust".to_string());
+    // Commit messages are short; `auto` would reserve most of the context.
+    let max_tokens = if cli.max_tokens == 0 {
+        256
+    } else {
+        cli.max_tokens
+    };
+    let context = generator.context_limit();
+    // Roughly three characters per token; halve until the prompt fits.
+    let mut max_chars = context.saturating_sub(max_tokens) * 3;
+    let prompt = loop {
+        let prompt = commit_prompt(&diff, language, max_chars);
+        if max_chars < 256 || generator.count_tokens(&prompt)? + max_tokens < context {
+            break prompt;
+        }
+        max_chars /= 2;
+    };
 
-        let mut prompt_display = PromptDisplay::new();
-        prompt_display.show_user_input(&prompt);
+    let spinner = Spinner::new("Writing commit message...");
+    let raw = generator.generate(
+        &prompt,
+        max_tokens,
+        cli.repeat_penalty,
+        cli.repeat_last_n,
+        |_| {},
+    );
+    spinner.finish_with_message("Commit message");
+    let message = clean_message(&raw?);
+    if message.is_empty() {
+        anyhow::bail!("The model returned an empty commit message");
+    }
+    println!("\n{}\n", message);
 
-        let mut gen_output = generator;
-        let mut stream = StreamOutput::new();
-        let mut thinking_spinner: Option<ThinkingSpinner> = None;
-        let context_limit = gen_output.context_limit();
-        let context_used = gen_output.context_used();
-        let mut prompt_token_count = 0usize;
+    if apply {
+        print!("{}", apply_commit(repo, &message, amend)?);
+    }
+    Ok(())
+}
 
-        pinned_pool.install(|| {
-            gen_output.generate_streaming(
-                &prompt,
-                cli.max_tokens,
-                cli.repeat_penalty,
-                cli.repeat_last_n,
-                |event| match event {
-                    StreamEvent::PrefillStatus(count) => {
-                        prompt_token_count = count;
-                        stream.set_prompt_tokens(count);
-                        if thinking_spinner.is_none() {
-                            thinking_spinner = Some(ThinkingSpinner::new());
-                        }
-                    }
-                    StreamEvent::Token(t) => {
-                        if let Some(spinner) = thinking_spinner.take() {
-                            spinner.stop();
-                        }
-                        stream.set_context(context_used, context_limit);
-                        stream.print_token(&t);
-                    }
-                    StreamEvent::Done => {
-                        stream.finish();
-                    }
-                },
-            )
-        })?;
+fn handle_agents(mut cli: Cli, config_path: &Path) -> Result<()> {
+    let config = AgentsConfig::load(config_path)?;
+    let models = config.models();
+    let default_model = if models.contains(&None) {
+        let Some(path) = resolve_model(&cli)? else {
+            return Ok(());
+        };
+        Some(path)
+    } else {
+        None
+    };
+    // Every persona shares the sampling settings, so the preset follows the
+    // first model.
+    if let Some(path) = models.first().cloned().flatten().or(default_model.clone()) {
+        apply_sampling_preset(&mut cli, &path);
+    }
 
+    let mut generators = Vec::with_capacity(models.len());
+    for model in models {
+        let path = model.or_else(|| default_model.clone()).unwrap_or_default();
+        generators.push(load_generator(&cli, path, false)?);
+    }
+    if generators.is_empty() {
         return Ok(());
     }
 
+    let options = AgentsOptions {
+        temperature: cli.temperature,
+        top_p: cli.top_p,
+        top_k: cli.top_k,
+        seed: cli.seed,
+        max_tokens: cli.max_tokens,
+        repeat_penalty: cli.repeat_penalty,
+        repeat_last_n: cli.repeat_last_n,
+    };
+
     print_divider();
-    print_welcome();
-    print_divider();
+    println!("  Topic: {}\n", config.topic);
+    let mut stream: Option<StreamOutput> = None;
+    run_agents(&config, &mut generators, &options, |event| match event {
+        AgentEvent::TurnStarted { turn, speaker } => {
+            let mut output = StreamOutput::with_granularity(cli.stream_granularity);
+            output.print_line(&format!(
+                "  [{}/{}] {}:",
+                turn + 1,
+                config.max_turns,
+                speaker
+            ));
+            output.start_thinking();
+            stream = Some(output);
+        }
+        AgentEvent::Token(t) => {
+            if let Some(output) = stream.as_mut() {
+                output.print_token(&t);
+            }
+        }
+        AgentEvent::TurnFinished => {
+            if let Some(mut output) = stream.take() {
+                output.finish();
+            }
+            println!();
+        }
+        AgentEvent::Moderated { verdict, .. } => {
+            println!("  Moderator: {}\n", verdict);
+        }
+        AgentEvent::Stopped(reason) => {
+            let why = match reason {
+                StopReason::MaxTurns => "turn limit reached",
+                StopReason::StopPhrase => "stop phrase found",
+                StopReason::Moderator => "stopped by the moderator",
+            };
+            println!("  Conversation ended: {}.", why);
+        }
+    })?;
 
-    interactive_mode(generator, cli, pinned_pool)
+    Ok(())
 }
 
-fn interactive_mode(generator: Generator, cli: Cli, pinned_pool: rayon::ThreadPool) -> Result<()> {
-    let mut generator = generator;
-    let mut prompt_display = PromptDisplay::new();
+/// Prints one line per input: the label and its probability, or with `json`
+/// an object with every label's probability. Blank stdin lines stay blank.
+fn handle_classify(cli: Cli, labels: Vec<String>, text: Option<String>, json: bool) -> Result<()> {
+    let labels: Vec<String> = labels
+        .iter()
+        .map(|label| label.trim().to_string())
+        .filter(|label| !label.is_empty())
+        .collect();
+    if labels.len() < 2 {
+        anyhow::bail!("--labels needs at least two labels");
+    }
+    let Some(model_path) = resolve_model(&cli)? else {
+        return Ok(());
+    };
+    let mut generator = load_generator(&cli, model_path, true)?;
 
-    loop {
-        prompt_display.show_input_prompt();
-        io::stdout().flush()?;
+    let mut classify = |text: &str| -> Result<String> {
+        if text.trim().is_empty() {
+            return Ok(String::new());
+        }
+        let prompt = classify_prompt(text, &labels);
+        let choice = generator.choose(&prompt, &labels)?;
+        if !json {
+            return Ok(format!("{}\t{:.3}", choice.label, choice.probability()));
+        }
+        let probabilities: serde_json::Map<String, serde_json::Value> = choice
+            .probabilities
+            .iter()
+            .map(|(label, p)| (label.clone(), (*p as f64).into()))
+            .collect();
+        Ok(serde_json::json!({
+            "label": choice.label,
+            "probabilities": probabilities,
+        })
+        .to_string())
+    };
+
+    let mut out = io::stdout().lock();
+    match text {
+        Some(text) => writeln!(out, "{}", classify(&text)?)?,
+        None => {
+            for (number, line) in io::stdin().lock().lines().enumerate() {
+                let line = line.context("Failed to read stdin")?;
+                let result = classify(&line).with_context(|| format!("Line {}", number + 1))?;
+                writeln!(out, "{}", result)?;
+                out.flush()?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn handle_step(mut cli: Cli, prompt: Option<String>, top: usize) -> Result<()> {
+    let Some(model_path) = resolve_model(&cli)? else {
+        return Ok(());
+    };
+    apply_sampling_preset(&mut cli, &model_path);
+    let mut generator = load_generator(&cli, model_path.clone(), false)?;
+    let Some(prompt) = prompt else {
+        print_divider();
+        print_welcome();
+        print_divider();
+        return interactive_mode(generator, cli, model_path, Some(top));
+    };
+    let reply = step_reply(&mut generator, &prompt, top, cli.max_tokens)?;
+    print_divider();
+    println!("{}", reply);
+    Ok(())
+}
+
+/// Loads each `--model` in turn, replies to `prompt` and drops the model
+/// before loading the next, so only one is in memory at a time. Every model
+/// gets the same seed and sampling settings; a preset follows the first.
+fn handle_compare(mut cli: Cli, prompt: &str) -> Result<()> {
+    use std::time::{Duration, Instant};
+
+    if cli.model.len() < 2 {
+        anyhow::bail!("compare needs at least two models: -m a.gguf -m b.gguf");
+    }
+    let paths = cli
+        .model
+        .iter()
+        .map(|path| fetch_model_path(&cli, path))
+        .collect::<Result<Vec<_>>>()?;
+    apply_sampling_preset(&mut cli, &paths[0]);
+
+    let mut columns = Vec::with_capacity(paths.len());
+    for path in paths {
+        let title = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| path.display().to_string());
+        let started = Instant::now();
+        let mut generator = load_generator(&cli, path, true)?;
+        let load_time = started.elapsed();
+
+        let spinner = Spinner::new(&format!("Running {}...", title));
+
+        let started = Instant::now();
+        let mut first_token = None;
+        let mut prompt_tokens = 0;
+        let mut reply = String::new();
+        let result = generator.generate_streaming(
+            prompt,
+            cli.max_tokens,
+            cli.repeat_penalty,
+            cli.repeat_last_n,
+            |event| match event {
+                StreamEvent::PrefillStatus(count) => prompt_tokens = count,
+                StreamEvent::Token(t) => {
+                    first_token.get_or_insert_with(|| started.elapsed());
+                    reply.push_str(&t);
+                }
+                _ => {}
+            },
+        );
+        spinner.finish_with_message(&title);
+        result?;
+
+        let decode: Duration = generator.token_latencies().iter().sum();
+        let steps = generator.token_latencies().len();
+        let mut stats = vec![
+            format!("Load: {:.1}s", load_time.as_secs_f64()),
+            format!("Prompt: {} tokens", prompt_tokens),
+            format!("Reply: {} tokens", steps + first_token.is_some() as usize),
+        ];
+        if let Some(first_token) = first_token {
+            stats.push(format!("First token: {:.2}s", first_token.as_secs_f64()));
+        }
+        if steps > 0 {
+            stats.push(format!(
+                "Speed: {:.1} tok/s",
+                steps as f64 / decode.as_secs_f64().max(f64::EPSILON)
+            ));
+        }
+        if generator.loop_detected() {
+            stats.push("Stopped: token loop".to_string());
+        }
+        columns.push(CompareColumn {
+            title,
+            text: reply,
+            stats,
+        });
+    }
+
+    print_divider();
+    println!("  > {}\n", prompt.replace('\n', "\n    "));
+    print_side_by_side(&columns);
+    Ok(())
+}
+
+fn handle_notes(action: NotesCommand) -> Result<()> {
+    let NotesCommand::Search { query } = action;
+    let store = NoteStore::load(&NoteStore::default_path()?)?;
+    let notes = store.search(&query.join(" "));
+    if notes.is_empty() {
+        println!("No matching notes.");
+        return Ok(());
+    }
+    for note in notes {
+        println!(
+            "#{}  {}  {}",
+            note.id,
+            note.created_at.format("%Y-%m-%d %H:%M"),
+            note.model
+        );
+        if let Some(text) = &note.note {
+            println!("  Note: {}", text);
+        }
+        println!("  > {}", note.prompt.replace('\n', "\n    "));
+        println!("  {}\n", note.response.replace('\n', "\n  "));
+    }
+    Ok(())
+}
+
+fn handle_template(cli: Cli, action: TemplateCommand) -> Result<()> {
+    let TemplateCommand::Render {
+        messages,
+        no_generation_prompt,
+    } = action;
+    let messages = load_messages(&messages)?;
+    let Some(model_path) = resolve_model(&cli)? else {
+        return Ok(());
+    };
+    let preview = render_template(
+        &model_path,
+        cli.tokenizer.as_ref(),
+        &messages,
+        !no_generation_prompt,
+    )?;
+    // Only the prompt goes to stdout, so it can be redirected verbatim.
+    print!("{}", preview.text);
+    io::stdout().flush()?;
+    eprintln!("\n{} tokens", preview.tokens);
+    Ok(())
+}
+
+fn handle_convert(input: &Path, output: &Path, preset: QuantPreset) -> Result<()> {
+    println!();
+    print_banner();
+    println!();
+
+    // A path that does not exist but looks like `owner/repo` is downloaded.
+    let repo_id = input
+        .to_str()
+        .filter(|s| !input.exists() && s.matches('/').count() == 1);
+    let model_dir = match repo_id {
+        Some(repo_id) => {
+            println!("  Downloading {} (safetensors)", repo_id);
+            let mut progress_bar = DownloadProgressBar::new(repo_id, 0);
+            let dir = download_safetensors(repo_id, |progress| progress_bar.update(&progress))?;
+            progress_bar.finish(dir.to_str().unwrap_or(""));
+            dir
+        }
+        None => input.to_path_buf(),
+    };
+
+    println!("  Converting {} to {}", model_dir.display(), preset);
+    print_divider();
+
+    let output_size = convert_safetensors(&model_dir, output, preset, print_quantize_progress)?;
+    if let Some(repo_id) = repo_id {
+        let filename = output
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("model.gguf");
+        register_model(repo_id, filename, output.to_path_buf(), output_size)?;
+    }
+
+    println!();
+    print_divider();
+    println!(
+        "  ✓ Wrote {} ({})",
+        output.display(),
+        format_size(output_size)
+    );
+    println!();
+    println!("  Run with:    oxide-rs --model {}", output.display());
+    println!();
+    Ok(())
+}
+
+fn print_quantize_progress(p: QuantizeProgress) {
+    print!(
+        "\r\x1b[2K  [{}/{}] {} {} -> {}",
+        p.index + 1,
+        p.total,
+        p.name,
+        p.from,
+        p.to
+    );
+    io::stdout().flush().ok();
+}
+
+fn handle_quantize(input: &Path, output: &Path, preset: QuantPreset) -> Result<()> {
+    let input_size = std::fs::metadata(input)?.len();
+
+    println!();
+    print_banner();
+    println!();
+    println!(
+        "  Quantizing {} ({}) to {}",
+        input.display(),
+        format_size(input_size),
+        preset
+    );
+    print_divider();
+
+    let output_size = quantize_gguf(input, output, preset, print_quantize_progress)?;
+
+    println!();
+    print_divider();
+    println!(
+        "  ✓ Wrote {} ({})",
+        output.display(),
+        format_size(output_size)
+    );
+    println!();
+    Ok(())
+}
+
+fn handle_summarize(
+    mut cli: Cli,
+    file: PathBuf,
+    chunk_tokens: Option<usize>,
+    focus: Option<String>,
+) -> Result<()> {
+    let Some(model_path) = resolve_model(&cli)? else {
+        return Ok(());
+    };
+    apply_sampling_preset(&mut cli, &model_path);
+    let text = read_document_text(&file)?;
+
+    let mut generator = load_generator(&cli, model_path, false)?;
+    let options = SummarizeOptions {
+        chunk_tokens: chunk_tokens.unwrap_or(0),
+        max_tokens: cli.max_tokens,
+        repeat_penalty: cli.repeat_penalty,
+        repeat_last_n: cli.repeat_last_n,
+        focus,
+    };
+
+    print_divider();
+    let mut stream = StreamOutput::with_granularity(cli.stream_granularity);
+    summarize(&mut generator, &text, &options, |event| match event {
+        SummarizeEvent::Chunked { chunks } => {
+            stream.print_line(&format!(
+                "  Split {} into {} chunks",
+                file.display(),
+                chunks
+            ));
+        }
+        SummarizeEvent::StepStarted {
+            round,
+            index,
+            total,
+        } => {
+            if round == 0 {
+                stream.print_line(&format!("  Summarizing chunk {}/{}", index + 1, total));
+            } else {
+                stream.print_line(&format!(
+                    "  Combining summaries (round {}, part {}/{})",
+                    round,
+                    index + 1,
+                    total
+                ));
+            }
+            if total == 1 {
+                // Only the final step is streamed.
+                stream.print_line("");
+            }
+        }
+        SummarizeEvent::Token(t) => stream.print_token(&t),
+    })?;
+    stream.finish();
+
+    Ok(())
+}
+
+fn handle_long(cli: &Cli, model_path: PathBuf, target_tokens: usize) -> Result<()> {
+    let prompt = cli.prompt.clone().unwrap_or_default();
+    let mut generator = load_generator(cli, model_path, false)?;
+    let options = LongFormOptions {
+        target_tokens,
+        repeat_penalty: cli.repeat_penalty,
+        repeat_last_n: cli.repeat_last_n,
+    };
+
+    print_divider();
+    let mut stream = StreamOutput::with_granularity(cli.stream_granularity);
+    let mut tee = open_tee(cli)?;
+    write_long(&mut generator, &prompt, &options, |event| match event {
+        LongFormEvent::Outline(titles) => {
+            stream.print_line(&format!("  Outline: {} sections", titles.len()));
+        }
+        LongFormEvent::SectionStarted {
+            index,
+            total,
+            title,
+        } => {
+            stream.print_line(&format!(
+                "  Writing section {}/{}: {}",
+                index + 1,
+                total,
+                title
+            ));
+            if index == 0 {
+                stream.print_line("");
+            }
+        }
+        LongFormEvent::Token(t) => {
+            tee_write(&mut tee, &t);
+            stream.print_token(&t);
+        }
+    })?;
+    stream.finish();
+    finish_tee(&mut tee);
+
+    Ok(())
+}
+
+fn loop_guard(cli: &Cli) -> LoopGuard {
+    LoopGuard {
+        window: cli.loop_window,
+        ..LoopGuard::new(cli.loop_guard)
+    }
+}
+
+/// `--redact-rules` replaces the built-in patterns enabled by `--redact`.
+fn redaction_config(cli: &Cli) -> Result<Option<RedactionConfig>> {
+    match cli.redact_rules {
+        Some(ref path) => Ok(Some(RedactionConfig::from_file(path)?)),
+        None => Ok(cli.redact.then(RedactionConfig::default)),
+    }
+}
+
+/// `--threads`, or one less than the number of CPUs.
+fn inference_threads(cli: &Cli) -> usize {
+    cli.threads
+        .unwrap_or_else(|| num_cpus::get().saturating_sub(1).max(1))
+}
+
+/// Builds the thread pool candle, the sampler and the tokenizer share, with
+/// `--threads` threads pinned to cores unless `--no-pin`. Returns its size.
+fn init_thread_pool(cli: &Cli) -> usize {
+    let config = ThreadPinnerConfig {
+        enabled: !cli.no_pin,
+        ..ThreadPinnerConfig::new(inference_threads(cli), 0)
+    };
+    let thread_pinner = init_thread_pinner(config);
+    if !cli.no_pin {
+        tracing::info!(
+            "Thread pinning: {} threads on cores {:?}",
+            thread_pinner.num_threads(),
+            thread_pinner.core_ids()
+        );
+    }
+    thread_pinner.install_shared_pool()
+}
+
+/// Sets up the pinned thread pool, then loads the model on it while the
+/// banner is printed, applies the generation flags, warms it up and prints
+/// the model summary. `quiet` skips the banner and summary, for modes whose
+/// stdout carries only results. `--verbose` prints where the load time went
+/// to stderr.
+fn load_generator(cli: &Cli, model_path: PathBuf, quiet: bool) -> Result<Generator> {
+    let simd_level = SimdLevel::from_str(&cli.simd);
+    let simd = init_simd(simd_level);
+    tracing::info!(
+        "SIMD: {:?} (AVX512: {}, AVX2: {}, NEON: {})",
+        simd.level,
+        simd.cpu_features.has_avx512,
+        simd.cpu_features.has_avx2,
+        simd.cpu_features.has_neon
+    );
+
+    // Before the loader starts, so loading runs on the shared pool too.
+    let num_threads = init_thread_pool(cli);
+    tracing::info!("Using {} threads for inference", num_threads);
+
+    let tokenizer_path = cli.tokenizer.clone();
+    let (temperature, top_p, top_k, seed, batch_size) = (
+        cli.temperature,
+        cli.top_p,
+        cli.top_k,
+        cli.seed,
+        cli.batch_size,
+    );
+    let system_prompt = cli.system.clone();
+    let examples = cli
+        .examples
+        .as_deref()
+        .map(load_examples)
+        .transpose()?
+        .unwrap_or_default();
+    let logit_bias = cli
+        .logit_bias
+        .iter()
+        .map(|spec| LogitBias::parse(spec))
+        .collect::<Result<HashMap<_, _>>>()?;
+    let top_n_sigma = cli.top_n_sigma.map(TopNSigma::new).transpose()?;
+    let retry = LoadRetry::new(cli.load_retries);
+
+    let load_handle = std::thread::spawn(move || {
+        load_with_retry(&model_path, &retry, || {
+            Generator::new(
+                &model_path,
+                tokenizer_path.as_ref(),
+                temperature,
+                top_p,
+                top_k,
+                seed,
+                system_prompt.clone(),
+                batch_size,
+            )
+        })
+    });
+
+    if !quiet {
+        print_banner();
+    }
+
+    let loader = (!quiet).then(ModelLoader::new);
+
+    let mut generator = match load_handle.join() {
+        Ok(Ok(g)) => g,
+        Ok(Err(e)) => {
+            if let Some(loader) = loader {
+                loader.finish_with_error(&format!("Failed: {:#}", e));
+            }
+            return Err(
+                match e.downcast_ref::<LoadError>().and_then(|l| l.kind.hint()) {
+                    Some(hint) => e.context(hint),
+                    None => e,
+                },
+            );
+        }
+        Err(_) => {
+            if let Some(loader) = loader {
+                loader.finish_with_error("Model loading thread panicked");
+            }
+            return Err(anyhow::anyhow!("Model loading thread panicked"));
+        }
+    };
+
+    if !examples.is_empty() {
+        generator.set_examples(examples)?;
+    }
+    generator.set_forced_language(cli.force_language.clone());
+    if !logit_bias.is_empty() {
+        generator.add_logits_transform(Box::new(LogitBias::new(logit_bias)));
+    }
+    if let Some(n) = top_n_sigma {
+        generator.add_logits_transform(Box::new(n));
+    }
+    let eos = EosControl::new(cli.eos_bias, cli.min_tokens);
+    if eos.is_active() {
+        generator.add_logits_transform(Box::new(eos));
+    }
+    generator.set_redaction(redaction_config(cli)?.as_ref())?;
+    generator.set_low_mem(cli.low_mem);
+    generator.set_confidence(cli.confidence);
+    if cli.show_probs {
+        let tap = ProbabilityTap::new();
+        generator.add_hooks(Box::new(tap.clone()));
+        show_probabilities(tap);
+    }
+    generator.set_finish_at_boundary(cli.finish_at_boundary);
+    generator.set_ttft_budget(cli.ttft_budget_ms.map(std::time::Duration::from_millis));
+    generator.set_loop_guard(loop_guard(cli));
+    generator.set_context_policy(cli.context_policy);
+
+    // One-shot runs want their answer as soon as possible.
+    let default = if cli.once {
+        WarmupPolicy::None
+    } else {
+        WarmupPolicy::Minimal
+    };
+    if let Err(e) = generator.warmup_with(cli.warmup.unwrap_or(default)) {
+        tracing::warn!("Model warmup failed: {}", e);
+    }
+    if cli.verbose {
+        eprintln!("  Load time");
+        for line in generator.load_report().to_string().lines() {
+            eprintln!("    {}", line);
+        }
+    }
+
+    if let Some(loader) = loader {
+        let metadata = generator.metadata().clone();
+        loader.finish(&metadata.name);
+
+        print_model_info(
+            &metadata.name,
+            &format_size(metadata.file_size),
+            &metadata.quant_summary(),
+            metadata.n_layer,
+            metadata.n_embd,
+            metadata.context_length,
+        );
+    }
+
+    if let Some(path) = &cli.document {
+        read_document(cli, &mut generator, path, quiet)?;
+    }
+
+    Ok(generator)
+}
+
+/// `--document`: pins the file ahead of the conversation, reporting the
+/// parts it was read in unless `quiet`.
+fn read_document(cli: &Cli, generator: &mut Generator, path: &Path, quiet: bool) -> Result<()> {
+    let text = read_document_text(path)?;
+    let options = DocumentOptions {
+        // With --once the question is known before reading.
+        question: cli.prompt.clone().filter(|_| cli.once),
+        repeat_penalty: cli.repeat_penalty,
+        repeat_last_n: cli.repeat_last_n,
+        ..Default::default()
+    };
+    let digest = generator.read_document(&text, &options, |event| {
+        if quiet {
+            return;
+        }
+        match event {
+            DocumentEvent::Segmented { segments } => eprintln!(
+                "  {} is longer than the context; reading it in {} parts",
+                path.display(),
+                segments
+            ),
+            DocumentEvent::SegmentStarted { index, total } => {
+                eprintln!("  Taking notes on part {}/{}", index + 1, total)
+            }
+            DocumentEvent::Condensed { first, last } => {
+                eprintln!("  Condensing notes on parts {}-{}", first, last)
+            }
+        }
+    })?;
+    if !quiet {
+        if digest.is_verbatim() {
+            eprintln!("  Pinned {} ({} tokens)", path.display(), digest.tokens);
+        } else {
+            eprintln!(
+                "  Pinned notes on {} ({} tokens in {} sections)",
+                path.display(),
+                digest.tokens,
+                digest.sections.len()
+            );
+        }
+    }
+    Ok(())
+}
+
+fn run_inference(cli: Cli, model_path: PathBuf) -> Result<()> {
+    let generator = load_generator(&cli, model_path.clone(), false)?;
+
+    if cli.once {
+        let prompt = cli
+            .prompt
+            .clone()
+            .unwrap_or_else(|| "Write a hello world program in Rust".to_string());
+        let Some(prompt) = run_pre_prompt_hook(&cli, &prompt) else {
+            return Ok(());
+        };
+
+        let mut prompt_display = PromptDisplay::new();
+        prompt_display.show_user_input(&prompt);
+
+        let mut gen_output = generator;
+        let mut stream = StreamOutput::with_granularity(cli.stream_granularity);
+        let mut tee = open_tee(&cli)?;
+        let mut timing = open_timing_dump(&cli)?;
+        let mut recorder = open_recorder(&cli, &model_path, &gen_output)?;
+        let mut collector = open_collector(&cli)?;
+        let mut reply = String::new();
+        let post_hook = cli
+            .post_response_cmd
+            .as_deref()
+            .map(ShellHook::post_response);
+        if post_hook.is_some() {
+            stream.hold();
+        }
+        let context_limit = gen_output.context_limit();
+        let context_used = gen_output.context_used();
+        let mut prompt_token_count = 0usize;
+
+        gen_output.generate_streaming(
+            &prompt,
+            cli.max_tokens,
+            cli.repeat_penalty,
+            cli.repeat_last_n,
+            |event| match event {
+                StreamEvent::PrefillStatus(count) => {
+                    prompt_token_count = count;
+                    stream.set_prompt_tokens(count);
+                    stream.start_thinking();
+                }
+                StreamEvent::Token(t) => {
+                    tee_write(&mut tee, &t);
+                    if recorder.is_some() {
+                        reply.push_str(&t);
+                    }
+                    stream.set_context(context_used, context_limit);
+                    stream.print_token(&t);
+                }
+                StreamEvent::ContextTruncated { dropped_tokens, .. } => {
+                    stream.print_truncation_warning(dropped_tokens);
+                }
+                StreamEvent::LoopDetected { period } => stream.set_loop_detected(period),
+                StreamEvent::Done => {}
+                StreamEvent::Error(message) => stream.print_error(&message),
+            },
+        )?;
+        finish_tee(&mut tee);
+        record_turn(&mut recorder, &gen_output, prompt, reply, false);
+        stream.set_confidence(gen_output.confidence());
+        let kept = if post_hook.is_some() {
+            finish_held_reply(&mut gen_output, &mut stream, post_hook.as_ref(), None)?
+        } else {
+            stream.finish();
+            true
+        };
+        if kept {
+            collect_turn(&mut collector, &cli, &model_path, &gen_output);
+        }
+        dump_token_timing(&mut timing, &gen_output);
+
+        return Ok(());
+    }
+
+    print_divider();
+    print_welcome();
+    print_divider();
+
+    interactive_mode(generator, cli, model_path, None)
+}
+
+/// `--debug-activations`: one forward pass over the prompt, with the
+/// statistics written to `path` and the first non-finite layer named.
+fn handle_debug_activations(cli: &Cli, model_path: PathBuf, path: &Path) -> Result<()> {
+    let prompt = cli.prompt.clone().unwrap_or_default();
+    let mut generator = load_generator(cli, model_path, true)?;
+    let report = generator.debug_activations(&prompt)?;
+    std::fs::write(path, serde_json::to_string_pretty(&report)?)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+
+    if report.layers.is_empty() {
+        eprintln!(
+            "Per-layer statistics are not available for {}; only the logits were recorded.",
+            report.architecture
+        );
+    }
+    match report.first_non_finite() {
+        Some(stats) => eprintln!(
+            "{} holds {} NaN/Inf value(s); the layers before it are finite.",
+            stats.name, stats.non_finite
+        ),
+        None => eprintln!("All recorded activations are finite."),
+    }
+    eprintln!("Wrote activation statistics to {}", path.display());
+    Ok(())
+}
+
+/// `verify`: runs every reference case and reports where the output
+/// diverges. Fails when any case does.
+fn handle_verify(cli: Cli, reference: &Path, json: bool) -> Result<()> {
+    let cases = load_reference(reference)?;
+    if cases.is_empty() {
+        anyhow::bail!("{} has no cases", reference.display());
+    }
+    let Some(model_path) = resolve_model(&cli)? else {
+        return Ok(());
+    };
+    let mut generator = load_generator(&cli, model_path, true)?;
+
+    let mut failed = 0usize;
+    for (i, case) in cases.iter().enumerate() {
+        let report = generator.verify(i + 1, case)?;
+        if !report.passed() {
+            failed += 1;
+        }
+        if json {
+            println!("{}", serde_json::to_string(&report)?);
+            continue;
+        }
+        let piece = |id: Option<u32>, piece: &Option<String>| match id {
+            Some(id) => format!("{} {:?}", id, piece.as_deref().unwrap_or("")),
+            None => "end of output".to_string(),
+        };
+        let mark = if report.passed() { "✓" } else { "✗" };
+        println!(
+            "  {} case {}: {}/{} tokens match",
+            mark, report.case, report.matched, report.expected_len
+        );
+        if let Some(d) = &report.prompt_divergence {
+            println!(
+                "      prompt tokens differ at {}: expected {}, got {}",
+                d.index,
+                piece(d.expected, &d.expected_piece),
+                piece(d.actual, &d.actual_piece)
+            );
+        }
+        if let Some(d) = &report.divergence {
+            println!(
+                "      output diverges at token {}: expected {}, got {}",
+                d.index,
+                piece(d.expected, &d.expected_piece),
+                piece(d.actual, &d.actual_piece)
+            );
+        }
+    }
+
+    if failed > 0 {
+        anyhow::bail!("{} of {} case(s) diverged", failed, cases.len());
+    }
+    if !json {
+        println!("  ✓ All {} case(s) match", cases.len());
+    }
+    Ok(())
+}
+
+/// `eval`: runs every case of the suite, shows progress on stderr and writes
+/// the report. Fails when a scored case failed, for use in scripts.
+fn handle_eval(
+    mut cli: Cli,
+    suite_path: &Path,
+    judge: Option<PathBuf>,
+    json: bool,
+    output: Option<&Path>,
+) -> Result<()> {
+    let mut suite = EvalSuite::load(suite_path)?;
+    if let Some(model) = judge {
+        suite.judge.get_or_insert_with(JudgeConfig::default).model = Some(model);
+    }
+    let Some(model_path) = resolve_model(&cli)? else {
+        return Ok(());
+    };
+    apply_sampling_preset(&mut cli, &model_path);
+    let mut generator = load_generator(&cli, model_path.clone(), true)?;
+    let judge_model = suite.judge.as_ref().and_then(|j| j.model.clone());
+    let mut judge = match judge_model {
+        Some(path) if suite.needs_judge() => Some(load_generator(&cli, path, true)?),
+        _ => None,
+    };
+
+    let options = EvalOptions {
+        temperature: cli.temperature,
+        top_p: cli.top_p,
+        top_k: cli.top_k,
+        seed: cli.seed,
+        max_tokens: cli.max_tokens,
+        repeat_penalty: cli.repeat_penalty,
+        repeat_last_n: cli.repeat_last_n,
+    };
+    let model = model_path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let total = suite.cases.len();
+    let mut done = 0;
+    let report = run_suite(
+        &suite,
+        &model,
+        &mut generator,
+        judge.as_mut(),
+        &options,
+        |case| {
+            done += 1;
+            let mark = match case.passed {
+                Some(true) => "✓",
+                Some(false) => "✗",
+                None => "·",
+            };
+            eprintln!("  {} [{}/{}] {}", mark, done, total, case.id);
+        },
+    )?;
+
+    let text = if json {
+        serde_json::to_string_pretty(&report)? + "\n"
+    } else {
+        report.to_markdown()
+    };
+    match output {
+        Some(path) => std::fs::write(path, text)
+            .with_context(|| format!("Failed to write {}", path.display()))?,
+        None => print!("{}", text),
+    }
+    let failed = report.scored - report.passed;
+    if failed > 0 {
+        anyhow::bail!("{} of {} scored case(s) failed", failed, report.scored);
+    }
+    Ok(())
+}
+
+/// `fuzz`: answers the prompt and its variants greedily, printing each
+/// variant as it finishes and a summary per perturbation.
+fn handle_fuzz(
+    cli: Cli,
+    prompt: &str,
+    perturbations: Vec<Perturbation>,
+    variants: usize,
+    json: bool,
+) -> Result<()> {
+    let Some(model_path) = resolve_model(&cli)? else {
+        return Ok(());
+    };
+    let mut generator = load_generator(&cli, model_path, true)?;
+    let options = FuzzOptions {
+        perturbations,
+        variants,
+        seed: cli.seed,
+        max_tokens: cli.max_tokens,
+        repeat_penalty: cli.repeat_penalty,
+        repeat_last_n: cli.repeat_last_n,
+    };
+    let report = run_fuzz(&mut generator, prompt, &options, |variant| {
+        if !json {
+            let mark = if variant.same { "=" } else { "≠" };
+            println!(
+                "  {} {:<10} {:>5.1}%  {:?}",
+                mark,
+                variant.perturbation,
+                variant.similarity * 100.0,
+                variant.prompt
+            );
+        }
+    })?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+
+    println!();
+    for summary in &report.summaries {
+        println!(
+            "  {:<10} {}/{} identical, {:.1}% similar",
+            summary.perturbation,
+            summary.same,
+            summary.variants,
+            summary.similarity * 100.0
+        );
+    }
+    println!("  Stability: {:.1}%", report.stability * 100.0);
+    Ok(())
+}
+
+/// `ctx-test`: runs the needle-in-a-haystack grid, showing progress on
+/// stderr, then prints one row per fill level with a mark per depth.
+fn handle_ctx_test(
+    cli: Cli,
+    levels: Vec<f64>,
+    depths: Vec<f64>,
+    context: Option<usize>,
+    json: bool,
+) -> Result<()> {
+    if let Some(bad) = levels
+        .iter()
+        .chain(&depths)
+        .find(|v| !(0.0..=1.0).contains(*v))
+    {
+        anyhow::bail!("Levels and depths must be between 0 and 1, got {}", bad);
+    }
+    let Some(model_path) = resolve_model(&cli)? else {
+        return Ok(());
+    };
+    let mut generator = load_generator(&cli, model_path, true)?;
+    let limit = generator.context_limit();
+    let options = NeedleOptions {
+        levels: levels.clone(),
+        depths: depths.clone(),
+        context: context.map_or(limit, |c| c.min(limit)),
+        seed: cli.seed,
+    };
+    let report = run_needle_test(&mut generator, &options, |trial| {
+        eprintln!(
+            "  {} {:>3.0}% full, passcode at {:>3.0}%: {} tokens, {:.1}s",
+            if trial.found { "✓" } else { "✗" },
+            trial.level * 100.0,
+            trial.depth * 100.0,
+            trial.tokens,
+            trial.duration.as_secs_f64()
+        );
+    })?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+
+    let header: Vec<String> = depths
+        .iter()
+        .map(|d| format!("{:>4.0}%", d * 100.0))
+        .collect();
+    println!("\n  Fill   Tokens   Depth {}", header.join(" "));
+    for &level in &levels {
+        let trials: Vec<_> = report.trials.iter().filter(|t| t.level == level).collect();
+        let tokens = trials.iter().map(|t| t.tokens).max().unwrap_or(0);
+        let marks: Vec<String> = trials
+            .iter()
+            .map(|t| format!("{:>5}", if t.found { "✓" } else { "✗" }))
+            .collect();
+        println!(
+            "  {:>3.0}%  {:>7}         {}   {:.0}%",
+            level * 100.0,
+            tokens,
+            marks.join(" "),
+            report.accuracy(level) * 100.0
+        );
+    }
+    match report.usable_tokens {
+        Some(tokens) => println!(
+            "\n  Usable context: about {} of {} tokens",
+            tokens, report.context
+        ),
+        None => println!("\n  The passcode was missed at the smallest fill level."),
+    }
+    Ok(())
+}
+
+/// `replay`: re-runs a `--record` file from its recorded state and compares
+/// every reply. After a divergence the recorded reply and sampler position
+/// are restored, so each later turn is checked on its own.
+fn handle_replay(mut cli: Cli, file: &Path, force: bool) -> Result<()> {
+    let recording = Recording::load(file)?;
+    if recording.turns() == 0 {
+        anyhow::bail!("{} has no turns", file.display());
+    }
+    let Some(model_path) = resolve_model(&cli)? else {
+        return Ok(());
+    };
+    let header = &recording.header;
+    if let Some(mismatch) = header.model_mismatch(&ModelFingerprint::from_file(&model_path)?) {
+        if !force {
+            anyhow::bail!("{}; pass --force to replay anyway", mismatch);
+        }
+        eprintln!("  ⚠ Replaying with a different model: {}", mismatch);
+    }
+    if header.oxide_version != env!("CARGO_PKG_VERSION") {
+        eprintln!(
+            "  ⚠ Recorded with oxide-rs {}, replaying with {}; replies may differ",
+            header.oxide_version,
+            env!("CARGO_PKG_VERSION")
+        );
+    }
+    match cli.threads {
+        None => cli.threads = Some(header.threads),
+        Some(threads) if threads != header.threads => eprintln!(
+            "  ⚠ Replaying with {} threads, recorded with {}; replies may differ",
+            threads, header.threads
+        ),
+        Some(_) => {}
+    }
+
+    let params = &header.params;
+    cli.temperature = params.temperature;
+    cli.top_p = params.top_p;
+    cli.top_k = params.top_k;
+    cli.repeat_penalty = params.repeat_penalty;
+    cli.repeat_last_n = params.repeat_last_n;
+    cli.max_tokens = params.max_tokens;
+    cli.seed = params.seed;
+    cli.system = header.system_prompt.clone();
+    cli.logit_bias = header.logit_bias.clone();
+    cli.force_language = header.force_language.clone();
+    cli.top_n_sigma = header.top_n_sigma;
+    cli.eos_bias = header.eos_bias;
+    cli.min_tokens = header.min_tokens;
+
+    let mut generator = load_generator(&cli, model_path, true)?;
+    generator.set_history(header.messages.clone())?;
+    generator.restore_sampler_state(header.sampler)?;
+
+    let snippet = |text: &str, offset: usize| text[offset..].chars().take(40).collect::<String>();
+    let mut turn = 0usize;
+    let mut diverged = 0usize;
+    for event in &recording.events {
+        let (prompt, expected, cancelled, sampler) = match event {
+            RecordedEvent::Clear => {
+                generator.clear_history();
+                continue;
+            }
+            RecordedEvent::Turn {
+                prompt,
+                reply,
+                cancelled,
+                sampler,
+            } => (prompt, reply, *cancelled, *sampler),
+        };
+        turn += 1;
+
+        if cancelled {
+            generator.push_message(Message::new("user", prompt.clone()))?;
+            generator.push_message(Message::new("assistant", expected.clone()))?;
+            generator.restore_sampler_state(sampler)?;
+            println!("  - turn {}: interrupted when recorded, restored", turn);
+            continue;
+        }
+
+        let mut reply = String::new();
+        generator.generate_streaming(
+            prompt,
+            cli.max_tokens,
+            cli.repeat_penalty,
+            cli.repeat_last_n,
+            |event| {
+                if let StreamEvent::Token(t) = event {
+                    reply.push_str(&t);
+                }
+            },
+        )?;
+
+        let Some(offset) = replay::first_difference(expected, &reply) else {
+            println!(
+                "  ✓ turn {}: {} characters match",
+                turn,
+                expected.chars().count()
+            );
+            continue;
+        };
+        diverged += 1;
+        println!("  ✗ turn {}: reply diverges at byte {}", turn, offset);
+        println!("      expected {:?}", snippet(expected, offset));
+        println!("      got      {:?}", snippet(&reply, offset));
+        let mut history = generator.history().to_vec();
+        if let Some(last) = history.last_mut().filter(|m| m.role == "assistant") {
+            last.content = expected.clone();
+        }
+        generator.set_history(history)?;
+        generator.restore_sampler_state(sampler)?;
+    }
+
+    if diverged > 0 {
+        anyhow::bail!("{} of {} turn(s) diverged", diverged, turn);
+    }
+    println!("  ✓ All {} turn(s) match", turn);
+    Ok(())
+}
+
+/// `serve --unix`: serves the model to local clients until stopped.
+#[cfg(unix)]
+fn handle_serve(mut cli: Cli, socket: &Path) -> Result<()> {
+    let Some(model_path) = resolve_model(&cli)? else {
+        return Ok(());
+    };
+    apply_sampling_preset(&mut cli, &model_path);
+    cli.warmup.get_or_insert(WarmupPolicy::Full);
+    let listener = daemon::bind(socket)?;
+    // Tokenizing has its own copy so it never waits for a generation.
+    let tokenizer = match &cli.tokenizer {
+        Some(path) => TokenizerWrapper::from_file(path)?,
+        None => TokenizerWrapper::from_gguf(&model_path)?,
+    };
+    let defaults = DaemonRequest {
+        prompt: String::new(),
+        system_prompt: cli.system.clone(),
+        max_tokens: cli.max_tokens,
+        temperature: cli.temperature,
+        top_p: cli.top_p,
+        top_k: cli.top_k,
+        seed: cli.seed,
+        repeat_penalty: cli.repeat_penalty,
+        repeat_last_n: cli.repeat_last_n,
+    };
+    let generator = load_generator(&cli, model_path, true)?;
+    eprintln!("Listening on {}", socket.display());
+    ipc::serve(generator, tokenizer, listener, defaults)
+}
+
+#[cfg(not(unix))]
+fn handle_serve(_cli: Cli, _socket: &Path) -> Result<()> {
+    anyhow::bail!("serve --unix needs unix sockets, which this platform lacks")
+}
+
+/// `daemon`: loads the model and serves `--shared-runtime` clients until it
+/// has been idle for `idle_timeout` seconds.
+#[cfg(unix)]
+fn handle_daemon(mut cli: Cli, idle_timeout: u64) -> Result<()> {
+    let Some(model_path) = resolve_model(&cli)? else {
+        return Ok(());
+    };
+    cli.warmup.get_or_insert(WarmupPolicy::Full);
+    // Bound before loading, so clients started meanwhile wait for this
+    // daemon instead of starting another.
+    let socket = daemon::socket_path(&model_path)?;
+    let listener = daemon::bind(&socket)?;
+    let generator = match load_generator(&cli, model_path, true) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            std::fs::remove_file(&socket).ok();
+            return Err(e);
+        }
+    };
+    daemon::serve(
+        generator,
+        listener,
+        std::time::Duration::from_secs(idle_timeout),
+    )
+}
+
+#[cfg(not(unix))]
+fn handle_daemon(_cli: Cli, _idle_timeout: u64) -> Result<()> {
+    anyhow::bail!("The daemon needs unix sockets, which this platform lacks")
+}
+
+/// `--once --shared-runtime`: sends the prompt to the model's daemon,
+/// starting it first when none is running, and prints the reply as it
+/// arrives.
+#[cfg(unix)]
+fn run_shared(cli: &Cli, model_path: &Path) -> Result<()> {
+    if cli.post_response_cmd.is_some() {
+        anyhow::bail!("--post-response-cmd is not supported with --shared-runtime");
+    }
+    let socket = daemon::socket_path(model_path)?;
+    if std::os::unix::net::UnixStream::connect(&socket).is_err() {
+        start_daemon(cli, model_path, &socket)?;
+    }
+
+    let prompt = cli
+        .prompt
+        .clone()
+        .unwrap_or_else(|| "Write a hello world program in Rust".to_string());
+    let Some(prompt) = run_pre_prompt_hook(cli, &prompt) else {
+        return Ok(());
+    };
+    let request = DaemonRequest {
+        prompt,
+        system_prompt: cli.system.clone(),
+        max_tokens: cli.max_tokens,
+        temperature: cli.temperature,
+        top_p: cli.top_p,
+        top_k: cli.top_k,
+        seed: cli.seed,
+        repeat_penalty: cli.repeat_penalty,
+        repeat_last_n: cli.repeat_last_n,
+    };
+    let mut tee = open_tee(cli)?;
+    let mut out = io::stdout().lock();
+    daemon::request(&socket, &request, |token| {
+        tee_write(&mut tee, token);
+        out.write_all(token.as_bytes()).ok();
+        out.flush().ok();
+    })?;
+    finish_tee(&mut tee);
+    writeln!(out)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn run_shared(_cli: &Cli, _model_path: &Path) -> Result<()> {
+    anyhow::bail!("--shared-runtime needs unix sockets, which this platform lacks")
+}
+
+/// Starts `oxide-rs daemon` for `model_path` in the background and waits
+/// until it accepts connections on `socket`.
+#[cfg(unix)]
+fn start_daemon(cli: &Cli, model_path: &Path, socket: &Path) -> Result<()> {
+    use std::os::unix::net::UnixStream;
+    use std::os::unix::process::CommandExt;
+    use std::process::Stdio;
+    use std::time::{Duration, Instant};
+
+    let mut command = std::process::Command::new(std::env::current_exe()?);
+    command.arg("daemon").arg("--model").arg(model_path);
+    if let Some(tokenizer) = &cli.tokenizer {
+        command.arg("--tokenizer").arg(tokenizer);
+    }
+    if let Some(threads) = cli.threads {
+        command.arg("--threads").arg(threads.to_string());
+    }
+    if cli.no_pin {
+        command.arg("--no-pin");
+    }
+    command.arg("--simd").arg(&cli.simd);
+    if cli.low_mem {
+        command.arg("--low-mem");
+    }
+    // Its own process group, so Ctrl-C in this terminal leaves it running.
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .process_group(0);
+    let mut child = command
+        .spawn()
+        .context("Failed to start the model daemon")?;
+    eprintln!("Started a model daemon for {}", model_path.display());
+
+    let deadline = Instant::now() + Duration::from_secs(120);
+    while UnixStream::connect(socket).is_err() {
+        if let Some(status) = child.try_wait()? {
+            // Another client may have started a daemon first.
+            if UnixStream::connect(socket).is_ok() {
+                break;
+            }
+            anyhow::bail!(
+                "The model daemon exited during startup ({}); run `oxide-rs daemon --model {}` to see why",
+                status,
+                model_path.display()
+            );
+        }
+        if Instant::now() >= deadline {
+            anyhow::bail!("The model daemon did not start within 2 minutes");
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    Ok(())
+}
+
+/// `--map`: one generation per stdin line, without history. Results are
+/// written as each batch of `--parallel` lines finishes, in input order.
+fn handle_map(cli: &Cli, model_path: PathBuf, template: &str) -> Result<()> {
+    let field = cli.map_field.as_deref();
+    let mut out = match &cli.map_output {
+        Some(path) => MapOutput::file(path, template, field, cli.resume)?,
+        None => MapOutput::stdout(template, field),
+    };
+    if out.resumed_lines() > 0 {
+        eprintln!(
+            "  Resuming after {} finished input lines",
+            out.resumed_lines()
+        );
+    }
+    // JSON Lines input is read and checked in full first, so a bad record
+    // fails the run now rather than hours into it.
+    let input: Box<dyn Iterator<Item = io::Result<String>>> = match field {
+        Some(field) => {
+            let lines = io::stdin()
+                .lock()
+                .lines()
+                .collect::<io::Result<Vec<_>>>()
+                .context("Failed to read stdin")?;
+            check_records(&lines, field)?;
+            Box::new(lines.into_iter().map(Ok))
+        }
+        None => Box::new(io::stdin().lock().lines()),
+    };
+    let mut generator = load_generator(cli, model_path, true)?;
+    let batch_size = cli.parallel.max(1);
+
+    let mut pending: Vec<MapLine> = Vec::new();
+    for (number, line) in input.enumerate() {
+        let line = line.context("Failed to read stdin")?;
+        if !out.input(&line)? {
+            continue;
+        }
+        let line = MapLine::parse(&line, field).with_context(|| format!("Line {}", number + 1))?;
+        pending.push(line);
+        if pending.iter().filter(|l| !l.is_blank()).count() >= batch_size {
+            let batch = std::mem::take(&mut pending);
+            map_batch(&mut generator, cli, template, &batch, &mut out)?;
+            out.checkpoint()?;
+        }
+    }
+    map_batch(&mut generator, cli, template, &pending, &mut out)?;
+    out.finish()
+}
+
+fn map_batch(
+    generator: &mut Generator,
+    cli: &Cli,
+    template: &str,
+    lines: &[MapLine],
+    out: &mut impl Write,
+) -> Result<()> {
+    // Lines with their own settings are generated one by one below.
+    let prompts = lines
+        .iter()
+        .filter(|l| !l.is_blank() && l.overrides().is_empty())
+        .map(|l| map_prompt(template, l.input()))
+        .collect();
+    let mut results = generator
+        .generate_batch(
+            prompts,
+            cli.max_tokens,
+            cli.repeat_penalty,
+            cli.repeat_last_n,
+        )?
+        .into_iter();
+    for line in lines {
+        let result = if line.is_blank() {
+            String::new()
+        } else if line.overrides().is_empty() {
+            results.next().unwrap_or_default()
+        } else {
+            map_with_overrides(
+                generator,
+                cli,
+                map_prompt(template, line.input()),
+                line.overrides(),
+            )?
+        };
+        writeln!(out, "{}", line.render(&result, &cli.map_output_field))?;
+        out.flush()?;
+    }
+    Ok(())
+}
+
+/// Generates `prompt` with a record's own settings, then restores the
+/// generator's. A record's `temperature` samples from a fresh `--seed`.
+fn map_with_overrides(
+    generator: &mut Generator,
+    cli: &Cli,
+    prompt: String,
+    overrides: &MapOverrides,
+) -> Result<String> {
+    let max_tokens = overrides.max_tokens.unwrap_or(cli.max_tokens);
+    let previous_system = generator.system_prompt().map(str::to_string);
+    if let Some(system) = &overrides.system {
+        generator.set_system_prompt(Some(system.clone()))?;
+    }
+    generator.set_stop_sequences(overrides.stop.clone());
+    let generate = |generator: &mut Generator| {
+        generator.generate_batch(
+            vec![prompt],
+            max_tokens,
+            cli.repeat_penalty,
+            cli.repeat_last_n,
+        )
+    };
+    let result = match overrides.temperature {
+        Some(temperature) => {
+            generator.with_sampling(temperature, cli.top_p, cli.top_k, cli.seed, generate)
+        }
+        None => generate(generator),
+    };
+    generator.set_stop_sequences(Vec::new());
+    if overrides.system.is_some() {
+        generator.set_system_prompt(previous_system)?;
+    }
+    Ok(result?.pop().unwrap_or_default())
+}
+
+/// Candidates a step shows after `/step`, unless `oxide-rs step --top` set it.
+const STEP_CANDIDATES: usize = 5;
+
+/// `step` starts with `/step` on, showing that many candidates a step.
+fn interactive_mode(
+    generator: Generator,
+    cli: Cli,
+    model_path: PathBuf,
+    step: Option<usize>,
+) -> Result<()> {
+    let mut generator = generator;
+    let mut stepping = step.is_some();
+    let step_candidates = step.unwrap_or(STEP_CANDIDATES);
+    let mut cli = cli;
+    let mut prompt_display = PromptDisplay::new();
+    let mut fingerprint: Option<ModelFingerprint> = None;
+    let post_hook = cli
+        .post_response_cmd
+        .as_deref()
+        .map(ShellHook::post_response);
+    let scripts = load_scripts(&cli);
+    // Replies are shown once complete when something may rewrite them.
+    let holding = post_hook.is_some() || scripts.has_transforms();
+    let mut tee = open_tee(&cli)?;
+    let mut timing = open_timing_dump(&cli)?;
+
+    if let Some(path) = cli.session.clone().filter(|p| p.exists()) {
+        let model = ModelFingerprint::from_file(&model_path)?;
+        let count = resume_session(&mut generator, &mut cli, &model, &path)?;
+        println!(
+            "  {}\n",
+            i18n::format(Msg::SessionResumed, &[&count, &path.display()])
+        );
+        fingerprint = Some(model);
+    }
+    // Opened after resuming, so the recording starts from that history.
+    let mut recorder = open_recorder(&cli, &model_path, &generator)?;
+    let mut collector = open_collector(&cli)?;
+
+    loop {
+        prompt_display.show_input_prompt();
+        io::stdout().flush()?;
 
         let mut prompt = String::new();
-        io::stdin().read_line(&mut prompt)?;
+        if io::stdin().read_line(&mut prompt)? == 0 {
+            // Ctrl+D (end of input) at the prompt.
+            println!();
+            break;
+        }
         let prompt = prompt.trim().to_string();
 
         if prompt.is_empty() {
@@ -495,17 +3208,148 @@ fn interactive_mode(generator: Generator, cli: Cli, pinned_pool: rayon::ThreadPo
 
         if prompt == "/clear" {
             generator.clear_history();
-            println!("  History cleared.\n");
+            record_event(&mut recorder, &RecordedEvent::Clear);
+            println!("  {}\n", i18n::text(Msg::HistoryCleared));
+            continue;
+        }
+
+        if let Some(arg) = session_command(&prompt, "/save") {
+            let Some(path) = arg.map(PathBuf::from).or_else(|| cli.session.clone()) else {
+                println!("  {}\n", i18n::text(Msg::UsageSave));
+                continue;
+            };
+            let model = match fingerprint.take() {
+                Some(model) => model,
+                None => ModelFingerprint::from_file(&model_path)?,
+            };
+            match save_session(&generator, &cli, &model, &path) {
+                Ok(()) => println!(
+                    "  {}\n",
+                    i18n::format(Msg::SessionSaved, &[&path.display()])
+                ),
+                Err(e) => println!(
+                    "  {}\n",
+                    i18n::format(Msg::SessionSaveFailed, &[&format!("{:#}", e)])
+                ),
+            }
+            fingerprint = Some(model);
+            continue;
+        }
+
+        if let Some(arg) = session_command(&prompt, "/load") {
+            if recorder.is_some() {
+                println!("  {}\n", i18n::text(Msg::LoadWhileRecording));
+                continue;
+            }
+            let Some(path) = arg.map(PathBuf::from).or_else(|| cli.session.clone()) else {
+                println!("  {}\n", i18n::text(Msg::UsageLoad));
+                continue;
+            };
+            let model = match fingerprint.take() {
+                Some(model) => model,
+                None => ModelFingerprint::from_file(&model_path)?,
+            };
+            match resume_session(&mut generator, &mut cli, &model, &path) {
+                Ok(count) => println!(
+                    "  {}\n",
+                    i18n::format(Msg::SessionLoaded, &[&count, &path.display()])
+                ),
+                Err(e) => println!(
+                    "  {}\n",
+                    i18n::format(Msg::SessionLoadFailed, &[&format!("{:#}", e)])
+                ),
+            }
+            fingerprint = Some(model);
+            continue;
+        }
+
+        if prompt == "/mark" {
+            mark_exchange(&generator, &model_path, None);
+            continue;
+        }
+
+        if let Some(arg) = session_command(&prompt, "/note") {
+            match arg.filter(|text| !text.is_empty()) {
+                Some(text) => mark_exchange(&generator, &model_path, Some(text.to_string())),
+                None => println!("  {}\n", i18n::text(Msg::UsageNote)),
+            }
+            continue;
+        }
+
+        if let Some(arg) = session_command(&prompt, "/file") {
+            if recorder.is_some() {
+                println!("  {}\n", i18n::text(Msg::FileWhileRecording));
+                continue;
+            }
+            match arg.filter(|path| !path.is_empty()) {
+                Some(path) => match read_document(&cli, &mut generator, Path::new(path), false) {
+                    Ok(()) => println!(),
+                    Err(e) => println!(
+                        "  {}\n",
+                        i18n::format(Msg::CommandFailed, &[&"file", &format!("{:#}", e)])
+                    ),
+                },
+                None => println!("  {}\n", i18n::text(Msg::UsageFile)),
+            }
+            continue;
+        }
+
+        if prompt == "/step" {
+            if recorder.is_some() {
+                println!("  {}\n", i18n::text(Msg::StepWhileRecording));
+                continue;
+            }
+            stepping = !stepping;
+            let msg = if stepping { Msg::StepOn } else { Msg::StepOff };
+            println!("  {}\n", i18n::text(msg));
             continue;
         }
 
         if prompt == "/help" {
-            println!("  Commands:");
-            println!("    /clear   - Clear conversation history");
-            println!("    /context - Show context usage");
-            println!("    /stats   - Show model info and settings");
-            println!("    /exit    - Exit the program");
-            println!("    /help    - Show this help\n");
+            println!("  {}", i18n::text(Msg::Commands));
+            let builtin = [
+                ("clear", Msg::HelpClear),
+                ("context", Msg::HelpContext),
+                ("preview", Msg::HelpPreview),
+                ("stats", Msg::HelpStats),
+                ("save", Msg::HelpSave),
+                ("load", Msg::HelpLoad),
+                ("mark", Msg::HelpMark),
+                ("note", Msg::HelpNote),
+                ("file", Msg::HelpFile),
+                ("step", Msg::HelpStep),
+            ];
+            for (command, help) in builtin {
+                println!("    /{:<8}- {}", command, i18n::text(help));
+            }
+            for command in scripts.commands() {
+                println!("    /{:<8}- {}", command, i18n::text(Msg::HelpScript));
+            }
+            println!("    /{:<8}- {}", "exit", i18n::text(Msg::HelpExit));
+            println!("    /{:<8}- {}\n", "help", i18n::text(Msg::HelpHelp));
+            continue;
+        }
+
+        if let Some(arg) = session_command(&prompt, "/preview") {
+            match generator.preview(arg.filter(|text| !text.is_empty())) {
+                Ok(preview) => {
+                    print_divider();
+                    print!("{}", preview.text);
+                    if !preview.text.ends_with('\n') {
+                        println!();
+                    }
+                    print_divider();
+                    println!(
+                        "  {} / {} tokens\n",
+                        format_token_count(preview.tokens),
+                        format_token_count(generator.context_limit())
+                    );
+                }
+                Err(e) => println!(
+                    "  {}\n",
+                    i18n::format(Msg::RenderFailed, &[&format!("{:#}", e)])
+                ),
+            }
             continue;
         }
 
@@ -514,10 +3358,15 @@ fn interactive_mode(generator: Generator, cli: Cli, pinned_pool: rayon::ThreadPo
             let limit = generator.context_limit();
             let percentage = generator.context_percentage();
             println!(
-                "  Context: {} / {} tokens ({:.1}%)\n",
-                format_token_count(used),
-                format_token_count(limit),
-                percentage
+                "  {}\n",
+                i18n::format(
+                    Msg::ContextUsage,
+                    &[
+                        &format_token_count(used),
+                        &format_token_count(limit),
+                        &format!("{:.1}", percentage),
+                    ],
+                )
             );
             continue;
         }
@@ -525,10 +3374,16 @@ fn interactive_mode(generator: Generator, cli: Cli, pinned_pool: rayon::ThreadPo
         if prompt == "/stats" {
             let meta = generator.metadata();
             println!("  Model:     {}", meta.name);
-            println!(
-                "  Quant:     {}",
-                meta.quantization.as_deref().unwrap_or("Unknown")
-            );
+            println!("  Quant:     {}", meta.quant_summary());
+            let params = meta.quant_info.total_params().max(1);
+            for (dtype, tensors, type_params) in meta.quant_info.type_mix() {
+                println!(
+                    "             {:<6} {:>4} tensors, {:>5.1}% of weights",
+                    dtype,
+                    tensors,
+                    type_params as f64 * 100.0 / params as f64
+                );
+            }
             println!(
                 "  Context:   {} tokens",
                 format_token_count(meta.context_length)
@@ -536,46 +3391,137 @@ fn interactive_mode(generator: Generator, cli: Cli, pinned_pool: rayon::ThreadPo
             println!("  Layers:    {}", meta.n_layer);
             println!("  Embedding: {}", meta.n_embd);
             println!("  Vocab:     {}", meta.vocab_size);
+            if let Some(preset) = cli.preset {
+                println!("  Preset:    {}", preset.as_str());
+            }
             println!("  Temp:      {}", cli.temperature);
-            println!("  Max Tok:   {}", cli.max_tokens);
+            println!("  Max Tok:   {}", format_max_tokens(cli.max_tokens));
             println!("  Seed:      {}", cli.seed);
             println!();
             continue;
         }
 
-        let mut stream = StreamOutput::new();
-        let mut thinking_spinner: Option<ThinkingSpinner> = None;
+        if let Some((name, args)) = script_command(&scripts, &prompt) {
+            let (lent_back, output) = scripts.run_command(name, args, generator);
+            generator = lent_back;
+            match output {
+                Ok(output) if output.is_empty() => println!(),
+                Ok(output) => println!("{}\n", output.trim_end()),
+                Err(e) => println!(
+                    "  {}\n",
+                    i18n::format(Msg::CommandFailed, &[&name, &format!("{:#}", e)])
+                ),
+            }
+            continue;
+        }
+
+        let Some(prompt) = run_pre_prompt_hook(&cli, &prompt) else {
+            continue;
+        };
+
+        if stepping {
+            match step_reply(&mut generator, &prompt, step_candidates, cli.max_tokens) {
+                Ok(reply) if reply.is_empty() => println!(),
+                Ok(reply) => {
+                    println!("\n{}\n", reply);
+                    collect_turn(&mut collector, &cli, &model_path, &generator);
+                }
+                Err(e) => match e.downcast::<ContextOverflow>() {
+                    Ok(overflow) => println!("  {}\n", overflow),
+                    Err(e) => return Err(e),
+                },
+            }
+            print_divider();
+            continue;
+        }
+
+        let mut stream = StreamOutput::with_granularity(cli.stream_granularity);
+        if holding {
+            stream.hold();
+        }
         let context_limit = generator.context_limit();
         let context_used = generator.context_used();
         let mut prompt_token_count = 0usize;
-
-        pinned_pool.install(|| {
-            generator.generate_streaming(
-                &prompt,
-                cli.max_tokens,
-                cli.repeat_penalty,
-                cli.repeat_last_n,
-                |event| match event {
-                    StreamEvent::PrefillStatus(count) => {
-                        prompt_token_count = count;
-                        stream.set_prompt_tokens(count);
-                        if thinking_spinner.is_none() {
-                            thinking_spinner = Some(ThinkingSpinner::new());
-                        }
+        let mut reply = String::new();
+
+        // Ctrl+C stops this reply instead of the program.
+        let cancel = CancelToken::new();
+        generator.set_cancel_token(Some(cancel.clone()));
+        oxide_rs::platform::catch_interrupts(true);
+
+        let result = generator.generate_streaming(
+            &prompt,
+            cli.max_tokens,
+            cli.repeat_penalty,
+            cli.repeat_last_n,
+            |event| match event {
+                StreamEvent::PrefillStatus(count) => {
+                    prompt_token_count = count;
+                    stream.set_prompt_tokens(count);
+                    stream.start_thinking();
+                }
+                StreamEvent::Token(t) => {
+                    if oxide_rs::platform::take_interrupt() {
+                        cancel.cancel();
                     }
-                    StreamEvent::Token(t) => {
-                        if let Some(spinner) = thinking_spinner.take() {
-                            spinner.stop();
-                        }
-                        stream.set_context(context_used, context_limit);
-                        stream.print_token(&t);
+                    tee_write(&mut tee, &t);
+                    if recorder.is_some() {
+                        reply.push_str(&t);
                     }
-                    StreamEvent::Done => {
-                        stream.finish();
-                    }
-                },
-            )
-        })?;
+                    stream.set_context(context_used, context_limit);
+                    stream.print_token(&t);
+                }
+                StreamEvent::ContextTruncated { dropped_tokens, .. } => {
+                    stream.print_truncation_warning(dropped_tokens);
+                }
+                StreamEvent::LoopDetected { period } => stream.set_loop_detected(period),
+                StreamEvent::Done => {}
+                StreamEvent::Error(message) => stream.print_error(&message),
+            },
+        );
+        oxide_rs::platform::catch_interrupts(false);
+        generator.set_cancel_token(None);
+        finish_tee(&mut tee);
+        if let Err(e) = result {
+            // A prompt that cannot fit is not added to the history; keep
+            // the conversation going so the user can /clear or rephrase.
+            match e.downcast::<ContextOverflow>() {
+                Ok(overflow) => {
+                    println!("  {}\n", overflow);
+                    continue;
+                }
+                // The partial reply is on screen; the turn is not kept.
+                Err(e) if e.is::<GenerationError>() => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        record_turn(
+            &mut recorder,
+            &generator,
+            prompt,
+            reply,
+            cancel.is_cancelled(),
+        );
+        stream.set_confidence(generator.confidence());
+        let kept = if holding {
+            finish_held_reply(
+                &mut generator,
+                &mut stream,
+                post_hook.as_ref(),
+                Some(&scripts),
+            )?
+        } else {
+            stream.finish();
+            true
+        };
+        // Interrupted replies are incomplete, so they make poor examples.
+        if kept && !cancel.is_cancelled() {
+            collect_turn(&mut collector, &cli, &model_path, &generator);
+        }
+        dump_token_timing(&mut timing, &generator);
+        if cancel.is_cancelled() {
+            println!("  {}", i18n::text(Msg::Interrupted));
+        }
 
         print_divider();
     }
@@ -592,3 +3538,505 @@ fn format_token_count(n: usize) -> String {
         n.to_string()
     }
 }
+
+/// Matches `/save` or `/save <arg>`; the inner option is the argument.
+fn session_command<'a>(prompt: &'a str, command: &str) -> Option<Option<&'a str>> {
+    let rest = prompt.strip_prefix(command)?;
+    if rest.is_empty() {
+        return Some(None);
+    }
+    rest.strip_prefix(' ').map(|arg| Some(arg.trim()))
+}
+
+/// Loads the user's Rhai scripts, or none if they fail to compile.
+fn load_scripts(cli: &Cli) -> ScriptHost {
+    let settings = ScriptSettings {
+        max_tokens: cli.max_tokens,
+        repeat_penalty: cli.repeat_penalty,
+        repeat_last_n: cli.repeat_last_n,
+    };
+    let loaded = ScriptHost::default_dir().and_then(|dir| ScriptHost::load(&dir, settings.clone()));
+    match loaded {
+        Ok(scripts) => scripts,
+        Err(e) => {
+            println!("  Scripts disabled: {:#}\n", e);
+            ScriptHost::empty(settings)
+        }
+    }
+}
+
+/// Splits `/name args` when `name` is a script command.
+fn script_command<'a>(scripts: &ScriptHost, prompt: &'a str) -> Option<(&'a str, &'a str)> {
+    let command = prompt.strip_prefix('/')?;
+    let (name, args) = command.split_once(' ').unwrap_or((command, ""));
+    scripts.has_command(name).then(|| (name, args.trim()))
+}
+
+/// Runs `--pre-prompt-cmd` on `prompt`. Returns the prompt to send, or
+/// `None` after reporting that the hook blocked it.
+fn run_pre_prompt_hook(cli: &Cli, prompt: &str) -> Option<String> {
+    let Some(command) = &cli.pre_prompt_cmd else {
+        return Some(prompt.to_string());
+    };
+    match ShellHook::pre_prompt(command.as_str()).apply(prompt) {
+        HookOutcome::Continue(prompt) => Some(prompt),
+        HookOutcome::Veto(reason) => {
+            println!(
+                "  Prompt blocked by the pre-prompt hook{}\n",
+                hook_reason(&reason)
+            );
+            None
+        }
+    }
+}
+
+/// The `--tee` file, opened for appending.
+fn open_tee(cli: &Cli) -> Result<Option<Tee<std::fs::File>>> {
+    let Some(path) = &cli.tee else {
+        return Ok(None);
+    };
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    Ok(Some(Tee::new(file, cli.tee_flush)))
+}
+
+/// Copies reply text to the `--tee` file. After a failed write the copy
+/// stops, so the error is reported once.
+fn tee_write(tee: &mut Option<Tee<std::fs::File>>, text: &str) {
+    if let Some(Err(e)) = tee.as_mut().map(|t| t.write(text)) {
+        eprintln!("  Stopped writing to the --tee file: {}", e);
+        *tee = None;
+    }
+}
+
+/// Ends a reply in the `--tee` file with a blank line.
+fn finish_tee(tee: &mut Option<Tee<std::fs::File>>) {
+    tee_write(tee, "\n\n");
+    if let Some(Err(e)) = tee.as_mut().map(Tee::finish) {
+        eprintln!("  Stopped writing to the --tee file: {}", e);
+        *tee = None;
+    }
+}
+
+/// Starts the `--record` file from the generator's current state.
+fn open_recorder(cli: &Cli, model_path: &Path, generator: &Generator) -> Result<Option<Recorder>> {
+    let Some(path) = &cli.record else {
+        return Ok(None);
+    };
+    let mut header = RecordingHeader::new(
+        ModelFingerprint::from_file(model_path)?,
+        generator.system_prompt().map(String::from),
+        session_params(cli),
+        inference_threads(cli),
+        generator.history().to_vec(),
+        generator.sampler_state(),
+    );
+    header.logit_bias = cli.logit_bias.clone();
+    header.force_language = cli.force_language.clone();
+    header.top_n_sigma = cli.top_n_sigma;
+    header.eos_bias = cli.eos_bias;
+    header.min_tokens = cli.min_tokens;
+    Recorder::create(path, &header).map(Some)
+}
+
+/// Appends an event to the `--record` file. After a failed write recording
+/// stops, so the error is reported once.
+fn record_event(recorder: &mut Option<Recorder>, event: &RecordedEvent) {
+    if let Some(Err(e)) = recorder.as_mut().map(|r| r.record(event)) {
+        eprintln!("  Stopped writing to the --record file: {:#}", e);
+        *recorder = None;
+    }
+}
+
+fn record_turn(
+    recorder: &mut Option<Recorder>,
+    generator: &Generator,
+    prompt: String,
+    reply: String,
+    cancelled: bool,
+) {
+    if recorder.is_some() {
+        let event = RecordedEvent::Turn {
+            prompt,
+            reply,
+            cancelled,
+            sampler: generator.sampler_state(),
+        };
+        record_event(recorder, &event);
+    }
+}
+
+/// The `--collect` dataset file, with `--collect-hook` run on each record.
+fn open_collector(cli: &Cli) -> Result<Option<DatasetWriter>> {
+    let Some(path) = &cli.collect else {
+        return Ok(None);
+    };
+    let redaction = redaction_config(cli)?.unwrap_or_default();
+    let mut writer = DatasetWriter::open(path, &redaction)?;
+    if let Some(command) = &cli.collect_hook {
+        let hook = ShellHook::collect(command.clone());
+        writer = writer.with_hook(move |record: DatasetRecord| {
+            match hook.run(&serde_json::to_string(&record)?)? {
+                HookOutcome::Continue(text) if text.trim().is_empty() => Ok(Some(record)),
+                HookOutcome::Continue(text) => serde_json::from_str(&text)
+                    .map(Some)
+                    .context("--collect-hook printed something other than a record"),
+                HookOutcome::Veto(_) => Ok(None),
+            }
+        });
+    }
+    Ok(Some(writer))
+}
+
+/// Appends the exchange that ends the history to the `--collect` file.
+/// After a failed write collecting stops, so the error is reported once.
+fn collect_turn(
+    collector: &mut Option<DatasetWriter>,
+    cli: &Cli,
+    model_path: &Path,
+    generator: &Generator,
+) {
+    let Some(writer) = collector.as_mut() else {
+        return;
+    };
+    let model = model_path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let record = DatasetRecord::new(
+        generator.system_prompt(),
+        generator.history(),
+        session_params(cli),
+        model,
+    );
+    if record.completion().is_none() {
+        return;
+    }
+    if let Err(e) = writer.append(record) {
+        eprintln!("  Stopped writing to the --collect file: {:#}", e);
+        *collector = None;
+    }
+}
+
+/// The `--per-token-timing` CSV file and the number of replies written to it.
+struct TimingDump {
+    file: std::io::BufWriter<std::fs::File>,
+    replies: usize,
+}
+
+/// Opens the `--per-token-timing` file for appending, writing the CSV
+/// header when it is new.
+fn open_timing_dump(cli: &Cli) -> Result<Option<TimingDump>> {
+    let Some(path) = &cli.per_token_timing else {
+        return Ok(None);
+    };
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    let is_new = file.metadata()?.len() == 0;
+    let mut file = std::io::BufWriter::new(file);
+    if is_new {
+        writeln!(file, "reply,step,latency_ms")?;
+    }
+    Ok(Some(TimingDump { file, replies: 0 }))
+}
+
+/// Appends the last reply's decode-step latencies to the `--per-token-timing`
+/// file and prints their percentiles.
+fn dump_token_timing(dump: &mut Option<TimingDump>, generator: &Generator) {
+    let Some(timing) = dump.as_mut() else {
+        return;
+    };
+    timing.replies += 1;
+    let reply = timing.replies;
+    let written = generator
+        .token_latencies()
+        .iter()
+        .enumerate()
+        .try_for_each(|(step, latency)| {
+            writeln!(
+                timing.file,
+                "{},{},{:.3}",
+                reply,
+                step + 1,
+                latency.as_secs_f64() * 1000.0
+            )
+        })
+        .and_then(|()| timing.file.flush());
+    if let Err(e) = written {
+        eprintln!("  Stopped writing to the --per-token-timing file: {}", e);
+        *dump = None;
+    }
+    if let Some(latency) = generator.latency() {
+        let ms = |d: std::time::Duration| d.as_secs_f64() * 1000.0;
+        eprintln!(
+            "  Decode latency over {} steps: p50 {:.1} ms, p95 {:.1} ms, p99 {:.1} ms, max {:.1} ms",
+            latency.steps,
+            ms(latency.p50),
+            ms(latency.p95),
+            ms(latency.p99),
+            ms(latency.max)
+        );
+    }
+}
+
+/// Runs `--post-response-cmd` and script transforms on the reply held back
+/// in `stream`, prints the result and updates the history to match.
+/// Returns false when the hook blocked the reply.
+fn finish_held_reply(
+    generator: &mut Generator,
+    stream: &mut StreamOutput,
+    hook: Option<&ShellHook>,
+    scripts: Option<&ScriptHost>,
+) -> Result<bool> {
+    let reply = stream.take_held().unwrap_or_default();
+    let mut outcome = match hook {
+        Some(hook) => hook.apply(&reply),
+        None => HookOutcome::Continue(reply.clone()),
+    };
+    let mut script_error = None;
+    if let (HookOutcome::Continue(text), Some(scripts)) = (&mut outcome, scripts) {
+        match scripts.transform(text, generator.history()) {
+            Ok(transformed) => *text = transformed,
+            Err(e) => script_error = Some(e),
+        }
+    }
+    if let HookOutcome::Continue(text) = &outcome {
+        stream.release(text);
+    }
+    stream.finish();
+    if let Some(e) = script_error {
+        println!("  Transform failed: {:#}", e);
+    }
+
+    let mut history = generator.history().to_vec();
+    let kept = matches!(outcome, HookOutcome::Continue(_));
+    match outcome {
+        HookOutcome::Continue(text) if text == reply => return Ok(true),
+        HookOutcome::Continue(text) => {
+            if let Some(last) = history.last_mut().filter(|m| m.role == "assistant") {
+                last.content = text;
+            }
+        }
+        HookOutcome::Veto(reason) => {
+            println!(
+                "  Reply blocked by the post-response hook{}",
+                hook_reason(&reason)
+            );
+            // Drop the exchange so the blocked reply does not shape later ones.
+            if history.last().is_some_and(|m| m.role == "assistant") {
+                history.pop();
+            }
+            if history.last().is_some_and(|m| m.role == "user") {
+                history.pop();
+            }
+        }
+    }
+    generator.set_history(history)?;
+    Ok(kept)
+}
+
+fn hook_reason(reason: &str) -> String {
+    if reason.is_empty() {
+        ".".to_string()
+    } else {
+        format!(": {}", reason)
+    }
+}
+
+/// Stores the last exchange in the notes file and reports the result.
+fn mark_exchange(generator: &Generator, model_path: &Path, note: Option<String>) {
+    let model = model_path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let result = NoteStore::default_path().and_then(|path| {
+        let mut store = NoteStore::load(&path)?;
+        let Some(id) = store.mark(generator.history(), &model, note).map(|n| n.id) else {
+            return Ok(None);
+        };
+        store.save(&path)?;
+        Ok(Some(id))
+    });
+    match result {
+        Ok(Some(id)) => println!("  {}\n", i18n::format(Msg::NoteSaved, &[&id])),
+        Ok(None) => println!("  {}\n", i18n::text(Msg::NothingToMark)),
+        Err(e) => println!(
+            "  {}\n",
+            i18n::format(Msg::NoteSaveFailed, &[&format!("{:#}", e)])
+        ),
+    }
+}
+
+fn session_params(cli: &Cli) -> SessionParams {
+    SessionParams {
+        temperature: cli.temperature,
+        top_p: cli.top_p,
+        top_k: cli.top_k,
+        repeat_penalty: cli.repeat_penalty,
+        repeat_last_n: cli.repeat_last_n,
+        max_tokens: cli.max_tokens,
+        seed: cli.seed,
+    }
+}
+
+fn save_session(
+    generator: &Generator,
+    cli: &Cli,
+    model: &ModelFingerprint,
+    path: &Path,
+) -> Result<()> {
+    let mut session = Session::new(
+        model.clone(),
+        generator.system_prompt().map(String::from),
+        session_params(cli),
+        generator.history().to_vec(),
+    );
+    session.sampler = Some(generator.sampler_state());
+    session.save(path)
+}
+
+/// Restores a saved session into `generator` and `cli`. Refuses a session
+/// saved with another model unless `--force` was given. Returns the number
+/// of restored messages.
+fn resume_session(
+    generator: &mut Generator,
+    cli: &mut Cli,
+    model: &ModelFingerprint,
+    path: &Path,
+) -> Result<usize> {
+    let session = Session::load(path)?;
+    if let Some(mismatch) = session.model_mismatch(model) {
+        if !cli.force {
+            anyhow::bail!("{}; pass --force to resume anyway", mismatch);
+        }
+        eprintln!("  ⚠ Resuming with a different model: {}", mismatch);
+    }
+
+    let params = session.params;
+    // Restoring the sampler continues the original random stream, so the
+    // next reply is the one the saved session would have sampled. Older
+    // sessions without it are reseeded.
+    generator.set_sampling(params.temperature, params.top_p, params.top_k, params.seed);
+    if let Some(state) = session.sampler {
+        generator.restore_sampler_state(state)?;
+    }
+    generator.set_system_prompt(session.system_prompt.clone())?;
+    generator.set_history(session.messages)?;
+    cli.temperature = params.temperature;
+    cli.top_p = params.top_p;
+    cli.top_k = params.top_k;
+    cli.repeat_penalty = params.repeat_penalty;
+    cli.repeat_last_n = params.repeat_last_n;
+    cli.max_tokens = params.max_tokens;
+    cli.seed = params.seed;
+    cli.system = session.system_prompt;
+    Ok(generator.history().len())
+}
+
+/// Parses `--max-tokens`, mapping `auto` to `0` (the library's auto budget).
+fn parse_max_tokens(value: &str) -> Result<usize, String> {
+    if value.eq_ignore_ascii_case("auto") {
+        return Ok(0);
+    }
+    value
+        .parse::<usize>()
+        .map_err(|e| format!("expected a number or `auto`: {}", e))
+}
+
+/// Parses `HOST:PORT`; IPv6 hosts keep their brackets (`[::]:8080`).
+fn parse_listen(value: &str) -> Result<(String, u16), String> {
+    let (host, port) = value
+        .rsplit_once(':')
+        .ok_or_else(|| "expected HOST:PORT, e.g. 0.0.0.0:8080".to_string())?;
+    if host.is_empty() {
+        return Err("missing host, e.g. 0.0.0.0:8080".to_string());
+    }
+    let port = port
+        .parse::<u16>()
+        .map_err(|e| format!("invalid port `{}`: {}", port, e))?;
+    Ok((host.to_string(), port))
+}
+
+fn format_max_tokens(max_tokens: usize) -> String {
+    if max_tokens == 0 {
+        "auto".to_string()
+    } else {
+        max_tokens.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use clap::{CommandFactory, Parser};
+
+    use super::{parse_listen, session_command, Cli, ExplicitSampling};
+
+    #[test]
+    fn cli_definition_is_valid() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn short_flags_keep_their_meaning() {
+        let cli = Cli::try_parse_from([
+            "oxide-rs", "-m", "a.gguf", "-t", "t.json", "-s", "Be brief", "-p", "Hi", "-o",
+        ])
+        .unwrap();
+        assert_eq!(cli.model, [PathBuf::from("a.gguf")]);
+        assert_eq!(cli.tokenizer, Some(PathBuf::from("t.json")));
+        assert_eq!(cli.system.as_deref(), Some("Be brief"));
+        assert_eq!(cli.prompt.as_deref(), Some("Hi"));
+        assert!(cli.once && !cli.models && !cli.tui);
+
+        let cli =
+            Cli::try_parse_from(["oxide-rs", "summarize", "--file", "x", "-m", "a.gguf"]).unwrap();
+        assert_eq!(cli.model, [PathBuf::from("a.gguf")]);
+    }
+
+    #[test]
+    fn detects_explicit_sampling_flags() {
+        let explicit = |args: &[&str]| {
+            let matches = Cli::command().try_get_matches_from(args).unwrap();
+            ExplicitSampling::from_matches(&matches)
+        };
+        let defaults = explicit(&["oxide-rs", "--preset", "code"]);
+        assert!(!defaults.temperature && !defaults.repeat_penalty);
+
+        let top_level = explicit(&["oxide-rs", "--temperature", "0.3"]);
+        assert!(top_level.temperature && !top_level.top_p);
+
+        let after_subcommand = explicit(&["oxide-rs", "notes", "search", "--top-k", "5"]);
+        assert!(after_subcommand.top_k && !after_subcommand.temperature);
+    }
+
+    #[test]
+    fn parses_listen_address() {
+        assert_eq!(
+            parse_listen("0.0.0.0:8080"),
+            Ok(("0.0.0.0".to_string(), 8080))
+        );
+        assert_eq!(parse_listen("[::]:9000"), Ok(("[::]".to_string(), 9000)));
+        assert!(parse_listen("8080").is_err());
+        assert!(parse_listen(":8080").is_err());
+        assert!(parse_listen("localhost:http").is_err());
+    }
+
+    #[test]
+    fn parses_session_commands() {
+        assert_eq!(session_command("/save", "/save"), Some(None));
+        assert_eq!(
+            session_command("/save chat.json", "/save"),
+            Some(Some("chat.json"))
+        );
+        assert_eq!(session_command("/saved", "/save"), None);
+        assert_eq!(session_command("/load x", "/save"), None);
+    }
+}
@@ -0,0 +1,127 @@
+//! Activation statistics for diagnosing broken output
+//! (`--debug-activations`, `Model::forward_debug`).
+//!
+//! A quantization that overflows or a kernel that misbehaves usually shows
+//! up as one layer whose output norm explodes or turns NaN, long before the
+//! sampled text makes the cause obvious. [`ActivationReport`] records, for
+//! one forward pass over a prompt, summary statistics of the embeddings,
+//! each layer's output and the final logits.
+
+use candle_core::{DType, Result, Tensor};
+use serde::Serialize;
+
+/// Summary of the values of one tensor, over every prompt position.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TensorStats {
+    pub name: String,
+    /// Number of values summarized.
+    pub count: usize,
+    pub mean: f32,
+    /// Root mean square, the per-value norm.
+    pub rms: f32,
+    pub max_abs: f32,
+    /// NaN and infinite values, which the other fields skip.
+    pub non_finite: usize,
+    #[serde(skip)]
+    sum: f64,
+    #[serde(skip)]
+    sum_sq: f64,
+}
+
+impl TensorStats {
+    pub fn from_values(name: impl Into<String>, values: &[f32]) -> Self {
+        let mut stats = Self {
+            name: name.into(),
+            count: 0,
+            mean: 0.0,
+            rms: 0.0,
+            max_abs: 0.0,
+            non_finite: 0,
+            sum: 0.0,
+            sum_sq: 0.0,
+        };
+        stats.add(values);
+        stats
+    }
+
+    pub fn from_tensor(name: impl Into<String>, tensor: &Tensor) -> Result<Self> {
+        let mut stats = Self::from_values(name, &[]);
+        stats.add_tensor(tensor)?;
+        Ok(stats)
+    }
+
+    /// Adds the values of another position.
+    pub fn add(&mut self, values: &[f32]) {
+        for &v in values {
+            if !v.is_finite() {
+                self.non_finite += 1;
+                continue;
+            }
+            self.count += 1;
+            self.sum += v as f64;
+            self.sum_sq += (v as f64) * (v as f64);
+            self.max_abs = self.max_abs.max(v.abs());
+        }
+        if self.count > 0 {
+            self.mean = (self.sum / self.count as f64) as f32;
+            self.rms = (self.sum_sq / self.count as f64).sqrt() as f32;
+        }
+    }
+
+    pub fn add_tensor(&mut self, tensor: &Tensor) -> Result<()> {
+        let values = tensor
+            .to_dtype(DType::F32)?
+            .flatten_all()?
+            .to_vec1::<f32>()?;
+        self.add(&values);
+        Ok(())
+    }
+}
+
+/// Statistics of one forward pass over a prompt.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ActivationReport {
+    pub architecture: String,
+    pub prompt_tokens: usize,
+    /// Embeddings followed by every layer's output, in order. Empty when
+    /// the architecture's implementation does not expose its layers.
+    pub layers: Vec<TensorStats>,
+    /// Logits of the last prompt position.
+    pub logits: TensorStats,
+}
+
+impl ActivationReport {
+    /// The first recorded tensor holding NaN or infinite values.
+    pub fn first_non_finite(&self) -> Option<&TensorStats> {
+        self.layers
+            .iter()
+            .chain(std::iter::once(&self.logits))
+            .find(|stats| stats.non_finite > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ActivationReport, TensorStats};
+
+    #[test]
+    fn summarizes_values_and_finds_the_first_broken_layer() {
+        let mut stats = TensorStats::from_values("blk.0", &[3.0, -4.0]);
+        stats.add(&[f32::NAN, 0.0]);
+        assert_eq!(stats.count, 3);
+        assert_eq!(stats.non_finite, 1);
+        assert_eq!(stats.max_abs, 4.0);
+        assert!((stats.mean - (-1.0 / 3.0)).abs() < 1e-6);
+        assert!((stats.rms - (25.0f32 / 3.0).sqrt()).abs() < 1e-6);
+
+        let report = ActivationReport {
+            architecture: "qwen35".to_string(),
+            prompt_tokens: 2,
+            layers: vec![TensorStats::from_values("embeddings", &[1.0]), stats],
+            logits: TensorStats::from_values("logits", &[f32::INFINITY]),
+        };
+        assert_eq!(report.first_non_finite().unwrap().name, "blk.0");
+        let json = serde_json::to_value(&report).unwrap();
+        assert!(json["layers"][1].get("sum").is_none());
+    }
+}
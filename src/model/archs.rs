@@ -0,0 +1,148 @@
+//! Which model architectures oxide-rs can run (`oxide-rs archs`).
+//!
+//! The loader picks an implementation from `general.architecture` and
+//! falls back to the LLaMA one for anything it does not know, which then
+//! fails on the first `llama.*` key or tensor it cannot find, often after
+//! reading much of the file. This table answers in advance, so tools can
+//! check before loading and `check` can give a definite answer.
+
+use serde::Serialize;
+
+/// How well an architecture is supported.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArchStatus {
+    /// Has an implementation.
+    Supported,
+    /// Known not to load: its layers differ from every implementation.
+    Unsupported,
+}
+
+impl ArchStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ArchStatus::Supported => "supported",
+            ArchStatus::Unsupported => "unsupported",
+        }
+    }
+}
+
+/// One row of the capability matrix.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct ArchInfo {
+    /// `general.architecture` value.
+    pub name: &'static str,
+    pub status: ArchStatus,
+    pub notes: &'static str,
+    /// Quantization types models of this architecture have been run with.
+    /// Other types candle can dequantize usually work too.
+    pub tested_quants: &'static [&'static str],
+}
+
+const COMMON_QUANTS: &[&str] = &["Q4_0", "Q4_K_M", "Q5_K_M", "Q6_K", "Q8_0", "F16"];
+
+static ARCHITECTURES: &[ArchInfo] = &[
+    ArchInfo {
+        name: "llama",
+        status: ArchStatus::Supported,
+        notes: "LLaMA 1-3 and fine-tunes; Mistral and Mixtral releases that declare llama",
+        tested_quants: COMMON_QUANTS,
+    },
+    ArchInfo {
+        name: "lfm2",
+        status: ArchStatus::Supported,
+        notes: "Liquid LFM2 and LFM2.5 hybrid convolution/attention models",
+        tested_quants: &["Q4_K_M", "Q8_0", "F16"],
+    },
+    ArchInfo {
+        name: "qwen2",
+        status: ArchStatus::Supported,
+        notes: "Qwen2 and Qwen2.5, including Coder and Math builds",
+        tested_quants: COMMON_QUANTS,
+    },
+    ArchInfo {
+        name: "qwen3",
+        status: ArchStatus::Supported,
+        notes: "Dense Qwen3; thinking builds get the thinking preset",
+        tested_quants: &["Q4_K_M", "Q6_K", "Q8_0"],
+    },
+    ArchInfo {
+        name: "qwen35",
+        status: ArchStatus::Supported,
+        notes: "Qwen3.5; built-in implementation with per-layer --debug-activations",
+        tested_quants: &["Q4_K_M", "Q8_0"],
+    },
+    ArchInfo {
+        name: "qwen2moe",
+        status: ArchStatus::Unsupported,
+        notes: "Mixture-of-experts layers are not implemented",
+        tested_quants: &[],
+    },
+    ArchInfo {
+        name: "qwen3moe",
+        status: ArchStatus::Unsupported,
+        notes: "Mixture-of-experts layers are not implemented",
+        tested_quants: &[],
+    },
+    ArchInfo {
+        name: "gemma",
+        status: ArchStatus::Unsupported,
+        notes: "Gemma's embedding scaling and norms differ from LLaMA",
+        tested_quants: &[],
+    },
+    ArchInfo {
+        name: "gemma2",
+        status: ArchStatus::Unsupported,
+        notes: "Needs logit soft-capping and sliding-window attention",
+        tested_quants: &[],
+    },
+    ArchInfo {
+        name: "gemma3",
+        status: ArchStatus::Unsupported,
+        notes: "Needs sliding-window attention and QK norms",
+        tested_quants: &[],
+    },
+    ArchInfo {
+        name: "phi3",
+        status: ArchStatus::Unsupported,
+        notes: "Fused QKV and gate/up tensors do not match the LLaMA layout",
+        tested_quants: &[],
+    },
+    ArchInfo {
+        name: "mamba",
+        status: ArchStatus::Unsupported,
+        notes: "State-space models have no attention layers",
+        tested_quants: &[],
+    },
+];
+
+/// Every architecture oxide-rs knows about, supported or not. Architectures
+/// missing from the list are loaded with the LLaMA implementation, which
+/// only finds their weights if they use LLaMA's metadata keys and tensor
+/// names.
+pub fn supported_architectures() -> &'static [ArchInfo] {
+    ARCHITECTURES
+}
+
+/// The row for `general.architecture` value `name`, if known.
+pub fn architecture_info(name: &str) -> Option<&'static ArchInfo> {
+    ARCHITECTURES.iter().find(|arch| arch.name == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{architecture_info, supported_architectures, ArchStatus};
+    use crate::model::check::SUPPORTED_ARCHITECTURES;
+
+    #[test]
+    fn supported_architectures_match_the_loader() {
+        let supported: Vec<&str> = supported_architectures()
+            .iter()
+            .filter(|arch| arch.status == ArchStatus::Supported)
+            .map(|arch| arch.name)
+            .collect();
+        assert_eq!(supported, SUPPORTED_ARCHITECTURES);
+        assert!(architecture_info("gemma2").is_some());
+        assert_eq!(architecture_info("falcon"), None);
+    }
+}
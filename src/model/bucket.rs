@@ -0,0 +1,145 @@
+//! `s3://` and `gs://` model sources, built with the `object-store` feature.
+//!
+//! Objects are downloaded into the same cache as HTTP(S) models, with the
+//! same resume and checksum handling. Credentials come from the usual
+//! chains: for S3, `AWS_ACCESS_KEY_ID`/`AWS_SECRET_ACCESS_KEY`, a web
+//! identity token, ECS task credentials or instance metadata, with the
+//! region from `AWS_REGION` and a custom endpoint from `AWS_ENDPOINT`; for
+//! GCS, `GOOGLE_APPLICATION_CREDENTIALS`, gcloud's application default
+//! credentials or the metadata server.
+
+use std::io::{self, Read};
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use futures_util::stream::{BoxStream, StreamExt};
+use object_store::aws::AmazonS3Builder;
+use object_store::gcp::GoogleCloudStorageBuilder;
+use object_store::path::Path as ObjectPath;
+use object_store::{GetOptions, GetRange, ObjectStore};
+use tokio::runtime::Runtime;
+
+use crate::model::download::DownloadProgress;
+use crate::model::remote::{cached_download, Body};
+
+/// Downloads the object at an `s3://bucket/key` or `gs://bucket/key` URL
+/// into the local cache and returns its path; see
+/// [`remote::fetch_model`](super::remote::fetch_model) for caching, resume
+/// and `sha256`.
+pub fn fetch_model<F>(url: &str, sha256: Option<&str>, progress: F) -> Result<PathBuf>
+where
+    F: FnMut(DownloadProgress),
+{
+    let (scheme, bucket, key) = parse_url(url)?;
+    let store: Box<dyn ObjectStore> = match scheme {
+        "s3" => Box::new(
+            AmazonS3Builder::from_env()
+                .with_bucket_name(bucket)
+                .build()?,
+        ),
+        _ => Box::new(
+            GoogleCloudStorageBuilder::from_env()
+                .with_bucket_name(bucket)
+                .build()?,
+        ),
+    };
+    let location = ObjectPath::from(key);
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+
+    cached_download(url, sha256, progress, move |offset| {
+        let size = runtime
+            .block_on(store.head(&location))
+            .with_context(|| format!("Failed to read {}", url))?
+            .size as u64;
+        if offset > 0 && offset == size {
+            return Ok(Body {
+                reader: Box::new(io::empty()),
+                resumed: true,
+                remaining: 0,
+            });
+        }
+        // A partial file longer than the object is stale; start over.
+        let start = if offset < size { offset } else { 0 };
+        let options = GetOptions {
+            range: (start > 0).then_some(GetRange::Offset(start as usize)),
+            ..Default::default()
+        };
+        let result = runtime
+            .block_on(store.get_opts(&location, options))
+            .with_context(|| format!("Failed to download {}", url))?;
+        let stream = result
+            .into_stream()
+            .map(|chunk| chunk.map(|bytes| bytes.to_vec()))
+            .boxed();
+        Ok(Body {
+            reader: Box::new(StreamReader {
+                runtime,
+                stream,
+                chunk: Vec::new(),
+                pos: 0,
+            }),
+            resumed: start == offset,
+            remaining: size - start,
+        })
+    })
+}
+
+/// Splits `s3://bucket/key` into its scheme, bucket and key.
+fn parse_url(url: &str) -> Result<(&str, &str, &str)> {
+    let (scheme, rest) = url
+        .split_once("://")
+        .filter(|(scheme, _)| matches!(*scheme, "s3" | "gs"))
+        .with_context(|| format!("Not an s3:// or gs:// URL: {}", url))?;
+    match rest.split_once('/') {
+        Some((bucket, key)) if !bucket.is_empty() && !key.is_empty() => Ok((scheme, bucket, key)),
+        _ => anyhow::bail!("Expected {}://<bucket>/<key>, got {}", scheme, url),
+    }
+}
+
+/// Blocking reads over an object's byte stream.
+struct StreamReader {
+    runtime: Runtime,
+    stream: BoxStream<'static, object_store::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl Read for StreamReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.chunk.len() {
+            match self.runtime.block_on(self.stream.next()) {
+                Some(Ok(chunk)) => {
+                    self.chunk = chunk;
+                    self.pos = 0;
+                }
+                Some(Err(e)) => return Err(io::Error::new(io::ErrorKind::Other, e)),
+                None => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.chunk.len() - self.pos);
+        buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_url;
+
+    #[test]
+    fn parses_bucket_urls() {
+        assert_eq!(
+            parse_url("s3://models/llama/q4.gguf").unwrap(),
+            ("s3", "models", "llama/q4.gguf")
+        );
+        assert_eq!(
+            parse_url("gs://team-models/q4.gguf").unwrap(),
+            ("gs", "team-models", "q4.gguf")
+        );
+        assert!(parse_url("s3://models").is_err());
+        assert!(parse_url("https://models/q4.gguf").is_err());
+    }
+}
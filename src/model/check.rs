@@ -0,0 +1,474 @@
+//! Preflight compatibility check for GGUF files (`oxide-rs check`).
+//!
+//! Reads only the GGUF header and tokenizer, so an incompatible file is
+//! reported in seconds instead of failing halfway through loading weights.
+
+use std::fs::File;
+use std::io::{Cursor, Read, Seek};
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use candle_core::quantized::gguf_file;
+use memmap2::Mmap;
+
+use crate::inference::{ChatTemplate, Message};
+use crate::model::archs::{architecture_info, ArchStatus};
+use crate::model::download::format_size;
+use crate::model::loader::{fit_context_length, QuantizationInfo};
+use crate::model::remote::RangeReader;
+use crate::model::TokenizerWrapper;
+
+/// Architectures with a dedicated implementation; anything else is loaded
+/// with the LLaMA implementation. See
+/// [`supported_architectures`](crate::model::archs::supported_architectures)
+/// for what that works for.
+pub const SUPPORTED_ARCHITECTURES: &[&str] = &["llama", "lfm2", "qwen2", "qwen3", "qwen35"];
+
+/// `tokenizer.ggml.model` values the tokenizer can load.
+const SUPPORTED_TOKENIZERS: &[&str] = &[
+    "llama", "mistral", "gemma", "gpt2", "qwen", "qwen2", "bert", "wpm", "rwkv", "t5", "ugm",
+    "plamo2",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+#[derive(Clone, Debug)]
+pub struct CheckItem {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub detail: String,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CheckReport {
+    pub items: Vec<CheckItem>,
+}
+
+impl CheckReport {
+    /// `true` when no check failed (warnings are allowed).
+    pub fn passed(&self) -> bool {
+        self.failures() == 0
+    }
+
+    pub fn failures(&self) -> usize {
+        self.count(CheckStatus::Fail)
+    }
+
+    pub fn warnings(&self) -> usize {
+        self.count(CheckStatus::Warn)
+    }
+
+    fn count(&self, status: CheckStatus) -> usize {
+        self.items.iter().filter(|i| i.status == status).count()
+    }
+
+    fn push(&mut self, name: &'static str, status: CheckStatus, detail: impl Into<String>) {
+        self.items.push(CheckItem {
+            name,
+            status,
+            detail: detail.into(),
+        });
+    }
+}
+
+/// Runs every preflight check against the GGUF file at `path`.
+///
+/// Only I/O errors opening the file are returned as `Err`; everything else is
+/// reported as a failed check so the report is always complete.
+pub fn check_model(path: &Path) -> Result<CheckReport> {
+    let file_size = std::fs::metadata(path)?.len();
+    let file = File::open(path)?;
+    let mmap = unsafe { Mmap::map(&file)? };
+    Ok(check_gguf(&mut Cursor::new(&mmap), file_size, Some(path)))
+}
+
+/// Runs the preflight checks against a GGUF file at an HTTP(S) URL, reading
+/// only its header with range requests. The tokenizer is not test-encoded,
+/// since building it needs a local file.
+pub fn check_remote_model(url: &str) -> Result<CheckReport> {
+    let mut reader = RangeReader::open(url)?;
+    let file_size = reader.len();
+    Ok(check_gguf(&mut reader, file_size, None))
+}
+
+/// The checks behind [`check_model`]; `path` is the local file the tokenizer
+/// is built from, when there is one.
+fn check_gguf<R: Read + Seek>(reader: &mut R, file_size: u64, path: Option<&Path>) -> CheckReport {
+    let mut report = CheckReport::default();
+    let content = match gguf_file::Content::read(reader) {
+        Ok(content) => {
+            report.push(
+                "GGUF header",
+                CheckStatus::Pass,
+                format!(
+                    "{} tensors, {} metadata keys",
+                    content.tensor_infos.len(),
+                    content.metadata.len()
+                ),
+            );
+            content
+        }
+        Err(e) => {
+            // candle rejects tensor types it cannot dequantize while parsing
+            // the header, so this is also where unsupported quants surface.
+            report.push("GGUF header", CheckStatus::Fail, e.to_string());
+            return report;
+        }
+    };
+    let md = &content.metadata;
+
+    let arch = md
+        .get("general.architecture")
+        .and_then(|v| v.to_string().ok().cloned())
+        .unwrap_or_else(|| "llama".to_string());
+    let implementation = match architecture_info(&arch) {
+        Some(info) if info.status == ArchStatus::Supported => {
+            report.push("Architecture", CheckStatus::Pass, arch.clone());
+            arch.clone()
+        }
+        Some(info) => {
+            report.push(
+                "Architecture",
+                CheckStatus::Fail,
+                format!("{} is not supported: {}", arch, info.notes),
+            );
+            return report;
+        }
+        None => {
+            report.push(
+                "Architecture",
+                CheckStatus::Warn,
+                format!("{} has no dedicated implementation; loading as llama", arch),
+            );
+            "llama".to_string()
+        }
+    };
+
+    let missing: Vec<String> = required_keys(&implementation)
+        .iter()
+        .map(|suffix| format!("{}.{}", implementation, suffix))
+        .filter(|key| !md.contains_key(key))
+        .collect();
+    if missing.is_empty() {
+        report.push(
+            "Metadata",
+            CheckStatus::Pass,
+            format!(
+                "all {} required keys present",
+                required_keys(&implementation).len()
+            ),
+        );
+    } else {
+        report.push(
+            "Metadata",
+            CheckStatus::Fail,
+            format!("missing {}", missing.join(", ")),
+        );
+    }
+
+    let quant = QuantizationInfo::from_content(&content);
+    let mix: Vec<String> = quant
+        .type_mix()
+        .iter()
+        .map(|(dtype, tensors, _)| format!("{} x{}", dtype, tensors))
+        .collect();
+    let bpw = quant
+        .bits_per_weight()
+        .map(|bpw| format!("{:.2} bpw: ", bpw))
+        .unwrap_or_default();
+    report.push(
+        "Quantization",
+        CheckStatus::Pass,
+        format!("{}{}", bpw, mix.join(", ")),
+    );
+
+    check_tokenizer(&mut report, path, md);
+    check_chat_template(&mut report, md);
+
+    match estimate_memory(md, &implementation, file_size) {
+        Some(estimate) => {
+            let detail = format!(
+                "~{} ({} weights + {} KV cache at {} tokens)",
+                format_size(estimate.total()),
+                format_size(estimate.weights),
+                format_size(estimate.kv_cache),
+                estimate.context_length
+            );
+            match available_memory() {
+                Some(available) if estimate.weights >= available => report.push(
+                    "Memory",
+                    CheckStatus::Fail,
+                    format!("{}, only {} available", detail, format_size(available)),
+                ),
+                Some(available) => {
+                    let fitted = fit_context_length(
+                        estimate.context_length,
+                        estimate.kv_cache / estimate.context_length.max(1) as u64,
+                        estimate.weights,
+                        available,
+                    );
+                    if fitted < estimate.context_length {
+                        report.push(
+                            "Memory",
+                            CheckStatus::Warn,
+                            format!(
+                                "{}, only {} available; context will be capped to {} tokens",
+                                detail,
+                                format_size(available),
+                                fitted
+                            ),
+                        )
+                    } else {
+                        report.push(
+                            "Memory",
+                            CheckStatus::Pass,
+                            format!("{}, {} available", detail, format_size(available)),
+                        )
+                    }
+                }
+                None => report.push("Memory", CheckStatus::Pass, detail),
+            }
+        }
+        None => report.push(
+            "Memory",
+            CheckStatus::Warn,
+            "cannot estimate without attention metadata",
+        ),
+    }
+
+    report
+}
+
+/// Metadata keys (without the architecture prefix) each implementation reads
+/// unconditionally.
+fn required_keys(arch: &str) -> &'static [&'static str] {
+    match arch {
+        "lfm2" => &[
+            "attention.head_count",
+            "attention.head_count_kv",
+            "embedding_length",
+            "context_length",
+            "block_count",
+            "attention.layer_norm_rms_epsilon",
+            "shortconv.l_cache",
+        ],
+        "qwen2" => &[
+            "attention.head_count",
+            "attention.head_count_kv",
+            "embedding_length",
+            "context_length",
+            "block_count",
+            "attention.layer_norm_rms_epsilon",
+        ],
+        "qwen3" => &[
+            "attention.head_count",
+            "attention.head_count_kv",
+            "attention.key_length",
+            "block_count",
+            "embedding_length",
+            "context_length",
+            "attention.layer_norm_rms_epsilon",
+            "rope.freq_base",
+        ],
+        "qwen35" => &[
+            "attention.head_count",
+            "attention.head_count_kv",
+            "block_count",
+            "embedding_length",
+            "attention.key_length",
+            "context_length",
+            "attention.layer_norm_rms_epsilon",
+            "rope.freq_base",
+            "full_attention_interval",
+            "ssm.group_count",
+        ],
+        _ => &[
+            "attention.head_count",
+            "attention.head_count_kv",
+            "block_count",
+            "embedding_length",
+            "rope.dimension_count",
+            "attention.layer_norm_rms_epsilon",
+        ],
+    }
+}
+
+fn check_tokenizer(
+    report: &mut CheckReport,
+    path: Option<&Path>,
+    md: &std::collections::HashMap<String, gguf_file::Value>,
+) {
+    let model = md
+        .get("tokenizer.ggml.model")
+        .and_then(|v| v.to_string().ok().cloned());
+    let Some(model) = model else {
+        report.push(
+            "Tokenizer",
+            CheckStatus::Fail,
+            "no tokenizer.ggml.model in metadata",
+        );
+        return;
+    };
+    if !SUPPORTED_TOKENIZERS.contains(&model.as_str()) {
+        report.push(
+            "Tokenizer",
+            CheckStatus::Fail,
+            format!("unsupported tokenizer type {}", model),
+        );
+        return;
+    }
+
+    let Some(path) = path else {
+        report.push(
+            "Tokenizer",
+            CheckStatus::Pass,
+            format!("{} (not test-encoded for a remote file)", model),
+        );
+        return;
+    };
+    let result = TokenizerWrapper::from_file(&PathBuf::from(path))
+        .and_then(|tokenizer| tokenizer.encode("Hello, world!").map(|t| t.len()));
+    match result {
+        Ok(tokens) => report.push(
+            "Tokenizer",
+            CheckStatus::Pass,
+            format!("{} (sample encodes to {} tokens)", model, tokens),
+        ),
+        Err(e) => report.push("Tokenizer", CheckStatus::Fail, format!("{}: {}", model, e)),
+    }
+}
+
+fn check_chat_template(
+    report: &mut CheckReport,
+    md: &std::collections::HashMap<String, gguf_file::Value>,
+) {
+    let source = md
+        .get("tokenizer.chat_template")
+        .and_then(|v| v.to_string().ok().cloned());
+    if source.is_none() {
+        report.push(
+            "Chat template",
+            CheckStatus::Fail,
+            "no tokenizer.chat_template embedded; chat generation needs one",
+        );
+        return;
+    }
+
+    let template = match ChatTemplate::new(source) {
+        Ok(template) => template,
+        Err(e) => {
+            report.push("Chat template", CheckStatus::Fail, format!("parse: {}", e));
+            return;
+        }
+    };
+
+    let user = Message::new("user", "Hello");
+    let with_system = [Message::new("system", "You are helpful."), user.clone()];
+    match template.apply(&with_system, true) {
+        Ok(_) => report.push("Chat template", CheckStatus::Pass, "renders"),
+        Err(system_err) => match template.apply(&[user], true) {
+            Ok(_) => report.push(
+                "Chat template",
+                CheckStatus::Warn,
+                format!("renders, but not with a system prompt: {}", system_err),
+            ),
+            Err(e) => report.push("Chat template", CheckStatus::Fail, format!("render: {}", e)),
+        },
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryEstimate {
+    /// Quantized weights, which are copied out of the mmap when loading.
+    pub weights: u64,
+    /// f32 K and V for every layer at the full context length.
+    pub kv_cache: u64,
+    pub context_length: usize,
+}
+
+impl MemoryEstimate {
+    pub fn total(&self) -> u64 {
+        self.weights + self.kv_cache
+    }
+}
+
+fn estimate_memory(
+    md: &std::collections::HashMap<String, gguf_file::Value>,
+    arch: &str,
+    file_size: u64,
+) -> Option<MemoryEstimate> {
+    let get = |suffix: &str| {
+        md.get(&format!("{}.{}", arch, suffix))
+            .and_then(|v| v.to_u64().ok())
+            .map(|n| n as usize)
+    };
+    let head_count = get("attention.head_count")?;
+    let head_dim = get("attention.key_length").or_else(|| {
+        get("embedding_length")
+            .filter(|_| head_count > 0)
+            .map(|n| n / head_count)
+    })?;
+    Some(kv_estimate(
+        file_size,
+        get("block_count")?,
+        get("attention.head_count_kv").unwrap_or(head_count),
+        head_dim,
+        get("context_length").unwrap_or(4096),
+    ))
+}
+
+fn kv_estimate(
+    weights: u64,
+    n_layer: usize,
+    kv_heads: usize,
+    head_dim: usize,
+    context_length: usize,
+) -> MemoryEstimate {
+    let per_token = kv_cache_bytes_per_token(n_layer, kv_heads, head_dim);
+    MemoryEstimate {
+        weights,
+        kv_cache: per_token * context_length as u64,
+        context_length,
+    }
+}
+
+/// f32 keys and values of one token across all layers.
+pub(crate) fn kv_cache_bytes_per_token(n_layer: usize, kv_heads: usize, head_dim: usize) -> u64 {
+    (2 * n_layer * kv_heads * head_dim * std::mem::size_of::<f32>()) as u64
+}
+
+/// Memory available to new allocations, where the platform reports it.
+pub(crate) fn available_memory() -> Option<u64> {
+    parse_mem_available(&std::fs::read_to_string("/proc/meminfo").ok()?)
+}
+
+fn parse_mem_available(meminfo: &str) -> Option<u64> {
+    let line = meminfo.lines().find(|l| l.starts_with("MemAvailable:"))?;
+    let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{kv_estimate, parse_mem_available};
+
+    #[test]
+    fn estimates_kv_cache_from_attention_shape() {
+        // 2 (K,V) * 28 layers * 8 heads * 128 dim * 4 bytes * 4096 tokens
+        let estimate = kv_estimate(1_000, 28, 8, 128, 4096);
+        assert_eq!(estimate.kv_cache, 2 * 28 * 8 * 128 * 4 * 4096);
+        assert_eq!(estimate.total(), estimate.kv_cache + 1_000);
+    }
+
+    #[test]
+    fn parses_mem_available() {
+        let meminfo = "MemTotal:       16000000 kB\nMemFree:         1000000 kB\nMemAvailable:    8000000 kB\n";
+        assert_eq!(parse_mem_available(meminfo), Some(8_000_000 * 1024));
+        assert_eq!(parse_mem_available("MemTotal: 1 kB"), None);
+    }
+}
@@ -0,0 +1,590 @@
+//! Hugging Face safetensors to GGUF conversion (`oxide-rs convert`).
+//!
+//! Supports the LLaMA/Mistral, Qwen2 and Qwen3 families with a `tokenizer.json`
+//! BPE tokenizer, which covers the architectures the loader runs. Tensor names,
+//! hyperparameters and tokenizer metadata follow llama.cpp's
+//! `convert_hf_to_gguf.py` so the output also works outside oxide-rs.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use candle_core::quantized::{gguf_file, GgmlDType, QTensor};
+use candle_core::safetensors::MmapedSafetensors;
+use candle_core::{DType, Device, Tensor};
+use serde_json::Value as Json;
+
+use crate::model::loader::dtype_name;
+use crate::model::quantize::{target_type, QuantPreset, QuantizeProgress};
+
+use gguf_file::Value;
+
+// llama.cpp token types.
+const TOKEN_NORMAL: i32 = 1;
+const TOKEN_CONTROL: i32 = 3;
+const TOKEN_USER_DEFINED: i32 = 4;
+const TOKEN_UNUSED: i32 = 5;
+const TOKEN_BYTE: i32 = 6;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Arch {
+    Llama,
+    Qwen2,
+    Qwen3,
+}
+
+impl Arch {
+    fn from_config(config: &Json) -> Result<Self> {
+        let name = config["architectures"][0].as_str().unwrap_or_default();
+        Ok(match name {
+            "LlamaForCausalLM" | "MistralForCausalLM" => Arch::Llama,
+            "Qwen2ForCausalLM" => Arch::Qwen2,
+            "Qwen3ForCausalLM" => Arch::Qwen3,
+            "" => anyhow::bail!("config.json has no architectures entry"),
+            other => anyhow::bail!(
+                "Unsupported architecture {} (supported: Llama, Mistral, Qwen2, Qwen3)",
+                other
+            ),
+        })
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Arch::Llama => "llama",
+            Arch::Qwen2 => "qwen2",
+            Arch::Qwen3 => "qwen3",
+        }
+    }
+}
+
+struct HParams {
+    n_layer: usize,
+    n_embd: usize,
+    n_ff: usize,
+    n_head: usize,
+    n_head_kv: usize,
+    head_dim: usize,
+    context_length: usize,
+    vocab_size: usize,
+    rms_norm_eps: f32,
+    rope_theta: f32,
+}
+
+impl HParams {
+    fn from_config(config: &Json) -> Result<Self> {
+        let get = |key: &str| {
+            config[key]
+                .as_u64()
+                .map(|v| v as usize)
+                .ok_or_else(|| anyhow::anyhow!("config.json is missing {}", key))
+        };
+        let n_embd = get("hidden_size")?;
+        let n_head = get("num_attention_heads")?;
+        Ok(Self {
+            n_layer: get("num_hidden_layers")?,
+            n_embd,
+            n_ff: get("intermediate_size")?,
+            n_head,
+            n_head_kv: get("num_key_value_heads").unwrap_or(n_head),
+            head_dim: get("head_dim").unwrap_or(n_embd / n_head),
+            context_length: get("max_position_embeddings").unwrap_or(4096),
+            vocab_size: get("vocab_size")?,
+            rms_norm_eps: config["rms_norm_eps"].as_f64().unwrap_or(1e-5) as f32,
+            rope_theta: config["rope_theta"].as_f64().unwrap_or(10_000.0) as f32,
+        })
+    }
+}
+
+/// Converts the Hugging Face model in `model_dir` (config.json, tokenizer
+/// files and `*.safetensors`) to a GGUF file at `output`.
+///
+/// Returns the size of the written file. Like `quantize`, the converted
+/// tensors are held in memory until the file is written.
+pub fn convert_safetensors<F>(
+    model_dir: &Path,
+    output: &Path,
+    preset: QuantPreset,
+    mut progress: F,
+) -> Result<u64>
+where
+    F: FnMut(QuantizeProgress),
+{
+    let config = read_json(&model_dir.join("config.json"))?;
+    let arch = Arch::from_config(&config)?;
+    let hparams = HParams::from_config(&config)?;
+
+    let mut metadata = model_metadata(arch, &hparams, preset);
+    let name = model_dir
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("model");
+    metadata.push(("general.name".into(), Value::String(name.into())));
+    metadata.extend(tokenizer_metadata(
+        model_dir,
+        arch,
+        &config,
+        hparams.vocab_size,
+    )?);
+
+    let files = safetensors_files(model_dir)?;
+    let st = unsafe { MmapedSafetensors::multi(&files)? };
+    let mut names: Vec<String> = st.tensors().into_iter().map(|(name, _)| name).collect();
+    names.sort();
+
+    let device = Device::Cpu;
+    let mut tensors: Vec<(String, QTensor)> = Vec::with_capacity(names.len());
+    let total = names.len();
+    for (index, hf_name) in names.iter().enumerate() {
+        let Some(name) = gguf_tensor_name(hf_name) else {
+            continue;
+        };
+        let mut tensor = st.load(hf_name, &device)?.to_dtype(DType::F32)?;
+        if arch == Arch::Llama {
+            if name.ends_with("attn_q.weight") {
+                tensor = unpermute_rope(&tensor, hparams.n_head)?;
+            } else if name.ends_with("attn_k.weight") {
+                tensor = unpermute_rope(&tensor, hparams.n_head_kv)?;
+            }
+        }
+
+        let target = target_type(
+            tensor.dims(),
+            GgmlDType::F32,
+            preset.tensor_type(&name, hparams.n_layer),
+        );
+        progress(QuantizeProgress {
+            index,
+            total,
+            name: &name,
+            from: "safetensors",
+            to: dtype_name(target),
+        });
+        let quantized = QTensor::quantize(&tensor, target)
+            .with_context(|| format!("Failed to quantize {} to {:?}", name, target))?;
+        tensors.push((name, quantized));
+    }
+    if !tensors.iter().any(|(name, _)| name == "token_embd.weight") {
+        anyhow::bail!("No embedding tensor found; is this a supported model directory?");
+    }
+
+    let metadata_refs: Vec<(&str, &Value)> =
+        metadata.iter().map(|(k, v)| (k.as_str(), v)).collect();
+    let tensor_refs: Vec<(&str, &QTensor)> = tensors.iter().map(|(n, t)| (n.as_str(), t)).collect();
+    let out = File::create(output).with_context(|| format!("Failed to create {:?}", output))?;
+    let mut writer = BufWriter::new(out);
+    gguf_file::write(&mut writer, &metadata_refs, &tensor_refs)?;
+    drop(writer);
+
+    Ok(std::fs::metadata(output)?.len())
+}
+
+fn model_metadata(arch: Arch, hp: &HParams, preset: QuantPreset) -> Vec<(String, Value)> {
+    let a = arch.as_str();
+    let u32v = |v: usize| Value::U32(v as u32);
+    let mut md = vec![
+        ("general.architecture".to_string(), Value::String(a.into())),
+        ("general.file_type".into(), Value::U32(preset.file_type())),
+        ("general.quantization_version".into(), Value::U32(2)),
+        (format!("{a}.context_length"), u32v(hp.context_length)),
+        (format!("{a}.embedding_length"), u32v(hp.n_embd)),
+        (format!("{a}.block_count"), u32v(hp.n_layer)),
+        (format!("{a}.feed_forward_length"), u32v(hp.n_ff)),
+        (format!("{a}.attention.head_count"), u32v(hp.n_head)),
+        (format!("{a}.attention.head_count_kv"), u32v(hp.n_head_kv)),
+        (
+            format!("{a}.attention.layer_norm_rms_epsilon"),
+            Value::F32(hp.rms_norm_eps),
+        ),
+        (format!("{a}.rope.freq_base"), Value::F32(hp.rope_theta)),
+        (format!("{a}.vocab_size"), u32v(hp.vocab_size)),
+    ];
+    match arch {
+        Arch::Llama => md.push((format!("{a}.rope.dimension_count"), u32v(hp.head_dim))),
+        Arch::Qwen2 => {}
+        Arch::Qwen3 => {
+            md.push((format!("{a}.attention.key_length"), u32v(hp.head_dim)));
+            md.push((format!("{a}.attention.value_length"), u32v(hp.head_dim)));
+        }
+    }
+    md
+}
+
+/// Maps a Hugging Face tensor name to its GGUF name, or `None` for tensors
+/// the GGUF does not store (e.g. rotary `inv_freq` buffers).
+fn gguf_tensor_name(hf: &str) -> Option<String> {
+    match hf {
+        "model.embed_tokens.weight" => return Some("token_embd.weight".into()),
+        "model.norm.weight" => return Some("output_norm.weight".into()),
+        "lm_head.weight" => return Some("output.weight".into()),
+        _ => {}
+    }
+
+    let rest = hf.strip_prefix("model.layers.")?;
+    let (layer, rest) = rest.split_once('.')?;
+    let layer: usize = layer.parse().ok()?;
+    let (module, suffix) = rest.rsplit_once('.')?;
+    let mapped = match module {
+        "input_layernorm" => "attn_norm",
+        "post_attention_layernorm" => "ffn_norm",
+        "self_attn.q_proj" => "attn_q",
+        "self_attn.k_proj" => "attn_k",
+        "self_attn.v_proj" => "attn_v",
+        "self_attn.o_proj" => "attn_output",
+        "self_attn.q_norm" => "attn_q_norm",
+        "self_attn.k_norm" => "attn_k_norm",
+        "mlp.gate_proj" => "ffn_gate",
+        "mlp.up_proj" => "ffn_up",
+        "mlp.down_proj" => "ffn_down",
+        _ => return None,
+    };
+    Some(format!("blk.{}.{}.{}", layer, mapped, suffix))
+}
+
+/// Undoes the Hugging Face rotary layout of LLaMA Q/K projections so they
+/// match the interleaved layout GGUF LLaMA models use.
+fn unpermute_rope(weight: &Tensor, n_head: usize) -> Result<Tensor> {
+    let (rows, cols) = weight.dims2()?;
+    Ok(weight
+        .reshape((n_head, 2, rows / n_head / 2, cols))?
+        .transpose(1, 2)?
+        .contiguous()?
+        .reshape((rows, cols))?)
+}
+
+fn safetensors_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let index = dir.join("model.safetensors.index.json");
+    let mut files: Vec<PathBuf> = if index.exists() {
+        let index = read_json(&index)?;
+        index["weight_map"]
+            .as_object()
+            .map(|map| {
+                map.values()
+                    .filter_map(Json::as_str)
+                    .map(|f| dir.join(f))
+                    .collect()
+            })
+            .unwrap_or_default()
+    } else {
+        std::fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|p| p.extension().is_some_and(|ext| ext == "safetensors"))
+            .collect()
+    };
+    files.sort();
+    files.dedup();
+    if files.is_empty() {
+        anyhow::bail!("No .safetensors files found in {}", dir.display());
+    }
+    Ok(files)
+}
+
+/// Builds the `tokenizer.ggml.*` metadata and chat template from
+/// `tokenizer.json` and `tokenizer_config.json`.
+fn tokenizer_metadata(
+    dir: &Path,
+    arch: Arch,
+    config: &Json,
+    vocab_size: usize,
+) -> Result<Vec<(String, Value)>> {
+    let tokenizer = read_json(&dir.join("tokenizer.json"))?;
+    let tokenizer_config = read_json(&dir.join("tokenizer_config.json")).unwrap_or(Json::Null);
+    let model = &tokenizer["model"];
+    if model["type"].as_str() != Some("BPE") {
+        anyhow::bail!("Only BPE tokenizer.json files are supported");
+    }
+    let byte_fallback = model["byte_fallback"].as_bool().unwrap_or(false);
+
+    let mut entries: HashMap<usize, (String, i32)> = HashMap::new();
+    if let Some(vocab) = model["vocab"].as_object() {
+        for (token, id) in vocab {
+            if let Some(id) = id.as_u64() {
+                let kind = if byte_fallback && is_byte_token(token) {
+                    TOKEN_BYTE
+                } else {
+                    TOKEN_NORMAL
+                };
+                entries.insert(id as usize, (token.clone(), kind));
+            }
+        }
+    }
+    let mut special: HashMap<String, usize> = HashMap::new();
+    for added in tokenizer["added_tokens"].as_array().into_iter().flatten() {
+        let (Some(id), Some(content)) = (added["id"].as_u64(), added["content"].as_str()) else {
+            continue;
+        };
+        let kind = if added["special"].as_bool().unwrap_or(false) {
+            TOKEN_CONTROL
+        } else {
+            TOKEN_USER_DEFINED
+        };
+        entries.insert(id as usize, (content.to_string(), kind));
+        special.insert(content.to_string(), id as usize);
+    }
+
+    let count = entries
+        .keys()
+        .max()
+        .map_or(0, |max| max + 1)
+        .max(vocab_size);
+    let mut tokens = Vec::with_capacity(count);
+    let mut types = Vec::with_capacity(count);
+    for id in 0..count {
+        let (token, kind) = entries
+            .remove(&id)
+            .unwrap_or_else(|| (format!("[PAD{}]", id), TOKEN_UNUSED));
+        tokens.push(Value::String(token));
+        types.push(Value::I32(kind));
+    }
+
+    let mut md = vec![
+        ("tokenizer.ggml.tokens".to_string(), Value::Array(tokens)),
+        ("tokenizer.ggml.token_type".into(), Value::Array(types)),
+    ];
+
+    if byte_fallback {
+        md.push(("tokenizer.ggml.model".into(), Value::String("llama".into())));
+        let scores = (0..count).map(|id| Value::F32(-(id as f32))).collect();
+        md.push(("tokenizer.ggml.scores".into(), Value::Array(scores)));
+    } else {
+        md.push(("tokenizer.ggml.model".into(), Value::String("gpt2".into())));
+        let pre = match arch {
+            Arch::Llama => "llama-bpe",
+            Arch::Qwen2 | Arch::Qwen3 => "qwen2",
+        };
+        md.push(("tokenizer.ggml.pre".into(), Value::String(pre.into())));
+        let merges = model["merges"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|merge| match merge {
+                Json::String(s) => Some(s.clone()),
+                Json::Array(pair) => Some(format!(
+                    "{} {}",
+                    pair.first()?.as_str()?,
+                    pair.get(1)?.as_str()?
+                )),
+                _ => None,
+            })
+            .map(Value::String)
+            .collect();
+        md.push(("tokenizer.ggml.merges".into(), Value::Array(merges)));
+    }
+
+    let token_id = |key: &str| -> Option<u32> {
+        let content = match &tokenizer_config[key] {
+            Json::String(s) => Some(s.as_str()),
+            Json::Object(o) => o.get("content").and_then(Json::as_str),
+            _ => None,
+        };
+        content
+            .and_then(|c| special.get(c).map(|&id| id as u32))
+            .or_else(|| {
+                let id = &config[format!("{}_id", key)];
+                id.as_u64()
+                    .or_else(|| id.get(0).and_then(Json::as_u64))
+                    .map(|id| id as u32)
+            })
+    };
+    for (key, gguf_key) in [
+        ("bos_token", "tokenizer.ggml.bos_token_id"),
+        ("eos_token", "tokenizer.ggml.eos_token_id"),
+        ("pad_token", "tokenizer.ggml.padding_token_id"),
+    ] {
+        if let Some(id) = token_id(key) {
+            md.push((gguf_key.into(), Value::U32(id)));
+        }
+    }
+    if let Some(add_bos) = tokenizer_config["add_bos_token"].as_bool() {
+        md.push(("tokenizer.ggml.add_bos_token".into(), Value::Bool(add_bos)));
+    }
+
+    if let Some(template) = chat_template(dir, &tokenizer_config) {
+        md.push(("tokenizer.chat_template".into(), Value::String(template)));
+    }
+    Ok(md)
+}
+
+/// Chat template from `tokenizer_config.json` (a string, or a list of named
+/// templates), falling back to a standalone `chat_template.jinja`.
+fn chat_template(dir: &Path, tokenizer_config: &Json) -> Option<String> {
+    match &tokenizer_config["chat_template"] {
+        Json::String(s) => return Some(s.clone()),
+        Json::Array(named) => {
+            let pick = named
+                .iter()
+                .find(|t| t["name"] == "default")
+                .or_else(|| named.first());
+            if let Some(template) = pick.and_then(|t| t["template"].as_str()) {
+                return Some(template.to_string());
+            }
+        }
+        _ => {}
+    }
+    std::fs::read_to_string(dir.join("chat_template.jinja")).ok()
+}
+
+fn is_byte_token(token: &str) -> bool {
+    token.len() == 6 && token.starts_with("<0x") && token.ends_with('>')
+}
+
+fn read_json(path: &Path) -> Result<Json> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    serde_json::from_str(&text).with_context(|| format!("Invalid JSON in {}", path.display()))
+}
+
+/// Converts a one-layer LLaMA with random weights and an 8-token
+/// vocabulary (`a`, `b`, `c`, `ab`, `Ġ`, `<s>`, `</s>`, `.`) into `dir`, for
+/// tests that need a real model. Its chat template concatenates the
+/// messages.
+#[cfg(test)]
+pub(crate) fn tiny_llama(dir: &Path) -> PathBuf {
+    use serde_json::json;
+
+    let (hidden, ff, vocab) = (64usize, 128usize, 8usize);
+
+    let config = json!({
+        "architectures": ["LlamaForCausalLM"],
+        "hidden_size": hidden, "intermediate_size": ff, "num_hidden_layers": 1,
+        "num_attention_heads": 2, "num_key_value_heads": 2, "vocab_size": vocab,
+        "max_position_embeddings": 512, "rms_norm_eps": 1e-5,
+    });
+    let tokenizer = json!({
+        "model": {
+            "type": "BPE",
+            "vocab": {"a": 0, "b": 1, "c": 2, "ab": 3, "Ġ": 4, ".": 7},
+            "merges": ["a b"],
+        },
+        "added_tokens": [
+            {"id": 5, "content": "<s>", "special": true},
+            {"id": 6, "content": "</s>", "special": true},
+        ],
+    });
+    let tokenizer_config = json!({
+        "bos_token": "<s>", "eos_token": "</s>",
+        "chat_template": "{% for m in messages %}{{ m.content }}{% endfor %}",
+    });
+    std::fs::write(dir.join("config.json"), config.to_string()).unwrap();
+    std::fs::write(dir.join("tokenizer.json"), tokenizer.to_string()).unwrap();
+    std::fs::write(
+        dir.join("tokenizer_config.json"),
+        tokenizer_config.to_string(),
+    )
+    .unwrap();
+
+    let device = Device::Cpu;
+    let rand = |shape: (usize, usize)| {
+        Tensor::randn(0f32, 0.02, shape, &device)
+            .unwrap()
+            .to_dtype(DType::BF16)
+            .unwrap()
+    };
+    let ones = Tensor::ones(hidden, DType::BF16, &device).unwrap();
+    let p = "model.layers.0";
+    let weights: HashMap<String, Tensor> = HashMap::from([
+        ("model.embed_tokens.weight".into(), rand((vocab, hidden))),
+        ("model.norm.weight".into(), ones.clone()),
+        (format!("{p}.input_layernorm.weight"), ones.clone()),
+        (format!("{p}.post_attention_layernorm.weight"), ones),
+        (
+            format!("{p}.self_attn.q_proj.weight"),
+            rand((hidden, hidden)),
+        ),
+        (
+            format!("{p}.self_attn.k_proj.weight"),
+            rand((hidden, hidden)),
+        ),
+        (
+            format!("{p}.self_attn.v_proj.weight"),
+            rand((hidden, hidden)),
+        ),
+        (
+            format!("{p}.self_attn.o_proj.weight"),
+            rand((hidden, hidden)),
+        ),
+        (format!("{p}.mlp.gate_proj.weight"), rand((ff, hidden))),
+        (format!("{p}.mlp.up_proj.weight"), rand((ff, hidden))),
+        (format!("{p}.mlp.down_proj.weight"), rand((hidden, ff))),
+    ]);
+    candle_core::safetensors::save(&weights, dir.join("model.safetensors")).unwrap();
+
+    let output = dir.join("model.gguf");
+    convert_safetensors(dir, &output, QuantPreset::Q8_0, |_| {}).unwrap();
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use candle_core::{Device, Tensor};
+
+    use super::{gguf_tensor_name, tiny_llama, unpermute_rope};
+    use crate::model::{Model, TokenizerWrapper};
+
+    #[test]
+    fn maps_hf_tensor_names() {
+        assert_eq!(
+            gguf_tensor_name("model.layers.3.self_attn.k_proj.bias").as_deref(),
+            Some("blk.3.attn_k.bias")
+        );
+        assert_eq!(
+            gguf_tensor_name("model.layers.0.mlp.down_proj.weight").as_deref(),
+            Some("blk.0.ffn_down.weight")
+        );
+        assert_eq!(
+            gguf_tensor_name("model.layers.0.self_attn.rotary_emb.inv_freq"),
+            None
+        );
+    }
+
+    #[test]
+    fn unpermutes_rotary_halves_into_pairs() {
+        // One head of dim 4: HF rows [r0, r1, r2, r3] hold halves (r0, r1 | r2, r3);
+        // GGUF interleaves them as r0, r2, r1, r3.
+        let w = Tensor::arange(0f32, 4.0, &Device::Cpu)
+            .unwrap()
+            .reshape((4, 1))
+            .unwrap();
+        let out = unpermute_rope(&w, 1).unwrap();
+        assert_eq!(
+            out.flatten_all().unwrap().to_vec1::<f32>().unwrap(),
+            vec![0.0, 2.0, 1.0, 3.0]
+        );
+    }
+
+    #[test]
+    fn converted_llama_loads_and_runs() {
+        let dir = std::env::temp_dir().join(format!("oxide-convert-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let output = tiny_llama(&dir);
+        let vocab = 8;
+
+        assert!(TokenizerWrapper::from_file(&output).is_ok());
+        let mut model = Model::load(&output).unwrap();
+        assert_eq!(model.metadata().architecture, "llama");
+        assert_eq!(model.metadata().vocab_size, vocab);
+        assert!(model.metadata().chat_template.is_some());
+        let logits = model.forward(&[5, 0, 1], 0).unwrap();
+        assert_eq!(logits.dims().last(), Some(&vocab));
+        let chunked = model.forward_chunked(&[5, 0, 1], 0, 2).unwrap();
+        let diff = (logits - chunked)
+            .unwrap()
+            .abs()
+            .unwrap()
+            .max_all()
+            .unwrap()
+            .to_scalar::<f32>()
+            .unwrap();
+        assert!(diff < 1e-4, "chunked prefill diverged by {}", diff);
+        let mut checks = 0;
+        let preempted = model.forward_preemptible(&[5, 0, 1], 0, 2, || {
+            checks += 1;
+            false
+        });
+        assert!(preempted.unwrap().is_none());
+        assert_eq!(checks, 1);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
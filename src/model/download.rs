@@ -208,6 +208,61 @@ where
     Ok((path, target_file))
 }
 
+/// Files `convert` needs from a safetensors repository.
+fn is_conversion_file(name: &str) -> bool {
+    matches!(
+        name,
+        "config.json"
+            | "tokenizer.json"
+            | "tokenizer_config.json"
+            | "chat_template.jinja"
+            | "model.safetensors.index.json"
+    ) || (name.ends_with(".safetensors") && !name.contains('/'))
+}
+
+/// Downloads the config, tokenizer and safetensors weights of `repo_id` and
+/// returns the snapshot directory that holds them.
+pub fn download_safetensors<F>(repo_id: &str, mut progress_callback: F) -> Result<PathBuf>
+where
+    F: FnMut(DownloadProgress),
+{
+    let api = Api::new()?;
+    let repo = api.repo(Repo::with_revision(
+        repo_id.to_string(),
+        hf_hub::RepoType::Model,
+        "main".to_string(),
+    ));
+
+    let files: Vec<RepoFile> = list_repo_files(repo_id)?
+        .into_iter()
+        .filter(|f| is_conversion_file(&f.rfilename))
+        .collect();
+    if !files.iter().any(|f| f.rfilename.ends_with(".safetensors")) {
+        anyhow::bail!("No safetensors weights found in {}", repo_id);
+    }
+
+    let total_bytes: u64 = files.iter().map(|f| f.size).sum();
+    let mut bytes_downloaded = 0;
+    let mut snapshot = None;
+    for file in &files {
+        progress_callback(DownloadProgress {
+            bytes_downloaded,
+            total_bytes,
+            filename: file.rfilename.clone(),
+        });
+        let path = repo.get(&file.rfilename)?;
+        snapshot = path.parent().map(PathBuf::from);
+        bytes_downloaded += file.size;
+    }
+    progress_callback(DownloadProgress {
+        bytes_downloaded,
+        total_bytes,
+        filename: String::new(),
+    });
+
+    snapshot.context("Repository has no files to convert")
+}
+
 pub fn parse_repo_id(input: &str) -> (String, Option<String>) {
     if input.contains('/') && input.ends_with(".gguf") {
         let parts: Vec<&str> = input.split('/').collect();
@@ -0,0 +1,296 @@
+//! GGUF metadata editing (`oxide-rs gguf`).
+//!
+//! Only the header is rewritten: the tensor table is re-emitted unchanged and
+//! the tensor data section is copied byte for byte, so editing a multi-GB file
+//! needs no more memory than its metadata.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Cursor, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use candle_core::quantized::gguf_file::{self, Value};
+use candle_core::quantized::GgmlDType;
+use memmap2::Mmap;
+
+use crate::inference::{ChatTemplate, Message};
+
+const GGUF_MAGIC: u32 = 0x46554747;
+const GGUF_VERSION: u32 = 3;
+
+/// Replaces `tokenizer.chat_template` after checking that `template` parses
+/// and renders a short conversation.
+pub fn set_chat_template(path: &Path, template: &str, output: Option<&Path>) -> Result<PathBuf> {
+    let rendered = ChatTemplate::new(Some(template.to_string()))
+        .and_then(|t| t.apply(&[Message::new("user", "Hello")], true))
+        .context("Template does not render")?;
+    if rendered.trim().is_empty() {
+        anyhow::bail!("Template renders an empty prompt");
+    }
+
+    rewrite_metadata(path, output, |metadata| {
+        metadata.insert(
+            "tokenizer.chat_template".to_string(),
+            Value::String(template.to_string()),
+        );
+        Ok(())
+    })
+}
+
+/// Sets `key` to `value`, keeping the existing value's type (so numbers stay
+/// numbers); new keys are stored as strings.
+pub fn set_key(path: &Path, key: &str, value: &str, output: Option<&Path>) -> Result<PathBuf> {
+    rewrite_metadata(path, output, |metadata| {
+        let parsed = parse_like(metadata.get(key), value)
+            .with_context(|| format!("Invalid value for {}", key))?;
+        metadata.insert(key.to_string(), parsed);
+        Ok(())
+    })
+}
+
+/// Applies `edit` to the metadata of `path` and writes the result to
+/// `output`, or back to `path` (via a temporary file and rename) when `None`.
+/// Returns the path written.
+pub fn rewrite_metadata<F>(path: &Path, output: Option<&Path>, edit: F) -> Result<PathBuf>
+where
+    F: FnOnce(&mut HashMap<String, Value>) -> Result<()>,
+{
+    let output = output.filter(|output| *output != path);
+    let file = File::open(path).with_context(|| format!("Failed to open {:?}", path))?;
+    let mmap = unsafe { Mmap::map(&file)? };
+    let mut content = gguf_file::Content::read(&mut Cursor::new(&mmap))
+        .with_context(|| format!("Failed to read GGUF file: {:?}", path))?;
+    edit(&mut content.metadata)?;
+
+    let alignment = content
+        .metadata
+        .get("general.alignment")
+        .and_then(|v| v.to_u64().ok())
+        .unwrap_or(gguf_file::DEFAULT_ALIGNMENT);
+
+    let target = output.map(Path::to_path_buf).unwrap_or_else(|| path.into());
+    let temp = match output {
+        Some(output) => output.to_path_buf(),
+        None => path.with_extension("gguf.tmp"),
+    };
+
+    let result = (|| -> Result<()> {
+        let mut writer = BufWriter::new(File::create(&temp)?);
+        let header = encode_header(&content)?;
+        let padding = (alignment - header.len() as u64 % alignment) % alignment;
+        writer.write_all(&header)?;
+        writer.write_all(&vec![0u8; padding as usize])?;
+        writer.write_all(&mmap[content.tensor_data_offset as usize..])?;
+        writer.flush()?;
+        Ok(())
+    })();
+    if let Err(e) = result {
+        std::fs::remove_file(&temp).ok();
+        return Err(e);
+    }
+
+    drop(mmap);
+    if temp != target {
+        std::fs::rename(&temp, &target)
+            .with_context(|| format!("Failed to replace {:?}", target))?;
+    }
+    Ok(target)
+}
+
+/// Serializes the GGUF header (magic, metadata, tensor table) with the
+/// tensor offsets unchanged.
+fn encode_header(content: &gguf_file::Content) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    out.extend_from_slice(&GGUF_MAGIC.to_le_bytes());
+    out.extend_from_slice(&GGUF_VERSION.to_le_bytes());
+    out.extend_from_slice(&(content.tensor_infos.len() as u64).to_le_bytes());
+    out.extend_from_slice(&(content.metadata.len() as u64).to_le_bytes());
+
+    let mut keys: Vec<&String> = content.metadata.keys().collect();
+    keys.sort();
+    for key in keys {
+        let value = &content.metadata[key];
+        write_string(&mut out, key);
+        out.extend_from_slice(&value_type_id(value).to_le_bytes());
+        write_value(&mut out, value)?;
+    }
+
+    let mut tensors: Vec<_> = content.tensor_infos.iter().collect();
+    tensors.sort_by_key(|(_, info)| info.offset);
+    for (name, info) in tensors {
+        write_string(&mut out, name);
+        let dims = info.shape.dims();
+        out.extend_from_slice(&(dims.len() as u32).to_le_bytes());
+        for &dim in dims.iter().rev() {
+            out.extend_from_slice(&(dim as u64).to_le_bytes());
+        }
+        out.extend_from_slice(&ggml_type_id(info.ggml_dtype).to_le_bytes());
+        out.extend_from_slice(&info.offset.to_le_bytes());
+    }
+    Ok(out)
+}
+
+fn write_string(out: &mut Vec<u8>, s: &str) {
+    out.extend_from_slice(&(s.len() as u64).to_le_bytes());
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn write_value(out: &mut Vec<u8>, value: &Value) -> Result<()> {
+    match value {
+        Value::U8(v) => out.extend_from_slice(&v.to_le_bytes()),
+        Value::I8(v) => out.extend_from_slice(&v.to_le_bytes()),
+        Value::U16(v) => out.extend_from_slice(&v.to_le_bytes()),
+        Value::I16(v) => out.extend_from_slice(&v.to_le_bytes()),
+        Value::U32(v) => out.extend_from_slice(&v.to_le_bytes()),
+        Value::I32(v) => out.extend_from_slice(&v.to_le_bytes()),
+        Value::U64(v) => out.extend_from_slice(&v.to_le_bytes()),
+        Value::I64(v) => out.extend_from_slice(&v.to_le_bytes()),
+        Value::F32(v) => out.extend_from_slice(&v.to_le_bytes()),
+        Value::F64(v) => out.extend_from_slice(&v.to_le_bytes()),
+        Value::Bool(v) => out.push(*v as u8),
+        Value::String(v) => write_string(out, v),
+        Value::Array(items) => {
+            // An empty array read back has lost its element type, and
+            // writing a guessed one would change the file's metadata.
+            let Some(item_type) = items.first().map(value_type_id) else {
+                anyhow::bail!("cannot rewrite an empty GGUF array: its element type is unknown");
+            };
+            if items.iter().any(|item| value_type_id(item) != item_type) {
+                anyhow::bail!("GGUF arrays must hold a single value type");
+            }
+            out.extend_from_slice(&item_type.to_le_bytes());
+            out.extend_from_slice(&(items.len() as u64).to_le_bytes());
+            for item in items {
+                write_value(out, item)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn value_type_id(value: &Value) -> u32 {
+    match value {
+        Value::U8(_) => 0,
+        Value::I8(_) => 1,
+        Value::U16(_) => 2,
+        Value::I16(_) => 3,
+        Value::U32(_) => 4,
+        Value::I32(_) => 5,
+        Value::F32(_) => 6,
+        Value::Bool(_) => 7,
+        Value::String(_) => 8,
+        Value::Array(_) => 9,
+        Value::U64(_) => 10,
+        Value::I64(_) => 11,
+        Value::F64(_) => 12,
+    }
+}
+
+fn ggml_type_id(dtype: GgmlDType) -> u32 {
+    match dtype {
+        GgmlDType::F32 => 0,
+        GgmlDType::F16 => 1,
+        GgmlDType::Q4_0 => 2,
+        GgmlDType::Q4_1 => 3,
+        GgmlDType::Q5_0 => 6,
+        GgmlDType::Q5_1 => 7,
+        GgmlDType::Q8_0 => 8,
+        GgmlDType::Q8_1 => 9,
+        GgmlDType::Q2K => 10,
+        GgmlDType::Q3K => 11,
+        GgmlDType::Q4K => 12,
+        GgmlDType::Q5K => 13,
+        GgmlDType::Q6K => 14,
+        GgmlDType::Q8K => 15,
+        GgmlDType::BF16 => 30,
+    }
+}
+
+/// Parses `text` as the same type as `existing`, or as a string.
+fn parse_like(existing: Option<&Value>, text: &str) -> Result<Value> {
+    Ok(match existing {
+        None | Some(Value::String(_)) => Value::String(text.to_string()),
+        Some(Value::U8(_)) => Value::U8(text.parse()?),
+        Some(Value::I8(_)) => Value::I8(text.parse()?),
+        Some(Value::U16(_)) => Value::U16(text.parse()?),
+        Some(Value::I16(_)) => Value::I16(text.parse()?),
+        Some(Value::U32(_)) => Value::U32(text.parse()?),
+        Some(Value::I32(_)) => Value::I32(text.parse()?),
+        Some(Value::U64(_)) => Value::U64(text.parse()?),
+        Some(Value::I64(_)) => Value::I64(text.parse()?),
+        Some(Value::F32(_)) => Value::F32(text.parse()?),
+        Some(Value::F64(_)) => Value::F64(text.parse()?),
+        Some(Value::Bool(_)) => Value::Bool(text.parse()?),
+        Some(Value::Array(_)) => anyhow::bail!("array values cannot be set from the command line"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Cursor;
+
+    use candle_core::quantized::gguf_file::{self, Value};
+    use candle_core::quantized::{GgmlDType, QTensor};
+    use candle_core::{Device, Tensor};
+
+    use super::{set_chat_template, set_key, write_value};
+
+    #[test]
+    fn edits_metadata_and_preserves_tensors() {
+        let dir = std::env::temp_dir().join(format!("oxide-gguf-edit-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("model.gguf");
+
+        let device = Device::Cpu;
+        let weight = Tensor::randn(0f32, 1.0, (2, 32), &device).unwrap();
+        let weight = QTensor::quantize(&weight, GgmlDType::Q8_0).unwrap();
+        let name = Value::String("old".to_string());
+        let layers = Value::U32(2);
+        gguf_file::write(
+            &mut File::create(&path).unwrap(),
+            &[("general.name", &name), ("llama.block_count", &layers)],
+            &[("blk.0.attn_q.weight", &weight)],
+        )
+        .unwrap();
+        let original = weight.data().unwrap().to_vec();
+
+        set_key(&path, "general.name", " new name ", None).unwrap();
+        set_key(&path, "llama.block_count", "3", None).unwrap();
+        assert!(set_key(&path, "llama.block_count", "three", None).is_err());
+        assert!(set_key(&path, "llama.block_count", " 3", None).is_err());
+        let template = "{% for m in messages %}{{ m.role }}: {{ m.content }}\n{% endfor %}";
+        set_chat_template(&path, template, None).unwrap();
+        assert!(set_chat_template(&path, "{% for %}", None).is_err());
+
+        let bytes = std::fs::read(&path).unwrap();
+        let mut cursor = Cursor::new(&bytes);
+        let content = gguf_file::Content::read(&mut cursor).unwrap();
+        assert_eq!(
+            content.metadata["general.name"].to_string().unwrap(),
+            " new name "
+        );
+        assert_eq!(content.metadata["llama.block_count"].to_u32().unwrap(), 3);
+        assert_eq!(
+            content.metadata["tokenizer.chat_template"]
+                .to_string()
+                .unwrap(),
+            template
+        );
+        let tensor = content
+            .tensor(&mut cursor, "blk.0.attn_q.weight", &device)
+            .unwrap();
+        assert_eq!(tensor.data().unwrap().to_vec(), original);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn rejects_empty_arrays_instead_of_guessing_their_type() {
+        assert!(write_value(&mut Vec::new(), &Value::Array(Vec::new())).is_err());
+        let mut out = Vec::new();
+        write_value(&mut out, &Value::Array(vec![Value::I32(7)])).unwrap();
+        assert_eq!(out[..4], 5u32.to_le_bytes());
+    }
+}
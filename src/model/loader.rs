@@ -1,9 +1,11 @@
+use std::collections::BTreeMap;
 use std::fs::File;
-use std::io::{Cursor, Seek};
-use std::path::PathBuf;
+use std::io::{BufReader, Cursor, Seek};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 use anyhow::{Context, Result};
-use candle_core::quantized::gguf_file;
+use candle_core::quantized::{gguf_file, GgmlDType};
 use candle_core::{Device, Tensor};
 use candle_transformers::models::quantized_lfm2::ModelWeights as Lfm2Model;
 use candle_transformers::models::quantized_llama::ModelWeights as LlamaModel;
@@ -11,6 +13,8 @@ use candle_transformers::models::quantized_qwen2::ModelWeights as Qwen2Model;
 use candle_transformers::models::quantized_qwen3::ModelWeights as Qwen3Model;
 use memmap2::Mmap;
 
+use crate::model::activations::{ActivationReport, TensorStats};
+use crate::model::check::{available_memory, kv_cache_bytes_per_token};
 use crate::model::quantized_qwen35::ModelWeights as Qwen35Model;
 
 #[derive(Debug, Clone)]
@@ -20,10 +24,243 @@ pub struct GgufMetadata {
     pub n_layer: usize,
     pub n_embd: usize,
     pub vocab_size: usize,
+    /// Usable context: the declared one, or less when its KV cache would not
+    /// fit in memory (see [`fit_context`](Self::fit_context)).
     pub context_length: usize,
+    /// Context the model was trained for, from `<arch>.context_length`.
+    pub declared_context_length: usize,
+    /// f32 KV cache size of one token across all layers, when the attention
+    /// shape is known.
+    pub kv_bytes_per_token: Option<u64>,
     pub file_size: u64,
     pub chat_template: Option<String>,
     pub quantization: Option<String>,
+    pub quant_info: QuantizationInfo,
+}
+
+impl GgufMetadata {
+    /// Quantization label with the measured bits per weight, e.g.
+    /// `Q4_K_M (4.89 bpw)`.
+    pub fn quant_summary(&self) -> String {
+        let label = self.quantization.as_deref().unwrap_or("Unknown");
+        match self.quant_info.bits_per_weight() {
+            Some(bpw) => format!("{} ({:.2} bpw)", label, bpw),
+            None => label.to_string(),
+        }
+    }
+
+    /// Caps `context_length` so the weights and a full KV cache fit in
+    /// `available` bytes, instead of running out of memory once a long
+    /// conversation fills the cache. Returns whether the context was capped.
+    pub fn fit_context(&mut self, available: u64) -> bool {
+        let Some(per_token) = self.kv_bytes_per_token else {
+            return false;
+        };
+        let fitted = fit_context_length(
+            self.declared_context_length,
+            per_token,
+            self.file_size,
+            available,
+        );
+        if fitted >= self.context_length {
+            return false;
+        }
+        tracing::warn!(
+            "Capping context from {} to {} tokens: a full KV cache ({} MB) would not fit \
+             next to the weights in {} MB of available memory",
+            self.declared_context_length,
+            fitted,
+            per_token * self.declared_context_length as u64 / 1_000_000,
+            available / 1_000_000
+        );
+        self.context_length = fitted;
+        true
+    }
+}
+
+/// Smallest context the cap goes down to; below it a model is barely usable
+/// and loading is left to fail or swap.
+const MIN_FITTED_CONTEXT: usize = 512;
+
+/// Largest context, in multiples of 256 tokens, whose KV cache fits in the
+/// memory left after the weights, keeping a fifth of it for activations and
+/// everything else.
+pub(crate) fn fit_context_length(
+    declared: usize,
+    per_token: u64,
+    weights: u64,
+    available: u64,
+) -> usize {
+    let budget = available.saturating_sub(weights) / 5 * 4;
+    let fitting = (budget / per_token.max(1)).min(declared as u64) as usize;
+    if fitting >= declared {
+        return declared;
+    }
+    (fitting / 256 * 256).max(MIN_FITTED_CONTEXT).min(declared)
+}
+
+/// Storage type of a single tensor.
+#[derive(Debug, Clone)]
+pub struct TensorQuant {
+    pub name: String,
+    pub dtype: &'static str,
+    pub params: u64,
+    pub bytes: u64,
+}
+
+/// Per-tensor quantization read from the GGUF tensor table.
+#[derive(Debug, Clone, Default)]
+pub struct QuantizationInfo {
+    /// Sorted by tensor name.
+    pub tensors: Vec<TensorQuant>,
+}
+
+impl QuantizationInfo {
+    pub fn from_content(content: &gguf_file::Content) -> Self {
+        let mut tensors: Vec<TensorQuant> = content
+            .tensor_infos
+            .iter()
+            .map(|(name, info)| {
+                let dtype = info.ggml_dtype;
+                let params = info.shape.elem_count() as u64;
+                let bytes = params / dtype.block_size() as u64 * dtype.type_size() as u64;
+                TensorQuant {
+                    name: name.clone(),
+                    dtype: dtype_name(dtype),
+                    params,
+                    bytes,
+                }
+            })
+            .collect();
+        tensors.sort_by(|a, b| a.name.cmp(&b.name));
+        Self { tensors }
+    }
+
+    pub fn total_params(&self) -> u64 {
+        self.tensors.iter().map(|t| t.params).sum()
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        self.tensors.iter().map(|t| t.bytes).sum()
+    }
+
+    /// Average storage bits per parameter across all tensors.
+    pub fn bits_per_weight(&self) -> Option<f64> {
+        let params = self.total_params();
+        (params > 0).then(|| self.total_bytes() as f64 * 8.0 / params as f64)
+    }
+
+    /// `(dtype, tensor count, parameter count)` per storage type, largest
+    /// share of parameters first.
+    pub fn type_mix(&self) -> Vec<(&'static str, usize, u64)> {
+        let mut mix: BTreeMap<&'static str, (usize, u64)> = BTreeMap::new();
+        for tensor in &self.tensors {
+            let entry = mix.entry(tensor.dtype).or_default();
+            entry.0 += 1;
+            entry.1 += tensor.params;
+        }
+        let mut mix: Vec<_> = mix
+            .into_iter()
+            .map(|(dtype, (count, params))| (dtype, count, params))
+            .collect();
+        mix.sort_by_key(|&(_, _, params)| std::cmp::Reverse(params));
+        mix
+    }
+}
+
+/// llama.cpp spelling of a tensor storage type.
+pub fn dtype_name(dtype: GgmlDType) -> &'static str {
+    match dtype {
+        GgmlDType::F32 => "F32",
+        GgmlDType::F16 => "F16",
+        GgmlDType::BF16 => "BF16",
+        GgmlDType::Q4_0 => "Q4_0",
+        GgmlDType::Q4_1 => "Q4_1",
+        GgmlDType::Q5_0 => "Q5_0",
+        GgmlDType::Q5_1 => "Q5_1",
+        GgmlDType::Q8_0 => "Q8_0",
+        GgmlDType::Q8_1 => "Q8_1",
+        GgmlDType::Q2K => "Q2_K",
+        GgmlDType::Q3K => "Q3_K",
+        GgmlDType::Q4K => "Q4_K",
+        GgmlDType::Q5K => "Q5_K",
+        GgmlDType::Q6K => "Q6_K",
+        GgmlDType::Q8K => "Q8_K",
+    }
+}
+
+/// Name of a llama.cpp `general.file_type` (the quantization preset the file
+/// was produced with, e.g. `Q4_K_M`).
+fn file_type_name(file_type: u64) -> Option<&'static str> {
+    Some(match file_type {
+        0 => "F32",
+        1 => "F16",
+        2 => "Q4_0",
+        3 => "Q4_1",
+        7 => "Q8_0",
+        8 => "Q5_0",
+        9 => "Q5_1",
+        10 => "Q2_K",
+        11 => "Q3_K_S",
+        12 => "Q3_K_M",
+        13 => "Q3_K_L",
+        14 => "Q4_K_S",
+        15 => "Q4_K_M",
+        16 => "Q5_K_S",
+        17 => "Q5_K_M",
+        18 => "Q6_K",
+        21 => "Q2_K_S",
+        32 => "BF16",
+        _ => return None,
+    })
+}
+
+/// Where the time to load a model went, phase by phase.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LoadReport {
+    /// Opening the file and memory-mapping it.
+    pub open: Duration,
+    /// Reading the GGUF header and metadata.
+    pub header: Duration,
+    /// Building the weights, which reads every tensor page of the mapping;
+    /// slow on a cold page cache or under memory pressure.
+    pub tensors: Duration,
+    pub tokenizer: Duration,
+    /// Compiling the chat template.
+    pub template: Duration,
+    /// The warmup forward pass, when one ran.
+    pub warmup: Option<Duration>,
+}
+
+impl LoadReport {
+    pub fn total(&self) -> Duration {
+        self.open
+            + self.header
+            + self.tensors
+            + self.tokenizer
+            + self.template
+            + self.warmup.unwrap_or_default()
+    }
+}
+
+impl std::fmt::Display for LoadReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        let mut phases = vec![
+            ("File open + mmap", self.open),
+            ("GGUF header", self.header),
+            ("Tensors", self.tensors),
+            ("Tokenizer", self.tokenizer),
+            ("Chat template", self.template),
+        ];
+        if let Some(warmup) = self.warmup {
+            phases.push(("Warmup", warmup));
+        }
+        for (name, time) in phases {
+            writeln!(f, "{:<17} {:>9.1} ms", name, ms(time))?;
+        }
+        write!(f, "{:<17} {:>9.1} ms", "Total", ms(self.total()))
+    }
 }
 
 pub enum ModelInner {
@@ -37,6 +274,8 @@ pub enum ModelInner {
 pub struct Model {
     inner: ModelInner,
     metadata: GgufMetadata,
+    /// Times of the phases `load_with_mmap` ran; the rest stay zero.
+    load_report: LoadReport,
 }
 
 pub struct ModelWithMmap {
@@ -50,6 +289,20 @@ impl Model {
         Ok(model)
     }
 
+    /// Reads the metadata from the GGUF header without loading any weights.
+    pub fn read_metadata(path: &Path) -> Result<GgufMetadata> {
+        let file_size = std::fs::metadata(path)?.len();
+        let filename = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("unknown");
+        let file =
+            File::open(path).with_context(|| format!("Failed to open model file: {:?}", path))?;
+        let content = gguf_file::Content::read(&mut BufReader::new(file))
+            .with_context(|| format!("Failed to read GGUF file: {:?}", path))?;
+        Self::extract_metadata(&content, filename, file_size)
+    }
+
     pub fn load_with_mmap(path: &PathBuf) -> Result<(Mmap, Self)> {
         let file_size = std::fs::metadata(path)?.len();
         let filename = path
@@ -59,6 +312,8 @@ impl Model {
 
         let device = Device::Cpu;
 
+        let mut report = LoadReport::default();
+        let started = Instant::now();
         let file =
             File::open(path).with_context(|| format!("Failed to open model file: {:?}", path))?;
 
@@ -69,27 +324,24 @@ impl Model {
         // Apply madvise hints BEFORE reading tensor data so the kernel begins
         // async read-ahead while candle's sequential seek+read_exact calls follow.
         // Calling these after from_gguf() would be useless — data already read.
-        {
-            let ptr = mmap.as_ptr() as *mut std::ffi::c_void;
-            let size = mmap.len();
-            unsafe {
-                libc::madvise(ptr, size, libc::MADV_SEQUENTIAL);
-                #[cfg(target_os = "linux")]
-                libc::madvise(ptr, size, libc::MADV_HUGEPAGE);
-                libc::madvise(ptr, size, libc::MADV_WILLNEED);
-            }
+        if crate::platform::advise_sequential(&mmap) {
             tracing::info!(
                 "madvise hints applied ({} MB): SEQUENTIAL + HUGEPAGE + WILLNEED",
-                size / 1_000_000
+                mmap.len() / 1_000_000
             );
         }
 
+        report.open = started.elapsed();
+        let started = Instant::now();
         let mut cursor = Cursor::new(&mmap);
 
         let content = gguf_file::Content::read(&mut cursor)
             .with_context(|| format!("Failed to read GGUF file: {:?}", path))?;
 
-        let metadata = Self::extract_metadata(&content, filename, file_size)?;
+        let mut metadata = Self::extract_metadata(&content, filename, file_size)?;
+        if let Some(available) = available_memory() {
+            metadata.fit_context(available);
+        }
 
         let arch = metadata.architecture.as_str();
         tracing::info!(
@@ -101,6 +353,8 @@ impl Model {
             arch
         );
 
+        report.header = started.elapsed();
+        let started = Instant::now();
         cursor.seek(std::io::SeekFrom::Start(0))?;
 
         let inner = if arch == "lfm2" {
@@ -125,9 +379,14 @@ impl Model {
             ModelInner::Llama(weights)
         };
 
+        report.tensors = started.elapsed();
         tracing::info!("Model loaded successfully");
 
-        let model = Self { inner, metadata };
+        let model = Self {
+            inner,
+            metadata,
+            load_report: report,
+        };
         Ok((mmap, model))
     }
 
@@ -186,47 +445,44 @@ impl Model {
             .get("tokenizer.chat_template")
             .and_then(|v| v.to_string().ok().cloned());
 
+        let quant_info = QuantizationInfo::from_content(content);
+
+        let n_layer = get_required("block_count")?;
+        let n_embd = get_required("embedding_length")?;
+        let context_length = get_optional("context_length", 4096);
+        let kv_bytes_per_token = find_key("attention.head_count")
+            .filter(|&heads| heads > 0)
+            .map(|heads| {
+                let kv_heads = find_key("attention.head_count_kv").unwrap_or(heads);
+                let head_dim = find_key("attention.key_length").unwrap_or(n_embd / heads);
+                kv_cache_bytes_per_token(n_layer, kv_heads, head_dim)
+            });
+
+        // Prefer the preset recorded by the quantizer; otherwise name the
+        // storage type holding most of the parameters.
         let quantization: Option<String> = md
-            .get("general.quantization")
-            .and_then(|v| v.to_string().ok().map(|s| s.to_string()))
+            .get("general.file_type")
+            .and_then(|v| v.to_u64().ok())
+            .and_then(file_type_name)
+            .map(str::to_string)
             .or_else(|| {
-                // Try to find quantization in various model metadata keys
-                let quant_keys = [
-                    "quantization_version",
-                    "quantization",
-                    "quantization_format",
-                ];
-                for key in quant_keys {
-                    if let Some(v) = md.get(&format!("{}.{}", arch, key)) {
-                        if let Ok(s) = v.to_string() {
-                            if !s.is_empty() {
-                                return Some(s.to_string());
-                            }
-                        }
-                    }
-                }
-                None
+                md.get("general.quantization")
+                    .and_then(|v| v.to_string().ok())
+                    .filter(|s| !s.is_empty())
+                    .cloned()
             })
             .or_else(|| {
-                filename
-                    .split('.')
-                    .filter(|s| !s.eq_ignore_ascii_case("gguf") && !s.eq_ignore_ascii_case("bin"))
-                    .last()
-                    .map(|s| s.to_string())
-                    .filter(|s| {
-                        s.len() >= 2
-                            && (s.starts_with("q") || s.starts_with("Q"))
-                            && s.chars()
-                                .skip(1)
-                                .all(|c| c.is_ascii_digit() || c == '_' || c == '-')
-                    })
+                quant_info
+                    .type_mix()
+                    .first()
+                    .map(|(dtype, _, _)| dtype.to_string())
             });
 
         Ok(GgufMetadata {
             name: model_name,
             architecture: arch.clone(),
-            n_layer: get_required("block_count")?,
-            n_embd: get_required("embedding_length")?,
+            n_layer,
+            n_embd,
             vocab_size: find_key("vocab_size")
                 .or_else(|| {
                     // Fallback: derive from tokenizer token list length.
@@ -236,13 +492,20 @@ impl Model {
                         .map(|arr| arr.len())
                 })
                 .ok_or_else(|| anyhow::anyhow!("Missing metadata key: vocab_size"))?,
-            context_length: get_optional("context_length", 4096),
+            context_length,
+            declared_context_length: context_length,
+            kv_bytes_per_token,
             file_size,
             chat_template,
             quantization,
+            quant_info,
         })
     }
 
+    pub fn load_report(&self) -> &LoadReport {
+        &self.load_report
+    }
+
     pub fn metadata(&self) -> &GgufMetadata {
         &self.metadata
     }
@@ -270,4 +533,186 @@ impl Model {
         };
         Ok(logits)
     }
+
+    /// Whether [`forward_debug`](Self::forward_debug) records per-layer
+    /// statistics. That needs access to the layers, which only the qwen35
+    /// implementation gives.
+    pub fn reports_layers(&self) -> bool {
+        matches!(self.inner, ModelInner::Qwen35(_))
+    }
+
+    /// Runs `tokens` from position 0 like [`forward`](Self::forward) and
+    /// records activation statistics; see
+    /// [`reports_layers`](Self::reports_layers). Other architectures report
+    /// the logits alone.
+    pub fn forward_debug(&mut self, tokens: &[u32]) -> Result<ActivationReport> {
+        let mut layers = Vec::new();
+        let logits = if let ModelInner::Qwen35(m) = &mut self.inner {
+            m.clear_kv_cache();
+            let input = Tensor::new(tokens, &Device::Cpu)?.unsqueeze(0)?;
+            m.forward_debug(&input, 0, &mut layers)?
+        } else {
+            self.forward(tokens, 0)?
+        };
+        Ok(ActivationReport {
+            architecture: self.metadata.architecture.clone(),
+            prompt_tokens: tokens.len(),
+            layers,
+            logits: TensorStats::from_tensor("logits", &logits)?,
+        })
+    }
+
+    /// Whether a multi-token forward can follow cached tokens. The llama,
+    /// qwen2 and lfm2 models build a square causal mask that ignores the KV
+    /// cache, so after the first chunk they must continue one token at a time.
+    fn supports_offset_prefill(&self) -> bool {
+        matches!(self.inner, ModelInner::Qwen3(_) | ModelInner::Qwen35(_))
+    }
+
+    /// Like [`forward`](Self::forward), but feeds `tokens` in chunks of at
+    /// most `chunk_size` so attention scratch scales with the chunk instead
+    /// of the whole prompt. Returns the logits of the last token.
+    pub fn forward_chunked(
+        &mut self,
+        tokens: &[u32],
+        pos: usize,
+        chunk_size: usize,
+    ) -> Result<Tensor> {
+        let logits = self.forward_chunked_while(tokens, pos, chunk_size, || true)?;
+        Ok(logits.expect("prefill is never stopped"))
+    }
+
+    /// Like [`forward_chunked`](Self::forward_chunked), calling
+    /// `keep_going` before every chunk and returning `None` as soon as it
+    /// says no. With chunks of `chunk_size` only on models that support
+    /// offset prefill; others run the prompt as one chunk.
+    pub fn forward_preemptible(
+        &mut self,
+        tokens: &[u32],
+        pos: usize,
+        chunk_size: usize,
+        keep_going: impl FnMut() -> bool,
+    ) -> Result<Option<Tensor>> {
+        let chunk_size = if self.supports_offset_prefill() {
+            chunk_size
+        } else {
+            tokens.len()
+        };
+        self.forward_chunked_while(tokens, pos, chunk_size, keep_going)
+    }
+
+    fn forward_chunked_while(
+        &mut self,
+        tokens: &[u32],
+        pos: usize,
+        chunk_size: usize,
+        mut keep_going: impl FnMut() -> bool,
+    ) -> Result<Option<Tensor>> {
+        let chunk_size = chunk_size.max(1);
+        let first = if pos == 0 || self.supports_offset_prefill() {
+            chunk_size.min(tokens.len())
+        } else {
+            1
+        };
+        let rest = if self.supports_offset_prefill() {
+            chunk_size
+        } else {
+            1
+        };
+
+        if !keep_going() {
+            return Ok(None);
+        }
+        let mut logits = self.forward(&tokens[..first], pos)?;
+        let mut done = first;
+        while done < tokens.len() {
+            if !keep_going() {
+                return Ok(None);
+            }
+            let end = (done + rest).min(tokens.len());
+            logits = self.forward(&tokens[done..end], pos + done)?;
+            done = end;
+        }
+        Ok(Some(logits))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use candle_core::quantized::gguf_file::{Content, TensorInfo, VersionedMagic};
+    use candle_core::quantized::GgmlDType;
+
+    use std::time::Duration;
+
+    use super::{fit_context_length, LoadReport, QuantizationInfo};
+
+    #[test]
+    fn reports_load_phases() {
+        let report = LoadReport {
+            tensors: Duration::from_millis(1500),
+            tokenizer: Duration::from_millis(250),
+            warmup: Some(Duration::from_millis(40)),
+            ..Default::default()
+        };
+        assert_eq!(report.total(), Duration::from_millis(1790));
+        let text = report.to_string();
+        assert!(text.contains("Tensors              1500.0 ms\n"));
+        assert!(text.contains("Warmup                 40.0 ms\n"));
+        assert!(text.ends_with("Total                1790.0 ms"));
+        assert!(!LoadReport::default().to_string().contains("Warmup"));
+    }
+
+    #[test]
+    fn fits_context_to_available_memory() {
+        const GB: u64 = 1 << 30;
+        // 128 KiB per token: 131072 tokens need 16 GiB of KV cache.
+        let per_token = 128 * 1024;
+        assert_eq!(
+            fit_context_length(131_072, per_token, 4 * GB, 64 * GB),
+            131_072
+        );
+        // 8 GiB left after the weights, 6.4 GiB of it for the cache.
+        assert_eq!(
+            fit_context_length(131_072, per_token, 4 * GB, 12 * GB),
+            52_224
+        );
+        assert_eq!(fit_context_length(131_072, per_token, 4 * GB, 3 * GB), 512);
+        assert_eq!(fit_context_length(256, per_token, 4 * GB, 3 * GB), 256);
+    }
+
+    fn tensor(dtype: GgmlDType, elems: usize) -> TensorInfo {
+        TensorInfo {
+            ggml_dtype: dtype,
+            shape: (elems,).into(),
+            offset: 0,
+        }
+    }
+
+    #[test]
+    fn measures_mixed_quantization() {
+        let content = Content {
+            magic: VersionedMagic::GgufV3,
+            metadata: HashMap::new(),
+            tensor_infos: HashMap::from([
+                (
+                    "blk.0.ffn_down.weight".to_string(),
+                    tensor(GgmlDType::Q6K, 256),
+                ),
+                (
+                    "blk.0.ffn_up.weight".to_string(),
+                    tensor(GgmlDType::Q4K, 768),
+                ),
+            ]),
+            tensor_data_offset: 0,
+        };
+        let info = QuantizationInfo::from_content(&content);
+
+        assert_eq!(info.tensors[0].dtype, "Q6_K");
+        assert_eq!(info.type_mix()[0], ("Q4_K", 1, 768));
+        // Q4_K stores 256 weights in 144 bytes, Q6_K in 210 bytes.
+        let expected = (3.0 * 144.0 + 210.0) * 8.0 / 1024.0;
+        assert!((info.bits_per_weight().unwrap() - expected).abs() < 1e-9);
+    }
 }
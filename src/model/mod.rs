@@ -1,13 +1,27 @@
+pub mod activations;
+pub mod archs;
+#[cfg(feature = "object-store")]
+pub mod bucket;
+pub mod check;
+pub mod convert;
 pub mod download;
+pub mod gguf_edit;
 pub mod loader;
+pub mod quantize;
 pub mod quantized_qwen35;
 pub mod registry;
+pub mod remote;
+pub mod retry;
 pub mod tokenizer;
 
+pub use activations::{ActivationReport, TensorStats};
+pub use archs::{architecture_info, supported_architectures, ArchInfo, ArchStatus};
+pub use check::{check_model, check_remote_model, CheckReport, CheckStatus};
 pub use download::{
     download_model, format_size, get_hf_cache_dir, get_model_info, list_repo_files,
     DownloadProgress,
 };
-pub use loader::{GgufMetadata, Model};
-pub use registry::{list_models, register_model, unregister_model, ModelEntry};
+pub use loader::{GgufMetadata, LoadReport, Model, QuantizationInfo, TensorQuant};
+pub use retry::{load_with_retry, LoadError, LoadErrorKind, LoadRetry};
+pub use registry::{discover_models, list_models, register_model, unregister_model, ModelEntry};
 pub use tokenizer::TokenizerWrapper;
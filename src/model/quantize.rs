@@ -0,0 +1,344 @@
+//! GGUF requantization (`oxide-rs quantize`).
+//!
+//! Every tensor is dequantized to f32 and quantized again with candle's
+//! kernels, so any supported input type can be converted to any preset. The
+//! `_M` and `_S` presets follow llama.cpp's mixes: the output projection and a
+//! subset of `attn_v`/`ffn_down` layers keep more bits than the base type.
+
+use std::fs::File;
+use std::io::{BufWriter, Cursor};
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use candle_core::quantized::{gguf_file, GgmlDType, QTensor};
+use candle_core::Device;
+use memmap2::Mmap;
+
+use crate::model::loader::dtype_name;
+
+/// Target quantization, named like llama.cpp's `llama-quantize` types.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QuantPreset {
+    F16,
+    Q8_0,
+    Q6K,
+    Q5KM,
+    Q5KS,
+    Q5_0,
+    Q4KM,
+    Q4KS,
+    Q4_0,
+    Q3KM,
+    Q2K,
+}
+
+impl QuantPreset {
+    pub const ALL: &'static [QuantPreset] = &[
+        QuantPreset::F16,
+        QuantPreset::Q8_0,
+        QuantPreset::Q6K,
+        QuantPreset::Q5KM,
+        QuantPreset::Q5KS,
+        QuantPreset::Q5_0,
+        QuantPreset::Q4KM,
+        QuantPreset::Q4KS,
+        QuantPreset::Q4_0,
+        QuantPreset::Q3KM,
+        QuantPreset::Q2K,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QuantPreset::F16 => "f16",
+            QuantPreset::Q8_0 => "q8_0",
+            QuantPreset::Q6K => "q6_k",
+            QuantPreset::Q5KM => "q5_k_m",
+            QuantPreset::Q5KS => "q5_k_s",
+            QuantPreset::Q5_0 => "q5_0",
+            QuantPreset::Q4KM => "q4_k_m",
+            QuantPreset::Q4KS => "q4_k_s",
+            QuantPreset::Q4_0 => "q4_0",
+            QuantPreset::Q3KM => "q3_k_m",
+            QuantPreset::Q2K => "q2_k",
+        }
+    }
+
+    /// llama.cpp `general.file_type` written to the output.
+    pub(crate) fn file_type(&self) -> u32 {
+        match self {
+            QuantPreset::F16 => 1,
+            QuantPreset::Q4_0 => 2,
+            QuantPreset::Q8_0 => 7,
+            QuantPreset::Q5_0 => 8,
+            QuantPreset::Q2K => 10,
+            QuantPreset::Q3KM => 12,
+            QuantPreset::Q4KS => 14,
+            QuantPreset::Q4KM => 15,
+            QuantPreset::Q5KS => 16,
+            QuantPreset::Q5KM => 17,
+            QuantPreset::Q6K => 18,
+        }
+    }
+
+    fn base(&self) -> GgmlDType {
+        match self {
+            QuantPreset::F16 => GgmlDType::F16,
+            QuantPreset::Q8_0 => GgmlDType::Q8_0,
+            QuantPreset::Q6K => GgmlDType::Q6K,
+            QuantPreset::Q5KM | QuantPreset::Q5KS => GgmlDType::Q5K,
+            QuantPreset::Q5_0 => GgmlDType::Q5_0,
+            QuantPreset::Q4KM | QuantPreset::Q4KS => GgmlDType::Q4K,
+            QuantPreset::Q4_0 => GgmlDType::Q4_0,
+            QuantPreset::Q3KM => GgmlDType::Q3K,
+            QuantPreset::Q2K => GgmlDType::Q2K,
+        }
+    }
+
+    /// Storage type for the tensor `name`, before block-size fallbacks.
+    pub(crate) fn tensor_type(&self, name: &str, n_layer: usize) -> GgmlDType {
+        let k_quant = matches!(
+            self.base(),
+            GgmlDType::Q2K | GgmlDType::Q3K | GgmlDType::Q4K | GgmlDType::Q5K
+        );
+        if name == "output.weight" && k_quant {
+            return GgmlDType::Q6K;
+        }
+
+        let medium = matches!(
+            self,
+            QuantPreset::Q5KM | QuantPreset::Q4KM | QuantPreset::Q3KM
+        );
+        let sensitive = name.ends_with("attn_v.weight") || name.ends_with("ffn_down.weight");
+        if medium && sensitive {
+            if let Some(layer) = layer_index(name) {
+                if use_more_bits(layer, n_layer) {
+                    return GgmlDType::Q6K;
+                }
+            }
+        }
+        self.base()
+    }
+}
+
+impl FromStr for QuantPreset {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let s = s.to_ascii_lowercase();
+        QuantPreset::ALL
+            .iter()
+            .copied()
+            .find(|preset| preset.as_str() == s)
+            .ok_or_else(|| {
+                let names: Vec<&str> = QuantPreset::ALL.iter().map(|p| p.as_str()).collect();
+                format!(
+                    "unknown type '{}' (expected one of {})",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+impl std::fmt::Display for QuantPreset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Progress reported once per tensor.
+pub struct QuantizeProgress<'a> {
+    pub index: usize,
+    pub total: usize,
+    pub name: &'a str,
+    pub from: &'static str,
+    pub to: &'static str,
+}
+
+/// Rewrites `input` to `output` with every weight tensor stored as `preset`.
+///
+/// Returns the size of the written file. The quantized tensors are held in
+/// memory until the file is written, so peak RAM is roughly the output size.
+pub fn quantize_gguf<F>(
+    input: &Path,
+    output: &Path,
+    preset: QuantPreset,
+    mut progress: F,
+) -> Result<u64>
+where
+    F: FnMut(QuantizeProgress),
+{
+    if input == output {
+        anyhow::bail!("Output must be a different file than the input");
+    }
+
+    let file = File::open(input).with_context(|| format!("Failed to open {:?}", input))?;
+    let mmap = unsafe { Mmap::map(&file)? };
+    let mut cursor = Cursor::new(&mmap);
+    let content = gguf_file::Content::read(&mut cursor)
+        .with_context(|| format!("Failed to read GGUF file: {:?}", input))?;
+
+    let arch = content
+        .metadata
+        .get("general.architecture")
+        .and_then(|v| v.to_string().ok().cloned())
+        .unwrap_or_else(|| "llama".to_string());
+    let n_layer = content
+        .metadata
+        .get(&format!("{}.block_count", arch))
+        .and_then(|v| v.to_u64().ok())
+        .unwrap_or(0) as usize;
+
+    let mut names: Vec<&String> = content.tensor_infos.keys().collect();
+    names.sort();
+
+    let device = Device::Cpu;
+    let total = names.len();
+    let mut tensors = Vec::with_capacity(total);
+    for (index, name) in names.into_iter().enumerate() {
+        let source = content.tensor(&mut cursor, name, &device)?;
+        let target = target_type(
+            source.shape().dims(),
+            source.dtype(),
+            preset.tensor_type(name, n_layer),
+        );
+        progress(QuantizeProgress {
+            index,
+            total,
+            name,
+            from: dtype_name(source.dtype()),
+            to: dtype_name(target),
+        });
+
+        let tensor = if source.dtype() == target {
+            source
+        } else {
+            let values = source.dequantize(&device)?;
+            QTensor::quantize(&values, target)
+                .with_context(|| format!("Failed to quantize {} to {:?}", name, target))?
+        };
+        tensors.push((name.as_str(), tensor));
+    }
+
+    let file_type = gguf_file::Value::U32(preset.file_type());
+    let quantization_version = gguf_file::Value::U32(2);
+    let mut metadata: Vec<(&str, &gguf_file::Value)> = content
+        .metadata
+        .iter()
+        .filter(|(key, _)| {
+            !matches!(
+                key.as_str(),
+                "general.file_type" | "general.quantization_version"
+            )
+        })
+        .map(|(key, value)| (key.as_str(), value))
+        .collect();
+    metadata.push(("general.file_type", &file_type));
+    metadata.push(("general.quantization_version", &quantization_version));
+    metadata.sort_by(|a, b| a.0.cmp(b.0));
+
+    let tensor_refs: Vec<(&str, &QTensor)> = tensors.iter().map(|(n, t)| (*n, t)).collect();
+    let out = File::create(output).with_context(|| format!("Failed to create {:?}", output))?;
+    let mut writer = BufWriter::new(out);
+    gguf_file::write(&mut writer, &metadata, &tensor_refs)?;
+    drop(writer);
+
+    Ok(std::fs::metadata(output)?.len())
+}
+
+/// Keeps vectors (norms, biases) in their original type and falls back to
+/// types with smaller blocks when a row does not divide into `wanted` blocks.
+pub(crate) fn target_type(dims: &[usize], source: GgmlDType, wanted: GgmlDType) -> GgmlDType {
+    if dims.len() < 2 {
+        return source;
+    }
+    let row = dims[dims.len() - 1];
+    [wanted, GgmlDType::Q8_0, GgmlDType::F16]
+        .into_iter()
+        .find(|dtype| row % dtype.block_size() == 0)
+        .unwrap_or(GgmlDType::F16)
+}
+
+fn layer_index(name: &str) -> Option<usize> {
+    name.strip_prefix("blk.")?.split('.').next()?.parse().ok()
+}
+
+/// llama.cpp's rule for which layers of a `_M` mix get extra bits: the first
+/// and last eighth, plus every third layer in between.
+fn use_more_bits(layer: usize, n_layer: usize) -> bool {
+    layer < n_layer / 8 || layer >= 7 * n_layer / 8 || (layer - n_layer / 8) % 3 == 2
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Cursor;
+
+    use candle_core::quantized::{gguf_file, GgmlDType, QTensor};
+    use candle_core::{Device, Tensor};
+
+    use super::{quantize_gguf, QuantPreset};
+
+    #[test]
+    fn rewrites_tensors_and_file_type() {
+        let dir = std::env::temp_dir().join(format!("oxide-quantize-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("in.gguf");
+        let output = dir.join("out.gguf");
+
+        let device = Device::Cpu;
+        let weight = Tensor::randn(0f32, 1.0, (4, 256), &device).unwrap();
+        let norm = Tensor::ones(256, candle_core::DType::F32, &device).unwrap();
+        let weight = QTensor::quantize(&weight, GgmlDType::F16).unwrap();
+        let norm = QTensor::quantize(&norm, GgmlDType::F32).unwrap();
+        let arch = gguf_file::Value::String("llama".to_string());
+        gguf_file::write(
+            &mut File::create(&input).unwrap(),
+            &[("general.architecture", &arch)],
+            &[
+                ("blk.0.ffn_up.weight", &weight),
+                ("output_norm.weight", &norm),
+            ],
+        )
+        .unwrap();
+
+        quantize_gguf(&input, &output, QuantPreset::Q4KM, |_| {}).unwrap();
+
+        let bytes = std::fs::read(&output).unwrap();
+        let content = gguf_file::Content::read(&mut Cursor::new(&bytes)).unwrap();
+        let dtype = |name: &str| content.tensor_infos[name].ggml_dtype;
+        assert_eq!(dtype("blk.0.ffn_up.weight"), GgmlDType::Q4K);
+        assert_eq!(dtype("output_norm.weight"), GgmlDType::F32);
+        assert_eq!(content.metadata["general.file_type"].to_u32().unwrap(), 15);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn parses_preset_names() {
+        assert_eq!("Q4_K_M".parse::<QuantPreset>(), Ok(QuantPreset::Q4KM));
+        assert!("q4_k_x".parse::<QuantPreset>().is_err());
+    }
+
+    #[test]
+    fn medium_mix_keeps_more_bits_in_sensitive_layers() {
+        let preset = QuantPreset::Q4KM;
+        assert_eq!(preset.tensor_type("output.weight", 32), GgmlDType::Q6K);
+        assert_eq!(
+            preset.tensor_type("blk.0.ffn_down.weight", 32),
+            GgmlDType::Q6K
+        );
+        assert_eq!(
+            preset.tensor_type("blk.5.ffn_down.weight", 32),
+            GgmlDType::Q4K
+        );
+        assert_eq!(
+            preset.tensor_type("blk.0.ffn_up.weight", 32),
+            GgmlDType::Q4K
+        );
+        assert_eq!(
+            QuantPreset::Q4KS.tensor_type("blk.0.attn_v.weight", 32),
+            GgmlDType::Q4K
+        );
+    }
+}
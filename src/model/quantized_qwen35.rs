@@ -7,6 +7,8 @@ use candle_nn::{kv_cache::ConcatKvCache, Activation, Embedding, Module};
 use candle_transformers::models::with_tracing::QMatMul;
 use candle_transformers::utils::repeat_kv;
 
+use crate::model::activations::TensorStats;
+
 #[derive(Debug, Clone)]
 struct ZeroCenteredRmsNorm {
     weight: Tensor,
@@ -522,6 +524,7 @@ struct LayerWeights {
 }
 
 impl LayerWeights {
+    #[allow(clippy::too_many_arguments)]
     fn new<R: Read + Seek>(
         gg: &mut Gguf<R>,
         layer_idx: usize,
@@ -683,6 +686,27 @@ impl ModelWeights {
     }
 
     pub fn forward(&mut self, input: &Tensor, offset: usize) -> Result<Tensor> {
+        self.forward_recording(input, offset, None)
+    }
+
+    /// Like [`forward`](Self::forward), also adding every position's
+    /// embeddings and layer outputs to `stats`: the embeddings first, then
+    /// one entry per layer.
+    pub fn forward_debug(
+        &mut self,
+        input: &Tensor,
+        offset: usize,
+        stats: &mut Vec<TensorStats>,
+    ) -> Result<Tensor> {
+        self.forward_recording(input, offset, Some(stats))
+    }
+
+    fn forward_recording(
+        &mut self,
+        input: &Tensor,
+        offset: usize,
+        mut stats: Option<&mut Vec<TensorStats>>,
+    ) -> Result<Tensor> {
         let _enter = self.span.enter();
         let (b, l) = input.dims2()?;
         if b != 1 {
@@ -693,8 +717,14 @@ impl ModelWeights {
         for i in 0..l {
             let tok = input.narrow(1, i, 1)?;
             let mut h = self.embed_tokens.forward(&tok)?;
-            for layer in &mut self.layers {
+            if let Some(stats) = stats.as_deref_mut() {
+                record(stats, 0, "embeddings", &h)?;
+            }
+            for (n, layer) in self.layers.iter_mut().enumerate() {
                 h = layer.forward(&h, offset + i)?;
+                if let Some(stats) = stats.as_deref_mut() {
+                    record(stats, n + 1, &format!("blk.{}", n), &h)?;
+                }
             }
             last_hidden = Some(h);
         }
@@ -712,3 +742,14 @@ impl ModelWeights {
         }
     }
 }
+
+/// Adds `tensor` to the statistics at `index`, creating them on first use.
+fn record(stats: &mut Vec<TensorStats>, index: usize, name: &str, tensor: &Tensor) -> Result<()> {
+    match stats.get_mut(index) {
+        Some(entry) => entry.add_tensor(tensor),
+        None => {
+            stats.push(TensorStats::from_tensor(name, tensor)?);
+            Ok(())
+        }
+    }
+}
@@ -1,4 +1,5 @@
-use std::path::PathBuf;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 
 use anyhow::{Context, Result};
 use chrono::{DateTime, Utc};
@@ -78,7 +79,11 @@ impl Registry {
 }
 
 pub fn generate_model_id(repo_id: &str, filename: &str) -> String {
-    let repo_name = repo_id.split('/').last().unwrap_or(repo_id).to_lowercase();
+    let repo_name = repo_id
+        .split('/')
+        .next_back()
+        .unwrap_or(repo_id)
+        .to_lowercase();
 
     let quant = filename
         .to_uppercase()
@@ -146,3 +151,70 @@ pub fn get_model_path(id: &str) -> Result<Option<PathBuf>> {
     let model = find_model(id)?;
     Ok(model.map(|m| m.path))
 }
+
+/// Registered models whose file still exists, followed by `.gguf` files
+/// found in `dirs` (searched two levels deep) that are not registered.
+pub fn discover_models(dirs: &[PathBuf]) -> Result<Vec<ModelEntry>> {
+    let mut models: Vec<ModelEntry> = list_models()?
+        .into_iter()
+        .filter(|m| m.path.is_file())
+        .collect();
+    let mut seen: HashSet<PathBuf> = models.iter().map(|m| canonical(&m.path)).collect();
+
+    let mut found = Vec::new();
+    for dir in dirs {
+        collect_gguf_files(dir, 2, &mut found);
+    }
+    found.sort();
+    for path in found {
+        if !seen.insert(canonical(&path)) {
+            continue;
+        }
+        let Ok(metadata) = std::fs::metadata(&path) else {
+            continue;
+        };
+        let filename = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        models.push(ModelEntry {
+            id: path
+                .file_stem()
+                .map(|s| s.to_string_lossy().to_lowercase())
+                .unwrap_or_default(),
+            repo_id: "local".to_string(),
+            quantization: extract_quantization(&filename),
+            filename,
+            size_bytes: metadata.len(),
+            downloaded_at: metadata
+                .modified()
+                .map(DateTime::<Utc>::from)
+                .unwrap_or_else(|_| Utc::now()),
+            path,
+        });
+    }
+    Ok(models)
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn collect_gguf_files(dir: &Path, depth: usize, out: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            if depth > 0 {
+                collect_gguf_files(&path, depth - 1, out);
+            }
+        } else if path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("gguf"))
+        {
+            out.push(path);
+        }
+    }
+}
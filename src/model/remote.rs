@@ -0,0 +1,381 @@
+//! GGUF files served over HTTP(S), e.g. `--model https://host/model.gguf`.
+//!
+//! Loading needs a local file to memory-map, so [`fetch_model`] downloads
+//! the file into `~/.oxide/remote` first, resuming an interrupted download
+//! with a range request and verifying its SHA-256. Operations that only read
+//! the header use a [`RangeReader`] instead, which fetches the bytes they
+//! touch and nothing else.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use reqwest::blocking::Client;
+use reqwest::header::{CONTENT_LENGTH, RANGE};
+use reqwest::StatusCode;
+use sha2::{Digest, Sha256};
+
+use crate::model::download::{get_oxide_dir, DownloadProgress};
+
+/// Bytes a [`RangeReader`] fetches per request.
+const RANGE_BLOCK: u64 = 4 << 20;
+/// Longest wait for a connection to a model host.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Longest a [`RangeReader`] block may take, body included.
+const RANGE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Whether a `--model` value is an HTTP(S) URL rather than a path.
+pub fn is_remote(model: &Path) -> bool {
+    model
+        .to_str()
+        .is_some_and(|s| s.starts_with("https://") || s.starts_with("http://"))
+}
+
+/// Whether a `--model` value is an `s3://` or `gs://` object URL, which
+/// builds with the `object-store` feature can fetch.
+pub fn is_bucket(model: &Path) -> bool {
+    model
+        .to_str()
+        .is_some_and(|s| s.starts_with("s3://") || s.starts_with("gs://"))
+}
+
+/// Where [`fetch_model`] keeps `url`: a directory per URL under
+/// `~/.oxide/remote`, holding the file under its remote name.
+pub fn cache_path(url: &str) -> Result<PathBuf> {
+    Ok(get_oxide_dir()?.join("remote").join(cache_entry(url)))
+}
+
+fn cache_entry(url: &str) -> PathBuf {
+    let digest = hex(&Sha256::digest(url.as_bytes()));
+    let name = url
+        .split(['?', '#'])
+        .next()
+        .and_then(|u| u.rsplit('/').next())
+        .filter(|name| !name.is_empty())
+        .unwrap_or("model.gguf");
+    Path::new(&digest[..16]).join(name)
+}
+
+/// Downloads `url` into the local cache and returns its path.
+///
+/// A cached copy is reused unless `sha256` is given and differs from the
+/// checksum recorded when it was downloaded. An interrupted download
+/// continues where it stopped when the server supports range requests. The
+/// finished file is checked against `sha256` before it is moved into place.
+pub fn fetch_model<F>(url: &str, sha256: Option<&str>, progress: F) -> Result<PathBuf>
+where
+    F: FnMut(DownloadProgress),
+{
+    cached_download(url, sha256, progress, |offset| {
+        open_download(&http_client(None)?, url, offset)
+    })
+}
+
+/// A client that gives up on connecting after [`CONNECT_TIMEOUT`] and on a
+/// whole request after `timeout`, or never when `None`. reqwest's default
+/// 30-second limit includes reading the body, which would cut off every
+/// download of a multi-GB model.
+fn http_client(timeout: Option<Duration>) -> Result<Client> {
+    Client::builder()
+        .connect_timeout(CONNECT_TIMEOUT)
+        .timeout(timeout)
+        .build()
+        .context("Failed to create an HTTP client")
+}
+
+/// Requests `url` from byte `offset` on.
+fn open_download(client: &Client, url: &str, offset: u64) -> Result<Body> {
+    let mut request = client.get(url);
+    if offset > 0 {
+        request = request.header(RANGE, format!("bytes={}-", offset));
+    }
+    let response = request
+        .send()
+        .with_context(|| format!("Failed to download {}", url))?;
+    // The partial file already holds everything; only the rename was missed.
+    if offset > 0 && response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        return Ok(Body {
+            reader: Box::new(io::empty()),
+            resumed: true,
+            remaining: 0,
+        });
+    }
+    let response = response
+        .error_for_status()
+        .with_context(|| format!("Failed to download {}", url))?;
+    Ok(Body {
+        resumed: response.status() == StatusCode::PARTIAL_CONTENT,
+        remaining: response.content_length().unwrap_or(0),
+        reader: Box::new(response),
+    })
+}
+
+/// The bytes of a remote file from the offset a download asked for.
+pub(crate) struct Body {
+    pub reader: Box<dyn Read>,
+    /// Whether the body starts at that offset; `false` means the source
+    /// sent the whole file instead.
+    pub resumed: bool,
+    /// Length of the body, or 0 when unknown.
+    pub remaining: u64,
+}
+
+/// Downloads `url` into [`cache_path`] as described on [`fetch_model`],
+/// reading it through `open`, which is called with the number of bytes
+/// already downloaded.
+pub(crate) fn cached_download<F, O>(
+    url: &str,
+    sha256: Option<&str>,
+    mut progress: F,
+    open: O,
+) -> Result<PathBuf>
+where
+    F: FnMut(DownloadProgress),
+    O: FnOnce(u64) -> Result<Body>,
+{
+    let path = cache_path(url)?;
+    let checksum_path = path.with_extension("sha256");
+    let expected = sha256.map(str::to_ascii_lowercase);
+    if path.exists() {
+        let recorded = std::fs::read_to_string(&checksum_path).unwrap_or_default();
+        match &expected {
+            Some(expected) if recorded.trim() != expected => {
+                tracing::warn!(
+                    "Cached {} does not match the expected checksum, downloading again",
+                    path.display()
+                );
+                std::fs::remove_file(&path)?;
+            }
+            _ => return Ok(path),
+        }
+    }
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    let filename = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let partial = path.with_extension("part");
+    let mut hasher = Sha256::new();
+    let mut downloaded = match File::open(&partial) {
+        // The bytes already on disk are part of the checksum.
+        Ok(mut file) => io::copy(&mut file, &mut hasher)?,
+        Err(_) => 0,
+    };
+
+    let mut body = open(downloaded)?;
+    if downloaded > 0 && !body.resumed {
+        tracing::info!("Source ignored the range request, downloading from the start");
+        downloaded = 0;
+        hasher = Sha256::new();
+    } else if downloaded > 0 {
+        tracing::info!("Resuming download of {} at {} bytes", url, downloaded);
+    }
+    let total = downloaded + body.remaining;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(body.resumed)
+        .truncate(!body.resumed)
+        .open(&partial)
+        .with_context(|| format!("Failed to open {}", partial.display()))?;
+
+    let mut buffer = vec![0u8; 1 << 20];
+    loop {
+        let n = body
+            .reader
+            .read(&mut buffer)
+            .with_context(|| format!("Download of {} was interrupted", url))?;
+        if n == 0 {
+            break;
+        }
+        file.write_all(&buffer[..n])?;
+        hasher.update(&buffer[..n]);
+        downloaded += n as u64;
+        progress(DownloadProgress {
+            bytes_downloaded: downloaded,
+            total_bytes: total,
+            filename: filename.clone(),
+        });
+    }
+    file.sync_all()?;
+    drop(file);
+
+    let actual = hex(&hasher.finalize());
+    if let Some(expected) = expected.filter(|expected| *expected != actual) {
+        std::fs::remove_file(&partial)?;
+        anyhow::bail!(
+            "Checksum mismatch for {}: expected {}, got {}",
+            url,
+            expected,
+            actual
+        );
+    }
+    std::fs::rename(&partial, &path)?;
+    std::fs::write(&checksum_path, format!("{}\n", actual))?;
+    tracing::info!("Downloaded {} (sha256 {})", url, actual);
+    Ok(path)
+}
+
+/// A remote file read with HTTP range requests, one block at a time, so
+/// reading its header costs a few requests instead of a full download.
+pub struct RangeReader {
+    client: Client,
+    url: String,
+    len: u64,
+    pos: u64,
+    block_start: u64,
+    block: Vec<u8>,
+}
+
+impl RangeReader {
+    /// Opens `url`, failing when the server does not report its length.
+    /// Each block request may take up to [`RANGE_TIMEOUT`].
+    pub fn open(url: &str) -> Result<Self> {
+        let client = http_client(Some(RANGE_TIMEOUT))?;
+        let response = client
+            .head(url)
+            .send()
+            .and_then(|r| r.error_for_status())
+            .with_context(|| format!("Failed to reach {}", url))?;
+        let len = response
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok())
+            .with_context(|| format!("{} did not report its size", url))?;
+        Ok(Self {
+            client,
+            url: url.to_string(),
+            len,
+            pos: 0,
+            block_start: 0,
+            block: Vec::new(),
+        })
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn fetch_block(&mut self, start: u64) -> io::Result<()> {
+        let end = (start + RANGE_BLOCK).min(self.len) - 1;
+        let response = self
+            .client
+            .get(&self.url)
+            .header(RANGE, format!("bytes={}-{}", start, end))
+            .send()
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        if response.status() != StatusCode::PARTIAL_CONTENT {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("{} does not support range requests", self.url),
+            ));
+        }
+        self.block = response
+            .bytes()
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?
+            .to_vec();
+        self.block_start = start;
+        Ok(())
+    }
+}
+
+impl Read for RangeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+        let in_block =
+            self.pos >= self.block_start && self.pos < self.block_start + self.block.len() as u64;
+        if !in_block {
+            self.fetch_block(self.pos)?;
+        }
+        let offset = (self.pos - self.block_start) as usize;
+        let n = buf.len().min(self.block.len() - offset);
+        buf[..n].copy_from_slice(&self.block[offset..offset + n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for RangeReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+        };
+        self.pos = target
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek before the start"))?;
+        Ok(self.pos)
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::path::Path;
+    use std::time::Duration;
+
+    use super::{cache_entry, http_client, is_remote, open_download};
+
+    #[test]
+    fn recognizes_and_caches_remote_models() {
+        assert!(is_remote(Path::new("https://models.example/q4.gguf")));
+        assert!(is_remote(Path::new("http://10.0.0.2:8080/q4.gguf")));
+        assert!(!is_remote(Path::new("models/q4.gguf")));
+
+        let path = cache_entry("https://models.example/llama/q4.gguf?token=abc");
+        assert_eq!(path.file_name().unwrap(), "q4.gguf");
+        let other = cache_entry("https://mirror.example/llama/q4.gguf");
+        assert_ne!(path.parent(), other.parent());
+    }
+
+    #[test]
+    fn slow_bodies_are_not_cut_off() {
+        // Every response stalls for a second between two halves of its body.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/model.gguf", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for stream in listener.incoming().take(2) {
+                let mut stream = stream.unwrap();
+                let mut request = Vec::new();
+                let mut byte = [0u8; 1];
+                while !request.ends_with(b"\r\n\r\n") && stream.read(&mut byte).unwrap() == 1 {
+                    request.push(byte[0]);
+                }
+                stream
+                    .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nhello")
+                    .unwrap();
+                stream.flush().unwrap();
+                std::thread::sleep(Duration::from_secs(1));
+                stream.write_all(b"world").ok();
+            }
+        });
+
+        let read_all = |timeout: Option<Duration>| {
+            let mut body = open_download(&http_client(timeout)?, &url, 0)?;
+            let mut text = String::new();
+            body.reader.read_to_string(&mut text)?;
+            anyhow::Ok(text)
+        };
+        // A limit on the whole request would end the download mid-body.
+        assert!(read_all(Some(Duration::from_millis(300))).is_err());
+        assert_eq!(read_all(None).unwrap(), "helloworld");
+    }
+}
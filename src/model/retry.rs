@@ -0,0 +1,311 @@
+//! Retrying model loads that fail for reasons that go away on their own.
+//!
+//! A model on a network filesystem or one still being copied can fail to
+//! load with a busy file or a short read, and the same load succeeds a
+//! moment later. [`load_with_retry`] retries those failures with backoff and
+//! wraps the final error in a [`LoadError`] whose [`LoadErrorKind`] says
+//! whether retrying, re-downloading or a different model would help.
+
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+
+/// Text of errors from model formats, quantization types or architectures
+/// this build cannot read.
+const UNSUPPORTED_MARKERS: &[&str] = &[
+    "unsupported",
+    "not supported",
+    "unknown dtype",
+    "unknown magic",
+];
+
+/// Why a model failed to load.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoadErrorKind {
+    /// The file was busy, changed while it was read, or the read timed out.
+    /// Loading again later may succeed.
+    Transient,
+    /// The file is truncated or malformed. Download it again.
+    Corrupt,
+    /// The file is valid but uses a format, quantization or architecture
+    /// this build does not support.
+    Unsupported,
+    /// Anything else, e.g. a missing file or denied permission.
+    Other,
+}
+
+impl LoadErrorKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LoadErrorKind::Transient => "transient",
+            LoadErrorKind::Corrupt => "corrupt",
+            LoadErrorKind::Unsupported => "unsupported",
+            LoadErrorKind::Other => "other",
+        }
+    }
+
+    /// What a user can do about it, or `None` when the error says it all.
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            LoadErrorKind::Transient => {
+                Some("The model file could not be read reliably; check the disk or network share and try again.")
+            }
+            LoadErrorKind::Corrupt => Some(
+                "The model file looks damaged or incomplete; download it again or run `oxide-rs check`.",
+            ),
+            LoadErrorKind::Unsupported => {
+                Some("This model format or architecture is not supported by this build.")
+            }
+            LoadErrorKind::Other => None,
+        }
+    }
+}
+
+impl fmt::Display for LoadErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A model load that failed, classified. Callers can downcast to it from
+/// the error [`load_with_retry`] returns.
+#[derive(Debug)]
+pub struct LoadError {
+    pub kind: LoadErrorKind,
+    /// Attempts made, including the first.
+    pub attempts: usize,
+    source: anyhow::Error,
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            LoadErrorKind::Transient => write!(
+                f,
+                "Model load still failing after {} attempts",
+                self.attempts
+            ),
+            LoadErrorKind::Corrupt => write!(f, "Model file is damaged or incomplete"),
+            LoadErrorKind::Unsupported => write!(f, "Model file is not supported"),
+            LoadErrorKind::Other => write!(f, "Failed to load model"),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.source.as_ref())
+    }
+}
+
+/// How often and how patiently a transient load failure is retried.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LoadRetry {
+    /// Retries after the first attempt; `0` disables retrying.
+    pub retries: usize,
+    /// Wait before the first retry, doubled for each one after it.
+    pub backoff: Duration,
+    /// Longest wait between attempts.
+    pub max_backoff: Duration,
+}
+
+impl LoadRetry {
+    /// `retries` retries with the default backoff.
+    pub fn new(retries: usize) -> Self {
+        Self {
+            retries,
+            ..Self::default()
+        }
+    }
+
+    fn delay(&self, retry: usize) -> Duration {
+        let factor = 1u32 << retry.min(16);
+        self.backoff.saturating_mul(factor).min(self.max_backoff)
+    }
+}
+
+impl Default for LoadRetry {
+    fn default() -> Self {
+        Self {
+            retries: 2,
+            backoff: Duration::from_millis(250),
+            max_backoff: Duration::from_secs(4),
+        }
+    }
+}
+
+/// Runs `load` until it succeeds, fails for a reason other than a transient
+/// one, or runs out of retries. Errors are returned as a [`LoadError`].
+///
+/// A truncated or malformed read counts as transient when the size or
+/// modification time of `path` changed during the attempt: the file was
+/// still being written or synced.
+pub fn load_with_retry<T>(
+    path: &Path,
+    retry: &LoadRetry,
+    mut load: impl FnMut() -> Result<T>,
+) -> Result<T> {
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let before = file_stamp(path);
+        let err = match load() {
+            Ok(value) => return Ok(value),
+            Err(err) => err,
+        };
+        let mut kind = classify(&err);
+        if kind == LoadErrorKind::Corrupt && before.is_some() && file_stamp(path) != before {
+            kind = LoadErrorKind::Transient;
+        }
+        if kind != LoadErrorKind::Transient || attempts > retry.retries {
+            return Err(LoadError {
+                kind,
+                attempts,
+                source: err,
+            }
+            .into());
+        }
+        let delay = retry.delay(attempts - 1);
+        tracing::warn!(
+            "Model load failed ({:#}); retrying in {} ms",
+            err,
+            delay.as_millis()
+        );
+        std::thread::sleep(delay);
+    }
+}
+
+/// Classifies a failed load by the first I/O error in its chain, or by its
+/// message when there is none.
+pub fn classify(err: &anyhow::Error) -> LoadErrorKind {
+    if let Some(io) = err.chain().find_map(io_error) {
+        return classify_io(io);
+    }
+    let text = format!("{:#}", err).to_ascii_lowercase();
+    if UNSUPPORTED_MARKERS.iter().any(|m| text.contains(m)) {
+        LoadErrorKind::Unsupported
+    } else {
+        LoadErrorKind::Corrupt
+    }
+}
+
+fn io_error<'a>(cause: &'a (dyn std::error::Error + 'static)) -> Option<&'a io::Error> {
+    if let Some(io) = cause.downcast_ref::<io::Error>() {
+        return Some(io);
+    }
+    cause
+        .downcast_ref::<candle_core::Error>()
+        .and_then(candle_io_error)
+}
+
+fn candle_io_error(err: &candle_core::Error) -> Option<&io::Error> {
+    use candle_core::Error;
+    match err {
+        Error::Io(io) => Some(io),
+        Error::Context { inner, .. }
+        | Error::WithPath { inner, .. }
+        | Error::WithBacktrace { inner, .. } => candle_io_error(inner),
+        _ => None,
+    }
+}
+
+fn classify_io(err: &io::Error) -> LoadErrorKind {
+    match err.kind() {
+        io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+            return LoadErrorKind::Transient
+        }
+        io::ErrorKind::UnexpectedEof | io::ErrorKind::InvalidData => return LoadErrorKind::Corrupt,
+        _ => {}
+    }
+    match err.raw_os_error() {
+        Some(code) if TRANSIENT_OS_ERRORS.contains(&code) => LoadErrorKind::Transient,
+        _ => LoadErrorKind::Other,
+    }
+}
+
+/// Busy files, stale network handles and I/O errors on network shares.
+#[cfg(unix)]
+const TRANSIENT_OS_ERRORS: &[i32] = &[
+    libc::EBUSY,
+    libc::ETXTBSY,
+    libc::EAGAIN,
+    libc::ESTALE,
+    libc::EIO,
+];
+
+/// `ERROR_SHARING_VIOLATION`, `ERROR_LOCK_VIOLATION` and
+/// `ERROR_NETNAME_DELETED`.
+#[cfg(windows)]
+const TRANSIENT_OS_ERRORS: &[i32] = &[32, 33, 64];
+
+#[cfg(not(any(unix, windows)))]
+const TRANSIENT_OS_ERRORS: &[i32] = &[];
+
+fn file_stamp(path: &Path) -> Option<(u64, Option<SystemTime>)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.len(), metadata.modified().ok()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::path::Path;
+    use std::time::Duration;
+
+    use anyhow::Context;
+
+    use super::{classify, load_with_retry, LoadError, LoadErrorKind, LoadRetry};
+
+    #[test]
+    fn retries_only_transient_failures() {
+        let busy = || anyhow::Error::from(io::Error::from(io::ErrorKind::WouldBlock));
+        let truncated = || {
+            anyhow::Error::from(candle_core::Error::Io(io::ErrorKind::UnexpectedEof.into()))
+                .context("Failed to read GGUF file")
+        };
+        assert_eq!(classify(&busy()), LoadErrorKind::Transient);
+        assert_eq!(classify(&truncated()), LoadErrorKind::Corrupt);
+        let dtype = Err::<(), _>(anyhow::anyhow!("unknown dtype for tensor 42"))
+            .context("Failed to load LLaMA model weights from GGUF")
+            .unwrap_err();
+        assert_eq!(classify(&dtype), LoadErrorKind::Unsupported);
+        let missing = anyhow::Error::from(io::Error::from(io::ErrorKind::NotFound));
+        assert_eq!(classify(&missing), LoadErrorKind::Other);
+
+        let retry = LoadRetry {
+            retries: 2,
+            backoff: Duration::ZERO,
+            max_backoff: Duration::ZERO,
+        };
+        let path = Path::new("does-not-exist.gguf");
+        let mut calls = 0;
+        let loaded = load_with_retry(path, &retry, || {
+            calls += 1;
+            if calls < 3 {
+                Err(busy())
+            } else {
+                Ok(calls)
+            }
+        });
+        assert_eq!(loaded.unwrap(), 3);
+
+        let mut calls = 0;
+        let err = load_with_retry(path, &retry, || -> anyhow::Result<()> {
+            calls += 1;
+            Err(truncated())
+        })
+        .unwrap_err();
+        assert_eq!(calls, 1);
+        let err = err.downcast_ref::<LoadError>().unwrap();
+        assert_eq!((err.kind, err.attempts), (LoadErrorKind::Corrupt, 1));
+
+        let err =
+            load_with_retry(path, &retry, || -> anyhow::Result<()> { Err(busy()) }).unwrap_err();
+        let err = err.downcast_ref::<LoadError>().unwrap();
+        assert_eq!((err.kind, err.attempts), (LoadErrorKind::Transient, 3));
+        assert_eq!(LoadRetry::default().delay(10), Duration::from_secs(4));
+    }
+}
@@ -4,14 +4,23 @@ use std::path::PathBuf;
 use anyhow::Result;
 use candle_core::quantized::gguf_file;
 use memmap2::Mmap;
+use rayon::prelude::*;
 use sha2::{Digest, Sha256};
-use shimmytok::Tokenizer as ShimmyTokenizer;
+use shimmytok::{EncodeOptions, Tokenizer as ShimmyTokenizer};
 
 const CACHE_DIR: &str = ".cache/oxide";
 
+/// Most threads [`TokenizerWrapper::encode_batch`] spreads a batch over.
+pub const TOKENIZER_THREADS: usize = 4;
+
 pub struct TokenizerWrapper {
     inner: ShimmyTokenizer,
     eos_token_id: u32,
+    bos_token_id: u32,
+    /// `tokenizer.ggml.add_bos_token`, defaulted per tokenizer model like llama.cpp.
+    add_bos_token: bool,
+    /// `tokenizer.ggml.add_eos_token`, off unless the GGUF asks for it.
+    add_eos_token: bool,
     pending_tokens: Vec<u32>,
     cached_decoded: String,
 }
@@ -30,13 +39,37 @@ fn get_cache_path(model_path: &PathBuf) -> Result<PathBuf> {
 
     let hash = format!("{:x}", hasher.finalize());
 
-    let home = std::env::var("HOME").unwrap_or_else(|_| ".".to_string());
-    let cache_dir = PathBuf::from(home).join(CACHE_DIR);
+    let cache_dir = crate::platform::home_dir().join(CACHE_DIR);
     fs::create_dir_all(&cache_dir)?;
 
     Ok(cache_dir.join(format!("{}.tokenizer_cache", hash)))
 }
 
+/// Reads the `(add_bos, add_eos)` flags from the GGUF metadata.
+///
+/// Uses `tokenizer.ggml.add_bos_token`/`add_eos_token` when present;
+/// otherwise follows llama.cpp's per-model defaults (SentencePiece adds BOS,
+/// GPT-2 style BPE does not, and neither adds EOS).
+fn read_special_token_flags(path: &PathBuf) -> Result<(bool, bool)> {
+    let file = File::open(path)?;
+    let mmap = unsafe { Mmap::map(&file)? };
+    let mut cursor = std::io::Cursor::new(&mmap);
+
+    let content = gguf_file::Content::read(&mut cursor)
+        .map_err(|e| anyhow::anyhow!("Failed to read GGUF: {}", e))?;
+    let md = &content.metadata;
+
+    let flag = |key: &str| md.get(key).and_then(|v| v.to_bool().ok());
+
+    let model = md
+        .get("tokenizer.ggml.model")
+        .and_then(|v| v.to_string().ok())
+        .map(String::as_str);
+    let add_bos = flag("tokenizer.ggml.add_bos_token").unwrap_or(!matches!(model, Some("gpt2")));
+    let add_eos = flag("tokenizer.ggml.add_eos_token").unwrap_or(false);
+    Ok((add_bos, add_eos))
+}
+
 fn extract_tokenizer_json(path: &PathBuf) -> Result<Option<String>> {
     let file = File::open(path)?;
     let mmap = unsafe { Mmap::map(&file)? };
@@ -101,11 +134,20 @@ impl TokenizerWrapper {
         };
 
         let eos_token_id = inner.eos_token();
-        tracing::info!("Loaded tokenizer, EOS={}", eos_token_id);
+        let (add_bos_token, add_eos_token) =
+            read_special_token_flags(path).unwrap_or((true, false));
+        tracing::info!(
+            "Loaded tokenizer, EOS={}, add_bos={}",
+            eos_token_id,
+            add_bos_token
+        );
 
         Ok(Self {
+            bos_token_id: inner.bos_token(),
             inner,
             eos_token_id,
+            add_bos_token,
+            add_eos_token,
             pending_tokens: Vec::new(),
             cached_decoded: String::new(),
         })
@@ -116,21 +158,68 @@ impl TokenizerWrapper {
             .map_err(|e| anyhow::anyhow!("Failed to load tokenizer: {}", e))?;
 
         let eos_token_id = inner.eos_token();
+        let (add_bos_token, add_eos_token) =
+            read_special_token_flags(path).unwrap_or((true, false));
 
-        tracing::info!("Loaded tokenizer from file, EOS={}", eos_token_id);
+        tracing::info!(
+            "Loaded tokenizer from file, EOS={}, add_bos={}",
+            eos_token_id,
+            add_bos_token
+        );
 
         Ok(Self {
+            bos_token_id: inner.bos_token(),
             inner,
             eos_token_id,
+            add_bos_token,
+            add_eos_token,
             pending_tokens: Vec::new(),
             cached_decoded: String::new(),
         })
     }
 
+    /// Encodes `text` with the model's BOS/EOS flags applied.
     pub fn encode(&self, text: &str) -> Result<Vec<u32>> {
-        self.inner
-            .encode(text, true)
-            .map_err(|e| anyhow::anyhow!("Encode failed: {}", e))
+        self.encode_with_options(text, true, false)
+    }
+
+    /// Encodes `text` the way llama.cpp tokenizes prompts.
+    ///
+    /// * `add_bos` - prepend BOS if `tokenizer.ggml.add_bos_token` allows it
+    ///   and the text does not already start with one (e.g. from a chat
+    ///   template that renders `bos_token` itself). Like llama.cpp's
+    ///   `add_special`, this also appends EOS when the GGUF sets
+    ///   `tokenizer.ggml.add_eos_token`.
+    /// * `add_special` - parse special-token text such as `<|im_start|>` into
+    ///   its token id instead of tokenizing it as plain text.
+    pub fn encode_with_options(
+        &self,
+        text: &str,
+        add_bos: bool,
+        add_special: bool,
+    ) -> Result<Vec<u32>> {
+        let mut tokens = self
+            .inner
+            .encode_with_options(text, &EncodeOptions::with_parse_special(false, add_special))
+            .map_err(|e| anyhow::anyhow!("Encode failed: {}", e))?;
+
+        if add_bos && self.add_bos_token && tokens.first() != Some(&self.bos_token_id) {
+            tokens.insert(0, self.bos_token_id);
+        }
+        if add_bos && self.add_eos_token && tokens.last() != Some(&self.eos_token_id) {
+            tokens.push(self.eos_token_id);
+        }
+        Ok(tokens)
+    }
+
+    /// Whether prompts for this model start with BOS.
+    pub fn add_bos_token(&self) -> bool {
+        self.add_bos_token
+    }
+
+    /// Whether encoded text for this model ends with EOS.
+    pub fn add_eos_token(&self) -> bool {
+        self.add_eos_token
     }
 
     pub fn encode_raw(&self, text: &str) -> Result<Vec<u32>> {
@@ -139,12 +228,18 @@ impl TokenizerWrapper {
             .map_err(|e| anyhow::anyhow!("Encode failed: {}", e))
     }
 
-    pub fn encode_batch(&self, texts: &[&str]) -> Result<Vec<Vec<u32>>> {
-        let mut results = Vec::with_capacity(texts.len());
-        for text in texts {
-            results.push(self.encode(text)?);
-        }
-        Ok(results)
+    /// Encodes several rendered chat prompts in parallel on the shared
+    /// thread pool, like [`encode_with_options`](Self::encode_with_options)
+    /// with BOS/EOS and special tokens. Uses at most [`TOKENIZER_THREADS`]
+    /// of its threads so a large batch does not hold up a generation running
+    /// alongside it.
+    pub fn encode_batch<S: AsRef<str> + Sync>(&self, texts: &[S]) -> Result<Vec<Vec<u32>>> {
+        let per_task = ((texts.len() + TOKENIZER_THREADS - 1) / TOKENIZER_THREADS).max(1);
+        texts
+            .par_iter()
+            .with_min_len(per_task)
+            .map(|text| self.encode_with_options(text.as_ref(), true, true))
+            .collect()
     }
 
     pub fn decode(&self, tokens: &[u32]) -> Result<String> {
@@ -165,6 +260,17 @@ impl TokenizerWrapper {
         self.eos_token_id
     }
 
+    pub fn vocab_size(&self) -> usize {
+        self.inner.vocab_size()
+    }
+
+    /// Text of a single token, with byte-fallback and space markers decoded.
+    pub fn token_to_piece(&self, token_id: u32) -> Result<String> {
+        self.inner
+            .token_to_piece(token_id)
+            .map_err(|e| anyhow::anyhow!("Decode failed: {}", e))
+    }
+
     pub fn is_special_token(&self, token_id: u32) -> bool {
         self.inner.is_special_token(token_id)
     }
@@ -204,3 +310,60 @@ impl TokenizerWrapper {
         Ok(result)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use candle_core::quantized::gguf_file::{self, Value};
+
+    use super::TokenizerWrapper;
+
+    /// A SentencePiece vocabulary with a chat special token and EOS added
+    /// after every text.
+    fn tokenizer(dir: &std::path::Path) -> TokenizerWrapper {
+        let path = dir.join("vocab.gguf");
+        let tokens = ["<unk>", "<s>", "</s>", "<|im_start|>", "▁", "h", "i", "▁hi", "<", ">", "|"];
+        let types = [2, 3, 3, 3, 1, 1, 1, 1, 1, 1, 1];
+        let metadata = [
+            ("tokenizer.ggml.model", Value::String("llama".to_string())),
+            (
+                "tokenizer.ggml.tokens",
+                Value::Array(tokens.iter().map(|t| Value::String(t.to_string())).collect()),
+            ),
+            (
+                "tokenizer.ggml.scores",
+                Value::Array((0..tokens.len()).map(|i| Value::F32(-(i as f32))).collect()),
+            ),
+            (
+                "tokenizer.ggml.token_type",
+                Value::Array(types.iter().map(|&t| Value::I32(t)).collect()),
+            ),
+            ("tokenizer.ggml.unknown_token_id", Value::U32(0)),
+            ("tokenizer.ggml.bos_token_id", Value::U32(1)),
+            ("tokenizer.ggml.eos_token_id", Value::U32(2)),
+            ("tokenizer.ggml.add_bos_token", Value::Bool(true)),
+            ("tokenizer.ggml.add_eos_token", Value::Bool(true)),
+        ];
+        let metadata: Vec<_> = metadata.iter().map(|(k, v)| (*k, v)).collect();
+        gguf_file::write(&mut File::create(&path).unwrap(), &metadata, &[]).unwrap();
+        TokenizerWrapper::from_file(&path).unwrap()
+    }
+
+    #[test]
+    fn batch_encodes_like_chat_prompts() {
+        let dir = std::env::temp_dir().join(format!("oxide-tokenizer-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let tokenizer = tokenizer(&dir);
+
+        let texts = ["<|im_start|>hi", "hi hi"];
+        let batch = tokenizer.encode_batch(&texts).unwrap();
+        for (text, tokens) in texts.iter().zip(&batch) {
+            assert_eq!(*tokens, tokenizer.encode_with_options(text, true, true).unwrap());
+        }
+        assert_eq!(batch[0].first(), Some(&1));
+        assert_eq!(batch[0].get(1), Some(&3));
+        assert_eq!(batch[0].last(), Some(&2));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,205 @@
+//! OS-specific calls behind portable wrappers.
+//!
+//! Everything that needs `libc` or a platform API lives here so the rest of
+//! the crate builds unchanged on Linux, macOS and Windows. Unsupported
+//! platforms get a no-op that reports it did nothing.
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Hints the kernel that a model mapping will be read front to back soon.
+/// Returns whether any hint was applied.
+pub fn advise_sequential(data: &[u8]) -> bool {
+    #[cfg(unix)]
+    {
+        let ptr = data.as_ptr() as *mut libc::c_void;
+        let size = data.len();
+        unsafe {
+            libc::madvise(ptr, size, libc::MADV_SEQUENTIAL);
+            #[cfg(target_os = "linux")]
+            libc::madvise(ptr, size, libc::MADV_HUGEPAGE);
+            libc::madvise(ptr, size, libc::MADV_WILLNEED);
+        }
+        true
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = data;
+        false
+    }
+}
+
+/// Pins the calling thread to `core_id`. Returns `false` when pinning failed
+/// or is not supported on this platform.
+pub fn pin_current_thread(core_id: usize) -> bool {
+    #[cfg(target_os = "linux")]
+    {
+        let mut cpuset: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+        unsafe {
+            libc::CPU_SET(core_id, &mut cpuset);
+            libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &cpuset) == 0
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = core_id;
+        false
+    }
+}
+
+/// Whether [`pin_current_thread`] can work on this platform.
+pub const fn supports_thread_pinning() -> bool {
+    cfg!(target_os = "linux")
+}
+
+/// The user's home directory, falling back to the current directory.
+pub fn home_dir() -> PathBuf {
+    dirs::home_dir().unwrap_or_else(|| PathBuf::from("."))
+}
+
+/// Makes raw ANSI escape sequences work in the terminal. On Windows this
+/// turns on virtual terminal processing for the console; elsewhere ANSI is
+/// always available. Returns whether escape sequences are supported.
+pub fn enable_ansi() -> bool {
+    #[cfg(windows)]
+    {
+        crossterm::ansi_support::supports_ansi()
+    }
+    #[cfg(not(windows))]
+    {
+        true
+    }
+}
+
+/// Set by the SIGINT handler while [`catch_interrupts`] is on.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+/// Set by the SIGCONT handler installed by [`watch_resume`].
+static RESUMED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn on_sigint(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+#[cfg(unix)]
+extern "C" fn on_sigcont(_: libc::c_int) {
+    RESUMED.store(true, Ordering::SeqCst);
+}
+
+#[cfg(unix)]
+fn set_signal_handler(signal: libc::c_int, handler: libc::sighandler_t) -> bool {
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = handler;
+        // Restart interrupted reads so a signal never surfaces as an I/O error.
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(signal, &action, std::ptr::null_mut()) == 0
+    }
+}
+
+/// While `catch` is true, Ctrl+C (SIGINT) sets a flag read by
+/// [`take_interrupt`] instead of ending the process. Returns whether the
+/// handler could be changed.
+pub fn catch_interrupts(catch: bool) -> bool {
+    INTERRUPTED.store(false, Ordering::SeqCst);
+    #[cfg(unix)]
+    {
+        let handler = if catch {
+            on_sigint as extern "C" fn(libc::c_int) as libc::sighandler_t
+        } else {
+            libc::SIG_DFL
+        };
+        set_signal_handler(libc::SIGINT, handler)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = catch;
+        false
+    }
+}
+
+/// Whether Ctrl+C was pressed since the last call.
+pub fn take_interrupt() -> bool {
+    INTERRUPTED.swap(false, Ordering::SeqCst)
+}
+
+/// Notes every SIGCONT (the process resumed after being stopped) for
+/// [`take_resumed`]. Returns whether job control signals are available.
+pub fn watch_resume() -> bool {
+    #[cfg(unix)]
+    {
+        set_signal_handler(
+            libc::SIGCONT,
+            on_sigcont as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        )
+    }
+    #[cfg(not(unix))]
+    {
+        false
+    }
+}
+
+/// Whether the process was resumed since the last call.
+pub fn take_resumed() -> bool {
+    RESUMED.swap(false, Ordering::SeqCst)
+}
+
+/// Stops the process the way Ctrl+Z does in a cooked terminal, returning
+/// once it is continued with `fg`. Returns `false` without job control.
+pub fn suspend_process() -> bool {
+    #[cfg(unix)]
+    unsafe {
+        libc::raise(libc::SIGTSTP) == 0
+    }
+    #[cfg(not(unix))]
+    {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{advise_sequential, catch_interrupts, home_dir, take_interrupt};
+
+    #[test]
+    fn portable_helpers_do_not_fail() {
+        let data = vec![0u8; 4096];
+        assert_eq!(advise_sequential(&data), cfg!(unix));
+        assert!(!home_dir().as_os_str().is_empty());
+    }
+
+    #[test]
+    fn interrupts_are_caught_while_enabled() {
+        assert_eq!(catch_interrupts(true), cfg!(unix));
+        #[cfg(unix)]
+        unsafe {
+            libc::raise(libc::SIGINT);
+        }
+        assert_eq!(take_interrupt(), cfg!(unix));
+        assert!(!take_interrupt());
+        catch_interrupts(false);
+    }
+}
+
+#[cfg(all(test, windows))]
+mod windows_tests {
+    use super::{enable_ansi, pin_current_thread, supports_thread_pinning};
+
+    #[test]
+    fn thread_pinning_is_a_no_op() {
+        assert!(!supports_thread_pinning());
+        assert!(!pin_current_thread(0));
+    }
+
+    #[test]
+    fn ansi_setup_is_idempotent() {
+        assert_eq!(enable_ansi(), enable_ansi());
+    }
+
+    #[test]
+    fn model_paths_use_native_separators() {
+        let path = crate::model::download::get_oxide_dir().unwrap().join("models.json");
+        assert!(path.to_string_lossy().contains('\\'));
+    }
+}
@@ -0,0 +1,224 @@
+//! Response cache for repeated identical chat completion requests.
+//!
+//! Requests are keyed by a SHA-256 of everything that shapes the reply:
+//! model, messages, sampling parameters, seed, tools and response format.
+//! `stream`, `stream_granularity` and `user` are left out, so a streamed
+//! request can be answered from a non-streamed one. Entries expire after a
+//! TTL and the oldest entry is evicted when the cache is full.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use axum::http::{header, HeaderMap};
+use sha2::{Digest, Sha256};
+
+use crate::server::types::{ChatCompletionRequest, ChatCompletionResponse};
+
+#[derive(Clone, Debug)]
+pub struct CacheConfig {
+    /// How long a response is served from the cache.
+    pub ttl: Duration,
+    /// Largest number of cached responses.
+    pub max_entries: usize,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            ttl: Duration::from_secs(300),
+            max_entries: 256,
+        }
+    }
+}
+
+/// What a request's `Cache-Control` header allows.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheControl {
+    /// `no-cache`: do not answer from the cache.
+    pub skip_lookup: bool,
+    /// `no-store`: do not cache the reply either.
+    pub skip_store: bool,
+}
+
+impl CacheControl {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let mut control = Self::default();
+        let directives = headers
+            .get_all(header::CACHE_CONTROL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|directive| directive.trim().to_ascii_lowercase());
+        for directive in directives {
+            match directive.as_str() {
+                "no-cache" => control.skip_lookup = true,
+                "no-store" => {
+                    control.skip_lookup = true;
+                    control.skip_store = true;
+                }
+                _ => {}
+            }
+        }
+        control
+    }
+}
+
+/// Cache key of a request.
+pub fn cache_key(req: &ChatCompletionRequest) -> String {
+    let mut value = serde_json::to_value(req).unwrap_or_default();
+    if let Some(fields) = value.as_object_mut() {
+        fields.remove("stream");
+        fields.remove("user");
+        fields.remove("stream_granularity");
+    }
+    Sha256::digest(value.to_string().as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[derive(Debug)]
+struct Entry {
+    stored_at: Instant,
+    response: ChatCompletionResponse,
+}
+
+#[derive(Debug)]
+pub struct ResponseCache {
+    config: CacheConfig,
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl ResponseCache {
+    pub fn new(config: CacheConfig) -> Self {
+        Self {
+            config,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn config(&self) -> &CacheConfig {
+        &self.config
+    }
+
+    /// A cached response for `key` and its age, if one is still fresh.
+    pub fn get(&self, key: &str) -> Option<(ChatCompletionResponse, Duration)> {
+        let mut entries = self.entries.lock().unwrap();
+        let age = entries.get(key)?.stored_at.elapsed();
+        if age > self.config.ttl {
+            entries.remove(key);
+            return None;
+        }
+        Some((entries[key].response.clone(), age))
+    }
+
+    pub fn insert(&self, key: String, response: ChatCompletionResponse) {
+        if self.config.max_entries == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        let ttl = self.config.ttl;
+        entries.retain(|_, entry| entry.stored_at.elapsed() <= ttl);
+        if entries.len() >= self.config.max_entries && !entries.contains_key(&key) {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.stored_at)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(
+            key,
+            Entry {
+                stored_at: Instant::now(),
+                response,
+            },
+        );
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use axum::http::HeaderMap;
+
+    use super::{cache_key, CacheConfig, CacheControl, ResponseCache};
+    use crate::server::types::{ChatCompletionRequest, ChatCompletionResponse, Usage};
+
+    fn request(body: serde_json::Value) -> ChatCompletionRequest {
+        serde_json::from_value(body).unwrap()
+    }
+
+    fn response(id: &str) -> ChatCompletionResponse {
+        ChatCompletionResponse {
+            id: id.to_string(),
+            object: "chat.completion".to_string(),
+            created: 0,
+            model: "m.gguf".to_string(),
+            choices: Vec::new(),
+            usage: Usage::new(1, 1),
+            language: None,
+            context_truncated: None,
+            moderation: None,
+        }
+    }
+
+    #[test]
+    fn keys_ignore_stream_and_user() {
+        let messages = serde_json::json!([{ "role": "user", "content": "hi" }]);
+        let a = request(serde_json::json!({ "model": "m", "messages": messages }));
+        let b = request(serde_json::json!({
+            "model": "m", "messages": messages, "stream": true, "user": "bob"
+        }));
+        let c = request(serde_json::json!({ "model": "m", "messages": messages, "seed": 1 }));
+        assert_eq!(cache_key(&a), cache_key(&b));
+        assert_ne!(cache_key(&a), cache_key(&c));
+    }
+
+    #[test]
+    fn expires_and_evicts_oldest() {
+        let cache = ResponseCache::new(CacheConfig {
+            ttl: Duration::from_secs(60),
+            max_entries: 2,
+        });
+        cache.insert("a".into(), response("1"));
+        cache.insert("b".into(), response("2"));
+        cache.insert("c".into(), response("3"));
+        assert!(cache.get("a").is_none());
+        assert_eq!(cache.get("c").unwrap().0.id, "3");
+        assert_eq!(cache.len(), 2);
+
+        let expired = ResponseCache::new(CacheConfig {
+            ttl: Duration::ZERO,
+            max_entries: 2,
+        });
+        expired.insert("a".into(), response("1"));
+        std::thread::sleep(Duration::from_millis(2));
+        assert!(expired.get("a").is_none());
+    }
+
+    #[test]
+    fn parses_cache_control() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(
+            CacheControl::from_headers(&headers),
+            CacheControl::default()
+        );
+        headers.insert("cache-control", "max-age=0, No-Cache".parse().unwrap());
+        let control = CacheControl::from_headers(&headers);
+        assert!(control.skip_lookup && !control.skip_store);
+        headers.insert("cache-control", "no-store".parse().unwrap());
+        assert!(CacheControl::from_headers(&headers).skip_store);
+    }
+}
@@ -5,9 +5,14 @@ use axum::{
 };
 use serde::Serialize;
 
+use crate::inference::ContextOverflow;
+use crate::server::quota::QuotaError;
+
 #[derive(Debug, Serialize)]
 pub struct OpenAIError {
     pub error: ErrorDetail,
+    #[serde(skip)]
+    pub status: StatusCode,
 }
 
 #[derive(Debug, Serialize)]
@@ -28,6 +33,7 @@ impl OpenAIError {
                 param: None,
                 code: None,
             },
+            status: StatusCode::BAD_REQUEST,
         }
     }
 
@@ -39,6 +45,7 @@ impl OpenAIError {
                 param: None,
                 code: None,
             },
+            status: StatusCode::BAD_REQUEST,
         }
     }
 
@@ -50,19 +57,89 @@ impl OpenAIError {
                 param: Some("model".to_string()),
                 code: None,
             },
+            status: StatusCode::BAD_REQUEST,
+        }
+    }
+
+    pub fn invalid_param(param: &str, message: &str) -> Self {
+        Self {
+            error: ErrorDetail {
+                message: message.to_string(),
+                error_type: "invalid_request_error".to_string(),
+                param: Some(param.to_string()),
+                code: None,
+            },
+            status: StatusCode::BAD_REQUEST,
+        }
+    }
+
+    pub fn unauthorized(message: &str) -> Self {
+        Self {
+            error: ErrorDetail {
+                message: message.to_string(),
+                error_type: "invalid_request_error".to_string(),
+                param: None,
+                code: Some("invalid_api_key".to_string()),
+            },
+            status: StatusCode::UNAUTHORIZED,
         }
     }
 }
 
 impl IntoResponse for OpenAIError {
     fn into_response(self) -> Response {
-        (StatusCode::BAD_REQUEST, Json(self)).into_response()
+        (self.status, Json(self)).into_response()
+    }
+}
+
+impl From<QuotaError> for OpenAIError {
+    fn from(err: QuotaError) -> Self {
+        let (error_type, code, param, status) = match err {
+            QuotaError::PromptTooLong { .. } => (
+                "invalid_request_error",
+                "context_length_exceeded",
+                Some("messages".to_string()),
+                StatusCode::BAD_REQUEST,
+            ),
+            QuotaError::SessionQuotaExceeded { .. } => (
+                "insufficient_quota",
+                "insufficient_quota",
+                None,
+                StatusCode::TOO_MANY_REQUESTS,
+            ),
+        };
+        Self {
+            error: ErrorDetail {
+                message: err.to_string(),
+                error_type: error_type.to_string(),
+                param,
+                code: Some(code.to_string()),
+            },
+            status,
+        }
+    }
+}
+
+impl From<ContextOverflow> for OpenAIError {
+    fn from(err: ContextOverflow) -> Self {
+        Self {
+            error: ErrorDetail {
+                message: err.to_string(),
+                error_type: "invalid_request_error".to_string(),
+                param: Some("messages".to_string()),
+                code: Some("context_length_exceeded".to_string()),
+            },
+            status: StatusCode::BAD_REQUEST,
+        }
     }
 }
 
 impl From<anyhow::Error> for OpenAIError {
     fn from(err: anyhow::Error) -> Self {
-        OpenAIError::internal(&err.to_string())
+        match err.downcast::<ContextOverflow>() {
+            Ok(overflow) => overflow.into(),
+            Err(err) => OpenAIError::internal(&err.to_string()),
+        }
     }
 }
 
@@ -0,0 +1,100 @@
+use std::sync::Arc;
+
+use axum::{extract::State, http::HeaderMap, Json};
+use serde::{Deserialize, Serialize};
+
+use super::bearer_token;
+use crate::server::error::OpenAIError;
+use crate::server::state::AppState;
+
+#[derive(Debug, Deserialize)]
+pub struct LoadModelRequest {
+    pub model: String,
+    /// Tokens run through the model after loading; `0` skips warmup.
+    #[serde(default)]
+    pub warmup_tokens: usize,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UnloadModelRequest {
+    pub model: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AdminModelResponse {
+    pub model: String,
+    /// `loaded`, `unloaded`, or `not_loaded`.
+    pub status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub load_time_ms: Option<u128>,
+}
+
+fn authorize(state: &AppState, headers: &HeaderMap) -> Result<(), OpenAIError> {
+    let expected = state
+        .admin_token()
+        .ok_or_else(|| OpenAIError::unauthorized("Admin endpoints are disabled"))?;
+    if bearer_token(headers) == Some(expected) {
+        Ok(())
+    } else {
+        Err(OpenAIError::unauthorized("Invalid admin token"))
+    }
+}
+
+pub async fn load_model(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(req): Json<LoadModelRequest>,
+) -> Result<Json<AdminModelResponse>, OpenAIError> {
+    authorize(&state, &headers)?;
+    let start = std::time::Instant::now();
+    state
+        .preload_model(&req.model, req.warmup_tokens)
+        .await
+        .map_err(|e| OpenAIError::invalid_model(&e.to_string()))?;
+
+    Ok(Json(AdminModelResponse {
+        model: req.model,
+        status: "loaded".to_string(),
+        load_time_ms: Some(start.elapsed().as_millis()),
+    }))
+}
+
+pub async fn unload_model(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(req): Json<UnloadModelRequest>,
+) -> Result<Json<AdminModelResponse>, OpenAIError> {
+    authorize(&state, &headers)?;
+    let status = if state.unload_model(&req.model).await {
+        "unloaded"
+    } else {
+        "not_loaded"
+    };
+
+    Ok(Json(AdminModelResponse {
+        model: req.model,
+        status: status.to_string(),
+        load_time_ms: None,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::authorize;
+    use crate::server::state::AppState;
+    use axum::http::HeaderMap;
+
+    #[test]
+    fn admin_requires_configured_bearer_token() {
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", "Bearer secret".parse().unwrap());
+
+        assert!(authorize(&AppState::new(), &headers).is_err());
+
+        let state = AppState::new().with_admin_token("secret");
+        assert!(authorize(&state, &headers).is_ok());
+        assert!(authorize(&state, &HeaderMap::new()).is_err());
+        headers.insert("authorization", "Bearer wrong".parse().unwrap());
+        assert!(authorize(&state, &headers).is_err());
+    }
+}
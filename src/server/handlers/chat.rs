@@ -1,25 +1,54 @@
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 use axum::{
     extract::State,
-    response::{sse::Event, sse::Sse, IntoResponse, Response},
+    http::HeaderMap,
+    response::{
+        sse::{Event, KeepAlive, KeepAliveStream, Sse},
+        IntoResponse, Response,
+    },
     Json,
 };
 use tokio::sync::mpsc;
 use tokio_stream::wrappers::ReceiverStream;
 
-use crate::inference::StreamEvent;
+use super::usage::request_key;
+use crate::inference::{
+    detect_language, CancelToken, ContextOverflow, Generator, ModerationStream, StreamChunker,
+    StreamEvent,
+};
+use crate::server::cache::{cache_key, CacheControl};
 use crate::server::error::OpenAIError;
+use crate::server::metrics::RequestTiming;
+use crate::server::quota::ANONYMOUS_SESSION;
 use crate::server::state::AppState;
+use crate::server::structured::{Reply, ReplyFormat, MAX_ATTEMPTS};
 use crate::server::types::{
-    ChatCompletionChunk, ChatCompletionRequest, ChatCompletionResponse, ChunkChoice, Choice,
-    Delta, Usage, create_completion_id, get_timestamp,
+    ChatCompletionChunk, ChatCompletionMessage, ChatCompletionRequest, ChatCompletionResponse,
+    ChoiceLogprobs, ChunkChoice, Choice, ContextTruncation, Delta, DeltaToolCall, Usage,
+    create_completion_id, get_timestamp,
 };
 
+/// OpenAI's upper bound for `top_logprobs`.
+const MAX_TOP_LOGPROBS: usize = 20;
+
+/// How often an idle stream sends a `: ping` comment, so proxies do not
+/// close it during long prefills.
+const HEARTBEAT_INTERVAL: std::time::Duration = std::time::Duration::from_secs(15);
+
+type EventStream = Sse<KeepAliveStream<ReceiverStream<Result<Event, std::convert::Infallible>>>>;
+
+fn event_stream(rx: mpsc::Receiver<Result<Event, std::convert::Infallible>>) -> EventStream {
+    Sse::new(ReceiverStream::new(rx))
+        .keep_alive(KeepAlive::new().interval(HEARTBEAT_INTERVAL).text("ping"))
+}
+
 pub async fn chat_completions(
     State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
     Json(req): Json<ChatCompletionRequest>,
-) -> Result<impl axum::response::IntoResponse, OpenAIError> {
+) -> Result<Response, OpenAIError> {
+    let key = request_key(&headers);
     let is_streaming = req.stream.unwrap_or(false);
     let request_id = create_completion_id();
     let model_path = req.model.clone();
@@ -35,16 +64,75 @@ pub async fn chat_completions(
         is_streaming
     );
 
-    if is_streaming {
-        Ok(ChatResponse::Streaming(handle_streaming(state, req, request_id).await?))
+    let reply_format = ReplyFormat::from_request(&req)?;
+    let logprobs = logprobs_request(&req)?;
+
+    let control = CacheControl::from_headers(&headers);
+    let cache_enabled = state.response_cache().is_some();
+    let cached_key = cache_enabled.then(|| cache_key(&req));
+    let mut cache_status = "BYPASS";
+    if let (Some(cache), Some(cache_key)) = (state.response_cache(), cached_key.as_deref()) {
+        if !control.skip_lookup {
+            let hit = cache.get(cache_key);
+            state.metrics().record_cache_lookup(hit.is_some());
+            if let Some((mut response, age)) = hit {
+                // A cached reply costs what it cost when it was generated.
+                let session = session_id(&req);
+                let usage = &response.usage;
+                state.quotas().admit(&session, usage.prompt_tokens, max_tokens)?;
+                state.quotas().record(&session, usage.total_tokens);
+                let tokens = (usage.prompt_tokens, usage.completion_tokens);
+                state.usage().record(&key, &req.model, tokens.0, tokens.1);
+                tracing::info!("[{}] Served from response cache", &request_id[..8]);
+                response.id = create_completion_id();
+                response.created = get_timestamp();
+                let body = if is_streaming {
+                    ChatResponse::Streaming(buffered_stream(response))
+                } else {
+                    ChatResponse::NonStreaming(Json(response))
+                };
+                let age = age.as_secs().to_string();
+                return Ok(([("x-cache", "HIT"), ("age", age.as_str())], body).into_response());
+            }
+            cache_status = "MISS";
+        }
+    }
+    let store_key = cached_key.filter(|_| !control.skip_store);
+
+    // Structured replies are validated before anything is sent, and logprobs
+    // are collected per call, so those streams are sent once complete.
+    let body = if is_streaming && (reply_format.is_some() || logprobs.is_some()) {
+        let response =
+            handle_non_streaming(state.clone(), req, request_id, key, reply_format, logprobs)
+                .await?;
+        store_response(&state, store_key, &response.0);
+        ChatResponse::Streaming(buffered_stream(response.0))
+    } else if is_streaming {
+        ChatResponse::Streaming(handle_streaming(state, req, request_id, key, store_key).await?)
+    } else {
+        let response =
+            handle_non_streaming(state.clone(), req, request_id, key, reply_format, logprobs)
+                .await?;
+        store_response(&state, store_key, &response.0);
+        ChatResponse::NonStreaming(response)
+    };
+
+    if cache_enabled {
+        Ok(([("x-cache", cache_status)], body).into_response())
     } else {
-        Ok(ChatResponse::NonStreaming(handle_non_streaming(state, req, request_id).await?))
+        Ok(body.into_response())
+    }
+}
+
+fn store_response(state: &AppState, key: Option<String>, response: &ChatCompletionResponse) {
+    if let (Some(cache), Some(key)) = (state.response_cache(), key) {
+        cache.insert(key, response.clone());
     }
 }
 
 enum ChatResponse {
     NonStreaming(Json<ChatCompletionResponse>),
-    Streaming(Sse<ReceiverStream<Result<Event, std::convert::Infallible>>>),
+    Streaming(EventStream),
 }
 
 impl IntoResponse for ChatResponse {
@@ -60,49 +148,148 @@ async fn handle_non_streaming(
     state: Arc<AppState>,
     req: ChatCompletionRequest,
     request_id: String,
+    key: String,
+    reply_format: Option<ReplyFormat>,
+    logprobs: Option<usize>,
 ) -> Result<Json<ChatCompletionResponse>, OpenAIError> {
+    let mut timing = RequestTiming::new(&req.model, req.stream.unwrap_or(false));
     let model_path = &req.model;
     let generator = state.get_or_load_model(model_path).await?;
 
-    let prompt = build_prompt(&req.messages);
+    let prompt = build_prompt(
+        &req.messages,
+        reply_format.as_ref().map(ReplyFormat::instruction),
+    );
+    let forced_language = state.default_options().force_language.as_deref();
+    let language = prompt_language(forced_language, &req.messages);
     let completion_id = create_completion_id();
     let timestamp = get_timestamp();
 
     let repeat_penalty = 1.1f32;
     let repeat_last_n = 64usize;
 
-    let prompt_tokens = prompt.split_whitespace().count();
+    let session = session_id(&req);
+    let (mut prompt_tokens, max_tokens) =
+        admit_request(&state, &generator, &session, &prompt, req.max_tokens).await?;
     let mut completion_tokens = 0;
     let mut generated_text = String::new();
+    let mut context_truncated = None;
+    let mut finish_reason = "stop";
+    let mut moderation = None;
+    let mut reply = None;
+    let mut token_logprobs = None;
 
     tracing::info!(
         "[{}] Generation started | prompt: {} tokens | max: {}",
         &request_id[..8],
         prompt_tokens,
-        req.max_tokens
+        max_tokens
     );
 
     let start_time = std::time::Instant::now();
 
     {
         let mut gen = generator.lock().map_err(|e| OpenAIError::internal(&e.to_string()))?;
-        gen.generate_streaming(
-            &prompt,
-            req.max_tokens,
-            repeat_penalty,
-            repeat_last_n,
-            |event| match event {
-                StreamEvent::Token(token) => {
-                    generated_text.push_str(&token);
-                    completion_tokens += 1;
+        prompt_tokens = exact_prompt_tokens(&gen, &prompt, prompt_tokens);
+        gen.set_logprobs(logprobs);
+        let mark = gen.history_mark();
+        let mut request = prompt.clone();
+        for attempt in 1..=MAX_ATTEMPTS {
+            generated_text.clear();
+            gen.generate_streaming(
+                &request,
+                max_tokens,
+                repeat_penalty,
+                repeat_last_n,
+                |event| match event {
+                    StreamEvent::Token(token) => {
+                        timing.mark_first_token();
+                        generated_text.push_str(&token);
+                        completion_tokens += 1;
+                    }
+                    StreamEvent::ContextTruncated {
+                        dropped_tokens,
+                        strategy,
+                    } => {
+                        tracing::warn!(
+                            "[{}] Context truncated | dropped: {} tokens",
+                            &request_id[..8],
+                            dropped_tokens
+                        );
+                        context_truncated = Some(ContextTruncation {
+                            dropped_tokens,
+                            strategy,
+                        });
+                    }
+                    StreamEvent::PrefillStatus(_) => timing.mark_prefill(),
+                    StreamEvent::LoopDetected { .. }
+                    | StreamEvent::Done
+                    | StreamEvent::Error(_) => {}
+                },
+            )
+            .map_err(OpenAIError::from)?;
+
+            let Some(format) = reply_format.as_ref() else {
+                break;
+            };
+            match format.parse(&generated_text) {
+                Ok(parsed) => {
+                    reply = Some(parsed);
+                    gen.collapse_history_since(mark, &prompt, &generated_text)
+                        .map_err(|e| OpenAIError::internal(&e.to_string()))?;
+                    break;
+                }
+                Err(err) if attempt < MAX_ATTEMPTS => {
+                    tracing::debug!(
+                        "[{}] Structured reply rejected (attempt {}): {}",
+                        &request_id[..8],
+                        attempt,
+                        err
+                    );
+                    request = format!(
+                        "Your reply was invalid: {}. Reply again with only the corrected JSON.",
+                        err
+                    );
                 }
-                StreamEvent::PrefillStatus(_) => {}
-                StreamEvent::Done => {}
-            },
-        )
-        .map_err(|e| OpenAIError::internal(&e.to_string()))?;
+                Err(err) => {
+                    gen.collapse_history_since(mark, &prompt, &generated_text)
+                        .map_err(|e| OpenAIError::internal(&e.to_string()))?;
+                    return Err(OpenAIError::internal(&format!(
+                        "No reply matched the requested format after {} attempts: {}",
+                        MAX_ATTEMPTS, err
+                    )));
+                }
+            }
+        }
+        if gen.loop_detected() {
+            tracing::warn!("[{}] Reply stopped repeating itself", &request_id[..8]);
+            finish_reason = "loop_detected";
+        }
+        state.metrics().record_token_latencies(gen.token_latencies());
+        if logprobs.is_some() {
+            token_logprobs = Some(ChoiceLogprobs::from_tokens(gen.logprobs()));
+            gen.set_logprobs(None);
+        }
+
+        if let Some(ref config) = state.default_options().moderation {
+            let result = config.check(&generated_text);
+            if result.blocked {
+                tracing::warn!("[{}] Reply blocked by moderation", &request_id[..8]);
+                generated_text = config.blocked_message.clone();
+                finish_reason = "content_filter";
+                gen.collapse_last_turns(1, &prompt, &generated_text)
+                    .map_err(|e| OpenAIError::internal(&e.to_string()))?;
+            }
+            moderation = Some(result);
+        }
     }
 
+    state.quotas().record(&session, prompt_tokens + completion_tokens);
+    state.usage().record(&key, &req.model, prompt_tokens, completion_tokens);
+    state.metrics().record_request(prompt_tokens, completion_tokens);
+    timing.finish(prompt_tokens, completion_tokens);
+    state.record_trace(timing);
+
     let elapsed = start_time.elapsed();
     let tokens_per_sec = if elapsed.as_secs_f32() > 0.0 {
         completion_tokens as f32 / elapsed.as_secs_f32()
@@ -118,6 +305,28 @@ async fn handle_non_streaming(
         tokens_per_sec
     );
 
+    let message = match reply {
+        // A blocked reply is replaced by the moderation message.
+        Some(Reply::ToolCalls(calls)) if finish_reason == "stop" => {
+            finish_reason = "tool_calls";
+            ChatCompletionMessage {
+                role: "assistant".to_string(),
+                content: None,
+                tool_calls: Some(calls),
+            }
+        }
+        Some(Reply::Json(json)) if finish_reason == "stop" => ChatCompletionMessage {
+            role: "assistant".to_string(),
+            content: Some(json),
+            tool_calls: None,
+        },
+        _ => ChatCompletionMessage {
+            role: "assistant".to_string(),
+            content: Some(generated_text),
+            tool_calls: None,
+        },
+    };
+
     let response = ChatCompletionResponse {
         id: completion_id,
         object: "chat.completion".to_string(),
@@ -125,13 +334,14 @@ async fn handle_non_streaming(
         model: req.model,
         choices: vec![Choice {
             index: 0,
-            message: crate::server::types::ChatCompletionMessage {
-                role: "assistant".to_string(),
-                content: generated_text,
-            },
-            finish_reason: Some("stop".to_string()),
+            message,
+            finish_reason: Some(finish_reason.to_string()),
+            logprobs: token_logprobs,
         }],
         usage: Usage::new(prompt_tokens, completion_tokens),
+        language,
+        context_truncated,
+        moderation,
     };
 
     Ok(Json(response))
@@ -141,18 +351,23 @@ async fn handle_streaming(
     state: Arc<AppState>,
     req: ChatCompletionRequest,
     request_id: String,
-) -> Result<Sse<ReceiverStream<Result<Event, std::convert::Infallible>>>, OpenAIError> {
+    key: String,
+    mut store_key: Option<String>,
+) -> Result<EventStream, OpenAIError> {
     let model_path = req.model.clone();
     let generator = state.get_or_load_model(&model_path).await?;
 
-    let prompt = build_prompt(&req.messages);
+    let prompt = build_prompt(&req.messages, None);
+    let forced_language = state.default_options().force_language.as_deref();
+    let language = prompt_language(forced_language, &req.messages);
     let completion_id = create_completion_id();
     let timestamp = get_timestamp();
     let repeat_penalty = 1.1f32;
     let repeat_last_n = 64usize;
-    let max_tokens = req.max_tokens;
 
-    let prompt_tokens = prompt.split_whitespace().count();
+    let session = session_id(&req);
+    let (prompt_tokens, max_tokens) =
+        admit_request(&state, &generator, &session, &prompt, req.max_tokens).await?;
 
     tracing::info!(
         "[{}] Streaming started | prompt: {} tokens | max: {}",
@@ -165,53 +380,132 @@ async fn handle_streaming(
 
     let model_clone = req.model.clone();
     let request_id_clone = request_id.clone();
+    let moderation = state.default_options().moderation.clone();
+    let granularity = req
+        .stream_granularity
+        .unwrap_or(state.default_options().stream_granularity);
+    let state_clone = state.clone();
 
     std::thread::spawn(move || {
+        let mut timing = RequestTiming::new(&model_clone, true);
         let mut first = true;
         let mut completion_tokens = 0;
         let mut generated_text = String::new();
         let start_time = std::time::Instant::now();
         let mut first_token_time = None;
+        let mut context_truncated: Option<ContextTruncation> = None;
+        // Once a block-action rule fires, nothing more is streamed,
+        // generation is cancelled and the reply ends with finish_reason
+        // "content_filter". Each word is scanned once.
+        let mut blocked = false;
+        let mut screen = moderation.as_ref().map(ModerationStream::new);
+        let mut loop_detected = false;
+        // Set when the client goes away; the generator stops at its next step.
+        let cancel = CancelToken::new();
+        let mut chunker = StreamChunker::new(granularity);
         
         let generator_result = generator.lock();
         if let Ok(mut gen) = generator_result {
+            let prompt_tokens = exact_prompt_tokens(&gen, &prompt, prompt_tokens);
+            gen.set_logprobs(None);
+            gen.set_cancel_token(Some(cancel.clone()));
             let result = gen.generate_streaming(
                 &prompt,
                 max_tokens,
                 repeat_penalty,
                 repeat_last_n,
                 |event| match event {
+                    StreamEvent::Token(_) if cancel.is_cancelled() => {}
                     StreamEvent::Token(token) => {
+                        if tx.is_closed() {
+                            cancel.cancel();
+                            return;
+                        }
                         if first_token_time.is_none() {
                             first_token_time = Some(start_time.elapsed());
+                            timing.mark_first_token();
                         }
                         
                         generated_text.push_str(&token);
                         completion_tokens += 1;
-                        
-                        let delta = if first {
-                            first = false;
-                            Delta::with_role("assistant", &token)
-                        } else {
-                            Delta::new_content(&token)
-                        };
 
-                        let chunk = ChatCompletionChunk {
-                            id: completion_id.clone(),
-                            object: "chat.completion.chunk".to_string(),
-                            created: timestamp,
-                            model: model_clone.clone(),
-                            choices: vec![ChunkChoice {
-                                index: 0,
-                                delta,
-                                finish_reason: None,
-                            }],
+                        if screen.as_mut().is_some_and(|screen| screen.push(&token)) {
+                            blocked = true;
+                            cancel.cancel();
+                            return;
+                        }
+                        
+                        let Some(text) = chunker.push(&token) else {
+                            return;
                         };
-
+                        let chunk = content_chunk(
+                            &completion_id,
+                            timestamp,
+                            &model_clone,
+                            &text,
+                            &mut first,
+                            &context_truncated,
+                        );
                         let _ = tx.blocking_send(Ok(Event::default().json_data(chunk).unwrap()));
                     }
-                    StreamEvent::PrefillStatus(_) => {}
+                    StreamEvent::ContextTruncated {
+                        dropped_tokens,
+                        strategy,
+                    } => {
+                        tracing::warn!(
+                            "[{}] Context truncated | dropped: {} tokens",
+                            &request_id_clone[..8],
+                            dropped_tokens
+                        );
+                        context_truncated = Some(ContextTruncation {
+                            dropped_tokens,
+                            strategy,
+                        });
+                    }
+                    StreamEvent::PrefillStatus(_) => timing.mark_prefill(),
+                    StreamEvent::LoopDetected { .. } => loop_detected = true,
+                    StreamEvent::Done if cancel.is_cancelled() && !blocked => {}
+                    // The error itself is sent once the call returns.
+                    StreamEvent::Error(_) => {
+                        if let Some(text) = chunker.finish().filter(|_| !blocked) {
+                            let chunk = content_chunk(
+                                &completion_id,
+                                timestamp,
+                                &model_clone,
+                                &text,
+                                &mut first,
+                                &context_truncated,
+                            );
+                            let _ = tx.blocking_send(Ok(Event::default().json_data(chunk).unwrap()));
+                        }
+                    }
                     StreamEvent::Done => {
+                        let moderation_result =
+                            moderation.as_ref().map(|config| config.check(&generated_text));
+                        let (content, finish_reason) = match moderation {
+                            Some(ref config) if blocked => {
+                                tracing::warn!(
+                                    "[{}] Reply blocked by moderation",
+                                    &request_id_clone[..8]
+                                );
+                                (config.blocked_message.clone(), "content_filter")
+                            }
+                            _ if loop_detected => (generated_text.clone(), "loop_detected"),
+                            _ => (generated_text.clone(), "stop"),
+                        };
+
+                        if let Some(text) = chunker.finish().filter(|_| !blocked) {
+                            let chunk = content_chunk(
+                                &completion_id,
+                                timestamp,
+                                &model_clone,
+                                &text,
+                                &mut first,
+                                &context_truncated,
+                            );
+                            let _ = tx.blocking_send(Ok(Event::default().json_data(chunk).unwrap()));
+                        }
+
                         let chunk = ChatCompletionChunk {
                             id: completion_id.clone(),
                             object: "chat.completion.chunk".to_string(),
@@ -220,8 +514,10 @@ async fn handle_streaming(
                             choices: vec![ChunkChoice {
                                 index: 0,
                                 delta: Delta::default(),
-                                finish_reason: Some("stop".to_string()),
+                                finish_reason: Some(finish_reason.to_string()),
+                                logprobs: None,
                             }],
+                            context_truncated: None,
                         };
                         let _ = tx.blocking_send(Ok(Event::default().json_data(chunk).unwrap()));
 
@@ -233,15 +529,21 @@ async fn handle_streaming(
                             model: model_clone.clone(),
                             choices: vec![Choice {
                                 index: 0,
-                                message: crate::server::types::ChatCompletionMessage {
+                                message: ChatCompletionMessage {
                                     role: "assistant".to_string(),
-                                    content: generated_text.clone(),
+                                    content: Some(content),
+                                    tool_calls: None,
                                 },
-                                finish_reason: Some("stop".to_string()),
+                                finish_reason: Some(finish_reason.to_string()),
+                                logprobs: None,
                             }],
                             usage: Usage::new(prompt_tokens, completion_tokens),
+                            language: language.clone(),
+                            context_truncated: context_truncated.clone(),
+                            moderation: moderation_result,
                         };
-                        let _ = tx.blocking_send(Ok(Event::default().json_data(complete_response).unwrap()));
+                        let _ = tx.blocking_send(Ok(Event::default().json_data(&complete_response).unwrap()));
+                        store_response(&state_clone, store_key.take(), &complete_response);
 
                         let _ = tx.blocking_send(Ok(Event::default().data("[DONE]")));
 
@@ -265,20 +567,215 @@ async fn handle_streaming(
                 },
             );
 
+            state_clone
+                .quotas()
+                .record(&session, prompt_tokens + completion_tokens);
+            gen.set_cancel_token(None);
+            state_clone
+                .usage()
+                .record(&key, &model_clone, prompt_tokens, completion_tokens);
+            state_clone
+                .metrics()
+                .record_request(prompt_tokens, completion_tokens);
+            state_clone
+                .metrics()
+                .record_token_latencies(gen.token_latencies());
+            timing.finish(prompt_tokens, completion_tokens);
+            state_clone.record_trace(timing);
+            if cancel.is_cancelled() && !blocked {
+                state_clone.metrics().record_stream_cancelled();
+                tracing::info!(
+                    "[{}] Client disconnected, generation cancelled after {} tokens",
+                    &request_id_clone[..8],
+                    completion_tokens
+                );
+            }
             if let Err(e) = result {
-                let _ = tx.blocking_send(Ok(Event::default().data(format!("Error: {}", e))));
+                let event = match e.downcast::<ContextOverflow>() {
+                    Ok(overflow) => Event::default()
+                        .json_data(OpenAIError::from(overflow))
+                        .unwrap(),
+                    Err(e) => Event::default().data(format!("Error: {}", e)),
+                };
+                let _ = tx.blocking_send(Ok(event));
+            } else if let Some(config) = moderation.as_ref().filter(|_| blocked) {
+                if let Err(e) = gen.collapse_last_turns(1, &prompt, &config.blocked_message) {
+                    tracing::warn!("[{}] Failed to update history: {}", &request_id_clone[..8], e);
+                }
             }
         } else {
             let _ = tx.blocking_send(Ok(Event::default().data("Error: Failed to acquire generator lock")));
         }
     });
 
-    let stream = ReceiverStream::new(rx);
+    Ok(event_stream(rx))
+}
 
-    Ok(Sse::new(stream))
+/// Sends a finished reply as a stream: one chunk with the whole message,
+/// the finishing chunk, the complete message, then `[DONE]`.
+fn buffered_stream(response: ChatCompletionResponse) -> EventStream {
+    let (tx, rx) = mpsc::channel::<Result<Event, std::convert::Infallible>>(4);
+    let choice = &response.choices[0];
+    let chunk = |delta: Delta, finish_reason: Option<String>, logprobs| ChatCompletionChunk {
+        id: response.id.clone(),
+        object: "chat.completion.chunk".to_string(),
+        created: response.created,
+        model: response.model.clone(),
+        choices: vec![ChunkChoice {
+            index: 0,
+            delta,
+            finish_reason,
+            logprobs,
+        }],
+        context_truncated: None,
+    };
+
+    let mut delta = Delta::role_only("assistant");
+    delta.content = choice.message.content.clone();
+    delta.tool_calls = choice.message.tool_calls.as_ref().map(|calls| {
+        calls
+            .iter()
+            .enumerate()
+            .map(|(index, call)| DeltaToolCall {
+                index,
+                call: call.clone(),
+            })
+            .collect()
+    });
+    let mut first = chunk(delta, None, choice.logprobs.clone());
+    first.context_truncated = response.context_truncated.clone();
+    let last = chunk(Delta::default(), choice.finish_reason.clone(), None);
+
+    for event in [
+        Event::default().json_data(first).unwrap(),
+        Event::default().json_data(last).unwrap(),
+        Event::default().json_data(&response).unwrap(),
+        Event::default().data("[DONE]"),
+    ] {
+        let _ = tx.try_send(Ok(event));
+    }
+    event_stream(rx)
 }
 
-fn build_prompt(messages: &[crate::server::types::ChatMessage]) -> String {
+/// A content chunk of a live stream. The first one carries the role and any
+/// truncation notice.
+fn content_chunk(
+    id: &str,
+    created: u64,
+    model: &str,
+    text: &str,
+    first: &mut bool,
+    context_truncated: &Option<ContextTruncation>,
+) -> ChatCompletionChunk {
+    let is_first = std::mem::replace(first, false);
+    let delta = if is_first {
+        Delta::with_role("assistant", text)
+    } else {
+        Delta::new_content(text)
+    };
+    ChatCompletionChunk {
+        id: id.to_string(),
+        object: "chat.completion.chunk".to_string(),
+        created,
+        model: model.to_string(),
+        choices: vec![ChunkChoice {
+            index: 0,
+            delta,
+            finish_reason: None,
+            logprobs: None,
+        }],
+        context_truncated: if is_first {
+            context_truncated.clone()
+        } else {
+            None
+        },
+    }
+}
+
+/// Number of alternatives to record per token, when `logprobs` is set.
+fn logprobs_request(req: &ChatCompletionRequest) -> Result<Option<usize>, OpenAIError> {
+    let top = req.top_logprobs.unwrap_or(0);
+    if top > MAX_TOP_LOGPROBS {
+        return Err(OpenAIError::invalid_param(
+            "top_logprobs",
+            &format!("top_logprobs must be at most {}", MAX_TOP_LOGPROBS),
+        ));
+    }
+    if req.logprobs.unwrap_or(false) {
+        Ok(Some(top))
+    } else if req.top_logprobs.is_some() {
+        Err(OpenAIError::invalid_param(
+            "top_logprobs",
+            "top_logprobs requires logprobs to be true",
+        ))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Quota session of a request: its `user`, or the shared anonymous session.
+fn session_id(req: &ChatCompletionRequest) -> String {
+    req.user
+        .clone()
+        .unwrap_or_else(|| ANONYMOUS_SESSION.to_string())
+}
+
+/// Counts the prompt and applies the server's quotas. Returns the prompt
+/// token count and the completion budget. Without quotas the count is a
+/// whitespace estimate, which avoids waiting for a busy generator.
+async fn admit_request(
+    state: &AppState,
+    generator: &Arc<Mutex<Generator>>,
+    session: &str,
+    prompt: &str,
+    max_tokens: usize,
+) -> Result<(usize, usize), OpenAIError> {
+    if !state.quotas().config().is_enabled() {
+        return Ok((prompt.split_whitespace().count(), max_tokens));
+    }
+
+    let generator = generator.clone();
+    let text = prompt.to_string();
+    let prompt_tokens = tokio::task::spawn_blocking(move || {
+        let gen = generator.lock().map_err(|e| anyhow::anyhow!(e.to_string()))?;
+        gen.count_tokens(&text)
+    })
+    .await
+    .map_err(|e| OpenAIError::internal(&e.to_string()))??;
+
+    let max_tokens = state.quotas().admit(session, prompt_tokens, max_tokens)?;
+    Ok((prompt_tokens, max_tokens))
+}
+
+/// Replaces the whitespace estimate from [`admit_request`] with the
+/// tokenizer's count, so usage reports are exact.
+fn exact_prompt_tokens(gen: &Generator, prompt: &str, estimate: usize) -> usize {
+    gen.count_tokens(prompt).unwrap_or(estimate)
+}
+
+/// The forced language, or else that of the most recent user message if it
+/// can be detected.
+fn prompt_language(
+    forced: Option<&str>,
+    messages: &[crate::server::types::ChatMessage],
+) -> Option<String> {
+    if let Some(language) = forced {
+        return Some(language.to_string());
+    }
+    messages
+        .iter()
+        .rev()
+        .find(|msg| msg.role.as_str() == "user")
+        .and_then(|msg| detect_language(&msg.content))
+        .map(String::from)
+}
+
+/// Flattens the conversation into one prompt. `instruction` describes the
+/// required reply format and is added just before the reply.
+fn build_prompt(
+    messages: &[crate::server::types::ChatMessage],
+    instruction: Option<&str>,
+) -> String {
     let mut prompt = String::new();
 
     for msg in messages {
@@ -296,10 +793,32 @@ fn build_prompt(messages: &[crate::server::types::ChatMessage]) -> String {
                 prompt.push_str(&format!("{}: ", msg.role.as_str()));
             }
         }
+        if let Some(name) = msg.name.as_ref().or(msg.tool_call_id.as_ref()) {
+            // Keep OpenAI `name` semantics: attribute the turn to a participant.
+            prompt.insert_str(prompt.len() - 2, &format!(" ({})", name));
+        }
         prompt.push_str(&msg.content);
-        prompt.push_str("\n");
+        if let Some(calls) = msg.tool_calls.as_ref().filter(|calls| !calls.is_empty()) {
+            // Replay earlier calls in the format the model was asked to use.
+            let calls: Vec<serde_json::Value> = calls
+                .iter()
+                .map(|call| {
+                    let arguments =
+                        serde_json::from_str::<serde_json::Value>(&call.function.arguments)
+                            .unwrap_or_else(|_| call.function.arguments.clone().into());
+                    serde_json::json!({ "name": call.function.name, "arguments": arguments })
+                })
+                .collect();
+            prompt.push_str(&serde_json::json!({ "tool_calls": calls }).to_string());
+        }
+        prompt.push('\n');
     }
 
+    if let Some(instruction) = instruction {
+        prompt.push_str("system: ");
+        prompt.push_str(instruction);
+        prompt.push('\n');
+    }
     prompt.push_str("assistant: ");
     prompt
 }
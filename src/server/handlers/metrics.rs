@@ -0,0 +1,27 @@
+use std::sync::Arc;
+
+use axum::{extract::State, Json};
+
+use crate::server::metrics::MetricsSnapshot;
+use crate::server::state::AppState;
+#[cfg(feature = "telemetry")]
+use crate::server::telemetry::prometheus_text;
+
+pub async fn get_metrics(State(state): State<Arc<AppState>>) -> Json<MetricsSnapshot> {
+    Json(state.metrics().snapshot())
+}
+
+/// `/metrics` in the Prometheus text format.
+#[cfg(feature = "telemetry")]
+pub async fn get_prometheus_metrics(
+    State(state): State<Arc<AppState>>,
+) -> ([(axum::http::HeaderName, &'static str); 1], String) {
+    let models_loaded = state.list_models().await.len();
+    (
+        [(
+            axum::http::header::CONTENT_TYPE,
+            "text/plain; version=0.0.4; charset=utf-8",
+        )],
+        prometheus_text(&state.metrics().snapshot(), models_loaded),
+    )
+}
@@ -1,5 +1,25 @@
+pub mod admin;
 pub mod chat;
+pub mod metrics;
 pub mod models;
+pub mod tokenize;
+pub mod usage;
 
+pub use admin::{load_model, unload_model};
 pub use chat::chat_completions;
+pub use metrics::get_metrics;
+#[cfg(feature = "telemetry")]
+pub use metrics::get_prometheus_metrics;
 pub use models::list_models;
+pub use tokenize::{detokenize, tokenize};
+pub use usage::get_usage;
+
+use axum::http::HeaderMap;
+
+/// Token from an `Authorization: Bearer` header.
+pub(crate) fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get("authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
@@ -0,0 +1,130 @@
+use std::sync::Arc;
+
+use axum::{extract::State, Json};
+use serde::{Deserialize, Serialize};
+
+use crate::server::error::OpenAIError;
+use crate::server::state::AppState;
+
+#[derive(Debug, Deserialize)]
+pub struct TokenizeRequest {
+    pub model: String,
+    pub content: String,
+    /// Prepend BOS (and append EOS) as the model's prompts do.
+    #[serde(default)]
+    pub add_special: bool,
+    /// Read special-token text such as `<|im_start|>` as its token id.
+    #[serde(default = "default_parse_special")]
+    pub parse_special: bool,
+    /// Return `{id, piece}` objects instead of bare ids.
+    #[serde(default)]
+    pub with_pieces: bool,
+}
+
+fn default_parse_special() -> bool {
+    true
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(untagged)]
+pub enum TokenEntry {
+    Id(u32),
+    Piece { id: u32, piece: String },
+}
+
+#[derive(Debug, Serialize)]
+pub struct TokenizeResponse {
+    pub tokens: Vec<TokenEntry>,
+    pub count: usize,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DetokenizeRequest {
+    pub model: String,
+    pub tokens: Vec<u32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DetokenizeResponse {
+    pub content: String,
+}
+
+pub async fn tokenize(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<TokenizeRequest>,
+) -> Result<Json<TokenizeResponse>, OpenAIError> {
+    let tokenizer = state
+        .tokenizer(&req.model)
+        .await
+        .map_err(|e| OpenAIError::invalid_model(&e.to_string()))?;
+    let ids = tokenizer
+        .encode_with_options(&req.content, req.add_special, req.parse_special)
+        .map_err(|e| OpenAIError::invalid_param("content", &e.to_string()))?;
+
+    let tokens = ids
+        .iter()
+        .map(|&id| {
+            if !req.with_pieces {
+                return Ok(TokenEntry::Id(id));
+            }
+            let piece = tokenizer
+                .token_to_piece(id)
+                .map_err(|e| OpenAIError::internal(&e.to_string()))?;
+            Ok(TokenEntry::Piece { id, piece })
+        })
+        .collect::<Result<Vec<_>, OpenAIError>>()?;
+
+    Ok(Json(TokenizeResponse {
+        count: tokens.len(),
+        tokens,
+    }))
+}
+
+pub async fn detokenize(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<DetokenizeRequest>,
+) -> Result<Json<DetokenizeResponse>, OpenAIError> {
+    let tokenizer = state
+        .tokenizer(&req.model)
+        .await
+        .map_err(|e| OpenAIError::invalid_model(&e.to_string()))?;
+    let vocab_size = tokenizer.vocab_size();
+    if let Some(bad) = req.tokens.iter().find(|&&id| id as usize >= vocab_size) {
+        return Err(OpenAIError::invalid_param(
+            "tokens",
+            &format!(
+                "Token id {} is outside the vocabulary of {}",
+                bad, vocab_size
+            ),
+        ));
+    }
+    let content = tokenizer
+        .decode(&req.tokens)
+        .map_err(|e| OpenAIError::invalid_param("tokens", &e.to_string()))?;
+
+    Ok(Json(DetokenizeResponse { content }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TokenEntry, TokenizeRequest};
+
+    #[test]
+    fn tokenize_defaults_and_entries() {
+        let req: TokenizeRequest =
+            serde_json::from_str(r#"{"model": "m.gguf", "content": "hi"}"#).unwrap();
+        assert!(!req.add_special && req.parse_special && !req.with_pieces);
+
+        let entries = vec![
+            TokenEntry::Id(7),
+            TokenEntry::Piece {
+                id: 8,
+                piece: " hi".to_string(),
+            },
+        ];
+        assert_eq!(
+            serde_json::to_string(&entries).unwrap(),
+            r#"[7,{"id":8,"piece":" hi"}]"#
+        );
+    }
+}
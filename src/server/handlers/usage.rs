@@ -0,0 +1,83 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Query, State},
+    http::{header, HeaderMap},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::bearer_token;
+use crate::server::error::OpenAIError;
+use crate::server::state::AppState;
+use crate::server::usage::{key_id, to_csv, Bucket, UsageFilter, UsageRow, ANONYMOUS_KEY};
+
+#[derive(Debug, Default, Deserialize)]
+pub struct UsageQuery {
+    #[serde(default)]
+    pub bucket: Bucket,
+    pub start: Option<DateTime<Utc>>,
+    pub end: Option<DateTime<Utc>>,
+    pub model: Option<String>,
+    /// Key id to report on; only honoured for the admin token.
+    pub key: Option<String>,
+    /// `json` (default) or `csv`.
+    pub format: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UsageResponse {
+    pub object: String,
+    pub bucket: Bucket,
+    pub data: Vec<UsageRow>,
+}
+
+/// Key id of the caller: a hash of its bearer token, or `anonymous`.
+pub fn request_key(headers: &HeaderMap) -> String {
+    bearer_token(headers)
+        .map(key_id)
+        .unwrap_or_else(|| ANONYMOUS_KEY.to_string())
+}
+
+/// Token usage in hour or day buckets. Callers see their own key; the admin
+/// token sees every key and may filter with `key`.
+pub async fn get_usage(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<UsageQuery>,
+) -> Result<Response, OpenAIError> {
+    let is_admin = state
+        .admin_token()
+        .is_some_and(|token| bearer_token(&headers) == Some(token));
+    let key = if is_admin {
+        query.key
+    } else {
+        Some(request_key(&headers))
+    };
+
+    let rows = state.usage().report(
+        query.bucket,
+        &UsageFilter {
+            key,
+            model: query.model,
+            start: query.start,
+            end: query.end,
+        },
+    );
+
+    match query.format.as_deref() {
+        None | Some("json") => Ok(Json(UsageResponse {
+            object: "list".to_string(),
+            bucket: query.bucket,
+            data: rows,
+        })
+        .into_response()),
+        Some("csv") => Ok(([(header::CONTENT_TYPE, "text/csv")], to_csv(&rows)).into_response()),
+        Some(other) => Err(OpenAIError::invalid_param(
+            "format",
+            &format!("Unknown format '{}', expected json or csv", other),
+        )),
+    }
+}
@@ -8,8 +8,27 @@ use tower_service::Service;
 
 use crate::server::router::create_router;
 use crate::server::state::AppState;
+use crate::GenerateOptions;
 
 pub async fn run(host: String, port: u16) -> Result<(), Box<dyn std::error::Error>> {
+    run_with_options(host, port, GenerateOptions::default()).await
+}
+
+/// Runs the server with `options` as the defaults for every loaded model.
+pub async fn run_with_options(
+    host: String,
+    port: u16,
+    options: GenerateOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    run_with_state(host, port, AppState::with_options(options)).await
+}
+
+/// Runs the server with a prepared [`AppState`], e.g. one with quotas.
+pub async fn run_with_state(
+    host: String,
+    port: u16,
+    state: AppState,
+) -> Result<(), Box<dyn std::error::Error>> {
     let addr = format!("{}:{}", host, port);
     let listener = TcpListener::bind(&addr).await?;
 
@@ -24,9 +43,19 @@ pub async fn run(host: String, port: u16) -> Result<(), Box<dyn std::error::Erro
     tracing::info!("  - POST /v1/chat/completions");
     tracing::info!("  - GET  /v1/models");
     tracing::info!("CORS: enabled (permissive)");
+    if let Some(ref redaction) = state.default_options().redaction {
+        tracing::info!("Redaction: {} rules", redaction.rules.len());
+    }
+    let quotas = state.quotas().config();
+    if let Some(limit) = quotas.max_prompt_tokens {
+        tracing::info!("Prompt limit: {} tokens per request", limit);
+    }
+    if let Some(quota) = quotas.session_token_quota {
+        tracing::info!("Session quota: {} tokens", quota);
+    }
     println!();
 
-    let state = Arc::new(AppState::new());
+    let state = Arc::new(state);
     let router = create_router(state);
 
     let cors = CorsLayer::permissive();
@@ -0,0 +1,142 @@
+//! Server counters, read as a point-in-time [`MetricsSnapshot`], and the
+//! phase timing of single requests.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+use serde::Serialize;
+
+use crate::inference::LatencyHistogram;
+
+/// Monotonic counters since the server started.
+#[derive(Debug, Default)]
+pub struct ServerMetrics {
+    requests: AtomicU64,
+    streams_cancelled: AtomicU64,
+    prompt_tokens: AtomicU64,
+    completion_tokens: AtomicU64,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    token_latency: Mutex<LatencyHistogram>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct MetricsSnapshot {
+    /// Chat completion requests that ran generation.
+    pub requests: u64,
+    /// Streams stopped early because the client disconnected.
+    pub streams_cancelled: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    /// Requests answered from the response cache.
+    pub cache_hits: u64,
+    /// Cacheable requests that had to be generated.
+    pub cache_misses: u64,
+    /// Decode-step latency percentiles over all completions, in
+    /// microseconds, estimated from the histogram buckets.
+    pub token_latency_p50_us: u64,
+    pub token_latency_p95_us: u64,
+    pub token_latency_p99_us: u64,
+    #[serde(skip)]
+    pub token_latency: LatencyHistogram,
+}
+
+impl ServerMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts one finished (or cancelled) completion.
+    pub fn record_request(&self, prompt_tokens: usize, completion_tokens: usize) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.prompt_tokens
+            .fetch_add(prompt_tokens as u64, Ordering::Relaxed);
+        self.completion_tokens
+            .fetch_add(completion_tokens as u64, Ordering::Relaxed);
+    }
+
+    /// Adds the decode-step latencies of one completion to the histogram.
+    pub fn record_token_latencies(&self, latencies: &[Duration]) {
+        let mut histogram = self.token_latency.lock().unwrap();
+        for &latency in latencies {
+            histogram.record(latency);
+        }
+    }
+
+    pub fn record_stream_cancelled(&self) {
+        self.streams_cancelled.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_cache_lookup(&self, hit: bool) {
+        let counter = if hit {
+            &self.cache_hits
+        } else {
+            &self.cache_misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let token_latency = self.token_latency.lock().unwrap().clone();
+        let micros = |q: f64| token_latency.quantile(q).as_micros() as u64;
+        MetricsSnapshot {
+            requests: self.requests.load(Ordering::Relaxed),
+            streams_cancelled: self.streams_cancelled.load(Ordering::Relaxed),
+            prompt_tokens: self.prompt_tokens.load(Ordering::Relaxed),
+            completion_tokens: self.completion_tokens.load(Ordering::Relaxed),
+            cache_hits: self.cache_hits.load(Ordering::Relaxed),
+            cache_misses: self.cache_misses.load(Ordering::Relaxed),
+            token_latency_p50_us: micros(0.50),
+            token_latency_p95_us: micros(0.95),
+            token_latency_p99_us: micros(0.99),
+            token_latency,
+        }
+    }
+}
+
+/// When one completion was received, started prefill, produced its first
+/// token and finished. Exported as spans when tracing is enabled.
+#[derive(Clone, Debug)]
+pub struct RequestTiming {
+    pub model: String,
+    pub stream: bool,
+    pub started_at: SystemTime,
+    pub received: Instant,
+    pub prefill_start: Option<Instant>,
+    pub first_token: Option<Instant>,
+    pub finished: Option<Instant>,
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+}
+
+impl RequestTiming {
+    pub fn new(model: &str, stream: bool) -> Self {
+        Self {
+            model: model.to_string(),
+            stream,
+            started_at: SystemTime::now(),
+            received: Instant::now(),
+            prefill_start: None,
+            first_token: None,
+            finished: None,
+            prompt_tokens: 0,
+            completion_tokens: 0,
+        }
+    }
+
+    /// Marks the start of prefill. Later calls (structured retries) are ignored.
+    pub fn mark_prefill(&mut self) {
+        self.prefill_start.get_or_insert_with(Instant::now);
+    }
+
+    pub fn mark_first_token(&mut self) {
+        self.first_token.get_or_insert_with(Instant::now);
+    }
+
+    pub fn finish(&mut self, prompt_tokens: usize, completion_tokens: usize) {
+        self.finished = Some(Instant::now());
+        self.prompt_tokens = prompt_tokens;
+        self.completion_tokens = completion_tokens;
+    }
+}
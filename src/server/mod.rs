@@ -1,8 +1,21 @@
+pub mod cache;
 pub mod error;
 pub mod handlers;
 pub mod main;
+pub mod metrics;
+pub mod quota;
 pub mod router;
 pub mod state;
+pub mod structured;
+#[cfg(feature = "telemetry")]
+pub mod telemetry;
 pub mod types;
+pub mod usage;
 
-pub use main::run;
+pub use cache::{CacheConfig, ResponseCache};
+pub use main::{run, run_with_options, run_with_state};
+pub use metrics::{MetricsSnapshot, RequestTiming, ServerMetrics};
+pub use quota::{QuotaConfig, QuotaError, QuotaTracker};
+#[cfg(feature = "telemetry")]
+pub use telemetry::{OtlpConfig, TraceExporter};
+pub use usage::{Bucket, UsageFilter, UsageRow, UsageTracker};
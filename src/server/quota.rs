@@ -0,0 +1,169 @@
+//! Per-request prompt limits and per-session token quotas.
+//!
+//! A session is identified by the OpenAI `user` field; requests without one
+//! share the `anonymous` session. Usage counts prompt and completion tokens
+//! and lives in memory, so it resets when the server restarts.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+
+/// Session used for requests that do not set `user`.
+pub const ANONYMOUS_SESSION: &str = "anonymous";
+
+#[derive(Clone, Debug, Default)]
+pub struct QuotaConfig {
+    /// Largest prompt, in tokens, a single request may send.
+    pub max_prompt_tokens: Option<usize>,
+    /// Total prompt plus completion tokens one session may use.
+    pub session_token_quota: Option<u64>,
+}
+
+impl QuotaConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.max_prompt_tokens.is_some() || self.session_token_quota.is_some()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum QuotaError {
+    PromptTooLong {
+        tokens: usize,
+        limit: usize,
+    },
+    SessionQuotaExceeded {
+        session: String,
+        used: u64,
+        quota: u64,
+    },
+}
+
+impl fmt::Display for QuotaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QuotaError::PromptTooLong { tokens, limit } => write!(
+                f,
+                "Prompt has {} tokens, more than the {} allowed per request",
+                tokens, limit
+            ),
+            QuotaError::SessionQuotaExceeded {
+                session,
+                used,
+                quota,
+            } => write!(
+                f,
+                "Session '{}' has used {} of its {} token quota",
+                session, used, quota
+            ),
+        }
+    }
+}
+
+impl std::error::Error for QuotaError {}
+
+/// Enforces a [`QuotaConfig`] and tracks usage per session.
+#[derive(Debug, Default)]
+pub struct QuotaTracker {
+    config: QuotaConfig,
+    usage: Mutex<HashMap<String, u64>>,
+}
+
+impl QuotaTracker {
+    pub fn new(config: QuotaConfig) -> Self {
+        Self {
+            config,
+            usage: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn config(&self) -> &QuotaConfig {
+        &self.config
+    }
+
+    /// Checks a request before it runs and returns the completion budget it
+    /// may use: `max_tokens`, lowered to what is left of the session quota.
+    pub fn admit(
+        &self,
+        session: &str,
+        prompt_tokens: usize,
+        max_tokens: usize,
+    ) -> Result<usize, QuotaError> {
+        if let Some(limit) = self.config.max_prompt_tokens {
+            if prompt_tokens > limit {
+                return Err(QuotaError::PromptTooLong {
+                    tokens: prompt_tokens,
+                    limit,
+                });
+            }
+        }
+
+        let Some(quota) = self.config.session_token_quota else {
+            return Ok(max_tokens);
+        };
+        let used = self.used(session);
+        let left = quota.saturating_sub(used + prompt_tokens as u64);
+        if left == 0 {
+            return Err(QuotaError::SessionQuotaExceeded {
+                session: session.to_string(),
+                used,
+                quota,
+            });
+        }
+        let left = usize::try_from(left).unwrap_or(usize::MAX);
+        // `0` asks the generator to fill the context; keep that within quota.
+        Ok(if max_tokens == 0 {
+            left
+        } else {
+            max_tokens.min(left)
+        })
+    }
+
+    /// Adds a finished request's tokens to the session's usage.
+    pub fn record(&self, session: &str, tokens: usize) {
+        if self.config.session_token_quota.is_none() {
+            return;
+        }
+        let mut usage = self.usage.lock().unwrap();
+        *usage.entry(session.to_string()).or_default() += tokens as u64;
+    }
+
+    pub fn used(&self, session: &str) -> u64 {
+        self.usage
+            .lock()
+            .unwrap()
+            .get(session)
+            .copied()
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{QuotaConfig, QuotaError, QuotaTracker};
+
+    #[test]
+    fn enforces_prompt_limit_and_session_quota() {
+        let tracker = QuotaTracker::new(QuotaConfig {
+            max_prompt_tokens: Some(100),
+            session_token_quota: Some(300),
+        });
+
+        assert_eq!(
+            tracker.admit("alice", 101, 10),
+            Err(QuotaError::PromptTooLong {
+                tokens: 101,
+                limit: 100
+            })
+        );
+        assert_eq!(tracker.admit("alice", 50, 512), Ok(250));
+        tracker.record("alice", 250);
+        assert_eq!(tracker.admit("alice", 20, 0), Ok(30));
+        tracker.record("alice", 50);
+        assert!(matches!(
+            tracker.admit("alice", 1, 10),
+            Err(QuotaError::SessionQuotaExceeded { used: 300, .. })
+        ));
+        // Sessions are tracked separately.
+        assert_eq!(tracker.admit("bob", 10, 10), Ok(10));
+    }
+}
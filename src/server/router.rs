@@ -5,12 +5,34 @@ use axum::{
     Router,
 };
 
-use crate::server::handlers::{chat_completions, list_models};
+use crate::server::handlers::{
+    chat_completions, detokenize, get_metrics, get_usage, list_models, load_model, tokenize,
+    unload_model,
+};
+#[cfg(feature = "telemetry")]
+use crate::server::handlers::get_prometheus_metrics;
 use crate::server::state::AppState;
 
 pub fn create_router(state: Arc<AppState>) -> Router {
-    Router::new()
+    let mut router = Router::new()
         .route("/v1/chat/completions", post(chat_completions))
         .route("/v1/models", get(list_models))
-        .with_state(state)
+        .route("/v1/usage", get(get_usage))
+        .route("/v1/metrics", get(get_metrics))
+        .route("/tokenize", post(tokenize))
+        .route("/detokenize", post(detokenize));
+
+    #[cfg(feature = "telemetry")]
+    {
+        router = router.route("/metrics", get(get_prometheus_metrics));
+    }
+
+    // Admin routes exist only when a token is configured.
+    if state.admin_token().is_some() {
+        router = router
+            .route("/admin/models/load", post(load_model))
+            .route("/admin/models/unload", post(unload_model));
+    }
+
+    router.with_state(state)
 }
@@ -1,24 +1,107 @@
 use std::collections::HashMap;
 use std::path::Path;
 use std::sync::Arc;
+use std::time::Duration;
 
 use tokio::sync::RwLock;
 use std::sync::Mutex;
 
-use crate::inference::Generator;
+use crate::inference::{EosControl, Generator, TopNSigma};
+use crate::model::{load_with_retry, LoadRetry, TokenizerWrapper};
+use crate::server::cache::{CacheConfig, ResponseCache};
+use crate::server::metrics::{RequestTiming, ServerMetrics};
+use crate::server::quota::{QuotaConfig, QuotaTracker};
+#[cfg(feature = "telemetry")]
+use crate::server::telemetry::{OtlpConfig, TraceExporter};
+use crate::server::usage::UsageTracker;
 use crate::GenerateOptions;
 
 pub struct AppState {
     model_cache: RwLock<HashMap<String, Arc<Mutex<Generator>>>>,
+    tokenizers: RwLock<HashMap<String, Arc<TokenizerWrapper>>>,
     default_options: GenerateOptions,
+    quotas: QuotaTracker,
+    usage: UsageTracker,
+    metrics: ServerMetrics,
+    response_cache: Option<ResponseCache>,
+    admin_token: Option<String>,
+    #[cfg(feature = "telemetry")]
+    trace_exporter: Option<TraceExporter>,
 }
 
 impl AppState {
     pub fn new() -> Self {
+        Self::with_options(GenerateOptions::default())
+    }
+
+    pub fn with_options(default_options: GenerateOptions) -> Self {
         Self {
             model_cache: RwLock::new(HashMap::new()),
-            default_options: GenerateOptions::default(),
+            tokenizers: RwLock::new(HashMap::new()),
+            default_options,
+            quotas: QuotaTracker::default(),
+            usage: UsageTracker::new(),
+            metrics: ServerMetrics::new(),
+            response_cache: None,
+            admin_token: None,
+            #[cfg(feature = "telemetry")]
+            trace_exporter: None,
+        }
+    }
+
+    /// Enables the `/admin` endpoints, which require this bearer token.
+    pub fn with_admin_token(mut self, token: impl Into<String>) -> Self {
+        self.admin_token = Some(token.into());
+        self
+    }
+
+    pub fn admin_token(&self) -> Option<&str> {
+        self.admin_token.as_deref()
+    }
+
+    /// Enforces per-request prompt limits and per-session token quotas.
+    pub fn with_quotas(mut self, config: QuotaConfig) -> Self {
+        self.quotas = QuotaTracker::new(config);
+        self
+    }
+
+    pub fn quotas(&self) -> &QuotaTracker {
+        &self.quotas
+    }
+
+    pub fn usage(&self) -> &UsageTracker {
+        &self.usage
+    }
+
+    pub fn metrics(&self) -> &ServerMetrics {
+        &self.metrics
+    }
+
+    /// Serves repeated identical requests from a cache.
+    pub fn with_response_cache(mut self, config: CacheConfig) -> Self {
+        self.response_cache = Some(ResponseCache::new(config));
+        self
+    }
+
+    pub fn response_cache(&self) -> Option<&ResponseCache> {
+        self.response_cache.as_ref()
+    }
+
+    /// Exports request spans to an OTLP/HTTP collector.
+    #[cfg(feature = "telemetry")]
+    pub fn with_trace_exporter(mut self, config: OtlpConfig) -> Self {
+        self.trace_exporter = Some(TraceExporter::new(config));
+        self
+    }
+
+    /// Hands a finished request to the trace exporter, if there is one.
+    pub fn record_trace(&self, timing: RequestTiming) {
+        #[cfg(feature = "telemetry")]
+        if let Some(exporter) = &self.trace_exporter {
+            exporter.export(timing);
         }
+        #[cfg(not(feature = "telemetry"))]
+        drop(timing);
     }
 
     pub async fn get_or_load_model(
@@ -49,30 +132,50 @@ impl AppState {
 
         let load_start = std::time::Instant::now();
         
-        let generator = Generator::new(
-            &path.to_path_buf(),
-            None,
-            self.default_options.temperature,
-            self.default_options.top_p,
-            self.default_options.top_k,
-            self.default_options.seed,
-            self.default_options.system_prompt.clone(),
-            self.default_options.batch_size,
-        )?;
+        crate::install_thread_pool(&self.default_options);
+        let retry = LoadRetry::new(self.default_options.load_retries);
+        let mut generator = load_with_retry(path, &retry, || {
+            Generator::new(
+                &path.to_path_buf(),
+                None,
+                self.default_options.temperature,
+                self.default_options.top_p,
+                self.default_options.top_k,
+                self.default_options.seed,
+                self.default_options.system_prompt.clone(),
+                self.default_options.batch_size,
+            )
+        })?;
+        generator.set_forced_language(self.default_options.force_language.clone());
+        generator.set_redaction(self.default_options.redaction.as_ref())?;
+        generator.set_low_mem(self.default_options.low_mem);
+        generator.set_finish_at_boundary(self.default_options.finish_at_boundary);
+        let ttft_budget = self.default_options.ttft_budget_ms.map(Duration::from_millis);
+        generator.set_ttft_budget(ttft_budget);
+        generator.set_loop_guard(self.default_options.loop_guard);
+        generator.set_context_policy(self.default_options.context_policy);
+        if let Some(n) = self.default_options.top_n_sigma {
+            generator.add_logits_transform(Box::new(TopNSigma::new(n)?));
+        }
+        let eos = EosControl::new(self.default_options.eos_bias, self.default_options.min_tokens);
+        if eos.is_active() {
+            generator.add_logits_transform(Box::new(eos));
+        }
 
         let load_time = load_start.elapsed();
         let metadata = generator.metadata();
 
         tracing::info!(
-            "[MODEL] Model loaded successfully: {} | quant: {:?} | layers: {} | embed: {} | ctx: {} | vocab: {} | loaded in {:.2}s",
+            "[MODEL] Model loaded successfully: {} | quant: {} | layers: {} | embed: {} | ctx: {} | vocab: {} | loaded in {:.2}s",
             metadata.name,
-            metadata.quantization,
+            metadata.quant_summary(),
             metadata.n_layer,
             metadata.n_embd,
             metadata.context_length,
             metadata.vocab_size,
             load_time.as_secs_f32()
         );
+        generator.warmup_with(self.default_options.warmup)?;
 
         let generator = Arc::new(Mutex::new(generator));
 
@@ -84,11 +187,75 @@ impl AppState {
         Ok(generator)
     }
 
+    /// Loads `model_path` if needed and runs `warmup_tokens` through it, so
+    /// the first request does not pay for loading or cold kernels.
+    pub async fn preload_model(
+        &self,
+        model_path: &str,
+        warmup_tokens: usize,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let generator = self.get_or_load_model(model_path).await?;
+        if warmup_tokens == 0 {
+            return Ok(());
+        }
+        tokio::task::spawn_blocking(move || {
+            let mut gen = generator
+                .lock()
+                .map_err(|e| format!("Failed to lock generator: {}", e))?;
+            gen.warmup(warmup_tokens)?;
+            gen.clear_kv_cache();
+            Ok::<_, Box<dyn std::error::Error + Send + Sync>>(())
+        })
+        .await??;
+        Ok(())
+    }
+
+    /// Drops a cached model. Requests already using it finish first; the
+    /// memory is freed when the last one does. Returns whether it was loaded.
+    pub async fn unload_model(&self, model_path: &str) -> bool {
+        let removed = self.model_cache.write().await.remove(model_path).is_some();
+        self.tokenizers.write().await.remove(model_path);
+        if removed {
+            tracing::info!("[MODEL] Unloaded model: {}", model_path);
+        }
+        removed
+    }
+
+    /// Tokenizer of `model_path`, loaded on its own so tokenizing does not
+    /// wait for a generation holding the model.
+    pub async fn tokenizer(
+        &self,
+        model_path: &str,
+    ) -> Result<Arc<TokenizerWrapper>, Box<dyn std::error::Error + Send + Sync>> {
+        if let Some(tokenizer) = self.tokenizers.read().await.get(model_path) {
+            return Ok(tokenizer.clone());
+        }
+
+        let path = Path::new(model_path).to_path_buf();
+        if !path.exists() {
+            return Err(format!("Model file not found: {}", model_path).into());
+        }
+        let tokenizer = tokio::task::spawn_blocking(move || TokenizerWrapper::from_gguf(&path))
+            .await?
+            .map_err(|e| -> Box<dyn std::error::Error + Send + Sync> { e.into() })?;
+        let tokenizer = Arc::new(tokenizer);
+
+        self.tokenizers
+            .write()
+            .await
+            .insert(model_path.to_string(), tokenizer.clone());
+        Ok(tokenizer)
+    }
+
     pub async fn list_models(&self) -> Vec<String> {
         let cache = self.model_cache.read().await;
         cache.keys().cloned().collect()
     }
 
+    pub fn default_options(&self) -> &GenerateOptions {
+        &self.default_options
+    }
+
     pub fn set_default_options(&mut self, options: GenerateOptions) {
         self.default_options = options;
     }
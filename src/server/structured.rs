@@ -0,0 +1,286 @@
+//! Maps `response_format`, `tools` and `tool_choice` onto the engine's
+//! structured output.
+//!
+//! Decoding is not constrained: the model is told which JSON to produce, and
+//! the reply is parsed and validated against a schema so the handler can
+//! retry with the error as feedback, as `Model::generate_result` does. Tool
+//! calls are requested as `{"tool_calls": [{"name": ..., "arguments": {...}}]}`
+//! and converted to OpenAI `tool_calls`.
+
+use serde_json::{json, Value};
+
+use crate::inference::structured::{extract_json, validate};
+use crate::server::error::OpenAIError;
+use crate::server::types::{
+    ChatCompletionRequest, ResponseFormatParam, Tool, ToolCall, ToolChoice,
+};
+
+/// Attempts, including the first, before a structured reply is given up on.
+pub const MAX_ATTEMPTS: usize = 3;
+
+/// What a request requires the reply to look like.
+#[derive(Debug)]
+pub struct ReplyFormat {
+    kind: ReplyKind,
+    schema: Value,
+    instruction: String,
+}
+
+#[derive(Debug, PartialEq)]
+enum ReplyKind {
+    Json,
+    /// With `required` false the model may answer in text instead.
+    ToolCalls {
+        required: bool,
+    },
+}
+
+/// A parsed reply.
+#[derive(Debug, PartialEq)]
+pub enum Reply {
+    Text(String),
+    Json(String),
+    ToolCalls(Vec<ToolCall>),
+}
+
+impl ReplyFormat {
+    /// Reads the request's structured output fields. Returns `None` for a
+    /// plain text reply.
+    pub fn from_request(req: &ChatCompletionRequest) -> Result<Option<Self>, OpenAIError> {
+        let tools = req.tools.as_deref().unwrap_or_default();
+        let choice = req.tool_choice.as_ref();
+
+        if !tools.is_empty() && choice != Some(&ToolChoice::Mode("none".to_string())) {
+            return tool_format(tools, choice).map(Some);
+        }
+        if let Some(ToolChoice::Function { .. }) = choice {
+            return Err(OpenAIError::invalid_param(
+                "tool_choice",
+                "tool_choice names a function but no tools were given",
+            ));
+        }
+
+        Ok(match &req.response_format {
+            None | Some(ResponseFormatParam::Text) => None,
+            Some(ResponseFormatParam::JsonObject) => Some(Self::json(
+                json!({ "type": "object" }),
+                "Respond with only a JSON object, with no other text.".to_string(),
+            )),
+            Some(ResponseFormatParam::JsonSchema { json_schema }) => {
+                let schema = json_schema.schema.clone().unwrap_or_else(|| json!({}));
+                Some(Self::json(
+                    schema.clone(),
+                    format!(
+                        "Respond with only a JSON value that matches this JSON Schema, with no other text:\n{}",
+                        schema
+                    ),
+                ))
+            }
+        })
+    }
+
+    fn json(schema: Value, instruction: String) -> Self {
+        Self {
+            kind: ReplyKind::Json,
+            schema,
+            instruction,
+        }
+    }
+
+    /// Instruction added to the prompt.
+    pub fn instruction(&self) -> &str {
+        &self.instruction
+    }
+
+    /// Parses `text`. The error is phrased as retry feedback for the model.
+    pub fn parse(&self, text: &str) -> Result<Reply, String> {
+        match self.kind {
+            ReplyKind::Json => {
+                let value = extract_json(text).ok_or("the reply is not valid JSON")?;
+                validate(&self.schema, &value)?;
+                Ok(Reply::Json(value.to_string()))
+            }
+            ReplyKind::ToolCalls { required } => {
+                let value = extract_json(text).filter(|v| v.get("tool_calls").is_some());
+                let Some(value) = value else {
+                    if required {
+                        return Err("the reply must be a JSON object with \"tool_calls\"".into());
+                    }
+                    return Ok(Reply::Text(text.to_string()));
+                };
+                validate(&self.schema, &value)?;
+                let calls = value["tool_calls"]
+                    .as_array()
+                    .map(|calls| {
+                        calls
+                            .iter()
+                            .map(|call| {
+                                ToolCall::function(
+                                    call["name"].as_str().unwrap_or_default(),
+                                    &call["arguments"],
+                                )
+                            })
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                Ok(Reply::ToolCalls(calls))
+            }
+        }
+    }
+}
+
+fn tool_format(tools: &[Tool], choice: Option<&ToolChoice>) -> Result<ReplyFormat, OpenAIError> {
+    let (allowed, required): (Vec<&Tool>, bool) = match choice {
+        None => (tools.iter().collect(), false),
+        Some(ToolChoice::Mode(mode)) => match mode.as_str() {
+            "auto" => (tools.iter().collect(), false),
+            "required" => (tools.iter().collect(), true),
+            other => {
+                return Err(OpenAIError::invalid_param(
+                    "tool_choice",
+                    &format!("Unknown tool_choice '{}'", other),
+                ))
+            }
+        },
+        Some(ToolChoice::Function { function, .. }) => {
+            let tool = tools
+                .iter()
+                .find(|t| t.function.name == function.name)
+                .ok_or_else(|| {
+                    OpenAIError::invalid_param(
+                        "tool_choice",
+                        &format!("No tool named '{}'", function.name),
+                    )
+                })?;
+            (vec![tool], true)
+        }
+    };
+
+    let calls: Vec<Value> = allowed
+        .iter()
+        .map(|tool| {
+            json!({
+                "type": "object",
+                "properties": {
+                    "name": { "const": tool.function.name },
+                    "arguments": tool
+                        .function
+                        .parameters
+                        .clone()
+                        .unwrap_or_else(|| json!({ "type": "object" })),
+                },
+                "required": ["name", "arguments"],
+            })
+        })
+        .collect();
+    let schema = json!({
+        "type": "object",
+        "properties": {
+            "tool_calls": { "type": "array", "minItems": 1, "items": { "anyOf": calls } },
+        },
+        "required": ["tool_calls"],
+    });
+
+    let listing: Vec<String> = allowed
+        .iter()
+        .map(|tool| {
+            format!(
+                "- {}: {}\n  arguments schema: {}",
+                tool.function.name,
+                tool.function.description.as_deref().unwrap_or(""),
+                tool.function
+                    .parameters
+                    .clone()
+                    .unwrap_or_else(|| json!({ "type": "object" }))
+            )
+        })
+        .collect();
+    let how = if required {
+        "You must call a tool. Respond with only"
+    } else {
+        "If a tool is needed, respond with only"
+    };
+    let mut instruction = format!(
+        "You can call these tools:\n{}\n{} a JSON object of the form {{\"tool_calls\": [{{\"name\": \"<tool>\", \"arguments\": {{...}}}}]}}, with no other text.",
+        listing.join("\n"),
+        how
+    );
+    if !required {
+        instruction.push_str(" Otherwise answer normally.");
+    }
+
+    Ok(ReplyFormat {
+        kind: ReplyKind::ToolCalls { required },
+        schema,
+        instruction,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Reply, ReplyFormat};
+    use crate::server::types::ChatCompletionRequest;
+
+    fn request(extra: serde_json::Value) -> ChatCompletionRequest {
+        let mut body = serde_json::json!({ "model": "m.gguf", "messages": [] });
+        body.as_object_mut()
+            .unwrap()
+            .extend(extra.as_object().unwrap().clone());
+        serde_json::from_value(body).unwrap()
+    }
+
+    #[test]
+    fn parses_tool_calls_and_json_replies() {
+        let weather = serde_json::json!({
+            "tools": [{
+                "type": "function",
+                "function": {
+                    "name": "get_weather",
+                    "parameters": {
+                        "type": "object",
+                        "properties": { "city": { "type": "string" } },
+                        "required": ["city"]
+                    }
+                }
+            }]
+        });
+        let format = ReplyFormat::from_request(&request(weather.clone()))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            format.parse("It is sunny.").unwrap(),
+            Reply::Text("It is sunny.".to_string())
+        );
+        let Reply::ToolCalls(calls) = format
+            .parse(r#"{"tool_calls": [{"name": "get_weather", "arguments": {"city": "Oslo"}}]}"#)
+            .unwrap()
+        else {
+            panic!("expected tool calls");
+        };
+        assert_eq!(calls[0].function.name, "get_weather");
+        assert_eq!(calls[0].function.arguments, r#"{"city":"Oslo"}"#);
+        assert!(format
+            .parse(r#"{"tool_calls": [{"name": "get_weather", "arguments": {}}]}"#)
+            .is_err());
+
+        let mut required = weather;
+        required["tool_choice"] = "required".into();
+        let format = ReplyFormat::from_request(&request(required))
+            .unwrap()
+            .unwrap();
+        assert!(format.parse("It is sunny.").is_err());
+
+        let json = request(serde_json::json!({ "response_format": { "type": "json_object" } }));
+        let format = ReplyFormat::from_request(&json).unwrap().unwrap();
+        assert_eq!(
+            format.parse("```json\n{\"a\": 1}\n```").unwrap(),
+            Reply::Json(r#"{"a":1}"#.to_string())
+        );
+        assert!(format.parse("[1]").is_err());
+
+        let bad = request(serde_json::json!({
+            "tool_choice": { "type": "function", "function": { "name": "missing" } }
+        }));
+        assert!(ReplyFormat::from_request(&bad).is_err());
+    }
+}
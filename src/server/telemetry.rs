@@ -0,0 +1,303 @@
+//! Prometheus and OpenTelemetry output, built with the `telemetry` feature.
+//!
+//! `/metrics` renders [`ServerMetrics`](super::ServerMetrics) in the
+//! Prometheus text format. [`TraceExporter`] turns each completion's
+//! [`RequestTiming`] into a `chat.completion` span with `prefill` and
+//! `decode` children and posts them in batches to an OTLP/HTTP collector as
+//! JSON, so no gRPC or protobuf stack is needed.
+
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use crate::server::metrics::{MetricsSnapshot, RequestTiming};
+
+/// Spans posted in one export request at most.
+const MAX_BATCH: usize = 256;
+
+/// Renders counters and the loaded model count in the Prometheus text format.
+pub fn prometheus_text(snapshot: &MetricsSnapshot, models_loaded: usize) -> String {
+    let metrics: [(&str, &str, &str, u64); 7] = [
+        (
+            "oxide_requests_total",
+            "counter",
+            "Chat completion requests that ran generation.",
+            snapshot.requests,
+        ),
+        (
+            "oxide_streams_cancelled_total",
+            "counter",
+            "Streams stopped early because the client disconnected.",
+            snapshot.streams_cancelled,
+        ),
+        (
+            "oxide_prompt_tokens_total",
+            "counter",
+            "Prompt tokens processed.",
+            snapshot.prompt_tokens,
+        ),
+        (
+            "oxide_completion_tokens_total",
+            "counter",
+            "Completion tokens generated.",
+            snapshot.completion_tokens,
+        ),
+        (
+            "oxide_cache_hits_total",
+            "counter",
+            "Requests answered from the response cache.",
+            snapshot.cache_hits,
+        ),
+        (
+            "oxide_cache_misses_total",
+            "counter",
+            "Cacheable requests that had to be generated.",
+            snapshot.cache_misses,
+        ),
+        (
+            "oxide_models_loaded",
+            "gauge",
+            "Models currently loaded.",
+            models_loaded as u64,
+        ),
+    ];
+
+    let mut text = String::new();
+    for (name, kind, help, value) in metrics {
+        text.push_str(&format!(
+            "# HELP {0} {1}\n# TYPE {0} {2}\n{0} {3}\n",
+            name, help, kind, value
+        ));
+    }
+
+    let name = "oxide_token_latency_seconds";
+    let histogram = &snapshot.token_latency;
+    text.push_str(&format!(
+        "# HELP {0} Time per decode step, from forward pass to sampled token.\n# TYPE {0} histogram\n",
+        name
+    ));
+    for (bound_ms, count) in histogram.cumulative_counts() {
+        text.push_str(&format!(
+            "{}_bucket{{le=\"{}\"}} {}\n",
+            name,
+            bound_ms as f64 / 1000.0,
+            count
+        ));
+    }
+    text.push_str(&format!(
+        "{0}_bucket{{le=\"+Inf\"}} {1}\n{0}_sum {2}\n{0}_count {1}\n",
+        name,
+        histogram.count(),
+        histogram.sum().as_secs_f64()
+    ));
+    text
+}
+
+/// Where and as what traces are exported.
+#[derive(Clone, Debug)]
+pub struct OtlpConfig {
+    /// Collector base URL, e.g. `http://localhost:4318`; spans go to
+    /// `{endpoint}/v1/traces`.
+    pub endpoint: String,
+    /// `service.name` resource attribute.
+    pub service_name: String,
+}
+
+/// Sends request spans to an OTLP/HTTP collector from a background thread.
+#[derive(Debug)]
+pub struct TraceExporter {
+    tx: mpsc::UnboundedSender<RequestTiming>,
+}
+
+impl TraceExporter {
+    pub fn new(config: OtlpConfig) -> Self {
+        let (tx, mut rx) = mpsc::unbounded_channel::<RequestTiming>();
+        std::thread::spawn(move || {
+            let client = reqwest::blocking::Client::new();
+            let url = format!("{}/v1/traces", config.endpoint.trim_end_matches('/'));
+            while let Some(timing) = rx.blocking_recv() {
+                let mut spans = request_spans(&timing);
+                while spans.len() < MAX_BATCH {
+                    let Ok(timing) = rx.try_recv() else {
+                        break;
+                    };
+                    spans.extend(request_spans(&timing));
+                }
+                let body = export_body(&config.service_name, spans);
+                let result = client
+                    .post(&url)
+                    .header("content-type", "application/json")
+                    .body(body.to_string())
+                    .send()
+                    .and_then(|response| response.error_for_status());
+                if let Err(e) = result {
+                    tracing::warn!("[TELEMETRY] Failed to export spans: {}", e);
+                }
+            }
+        });
+        Self { tx }
+    }
+
+    /// Queues a finished request for export. Never blocks.
+    pub fn export(&self, timing: RequestTiming) {
+        let _ = self.tx.send(timing);
+    }
+}
+
+fn unix_nanos(timing: &RequestTiming, at: Instant) -> String {
+    let offset = at.saturating_duration_since(timing.received);
+    let time = timing.started_at + offset;
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_nanos()
+        .to_string()
+}
+
+fn span_id() -> String {
+    Uuid::new_v4().simple().to_string()[..16].to_string()
+}
+
+fn attribute(key: &str, value: Value) -> Value {
+    let value = match value {
+        Value::Bool(b) => json!({ "boolValue": b }),
+        Value::Number(n) => json!({ "intValue": n.to_string() }),
+        other => json!({ "stringValue": other.as_str().unwrap_or_default() }),
+    };
+    json!({ "key": key, "value": value })
+}
+
+/// OTLP spans of one request: the request itself, then prefill and decode.
+fn request_spans(timing: &RequestTiming) -> Vec<Value> {
+    let trace_id = Uuid::new_v4().simple().to_string();
+    let root_id = span_id();
+    let end = timing.finished.unwrap_or_else(Instant::now);
+    let span = |id: &str,
+                parent: Option<&str>,
+                name: &str,
+                start: Instant,
+                end: Instant,
+                attributes: Vec<Value>| {
+        json!({
+            "traceId": trace_id,
+            "spanId": id,
+            "parentSpanId": parent.unwrap_or_default(),
+            "name": name,
+            // SPAN_KIND_SERVER for the request, SPAN_KIND_INTERNAL below it.
+            "kind": if parent.is_none() { 2 } else { 1 },
+            "startTimeUnixNano": unix_nanos(timing, start),
+            "endTimeUnixNano": unix_nanos(timing, end),
+            "attributes": attributes,
+        })
+    };
+
+    let mut spans = vec![span(
+        &root_id,
+        None,
+        "chat.completion",
+        timing.received,
+        end,
+        vec![
+            attribute("gen_ai.request.model", json!(timing.model)),
+            attribute("oxide.stream", json!(timing.stream)),
+            attribute("gen_ai.usage.input_tokens", json!(timing.prompt_tokens)),
+            attribute(
+                "gen_ai.usage.output_tokens",
+                json!(timing.completion_tokens),
+            ),
+        ],
+    )];
+    if let Some(prefill_start) = timing.prefill_start {
+        let prefill_end = timing.first_token.unwrap_or(end);
+        spans.push(span(
+            &span_id(),
+            Some(&root_id),
+            "prefill",
+            prefill_start,
+            prefill_end,
+            vec![attribute(
+                "oxide.prompt_tokens",
+                json!(timing.prompt_tokens),
+            )],
+        ));
+        if timing.first_token.is_some() {
+            spans.push(span(
+                &span_id(),
+                Some(&root_id),
+                "decode",
+                prefill_end,
+                end,
+                vec![attribute(
+                    "oxide.completion_tokens",
+                    json!(timing.completion_tokens),
+                )],
+            ));
+        }
+    }
+    spans
+}
+
+fn export_body(service_name: &str, spans: Vec<Value>) -> Value {
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [attribute("service.name", json!(service_name))],
+            },
+            "scopeSpans": [{
+                "scope": { "name": "oxide-rs", "version": env!("CARGO_PKG_VERSION") },
+                "spans": spans,
+            }],
+        }],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::{prometheus_text, request_spans};
+    use crate::server::metrics::{MetricsSnapshot, RequestTiming};
+
+    #[test]
+    fn renders_prometheus_text() {
+        let snapshot = MetricsSnapshot {
+            requests: 3,
+            cache_hits: 1,
+            ..Default::default()
+        };
+        let text = prometheus_text(&snapshot, 2);
+        assert!(text.contains("# TYPE oxide_requests_total counter\noxide_requests_total 3\n"));
+        assert!(text.contains("oxide_cache_hits_total 1\n"));
+        assert!(text.contains("# TYPE oxide_models_loaded gauge\noxide_models_loaded 2\n"));
+
+        let mut snapshot = MetricsSnapshot::default();
+        snapshot.token_latency.record(Duration::from_millis(15));
+        let text = prometheus_text(&snapshot, 0);
+        assert!(text.contains("oxide_token_latency_seconds_bucket{le=\"0.01\"} 0\n"));
+        assert!(text.contains("oxide_token_latency_seconds_bucket{le=\"0.02\"} 1\n"));
+        assert!(text.contains("oxide_token_latency_seconds_bucket{le=\"+Inf\"} 1\n"));
+        assert!(text.contains("oxide_token_latency_seconds_count 1\n"));
+    }
+
+    #[test]
+    fn builds_request_prefill_and_decode_spans() {
+        let mut timing = RequestTiming::new("m.gguf", true);
+        timing.started_at = SystemTime::UNIX_EPOCH + Duration::from_secs(10);
+        timing.prefill_start = Some(timing.received + Duration::from_millis(5));
+        timing.first_token = Some(timing.received + Duration::from_millis(40));
+        timing.finished = Some(timing.received + Duration::from_millis(100));
+
+        let spans = request_spans(&timing);
+        let names: Vec<&str> = spans.iter().map(|s| s["name"].as_str().unwrap()).collect();
+        assert_eq!(names, ["chat.completion", "prefill", "decode"]);
+        assert_eq!(spans[1]["parentSpanId"], spans[0]["spanId"]);
+        assert_eq!(spans[1]["traceId"], spans[0]["traceId"]);
+        assert_eq!(spans[0]["startTimeUnixNano"], "10000000000");
+        assert_eq!(spans[1]["endTimeUnixNano"], "10040000000");
+        assert_eq!(spans[2]["endTimeUnixNano"], "10100000000");
+
+        timing.first_token = None;
+        assert_eq!(request_spans(&timing).len(), 2);
+    }
+}
@@ -1,8 +1,13 @@
 pub mod request;
 pub mod response;
 
-pub use request::{ChatCompletionRequest, ChatMessage, ChatMessage as Message, MessageRole, Stop};
+pub use request::{
+    ChatCompletionRequest, ChatMessage, ChatMessage as Message, FunctionDefinition,
+    JsonSchemaFormat, MessageRole, NamedFunction, ResponseFormatParam, Stop, Tool, ToolChoice,
+};
 pub use response::{
     create_completion_id, get_timestamp, ChatCompletionChunk, ChatCompletionMessage,
-    ChatCompletionResponse, Choice, ChunkChoice, Delta, Model, ModelList, ModelPermission, Usage,
+    ChatCompletionResponse, ChoiceLogprobs, Choice, ChunkChoice, ContextTruncation, Delta,
+    DeltaToolCall, FunctionCall, LogprobContent, Model, ModelList, ModelPermission, ToolCall,
+    TopLogprobContent, Usage,
 };
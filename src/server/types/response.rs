@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
 
+use crate::inference::{ModerationResult, TokenLogprob, TruncationStrategy};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatCompletionResponse {
     pub id: String,
@@ -8,6 +10,21 @@ pub struct ChatCompletionResponse {
     pub model: String,
     pub choices: Vec<Choice>,
     pub usage: Usage,
+    /// Detected language (ISO 639-1) of the last user message.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// Set when older history was dropped to fit the prompt in the context window.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_truncated: Option<ContextTruncation>,
+    /// Moderation scores for the reply, when moderation is configured.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub moderation: Option<ModerationResult>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContextTruncation {
+    pub dropped_tokens: usize,
+    pub strategy: TruncationStrategy,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -16,12 +33,97 @@ pub struct Choice {
     pub message: ChatCompletionMessage,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub finish_reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<ChoiceLogprobs>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatCompletionMessage {
     pub role: String,
-    pub content: String,
+    /// `null` when the reply is only tool calls.
+    pub content: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCall>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ToolCall {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub call_type: String,
+    pub function: FunctionCall,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FunctionCall {
+    pub name: String,
+    /// Arguments as a JSON-encoded string, as in the OpenAI API.
+    pub arguments: String,
+}
+
+impl ToolCall {
+    pub fn function(name: &str, arguments: &serde_json::Value) -> Self {
+        Self {
+            id: format!("call_{}", uuid::Uuid::new_v4().simple()),
+            call_type: "function".to_string(),
+            function: FunctionCall {
+                name: name.to_string(),
+                arguments: arguments.to_string(),
+            },
+        }
+    }
+}
+
+/// A tool call inside a streamed delta.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeltaToolCall {
+    pub index: usize,
+    #[serde(flatten)]
+    pub call: ToolCall,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChoiceLogprobs {
+    pub content: Vec<LogprobContent>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogprobContent {
+    pub token: String,
+    pub logprob: f32,
+    pub bytes: Vec<u8>,
+    pub top_logprobs: Vec<TopLogprobContent>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TopLogprobContent {
+    pub token: String,
+    pub logprob: f32,
+    pub bytes: Vec<u8>,
+}
+
+impl ChoiceLogprobs {
+    pub fn from_tokens(tokens: &[TokenLogprob]) -> Self {
+        Self {
+            content: tokens
+                .iter()
+                .map(|t| LogprobContent {
+                    token: t.token.clone(),
+                    logprob: t.logprob,
+                    bytes: t.token.as_bytes().to_vec(),
+                    top_logprobs: t
+                        .top
+                        .iter()
+                        .map(|top| TopLogprobContent {
+                            token: top.token.clone(),
+                            logprob: top.logprob,
+                            bytes: top.token.as_bytes().to_vec(),
+                        })
+                        .collect(),
+                })
+                .collect(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -48,6 +150,9 @@ pub struct ChatCompletionChunk {
     pub created: u64,
     pub model: String,
     pub choices: Vec<ChunkChoice>,
+    /// Set on the first chunk when older history was dropped to fit the prompt.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_truncated: Option<ContextTruncation>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -56,6 +161,8 @@ pub struct ChunkChoice {
     pub delta: Delta,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub finish_reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<ChoiceLogprobs>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -64,6 +171,8 @@ pub struct Delta {
     pub role: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub content: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<DeltaToolCall>>,
 }
 
 impl Delta {
@@ -71,6 +180,7 @@ impl Delta {
         Self {
             content: Some(content.to_string()),
             role: None,
+            tool_calls: None,
         }
     }
 
@@ -78,6 +188,7 @@ impl Delta {
         Self {
             role: Some(role.to_string()),
             content: Some(content.to_string()),
+            tool_calls: None,
         }
     }
 
@@ -85,6 +196,7 @@ impl Delta {
         Self {
             role: Some(role.to_string()),
             content: None,
+            tool_calls: None,
         }
     }
 
@@ -92,6 +204,7 @@ impl Delta {
         Self {
             role: None,
             content: Some(content.to_string()),
+            tool_calls: None,
         }
     }
 }
@@ -0,0 +1,231 @@
+//! Token usage per API key and model, in hourly buckets.
+//!
+//! The key is the bearer token of the request, stored only as a short
+//! SHA-256 id (`key_` plus 12 hex digits); requests without one count as
+//! `anonymous`. Usage lives in memory, resets when the server restarts, and
+//! buckets older than [`RETENTION_DAYS`] are dropped.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+/// Key id used for requests without a bearer token.
+pub const ANONYMOUS_KEY: &str = "anonymous";
+
+/// How long hourly buckets are kept.
+pub const RETENTION_DAYS: i64 = 31;
+
+/// Size of the buckets a report aggregates into.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Bucket {
+    Hour,
+    #[default]
+    Day,
+}
+
+impl Bucket {
+    fn duration(self) -> Duration {
+        match self {
+            Bucket::Hour => Duration::hours(1),
+            Bucket::Day => Duration::days(1),
+        }
+    }
+}
+
+/// Usage of one key and model in one bucket.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct UsageRow {
+    pub start: DateTime<Utc>,
+    pub key: String,
+    pub model: String,
+    pub requests: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_tokens: u64,
+}
+
+/// Which usage a report covers. Unset fields match everything.
+#[derive(Clone, Debug, Default)]
+pub struct UsageFilter {
+    pub key: Option<String>,
+    pub model: Option<String>,
+    pub start: Option<DateTime<Utc>>,
+    pub end: Option<DateTime<Utc>>,
+}
+
+/// Hourly counts keyed by bucket start, key id and model.
+type UsageMap = BTreeMap<(DateTime<Utc>, String, String), Counts>;
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Counts {
+    requests: u64,
+    prompt_tokens: u64,
+    completion_tokens: u64,
+}
+
+/// Records token usage and reports it in hour or day buckets.
+#[derive(Debug, Default)]
+pub struct UsageTracker {
+    usage: Mutex<UsageMap>,
+}
+
+/// Short, stable id for an API key, so the key itself is never stored.
+pub fn key_id(token: &str) -> String {
+    let digest = Sha256::digest(token.as_bytes());
+    let hex: String = digest
+        .iter()
+        .take(6)
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    format!("key_{}", hex)
+}
+
+fn truncate(time: DateTime<Utc>, bucket: Bucket) -> DateTime<Utc> {
+    time.duration_trunc(bucket.duration()).unwrap_or(time)
+}
+
+impl UsageTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds one finished request.
+    pub fn record(&self, key: &str, model: &str, prompt_tokens: usize, completion_tokens: usize) {
+        self.record_at(Utc::now(), key, model, prompt_tokens, completion_tokens);
+    }
+
+    fn record_at(
+        &self,
+        time: DateTime<Utc>,
+        key: &str,
+        model: &str,
+        prompt_tokens: usize,
+        completion_tokens: usize,
+    ) {
+        let start = truncate(time, Bucket::Hour);
+        let mut usage = self.usage.lock().unwrap();
+        let counts = usage
+            .entry((start, key.to_string(), model.to_string()))
+            .or_default();
+        counts.requests += 1;
+        counts.prompt_tokens += prompt_tokens as u64;
+        counts.completion_tokens += completion_tokens as u64;
+
+        let cutoff = start - Duration::days(RETENTION_DAYS);
+        usage.retain(|(bucket_start, _, _), _| *bucket_start >= cutoff);
+    }
+
+    /// Usage matching `filter`, summed into `bucket`s, oldest first.
+    pub fn report(&self, bucket: Bucket, filter: &UsageFilter) -> Vec<UsageRow> {
+        let usage = self.usage.lock().unwrap();
+        let mut rows = UsageMap::new();
+        for ((start, key, model), counts) in usage.iter() {
+            if filter.key.as_ref().is_some_and(|k| k != key)
+                || filter.model.as_ref().is_some_and(|m| m != model)
+                || filter
+                    .start
+                    .is_some_and(|s| *start < truncate(s, Bucket::Hour))
+                || filter.end.is_some_and(|e| *start >= e)
+            {
+                continue;
+            }
+            let row = rows
+                .entry((truncate(*start, bucket), key.clone(), model.clone()))
+                .or_default();
+            row.requests += counts.requests;
+            row.prompt_tokens += counts.prompt_tokens;
+            row.completion_tokens += counts.completion_tokens;
+        }
+
+        rows.into_iter()
+            .map(|((start, key, model), counts)| UsageRow {
+                start,
+                key,
+                model,
+                requests: counts.requests,
+                prompt_tokens: counts.prompt_tokens,
+                completion_tokens: counts.completion_tokens,
+                total_tokens: counts.prompt_tokens + counts.completion_tokens,
+            })
+            .collect()
+    }
+}
+
+/// Renders rows as CSV with a header line.
+pub fn to_csv(rows: &[UsageRow]) -> String {
+    let mut csv =
+        String::from("start,key,model,requests,prompt_tokens,completion_tokens,total_tokens\n");
+    for row in rows {
+        csv.push_str(&format!(
+            "{},{},{},{},{},{},{}\n",
+            row.start.to_rfc3339(),
+            csv_field(&row.key),
+            csv_field(&row.model),
+            row.requests,
+            row.prompt_tokens,
+            row.completion_tokens,
+            row.total_tokens
+        ));
+    }
+    csv
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use super::{key_id, to_csv, Bucket, UsageFilter, UsageTracker};
+
+    #[test]
+    fn buckets_usage_by_key_and_model() {
+        let tracker = UsageTracker::new();
+        let at = |h, m| Utc.with_ymd_and_hms(2026, 3, 1, h, m, 0).unwrap();
+        let alice = key_id("sk-alice");
+        tracker.record_at(at(9, 5), &alice, "a.gguf", 10, 5);
+        tracker.record_at(at(9, 50), &alice, "a.gguf", 20, 5);
+        tracker.record_at(at(11, 0), &alice, "a.gguf", 1, 1);
+        tracker.record_at(at(11, 0), "anonymous", "b,c.gguf", 3, 4);
+
+        let hourly = tracker.report(Bucket::Hour, &UsageFilter::default());
+        assert_eq!(hourly.len(), 3);
+        assert_eq!(hourly[0].requests, 2);
+        assert_eq!(hourly[0].total_tokens, 40);
+
+        let daily = tracker.report(
+            Bucket::Day,
+            &UsageFilter {
+                key: Some(alice.clone()),
+                ..Default::default()
+            },
+        );
+        assert_eq!(daily.len(), 1);
+        assert_eq!(daily[0].requests, 3);
+        assert_eq!(daily[0].prompt_tokens, 31);
+
+        let late = tracker.report(
+            Bucket::Hour,
+            &UsageFilter {
+                start: Some(at(10, 30)),
+                ..Default::default()
+            },
+        );
+        assert_eq!(late.len(), 2);
+
+        assert!(alice.starts_with("key_") && alice.len() == 16);
+        let csv = to_csv(&late);
+        assert!(csv.starts_with("start,key,model,"));
+        assert!(csv.contains(",\"b,c.gguf\",1,3,4,7\n"));
+    }
+}
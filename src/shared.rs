@@ -0,0 +1,383 @@
+//! One loaded model serving independent conversations from many threads.
+//!
+//! # Threading contract
+//!
+//! [`SharedModel`] and [`Session`] are `Send + Sync`; a `SharedModel` is
+//! cheap to clone and every clone refers to the same weights. Each
+//! `Session` keeps its own history, so sessions never see each other's
+//! messages.
+//!
+//! The KV cache lives inside the model, so only one generation runs at a
+//! time, and generations are serialized per reply, not per decode step: a
+//! session holds the model from its prompt to the end of its reply and
+//! other sessions wait for it, in no particular order. When a session
+//! generates again before any other session has, its cached tokens are
+//! reused; otherwise its whole history is prefilled again first, so every
+//! switch between sessions costs a prefill of the incoming conversation.
+//! Interleaving sessions token by token would pay that cost at every step. Streaming callbacks run
+//! while the model is held, so generating from another session inside one
+//! deadlocks. A generation that panics leaves the model usable by the
+//! other sessions.
+//!
+//! To share the model fairly between long replies, generate in slices with
+//! [`Session::generate_until`]: the reply pauses once its `should_pause`
+//! check returns `true` and releases the model, and
+//! [`Session::resume_until`] carries on from where it stopped. A resumed
+//! slice prefills the conversation again, so slices should be long enough
+//! to make that worthwhile.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::inference::{Generator, Message, StreamChunker, StreamEvent, StreamGranularity};
+
+type SessionResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Settings every session generates with.
+#[derive(Clone, Copy, Debug)]
+struct SessionSettings {
+    max_tokens: usize,
+    repeat_penalty: f32,
+    repeat_last_n: usize,
+    stream_granularity: StreamGranularity,
+}
+
+/// Set by [`Session::generate_until`] and read by the stop condition every
+/// shared generator carries.
+#[derive(Default)]
+struct SliceState {
+    pause_requested: AtomicBool,
+    paused: AtomicBool,
+    /// Tokens generated before the pause.
+    tokens: AtomicUsize,
+}
+
+impl SliceState {
+    fn reset(&self) {
+        self.pause_requested.store(false, Ordering::Relaxed);
+        self.paused.store(false, Ordering::Relaxed);
+        self.tokens.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Part of a reply from [`Session::generate_until`] or
+/// [`Session::resume_until`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Slice {
+    /// Text generated in this slice.
+    pub text: String,
+    /// The reply was paused before it finished and can be resumed.
+    pub paused: bool,
+    /// Tokens of older history dropped to fit the context window first.
+    pub dropped_tokens: usize,
+}
+
+struct Shared {
+    generator: Generator,
+    /// Session whose history is in the generator and KV cache.
+    owner: Option<u64>,
+}
+
+/// A loaded model that hands out independent [`Session`]s.
+///
+/// Create one with [`Model::into_shared`](crate::Model::into_shared).
+#[derive(Clone)]
+pub struct SharedModel {
+    shared: Arc<Mutex<Shared>>,
+    settings: SessionSettings,
+    slice: Arc<SliceState>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl SharedModel {
+    pub(crate) fn new(
+        mut generator: Generator,
+        max_tokens: usize,
+        repeat_penalty: f32,
+        repeat_last_n: usize,
+        stream_granularity: StreamGranularity,
+    ) -> Self {
+        let slice = Arc::new(SliceState::default());
+        let state = Arc::clone(&slice);
+        generator.add_stop_condition(move |ctx| {
+            if !state.pause_requested.load(Ordering::Relaxed) {
+                return false;
+            }
+            state.tokens.store(ctx.tokens, Ordering::Relaxed);
+            state.paused.store(true, Ordering::Relaxed);
+            true
+        });
+        Self {
+            shared: Arc::new(Mutex::new(Shared {
+                generator,
+                owner: None,
+            })),
+            settings: SessionSettings {
+                max_tokens,
+                repeat_penalty,
+                repeat_last_n,
+                stream_granularity,
+            },
+            slice,
+            next_id: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Starts a conversation with an empty history.
+    pub fn new_session(&self) -> Session {
+        Session {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            model: self.clone(),
+            messages: Vec::new(),
+            paused_after: None,
+            context_used: 0,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Shared> {
+        self.shared.lock().unwrap_or_else(|poisoned| {
+            // A generation panicked; its history and cache cannot be trusted.
+            let mut shared = poisoned.into_inner();
+            shared.owner = None;
+            shared
+        })
+    }
+}
+
+/// One conversation over a [`SharedModel`]. Move it to any thread; see the
+/// [module docs](self) for how concurrent sessions share the model.
+pub struct Session {
+    id: u64,
+    model: SharedModel,
+    messages: Vec<Message>,
+    /// Tokens generated so far when the last reply was paused.
+    paused_after: Option<usize>,
+    context_used: usize,
+}
+
+impl Session {
+    /// Generates a reply to `prompt` and adds both to this session's history.
+    /// Holds the model until the reply is complete; see the
+    /// [module docs](self).
+    pub fn generate(&mut self, prompt: &str) -> SessionResult<String> {
+        self.generate_stream(prompt, |_| {})
+    }
+
+    /// Like [`generate`](Self::generate), calling `callback` with chunks of
+    /// the reply as they are generated.
+    pub fn generate_stream<F>(&mut self, prompt: &str, callback: F) -> SessionResult<String>
+    where
+        F: FnMut(String),
+    {
+        let slice = self.run(Some(prompt), callback, || false)?;
+        Ok(slice.text)
+    }
+
+    /// Like [`generate_stream`](Self::generate_stream), but pauses the reply
+    /// once `should_pause`, checked after every token, returns `true`. The
+    /// partial reply is in the history; finish it with
+    /// [`resume_until`](Self::resume_until) or move on with a new prompt.
+    pub fn generate_until<F, P>(
+        &mut self,
+        prompt: &str,
+        callback: F,
+        should_pause: P,
+    ) -> SessionResult<Slice>
+    where
+        F: FnMut(String),
+        P: FnMut() -> bool,
+    {
+        self.run(Some(prompt), callback, should_pause)
+    }
+
+    /// Continues the reply paused by [`generate_until`](Self::generate_until)
+    /// or an earlier call of this method. The model's `max_tokens` applies
+    /// to the reply as a whole. After an error the partial reply stays in
+    /// the history but can no longer be resumed.
+    pub fn resume_until<F, P>(&mut self, callback: F, should_pause: P) -> SessionResult<Slice>
+    where
+        F: FnMut(String),
+        P: FnMut() -> bool,
+    {
+        if self.paused_after.is_none() {
+            return Err("No paused reply to resume".into());
+        }
+        self.run(None, callback, should_pause)
+    }
+
+    /// Whether the last reply was paused and not yet resumed.
+    pub fn is_paused(&self) -> bool {
+        self.paused_after.is_some()
+    }
+
+    /// Tokens of context this session's history took after its last reply.
+    pub fn context_used(&self) -> usize {
+        self.context_used
+    }
+
+    /// Generates a reply to `prompt`, or continues the paused one when it is
+    /// `None`.
+    fn run<F, P>(
+        &mut self,
+        prompt: Option<&str>,
+        mut callback: F,
+        mut should_pause: P,
+    ) -> SessionResult<Slice>
+    where
+        F: FnMut(String),
+        P: FnMut() -> bool,
+    {
+        let settings = self.model.settings;
+        let generated = match prompt {
+            Some(_) => 0,
+            None => self.paused_after.unwrap_or(0),
+        };
+        let max_tokens = match settings.max_tokens {
+            0 => 0,
+            max if generated >= max => {
+                // The paused reply already used its budget.
+                self.paused_after = None;
+                return Ok(Slice {
+                    text: String::new(),
+                    paused: false,
+                    dropped_tokens: 0,
+                });
+            }
+            max => max - generated,
+        };
+
+        let slice = Arc::clone(&self.model.slice);
+        let mut shared = self.model.lock();
+        if shared.owner != Some(self.id) {
+            shared.owner = None;
+            shared.generator.set_history(self.messages.clone())?;
+            shared.owner = Some(self.id);
+        }
+
+        slice.reset();
+        let mut chunker = StreamChunker::new(settings.stream_granularity);
+        let mut dropped_tokens = 0;
+        let on_event = |event| match event {
+            StreamEvent::Token(t) => {
+                if let Some(chunk) = chunker.push(&t) {
+                    callback(chunk);
+                }
+                if should_pause() {
+                    slice.pause_requested.store(true, Ordering::Relaxed);
+                }
+            }
+            StreamEvent::ContextTruncated {
+                dropped_tokens: n, ..
+            } => dropped_tokens = n,
+            _ => {}
+        };
+        let result = match prompt {
+            Some(prompt) => shared.generator.generate(
+                prompt,
+                max_tokens,
+                settings.repeat_penalty,
+                settings.repeat_last_n,
+                on_event,
+            ),
+            None => shared.generator.continue_reply(
+                max_tokens,
+                settings.repeat_penalty,
+                settings.repeat_last_n,
+                on_event,
+            ),
+        };
+        let paused = slice.paused.load(Ordering::Relaxed);
+        let tokens = slice.tokens.load(Ordering::Relaxed);
+        slice.reset();
+        let text = match result {
+            Ok(text) => text,
+            Err(e) => {
+                // The prompt may be in the generator's history but not ours.
+                shared.owner = None;
+                self.paused_after = None;
+                return Err(crate::generation_error(e));
+            }
+        };
+        if let Some(rest) = chunker.finish() {
+            callback(rest);
+        }
+        self.paused_after = paused.then_some(generated + tokens);
+        self.messages = shared.generator.history().to_vec();
+        self.context_used = shared.generator.context_used();
+        Ok(Slice {
+            text,
+            paused,
+            dropped_tokens,
+        })
+    }
+
+    /// This session's conversation, excluding the system prompt.
+    pub fn history(&self) -> &[Message] {
+        &self.messages
+    }
+
+    pub fn clear_history(&mut self) {
+        self.messages.clear();
+        self.paused_after = None;
+        self.context_used = 0;
+        let mut shared = self.model.lock();
+        if shared.owner == Some(self.id) {
+            shared.owner = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Session, SharedModel};
+    use crate::inference::{Generator, StreamGranularity};
+    use crate::model::convert::tiny_llama;
+
+    #[test]
+    fn sessions_can_move_between_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<SharedModel>();
+        assert_send_sync::<Session>();
+    }
+
+    #[test]
+    fn concurrent_sessions_keep_their_own_histories() {
+        const THREADS: usize = 4;
+        const ROUNDS: usize = 3;
+        let dir = std::env::temp_dir().join(format!("oxide-shared-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = tiny_llama(&dir);
+        let generator = Generator::new(&path, Some(&path), 0.8, None, None, 42, None, 32).unwrap();
+        let model = SharedModel::new(generator, 4, 1.1, 64, StreamGranularity::Token);
+
+        // Every thread sends its own prompts: thread 1 asks "aab", "aabb", ...
+        let prompt = |thread: usize, round: usize| {
+            format!("{}{}", "a".repeat(thread + 1), "b".repeat(round + 1))
+        };
+        let workers: Vec<_> = (0..THREADS)
+            .map(|thread| {
+                let mut session = model.new_session();
+                std::thread::spawn(move || {
+                    for round in 0..ROUNDS {
+                        session.generate(&prompt(thread, round)).unwrap();
+                    }
+                    session
+                })
+            })
+            .collect();
+
+        for (thread, worker) in workers.into_iter().enumerate() {
+            let session = worker.join().unwrap();
+            let history = session.history();
+            assert_eq!(history.len(), 2 * ROUNDS);
+            for (round, turn) in history.chunks(2).enumerate() {
+                assert_eq!(turn[0].role, "user");
+                assert_eq!(turn[0].content, prompt(thread, round));
+                assert_eq!(turn[1].role, "assistant");
+            }
+        }
+        assert!(!model.shared.is_poisoned());
+        assert!(model.new_session().generate("c").is_ok());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -1,12 +1,15 @@
+use std::collections::VecDeque;
 use std::path::PathBuf;
 use std::sync::{mpsc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
 
 use anyhow::Result;
-use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
 use ratatui::layout::{Constraint, Layout};
 use ratatui::{backend::CrosstermBackend, Frame, Terminal};
 
+use crate::tui::attachment::Attachment;
 use crate::tui::components::input::InputWidget;
 use crate::tui::components::notification::Notification;
 use crate::tui::components::sidebar::Sidebar;
@@ -15,7 +18,9 @@ use crate::tui::screens::chat::ChatScreen;
 use crate::tui::screens::models::ModelsScreen;
 use crate::tui::screens::settings::SettingsScreen;
 use crate::tui::state::{AppState, FocusArea, NotificationLevel, PendingAction, Screen};
-use crate::{list_models, unregister_model, GenerateOptions, Model};
+use crate::{
+    list_models, unregister_model, GenerateOptions, Model, ProbabilityTap, Session, SharedModel,
+};
 
 static APP_STATE: Mutex<Option<AppState>> = Mutex::new(None);
 
@@ -29,6 +34,7 @@ enum WorkerCommand {
         reload_model: bool,
     },
     Generate {
+        tab: usize,
         prompt: String,
     },
     Download {
@@ -41,15 +47,18 @@ enum WorkerEvent {
     ModelLoadStarted(String),
     ModelLoaded {
         path: PathBuf,
+        context_limit: usize,
     },
     ContextUpdated {
+        tab: usize,
         used: usize,
-        limit: usize,
     },
-    FirstToken,
-    Token(String),
-    GenerationStarted,
-    GenerationFinished,
+    FirstToken(usize),
+    Token(usize, String, Option<f32>),
+    ContextTruncated(usize),
+    GenerationStarted(usize),
+    GenerationFinished(usize),
+    GenerationFailed(usize, String),
     DownloadStarted(String),
     DownloadProgress {
         filename: String,
@@ -63,6 +72,19 @@ enum WorkerEvent {
 
 const SETTINGS_FIELD_COUNT: usize = 8;
 
+/// How long a reply streams before it yields the model to a waiting tab.
+/// Resuming prefills the conversation again, so this is kept well above
+/// the prefill time of a typical chat.
+const GENERATION_SLICE: Duration = Duration::from_millis(1500);
+
+/// A queued or paused reply in one tab.
+struct Job {
+    tab: usize,
+    /// The prompt, until the first slice has run.
+    prompt: Option<String>,
+    streamed: bool,
+}
+
 pub struct App {
     terminal: Terminal<CrosstermBackend<std::io::Stdout>>,
     input: InputWidget,
@@ -72,7 +94,11 @@ pub struct App {
 }
 
 impl App {
-    pub fn new(model_path: Option<PathBuf>, initial_screen: Option<Screen>) -> Self {
+    pub fn new(
+        model_path: Option<PathBuf>,
+        initial_screen: Option<Screen>,
+        show_probs: bool,
+    ) -> Self {
         let backend = CrosstermBackend::new(std::io::stdout());
         let mut terminal = Terminal::new(backend).expect("Failed to create terminal");
         terminal.clear().expect("Failed to clear terminal");
@@ -96,7 +122,8 @@ impl App {
         let (worker_tx, worker_cmd_rx) = mpsc::channel();
         let (worker_event_tx, worker_rx) = mpsc::channel();
 
-        thread::spawn(move || Self::worker_loop(worker_cmd_rx, worker_event_tx));
+        let tap = show_probs.then(ProbabilityTap::new);
+        thread::spawn(move || Self::worker_loop(worker_cmd_rx, worker_event_tx, tap));
 
         let mut app = Self {
             terminal,
@@ -146,12 +173,65 @@ impl App {
         Ok(())
     }
 
-    fn worker_loop(rx: mpsc::Receiver<WorkerCommand>, tx: mpsc::Sender<WorkerEvent>) {
-        let mut model: Option<Model> = None;
+    /// Runs commands in order, except that replies are generated in
+    /// [`GENERATION_SLICE`]s, round-robin across the tabs waiting for one,
+    /// and loads and downloads wait until no reply is in progress.
+    fn worker_loop(
+        rx: mpsc::Receiver<WorkerCommand>,
+        tx: mpsc::Sender<WorkerEvent>,
+        tap: Option<ProbabilityTap>,
+    ) {
+        let mut model: Option<SharedModel> = None;
+        let mut sessions: Vec<Session> = Vec::new();
         let mut current_path: Option<PathBuf> = None;
+        let mut jobs: VecDeque<Job> = VecDeque::new();
+        // Received while a slice ran, and held back until the replies finish.
+        let mut inbox: VecDeque<WorkerCommand> = VecDeque::new();
+        let mut deferred: VecDeque<WorkerCommand> = VecDeque::new();
+
+        loop {
+            let command = match inbox.pop_front() {
+                Some(command) => Some(command),
+                None if !jobs.is_empty() => match rx.try_recv() {
+                    Ok(command) => Some(command),
+                    Err(mpsc::TryRecvError::Empty) => None,
+                    Err(mpsc::TryRecvError::Disconnected) => break,
+                },
+                None => match deferred.pop_front() {
+                    Some(command) => Some(command),
+                    None => match rx.recv() {
+                        Ok(command) => Some(command),
+                        Err(_) => break,
+                    },
+                },
+            };
+
+            let Some(command) = command else {
+                let mut job = jobs.pop_front().expect("a queued reply");
+                Self::run_slice(
+                    &mut job,
+                    model.as_ref(),
+                    &mut sessions,
+                    &rx,
+                    &tx,
+                    &mut inbox,
+                    tap.as_ref(),
+                    !jobs.is_empty(),
+                );
+                if sessions.get(job.tab).is_some_and(Session::is_paused) {
+                    jobs.push_back(job);
+                }
+                continue;
+            };
 
-        while let Ok(command) = rx.recv() {
             match command {
+                WorkerCommand::Generate { tab, prompt } => jobs.push_back(Job {
+                    tab,
+                    prompt: Some(prompt),
+                    streamed: false,
+                }),
+                WorkerCommand::Shutdown => break,
+                command if !jobs.is_empty() => deferred.push_back(command),
                 WorkerCommand::LoadModel { path, options } => {
                     let label = path
                         .file_name()
@@ -160,15 +240,16 @@ impl App {
                         .to_string();
                     let _ = tx.send(WorkerEvent::ModelLoadStarted(label));
 
-                    match Model::new(&path).map(|m| m.with_options(options)) {
-                        Ok(mut loaded) => match loaded.load() {
-                            Ok(_) => {
-                                let used = loaded.context_used().unwrap_or(0);
-                                let limit = loaded.context_limit().unwrap_or(0);
+                    match Model::new(&path).map(|m| configure(m, options, tap.as_ref())) {
+                        Ok(mut loaded) => match loaded.load().and_then(|_| share(loaded)) {
+                            Ok((shared, context_limit)) => {
                                 current_path = Some(path.clone());
-                                model = Some(loaded);
-                                let _ = tx.send(WorkerEvent::ModelLoaded { path });
-                                let _ = tx.send(WorkerEvent::ContextUpdated { used, limit });
+                                model = Some(shared);
+                                sessions.clear();
+                                let _ = tx.send(WorkerEvent::ModelLoaded {
+                                    path,
+                                    context_limit,
+                                });
                             }
                             Err(err) => {
                                 let _ = tx.send(WorkerEvent::Error(format!(
@@ -185,42 +266,6 @@ impl App {
                         }
                     }
                 }
-                WorkerCommand::Generate { prompt } => {
-                    let Some(active_model) = model.as_mut() else {
-                        let _ = tx.send(WorkerEvent::Error("No model loaded".to_string()));
-                        continue;
-                    };
-
-                    if current_path.is_some() {
-                        let _ = tx.send(WorkerEvent::GenerationStarted);
-                    }
-
-                    let _ = tx.send(WorkerEvent::ContextUpdated {
-                        used: active_model.context_used().unwrap_or(0),
-                        limit: active_model.context_limit().unwrap_or(0),
-                    });
-
-                    let mut saw_first_token = false;
-                    match active_model.generate_stream(&prompt, |token| {
-                        if !saw_first_token {
-                            saw_first_token = true;
-                            let _ = tx.send(WorkerEvent::FirstToken);
-                        }
-                        let _ = tx.send(WorkerEvent::Token(token));
-                    }) {
-                        Ok(_) => {
-                            let _ = tx.send(WorkerEvent::ContextUpdated {
-                                used: active_model.context_used().unwrap_or(0),
-                                limit: active_model.context_limit().unwrap_or(0),
-                            });
-                            let _ = tx.send(WorkerEvent::GenerationFinished);
-                        }
-                        Err(err) => {
-                            let _ =
-                                tx.send(WorkerEvent::Error(format!("Generation error: {}", err)));
-                        }
-                    }
-                }
                 WorkerCommand::UpdateOptions {
                     options,
                     reload_model,
@@ -238,14 +283,15 @@ impl App {
                             .to_string();
                         let _ = tx.send(WorkerEvent::ModelLoadStarted(label));
 
-                        match Model::new(&path).map(|m| m.with_options(options)) {
-                            Ok(mut loaded) => match loaded.load() {
-                                Ok(_) => {
-                                    let used = loaded.context_used().unwrap_or(0);
-                                    let limit = loaded.context_limit().unwrap_or(0);
-                                    model = Some(loaded);
-                                    let _ = tx.send(WorkerEvent::ModelLoaded { path });
-                                    let _ = tx.send(WorkerEvent::ContextUpdated { used, limit });
+                        match Model::new(&path).map(|m| configure(m, options, tap.as_ref())) {
+                            Ok(mut loaded) => match loaded.load().and_then(|_| share(loaded)) {
+                                Ok((shared, context_limit)) => {
+                                    model = Some(shared);
+                                    sessions.clear();
+                                    let _ = tx.send(WorkerEvent::ModelLoaded {
+                                        path,
+                                        context_limit,
+                                    });
                                 }
                                 Err(err) => {
                                     let _ = tx.send(WorkerEvent::Error(format!(
@@ -262,15 +308,12 @@ impl App {
                             }
                         }
                     } else if let Some(path) = current_path.clone() {
-                        model = Model::new(&path).ok().map(|m| m.with_options(options));
-                        if let Some(active_model) = model.as_mut() {
-                            if active_model.load().is_ok() {
-                                let _ = tx.send(WorkerEvent::ContextUpdated {
-                                    used: active_model.context_used().unwrap_or(0),
-                                    limit: active_model.context_limit().unwrap_or(0),
-                                });
-                            }
-                        }
+                        sessions.clear();
+                        model = Model::new(&path)
+                            .ok()
+                            .map(|m| configure(m, options, tap.as_ref()))
+                            .and_then(|mut m| m.load().and_then(|_| share(m)).ok())
+                            .map(|(shared, _)| shared);
                     }
                 }
                 WorkerCommand::Download { repo_id } => {
@@ -302,7 +345,89 @@ impl App {
                         }
                     }
                 }
-                WorkerCommand::Shutdown => break,
+            }
+        }
+    }
+
+    /// Generates the next slice of `job`'s reply. The slice ends early only
+    /// when another tab is waiting: `others_waiting`, or a prompt that
+    /// arrives meanwhile. Commands read while checking go to `inbox`.
+    #[allow(clippy::too_many_arguments)]
+    fn run_slice(
+        job: &mut Job,
+        model: Option<&SharedModel>,
+        sessions: &mut Vec<Session>,
+        rx: &mpsc::Receiver<WorkerCommand>,
+        tx: &mpsc::Sender<WorkerEvent>,
+        inbox: &mut VecDeque<WorkerCommand>,
+        tap: Option<&ProbabilityTap>,
+        others_waiting: bool,
+    ) {
+        let tab = job.tab;
+        let Some(model) = model else {
+            job.prompt = None;
+            let _ = tx.send(WorkerEvent::GenerationFailed(
+                tab,
+                "No model loaded".to_string(),
+            ));
+            return;
+        };
+        while sessions.len() <= tab {
+            sessions.push(model.new_session());
+        }
+        let session = &mut sessions[tab];
+
+        if job.prompt.is_some() {
+            let _ = tx.send(WorkerEvent::GenerationStarted(tab));
+        }
+
+        let started = Instant::now();
+        let mut contended = others_waiting;
+        let streamed = &mut job.streamed;
+        let on_chunk = |chunk: String| {
+            if !*streamed {
+                *streamed = true;
+                let _ = tx.send(WorkerEvent::FirstToken(tab));
+            }
+            let probability = tap.and_then(ProbabilityTap::take);
+            let _ = tx.send(WorkerEvent::Token(tab, chunk, probability));
+        };
+        let should_pause = || {
+            if started.elapsed() < GENERATION_SLICE {
+                return false;
+            }
+            while let Ok(command) = rx.try_recv() {
+                contended |= matches!(
+                    command,
+                    WorkerCommand::Generate { .. } | WorkerCommand::Shutdown
+                );
+                inbox.push_back(command);
+            }
+            contended
+        };
+        let result = match job.prompt.take() {
+            Some(prompt) => session.generate_until(&prompt, on_chunk, should_pause),
+            None => session.resume_until(on_chunk, should_pause),
+        };
+
+        match result {
+            Ok(slice) => {
+                if slice.dropped_tokens > 0 {
+                    let _ = tx.send(WorkerEvent::ContextTruncated(slice.dropped_tokens));
+                }
+                let _ = tx.send(WorkerEvent::ContextUpdated {
+                    tab,
+                    used: session.context_used(),
+                });
+                if !slice.paused {
+                    let _ = tx.send(WorkerEvent::GenerationFinished(tab));
+                }
+            }
+            Err(err) => {
+                let _ = tx.send(WorkerEvent::GenerationFailed(
+                    tab,
+                    format!("Generation error: {}", err),
+                ));
             }
         }
     }
@@ -320,10 +445,17 @@ impl App {
                         );
                     }
                 }
-                WorkerEvent::ModelLoaded { path } => {
+                WorkerEvent::ModelLoaded {
+                    path,
+                    context_limit,
+                } => {
                     let mut state_guard = Self::state_mut();
                     if let Some(state) = state_guard.as_mut() {
                         state.model_path = Some(path.clone());
+                        state.context_limit = context_limit;
+                        for tab in &mut state.tabs {
+                            tab.context_used = 0;
+                        }
                         state.is_loading_model = false;
                         state.options = state.draft_options.clone();
                         state.settings_dirty = false;
@@ -336,47 +468,69 @@ impl App {
                         );
                     }
                 }
-                WorkerEvent::ContextUpdated { used, limit } => {
+                WorkerEvent::ContextUpdated { tab, used } => {
                     let mut state_guard = Self::state_mut();
-                    if let Some(state) = state_guard.as_mut() {
-                        state.context_used = used;
-                        state.context_limit = limit;
+                    if let Some(tab) = state_guard.as_mut().and_then(|s| s.tabs.get_mut(tab)) {
+                        tab.context_used = used;
                     }
                 }
-                WorkerEvent::GenerationStarted => {
+                WorkerEvent::GenerationStarted(tab) => {
                     let mut state_guard = Self::state_mut();
                     if let Some(state) = state_guard.as_mut() {
-                        state.is_generating = true;
-                        state.tokens_generated = 0;
-                        state.tokens_per_second = 0.0;
+                        if let Some(tab) = state.tabs.get_mut(tab) {
+                            tab.is_generating = true;
+                            tab.tokens_generated = 0;
+                            tab.tokens_per_second = 0.0;
+                        }
                         state.clear_notification();
                     }
                 }
-                WorkerEvent::FirstToken => {
+                WorkerEvent::FirstToken(tab) => {
                     let mut state_guard = Self::state_mut();
-                    if let Some(state) = state_guard.as_mut() {
-                        state.finish_thinking();
+                    if let Some(tab) = state_guard.as_mut().and_then(|s| s.tabs.get_mut(tab)) {
+                        tab.finish_thinking();
                     }
                 }
-                WorkerEvent::Token(token) => {
+                WorkerEvent::Token(tab, token, probability) => {
                     let mut state_guard = Self::state_mut();
-                    if let Some(state) = state_guard.as_mut() {
-                        state.append_to_last_message(&token);
-                        state.tokens_generated += 1;
-                        let elapsed = state
+                    if let Some(tab) = state_guard.as_mut().and_then(|s| s.tabs.get_mut(tab)) {
+                        tab.append_to_last_message(&token, probability);
+                        tab.tokens_generated += 1;
+                        let elapsed = tab
                             .messages
                             .last()
                             .map(|msg| msg.timestamp.elapsed().as_secs_f64())
                             .unwrap_or(0.001);
-                        state.tokens_per_second =
-                            state.tokens_generated as f64 / elapsed.max(0.001);
+                        tab.tokens_per_second = tab.tokens_generated as f64 / elapsed.max(0.001);
+                    }
+                }
+                WorkerEvent::ContextTruncated(dropped_tokens) => {
+                    let mut state_guard = Self::state_mut();
+                    if let Some(state) = state_guard.as_mut() {
+                        state.set_notification(
+                            NotificationLevel::Warning,
+                            format!(
+                                "Dropped {} tokens of older history to fit the context window",
+                                dropped_tokens
+                            ),
+                        );
                     }
                 }
-                WorkerEvent::GenerationFinished => {
+                WorkerEvent::GenerationFinished(tab) => {
+                    let mut state_guard = Self::state_mut();
+                    if let Some(tab) = state_guard.as_mut().and_then(|s| s.tabs.get_mut(tab)) {
+                        tab.finish_thinking();
+                        tab.is_generating = false;
+                    }
+                }
+                WorkerEvent::GenerationFailed(tab, message) => {
                     let mut state_guard = Self::state_mut();
                     if let Some(state) = state_guard.as_mut() {
-                        state.finish_thinking();
-                        state.is_generating = false;
+                        if let Some(tab) = state.tabs.get_mut(tab) {
+                            tab.finish_thinking();
+                            tab.is_generating = false;
+                        }
+                        state.set_notification(NotificationLevel::Error, message);
                     }
                 }
                 WorkerEvent::DownloadStarted(repo_id) => {
@@ -440,8 +594,6 @@ impl App {
                 WorkerEvent::Error(message) => {
                     let mut state_guard = Self::state_mut();
                     if let Some(state) = state_guard.as_mut() {
-                        state.finish_thinking();
-                        state.is_generating = false;
                         state.is_loading_model = false;
                         state.set_notification(NotificationLevel::Error, message);
                     }
@@ -466,6 +618,9 @@ impl App {
     }
 
     fn handle_input(&mut self) -> Result<()> {
+        if crate::cli::terminal::restore_after_resume()? {
+            self.terminal.clear()?;
+        }
         if !event::poll(std::time::Duration::from_millis(16))? {
             return Ok(());
         }
@@ -478,6 +633,13 @@ impl App {
             return Ok(());
         }
 
+        if key.code == KeyCode::Char('z') && key.modifiers.contains(KeyModifiers::CONTROL) {
+            if crate::cli::terminal::suspend()? {
+                self.terminal.clear()?;
+            }
+            return Ok(());
+        }
+
         if matches!(key.code, KeyCode::Char('?') | KeyCode::F(1)) {
             let mut state_guard = Self::state_mut();
             if let Some(state) = state_guard.as_mut() {
@@ -487,6 +649,32 @@ impl App {
         }
 
         let state = Self::current_state();
+        if state.current_screen == Screen::Chat
+            && !state.show_help
+            && key.modifiers.contains(KeyModifiers::CONTROL)
+        {
+            let mut state_guard = Self::state_mut();
+            let state = state_guard.as_mut().unwrap();
+            match key.code {
+                KeyCode::Char('t') => {
+                    state.new_tab();
+                    state.focus_area = FocusArea::Input;
+                    return Ok(());
+                }
+                // Most terminals only report Ctrl+Tab with keyboard
+                // enhancements; Ctrl+PageDown/PageUp work everywhere.
+                KeyCode::Tab | KeyCode::PageDown => {
+                    state.next_tab();
+                    return Ok(());
+                }
+                KeyCode::BackTab | KeyCode::PageUp => {
+                    state.prev_tab();
+                    return Ok(());
+                }
+                _ => {}
+            }
+        }
+
         if state.show_help {
             if matches!(key.code, KeyCode::Esc | KeyCode::Char('?') | KeyCode::F(1)) {
                 let mut state_guard = Self::state_mut();
@@ -545,10 +733,12 @@ impl App {
                 KeyCode::Tab => state.cycle_focus_forward(),
                 KeyCode::BackTab => state.cycle_focus_backward(),
                 KeyCode::Down | KeyCode::Char('j') => {
-                    state.chat_scroll = state.chat_scroll.saturating_add(1);
+                    let tab = state.tab_mut();
+                    tab.chat_scroll = tab.chat_scroll.saturating_add(1);
                 }
                 KeyCode::Up | KeyCode::Char('k') => {
-                    state.chat_scroll = state.chat_scroll.saturating_sub(1);
+                    let tab = state.tab_mut();
+                    tab.chat_scroll = tab.chat_scroll.saturating_sub(1);
                 }
                 KeyCode::Esc => {
                     if state.notification.is_some() {
@@ -562,7 +752,7 @@ impl App {
             return;
         }
 
-        if state.is_loading_model || state.is_generating {
+        if state.is_loading_model || state.tab().is_generating {
             if matches!(code, KeyCode::Esc) {
                 if state.notification.is_some() {
                     state.clear_notification();
@@ -588,18 +778,48 @@ impl App {
             KeyCode::Home => self.input.move_cursor_to_start(),
             KeyCode::End => self.input.move_cursor_to_end(),
             KeyCode::Backspace => self.input.delete_char(),
-            KeyCode::Enter => {
-                if !self.input.is_empty() {
-                    let prompt = self.input.value().to_string();
-                    self.input.clear();
-                    state.add_user_message(&prompt);
-                    state.chat_scroll = 0;
-                    state.tokens_generated = 0;
-                    state.tokens_per_second = 0.0;
-                    state.start_assistant_message();
-
-                    let _ = self.worker_tx.send(WorkerCommand::Generate { prompt });
+            KeyCode::Enter if image_command(self.input.value()).is_some() => {
+                let path = image_command(self.input.value()).unwrap_or("").to_string();
+                self.input.clear();
+                let tab = state.tab_mut();
+                if path.is_empty() {
+                    tab.pending_attachments.clear();
+                    state.set_notification(NotificationLevel::Info, "Attachments removed");
+                    return;
+                }
+                match Attachment::open(std::path::Path::new(&path)) {
+                    Ok(attachment) => {
+                        let chip = attachment.chip();
+                        tab.pending_attachments.push(attachment);
+                        state.set_notification(
+                            NotificationLevel::Info,
+                            format!("Attached {}", chip),
+                        );
+                    }
+                    Err(err) => {
+                        state.set_notification(NotificationLevel::Error, format!("{:#}", err))
+                    }
+                }
+            }
+            KeyCode::Enter if !self.input.is_empty() => {
+                let prompt = self.input.value().to_string();
+                self.input.clear();
+                if !state.tab().pending_attachments.is_empty() {
+                    state.set_notification(
+                        NotificationLevel::Warning,
+                        "Images are not sent to the model: no supported architecture reads them yet",
+                    );
                 }
+                let tab = state.active_tab;
+                let chat = state.tab_mut();
+                chat.add_user_message(&prompt);
+                chat.chat_scroll = 0;
+                chat.is_generating = true;
+                chat.tokens_generated = 0;
+                chat.tokens_per_second = 0.0;
+                chat.start_assistant_message();
+
+                let _ = self.worker_tx.send(WorkerCommand::Generate { tab, prompt });
             }
             KeyCode::Char(c) => self.input.insert_char(c),
             _ => {}
@@ -636,9 +856,12 @@ impl App {
                             Ok(Some(_)) => {
                                 let mut state_guard = Self::state_mut();
                                 if was_active {
-                                    state_guard.as_mut().unwrap().model_path = None;
-                                    state_guard.as_mut().unwrap().context_used = 0;
-                                    state_guard.as_mut().unwrap().context_limit = 0;
+                                    let state = state_guard.as_mut().unwrap();
+                                    state.model_path = None;
+                                    state.context_limit = 0;
+                                    for tab in &mut state.tabs {
+                                        tab.context_used = 0;
+                                    }
                                 }
                                 state_guard.as_mut().unwrap().set_notification(
                                     NotificationLevel::Success,
@@ -732,7 +955,6 @@ impl App {
                         let path = model.path.clone();
                         drop(state_guard);
                         self.load_model(path);
-                        return;
                     }
                 }
             }
@@ -844,18 +1066,16 @@ impl App {
             KeyCode::Char('r') => {
                 state.sync_draft_options();
             }
-            KeyCode::Enter => {
-                if state.settings_dirty {
-                    state.options = state.draft_options.clone();
-                    state.settings_dirty = false;
-                    let options = state.options.clone();
-                    let reload_model = true;
-                    let _ = self.worker_tx.send(WorkerCommand::UpdateOptions {
-                        options,
-                        reload_model,
-                    });
-                    state.set_notification(NotificationLevel::Info, "Applying settings...");
-                }
+            KeyCode::Enter if state.settings_dirty => {
+                state.options = state.draft_options.clone();
+                state.settings_dirty = false;
+                let options = state.options.clone();
+                let reload_model = true;
+                let _ = self.worker_tx.send(WorkerCommand::UpdateOptions {
+                    options,
+                    reload_model,
+                });
+                state.set_notification(NotificationLevel::Info, "Applying settings...");
             }
             KeyCode::Esc => {
                 if state.notification.is_some() {
@@ -903,7 +1123,7 @@ impl App {
                 f.render_widget(ChatScreen::new(), chat_sections[0]);
 
                 let mut input = input.clone();
-                input.set_focused(!state.is_generating && !state.is_loading_model);
+                input.set_focused(!state.tab().is_generating && !state.is_loading_model);
                 f.render_widget(input, chat_sections[1]);
             }
             Screen::Models => f.render_widget(ModelsScreen::new(), main_area),
@@ -921,6 +1141,7 @@ impl App {
             );
             let notif = match notification.level {
                 NotificationLevel::Error => Notification::error(notification.message.clone()),
+                NotificationLevel::Warning => Notification::warning(notification.message.clone()),
                 NotificationLevel::Info => Notification::info(notification.message.clone()),
                 NotificationLevel::Success => Notification::success(notification.message.clone()),
             };
@@ -935,7 +1156,7 @@ impl App {
                 area.height * 2 / 3,
             );
             let help = ratatui::widgets::Paragraph::new(
-                "Shortcuts\n\nGlobal\n  F1 or ?   Toggle help\n  Tab       Next focus\n  Shift+Tab Previous focus\n  Esc       Dismiss/quit\n\nSidebar\n  j/k       Move screens\n  Enter     Open screen\n\nChat\n  Enter     Send prompt\n  j/k       Scroll chat (main focus)\n\nModels\n  j/k       Move models\n  Enter     Load model\n  x         Remove highlighted model\n\nSettings\n  j/k       Move field\n  h/l       Adjust value\n  Enter     Apply settings\n  r         Reset draft\n  Type      Edit system prompt field",
+                "Shortcuts\n\nGlobal\n  F1 or ?   Toggle help\n  Tab       Next focus\n  Shift+Tab Previous focus\n  Esc       Dismiss/quit\n\nSidebar\n  j/k       Move screens\n  Enter     Open screen\n\nChat\n  Enter     Send prompt\n  j/k       Scroll chat (main focus)\n  /image P  Attach image P (/image alone removes)\n  Ctrl+T    New tab\n  Ctrl+Tab  Next tab (also Ctrl+PgDn)\n  Ctrl+Shift+Tab Previous tab (also Ctrl+PgUp)\n\nModels\n  j/k       Move models\n  Enter     Load model\n  x         Remove highlighted model\n\nSettings\n  j/k       Move field\n  h/l       Adjust value\n  Enter     Apply settings\n  r         Reset draft\n  Type      Edit system prompt field",
             )
             .block(
                 ratatui::widgets::Block::bordered()
@@ -945,31 +1166,27 @@ impl App {
             f.render_widget(help, help_area);
         }
 
-        if let Some(pending_action) = &state.pending_action {
-            if let PendingAction::RemoveModel(model_id) = pending_action {
-                let modal_width = 50u16;
-                let modal_height = 7u16;
-                let modal_area = ratatui::layout::Rect::new(
-                    area.x + (area.width.saturating_sub(modal_width)) / 2,
-                    area.y + (area.height.saturating_sub(modal_height)) / 2,
-                    modal_width,
-                    modal_height,
-                );
-
-                let confirm_text = format!("Remove model '{}'?", model_id);
-                let modal = ratatui::widgets::Paragraph::new(format!(
-                    "{}\n\n  [y] Yes  [n] No",
-                    confirm_text
-                ))
-                .block(
-                    ratatui::widgets::Block::bordered()
-                        .border_type(ratatui::widgets::BorderType::Double)
-                        .title(" Confirm "),
-                )
-                .style(ratatui::style::Style::new().fg(ratatui::style::Color::Yellow));
+        if let Some(PendingAction::RemoveModel(model_id)) = &state.pending_action {
+            let modal_width = 50u16;
+            let modal_height = 7u16;
+            let modal_area = ratatui::layout::Rect::new(
+                area.x + (area.width.saturating_sub(modal_width)) / 2,
+                area.y + (area.height.saturating_sub(modal_height)) / 2,
+                modal_width,
+                modal_height,
+            );
 
-                f.render_widget(modal, modal_area);
-            }
+            let confirm_text = format!("Remove model '{}'?", model_id);
+            let modal =
+                ratatui::widgets::Paragraph::new(format!("{}\n\n  [y] Yes  [n] No", confirm_text))
+                    .block(
+                        ratatui::widgets::Block::bordered()
+                            .border_type(ratatui::widgets::BorderType::Double)
+                            .title(" Confirm "),
+                    )
+                    .style(ratatui::style::Style::new().fg(ratatui::style::Color::Yellow));
+
+            f.render_widget(modal, modal_area);
         }
     }
 }
@@ -1012,3 +1229,28 @@ fn adjust_setting(state: &mut AppState, direction: i32) {
 
     state.mark_settings_dirty();
 }
+
+/// The path after `/image`, or `Some("")` for a bare `/image`.
+fn image_command(input: &str) -> Option<&str> {
+    match input.trim().strip_prefix("/image")? {
+        "" => Some(""),
+        rest if rest.starts_with(char::is_whitespace) => Some(rest.trim()),
+        _ => None,
+    }
+}
+
+/// Applies `options` and, with `--show-probs`, reports token
+/// probabilities to `tap`.
+fn configure(model: Model, options: GenerateOptions, tap: Option<&ProbabilityTap>) -> Model {
+    let model = model.with_options(options);
+    match tap {
+        Some(tap) => model.with_hooks(tap.clone()),
+        None => model,
+    }
+}
+
+/// Turns a loaded model into a shared one, with its context length.
+fn share(model: Model) -> Result<(SharedModel, usize), Box<dyn std::error::Error>> {
+    let context_limit = model.context_limit().unwrap_or(0);
+    Ok((model.into_shared()?, context_limit))
+}
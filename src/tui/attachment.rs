@@ -0,0 +1,185 @@
+//! Images attached to a chat message with `/image <path>`.
+//!
+//! No supported architecture reads images yet, so an attachment is only
+//! shown in the chat log as a chip with its size and dimensions; the prompt
+//! goes to the model as text. The dimensions come from the file header, so
+//! no image decoder is needed.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+/// Enough of the file to reach a JPEG frame header behind EXIF data.
+const HEADER_BYTES: u64 = 256 * 1024;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Attachment {
+    pub path: PathBuf,
+    /// `PNG`, `JPEG`, `GIF`, `WebP` or `BMP`.
+    pub format: &'static str,
+    pub width: u32,
+    pub height: u32,
+    pub bytes: u64,
+}
+
+impl Attachment {
+    /// Reads the header of the image at `path`. Fails for files that are
+    /// not in one of the formats above.
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("Cannot open {}", path.display()))?;
+        let bytes = file.metadata()?.len();
+        let mut header = Vec::new();
+        file.take(HEADER_BYTES)
+            .read_to_end(&mut header)
+            .with_context(|| format!("Cannot read {}", path.display()))?;
+        let (format, width, height) = image_dimensions(&header).with_context(|| {
+            format!(
+                "{} is not a PNG, JPEG, GIF, WebP or BMP image",
+                path.display()
+            )
+        })?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            format,
+            width,
+            height,
+            bytes,
+        })
+    }
+
+    pub fn file_name(&self) -> &str {
+        self.path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("image")
+    }
+
+    /// One-line label for the chat log, e.g. `[PNG cat.png 640x480 12.0KB]`.
+    pub fn chip(&self) -> String {
+        format!(
+            "[{} {} {}x{} {}]",
+            self.format,
+            self.file_name(),
+            self.width,
+            self.height,
+            crate::format_size(self.bytes)
+        )
+    }
+}
+
+/// Format and pixel dimensions from the first bytes of an image file.
+pub fn image_dimensions(data: &[u8]) -> Option<(&'static str, u32, u32)> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") && data.get(12..16)? == b"IHDR" {
+        return Some(("PNG", be32(data, 16)?, be32(data, 20)?));
+    }
+    if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        return Some(("GIF", le16(data, 6)? as u32, le16(data, 8)? as u32));
+    }
+    if data.starts_with(b"BM") {
+        let width = le32(data, 18)? as i32;
+        // Negative heights mark top-down bitmaps.
+        let height = le32(data, 22)? as i32;
+        return Some(("BMP", width.unsigned_abs(), height.unsigned_abs()));
+    }
+    if data.starts_with(b"RIFF") && data.get(8..12)? == b"WEBP" {
+        return webp_dimensions(data).map(|(w, h)| ("WebP", w, h));
+    }
+    if data.starts_with(&[0xff, 0xd8]) {
+        return jpeg_dimensions(data).map(|(w, h)| ("JPEG", w, h));
+    }
+    None
+}
+
+fn webp_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    match data.get(12..16)? {
+        b"VP8 " => Some((
+            (le16(data, 26)? & 0x3fff) as u32,
+            (le16(data, 28)? & 0x3fff) as u32,
+        )),
+        b"VP8L" => {
+            let bits = le32(data, 21)?;
+            Some(((bits & 0x3fff) + 1, ((bits >> 14) & 0x3fff) + 1))
+        }
+        b"VP8X" => Some((le24(data, 24)? + 1, le24(data, 27)? + 1)),
+        _ => None,
+    }
+}
+
+/// Walks the JPEG segments up to the first start-of-frame marker.
+fn jpeg_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    let mut pos = 2;
+    loop {
+        while *data.get(pos)? != 0xff {
+            pos += 1;
+        }
+        while *data.get(pos)? == 0xff {
+            pos += 1;
+        }
+        let marker = *data.get(pos)?;
+        pos += 1;
+        if matches!(marker, 0xd0..=0xd9 | 0x01) {
+            continue;
+        }
+        let length = be16(data, pos)? as usize;
+        // SOF0-SOF15, except DHT (c4), JPG (c8) and DAC (cc).
+        if matches!(marker, 0xc0..=0xcf) && !matches!(marker, 0xc4 | 0xc8 | 0xcc) {
+            let height = be16(data, pos + 3)? as u32;
+            let width = be16(data, pos + 5)? as u32;
+            return Some((width, height));
+        }
+        pos += length;
+    }
+}
+
+fn be16(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn be32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn le16(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn le24(data: &[u8], at: usize) -> Option<u32> {
+    let bytes = data.get(at..at + 3)?;
+    Some(u32::from(bytes[0]) | u32::from(bytes[1]) << 8 | u32::from(bytes[2]) << 16)
+}
+
+fn le32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::image_dimensions;
+
+    #[test]
+    fn reads_dimensions_from_image_headers() {
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+        png.extend(640u32.to_be_bytes());
+        png.extend(480u32.to_be_bytes());
+        assert_eq!(image_dimensions(&png), Some(("PNG", 640, 480)));
+
+        let gif = b"GIF89a\x20\x03\x58\x02";
+        assert_eq!(image_dimensions(gif), Some(("GIF", 800, 600)));
+
+        // SOI, an APP0 segment to skip, then a baseline frame header.
+        let jpeg = [
+            0xff, 0xd8, 0xff, 0xe0, 0x00, 0x04, 0x4a, 0x46, 0xff, 0xc0, 0x00, 0x11, 0x08, 0x01,
+            0x2c, 0x01, 0x90,
+        ];
+        assert_eq!(image_dimensions(&jpeg), Some(("JPEG", 400, 300)));
+
+        let mut webp = b"RIFF\0\0\0\0WEBPVP8X\x0a\0\0\0\0\0\0\0".to_vec();
+        webp.extend([0x7f, 0x02, 0x00, 0xdf, 0x01, 0x00]);
+        assert_eq!(image_dimensions(&webp), Some(("WebP", 640, 480)));
+
+        assert_eq!(image_dimensions(b"plain text"), None);
+        assert_eq!(image_dimensions(&png[..18]), None);
+    }
+}
@@ -122,26 +122,21 @@ impl Widget for InputWidget {
                 break;
             }
 
-            let style: Style = if self.value.is_empty() && !self.focused {
-                TEXT_PRIMARY.into()
-            } else {
-                TEXT_PRIMARY.into()
-            };
+            let style: Style = TEXT_PRIMARY.into();
 
             buf[(x, y)].set_char(c).set_style(style);
             x += 1;
 
-            if cursor_index == Some(i) && self.focused {
-                if x < content_area.x + content_area.width {
-                    buf[(x, y)].set_char('█').set_style(RUST_ORANGE);
-                }
+            if cursor_index == Some(i) && self.focused && x < content_area.x + content_area.width {
+                buf[(x, y)].set_char('█').set_style(RUST_ORANGE);
             }
         }
 
-        if cursor_index == Some(display_text.len()) && self.focused {
-            if x < content_area.x + content_area.width {
-                buf[(x, y)].set_char('█').set_style(RUST_ORANGE);
-            }
+        if cursor_index == Some(display_text.len())
+            && self.focused
+            && x < content_area.x + content_area.width
+        {
+            buf[(x, y)].set_char('█').set_style(RUST_ORANGE);
         }
     }
 }
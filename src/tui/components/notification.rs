@@ -5,7 +5,7 @@ use ratatui::{
 };
 
 use crate::tui::state::NotificationLevel;
-use crate::tui::theme::{ACCENT_CYAN, ERROR_RED, SUCCESS_GREEN};
+use crate::tui::theme::{ACCENT_CYAN, ERROR_RED, FERRIS_ORANGE, SUCCESS_GREEN};
 
 pub struct Notification {
     message: String,
@@ -20,6 +20,13 @@ impl Notification {
         }
     }
 
+    pub fn warning(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            notification_type: NotificationLevel::Warning,
+        }
+    }
+
     pub fn info(message: impl Into<String>) -> Self {
         Self {
             message: message.into(),
@@ -43,12 +50,14 @@ impl Widget for Notification {
 
         let title = match self.notification_type {
             NotificationLevel::Error => " Error ",
+            NotificationLevel::Warning => " Warning ",
             NotificationLevel::Info => " Info ",
             NotificationLevel::Success => " Success ",
         };
 
         let border_color = match self.notification_type {
             NotificationLevel::Error => ERROR_RED,
+            NotificationLevel::Warning => FERRIS_ORANGE,
             NotificationLevel::Info => ACCENT_CYAN,
             NotificationLevel::Success => SUCCESS_GREEN,
         };
@@ -64,7 +64,7 @@ impl Widget for Sidebar {
 
             let is_active = *screen == self.active_screen;
             let is_selected = *screen == self.selected_screen;
-            let label = format!("{}", screen.label());
+            let label = screen.label().to_string();
 
             let x = content_area.x + 1;
             let style = if is_active {
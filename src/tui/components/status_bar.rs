@@ -85,7 +85,9 @@ impl Widget for StatusBar<'_> {
 
         let context_text = format!(
             " {}:{}/{} ",
-            "Ctx", self.state.context_used, self.state.context_limit
+            "Ctx",
+            self.state.tab().context_used,
+            self.state.context_limit
         );
         for c in context_text.chars() {
             if x < content_area.x + content_area.width {
@@ -96,7 +98,7 @@ impl Widget for StatusBar<'_> {
 
         x += 1;
 
-        let tok_s_text = format!(" {:.1} tok/s ", self.state.tokens_per_second);
+        let tok_s_text = format!(" {:.1} tok/s ", self.state.tab().tokens_per_second);
         for c in tok_s_text.chars() {
             if x < content_area.x + content_area.width {
                 buf[(x, y)].set_char(c).set_style(SUCCESS_GREEN);
@@ -125,7 +127,22 @@ impl Widget for StatusBar<'_> {
             }
         }
 
-        if self.state.is_generating {
+        if self.state.tabs.len() > 1 {
+            x += 1;
+            let tab_text = format!(
+                " Tab {}/{} ",
+                self.state.active_tab + 1,
+                self.state.tabs.len()
+            );
+            for c in tab_text.chars() {
+                if x < content_area.x + content_area.width {
+                    buf[(x, y)].set_char(c).set_style(TEXT_SECONDARY);
+                    x += 1;
+                }
+            }
+        }
+
+        if self.state.tab().is_generating {
             x += 1;
             let gen_text = " [generating] ";
             for c in gen_text.chars() {
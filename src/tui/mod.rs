@@ -1,4 +1,5 @@
 pub mod app;
+pub mod attachment;
 pub mod components;
 pub mod run;
 pub mod screens;
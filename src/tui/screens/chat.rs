@@ -1,11 +1,13 @@
 use ratatui::{
     buffer::Buffer,
     layout::Rect,
+    style::Color,
     widgets::{Block, Paragraph, Widget},
 };
 use std::time::Duration;
 
-use crate::tui::state::MessageRole;
+use crate::inference::probability_rgb;
+use crate::tui::state::{AppState, ChatMessage, MessageRole};
 use crate::tui::theme::{ACCENT_CYAN, FERRIS_ORANGE, RUST_ORANGE, TEXT_PRIMARY, TEXT_SECONDARY};
 
 pub struct ChatScreen;
@@ -24,16 +26,17 @@ impl Default for ChatScreen {
 
 impl Widget for ChatScreen {
     fn render(self, area: Rect, buf: &mut Buffer) {
+        let state = crate::tui::app::App::current_state();
         let block = Block::bordered()
             .border_type(ratatui::widgets::BorderType::Thick)
             .border_style(crate::tui::theme::IRON_GRAY)
-            .title(" Chat ");
+            .title(tab_strip(&state));
 
         let content_area = block.inner(area);
         block.render(area, buf);
-        let state = crate::tui::app::App::current_state();
+        let tab = state.tab();
 
-        if state.messages.is_empty() {
+        if tab.messages.is_empty() && tab.pending_attachments.is_empty() {
             Paragraph::new(
                 "Welcome to oxide-rs TUI!\n\nType your message and press Enter to start chatting.\n\nUse Tab to switch screens, Esc to quit.",
             )
@@ -42,59 +45,148 @@ impl Widget for ChatScreen {
             return;
         }
 
-        let mut lines = Vec::new();
+        // Each line is a run of differently colored spans.
+        let mut lines: Vec<Vec<(String, Color)>> = Vec::new();
         let active_model = state
             .model_path
             .as_ref()
             .and_then(|path| path.file_name())
             .and_then(|name| name.to_str())
             .unwrap_or("none selected");
-        lines.push((format!("Model: {}", active_model), TEXT_SECONDARY));
-        lines.push((String::new(), TEXT_SECONDARY));
+        lines.push(vec![(format!("Model: {}", active_model), TEXT_SECONDARY)]);
+        lines.push(Vec::new());
 
-        let visible_messages = state.messages.len().saturating_sub(8 + state.chat_scroll);
-        let end = state.messages.len().saturating_sub(state.chat_scroll);
-        for msg in state.messages[visible_messages..end].iter() {
+        let visible_messages = tab.messages.len().saturating_sub(8 + tab.chat_scroll);
+        let end = tab.messages.len().saturating_sub(tab.chat_scroll);
+        for msg in tab.messages[visible_messages..end].iter() {
             let header = match msg.role {
                 MessageRole::User => ("You", RUST_ORANGE),
                 MessageRole::Assistant => ("Assistant", ACCENT_CYAN),
             };
-            lines.push((header.0.to_string(), header.1));
+            lines.push(vec![(header.0.to_string(), header.1)]);
+            for attachment in &msg.attachments {
+                lines.push(vec![(attachment.chip(), ACCENT_CYAN)]);
+            }
+            let width = content_area.width.saturating_sub(1) as usize;
             if msg.is_thinking {
                 let spinner = spinner_frame(msg.timestamp.elapsed());
-                lines.push((format!("{} Thinking...", spinner), FERRIS_ORANGE));
+                lines.push(vec![(format!("{} Thinking...", spinner), FERRIS_ORANGE)]);
+            } else if !msg.probabilities.is_empty() {
+                lines.extend(wrap_shaded(msg, width));
             } else {
-                for line in wrap_text(&msg.content, content_area.width.saturating_sub(1) as usize) {
-                    lines.push((line, TEXT_PRIMARY));
+                for line in wrap_text(&msg.content, width) {
+                    lines.push(vec![(line, TEXT_PRIMARY)]);
                 }
             }
-            lines.push((String::new(), TEXT_SECONDARY));
+            lines.push(Vec::new());
         }
 
-        if state.chat_scroll > 0 {
-            lines.push((
-                format!("[scrolled up {}]", state.chat_scroll),
+        for attachment in &tab.pending_attachments {
+            lines.push(vec![(
+                format!("Attached to next message: {}", attachment.chip()),
                 TEXT_SECONDARY,
-            ));
+            )]);
         }
 
-        let mut y = content_area.y;
-        for (line, color) in lines {
+        if tab.chat_scroll > 0 {
+            lines.push(vec![(
+                format!("[scrolled up {}]", tab.chat_scroll),
+                TEXT_SECONDARY,
+            )]);
+        }
+
+        for (y, line) in (content_area.y..).zip(lines) {
             if y >= content_area.y + content_area.height {
                 break;
             }
-            for (idx, ch) in line.chars().enumerate() {
+            let chars = line
+                .iter()
+                .flat_map(|(text, color)| text.chars().map(move |ch| (ch, *color)));
+            for (idx, (ch, color)) in chars.enumerate() {
                 let x = content_area.x + idx as u16;
                 if x >= content_area.x + content_area.width {
                     break;
                 }
                 buf[(x, y)].set_char(ch).set_style(color);
             }
-            y += 1;
         }
     }
 }
 
+/// " Chat " for a single tab, otherwise the tab numbers with the active one
+/// in brackets and a `*` on tabs with a reply in progress.
+fn tab_strip(state: &AppState) -> String {
+    if state.tabs.len() == 1 {
+        return " Chat ".to_string();
+    }
+    let mut title = String::from(" Chat ");
+    for (index, tab) in state.tabs.iter().enumerate() {
+        let busy = if tab.is_generating { "*" } else { "" };
+        if index == state.active_tab {
+            title.push_str(&format!("[{}{}] ", index + 1, busy));
+        } else {
+            title.push_str(&format!(" {}{}  ", index + 1, busy));
+        }
+    }
+    title
+}
+
+/// Wraps a reply like [`wrap_text`], coloring each character by the
+/// probability of the chunk it was streamed in (`--show-probs`).
+fn wrap_shaded(msg: &ChatMessage, max_width: usize) -> Vec<Vec<(String, Color)>> {
+    let color_at = |offset: usize| {
+        msg.probabilities
+            .iter()
+            .find(|(end, _)| *end > offset)
+            .map_or(TEXT_PRIMARY, |&(_, probability)| {
+                let (r, g, b) = probability_rgb(probability);
+                Color::Rgb(r, g, b)
+            })
+    };
+    let mut words: Vec<Vec<(char, Color)>> = Vec::new();
+    let mut word = Vec::new();
+    for (offset, ch) in msg.content.char_indices() {
+        if ch.is_whitespace() {
+            if !word.is_empty() {
+                words.push(std::mem::take(&mut word));
+            }
+        } else {
+            word.push((ch, color_at(offset)));
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+
+    let mut lines = Vec::new();
+    let mut current: Vec<(char, Color)> = Vec::new();
+    for word in words {
+        if !current.is_empty() && current.len() + 1 + word.len() > max_width.max(1) {
+            lines.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push((' ', TEXT_PRIMARY));
+        }
+        current.extend(word);
+    }
+    if !current.is_empty() || lines.is_empty() {
+        lines.push(current);
+    }
+    lines
+        .into_iter()
+        .map(|line| {
+            let mut spans: Vec<(String, Color)> = Vec::new();
+            for (ch, color) in line {
+                match spans.last_mut() {
+                    Some((text, last)) if *last == color => text.push(ch),
+                    _ => spans.push((ch.to_string(), color)),
+                }
+            }
+            spans
+        })
+        .collect()
+}
+
 fn wrap_text(text: &str, max_width: usize) -> Vec<String> {
     if max_width == 0 {
         return vec![String::new()];
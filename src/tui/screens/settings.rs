@@ -106,8 +106,7 @@ impl Widget for SettingsScreen {
             lines.push((format!("{} {:<16} {}", marker, label, values[idx]), color));
         }
 
-        let mut y = sections[1].y;
-        for (line, color) in lines {
+        for (y, (line, color)) in (sections[1].y..).zip(lines) {
             if y >= sections[1].y + sections[1].height {
                 break;
             }
@@ -119,8 +118,6 @@ impl Widget for SettingsScreen {
                 }
                 buf[(x, y)].set_char(ch).set_style(color);
             }
-
-            y += 1;
         }
 
         Paragraph::new(if state.settings_dirty {
@@ -1,6 +1,7 @@
 use std::path::PathBuf;
 use std::time::{Duration, Instant};
 
+use crate::tui::attachment::Attachment;
 use crate::GenerateOptions;
 
 #[derive(Clone, Copy, PartialEq, Eq)]
@@ -53,6 +54,7 @@ pub enum MessageRole {
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum NotificationLevel {
     Error,
+    Warning,
     Info,
     Success,
 }
@@ -71,6 +73,10 @@ pub struct ChatMessage {
     pub content: String,
     pub timestamp: Instant,
     pub is_thinking: bool,
+    pub attachments: Vec<Attachment>,
+    /// With `--show-probs`: where each streamed chunk ends in `content`,
+    /// and the probability of its least likely token.
+    pub probabilities: Vec<(usize, f32)>,
 }
 
 impl ChatMessage {
@@ -80,6 +86,8 @@ impl ChatMessage {
             content: content.into(),
             timestamp: Instant::now(),
             is_thinking: false,
+            attachments: Vec::new(),
+            probabilities: Vec::new(),
         }
     }
 
@@ -89,6 +97,8 @@ impl ChatMessage {
             content: String::new(),
             timestamp: Instant::now(),
             is_thinking: true,
+            attachments: Vec::new(),
+            probabilities: Vec::new(),
         }
     }
 
@@ -97,6 +107,52 @@ impl ChatMessage {
     }
 }
 
+/// One chat tab: a conversation with its own session over the shared model.
+#[derive(Clone, Default)]
+pub struct ChatTab {
+    pub messages: Vec<ChatMessage>,
+    pub chat_scroll: usize,
+    /// A reply is queued or streaming.
+    pub is_generating: bool,
+    pub tokens_generated: usize,
+    pub tokens_per_second: f64,
+    pub context_used: usize,
+    /// Added with `/image`, shown with the next message.
+    pub pending_attachments: Vec<Attachment>,
+}
+
+impl ChatTab {
+    /// Adds a message with the pending attachments.
+    pub fn add_user_message(&mut self, content: impl Into<String>) {
+        let mut message = ChatMessage::user(content);
+        message.attachments = std::mem::take(&mut self.pending_attachments);
+        self.messages.push(message);
+    }
+
+    pub fn start_assistant_message(&mut self) {
+        self.messages.push(ChatMessage::assistant());
+    }
+
+    pub fn append_to_last_message(&mut self, token: &str, probability: Option<f32>) {
+        if let Some(msg) = self.messages.last_mut() {
+            if msg.role == MessageRole::Assistant {
+                msg.content.push_str(token);
+                if let Some(probability) = probability {
+                    msg.probabilities.push((msg.content.len(), probability));
+                }
+            }
+        }
+    }
+
+    pub fn finish_thinking(&mut self) {
+        if let Some(msg) = self.messages.last_mut() {
+            if msg.role == MessageRole::Assistant {
+                msg.finish_thinking();
+            }
+        }
+    }
+}
+
 #[derive(Clone)]
 pub enum PendingAction {
     RemoveModel(String),
@@ -123,24 +179,20 @@ pub struct AppState {
     pub model_path: Option<PathBuf>,
     pub options: GenerateOptions,
     pub draft_options: GenerateOptions,
-    pub messages: Vec<ChatMessage>,
+    pub tabs: Vec<ChatTab>,
+    pub active_tab: usize,
     pub current_screen: Screen,
     pub sidebar_selected_screen: Screen,
     pub focus_area: FocusArea,
     pub selected_model_index: usize,
     pub settings_selected_field: usize,
-    pub chat_scroll: usize,
     pub sidebar_width: u16,
     pub is_loading_model: bool,
-    pub is_generating: bool,
     pub notification: Option<NotificationState>,
     pub show_help: bool,
     pub pending_action: Option<PendingAction>,
     pub settings_dirty: bool,
-    pub tokens_generated: usize,
     pub prompt_tokens: usize,
-    pub tokens_per_second: f64,
-    pub context_used: usize,
     pub context_limit: usize,
     pub download_state: DownloadState,
     pub download_input: String,
@@ -152,24 +204,20 @@ impl AppState {
             model_path: None,
             options: GenerateOptions::default(),
             draft_options: GenerateOptions::default(),
-            messages: Vec::new(),
+            tabs: vec![ChatTab::default()],
+            active_tab: 0,
             current_screen: Screen::Chat,
             sidebar_selected_screen: Screen::Chat,
             focus_area: FocusArea::Input,
             selected_model_index: 0,
             settings_selected_field: 0,
-            chat_scroll: 0,
             sidebar_width: 0,
             is_loading_model: false,
-            is_generating: false,
             notification: None,
             show_help: false,
             pending_action: None,
             settings_dirty: false,
-            tokens_generated: 0,
             prompt_tokens: 0,
-            tokens_per_second: 0.0,
-            context_used: 0,
             context_limit: 4096,
             download_state: DownloadState::Idle,
             download_input: String::new(),
@@ -180,36 +228,36 @@ impl AppState {
         if self.context_limit == 0 {
             return 0.0;
         }
-        (self.context_used as f32 / self.context_limit as f32) * 100.0
+        (self.tab().context_used as f32 / self.context_limit as f32) * 100.0
     }
 
-    pub fn add_user_message(&mut self, content: impl Into<String>) {
-        self.messages.push(ChatMessage::user(content));
+    /// The tab shown on the chat screen.
+    pub fn tab(&self) -> &ChatTab {
+        &self.tabs[self.active_tab]
     }
 
-    pub fn start_assistant_message(&mut self) {
-        self.messages.push(ChatMessage::assistant());
+    pub fn tab_mut(&mut self) -> &mut ChatTab {
+        &mut self.tabs[self.active_tab]
     }
 
-    pub fn append_to_last_message(&mut self, token: &str) {
-        if let Some(msg) = self.messages.last_mut() {
-            if msg.role == MessageRole::Assistant {
-                msg.content.push_str(token);
-            }
-        }
+    /// Opens an empty tab and switches to it.
+    pub fn new_tab(&mut self) {
+        self.tabs.push(ChatTab::default());
+        self.active_tab = self.tabs.len() - 1;
     }
 
-    pub fn finish_thinking(&mut self) {
-        if let Some(msg) = self.messages.last_mut() {
-            if msg.role == MessageRole::Assistant {
-                msg.finish_thinking();
-            }
-        }
+    pub fn next_tab(&mut self) {
+        self.active_tab = (self.active_tab + 1) % self.tabs.len();
+    }
+
+    pub fn prev_tab(&mut self) {
+        self.active_tab = (self.active_tab + self.tabs.len() - 1) % self.tabs.len();
     }
 
     pub fn set_notification(&mut self, level: NotificationLevel, message: impl Into<String>) {
         let ttl = match level {
             NotificationLevel::Error => None,
+            NotificationLevel::Warning => Some(Duration::from_secs(4)),
             NotificationLevel::Info => Some(Duration::from_secs(2)),
             NotificationLevel::Success => Some(Duration::from_secs(2)),
         };